@@ -3,7 +3,7 @@ use std::path::Path;
 use crate::ast;
 use crate::pos::{Sp};
 use crate::game::{Game, LanguageKey};
-use crate::diagnostic::{RootEmitter, IntoDiagnostics};
+use crate::diagnostic::{Emitter, RootEmitter, IntoDiagnostics};
 use crate::error::ErrorReported;
 use crate::context::{CompilerContext, Scope};
 use crate::llir::DecompileOptions;
@@ -23,6 +23,10 @@ pub struct Truth<'ctx> {
 /// Builder for constructing [`Truth`].
 pub struct Builder {
     capture_diagnostics: bool,
+    collect_diagnostics: bool,
+    render_diagnostics: bool,
+    max_errors: Option<usize>,
+    num_threads: Option<usize>,
 }
 
 impl Default for Builder {
@@ -33,6 +37,10 @@ impl Builder {
     pub fn new() -> Self {
         Builder {
             capture_diagnostics: false,
+            collect_diagnostics: false,
+            render_diagnostics: true,
+            max_errors: Some(crate::diagnostic::DEFAULT_MAX_ERRORS),
+            num_threads: None,
         }
     }
 
@@ -40,16 +48,54 @@ impl Builder {
     ///
     /// To finish constructing it, store the result in a local variable and call [`Scope::truth`].
     pub fn build(&self) -> Scope {
-        let emitter = match self.capture_diagnostics {
-            true => RootEmitter::new_captured(),
-            false => RootEmitter::new_stderr(),
+        let mut emitter = match (self.render_diagnostics, self.capture_diagnostics) {
+            (false, _) => RootEmitter::new_silent(),
+            (true, true) => RootEmitter::new_captured(),
+            (true, false) => RootEmitter::new_stderr(),
         };
-        Scope::new(emitter)
+        emitter.set_max_errors(self.max_errors);
+        emitter.collect_diagnostics(self.collect_diagnostics);
+        let mut scope = Scope::new(emitter);
+        scope.set_num_threads(self.num_threads.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        }));
+        scope
     }
 
     pub fn capture_diagnostics(&mut self, capture: bool) -> &mut Self {
         self.capture_diagnostics = capture; self
     }
+
+    /// Collect diagnostics in a structured form, to be retrieved with [`Truth::take_collected_diagnostics`].
+    ///
+    /// This does not affect whether diagnostics are also rendered; see [`Self::render_diagnostics`].
+    pub fn collect_diagnostics(&mut self, collect: bool) -> &mut Self {
+        self.collect_diagnostics = collect; self
+    }
+
+    /// Setting this to `false` prevents diagnostics from being written to STDERR (or captured as text).
+    /// This is mostly useful together with [`Self::collect_diagnostics`].  Defaults to `true`.
+    pub fn render_diagnostics(&mut self, render: bool) -> &mut Self {
+        self.render_diagnostics = render; self
+    }
+
+    /// Limit the number of errors that are displayed.  Further errors will be counted and summarized
+    /// in a single line at the end.
+    ///
+    /// Defaults to [`DEFAULT_MAX_ERRORS`][`crate::diagnostic::DEFAULT_MAX_ERRORS`].  Use `None` to show all errors.
+    pub fn max_errors(&mut self, max_errors: Option<usize>) -> &mut Self {
+        self.max_errors = max_errors; self
+    }
+
+    /// Set the maximum number of threads used to compile or decompile scripts.  `None` (the default)
+    /// uses the available parallelism of the machine, while `Some(1)` does everything on the calling
+    /// thread, which can be useful for debugging.
+    ///
+    /// The output (including the order of diagnostics) does not depend on this setting.
+    pub fn num_threads(&mut self, num_threads: Option<usize>) -> &mut Self {
+        assert_ne!(num_threads, Some(0));
+        self.num_threads = num_threads; self
+    }
 }
 
 impl Scope {
@@ -67,6 +113,114 @@ impl Truth<'_> {
     pub fn get_captured_diagnostics(&self) -> Option<String> {
         self.ctx.emitter.get_captured_diagnostics()
     }
+
+    /// Take all diagnostics emitted since the last call, in structured form.
+    ///
+    /// **Note:** Requires having called [`Builder::collect_diagnostics`].
+    pub fn take_collected_diagnostics(&self) -> Option<Vec<crate::diagnostic::CollectedDiagnostic>> {
+        self.ctx.emitter.take_collected_diagnostics()
+    }
+
+    /// Write the summary line for any errors that were hidden due to [`Builder::max_errors`].
+    ///
+    /// This happens automatically when the [`Scope`] is dropped, but it can be done earlier
+    /// (e.g. before exiting the process, or before calling [`Self::get_captured_diagnostics`]).
+    pub fn emit_diagnostic_summary(&self) {
+        self.ctx.emitter.emit_summary()
+    }
+
+    /// Get debug info for every script compiled so far by this [`Truth`].
+    ///
+    /// Among other things, this includes a source map for each script, giving the offset, size, and
+    /// source span of every instruction.  (see [`debug_info::ScriptOffsetInfo`])
+    pub fn script_debug_info(&self) -> &[debug_info::Script] {
+        &self.ctx.script_debug_info
+    }
+
+    /// Get the size, instruction count, and registers used by every script compiled so far by this [`Truth`].
+    ///
+    /// This is derived from [`Self::script_debug_info`], and can be printed as a table.
+    pub fn script_stats(&self) -> crate::script_stats::ScriptStats {
+        crate::script_stats::ScriptStats::from_debug_info(&self.ctx.script_debug_info)
+    }
+
+    /// Change the maximum number of threads used to compile or decompile scripts.
+    /// See [`Builder::num_threads`].
+    pub fn set_num_threads(&mut self, num_threads: usize) {
+        assert!(num_threads > 0);
+        self.ctx.num_threads = num_threads;
+    }
+
+    /// Enable or disable the warning for gotos whose explicit time differs from the time at their label.
+    /// Enabled by default.
+    pub fn set_warn_goto_times(&mut self, warn: bool) {
+        self.ctx.warn_goto_times = warn;
+    }
+
+    /// Enable or disable the warning for instructions whose time is lower than that of the previous instruction,
+    /// without a label in between that something jumps to.  Enabled by default.
+    pub fn set_warn_time_decreases(&mut self, warn: bool) {
+        self.ctx.warn_time_decreases = warn;
+    }
+
+    /// Enable or disable warnings for a `@blob` whose size, or a `@mask` whose bits, disagree with the
+    /// instruction's known signature.  Enabled by default.
+    pub fn set_warn_blob_signatures(&mut self, warn: bool) {
+        self.ctx.warn_blob_signatures = warn;
+    }
+
+    /// Choose which `==` and `!=` comparisons of floats produce a warning.  Disabled by default.
+    pub fn set_warn_float_equality(&mut self, warn: crate::passes::float_equality::WarnFloatEquality) {
+        self.ctx.warn_float_equality = warn;
+    }
+
+    /// Change the maximum number of iterations that a `times(inline n)` loop may be unrolled into.
+    pub fn set_max_unroll_count(&mut self, count: u32) {
+        self.ctx.max_unroll_count = count;
+    }
+
+    /// Change the maximum depth of nested brackets, blocks and expressions.  Anything nested more deeply
+    /// is an error, rather than a risk of overflowing the stack.
+    pub fn set_max_nesting_depth(&mut self, depth: u32) {
+        self.ctx.max_nesting_depth = depth;
+    }
+
+    /// Enable or disable informational diagnostics describing the peak scratch register usage of each script.
+    /// Disabled by default.
+    pub fn set_report_register_pressure(&mut self, report: bool) {
+        self.ctx.report_register_pressure = report;
+    }
+
+    /// Enable or disable warnings for ANM scripts and sprites that no instruction in the same file refers to,
+    /// and for entries with no sprites.  Disabled by default, as these are often used by other files.
+    pub fn set_warn_unreferenced_anm_items(&mut self, warn: bool) {
+        self.ctx.warn_unreferenced_anm_items = warn;
+    }
+
+    /// Enable or disable warnings for ANM scripts with a loop that jumps back without advancing time.
+    /// Disabled by default, as such a loop may be deliberately left by an interrupt.
+    pub fn set_warn_anm_busy_loops(&mut self, warn: bool) {
+        self.ctx.warn_anm_busy_loops = warn;
+    }
+
+    /// Enable or disable lenient reading of ANM files.  Disabled by default.
+    ///
+    /// When enabled, a script whose entry in the script table points past the end of the file is read as an
+    /// empty script (with a warning) instead of being an error, so that the rest of a truncated file can be
+    /// decompiled.
+    pub fn set_lenient_reading(&mut self, lenient: bool) {
+        self.ctx.lenient_reading = lenient;
+    }
+}
+
+impl<'ctx> Truth<'ctx> {
+    /// Add a hook that will be notified at various points during compilation, for debugging purposes.
+    ///
+    /// See [`PassObserver`][crate::passes::observer::PassObserver].  A built-in observer that dumps the AST
+    /// after each pass is available as [`DumpAstObserver`][crate::passes::observer::DumpAstObserver].
+    pub fn add_pass_observer(&mut self, observer: impl crate::passes::observer::PassObserver + 'ctx) {
+        self.ctx.observers.push(Box::new(observer));
+    }
 }
 
 /// # Reading text files
@@ -75,10 +229,18 @@ impl Truth<'_> {
     //        but they do so that they can verify timeline arg0 presence...
     /// For unit tests.
     pub fn apply_mapfile_str(&mut self, text: &str, game: Game) -> Result<(), ErrorReported> {
-        let (file_id, text_rc) = self.ctx.emitter.files.add("<input mapfile>", text.as_ref()).map_err(|e| self.emit(e))?;
+        let mapfile = self.parse_mapfile("<input mapfile>", text)?;
+        self.apply_mapfile(&mapfile, game)
+    }
+
+    /// Parse the text of a mapfile without applying it.
+    ///
+    /// Like with [`Self::parse`], the name is only used for display in diagnostics.
+    pub fn parse_mapfile(&mut self, display_name: &str, text: &str) -> Result<crate::Mapfile, ErrorReported> {
+        let (file_id, text_rc) = self.ctx.emitter.files.add(display_name, text.as_ref()).map_err(|e| self.emit(e))?;
         let source_str = crate::pos::SourceStr::from_full_source(file_id, &text_rc[..]);
         let seqmap = crate::parse::seqmap::SeqmapRaw::parse(source_str, &self.ctx.emitter)?;
-        self.apply_mapfile(&crate::Mapfile::from_seqmap(seqmap, &self.ctx.emitter)?, game)
+        crate::Mapfile::from_seqmap(seqmap, &self.ctx.emitter)
     }
 
     pub fn apply_mapfile(&mut self, mapfile: &crate::Mapfile, game: Game) -> Result<(), ErrorReported> {
@@ -114,17 +276,33 @@ impl Truth<'_> {
         let (file_id, source_str) = self.ctx.emitter.files.add(display_name, text).map_err(|e| self.emit(e))?;
         let source_str = crate::pos::SourceStr::from_full_source(file_id, &source_str[..]);
         let mut state = crate::parse::State::new();
+        state.max_nesting_depth = self.ctx.max_nesting_depth;
         let mut lexer = crate::parse::lexer::GenericLexer::<>::new(source_str);
         A::parse_stream(&mut state, &mut lexer)
-            .map_err(|e| self.emit(e))
+            .map_err(|e| {
+                let reported = self.emit(e);
+                self.emit(state.take_recovered_errors()).ignore();
+                reported
+            })
             .and_then(|mut ast| {
-                crate::passes::resolution::fill_missing_node_ids(&mut ast, &self.ctx.unused_node_ids)?;
-                crate::passes::resolution::assign_res_ids(&mut ast, &mut self.ctx)?;
-                crate::passes::resolution::assign_loop_ids(&mut ast, &mut self.ctx)?;
+                self.fill_missing_ids(&mut ast)?;
                 Ok(ast)
             })
     }
 
+    /// Fill in any missing [`NodeId`]s, [`ResId`]s, and [`LoopId`]s on an AST node that was not
+    /// produced by [`Self::parse`].
+    ///
+    /// This must be called on e.g. an AST that was deserialized, as those IDs are never serialized.
+    /// It also checks that the AST is not [nested too deeply](Self::set_max_nesting_depth) to be compiled.
+    pub fn fill_missing_ids<A: crate::ast::Visitable>(&mut self, ast: &mut A) -> Result<(), ErrorReported> {
+        crate::passes::validate_nesting::run(ast, self.ctx.max_nesting_depth).map_err(|e| self.emit(e))?;
+        crate::passes::resolution::fill_missing_node_ids(ast, &self.ctx.unused_node_ids)?;
+        crate::passes::resolution::assign_res_ids(ast, &mut self.ctx)?;
+        crate::passes::resolution::assign_loop_ids(ast, &mut self.ctx)?;
+        Ok(())
+    }
+
     /// Register a source text for the purpose of display in diagnostics, without parsing it.
     ///
     /// This is primarily for unit tests for parsers of things besides the full AST. (otherwise you
@@ -136,17 +314,109 @@ impl Truth<'_> {
     }
 }
 
+/// # Tooling
+impl Truth<'_> {
+    /// List the items defined at the top level of a script file, without compiling it.
+    ///
+    /// This is best-effort, and is designed to still produce a listing for files that contain errors.
+    /// Any errors that are encountered will be emitted, and may cause some information to be missing
+    /// (e.g. the values of consts).  Consts that depend on names defined by other items (such as sprites
+    /// in ANM or subs in ECL) can not be evaluated.
+    ///
+    /// Mapfiles should be loaded beforehand, but [`Self::validate_defs`] is not required.
+    pub fn list_symbols(&mut self, ast: &ast::ScriptFile) -> Vec<crate::symbols::Symbol> {
+        crate::symbols::list_symbols(ast, &mut self.ctx)
+    }
+
+    /// Parse the text of a script file and write it back out in the standard format, as a code formatter.
+    ///
+    /// Comments are preserved.  No passes are run, so the script does not need to be valid beyond
+    /// its syntax.
+    pub fn reformat(&mut self, display_name: &str, text: &[u8]) -> Result<String, ErrorReported> {
+        let ast = self.parse::<ast::ScriptFile>(display_name, text)?;
+        Ok(crate::fmt::stringify_with(&ast.value, crate::fmt::Config::new()))
+    }
+
+    /// Resolve all of the names in a script file and build an index of them, which can be used to find
+    /// the name at a given position or all references to a definition.
+    ///
+    /// `language` is the primary language of the file's format (e.g. [`LanguageKey::Ecl`] for ECL),
+    /// which determines what instruction and register aliases are available.
+    ///
+    /// Like [`Self::list_symbols`], this tolerates errors.  Names that cannot be resolved are still
+    /// included in the index, but have no [`DefId`][crate::DefId].
+    pub fn index_names(&mut self, language: LanguageKey, ast: &ast::ScriptFile) -> crate::navigate::NameIndex {
+        crate::navigate::index_names(language, ast, &mut self.ctx)
+    }
+
+    /// Look up where the definition of a name in a [`NameIndex`][crate::navigate::NameIndex] is located.
+    ///
+    /// Returns `None` if the name was not resolved.
+    pub fn definition(&self, name: &crate::navigate::NameUse) -> Option<crate::navigate::Definition> {
+        name.def_id.map(|def_id| self.ctx.defs.definition(name.ns, def_id))
+    }
+
+    /// Save the values of all consts evaluated so far, so that a later [`Truth`] created from the same
+    /// [`Scope`] can reuse them through [`Self::reuse_const_cache`].  This is useful for tools that
+    /// repeatedly recompile a file as it is edited.
+    pub fn const_cache(&self) -> crate::context::ConstCache {
+        self.ctx.consts.cache(&self.ctx.defs, &self.ctx.resolutions)
+    }
+
+    /// Reuse const values saved by [`Self::const_cache`] wherever the definitions they were computed
+    /// from have not changed.
+    pub fn reuse_const_cache(&mut self, cache: crate::context::ConstCache) {
+        self.ctx.consts.reuse_cache(cache);
+    }
+
+    /// Get the consts whose values were taken from the cache given to [`Self::reuse_const_cache`]
+    /// instead of being evaluated.
+    pub fn reused_consts(&self) -> Vec<crate::DefId> {
+        self.ctx.consts.reused().iter().map(|const_id| const_id.def_id).collect()
+    }
+
+    /// Forget the value of a const or the results of a `const` function, along with the values of every
+    /// const computed from it, so that they will be evaluated again.
+    ///
+    /// Returns the [`DefId`][crate::DefId]s of the consts whose values were forgotten.
+    pub fn invalidate_const(&mut self, def_id: crate::DefId) -> Vec<crate::DefId> {
+        self.ctx.consts.invalidate(def_id).into_iter().map(|const_id| const_id.def_id).collect()
+    }
+}
+
 /// # Common behavior of pragmas
 impl Truth<'_> {
     /// Loads mapfiles from a parsed script.
+    ///
+    /// This also applies its `#pragma reg` lines (see [`Self::apply_reg_pragmas`]), after the mapfiles.
     pub fn load_mapfiles_from_pragmas(&mut self, game: Game, script: &ast::ScriptFile) -> Result<(), ErrorReported> {
         for path_literal in &script.mapfiles {
             let path: &Path = path_literal.string.as_ref();
             self.load_mapfile(&path, game)?;
         }
+        self.apply_reg_pragmas(game, script)
+    }
+
+    /// Applies the `#pragma reg` lines of a parsed script, which define register aliases exactly like
+    /// `!gvar_names` and `!gvar_types` in a mapfile.
+    pub fn apply_reg_pragmas(&mut self, game: Game, script: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        for mapfile in crate::Mapfile::from_reg_pragmas(&script.reg_pragmas, &self.ctx.emitter)? {
+            self.apply_mapfile(&mapfile, game)?;
+        }
         Ok(())
     }
 
+    /// Used by functions that must not touch the filesystem.
+    fn expect_no_mapfile_pragmas(&self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        if let Some(path) = ast.mapfiles.get(0) {
+            Err(self.emit(error!(
+                message("unexpected mapfile pragma"),
+                primary(path.span, "cannot load mapfiles by path here"),
+                note("mapfiles must be supplied to the function that performs the compilation"),
+            )))
+        } else { Ok(()) }
+    }
+
     pub fn expect_no_image_sources(&self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
         if let Some(path) = ast.image_sources.get(0) {
             Err(self.emit(error!(
@@ -197,18 +467,23 @@ impl<'a, 'ctx> core::ops::DerefMut for TruthWithValidatedDefs<'a, 'ctx> {
 /// # Compilation and decompilation
 impl TruthWithValidatedDefs<'_, '_> {
     pub fn compile_anm(&mut self, game: Game, ast: &ast::ScriptFile) -> Result<crate::WorkingAnmFile, ErrorReported> {
+        self.ctx.observers.after_parse(ast)?;
         crate::WorkingAnmFile::compile_from_ast(game, ast, &mut self.ctx)
     }
     pub fn compile_msg(&mut self, game: Game, language: LanguageKey, ast: &ast::ScriptFile) -> Result<crate::MsgFile, ErrorReported> {
+        self.ctx.observers.after_parse(ast)?;
         crate::MsgFile::compile_from_ast(game, language, ast, &mut self.ctx)
     }
     pub fn compile_mission(&mut self, game: Game, ast: &ast::ScriptFile) -> Result<crate::MissionMsgFile, ErrorReported> {
+        self.ctx.observers.after_parse(ast)?;
         crate::MissionMsgFile::compile_from_ast(game, ast, &mut self.ctx)
     }
     pub fn compile_std(&mut self, game: Game, ast: &ast::ScriptFile) -> Result<crate::StdFile, ErrorReported> {
+        self.ctx.observers.after_parse(ast)?;
         crate::StdFile::compile_from_ast(game, ast, &mut self.ctx)
     }
     pub fn compile_ecl(&mut self, game: Game, ast: &ast::ScriptFile) -> Result<crate::EclFile, ErrorReported> {
+        self.ctx.observers.after_parse(ast)?;
         crate::EclFile::compile_from_ast(game, ast, &mut self.ctx)
     }
 
@@ -232,10 +507,14 @@ impl TruthWithValidatedDefs<'_, '_> {
 /// # Binary file IO
 impl TruthWithValidatedDefs<'_, '_> {
     pub fn read_anm(&mut self, game: Game, path: &Path, with_images: bool) -> Result<crate::AnmFile, ErrorReported> {
+        let read_from_stream = match self.ctx.lenient_reading {
+            true => crate::AnmFile::read_from_stream_lenient,
+            false => crate::AnmFile::read_from_stream,
+        };
         match with_images {
             true => {
                 let mut reader = self.fs().open_read(path)?;
-                crate::AnmFile::read_from_stream(&mut reader, game, with_images)
+                read_from_stream(&mut reader, game, with_images)
             },
             false => {
                 // Here we don't read the whole thing because seeking can skip costly reads of megabytes of image data.
@@ -244,7 +523,7 @@ impl TruthWithValidatedDefs<'_, '_> {
                 let buffer_size = 64;
                 let mut reader = self.fs().open(path)?
                     .map_reader(|r| std::io::BufReader::with_capacity(buffer_size, r));
-                crate::AnmFile::read_from_stream(&mut reader, game, with_images)
+                read_from_stream(&mut reader, game, with_images)
             },
         }
     }
@@ -308,6 +587,480 @@ impl TruthWithValidatedDefs<'_, '_> {
     }
 }
 
+/// A binary file format, for use with the in-memory compilation functions such as [`Truth::compile_from_str`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FileFormat {
+    Anm,
+    Std,
+    /// Stage MSG files.
+    Msg,
+    /// Mission MSG files. (`msg.dat` in TH095 and TH125)
+    Mission,
+    Ecl,
+}
+
+impl FileFormat {
+    /// Languages whose core mapfiles are used when compiling or decompiling this format.
+    fn core_mapfile_languages(self) -> &'static [LanguageKey] {
+        match self {
+            FileFormat::Anm => &[LanguageKey::Anm],
+            FileFormat::Std => &[LanguageKey::Std],
+            FileFormat::Msg => &[LanguageKey::Msg],
+            FileFormat::Mission => &[],
+            FileFormat::Ecl => &[LanguageKey::Ecl, LanguageKey::Timeline],
+        }
+    }
+}
+
+/// # In-memory compilation and decompilation
+///
+/// These functions never touch the filesystem, which makes them suitable for e.g. web builds and test
+/// harnesses.  The display names they take are registered with [`Files`][crate::Files] just like the
+/// path of a file on disk would be, so that diagnostics can refer to them.
+///
+/// The core mapfiles for the format are applied automatically, before any of the provided mapfiles.
+/// (see [`Self::parse_mapfile`] for constructing a [`Mapfile`][crate::Mapfile] from text)
+impl Truth<'_> {
+    /// Compile source text into the bytes of a binary file.
+    ///
+    /// The script may not contain `#pragma mapfile`.  For ANM files, it also may not contain `#pragma image_source`;
+    /// see [`Self::compile_from_str_with_image_sources`].
+    pub fn compile_from_str(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        display_name: &str,
+        text: &str,
+        mapfiles: &[crate::Mapfile],
+    ) -> Result<Vec<u8>, ErrorReported> {
+        self.compile_from_str_with_image_sources(format, game, display_name, text, mapfiles, |_| None)
+    }
+
+    /// Like [`Self::compile_from_str`], but for ANM files, each `#pragma image_source` is resolved by
+    /// calling `image_sources` with the string from the pragma.  It should return the bytes of an ANM file.
+    ///
+    /// (directories of images cannot be used as image sources here)
+    pub fn compile_from_str_with_image_sources(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        display_name: &str,
+        text: &str,
+        mapfiles: &[crate::Mapfile],
+        image_sources: impl FnMut(&str) -> Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, ErrorReported> {
+        self.apply_mapfiles_for_in_memory(format, game, mapfiles)?;
+
+        let ast = self.parse::<ast::ScriptFile>(display_name, text.as_bytes())?.value;
+        self.expect_no_mapfile_pragmas(&ast)?;
+        self.apply_reg_pragmas(game, &ast)?;
+        if format != FileFormat::Anm {
+            self.expect_no_image_sources(&ast)?;
+        }
+
+        self.validate_defs()?.compile_to_bytes(format, game, display_name, &ast, image_sources)
+    }
+
+    /// Check source text for errors without producing a binary file.
+    ///
+    /// This loads mapfiles and runs the same compilation steps as [`Self::compile_from_str`] (name resolution,
+    /// type checking, const evaluation, and the checks that instructions are available in the game), but stops
+    /// before anything that needs image data or writes output.  In particular, `#pragma image_source` is
+    /// permitted in ANM scripts and ignored, so that scripts can be checked without their image sources.
+    pub fn check(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        display_name: &str,
+        text: &str,
+        mapfiles: &[crate::Mapfile],
+    ) -> Result<(), ErrorReported> {
+        self.apply_mapfiles_for_in_memory(format, game, mapfiles)?;
+
+        let ast = self.parse::<ast::ScriptFile>(display_name, text.as_bytes())?.value;
+        self.expect_no_mapfile_pragmas(&ast)?;
+        self.apply_reg_pragmas(game, &ast)?;
+        if format != FileFormat::Anm {
+            self.expect_no_image_sources(&ast)?;
+        }
+
+        let mut truth = self.validate_defs()?;
+        match format {
+            FileFormat::Anm => { truth.compile_anm(game, &ast)?; },
+            FileFormat::Std => { truth.compile_std(game, &ast)?; },
+            FileFormat::Msg => { truth.compile_msg(game, LanguageKey::Msg, &ast)?; },
+            FileFormat::Mission => { truth.compile_mission(game, &ast)?; },
+            FileFormat::Ecl => { truth.compile_ecl(game, &ast)?; },
+        }
+        Ok(())
+    }
+
+    /// Decompile the bytes of a binary file into formatted source text.
+    pub fn decompile_from_bytes(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        display_name: &str,
+        bytes: &[u8],
+        mapfiles: &[crate::Mapfile],
+        decompile_options: &DecompileOptions,
+    ) -> Result<String, ErrorReported> {
+        self.apply_mapfiles_for_in_memory(format, game, mapfiles)?;
+
+        let emitter = self.ctx.emitter;
+        let mut reader = crate::io::BinReader::from_reader(emitter, display_name, std::io::Cursor::new(bytes));
+        let mut truth = self.validate_defs()?;
+        let ast = match format {
+            FileFormat::Anm => {
+                let with_images = false;
+                let anm = match truth.ctx.lenient_reading {
+                    true => crate::AnmFile::read_from_stream_lenient(&mut reader, game, with_images)?,
+                    false => crate::AnmFile::read_from_stream(&mut reader, game, with_images)?,
+                };
+                truth.decompile_anm(game, &anm, decompile_options)?
+            },
+            FileFormat::Std => {
+                let std = crate::StdFile::read_from_stream(&mut reader, game)?;
+                truth.decompile_std(game, &std, decompile_options)?
+            },
+            FileFormat::Msg => {
+                let msg = crate::MsgFile::read_from_stream(&mut reader, game, LanguageKey::Msg)?;
+                truth.decompile_msg(game, LanguageKey::Msg, &msg, decompile_options)?
+            },
+            FileFormat::Mission => {
+                let msg = crate::MissionMsgFile::read_from_stream(&mut reader, game)?;
+                truth.decompile_mission(game, &msg)?
+            },
+            FileFormat::Ecl => {
+                let ecl = crate::EclFile::read_from_stream(&mut reader, game)?;
+                truth.decompile_ecl(game, &ecl, decompile_options)?
+            },
+        };
+
+        let mut formatter = crate::Formatter::with_config(vec![], decompile_options.fmt_config());
+        formatter.fmt(&ast).map_err(|e| truth.emit(error!("{:#}", e)))?;
+        let bytes = formatter.into_inner().map_err(|e| truth.emit(error!("{:#}", e)))?;
+        Ok(String::from_utf8(bytes).expect("formatter wrote non-utf8?!"))
+    }
+
+    /// Read the bytes of two binary files of the same format and compare their scripts instruction by instruction.
+    ///
+    /// Each of `old` and `new` is a display name paired with the file's bytes.  Instructions are decoded using
+    /// the signatures from the core mapfiles and `mapfiles`.  Mission MSG files are not supported, as they
+    /// contain no instructions.
+    pub fn diff_from_bytes(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        old: (&str, &[u8]),
+        new: (&str, &[u8]),
+        mapfiles: &[crate::Mapfile],
+    ) -> Result<crate::bindiff::BinDiff, ErrorReported> {
+        if format == FileFormat::Mission {
+            return Err(self.emit(error!("mission MSG files cannot be diffed")));
+        }
+        self.apply_mapfiles_for_in_memory(format, game, mapfiles)?;
+
+        let emitter = self.ctx.emitter;
+        let reader = |(display_name, bytes): (&str, &[u8])| {
+            crate::io::BinReader::from_reader(emitter, display_name, std::io::Cursor::new(bytes.to_vec()))
+        };
+        let (mut old_reader, mut new_reader) = (reader(old), reader(new));
+        let emitter = emitter.get_chained(format!("while comparing '{}' to '{}'", old.0, new.0));
+
+        let truth = self.validate_defs()?;
+        let ctx = &truth.ctx;
+        Ok(match format {
+            FileFormat::Anm => {
+                let with_images = false;
+                let old = crate::AnmFile::read_from_stream(&mut old_reader, game, with_images)?;
+                let new = crate::AnmFile::read_from_stream(&mut new_reader, game, with_images)?;
+                crate::bindiff::diff_anm(&emitter, ctx, game, &old, &new)
+            },
+            FileFormat::Std => {
+                let old = crate::StdFile::read_from_stream(&mut old_reader, game)?;
+                let new = crate::StdFile::read_from_stream(&mut new_reader, game)?;
+                crate::bindiff::diff_std(&emitter, ctx, game, &old, &new)
+            },
+            FileFormat::Msg => {
+                let old = crate::MsgFile::read_from_stream(&mut old_reader, game, LanguageKey::Msg)?;
+                let new = crate::MsgFile::read_from_stream(&mut new_reader, game, LanguageKey::Msg)?;
+                crate::bindiff::diff_msg(&emitter, ctx, game, LanguageKey::Msg, &old, &new)
+            },
+            FileFormat::Ecl => {
+                let old = crate::EclFile::read_from_stream(&mut old_reader, game)?;
+                let new = crate::EclFile::read_from_stream(&mut new_reader, game)?;
+                crate::bindiff::diff_ecl(&emitter, ctx, game, &old, &new)?
+            },
+            FileFormat::Mission => unreachable!(),
+        })
+    }
+
+    /// Read the bytes of binary files of a single format and count how often each opcode appears.
+    ///
+    /// Each file is a display name paired with its bytes; the counts of all files are combined.  Names and
+    /// signatures are looked up in the core mapfiles and `mapfiles`, but instructions are not decoded, so
+    /// opcodes with no signature are still counted.  Mission MSG files are not supported, as they contain
+    /// no instructions.
+    pub fn opcode_stats_from_bytes(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        files: &[(&str, &[u8])],
+        mapfiles: &[crate::Mapfile],
+    ) -> Result<crate::opcode_stats::OpcodeStats, ErrorReported> {
+        if format == FileFormat::Mission {
+            return Err(self.emit(error!("mission MSG files contain no instructions")));
+        }
+        self.apply_mapfiles_for_in_memory(format, game, mapfiles)?;
+
+        let emitter = self.ctx.emitter;
+        let truth = self.validate_defs()?;
+        let ctx = &truth.ctx;
+        let mut stats = crate::opcode_stats::OpcodeStats::new();
+        for &(display_name, bytes) in files {
+            let mut reader = crate::io::BinReader::from_reader(emitter, display_name, std::io::Cursor::new(bytes));
+            match format {
+                FileFormat::Anm => {
+                    let with_images = false;
+                    let anm = crate::AnmFile::read_from_stream(&mut reader, game, with_images)?;
+                    crate::opcode_stats::add_anm(&mut stats, ctx, &anm);
+                },
+                FileFormat::Std => {
+                    let std = crate::StdFile::read_from_stream(&mut reader, game)?;
+                    crate::opcode_stats::add_std(&mut stats, ctx, &std);
+                },
+                FileFormat::Msg => {
+                    let msg = crate::MsgFile::read_from_stream(&mut reader, game, LanguageKey::Msg)?;
+                    crate::opcode_stats::add_msg(&mut stats, ctx, LanguageKey::Msg, &msg);
+                },
+                FileFormat::Ecl => {
+                    let ecl = crate::EclFile::read_from_stream(&mut reader, game)?;
+                    crate::opcode_stats::add_ecl(&mut stats, ctx, &ecl);
+                },
+                FileFormat::Mission => unreachable!(),
+            }
+        }
+        Ok(stats)
+    }
+
+    fn apply_mapfiles_for_in_memory(&mut self, format: FileFormat, game: Game, mapfiles: &[crate::Mapfile]) -> Result<(), ErrorReported> {
+        self.apply_core_mapfiles(format, game);
+        for mapfile in mapfiles {
+            self.apply_mapfile(mapfile, game)?;
+        }
+        Ok(())
+    }
+
+    fn apply_core_mapfiles(&mut self, format: FileFormat, game: Game) {
+        for &language in format.core_mapfile_languages() {
+            let core_mapfile = crate::core_mapfiles::core_mapfile(self.ctx.emitter, game, language);
+            self.apply_mapfile(&core_mapfile, game).expect("failed to apply core mapfile!?");
+        }
+    }
+}
+
+impl TruthWithValidatedDefs<'_, '_> {
+    /// Compile a parsed script into the bytes of a binary file, for the in-memory compilation functions.
+    fn compile_to_bytes(
+        &mut self,
+        format: FileFormat,
+        game: Game,
+        display_name: &str,
+        ast: &ast::ScriptFile,
+        mut image_sources: impl FnMut(&str) -> Option<Vec<u8>>,
+    ) -> Result<Vec<u8>, ErrorReported> {
+        let emitter = self.ctx.emitter;
+        let mut writer = crate::io::BinWriter::from_writer(emitter, display_name, std::io::Cursor::new(vec![]));
+        match format {
+            FileFormat::Anm => {
+                let mut compiled = self.compile_anm(game, ast)?;
+                for path_literal in &ast.image_sources {
+                    let source_name = &path_literal.string[..];
+                    let bytes = image_sources(source_name).ok_or_else(|| self.emit(error!(
+                        message("image source '{source_name}' was not provided"),
+                        primary(path_literal, "not found"),
+                    )))?;
+                    let mut reader = crate::io::BinReader::from_reader(emitter, source_name, std::io::Cursor::new(bytes));
+                    let source_anm = crate::AnmFile::read_from_stream(&mut reader, game, true)?;
+                    compiled.apply_image_source(crate::anm::ImageSource::Anm(source_anm), &self.fs())?;
+                }
+                let compiled = self.finalize_anm(game, compiled)?;
+                compiled.write_to_stream(&mut writer, game)?;
+            },
+            FileFormat::Std => self.compile_std(game, ast)?.write_to_stream(&mut writer, game)?,
+            FileFormat::Msg => self.compile_msg(game, LanguageKey::Msg, ast)?.write_to_stream(&mut writer, game, LanguageKey::Msg)?,
+            FileFormat::Mission => self.compile_mission(game, ast)?.write_to_stream(&mut writer, game)?,
+            FileFormat::Ecl => self.compile_ecl(game, ast)?.write_to_stream(&mut writer, game)?,
+        }
+        Ok(writer.into_inner().into_inner())
+    }
+}
+
+/// # Batch compilation
+impl Truth<'_> {
+    /// Compile many source files in memory, all for the same game and with the same mapfiles.
+    ///
+    /// This is like calling [`Self::compile_from_str`] on a fresh [`Truth`] for each job, except that the core
+    /// mapfiles and `mapfiles` are only applied once, and the definitions built from them are shared by all of
+    /// the jobs.  Anything defined by a file (including its `#pragma reg` lines) is forgotten before the next
+    /// job begins, so that jobs cannot see each other's items.  The limit set by [`Builder::max_errors`]
+    /// applies to the batch as a whole.
+    ///
+    /// Jobs may not use `#pragma mapfile` or `#pragma image_source`.  An `Err` is only returned if the mapfiles
+    /// have errors; the failure of an individual job is recorded in its [`BatchJobResult`].
+    pub fn compile_batch(
+        &mut self,
+        game: Game,
+        jobs: &[BatchJob<'_>],
+        mapfiles: &[crate::Mapfile],
+    ) -> Result<BatchResults, ErrorReported> {
+        let formats = jobs.iter().map(|job| job.format).collect::<indexmap::IndexSet<_>>();
+        for format in formats {
+            self.apply_core_mapfiles(format, game);
+        }
+        for mapfile in mapfiles {
+            self.apply_mapfile(mapfile, game)?;
+        }
+
+        let mut truth = self.validate_defs()?;
+        let diagnostics = truth.take_collected_diagnostics().unwrap_or_default();
+        let saved_defs = truth.ctx.save_defs();
+        let jobs = jobs.iter().map(|job| {
+            truth.ctx.restore_defs(&saved_defs);
+            let output = truth.compile_batch_job(game, job);
+            let diagnostics = truth.take_collected_diagnostics().unwrap_or_default();
+            let script_stats = truth.script_stats();
+            BatchJobResult { output, diagnostics, script_stats }
+        }).collect();
+        truth.ctx.restore_defs(&saved_defs);
+
+        Ok(BatchResults { jobs, diagnostics })
+    }
+}
+
+impl TruthWithValidatedDefs<'_, '_> {
+    fn compile_batch_job(&mut self, game: Game, job: &BatchJob<'_>) -> Result<Vec<u8>, ErrorReported> {
+        let ast = self.parse::<ast::ScriptFile>(job.display_name, job.text.as_bytes())?.value;
+        self.expect_no_mapfile_pragmas(&ast)?;
+        self.apply_reg_pragmas(game, &ast)?;
+        if job.format != FileFormat::Anm {
+            self.expect_no_image_sources(&ast)?;
+        }
+        self.compile_to_bytes(job.format, game, job.display_name, &ast, |_| None)
+    }
+}
+
+/// A source file to be compiled by [`Truth::compile_batch`].
+#[derive(Debug, Copy, Clone)]
+pub struct BatchJob<'a> {
+    pub format: FileFormat,
+    /// The name used for the file in diagnostics.  (see [`Truth::parse`])
+    pub display_name: &'a str,
+    pub text: &'a str,
+}
+
+/// The outcome of a single [`BatchJob`].
+#[derive(Debug)]
+pub struct BatchJobResult {
+    /// The bytes of the compiled file.
+    pub output: Result<Vec<u8>, ErrorReported>,
+    /// Diagnostics emitted while compiling this file, if [`Builder::collect_diagnostics`] was enabled.
+    pub diagnostics: Vec<crate::diagnostic::CollectedDiagnostic>,
+    /// The size and register usage of each script in this file.  (see [`Truth::script_stats`])
+    pub script_stats: crate::script_stats::ScriptStats,
+}
+
+/// The results of [`Truth::compile_batch`].
+#[derive(Debug)]
+pub struct BatchResults {
+    /// The outcome of each job, in the same order as the jobs.
+    pub jobs: Vec<BatchJobResult>,
+    /// Diagnostics emitted before any of the jobs (e.g. warnings about the mapfiles), if
+    /// [`Builder::collect_diagnostics`] was enabled.
+    pub diagnostics: Vec<crate::diagnostic::CollectedDiagnostic>,
+}
+
+impl BatchResults {
+    /// The number of jobs that were compiled successfully.
+    pub fn num_ok(&self) -> usize {
+        self.jobs.iter().filter(|job| job.output.is_ok()).count()
+    }
+
+    /// The number of jobs that failed to compile.
+    pub fn num_failed(&self) -> usize {
+        self.jobs.len() - self.num_ok()
+    }
+}
+
+/// # Raw instructions
+///
+/// These functions build and read binary files at the level of [`RawInstr`][crate::llir::RawInstr]s, bypassing
+/// the AST entirely.  This is a stable layer for interop with external assemblers and fuzzers.  Instructions
+/// are written exactly as given, so no mapfiles are involved; only the layout of the file (headers, offsets,
+/// and end-of-script markers) is generated.
+impl Truth<'_> {
+    /// Build the bytes of an ANM file from raw instructions.
+    ///
+    /// `skeleton` is ANM source text with nothing but `entry` blocks, and `scripts` holds the named scripts
+    /// of each entry, in order.  Entries may not have `has_data: true`, as there is no way to supply an image.
+    pub fn build_anm_from_raw(
+        &mut self,
+        game: Game,
+        display_name: &str,
+        skeleton: &str,
+        scripts: Vec<Vec<(crate::Ident, crate::anm::Script)>>,
+    ) -> Result<Vec<u8>, ErrorReported> {
+        let ast = self.parse::<ast::ScriptFile>(display_name, skeleton.as_bytes())?.value;
+        self.expect_no_mapfile_pragmas(&ast)?;
+        self.expect_no_image_sources(&ast)?;
+
+        let emitter = self.ctx.emitter;
+        let mut truth = self.validate_defs()?;
+        let anm = crate::WorkingAnmFile::from_raw_scripts(&ast, scripts, &mut truth.ctx)?;
+        let anm = truth.finalize_anm(game, anm)?;
+        let mut writer = crate::io::BinWriter::from_writer(emitter, display_name, std::io::Cursor::new(vec![]));
+        anm.write_to_stream(&mut writer, game)?;
+        Ok(writer.into_inner().into_inner())
+    }
+
+    /// Build the bytes of an STD file from raw instructions.
+    ///
+    /// `skeleton` is STD source text with nothing but a `meta` block.
+    pub fn build_std_from_raw(
+        &mut self,
+        game: Game,
+        display_name: &str,
+        skeleton: &str,
+        script: Vec<crate::llir::RawInstr>,
+    ) -> Result<Vec<u8>, ErrorReported> {
+        let ast = self.parse::<ast::ScriptFile>(display_name, skeleton.as_bytes())?.value;
+        self.expect_no_mapfile_pragmas(&ast)?;
+
+        let emitter = self.ctx.emitter;
+        let mut truth = self.validate_defs()?;
+        let std = crate::StdFile::from_raw_script(game, &ast, script, &mut truth.ctx)?;
+        let mut writer = crate::io::BinWriter::from_writer(emitter, display_name, std::io::Cursor::new(vec![]));
+        std.write_to_stream(&mut writer, game)?;
+        Ok(writer.into_inner().into_inner())
+    }
+
+    /// Read the bytes of an ANM file into its raw instructions, without decompiling them.
+    ///
+    /// Embedded images are skipped.
+    pub fn read_anm_from_bytes(&mut self, game: Game, display_name: &str, bytes: &[u8]) -> Result<crate::AnmFile, ErrorReported> {
+        let mut reader = crate::io::BinReader::from_reader(self.ctx.emitter, display_name, std::io::Cursor::new(bytes));
+        let with_images = false;
+        crate::AnmFile::read_from_stream(&mut reader, game, with_images)
+    }
+
+    /// Read the bytes of an STD file into its raw instructions, without decompiling them.
+    pub fn read_std_from_bytes(&mut self, game: Game, display_name: &str, bytes: &[u8]) -> Result<crate::StdFile, ErrorReported> {
+        let mut reader = crate::io::BinReader::from_reader(self.ctx.emitter, display_name, std::io::Cursor::new(bytes));
+        crate::StdFile::read_from_stream(&mut reader, game)
+    }
+}
+
 // =============================================================================
 // Helpers
 
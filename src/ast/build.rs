@@ -0,0 +1,275 @@
+//! Helpers for constructing AST nodes, for use by code outside of the crate.
+//!
+//! All nodes produced by these functions use [`Span::NULL`][crate::Span::NULL] and have no [`NodeId`][crate::resolve::NodeId]s or
+//! [`ResId`][crate::resolve::ResId]s.  Once a fragment is finished, it must be given IDs using
+//! [`Truth::fill_missing_ids`][crate::Truth::fill_missing_ids] before it can be given to any passes.
+//!
+//! ```
+//! use truth::ast::{self, build};
+//!
+//! // void sub0(int x) { foo(x + 1, 2.0); }
+//! let item = build::func(ast::TypeKeyword::Void, "sub0", [(ast::TypeKeyword::Int, "x")], build::block([
+//!     build::expr_stmt(build::call("foo", [
+//!         build::binop(build::var("x"), ast::BinOpKind::Add, build::int(1)),
+//!         build::float(2.0),
+//!     ])),
+//! ]));
+//! let script = build::script_file([item]);
+//! assert_eq!(truth::fmt::stringify(&script).trim(), "void sub0(int x) {\n    foo((x + 1), 2.0);\n}");
+//! ```
+//!
+//! # Panics
+//!
+//! Functions that take names will panic if the name is not a valid [`Ident`].
+
+use crate::ast;
+use crate::ident::{Ident, ResIdent};
+use crate::pos::Sp;
+use crate::raw;
+use crate::resolve::RegId;
+
+fn ident(name: &str) -> Ident {
+    Ident::new_system(name).unwrap_or_else(|e| panic!("invalid identifier {name:?}: {e}"))
+}
+
+fn res_ident(name: &str) -> ResIdent {
+    ResIdent::new_null(ident(name))
+}
+
+// =============================================================================
+// Items
+
+/// Construct a complete script file from items.
+pub fn script_file(items: impl IntoIterator<Item=Sp<ast::Item>>) -> ast::ScriptFile {
+    ast::ScriptFile {
+        mapfiles: vec![],
+        image_sources: vec![],
+        reg_pragmas: vec![],
+        items: items.into_iter().collect(),
+        comments: vec![],
+    }
+}
+
+/// Construct a function definition.  `ty_keyword` is the return type.
+pub fn func<'a>(
+    ty_keyword: ast::TypeKeyword,
+    name: &str,
+    params: impl IntoIterator<Item=(ast::TypeKeyword, &'a str)>,
+    code: ast::Block,
+) -> Sp<ast::Item> {
+    sp!(ast::Item::Func(ast::ItemFunc {
+        qualifier: None,
+        ty_keyword: sp!(ty_keyword),
+        ident: sp!(res_ident(name)),
+        params: params.into_iter().map(|(ty_keyword, name)| sp!(ast::FuncParam {
+            qualifier: None,
+            ty_keyword: sp!(ty_keyword),
+            ident: Some(sp!(res_ident(name))),
+        })).collect(),
+        code: Some(code),
+    }))
+}
+
+/// Construct an ANM `script`.
+pub fn anm_script(name: &str, code: ast::Block) -> Sp<ast::Item> {
+    sp!(ast::Item::AnmScript {
+        keyword: sp!(()),
+        number: None,
+        ident: sp!(ident(name)),
+        code,
+        meta: None,
+    })
+}
+
+/// Construct an ECL `timeline`.
+pub fn timeline(number: Option<raw::LangInt>, code: ast::Block) -> Sp<ast::Item> {
+    sp!(ast::Item::Timeline {
+        keyword: sp!(()),
+        number: number.map(|x| sp!(x)),
+        ident: None,
+        code,
+    })
+}
+
+/// Construct a `const` variable definition.
+pub fn const_var(ty_keyword: ast::TypeKeyword, name: &str, value: Sp<ast::Expr>) -> Sp<ast::Item> {
+    sp!(ast::Item::ConstVar {
+        ty_keyword: sp!(ty_keyword),
+        vars: vec![sp!((named_var(name), value))],
+    })
+}
+
+// =============================================================================
+// Statements
+
+/// Construct a block, adding the bookend statements that every [`ast::Block`] is required to have.
+pub fn block(stmts: impl IntoIterator<Item=Sp<ast::Stmt>>) -> ast::Block {
+    let mut out = vec![stmt(ast::StmtKind::NoInstruction)];
+    out.extend(stmts);
+    out.push(stmt(ast::StmtKind::NoInstruction));
+    ast::Block(out)
+}
+
+/// Construct a statement of any kind, with no difficulty label.
+pub fn stmt(kind: ast::StmtKind) -> Sp<ast::Stmt> {
+    sp!(ast::Stmt { node_id: None, diff_label: None, kind })
+}
+
+/// Construct a statement from an item. (e.g. a `const` inside a function)
+pub fn item_stmt(item: Sp<ast::Item>) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Item(Box::new(item)))
+}
+
+/// Add a difficulty label (e.g. `"ENH"`) to a statement.
+pub fn with_diff_label(mut stmt: Sp<ast::Stmt>, label: &str) -> Sp<ast::Stmt> {
+    stmt.diff_label = Some(sp!(ast::DiffLabel { mask: None, expr: Some(sp!(ast::Expr::LitString(label.into()))) }));
+    stmt
+}
+
+/// Construct an expression statement. `expr;`
+pub fn expr_stmt(expr: Sp<ast::Expr>) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Expr(expr))
+}
+
+/// Construct an assignment statement. `var = value;`, `var += value;`, etc.
+pub fn assign(var: Sp<ast::Var>, op: ast::AssignOpKind, value: Sp<ast::Expr>) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Assignment { var, op: sp!(op), value })
+}
+
+/// Construct a declaration of a single local variable.  `int x = value;`
+pub fn declare(ty_keyword: ast::TypeKeyword, name: &str, value: Option<Sp<ast::Expr>>) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Declaration {
+        ty_keyword: sp!(ty_keyword),
+        vars: vec![sp!((named_var(name), value))],
+    })
+}
+
+/// Construct a `return` statement.
+pub fn return_(value: Option<Sp<ast::Expr>>) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Return { keyword: sp!(()), value })
+}
+
+/// Construct a label that can be jumped to. `name:`
+pub fn label(name: &str) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Label(sp!(ident(name))))
+}
+
+/// Construct an absolute time label. `time:`
+pub fn abs_time_label(time: raw::Time) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::AbsTimeLabel(sp!(time)))
+}
+
+/// Construct a relative time label. `+delta:`
+pub fn rel_time_label(delta: raw::Time) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::RelTimeLabel { delta: sp!(delta), _absolute_time_comment: None })
+}
+
+/// Construct an unconditional `goto`.
+pub fn goto(label: &str) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Jump(goto_jump(label)))
+}
+
+/// Construct a conditional `goto`.  `if (cond) goto label;`
+pub fn cond_goto(keyword: ast::CondKeyword, cond: Sp<ast::Expr>, label: &str) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::CondJump { keyword: sp!(keyword), cond, jump: goto_jump(label) })
+}
+
+fn goto_jump(label: &str) -> ast::StmtJumpKind {
+    ast::StmtJumpKind::Goto(ast::StmtGoto { destination: sp!(ident(label)), time: None })
+}
+
+/// Construct a `loop { ... }`.
+pub fn loop_(block: ast::Block) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Loop { loop_id: None, keyword: sp!(()), block })
+}
+
+/// Construct a `times(count) { ... }`.
+pub fn times(count: Sp<ast::Expr>, block: ast::Block) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::Times { loop_id: None, keyword: sp!(()), inline_keyword: None, clobber: None, count, block })
+}
+
+/// Construct an `if`/`unless` statement with no `else`.
+pub fn if_(keyword: ast::CondKeyword, cond: Sp<ast::Expr>, block: ast::Block) -> Sp<ast::Stmt> {
+    stmt(ast::StmtKind::CondChain(ast::StmtCondChain {
+        cond_blocks: vec![ast::CondBlock { keyword: sp!(keyword), cond, block }],
+        else_block: None,
+    }))
+}
+
+// =============================================================================
+// Expressions
+
+/// Construct an integer literal.
+pub fn int(value: raw::LangInt) -> Sp<ast::Expr> {
+    sp!(ast::Expr::from(value))
+}
+
+/// Construct a float literal.
+pub fn float(value: raw::LangFloat) -> Sp<ast::Expr> {
+    sp!(ast::Expr::from(value))
+}
+
+/// Construct a string literal.
+pub fn string(value: &str) -> Sp<ast::Expr> {
+    sp!(ast::Expr::from(value.to_owned()))
+}
+
+/// Construct an expression that reads a variable by name.
+pub fn var(name: &str) -> Sp<ast::Expr> {
+    named_var(name).into()
+}
+
+/// Construct an expression that reads a register, with an optional type sigil.
+pub fn reg(ty_sigil: Option<ast::VarSigil>, reg: RegId) -> Sp<ast::Expr> {
+    reg_var(ty_sigil, reg).into()
+}
+
+/// Construct a variable referenced by name (a local, a const, or a register alias), for use in e.g. [`assign`].
+pub fn named_var(name: &str) -> Sp<ast::Var> {
+    sp!(ast::Var { ty_sigil: None, name: ast::VarName::from_parsed_ident(res_ident(name)) })
+}
+
+/// Construct a register for use in e.g. [`assign`].
+pub fn reg_var(ty_sigil: Option<ast::VarSigil>, reg: RegId) -> Sp<ast::Var> {
+    sp!(ast::Var { ty_sigil, name: ast::VarName::Reg { reg, language: None, language_prefix: None } })
+}
+
+/// Construct a call to a function or instruction alias by name.
+pub fn call(name: &str, args: impl IntoIterator<Item=Sp<ast::Expr>>) -> Sp<ast::Expr> {
+    let name = ast::CallableName::Normal { ident: res_ident(name), language_if_ins: None };
+    call_impl(name, args)
+}
+
+/// Construct a raw instruction call. `ins_23(...)`
+pub fn ins(opcode: raw::Opcode, args: impl IntoIterator<Item=Sp<ast::Expr>>) -> Sp<ast::Expr> {
+    call_impl(ast::CallableName::Ins { opcode, language: None, language_prefix: None }, args)
+}
+
+fn call_impl(name: ast::CallableName, args: impl IntoIterator<Item=Sp<ast::Expr>>) -> Sp<ast::Expr> {
+    sp!(ast::Expr::Call(ast::ExprCall {
+        name: sp!(name),
+        pseudos: vec![],
+        args: args.into_iter().collect(),
+    }))
+}
+
+/// Construct a binary operation.
+pub fn binop(a: Sp<ast::Expr>, op: ast::BinOpKind, b: Sp<ast::Expr>) -> Sp<ast::Expr> {
+    sp!(ast::Expr::BinOp(Box::new(a), sp!(op), Box::new(b)))
+}
+
+/// Construct a unary operation.
+pub fn unop(op: ast::UnOpKind, x: Sp<ast::Expr>) -> Sp<ast::Expr> {
+    sp!(ast::Expr::UnOp(sp!(op), Box::new(x)))
+}
+
+/// Construct a ternary. `cond ? left : right`
+pub fn ternary(cond: Sp<ast::Expr>, left: Sp<ast::Expr>, right: Sp<ast::Expr>) -> Sp<ast::Expr> {
+    sp!(ast::Expr::Ternary {
+        cond: Box::new(cond),
+        question: sp!(()),
+        left: Box::new(left),
+        colon: sp!(()),
+        right: Box::new(right),
+    })
+}
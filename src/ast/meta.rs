@@ -1,6 +1,4 @@
-use std::collections::HashSet;
-
-use indexmap::IndexMap as Map;
+use indexmap::{IndexMap as Map, IndexSet};
 
 use crate::ast;
 use crate::pos::Sp;
@@ -9,15 +7,17 @@ use crate::fmt::Formatter;
 use crate::value::ScalarValue;
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Meta {
     Scalar(Sp<ast::Expr>),
     // { key: value, ... }
-    Object(Sp<Fields>),
+    Object(#[cfg_attr(feature = "serde", serde(with = "serde_fields"))] Sp<Fields>),
     // [ value, ... ]
     Array(Vec<Sp<Meta>>),
     // ident { key: value, ... }
     Variant {
         name: Sp<Ident>,
+        #[cfg_attr(feature = "serde", serde(with = "serde_fields"))]
         fields: Sp<Fields>,
     },
 }
@@ -27,6 +27,38 @@ pub enum Meta {
 /// Note that these idents can also be (canonically-formatted) integers.
 pub type Fields = Map<Sp<Ident>, Sp<Meta>>;
 
+/// [`Fields`] are serialized as a list of key-value pairs, since the keys are not strings.
+#[cfg(feature = "serde")]
+pub(super) mod serde_fields {
+    use super::*;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<S: Serializer>(fields: &Sp<Fields>, serializer: S) -> Result<S::Ok, S::Error> {
+        sp!(fields.span => fields.value.iter().collect::<Vec<_>>()).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Sp<Fields>, D::Error> {
+        let pairs = Sp::<Vec<(Sp<Ident>, Sp<Meta>)>>::deserialize(deserializer)?;
+        Ok(pairs.sp_map(|pairs| pairs.into_iter().collect()))
+    }
+}
+
+/// Like [`serde_fields`], for an optional [`Fields`].
+#[cfg(feature = "serde")]
+pub(super) mod serde_opt_fields {
+    use super::*;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<S: Serializer>(fields: &Option<Sp<Fields>>, serializer: S) -> Result<S::Ok, S::Error> {
+        fields.as_ref().map(|fields| sp!(fields.span => fields.value.iter().collect::<Vec<_>>())).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Sp<Fields>>, D::Error> {
+        let pairs = Option::<Sp<Vec<(Sp<Ident>, Sp<Meta>)>>>::deserialize(deserializer)?;
+        Ok(pairs.map(|pairs| pairs.sp_map(|pairs| pairs.into_iter().collect())))
+    }
+}
+
 // For error messages
 impl std::fmt::Display for Meta {
     fn fmt(&self, std_fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -82,13 +114,18 @@ pub enum FromMetaError<'a> {
     },
     MissingField {
         fields: &'a Sp<Fields>,
-        missing: &'static str,
+        missing: Vec<&'static str>,
+        /// Unrecognized keys that look like typos of the missing fields.
+        misspelled: Vec<(&'a Sp<Ident>, &'static str)>,
+        descr: Option<&'static str>,
     },
     ConflictingFields {
         conflict: [Sp<Ident>; 2],
     },
     UnrecognizedField {
         invalid: &'a Sp<Ident>,
+        suggestion: Option<&'static str>,
+        descr: Option<&'static str>,
     },
     UnrecognizedVariant {
         invalid: &'a Sp<Ident>,
@@ -116,18 +153,28 @@ impl crate::diagnostic::IntoDiagnostics for FromMetaError<'_> {
             message("const expression required"),
             primary(expr, "non-const expression"),
         )],
-        FromMetaError::MissingField { fields, missing } => vec![error!(
-            message("incomplete metadata object"),
-            primary(fields, "missing field '{}'", missing),
-        )],
+        FromMetaError::MissingField { fields, missing, misspelled, descr } => {
+            let missing_str = missing.iter().map(|field| format!("'{}'", field)).collect::<Vec<_>>().join(", ");
+            let mut diag = error!(
+                message("incomplete {}", descr.unwrap_or("metadata object")),
+                primary(fields, "missing {} {}", if missing.len() == 1 { "field" } else { "fields" }, missing_str),
+            );
+            for (key, suggestion) in misspelled {
+                diag.secondary(key, format!("did you mean `{}`?", suggestion));
+            }
+            vec![diag]
+        },
         FromMetaError::ConflictingFields { ref conflict } => vec![error!(
             message("cannot supply both '{}' and '{}'", conflict[0], conflict[1]),
             primary(conflict[0], "conflicting field"),
             primary(conflict[1], "conflicting field"),
         )],
-        FromMetaError::UnrecognizedField { invalid } => vec![error!(
-            message("unexpected field in metadata"),
-            primary(invalid, "not a valid field here"),
+        FromMetaError::UnrecognizedField { invalid, suggestion, descr } => vec![error!(
+            message("unexpected field in {}", descr.unwrap_or("metadata")),
+            primary(invalid, "{}", match suggestion {
+                Some(suggestion) => format!("did you mean `{}`?", suggestion),
+                None => "not a valid field here".to_string(),
+            }),
         )],
         FromMetaError::UnrecognizedVariant { invalid, valid_variants } => vec![error!(
             message("unrecognized variant in metadata"),
@@ -140,8 +187,9 @@ impl crate::diagnostic::IntoDiagnostics for FromMetaError<'_> {
 /// Used to parse an object.
 pub struct ParseObject<'a> {
     map: &'a Sp<Fields>,
-    valid_fields: HashSet<&'static str>,
+    valid_fields: IndexSet<&'static str>,
     allow_unrecognized: bool,
+    descr: Option<&'static str>,
 }
 
 /// Used to parse a variant.
@@ -249,7 +297,7 @@ impl<'a> ParseObject<'a> {
     /// then it is preferable to use [`Sp<Meta>::parse_object`] instead which will automatically call
     /// the `finish` method for you.
     pub fn new(map: &'a Sp<Fields>) -> Self {
-        ParseObject { map, valid_fields: HashSet::new(), allow_unrecognized: false }
+        ParseObject { map, valid_fields: IndexSet::new(), allow_unrecognized: false, descr: None }
     }
 
     /// Briefly construct a [`ParseObject`] for the duration of a closure.
@@ -312,8 +360,34 @@ impl<'a> ParseObject<'a> {
         }
     }
 
+    /// Check that all of the given fields are present, reporting every missing one at once.
+    ///
+    /// Call this before reading any required fields, as [`Self::expect_field`] can only report one.
+    pub fn require_fields(&mut self, fields: &[&'static str]) -> Result<(), FromMetaError<'a>> {
+        self.valid_fields.extend(fields.iter().copied());
+        let missing = fields.iter().copied().filter(|&field| self.map.get(field).is_none()).collect::<Vec<_>>();
+        match missing.is_empty() {
+            true => Ok(()),
+            false => Err(self.missing_fields(missing)),
+        }
+    }
+
     fn missing_field(&self, field: &'static str) -> FromMetaError<'a> {
-        FromMetaError::MissingField { fields: &self.map, missing: field }
+        self.missing_fields(vec![field])
+    }
+
+    fn missing_fields(&self, missing: Vec<&'static str>) -> FromMetaError<'a> {
+        // a missing field is often just misspelled
+        let misspelled = self.unrecognized_keys()
+            .filter_map(|key| find_similar_field(key.as_str(), missing.iter().copied()).map(|field| (key, field)))
+            .collect();
+        FromMetaError::MissingField { fields: self.map, missing, misspelled, descr: self.descr }
+    }
+
+    /// Name the kind of object being parsed in error messages, e.g. `"ANM entry"`.
+    pub fn describe(&mut self, descr: &'static str) -> Result<(), FromMetaError<'a>> {
+        self.descr = Some(descr);
+        Ok(())
     }
 
     /// Mark a field as valid without attempting to parse it.
@@ -331,14 +405,28 @@ impl<'a> ParseObject<'a> {
     /// Check for any user-supplied fields that were not parsed and emit errors on them.
     pub fn finish(self) -> Result<(), FromMetaError<'a>> {
         if !self.allow_unrecognized {
-            for key in self.map.keys() {
-                if !self.valid_fields.iter().map(|x| -> &str { x.as_ref() }).any(|x| x == key) {
-                    return Err(FromMetaError::UnrecognizedField { invalid: key });
-                }
+            if let Some(key) = self.unrecognized_keys().next() {
+                let suggestion = find_similar_field(key.as_str(), self.valid_fields.iter().copied());
+                return Err(FromMetaError::UnrecognizedField { invalid: key, suggestion, descr: self.descr });
             }
         }
         Ok(())
     }
+
+    /// Keys that have not (yet) been recognized as fields.
+    fn unrecognized_keys(&self) -> impl Iterator<Item=&'a Sp<Ident>> + '_ {
+        self.map.keys().filter(move |key| !self.valid_fields.contains(key.as_str()))
+    }
+}
+
+fn find_similar_field(input: &str, candidates: impl Iterator<Item=&'static str>) -> Option<&'static str> {
+    let max_distance = input.len() / 3;
+
+    candidates
+        .map(|candidate| (candidate, strsim::osa_distance(input, candidate)))
+        .min_by_key(|&(_, distance)| distance)
+        .filter(|&(_, distance)| distance <= max_distance)
+        .map(|(candidate, _)| candidate)
 }
 
 impl<'a, T> ParseVariant<'a, T> {
@@ -443,6 +531,12 @@ impl BuildObject {
     }
 }
 
+/// Wrapper around an integer that is best read in hexadecimal (e.g. a color or a bitfield).
+///
+/// This parses exactly like the integer it wraps, but [`ToMeta`] writes it as a hex literal.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Hex<T>(pub T);
+
 // =============================================================================
 
 impl<'m> FromMeta<'m> for &'m Sp<Meta> {
@@ -498,6 +592,12 @@ impl FromMeta<'_> for u32 {
     }
 }
 
+impl<'m, T: FromMeta<'m>> FromMeta<'m> for Hex<T> {
+    fn from_meta(meta: &'m Sp<Meta>) -> Result<Self, FromMetaError<'m>> {
+        T::from_meta(meta).map(Hex)
+    }
+}
+
 impl FromMeta<'_> for f32 {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
         match ScalarValue::from_meta(meta)? {
@@ -570,6 +670,12 @@ impl ToMeta for i32 {
 impl ToMeta for u32 {
     fn to_meta(&self) -> Meta { Meta::Scalar(sp!((*self as i32).into())) }
 }
+impl ToMeta for Hex<i32> {
+    fn to_meta(&self) -> Meta { Meta::Scalar(sp!(ast::Expr::LitInt { value: self.0, radix: ast::IntRadix::Hex })) }
+}
+impl ToMeta for Hex<u32> {
+    fn to_meta(&self) -> Meta { Hex(self.0 as i32).to_meta() }
+}
 impl ToMeta for f32 {
     fn to_meta(&self) -> Meta { Meta::Scalar(sp!((*self).into())) }
 }
@@ -687,6 +793,49 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn suggestions() {
+        let meta = str_meta(r"{ abc: 123, def: { x: 4 }, opr: 10 }");
+        match meta.parse::<Outer>() {
+            Err(FromMetaError::UnrecognizedField { invalid, suggestion, .. }) => {
+                assert_eq!(invalid, "opr");
+                assert_eq!(suggestion, Some("opt"));
+            },
+            other => panic!("{:?}", other),
+        }
+
+        let meta = str_meta(r"{ abc: 123, def: { x: 4 }, zzz: 10 }");
+        assert!(matches!(
+            meta.parse::<Outer>(),
+            Err(FromMetaError::UnrecognizedField { suggestion: None, .. }),
+        ));
+
+        // a misspelled required field shows up alongside the missing field error
+        let meta = str_meta(r"{ abcd: 123, def: { x: 4 } }");
+        match meta.parse::<Outer>() {
+            Err(FromMetaError::MissingField { missing, misspelled, .. }) => {
+                assert_eq!(missing, vec!["abc"]);
+                assert_eq!(misspelled.len(), 1);
+                assert_eq!(misspelled[0].0, "abcd");
+                assert_eq!(misspelled[0].1, "abc");
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn require_fields() {
+        let meta = str_meta(r"{ b: 2 }");
+        let result = meta.parse_object(|m| {
+            m.require_fields(&["a", "b", "c"])?;
+            Ok(())
+        });
+        match result {
+            Err(FromMetaError::MissingField { missing, .. }) => assert_eq!(missing, vec!["a", "c"]),
+            other => panic!("{:?}", other),
+        }
+    }
+
     #[test]
     fn parse_variant() {
         assert!(matches!(
@@ -13,6 +13,8 @@ pub mod meta;
 
 pub mod pseudo;
 
+pub mod build;
+
 // =============================================================================
 
 /// Type used in the AST for the span of a single token with no useful data.
@@ -22,22 +24,75 @@ pub mod pseudo;
 pub type TokenSpan = Sp<()>;
 
 /// Represents a complete script file.
+///
+/// # Serialization
+///
+/// With the `serde` feature, the AST can be serialized and deserialized.  Information that is only
+/// meaningful to the [`CompilerContext`][crate::CompilerContext] that produced it ([`NodeId`]s,
+/// [`ResId`][crate::resolve::ResId]s, [`LoopId`]s, cached difficulty masks, and language hints)
+/// is not serialized.  Before a deserialized AST can be given to any passes, the IDs must be filled
+/// in again using [`Truth::fill_missing_ids`][crate::Truth::fill_missing_ids]. (the rest is
+/// recomputed by passes that run during compilation)
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ScriptFile {
     pub mapfiles: Vec<Sp<LitString>>,
     pub image_sources: Vec<Sp<LitString>>,
+    pub reg_pragmas: Vec<Sp<RegPragma>>,
     pub items: Vec<Sp<Item>>,
+    /// Comments from the source text, in order of appearance.  These are only used by the formatter,
+    /// which places them around the statements and items that they were written next to.
+    pub comments: Vec<Sp<Comment>>,
+}
+
+/// A `#pragma reg` line, which names a register like an entry of `!gvar_names` in a mapfile.
+///
+/// E.g. `#pragma reg anm $my_counter = 10005;`.  The optional sigil plays the role of `!gvar_types`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegPragma {
+    /// The language of the register, written like the prefix of `anm.REG[10000]`.
+    pub language: Sp<Ident>,
+    pub ty_sigil: Option<Sp<VarSigil>>,
+    pub ident: Sp<Ident>,
+    pub reg: Sp<i32>,
 }
 
+/// A comment in a script file, recorded by the parser.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Comment {
+    /// The full text of the comment, including the `//` or `/* */`.  It may span multiple lines.
+    pub text: String,
+    /// For a comment that follows other code on the same line, the end of that code.
+    ///
+    /// Such a comment is considered to belong to the statement or item that ends there.  Comments on
+    /// their own lines belong to whatever follows them.
+    #[cfg_attr(feature = "serde", serde(with = "crate::pos::serde_opt_byte_pos"))]
+    pub trailing_after: Option<crate::pos::BytePos>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Item {
     Func(ItemFunc),
     AnmScript {
         keyword: TokenSpan,
         number: Option<Sp<raw::LangInt>>,
         ident: Sp<Ident>,  // not `ResIdent` because it doesn't define something in all languages
+        /// `script 3 script3 meta { corrupt: true } {}`.  Only ANM uses this, to mark a script that was
+        /// decompiled in place of one that couldn't be read from a corrupted file.
+        #[cfg_attr(feature = "serde", serde(with = "meta::serde_opt_fields"))]
+        meta: Option<Sp<meta::Fields>>,
         code: Block,
     },
+    /// `script 5 script5 = script2;`, an ANM script that shares the body of another script in its entry.
+    AnmScriptAlias {
+        keyword: TokenSpan,
+        number: Option<Sp<raw::LangInt>>,
+        ident: Sp<Ident>,
+        target: Sp<Ident>,
+    },
     Timeline {
         keyword: TokenSpan,
         number: Option<Sp<raw::LangInt>>,
@@ -46,6 +101,7 @@ pub enum Item {
     },
     Meta {
         keyword: Sp<MetaKeyword>,
+        #[cfg_attr(feature = "serde", serde(with = "meta::serde_fields"))]
         fields: Sp<meta::Fields>,
     },
     ConstVar {
@@ -55,6 +111,7 @@ pub enum Item {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ItemFunc {
     pub qualifier: Option<Sp<FuncQualifier>>,
     pub ty_keyword: Sp<TypeKeyword>,
@@ -65,6 +122,7 @@ pub struct ItemFunc {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FuncParam {
     pub qualifier: Option<Sp<ParamQualifier>>,
     pub ty_keyword: Sp<TypeKeyword>,
@@ -83,6 +141,7 @@ impl Item {
         Item::Func(ItemFunc { qualifier: Some(sp_pat![token![inline]]), .. }) => "inline function definition",
         Item::Func(ItemFunc { qualifier: None, .. }) => "exported function definition",
         Item::AnmScript { .. } => "script",
+        Item::AnmScriptAlias { .. } => "script alias",
         Item::Timeline { .. } => "timeline",
         Item::Meta { .. } => "meta",
         Item::ConstVar { .. } => "const definition",
@@ -110,22 +169,32 @@ string_enum! {
 // =============================================================================
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Stmt {
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub node_id: Option<NodeId>,
     pub diff_label: Option<Sp<DiffLabel>>,
     pub kind: StmtKind,
 }
 
-/// Difficulty label. `{"ENH"}:`
+/// Difficulty label. `{"ENH"}:`, `{0x83}:` or `{MIDBOSS_DIFFS}:`
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DiffLabel {
     /// Cached bitflag form of the difficulty mask.  This may be `None` before
     /// [`crate::passes::resolution::compute_diff_label_masks`] runs.
+    ///
+    /// For a numeric label, this is the mask itself, and is always present.
     pub mask: Option<crate::bitset::BitSet32>,
-    pub string: Sp<LitString>,
+    /// The expression in the braces.  `None` for a numeric label.
+    ///
+    /// This is usually a string of difficulty flag names, but it can be any constant expression
+    /// of string type (flag names) or integer type (a mask).
+    pub expr: Option<Sp<Expr>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StmtKind {
     /// Some items are allowed to appear as statements. (`const`s and functions)
     Item(Box<Sp<Item>>),
@@ -151,6 +220,7 @@ pub enum StmtKind {
 
     /// Unconditional loop.  `loop { ... }`
     Loop {
+        #[cfg_attr(feature = "serde", serde(skip))]
         loop_id: Option<LoopId>,
         keyword: TokenSpan,
         block: Block,
@@ -158,6 +228,7 @@ pub enum StmtKind {
 
     /// While loop.  `while (...) { ... }` or `do { ... } while (...);`
     While {
+        #[cfg_attr(feature = "serde", serde(skip))]
         loop_id: Option<LoopId>,
         while_keyword: TokenSpan,
         do_keyword: Option<TokenSpan>,
@@ -166,9 +237,14 @@ pub enum StmtKind {
     },
 
     /// Times loop.  `times(n) { ... }`
+    ///
+    /// `times(inline n) { ... }` requests that the loop be unrolled.
+    /// (see [`crate::passes::unroll_times`])
     Times {
+        #[cfg_attr(feature = "serde", serde(skip))]
         loop_id: Option<LoopId>,
         keyword: TokenSpan,
+        inline_keyword: Option<TokenSpan>,
         clobber: Option<Sp<Var>>,
         count: Sp<Expr>,
         block: Block,
@@ -201,15 +277,16 @@ pub enum StmtKind {
     ///
     /// Will always have at least one of either the `@` or `async`.
     /// (otherwise, it will fall under `Expr` instead)
+    ///
+    /// The call never has pseudo-args.
     CallSub {
         at_symbol: bool,
         async_: Option<CallAsyncKind>,
-        func: Sp<Ident>,
-        args: Vec<Sp<Expr>>,
+        call: ExprCall,
     },
 
-    /// An interrupt label: `interrupt[2]:`.
-    InterruptLabel(Sp<raw::LangInt>),
+    /// An interrupt label: `interrupt[2]:`.  The id must be a compile-time constant.
+    InterruptLabel(Sp<Expr>),
 
     /// An absolute time label: `30:` or `-30:`.
     AbsTimeLabel(Sp<raw::LangInt>),
@@ -274,6 +351,7 @@ impl StmtKind {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum StmtJumpKind {
     /// The body of a `goto` statement, without the `;`.
     Goto(StmtGoto) ,
@@ -282,11 +360,13 @@ pub enum StmtJumpKind {
         keyword: Sp<BreakContinueKeyword>,
         /// This is used to prevent or detect bugs where a `break` or `continue` could somehow
         /// end up referring to the wrong loop after a code transformation.
+        #[cfg_attr(feature = "serde", serde(skip))]
         loop_id: Option<LoopId>,
     },
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StmtGoto {
     pub destination: Sp<Ident>,
     pub time: Option<Sp<raw::LangInt>>,
@@ -303,6 +383,7 @@ string_enum! {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StmtCondChain {
     pub cond_blocks: Vec<CondBlock>,
     pub else_block: Option<Block>,
@@ -316,6 +397,7 @@ impl StmtCondChain {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CondBlock {
     pub keyword: Sp<CondKeyword>,
     pub cond: Sp<Expr>,
@@ -323,9 +405,21 @@ pub struct CondBlock {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CallAsyncKind {
-    CallAsync,
-    CallAsyncId(Box<Sp<Expr>>),
+    /// `async`
+    CallAsync { keyword: TokenSpan },
+    /// `async <id>`, which runs the sub in a numbered slot.
+    CallAsyncId { keyword: TokenSpan, id: Box<Sp<Expr>> },
+}
+
+impl CallAsyncKind {
+    pub fn keyword_span(&self) -> Span {
+        match *self {
+            CallAsyncKind::CallAsync { keyword } => keyword.span,
+            CallAsyncKind::CallAsyncId { keyword, .. } => keyword.span,
+        }
+    }
 }
 
 string_enum! {
@@ -411,6 +505,7 @@ impl AssignOpKind {
 /// Every Block always has at least two [`Stmt`]s, as on creation it is bookended by dummy
 /// statements to ensure it has a well-defined start and end time.
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Block(pub Vec<Sp<Stmt>>);
 
 impl Block {
@@ -438,6 +533,7 @@ impl Block {
 // =============================================================================
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Expr {
     Ternary {
         cond: Box<Sp<Expr>>,
@@ -461,12 +557,15 @@ pub enum Expr {
     LitInt {
         value: raw::LangInt,
         /// A hint to the formatter on how it should write the integer.
-        /// (may not necessarily represent the original radix of a parsed token)
+        /// (for a parsed token, this is the radix it was written in)
         radix: IntRadix,
     },
     LitFloat { value: raw::LangFloat },
     LitString(LitString),
+    /// `offsetof(label)` or `timeof(label)`.
     LabelProperty {
+        /// For `offsetof(script.label)`, a label in another script of the same file.
+        script: Option<Sp<Ident>>,
         label: Sp<Ident>,
         keyword: Sp<LabelPropertyKeyword>
     },
@@ -477,6 +576,7 @@ pub enum Expr {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum IntRadix {
     /// Display as decimal.
     Dec,
@@ -527,6 +627,7 @@ impl Expr {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ExprCall {
     // note: deliberately called 'name' instead of 'ident' so that you can
     //       match both this and the inner ident without shadowing
@@ -557,6 +658,7 @@ impl ExprCall {
 /// Raw instructions (`ins_`) are separately recognized so that they don't have to take part
 /// in name resolution.  This makes it easier to use the AST VM [`crate::vm::AstVm`].
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CallableName {
     Normal {
         ident: ResIdent,
@@ -564,6 +666,7 @@ pub enum CallableName {
         ///
         /// It is only here so that name resolution can consider instruction aliases; nothing else should ever need it,
         /// as all useful information can be found through the resolved [`DefId`].
+        #[cfg_attr(feature = "serde", serde(skip))]
         language_if_ins: Option<LanguageKey>,
     },
     Ins {
@@ -572,11 +675,17 @@ pub enum CallableName {
         /// It exists to help a variety of other passes look up e.g. type info about raw registers.
         ///
         /// Notably, in ECL, some of these may be set to [`InstrLanguage::Timeline`] instead of [`InstrLanguage::ECL`].
+        #[cfg_attr(feature = "serde", serde(skip))]
         language: Option<LanguageKey>,
+        /// An explicit language written by the user, as in `timeline.ins_30()`.
+        ///
+        /// When present, [`crate::passes::assign_languages`] uses this instead of inferring the language.
+        language_prefix: Option<Sp<Ident>>,
     },
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Var {
     /// A variable mentioned by name, possibly with a type sigil.
     pub ty_sigil: Option<VarSigil>,
@@ -612,6 +721,7 @@ impl VarName {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VarName {
     Normal {
         ident: ResIdent,
@@ -619,13 +729,19 @@ pub enum VarName {
         ///
         /// It is only here so that name resolution can consider register aliases; nothing else should ever need it,
         /// as all useful information can be found through the resolved [`DefId`].
+        #[cfg_attr(feature = "serde", serde(skip))]
         language_if_reg: Option<LanguageKey>,
     },
     Reg {
         reg: RegId,
         /// This field is `None` until initialized by [`crate::passes::assign_languages`] (after which it is guaranteed to be `Some`).
         /// It exists to help a variety of other passes look up e.g. type info about raw instructions.
+        #[cfg_attr(feature = "serde", serde(skip))]
         language: Option<LanguageKey>,
+        /// An explicit language written by the user, as in `timeline.REG[10000]`.
+        ///
+        /// When present, [`crate::passes::assign_languages`] uses this instead of inferring the language.
+        language_prefix: Option<Sp<Ident>>,
     },
 }
 
@@ -776,6 +892,7 @@ impl XcrementOpKind {
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(strum::EnumIter)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum XcrementOpOrder { Pre, Post }
 
 impl XcrementOpOrder {
@@ -815,6 +932,7 @@ impl OpClass {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PseudoArg {
     pub at_sign: Sp<()>,
     pub kind: Sp<PseudoArgKind>,
@@ -836,6 +954,7 @@ string_enum! {
         #[strum(serialize = "pop")] Pop,
         #[strum(serialize = "blob")] Blob,
         #[strum(serialize = "arg0")] ExtraArg,
+        #[strum(serialize = "nargs")] ArgCount,
     }
 }
 
@@ -960,6 +1079,7 @@ impl From<VarSigil> for value::ScalarType {
 
 /// A string literal.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LitString {
     pub string: String,
 }
@@ -978,7 +1098,8 @@ impl std::fmt::Display for CallableName {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             CallableName::Normal { ident, language_if_ins: _ } => fmt::Display::fmt(ident, f),
-            CallableName::Ins { opcode, language: _ } => write!(f, "ins_{}", opcode),
+            CallableName::Ins { opcode, language: _, language_prefix: None } => write!(f, "ins_{}", opcode),
+            CallableName::Ins { opcode, language: _, language_prefix: Some(prefix) } => write!(f, "{}.ins_{}", prefix, opcode),
         }
     }
 }
@@ -1029,6 +1150,10 @@ macro_rules! generate_visitor_stuff {
             fn visit_stmt(&mut self, e: & $($mut)? Sp<Stmt>) { walk_stmt(self, e) }
             fn visit_jump(&mut self, e: & $($mut)? StmtJumpKind) { walk_jump(self, e) }
             fn visit_expr(&mut self, e: & $($mut)? Sp<Expr>) { walk_expr(self, e) }
+            /// Called on function calls, both in expressions and in `@` sub calls.
+            ///
+            /// The default definition visits the name, the pseudo-args, and then the positional args.
+            fn visit_call(&mut self, e: & $($mut)? ExprCall) { walk_call(self, e) }
             /// Called on expressions that appear in conditions for e.g. `if`/`while`.
             ///
             /// The default definition simply delegates to [`Self::visit_expr`].
@@ -1037,6 +1162,8 @@ macro_rules! generate_visitor_stuff {
             fn visit_callable_name(&mut self, e: & $($mut)? Sp<CallableName>) { walk_callable_name(self, e) }
             fn visit_meta(&mut self, e: & $($mut)? Sp<meta::Meta>) { walk_meta(self, e) }
             fn visit_res_ident(&mut self, _: & $($mut)? ResIdent) { }
+            /// Called on the difficulty label of a statement, before the rest of the statement.
+            fn visit_diff_label(&mut self, e: & $($mut)? Sp<DiffLabel>) { walk_diff_label(self, e) }
             fn visit_node_id(&mut self, _: & $($mut)? Option<NodeId>) { }
             // this is factored out like this because otherwise the caller would have to repeat
             // the logic for matching over the different loop types.
@@ -1072,9 +1199,13 @@ macro_rules! generate_visitor_stuff {
                         }
                     }
                 },
-                Item::AnmScript { keyword: _, number: _, ident: _, code } => {
+                Item::AnmScript { keyword: _, number: _, ident: _, meta, code } => {
+                    if let Some(fields) = meta {
+                        walk_meta_fields(v, fields);
+                    }
                     v.visit_root_block(code);
                 },
+                Item::AnmScriptAlias { keyword: _, number: _, ident: _, target: _ } => {},
                 Item::Timeline { keyword: _, number: _, ident: _, code } => {
                     v.visit_root_block(code);
                 },
@@ -1127,6 +1258,15 @@ macro_rules! generate_visitor_stuff {
             }
         }
 
+        pub fn walk_diff_label<V>(v: &mut V, x: & $($mut)? Sp<DiffLabel>)
+        where V: ?Sized + $Visit,
+        {
+            let DiffLabel { mask: _, expr } = & $($mut)? x.value;
+            if let Some(expr) = expr {
+                v.visit_expr(expr);
+            }
+        }
+
         pub fn walk_stmt<V>(v: &mut V, x: & $($mut)? Sp<Stmt>)
         where V: ?Sized + $Visit,
         {
@@ -1135,8 +1275,7 @@ macro_rules! generate_visitor_stuff {
             v.visit_node_id(node_id);
 
             if let Some(diff_label) = diff_label {
-                let DiffLabel { string, mask: _ } = & $($mut)? diff_label.value;
-                let _: Sp<LitString> = *string;
+                v.visit_diff_label(diff_label);
             }
 
             match kind {
@@ -1180,7 +1319,7 @@ macro_rules! generate_visitor_stuff {
                     v.visit_loop_end(loop_id);
                     v.visit_cond(cond);
                 },
-                StmtKind::Times { clobber, count, block, loop_id, keyword: _ } => {
+                StmtKind::Times { clobber, count, block, loop_id, keyword: _, inline_keyword: _ } => {
                     if let Some(clobber) = clobber {
                         v.visit_var(clobber);
                     }
@@ -1207,13 +1346,14 @@ macro_rules! generate_visitor_stuff {
                         }
                     }
                 },
-                StmtKind::CallSub { at_symbol: _, async_: _, func: _, args } => {
-                    for arg in args {
-                        v.visit_expr(arg);
+                StmtKind::CallSub { at_symbol: _, async_, call } => {
+                    v.visit_call(call);
+                    if let Some(CallAsyncKind::CallAsyncId { keyword: _, id }) = async_ {
+                        v.visit_expr(id);
                     }
                 },
                 StmtKind::Label(_) => {},
-                StmtKind::InterruptLabel(_) => {},
+                StmtKind::InterruptLabel(id) => v.visit_expr(id),
                 StmtKind::AbsTimeLabel { .. } => {},
                 StmtKind::RelTimeLabel { .. } => {},
                 StmtKind::ScopeEnd(_) => {},
@@ -1253,15 +1393,7 @@ macro_rules! generate_visitor_stuff {
                         }
                     }
                 },
-                Expr::Call(ExprCall { name, args, pseudos }) => {
-                    v.visit_callable_name(name);
-                    for sp_pat![PseudoArg { value, kind: _, at_sign: _, eq_sign: _ }] in pseudos {
-                        v.visit_expr(value);
-                    }
-                    for arg in args {
-                        v.visit_expr(arg);
-                    }
-                },
+                Expr::Call(call) => v.visit_call(call),
                 Expr::UnOp(_op, x) => v.visit_expr(x),
                 Expr::XcrementOp { op: _, order: _, var } => {
                     v.visit_var(var);
@@ -1277,12 +1409,25 @@ macro_rules! generate_visitor_stuff {
             }
         }
 
+        pub fn walk_call<V>(v: &mut V, x: & $($mut)? ExprCall)
+        where V: ?Sized + $Visit,
+        {
+            let ExprCall { name, args, pseudos } = x;
+            v.visit_callable_name(name);
+            for sp_pat![PseudoArg { value, kind: _, at_sign: _, eq_sign: _ }] in pseudos {
+                v.visit_expr(value);
+            }
+            for arg in args {
+                v.visit_expr(arg);
+            }
+        }
+
         pub fn walk_callable_name<V>(v: &mut V, x: & $($mut)? Sp<CallableName>)
         where V: ?Sized + $Visit,
         {
             match & $($mut)? x.value {
                 CallableName::Normal { language_if_ins: _, ident } => v.visit_res_ident(ident),
-                CallableName::Ins { language: _, opcode: _ } => {},
+                CallableName::Ins { language: _, language_prefix: _, opcode: _ } => {},
             }
         }
 
@@ -1292,7 +1437,7 @@ macro_rules! generate_visitor_stuff {
             let Var { name, ty_sigil: _ } = & $($mut)? x.value;
             match name {
                 VarName::Normal { language_if_reg: _, ident } => v.visit_res_ident(ident),
-                VarName::Reg { language: _, reg: _ } => {},
+                VarName::Reg { language: _, language_prefix: _, reg: _ } => {},
             }
         }
     };
@@ -1339,6 +1484,7 @@ mod mut_ {
 pub use self::mut_::{
     VisitMut,
     walk_block as walk_block_mut,
+    walk_call as walk_call_mut,
     walk_callable_name as walk_callable_name_mut,
     walk_expr as walk_expr_mut,
     walk_file as walk_file_mut,
@@ -1353,6 +1499,46 @@ mod ref_ {
     generate_visitor_stuff!(Visit, Visitable::visit);
 }
 pub use self::ref_::{
-    Visit, walk_block, walk_callable_name, walk_expr, walk_file, walk_item, walk_jump, walk_meta, walk_stmt,
+    Visit, walk_block, walk_call, walk_callable_name, walk_expr, walk_file, walk_item, walk_jump, walk_meta, walk_stmt,
     walk_var,
 };
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct CountVisitor {
+        calls: u32,
+        diff_labels: u32,
+    }
+
+    impl Visit for CountVisitor {
+        fn visit_call(&mut self, call: &ExprCall) {
+            self.calls += 1;
+            walk_call(self, call);
+        }
+
+        fn visit_diff_label(&mut self, _: &Sp<DiffLabel>) {
+            self.diff_labels += 1;
+        }
+    }
+
+    #[test]
+    fn visit_calls_and_diff_labels() {
+        let mut scope = crate::Builder::new().build();
+        let mut truth = scope.truth();
+        let block = truth.parse::<Block>("<input>", br#"{
+            foo(bar(1), 2);
+            {"EN"}: @qux(baz());
+            {"HL"}: {
+                {"H"}: I0 = 3;
+            }
+        }"#).unwrap();
+
+        let mut visitor = CountVisitor::default();
+        block.visit_with(&mut visitor);
+        assert_eq!(visitor.calls, 4);
+        assert_eq!(visitor.diff_labels, 3);
+    }
+}
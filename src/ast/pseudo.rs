@@ -12,6 +12,7 @@ pub struct PseudoArgData {
     pub blob: Option<Sp<Vec<u8>>>,
     pub pop: Option<Sp<raw::StackPop>>,
     pub extra_arg: Option<Sp<raw::ExtraArg>>,
+    pub arg_count: Option<Sp<raw::ArgCount>>,
 }
 
 impl PseudoArgData {
@@ -20,6 +21,7 @@ impl PseudoArgData {
         let mut extra_arg = None;
         let mut blob = None;
         let mut pop = None;
+        let mut arg_count = None;
 
         for pseudo in pseudos {
             macro_rules! set_option {
@@ -38,8 +40,8 @@ impl PseudoArgData {
                         value.$as_const_method()
                             .map(|const_value| sp!(value.span => const_value))
                             .ok_or_else(|| error!(
-                                message("non-const pseudo-arg"),
-                                primary(value, "non-const pseudo-arg"),
+                                message("pseudo-arg must be a compile-time constant"),
+                                primary(value, "not constant"),
                             ))?
                     });
                 }}
@@ -50,6 +52,7 @@ impl PseudoArgData {
                 ast::PseudoArgKind::Mask => set_option!(param_mask, as_const_int),
                 ast::PseudoArgKind::Pop => set_option!(pop, as_const_int),
                 ast::PseudoArgKind::ExtraArg => set_option!(extra_arg, as_const_int),
+                ast::PseudoArgKind::ArgCount => set_option!(arg_count, as_const_int),
             }
         }
 
@@ -58,6 +61,7 @@ impl PseudoArgData {
             param_mask: param_mask.map(|x| sp!(x.span => x.value as _)),
             pop: pop.map(|x| sp!(x.span => x.value as _)),
             extra_arg: extra_arg.map(|x| sp!(x.span => x.value as _)),
+            arg_count: arg_count.map(|x| sp!(x.span => x.value as _)),
         })
     }
 }
@@ -67,6 +71,13 @@ fn parse_args_blob(str: Sp<&str>) -> Result<Vec<u8>, Diagnostic> {
     let mut first_char = None;
     for c in str.chars() {
         if c.is_ascii_whitespace() {
+            if first_char.is_some() {
+                return Err(error!(
+                    message("whitespace in the middle of a byte in blob literal"),
+                    primary(str, "invalid blob literal"),
+                    note("whitespace is only allowed between pairs of hexadecimal digits"),
+                ));
+            }
             continue;
         }
         let value = match c {
@@ -92,19 +103,15 @@ fn parse_args_blob(str: Sp<&str>) -> Result<Vec<u8>, Diagnostic> {
         ));
     }
 
-    if out.len() % 4 != 0 {
-        return Err(error!(
-            message("number of bytes in blob not divisible by 4"),
-            primary(str, "blob literal of length {}", out.len()),
-        ))
-    }
-
+    // (the length is checked during lowering, as it depends on the format)
     Ok(out)
 }
 
 #[test]
 fn test_parse_args_blob() {
-    assert_eq!(parse_args_blob(sp!("0b0 C0d21")).unwrap(), vec![11, 12, 13, 33]);
+    assert_eq!(parse_args_blob(sp!("0b0C 0d 21")).unwrap(), vec![11, 12, 13, 33]);
+    assert_eq!(parse_args_blob(sp!(" 0b0c0d21 ")).unwrap(), vec![11, 12, 13, 33]);
+    assert!(parse_args_blob(sp!("0b0 C0d21")).is_err());  // space inside a byte
     assert!(parse_args_blob(sp!("0b0c0d2")).is_err());  // odd length
     assert!(parse_args_blob(sp!("0b0c==0d21")).is_err());  // bad character
 }
@@ -0,0 +1,535 @@
+//! Structural comparison of two binary files of the same format.
+//!
+//! See [`Truth::diff_from_bytes`][crate::Truth::diff_from_bytes].
+
+use std::fmt;
+
+use crate::context::CompilerContext;
+use crate::diagnostic::Emitter;
+use crate::error::ErrorReported;
+use crate::game::{Game, LanguageKey};
+use crate::ident::Ident;
+use crate::llir::{ArgEncoding, LanguageHooks, RawInstr, SimpleArg};
+use crate::passes::semantics::time_and_difficulty::DEFAULT_DIFFICULTY_MASK_BYTE;
+use crate::raw;
+use crate::value::ScalarValue;
+
+/// After two scripts differ, this many consecutive equal instructions are required before the
+/// scripts are considered to be back in sync.
+const RESYNC_RUN_LEN: usize = 3;
+
+/// All differences between two binary files, in order of appearance.
+///
+/// Only things that contain instructions are compared; e.g. differences in ANM sprites or STD objects
+/// are not reported.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BinDiff {
+    pub changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// An ANM entry that only exists in the new file.  Entries are named by their image path.
+    EntryAdded { name: String },
+    /// An ANM entry that only exists in the old file.
+    EntryRemoved { name: String },
+    /// An ANM entry whose image path differs.
+    EntryRenamed { old_name: String, new_name: String },
+    /// A script, sub, or timeline that only exists in the new file.
+    ScriptAdded { name: String },
+    /// A script, sub, or timeline that only exists in the old file.
+    ScriptRemoved { name: String },
+    /// A script whose instructions are identical, but which has moved to a different name.
+    ///
+    /// In binary files, most names are derived from an index, so this usually means that
+    /// something was added or removed before it.
+    ScriptRenamed { old_name: String, new_name: String },
+    /// A script whose instructions differ.
+    ScriptModified(ScriptDiff),
+}
+
+/// Differences between the instructions of two versions of a script.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptDiff {
+    pub old_name: String,
+    pub new_name: String,
+    /// Runs of differing instructions, in order.  Never empty.
+    pub hunks: Vec<Hunk>,
+}
+
+/// A run of instructions that differ between two scripts, followed either by the end of both scripts,
+/// or by a run of equal instructions.
+///
+/// One side may be empty, if instructions were only inserted or deleted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// Index in the old script of the first instruction in this hunk.
+    pub old_index: usize,
+    /// Index in the new script of the first instruction in this hunk.
+    pub new_index: usize,
+    pub old: Vec<DecodedInstr>,
+    pub new: Vec<DecodedInstr>,
+}
+
+/// An instruction whose arguments have been decoded according to its signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstr {
+    /// Index of the instruction within its script.
+    pub index: usize,
+    /// Offset of the instruction from the beginning of its script.
+    pub offset: raw::BytePos,
+    pub time: raw::Time,
+    pub opcode: raw::Opcode,
+    /// Alias of the instruction from a mapfile, if there is one.
+    pub name: Option<Ident>,
+    pub param_mask: raw::ParamMask,
+    pub difficulty: raw::DifficultyMask,
+    pub pop: raw::StackPop,
+    pub arg_count: raw::ArgCount,
+    pub extra_arg: Option<raw::ExtraArg>,
+    /// `None` if the instruction has no signature, or if its arguments could not be decoded using it.
+    pub args: Option<Vec<SimpleArg>>,
+    pub args_blob: Vec<u8>,
+    /// For instructions with a jump offset argument, the index of the instruction it jumps to.
+    /// (this may be equal to the number of instructions, for a jump to the end of the script)
+    pub jump_target: Option<usize>,
+}
+
+impl BinDiff {
+    /// Returns `true` if no differences were found.
+    pub fn is_empty(&self) -> bool { self.changes.is_empty() }
+}
+
+impl ScriptDiff {
+    /// Get the first instruction that differs in each file.  One of them may be `None` if the
+    /// first difference is an insertion or deletion.
+    pub fn first_difference(&self) -> (Option<&DecodedInstr>, Option<&DecodedInstr>) {
+        let hunk = &self.hunks[0];
+        (hunk.old.first(), hunk.new.first())
+    }
+}
+
+// =============================================================================
+// Display
+
+impl fmt::Display for BinDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{change}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::EntryAdded { name } => write!(f, "entry added: {name}"),
+            Change::EntryRemoved { name } => write!(f, "entry removed: {name}"),
+            Change::EntryRenamed { old_name, new_name } => write!(f, "entry renamed: {old_name} -> {new_name}"),
+            Change::ScriptAdded { name } => write!(f, "script added: {name}"),
+            Change::ScriptRemoved { name } => write!(f, "script removed: {name}"),
+            Change::ScriptRenamed { old_name, new_name } => write!(f, "script renamed: {old_name} -> {new_name}"),
+            Change::ScriptModified(diff) => write!(f, "{diff}"),
+        }
+    }
+}
+
+impl fmt::Display for ScriptDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.old_name == self.new_name {
+            true => write!(f, "script modified: {}", self.old_name)?,
+            false => write!(f, "script modified: {} -> {}", self.old_name, self.new_name)?,
+        }
+        for hunk in &self.hunks {
+            write!(f, "\n  at old #{}, new #{}:", hunk.old_index, hunk.new_index)?;
+            for instr in &hunk.old {
+                write!(f, "\n  - {instr}")?;
+            }
+            for instr in &hunk.new {
+                write!(f, "\n  + {instr}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DecodedInstr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{} @{:#x} t={}: ", self.index, self.offset, self.time)?;
+        match &self.name {
+            Some(name) => write!(f, "{name}")?,
+            None => write!(f, "ins_{}", self.opcode)?,
+        }
+
+        let mut pseudos = vec![];
+        if self.param_mask != 0 {
+            pseudos.push(format!("@mask={:#b}", self.param_mask));
+        }
+        if self.pop != 0 {
+            pseudos.push(format!("@pop={}", self.pop));
+        }
+        if self.arg_count != 0 {
+            pseudos.push(format!("@nargs={}", self.arg_count));
+        }
+        let args = match &self.args {
+            Some(args) => args.iter().map(|arg| match arg.is_reg {
+                true => format!("[{}]", arg.value),
+                false => format!("{}", arg.value),
+            }).collect(),
+            None => {
+                if let Some(extra_arg) = self.extra_arg {
+                    pseudos.push(format!("@arg0={extra_arg}"));
+                }
+                pseudos.push(format!("@blob=\"{}\"", crate::ast::pseudo::format_blob(&self.args_blob)));
+                vec![]
+            },
+        };
+        write!(f, "({})", pseudos.into_iter().chain(args).collect::<Vec<_>>().join(", "))?;
+
+        if let Some(target) = self.jump_target {
+            write!(f, " -> #{target}")?;
+        }
+        if self.difficulty != DEFAULT_DIFFICULTY_MASK_BYTE {
+            write!(f, " difficulty={:#04x}", self.difficulty)?;
+        }
+        Ok(())
+    }
+}
+
+// =============================================================================
+// Gathering the things to compare from each format
+
+/// The parts of a binary file that get compared.
+struct DiffableFile<'a> {
+    /// Names of ANM entries.  Empty in other formats.
+    entries: Vec<String>,
+    scripts: Vec<DiffableScript<'a>>,
+}
+
+struct DiffableScript<'a> {
+    name: String,
+    instrs: &'a [RawInstr],
+    hooks: &'a dyn LanguageHooks,
+}
+
+pub(crate) fn diff_anm(emitter: &impl Emitter, ctx: &CompilerContext, game: Game, old: &crate::AnmFile, new: &crate::AnmFile) -> BinDiff {
+    fn to_diffable<'a>(file: &'a crate::AnmFile, hooks: &'a dyn LanguageHooks) -> DiffableFile<'a> {
+        DiffableFile {
+            entries: file.entries.iter().map(|entry| entry.path.value.clone()).collect(),
+            scripts: file.entries.iter().flat_map(|entry| &entry.scripts).map(|(name, script)| {
+                DiffableScript { name: name.to_string(), instrs: &script.instrs, hooks }
+            }).collect(),
+        }
+    }
+    let hooks = crate::anm::game_hooks(game);
+    diff_files(emitter, ctx, &to_diffable(old, &*hooks), &to_diffable(new, &*hooks))
+}
+
+pub(crate) fn diff_std(emitter: &impl Emitter, ctx: &CompilerContext, game: Game, old: &crate::StdFile, new: &crate::StdFile) -> BinDiff {
+    fn to_diffable<'a>(file: &'a crate::StdFile, hooks: &'a dyn LanguageHooks) -> DiffableFile<'a> {
+        DiffableFile {
+            entries: vec![],
+            scripts: vec![DiffableScript { name: "main".to_string(), instrs: &file.script, hooks }],
+        }
+    }
+    let hooks = crate::std::game_hooks(game);
+    diff_files(emitter, ctx, &to_diffable(old, &*hooks), &to_diffable(new, &*hooks))
+}
+
+pub(crate) fn diff_msg(emitter: &impl Emitter, ctx: &CompilerContext, game: Game, language: LanguageKey, old: &crate::MsgFile, new: &crate::MsgFile) -> BinDiff {
+    fn to_diffable<'a>(file: &'a crate::MsgFile, hooks: &'a dyn LanguageHooks) -> DiffableFile<'a> {
+        DiffableFile {
+            entries: vec![],
+            scripts: file.scripts.iter().map(|(name, instrs)| {
+                DiffableScript { name: name.to_string(), instrs, hooks }
+            }).collect(),
+        }
+    }
+    let hooks = crate::msg::game_hooks(game, language);
+    diff_files(emitter, ctx, &to_diffable(old, &*hooks), &to_diffable(new, &*hooks))
+}
+
+pub(crate) fn diff_ecl(emitter: &impl Emitter, ctx: &CompilerContext, game: Game, old: &crate::EclFile, new: &crate::EclFile) -> Result<BinDiff, ErrorReported> {
+    fn to_diffable<'a>(file: &'a crate::EclFile, ecl_hooks: &'a dyn LanguageHooks, timeline_hooks: Option<&'a dyn LanguageHooks>) -> DiffableFile<'a> {
+        let timelines = timeline_hooks.into_iter().flat_map(|hooks| file.timelines.iter().enumerate().map(move |(index, instrs)| {
+            DiffableScript { name: format!("timeline {index}"), instrs, hooks }
+        }));
+        let subs = file.subs.iter().map(|(name, instrs)| {
+            DiffableScript { name: name.to_string(), instrs, hooks: ecl_hooks }
+        });
+        DiffableFile { entries: vec![], scripts: timelines.chain(subs).collect() }
+    }
+    let (ecl_hooks, timeline_hooks) = crate::ecl::game_hooks(game, emitter)?;
+    let old = to_diffable(old, &*ecl_hooks, timeline_hooks.as_deref());
+    let new = to_diffable(new, &*ecl_hooks, timeline_hooks.as_deref());
+    Ok(diff_files(emitter, ctx, &old, &new))
+}
+
+// =============================================================================
+// Comparison
+
+fn diff_files(emitter: &impl Emitter, ctx: &CompilerContext, old: &DiffableFile, new: &DiffableFile) -> BinDiff {
+    let mut changes = vec![];
+
+    for pair in align(&old.entries, &new.entries, |a, b| a == b) {
+        match pair {
+            (Some(a), Some(b)) => if old.entries[a] != new.entries[b] {
+                changes.push(Change::EntryRenamed { old_name: old.entries[a].clone(), new_name: new.entries[b].clone() });
+            },
+            (Some(a), None) => changes.push(Change::EntryRemoved { name: old.entries[a].clone() }),
+            (None, Some(b)) => changes.push(Change::EntryAdded { name: new.entries[b].clone() }),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    let old_scripts = old.scripts.iter().map(|script| prepare_script(emitter, ctx, script)).collect::<Vec<_>>();
+    let new_scripts = new.scripts.iter().map(|script| prepare_script(emitter, ctx, script)).collect::<Vec<_>>();
+    for pair in align(&old_scripts, &new_scripts, |a, b| a.keys == b.keys) {
+        match pair {
+            (Some(a), Some(b)) => {
+                let (a, b) = (&old_scripts[a], &new_scripts[b]);
+                if a.keys != b.keys {
+                    changes.push(Change::ScriptModified(diff_scripts(a, b)));
+                } else if a.name != b.name {
+                    changes.push(Change::ScriptRenamed { old_name: a.name.clone(), new_name: b.name.clone() });
+                }
+            },
+            (Some(a), None) => changes.push(Change::ScriptRemoved { name: old_scripts[a].name.clone() }),
+            (None, Some(b)) => changes.push(Change::ScriptAdded { name: new_scripts[b].name.clone() }),
+            (None, None) => unreachable!(),
+        }
+    }
+    BinDiff { changes }
+}
+
+struct PreparedScript {
+    name: String,
+    instrs: Vec<DecodedInstr>,
+    keys: Vec<InstrKey>,
+}
+
+/// Everything about an instruction that is compared.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct InstrKey {
+    time: raw::Time,
+    opcode: raw::Opcode,
+    param_mask: raw::ParamMask,
+    difficulty: raw::DifficultyMask,
+    pop: raw::StackPop,
+    arg_count: raw::ArgCount,
+    extra_arg: Option<raw::ExtraArg>,
+    args: ArgsKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArgsKey {
+    /// Most instructions are compared byte-for-byte.
+    Blob(Vec<u8>),
+    /// Instructions with jumps compare the destination by its distance in instructions, so that
+    /// a jump over unchanged code still compares equal when code elsewhere in the script changes size.
+    Jump(Vec<ArgKey>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ArgKey {
+    Int { value: i32, is_reg: bool },
+    Float { bits: u32, is_reg: bool },
+    String(String),
+    /// A jump offset that points to an instruction.
+    RelativeJump(isize),
+    /// A jump offset that does not point to an instruction.
+    BadJump(raw::BytePos),
+}
+
+fn prepare_script(emitter: &impl Emitter, ctx: &CompilerContext, script: &DiffableScript) -> PreparedScript {
+    let hooks = script.hooks;
+    let language = hooks.language();
+    let emitter = emitter.get_chained_with(|f| write!(f, "in {}", script.name));
+
+    let mut offsets = vec![0];
+    for instr in script.instrs {
+        offsets.push(offsets.last().unwrap() + hooks.instr_format().instr_size(instr) as raw::BytePos);
+    }
+
+    let mut instrs = vec![];
+    let mut keys = vec![];
+    for (index, (instr, &offset)) in script.instrs.iter().zip(&offsets).enumerate() {
+        let emitter = emitter.get_chained_with(|f| write!(f, "instr {index} (opcode {}, offset {offset:#X})", instr.opcode));
+        let args = match crate::llir::decode_args_by_signature(&emitter, hooks, instr, offset, &ctx.defs) {
+            Some(Ok(args)) => Some(args),
+            Some(Err(ErrorReported)) | None => None,
+        };
+
+        let mut jump_target = None;
+        let mut args_key = ArgsKey::Blob(instr.args_blob.clone());
+        if let Some(args) = &args {
+            let (abi, _) = ctx.defs.ins_abi(language, instr.opcode).expect("decoded, so abi is known");
+            if abi.arg_encodings().any(|enc| enc == &ArgEncoding::JumpOffset) {
+                let arg_keys = args.iter().zip(abi.arg_encodings()).map(|(arg, enc)| match (enc, &arg.value) {
+                    (ArgEncoding::JumpOffset, _) => {
+                        let dest_offset = ctx.defs.label_encoding(hooks).decode(&offsets, offset, arg.expect_immediate_int() as raw::RawDwordBits);
+                        match offsets.binary_search(&dest_offset) {
+                            Ok(dest_index) => {
+                                jump_target = Some(dest_index);
+                                ArgKey::RelativeJump(dest_index as isize - index as isize)
+                            },
+                            Err(_) => ArgKey::BadJump(dest_offset),
+                        }
+                    },
+                    (_, &ScalarValue::Int(value)) => ArgKey::Int { value, is_reg: arg.is_reg },
+                    (_, &ScalarValue::Float(value)) => ArgKey::Float { bits: value.to_bits(), is_reg: arg.is_reg },
+                    (_, ScalarValue::String(value)) => ArgKey::String(value.clone()),
+                }).collect();
+                args_key = ArgsKey::Jump(arg_keys);
+            }
+        }
+
+        keys.push(InstrKey {
+            time: instr.time,
+            opcode: instr.opcode,
+            param_mask: instr.param_mask,
+            difficulty: instr.difficulty,
+            pop: instr.pop,
+            arg_count: instr.arg_count,
+            extra_arg: instr.extra_arg,
+            args: args_key,
+        });
+        instrs.push(DecodedInstr {
+            index,
+            offset,
+            time: instr.time,
+            opcode: instr.opcode,
+            name: match ctx.ins_to_ast(language, instr.opcode) {
+                crate::ast::CallableName::Normal { ident, .. } => Some(ident.as_raw().clone()),
+                crate::ast::CallableName::Ins { .. } => None,
+            },
+            param_mask: instr.param_mask,
+            difficulty: instr.difficulty,
+            pop: instr.pop,
+            arg_count: instr.arg_count,
+            extra_arg: instr.extra_arg,
+            args,
+            args_blob: instr.args_blob.clone(),
+            jump_target,
+        });
+    }
+    PreparedScript { name: script.name.clone(), instrs, keys }
+}
+
+fn diff_scripts(old: &PreparedScript, new: &PreparedScript) -> ScriptDiff {
+    let (old_len, new_len) = (old.keys.len(), new.keys.len());
+    let mut hunks = vec![];
+    let (mut i, mut j) = (0, 0);
+    loop {
+        while i < old_len && j < new_len && old.keys[i] == new.keys[j] {
+            i += 1;
+            j += 1;
+        }
+        if i == old_len && j == new_len {
+            break;
+        }
+        let (next_i, next_j) = resync(&old.keys, &new.keys, i, j);
+        hunks.push(Hunk {
+            old_index: i,
+            new_index: j,
+            old: old.instrs[i..next_i].to_vec(),
+            new: new.instrs[j..next_j].to_vec(),
+        });
+        (i, j) = (next_i, next_j);
+    }
+    ScriptDiff { old_name: old.name.clone(), new_name: new.name.clone(), hunks }
+}
+
+/// Given the indices of two differing instructions, find the nearest pair of indices after which
+/// the scripts appear to agree again.
+///
+/// This prefers skipping the fewest total instructions, which handles both modified instructions
+/// and insertions or deletions.  It always succeeds, because the ends of both scripts are in sync.
+fn resync(old: &[InstrKey], new: &[InstrKey], start_i: usize, start_j: usize) -> (usize, usize) {
+    let is_in_sync = |i: usize, j: usize| {
+        for k in 0..RESYNC_RUN_LEN {
+            match (old.get(i + k), new.get(j + k)) {
+                (None, None) => return true,
+                (Some(a), Some(b)) if a == b => {},
+                _ => return false,
+            }
+        }
+        true
+    };
+
+    let max_skip = (old.len() - start_i) + (new.len() - start_j);
+    for skip in 1..=max_skip {
+        for skip_old in 0..=skip {
+            let (i, j) = (start_i + skip_old, start_j + (skip - skip_old));
+            if i <= old.len() && j <= new.len() && is_in_sync(i, j) {
+                return (i, j);
+            }
+        }
+    }
+    unreachable!("the ends of the scripts are always in sync")
+}
+
+/// Pair up the elements of two lists, using a longest common subsequence of equal elements as
+/// anchors.  Elements that lie between two anchors are paired up in order, and any excess are
+/// left unpaired.
+fn align<T>(old: &[T], new: &[T], eq: impl Fn(&T, &T) -> bool) -> Vec<(Option<usize>, Option<usize>)> {
+    let (n, m) = (old.len(), new.len());
+
+    // lcs[i][j] is the length of the LCS of old[i..] and new[j..]
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = match eq(&old[i], &new[j]) {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut gap_old, mut gap_new) = (vec![], vec![]);
+    let flush_gap = |out: &mut Vec<_>, gap_old: &mut Vec<usize>, gap_new: &mut Vec<usize>| {
+        for k in 0..gap_old.len().max(gap_new.len()) {
+            out.push((gap_old.get(k).copied(), gap_new.get(k).copied()));
+        }
+        gap_old.clear();
+        gap_new.clear();
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && eq(&old[i], &new[j]) && lcs[i][j] == lcs[i + 1][j + 1] + 1 {
+            flush_gap(&mut out, &mut gap_old, &mut gap_new);
+            out.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        } else if j == m || (i < n && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            gap_old.push(i);
+            i += 1;
+        } else {
+            gap_new.push(j);
+            j += 1;
+        }
+    }
+    flush_gap(&mut out, &mut gap_old, &mut gap_new);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn align_pairs_gaps_in_order() {
+        let check = |old: &[i32], new: &[i32]| {
+            align(old, new, |a, b| a == b).into_iter()
+                .map(|(a, b)| (a.map(|a| old[a]), b.map(|b| new[b])))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(check(&[1, 2, 3], &[1, 2, 3]), [(Some(1), Some(1)), (Some(2), Some(2)), (Some(3), Some(3))]);
+        assert_eq!(check(&[1, 3], &[1, 2, 3]), [(Some(1), Some(1)), (None, Some(2)), (Some(3), Some(3))]);
+        assert_eq!(check(&[1, 4, 5, 3], &[1, 6, 3]), [(Some(1), Some(1)), (Some(4), Some(6)), (Some(5), None), (Some(3), Some(3))]);
+        assert_eq!(check(&[], &[7]), [(None, Some(7))]);
+    }
+}
@@ -6,6 +6,7 @@ use core::fmt;
 
 /// Bit set backed by a single integer.
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BitSet32(u32);
 
 impl BitSet32 {
@@ -4,7 +4,9 @@ use crate::ast::ScriptFile;
 use crate::api::Truth;
 use crate::game::{Game, LanguageKey};
 use crate::error::ErrorReported;
-use crate::llir::DecompileOptions;
+use crate::llir::{DecompileOptions, MapfilePaths};
+use crate::ident::Ident;
+use crate::passes::float_equality::WarnFloatEquality;
 
 pub fn main(version: &str) -> ! {
     let mut args = std::env::args();
@@ -114,13 +116,21 @@ pub mod anm_decompile {
     use super::*;
 
     pub fn main(version: &str, args: &[String]) -> ! {
-        let (common_options, output, fmt_config) = cli::parse_args(version, args, CmdSpec {
+        let (common_options, dummy_images, script_order, duplicate_shared_scripts, lenient, output) = cli::parse_args(version, args, CmdSpec {
             program: "truanm decompile",
             usage_args: "FILE -g GAME [OPTIONS...]",
-            options: (cli::common_decompile_options(), cli::output(), cli::fmt_config()),
+            options: (
+                cli::common_decompile_options(), cli::dummy_images(), cli::script_order(), cli::duplicate_shared_scripts(),
+                cli::lenient(), cli::output(),
+            ),
         });
+        let mut common_options = common_options.relative_to_output(output.as_deref());
+        common_options.decompile_options.dummy_images = dummy_images;
+        common_options.decompile_options.script_order = script_order;
+        common_options.decompile_options.script_aliases = !duplicate_shared_scripts;
 
-        wrap_decompile_to_stdout(fmt_config, output, |truth| {
+        wrap_decompile_to_stdout(common_options.decompile_options.fmt_config(), output, |truth| {
+            truth.set_lenient_reading(lenient);
             decompile(truth, &common_options)
         });
     }
@@ -130,8 +140,9 @@ pub mod anm_decompile {
         common_options: &CommonDecompileOptions,
     ) -> Result<ScriptFile, ErrorReported> {
         let &CommonDecompileOptions {
-            game, ref in_path, ref mapfile_options, ref decompile_options
+            game, ref in_path, ref mapfile_options, ref decompile_options, num_threads,
         } = common_options;
+        set_num_threads(truth, num_threads);
 
         let mapfile_options = add_env_mapfile_for_decomp(mapfile_options, ".anmm");
         load_mapfiles(truth, game, &[LanguageKey::Anm], &mapfile_options)?;
@@ -173,13 +184,22 @@ pub mod anm_compile {
     use super::*;
 
     pub fn main(version: &str, args: &[String]) -> ! {
-        let (common_options, image_sources, output_thecl_defs) = cli::parse_args(version, args, CmdSpec {
+        let (
+            common_options, image_sources, output_thecl_defs, sprite_bounds, warn_unreferenced, warn_busy_loops, merge_identical_scripts,
+        ) = cli::parse_args(version, args, CmdSpec {
             program: "truanm compile",
             usage_args: "SCRIPT -g GAME -o OUTPUT [OPTIONS...]",
-            options: (cli::common_compile_options(), cli::image_sources(), cli::output_thecl_defs()),
+            options: (
+                cli::common_compile_options(), cli::image_sources(), cli::output_thecl_defs(), cli::sprite_bounds(),
+                cli::warn_unreferenced(), cli::warn_busy_loops(), cli::merge_identical_scripts(),
+            ),
         });
 
-        wrap_exit_code(|truth| run(truth, &common_options, &image_sources, output_thecl_defs));
+        wrap_exit_code(|truth| {
+            truth.set_warn_unreferenced_anm_items(warn_unreferenced);
+            truth.set_warn_anm_busy_loops(warn_busy_loops);
+            run(truth, &common_options, &image_sources, output_thecl_defs, sprite_bounds, merge_identical_scripts)
+        });
     }
 
     pub(super) fn run(
@@ -187,10 +207,23 @@ pub mod anm_compile {
         common_options: &CommonCompileOptions,
         cli_image_source_paths: &[PathBuf],
         output_thecl_defs: Option<PathBuf>,
+        sprite_bounds: crate::anm::SpriteBoundsCheck,
+        merge_identical_scripts: bool,
     ) -> Result<(), ErrorReported> {
         let &CommonCompileOptions {
-            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path,
+            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path, ref dump_passes_path, num_threads,
+            no_goto_time_warnings, no_time_decrease_warnings, no_blob_signature_warnings, warn_float_equality, max_unroll, register_report, script_report,
         } = common_options;
+        set_num_threads(truth, num_threads);
+        truth.set_warn_goto_times(!no_goto_time_warnings);
+        truth.set_warn_time_decreases(!no_time_decrease_warnings);
+        truth.set_warn_blob_signatures(!no_blob_signature_warnings);
+        truth.set_warn_float_equality(warn_float_equality);
+        if let Some(max_unroll) = max_unroll {
+            truth.set_max_unroll_count(max_unroll);
+        }
+        truth.set_report_register_pressure(register_report);
+        add_dump_passes_observer(truth, dump_passes_path)?;
         load_mapfiles(truth, game, &[LanguageKey::Anm], mapfile_options)?;
 
         let ast = truth.read_script(&in_path)?;
@@ -208,6 +241,8 @@ pub mod anm_compile {
             compiled.apply_image_source(source_anm, &truth.fs())?;
         }
 
+        compiled.set_sprite_bounds_check(sprite_bounds);
+        compiled.set_merge_identical_scripts(merge_identical_scripts);
         let compiled = truth.finalize_anm(game, compiled)?;
         truth.write_anm(game, &out_path, &compiled)?;
 
@@ -218,6 +253,9 @@ pub mod anm_compile {
         if let Some(debug_info_path) = debug_info_path {
             truth.prepare_and_write_debug_info(debug_info_path)?;
         }
+        if script_report {
+            print!("{}", truth.script_stats());
+        }
 
         Ok(())
     }
@@ -266,8 +304,19 @@ pub mod ecl_compile {
         common_options: &CommonCompileOptions,
     ) -> Result<(), ErrorReported> {
         let &CommonCompileOptions {
-            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path,
+            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path, ref dump_passes_path, num_threads,
+            no_goto_time_warnings, no_time_decrease_warnings, no_blob_signature_warnings, warn_float_equality, max_unroll, register_report, script_report,
         } = common_options;
+        set_num_threads(truth, num_threads);
+        truth.set_warn_goto_times(!no_goto_time_warnings);
+        truth.set_warn_time_decreases(!no_time_decrease_warnings);
+        truth.set_warn_blob_signatures(!no_blob_signature_warnings);
+        truth.set_warn_float_equality(warn_float_equality);
+        if let Some(max_unroll) = max_unroll {
+            truth.set_max_unroll_count(max_unroll);
+        }
+        truth.set_report_register_pressure(register_report);
+        add_dump_passes_observer(truth, dump_passes_path)?;
 
         load_mapfiles(truth, game, &[LanguageKey::Ecl, LanguageKey::Timeline], mapfile_options)?;
 
@@ -281,6 +330,9 @@ pub mod ecl_compile {
         if let Some(debug_info_path) = debug_info_path {
             truth.prepare_and_write_debug_info(debug_info_path)?;
         }
+        if script_report {
+            print!("{}", truth.script_stats());
+        }
         Ok(())
     }
 }
@@ -289,13 +341,14 @@ pub mod ecl_decompile {
     use super::*;
 
     pub fn main(version: &str, args: &[String]) -> ! {
-        let (common_options, output, fmt_config) = cli::parse_args(version, args, CmdSpec {
+        let (common_options, output) = cli::parse_args(version, args, CmdSpec {
             program: "truecl decompile",
             usage_args: "FILE -g GAME [OPTIONS...]",
-            options: (cli::common_decompile_options(), cli::output(), cli::fmt_config()),
+            options: (cli::common_decompile_options(), cli::output()),
         });
+        let common_options = common_options.relative_to_output(output.as_deref());
 
-        wrap_decompile_to_stdout(fmt_config, output, |truth| {
+        wrap_decompile_to_stdout(common_options.decompile_options.fmt_config(), output, |truth| {
             decompile(truth, &common_options)
         });
     }
@@ -305,8 +358,9 @@ pub mod ecl_decompile {
         common_options: &CommonDecompileOptions,
     ) -> Result<ScriptFile, ErrorReported> {
         let &CommonDecompileOptions {
-            game, ref in_path, ref mapfile_options, ref decompile_options
+            game, ref in_path, ref mapfile_options, ref decompile_options, num_threads,
         } = common_options;
+        set_num_threads(truth, num_threads);
 
         let mapfile_options = add_env_mapfile_for_decomp(mapfile_options, ".eclm");
         load_mapfiles(truth, game, &[LanguageKey::Ecl, LanguageKey::Timeline], &mapfile_options)?;
@@ -373,6 +427,7 @@ pub mod anm_benchmark {
             in_path: anm_path.to_owned(),
             mapfile_options: mapfile_options.clone(),
             decompile_options: decompile_options.clone(),
+            num_threads: None,
         };
         let common_compile_options = CommonCompileOptions {
             game,
@@ -380,11 +435,20 @@ pub mod anm_benchmark {
             out_path: out_path.to_owned(),
             mapfile_options: mapfile_options.clone(),
             debug_info_path: None,
+            dump_passes_path: None,
+            num_threads: None,
+            no_goto_time_warnings: false,
+            no_time_decrease_warnings: false,
+            no_blob_signature_warnings: false,
+            warn_float_equality: Default::default(),
+            max_unroll: None,
+            register_report: false,
+            script_report: false,
         };
         loop {
             let ast = super::anm_decompile::decompile(truth, &common_decompile_options)?;
 
-            let fmt_config = crate::fmt::Config::new().max_columns(100);
+            let fmt_config = common_decompile_options.decompile_options.fmt_config();
             let mut script_out_utf8 = vec![];
             let mut f = crate::Formatter::with_config(&mut script_out_utf8, fmt_config);
             f.fmt(&ast).map_err(|e| truth.emit(error!("{:#}", e)))?;
@@ -392,7 +456,7 @@ pub mod anm_benchmark {
 
             truth.fs().write(script_path, &script_out_utf8)?;
 
-            super::anm_compile::run(truth, &common_compile_options, &image_source_paths, None)?;
+            super::anm_compile::run(truth, &common_compile_options, &image_source_paths, None, crate::anm::SpriteBoundsCheck::Allow, false)?;
         }
     }
 }
@@ -427,6 +491,7 @@ pub mod ecl_benchmark {
             in_path: ecl_path.to_owned(),
             mapfile_options: mapfile_options.clone(),
             decompile_options: decompile_options.clone(),
+            num_threads: None,
         };
         let common_compile_options = CommonCompileOptions {
             game,
@@ -434,11 +499,20 @@ pub mod ecl_benchmark {
             out_path: out_path.to_owned(),
             mapfile_options: mapfile_options.clone(),
             debug_info_path: None,
+            dump_passes_path: None,
+            num_threads: None,
+            no_goto_time_warnings: false,
+            no_time_decrease_warnings: false,
+            no_blob_signature_warnings: false,
+            warn_float_equality: Default::default(),
+            max_unroll: None,
+            register_report: false,
+            script_report: false,
         };
         loop {
             let ast = super::ecl_decompile::decompile(truth, &common_decompile_options)?;
 
-            let fmt_config = crate::fmt::Config::new().max_columns(100);
+            let fmt_config = common_decompile_options.decompile_options.fmt_config();
             let mut script_out_utf8 = vec![];
             let mut f = crate::Formatter::with_config(&mut script_out_utf8, fmt_config);
             f.fmt(&ast).map_err(|e| truth.emit(error!("{:#}", e)))?;
@@ -469,8 +543,19 @@ pub mod std_compile {
         common_options: &CommonCompileOptions,
     ) -> Result<(), ErrorReported> {
         let &CommonCompileOptions {
-            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path,
+            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path, ref dump_passes_path, num_threads,
+            no_goto_time_warnings, no_time_decrease_warnings, no_blob_signature_warnings, warn_float_equality, max_unroll, register_report, script_report,
         } = common_options;
+        set_num_threads(truth, num_threads);
+        truth.set_warn_goto_times(!no_goto_time_warnings);
+        truth.set_warn_time_decreases(!no_time_decrease_warnings);
+        truth.set_warn_blob_signatures(!no_blob_signature_warnings);
+        truth.set_warn_float_equality(warn_float_equality);
+        if let Some(max_unroll) = max_unroll {
+            truth.set_max_unroll_count(max_unroll);
+        }
+        truth.set_report_register_pressure(register_report);
+        add_dump_passes_observer(truth, dump_passes_path)?;
 
         load_mapfiles(truth, game, &[LanguageKey::Std], mapfile_options)?;
 
@@ -484,6 +569,9 @@ pub mod std_compile {
         if let Some(debug_info_path) = debug_info_path {
             truth.prepare_and_write_debug_info(debug_info_path)?;
         }
+        if script_report {
+            print!("{}", truth.script_stats());
+        }
         Ok(())
     }
 }
@@ -492,12 +580,13 @@ pub mod std_decompile {
     use super::*;
 
     pub fn main(version: &str, args: &[String]) -> ! {
-        let (common_options, output, fmt_config) = cli::parse_args(version, args, CmdSpec {
+        let (common_options, output) = cli::parse_args(version, args, CmdSpec {
             program: "trustd decompile",
             usage_args: "FILE -g GAME [OPTIONS...]",
-            options: (cli::common_decompile_options(), cli::output(), cli::fmt_config()),
+            options: (cli::common_decompile_options(), cli::output()),
         });
-        wrap_decompile_to_stdout(fmt_config, output, |truth| {
+        let common_options = common_options.relative_to_output(output.as_deref());
+        wrap_decompile_to_stdout(common_options.decompile_options.fmt_config(), output, |truth| {
             decompile(truth, &common_options)
         })
     }
@@ -507,8 +596,9 @@ pub mod std_decompile {
         common_options: &CommonDecompileOptions,
     ) -> Result<ScriptFile, ErrorReported> {
         let &CommonDecompileOptions {
-            game, ref in_path, ref mapfile_options, ref decompile_options
+            game, ref in_path, ref mapfile_options, ref decompile_options, num_threads,
         } = common_options;
+        set_num_threads(truth, num_threads);
 
         let mapfile_options = add_env_mapfile_for_decomp(mapfile_options, ".stdm");
         load_mapfiles(truth, game, &[LanguageKey::Std], &mapfile_options)?;
@@ -564,8 +654,19 @@ pub mod msg_compile {
         msg_mode: MsgMode,
     ) -> Result<(), ErrorReported> {
         let &CommonCompileOptions {
-            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path,
+            ref in_path, ref out_path, game, ref mapfile_options, ref debug_info_path, ref dump_passes_path, num_threads,
+            no_goto_time_warnings, no_time_decrease_warnings, no_blob_signature_warnings, warn_float_equality, max_unroll, register_report, script_report,
         } = common_options;
+        set_num_threads(truth, num_threads);
+        truth.set_warn_goto_times(!no_goto_time_warnings);
+        truth.set_warn_time_decreases(!no_time_decrease_warnings);
+        truth.set_warn_blob_signatures(!no_blob_signature_warnings);
+        truth.set_warn_float_equality(warn_float_equality);
+        if let Some(max_unroll) = max_unroll {
+            truth.set_max_unroll_count(max_unroll);
+        }
+        truth.set_report_register_pressure(register_report);
+        add_dump_passes_observer(truth, dump_passes_path)?;
 
         let ast = truth.read_script(&in_path)?;
         truth.expect_no_image_sources(&ast)?;
@@ -595,6 +696,9 @@ pub mod msg_compile {
         if let Some(debug_info_path) = debug_info_path {
             truth.prepare_and_write_debug_info(debug_info_path)?;
         }
+        if script_report {
+            print!("{}", truth.script_stats());
+        }
         Ok(())
     }
 }
@@ -603,12 +707,13 @@ pub mod msg_decompile {
     use super::*;
 
     pub fn main(version: &str, args: &[String]) -> ! {
-        let (common_options, output, fmt_config, msg_mode) = cli::parse_args(version, args, CmdSpec {
+        let (common_options, output, msg_mode) = cli::parse_args(version, args, CmdSpec {
             program: "trumsg decompile",
             usage_args: "FILE -g GAME [OPTIONS...]",
-            options: (cli::common_decompile_options(), cli::output(), cli::fmt_config(), cli::msg_mode()),
+            options: (cli::common_decompile_options(), cli::output(), cli::msg_mode()),
         });
-        wrap_decompile_to_stdout(fmt_config, output, |truth| {
+        let common_options = common_options.relative_to_output(output.as_deref());
+        wrap_decompile_to_stdout(common_options.decompile_options.fmt_config(), output, |truth| {
             decompile(truth, &common_options, msg_mode)
         })
     }
@@ -619,8 +724,9 @@ pub mod msg_decompile {
         msg_mode: MsgMode,
     ) -> Result<ScriptFile, ErrorReported> {
         let &CommonDecompileOptions {
-            game, ref in_path, ref mapfile_options, ref decompile_options
+            game, ref in_path, ref mapfile_options, ref decompile_options, num_threads,
         } = common_options;
+        set_num_threads(truth, num_threads);
 
         match msg_mode {
             MsgMode::Stage => {
@@ -677,6 +783,20 @@ fn load_mapfiles(
     Ok(())
 }
 
+fn set_num_threads(truth: &mut Truth, num_threads: Option<usize>) {
+    if let Some(num_threads) = num_threads {
+        truth.set_num_threads(num_threads);
+    }
+}
+
+fn add_dump_passes_observer(truth: &mut Truth, dump_passes_path: &Option<PathBuf>) -> Result<(), ErrorReported> {
+    if let Some(dump_passes_path) = dump_passes_path {
+        let observer = crate::passes::observer::DumpAstObserver::new(truth.fs(), dump_passes_path)?;
+        truth.add_pass_observer(observer);
+    }
+    Ok(())
+}
+
 // =============================================================================
 
 /// Basic wrapper for entry points that constructs an instance of the compiler API and converts Result into exit codes.
@@ -684,7 +804,10 @@ fn wrap_exit_code(func: impl FnOnce(&mut Truth) -> Result<(), ErrorReported>) ->
     let mut scope = crate::Builder::new().build();
     let mut truth = scope.truth();
 
-    match func(&mut truth) {
+    let result = func(&mut truth);
+    // std::process::exit doesn't run destructors, so this must be done explicitly
+    truth.emit_diagnostic_summary();
+    match result {
         Ok(()) => std::process::exit(0),
         Err(ErrorReported) => std::process::exit(1),
     }
@@ -727,6 +850,15 @@ mod cli {
         pub out_path: PathBuf,
         pub mapfile_options: MapfileOptions,
         pub debug_info_path: Option<PathBuf>,
+        pub dump_passes_path: Option<PathBuf>,
+        pub num_threads: Option<usize>,
+        pub no_goto_time_warnings: bool,
+        pub no_time_decrease_warnings: bool,
+        pub no_blob_signature_warnings: bool,
+        pub warn_float_equality: WarnFloatEquality,
+        pub max_unroll: Option<u32>,
+        pub register_report: bool,
+        pub script_report: bool,
     }
 
     /// Options shared by all 'decompile' commands. This struct exists to help reduce the tedium of adding a new option.
@@ -735,6 +867,19 @@ mod cli {
         pub in_path: PathBuf,
         pub mapfile_options: MapfileOptions,
         pub decompile_options: DecompileOptions,
+        pub num_threads: Option<usize>,
+    }
+
+    impl CommonDecompileOptions {
+        /// Make `--relative-mapfile-paths` relative to the directory of the output file, if there is one.
+        pub fn relative_to_output(mut self, output: Option<&Path>) -> Self {
+            if let MapfilePaths::RelativeTo(dir) = &mut self.decompile_options.mapfile_paths {
+                if let Some(parent) = output.and_then(Path::parent).filter(|parent| !parent.as_os_str().is_empty()) {
+                    *dir = parent.to_owned();
+                }
+            }
+            self
+        }
     }
 
     /// Options related to mapfiles.
@@ -745,16 +890,17 @@ mod cli {
     }
 
     pub fn common_compile_options() -> impl CliArg<Value=CommonCompileOptions> {
-        game().zip(required_output()).zip(input()).zip(mapfile_options()).zip(debug_info())
-            .and_then(|((((game, out_path), in_path), mapfile_options), debug_info_path)| {
-                Ok(CommonCompileOptions { game, out_path, in_path, mapfile_options, debug_info_path })
+        game().zip(required_output()).zip(input()).zip(mapfile_options()).zip(debug_info()).zip(dump_passes()).zip(num_threads())
+            .zip(no_goto_time_warnings()).zip(no_time_decrease_warnings()).zip(no_blob_signature_warnings()).zip(warn_float_equality()).zip(max_unroll()).zip(register_report()).zip(script_report())
+            .and_then(|(((((((((((((game, out_path), in_path), mapfile_options), debug_info_path), dump_passes_path), num_threads), no_goto_time_warnings), no_time_decrease_warnings), no_blob_signature_warnings), warn_float_equality), max_unroll), register_report), script_report)| {
+                Ok(CommonCompileOptions { game, out_path, in_path, mapfile_options, debug_info_path, dump_passes_path, num_threads, no_goto_time_warnings, no_time_decrease_warnings, no_blob_signature_warnings, warn_float_equality, max_unroll, register_report, script_report })
             })
     }
 
     pub fn common_decompile_options() -> impl CliArg<Value=CommonDecompileOptions> {
-        game().zip(input()).zip(mapfile_options()).zip(decompile_options())
-            .and_then(|(((game, in_path), mapfile_options), decompile_options)| {
-                Ok(CommonDecompileOptions { game, in_path, mapfile_options, decompile_options })
+        game().zip(input()).zip(mapfile_options()).zip(decompile_options()).zip(num_threads())
+            .and_then(|((((game, in_path), mapfile_options), decompile_options), num_threads)| {
+                Ok(CommonDecompileOptions { game, in_path, mapfile_options, decompile_options, num_threads })
             })
     }
 
@@ -783,7 +929,7 @@ mod cli {
     pub fn game() -> impl CliArg<Value=Game> {
         opts::ReqOpt(opts::Opt {
             short: "g", long: "game", metavar: "GAME",
-            help: "game number, e.g. 'th095' or '8'. Don't include a point in point titles. Also supports 'alcostg'.",
+            help: "game number, e.g. 'th095' or '8' (don't include a point in point titles), or a name like 'wbawc' or 'alcostg'.",
         }).and_then(|s| s.parse())
     }
 
@@ -810,8 +956,72 @@ mod cli {
         }.map(|opt| opt.map(Into::into))
     }
 
-    pub fn fmt_config() -> impl CliArg<Value=crate::fmt::Config> {
-        fmt_max_columns().map(|ncol| crate::fmt::Config::new().max_columns(ncol))
+    pub fn dump_passes() -> impl CliArg<Value=Option<PathBuf>> {
+        opts::Opt {
+            short: "", long: "dump-passes", metavar: "DIR",
+            help: "for debugging the compiler; write the AST to DIR after each compilation pass",
+        }.map(|opt| opt.map(Into::into))
+    }
+
+    pub fn num_threads() -> impl CliArg<Value=Option<usize>> {
+        opts::Opt {
+            short: "j", long: "threads", metavar: "NUM",
+            help: "use up to NUM threads to compile or decompile scripts.  Defaults to the number of CPUs.  \
+            Use 1 to do everything on a single thread.",
+        }.and_then(|opt| opt.map(|s| match s.parse() {
+            Ok(0) => Err(error!("--threads must be at least 1")),
+            Ok(num) => Ok(num),
+            Err(e) => Err(error!("{}", e)),
+        }).transpose())
+    }
+
+    pub fn no_goto_time_warnings() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "no-goto-time-warnings",
+            help: "don't warn about gotos whose explicit time differs from the time at their label",
+        }
+    }
+
+    pub fn no_time_decrease_warnings() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "no-time-decrease-warnings",
+            help: "don't warn about instructions at an earlier time than the instruction before them",
+        }
+    }
+
+    pub fn no_blob_signature_warnings() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "no-blob-signature-warnings",
+            help: "don't warn about @blob and @mask arguments that disagree with an instruction's known signature",
+        }
+    }
+
+    pub fn warn_float_equality() -> impl CliArg<Value=WarnFloatEquality> {
+        opts::Opt {
+            short: "", long: "warn-float-equality", metavar: "WHICH",
+            help: "warn about '==' and '!=' on floats: 'all', 'nonzero' to allow comparisons against 0.0, or 'never' (the default)",
+        }.and_then(|opt| opt.map_or(Ok(Default::default()), |s| s.parse()))
+    }
+
+    pub fn max_unroll() -> impl CliArg<Value=Option<u32>> {
+        opts::Opt {
+            short: "", long: "max-unroll", metavar: "NUM",
+            help: "allow 'times(inline n)' loops of up to NUM iterations to be unrolled.  Defaults to 32.",
+        }.and_then(|opt| opt.map(|s| s.parse().map_err(|e| error!("{}", e))).transpose())
+    }
+
+    pub fn register_report() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "register-report",
+            help: "for each script that uses scratch registers, report the peak number in use and where it occurs",
+        }
+    }
+
+    pub fn script_report() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "script-report",
+            help: "print the size, instruction count, and registers used by each compiled script",
+        }
     }
 
     fn fmt_max_columns() -> impl CliArg<Value=usize> {
@@ -834,6 +1044,27 @@ mod cli {
         }).map(|strs| strs.into_iter().map(Into::into).collect())
     }
 
+    pub fn sprite_bounds() -> impl CliArg<Value=crate::anm::SpriteBoundsCheck> {
+        opts::Opt {
+            short: "", long: "sprite-bounds", metavar: "LEVEL",
+            help: "how to report sprites that don't fit inside their texture: 'allow', 'warn' (the default) or 'deny'",
+        }.and_then(|opt| opt.map_or(Ok(Default::default()), |s| s.parse()))
+    }
+
+    pub fn warn_unreferenced() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "warn-unreferenced",
+            help: "warn about scripts and sprites that no instruction in the file uses, and entries with no sprites",
+        }
+    }
+
+    pub fn warn_busy_loops() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "warn-busy-loops",
+            help: "warn about scripts with a loop that jumps back without advancing time",
+        }
+    }
+
     pub fn output_thecl_defs() -> impl CliArg<Value=Option<PathBuf>> {
         opts::Opt {
             short: "", long: "output-thecl-defs", metavar: "FILE",
@@ -841,6 +1072,41 @@ mod cli {
         }.map(|opt| opt.map(Into::into))
     }
 
+    pub fn dummy_images() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "dummy-images",
+            help: "write 'has_data: \"dummy\"' on entries with images, for scripts that will be compiled without image sources",
+        }
+    }
+
+    pub fn script_order() -> impl CliArg<Value=crate::anm::ScriptOrder> {
+        opts::Opt {
+            short: "", long: "script-order", metavar: "ORDER",
+            help: "order of the scripts in each entry: 'file' (the default) for the order of the script table, or 'id' to sort them by ID",
+        }.and_then(|opt| opt.map_or(Ok(Default::default()), |s| s.parse()))
+    }
+
+    pub fn duplicate_shared_scripts() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "duplicate-shared-scripts",
+            help: "write a full copy of each script that shares its body with another, instead of an alias like 'script 5 script5 = script2;'",
+        }
+    }
+
+    pub fn lenient() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "lenient",
+            help: "salvage what can be read from a corrupted or truncated file.  Scripts that begin past the end of the file, inside the entry header, or inside another script are written as empty scripts, with a warning",
+        }
+    }
+
+    pub fn merge_identical_scripts() -> impl CliArg<Value=bool> {
+        opts::Flag {
+            short: "", long: "merge-identical-scripts",
+            help: "let scripts in the same entry that compile to identical instructions share one body in the output file",
+        }
+    }
+
     pub fn decompile_options() -> impl CliArg<Value=DecompileOptions> {
         let no_blocks = opts::Flag {
             short: "", long: "no-blocks",
@@ -858,10 +1124,38 @@ mod cli {
             short: "", long: "no-diff-switches",
             help: "prevent decompilation of diff switches, forcing direct usage of difficulty flags",
         };
-        let zipped = no_intrinsics.zip(no_blocks).zip(no_arguments).zip(no_diff_switches);
-        zipped.map(|(((no_intrinsics, no_blocks), no_arguments), no_diff_switches)| DecompileOptions {
-            intrinsics: !no_intrinsics, blocks: !no_blocks, arguments: !no_arguments,
-            diff_switches: !no_diff_switches
+        let no_const_names = opts::Flag {
+            short: "", long: "no-const-names",
+            help: "write integer arguments as numbers even when an enum const or sprite has that value",
+        };
+        let renumber_labels = opts::Flag {
+            short: "", long: "renumber-labels",
+            help: "name labels label_0, label_1, ... in order, instead of after their byte offsets",
+        };
+        let explicit_arg0 = opts::Flag {
+            short: "", long: "explicit-arg0",
+            help: "in EoSD and PCB timelines, write '@arg0=' on every instruction that doesn't use arg0 as an argument, even when it is zero",
+        };
+        let interrupt_enum = opts::Opt {
+            short: "", long: "interrupt-enum", metavar: "ENUM",
+            help: "write interrupt labels using the names of the consts in this mapfile enum, e.g. 'interrupt[CARD_END]:'",
+        }.and_then(|opt| opt.map(|s| Ident::new_user(&s).map_err(|e| error!("--interrupt-enum: {e}"))).transpose());
+        let relative_mapfile_paths = opts::Flag {
+            short: "", long: "relative-mapfile-paths",
+            help: "write '#pragma mapfile' paths relative to the directory of the output file (or the current directory), instead of as they were given",
+        };
+        let zipped = no_intrinsics.zip(no_blocks).zip(no_arguments).zip(no_diff_switches)
+            .zip(no_const_names).zip(renumber_labels).zip(explicit_arg0).zip(interrupt_enum).zip(relative_mapfile_paths).zip(fmt_max_columns());
+        zipped.map(|(((((((((no_intrinsics, no_blocks), no_arguments), no_diff_switches), no_const_names), renumber_labels), explicit_arg0), interrupt_enum), relative_mapfile_paths), max_columns)| {
+            let mapfile_paths = match relative_mapfile_paths {
+                true => MapfilePaths::RelativeTo(".".into()),
+                false => MapfilePaths::AsGiven,
+            };
+            DecompileOptions::new()
+                .intrinsics(!no_intrinsics).blocks(!no_blocks).arguments(!no_arguments)
+                .diff_switches(!no_diff_switches).const_names(!no_const_names)
+                .renumber_labels(renumber_labels).explicit_arg0(explicit_arg0).max_columns(max_columns)
+                .interrupt_enum(interrupt_enum).mapfile_paths(mapfile_paths)
         })
     }
 
@@ -1,13 +1,13 @@
-use indexmap::IndexMap;
-
-use crate::ast;
-use crate::error::{ErrorReported};
+use crate::ast::{self, Visit};
+use crate::error::{ErrorReported, GatherErrorIteratorExt};
 use crate::diagnostic::{Diagnostic, RootEmitter};
 use crate::pos::{Sp, Span};
-use crate::resolve::{DefId, ConstId, Resolutions};
+use crate::resolve::{DefId, ConstId, Resolutions, IdMap};
 use crate::context::defs::{self, Defs};
 use crate::value::ScalarValue;
 use crate::debug_info;
+use crate::fast_hash::{FastHashMap, FastHashSet, FastIndexMap};
+use crate::fmt::stringify;
 
 /// Orchestrates the evaluation of all `const` variables, and caches their values.
 ///
@@ -19,7 +19,103 @@ use crate::debug_info;
 pub struct Consts {
     deferred_ids: Vec<ConstId>,
     deferred_equality_checks: Vec<EqualityCheck>,
-    values: IndexMap<ConstId, ScalarValue>,
+    values: FastIndexMap<ConstId, ScalarValue>,
+    /// Consts whose evaluation produced an error, so that the error is not reported again by consts that use them.
+    failed: FastHashSet<ConstId>,
+    /// Results of calls to `const` functions made while evaluating consts.
+    func_results: FastHashMap<ConstFuncCall, Option<ScalarValue>>,
+    /// Calls to `const` functions that produced an error.
+    failed_func_calls: FastHashSet<ConstFuncCall>,
+    /// For each const that has been evaluated (successfully or not), the consts and `const` functions that
+    /// its evaluation read.  Anything read by a function it called is included here as well.
+    dependencies: IdMap<ConstId, Vec<Dependency>>,
+    /// The same as [`Self::dependencies`], for cached calls to `const` functions.
+    func_call_dependencies: FastHashMap<ConstFuncCall, Vec<Dependency>>,
+    /// Values saved from an earlier [`CompilerContext`][`crate::CompilerContext`] that may be reused.
+    reusable: Option<ConstCache>,
+    /// Consts whose values were taken from [`Self::reusable`].
+    reused: Vec<ConstId>,
+}
+
+/// Something whose definition was read while evaluating a const.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+enum Dependency {
+    Const(ConstId),
+    Func(DefId),
+}
+
+/// Const values computed by one [`CompilerContext`][`crate::CompilerContext`], in a form that can be reused
+/// by another one.  (e.g. after a file has been edited)
+///
+/// Because [`DefId`]s are not stable between contexts, consts are identified by their name and the text of
+/// their definition.  A value is only reused if everything that was read to compute it is still defined
+/// identically, and if every name in those definitions still refers to the same things.  Definitions that
+/// can't be told apart this way (e.g. `const int X = Y;` in two scripts that each define their own `Y`)
+/// are never cached.
+///
+/// Created by [`Consts::cache`], and used by [`Consts::reuse_cache`].
+#[derive(Debug, Clone, Default)]
+pub struct ConstCache {
+    entries: FastHashMap<DefKey, CachedConst>,
+    /// For each definition used by [`Self::entries`], the definitions that the names in it refer to.
+    references: FastHashMap<DefKey, Vec<DefKey>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedConst {
+    value: ScalarValue,
+    dependencies: Vec<DefKey>,
+}
+
+/// Identifies a definition across contexts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum DefKey {
+    Const(String),
+    Func(String),
+}
+
+/// The keys of the consts and `const` functions in one context.
+///
+/// Keys that belong to more than one definition are left out, so that a key always identifies a single thing.
+struct DefKeys {
+    by_dependency: FastHashMap<Dependency, DefKey>,
+    by_key: FastHashMap<DefKey, Dependency>,
+    ambiguous: FastHashSet<Dependency>,
+}
+
+impl ConstCache {
+    /// The number of const values in the cache.
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+}
+
+/// Maximum nesting depth of calls to `const` functions, to stop runaway recursion.
+pub const MAX_CONST_FUNC_CALL_DEPTH: usize = 100;
+
+/// Key for caching the result of a `const` function call.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ConstFuncCall {
+    def_id: DefId,
+    args: Vec<ConstArgKey>,
+}
+
+/// A [`ScalarValue`] that can be hashed, by comparing floats bitwise.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ConstArgKey {
+    Int(i32),
+    Float(u32),
+    String(String),
+}
+
+impl From<&ScalarValue> for ConstArgKey {
+    fn from(value: &ScalarValue) -> Self {
+        match *value {
+            ScalarValue::Int(x) => ConstArgKey::Int(x),
+            ScalarValue::Float(x) => ConstArgKey::Float(x.to_bits()),
+            ScalarValue::String(ref x) => ConstArgKey::String(x.clone()),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -70,17 +166,41 @@ impl Consts {
         self.values.get(&id)
     }
 
+    /// Evaluate a call to a `const` function with the given argument values.  Returns `None` if the
+    /// function finishes without returning a value.
+    ///
+    /// This is meant to be used after [`crate::passes::evaluate_const_vars`] (e.g. when simplifying calls
+    /// in script bodies, or in the [AST VM][`crate::vm::AstVm`]), and does not add to the cache.
+    pub fn eval_const_func_call(
+        &self,
+        call_span: Span,
+        def_id: DefId,
+        args: Vec<ScalarValue>,
+        defs: &Defs,
+        resolutions: &Resolutions,
+        emitter: &RootEmitter,
+    ) -> Result<Option<ScalarValue>, ErrorReported> {
+        Evaluator {
+            consts: ConstsAccess::Shared(self), defs, resolutions, emitter,
+            eval_stack: vec![],
+            locals_stack: vec![],
+            reads_stack: vec![],
+        }._call_const_func(call_span, def_id, args)
+    }
+
     fn do_deferred_evaluations(
         &mut self,
         defs: &Defs,
         resolutions: &Resolutions,
         emitter: &RootEmitter,
     ) -> Result<(), ErrorReported> {
+        // keep going after errors, so that as many values as possible are available to e.g. Truth::list_symbols
+        self.apply_reusable_cache(defs, resolutions);
+
         let deferred_ids = std::mem::replace(&mut self.deferred_ids, vec![]);
-        for id in deferred_ids {
-            Evaluator::run_rooted(self, id, defs, resolutions, emitter)?;
-        }
-        Ok(())
+        deferred_ids.into_iter().map(|id| {
+            Evaluator::run_rooted(self, id, defs, resolutions, emitter)
+        }).collect_with_recovery()
     }
 
     fn do_deferred_equality(
@@ -107,6 +227,160 @@ impl Consts {
         Ok(())
     }
 
+    /// Forget the value of a const (or the results of calls to a `const` function), along with everything
+    /// that was computed from it.  The affected consts will be evaluated again by the next call to
+    /// [`crate::passes::evaluate_const_vars`].
+    ///
+    /// Returns the consts whose values (or errors) were forgotten, starting with `def_id` itself if it is one.
+    pub fn invalidate(&mut self, def_id: DefId) -> Vec<ConstId> {
+        let mut dependents = IdMap::<DefId, Vec<ConstId>>::default();
+        for (&const_id, dependencies) in &self.dependencies {
+            for dependency in dependencies {
+                dependents.entry(dependency.def_id()).or_default().push(const_id);
+            }
+        }
+
+        let mut stale = vec![def_id];
+        let mut stale_set = FastHashSet::from_iter([def_id]);
+        let mut index = 0;
+        while let Some(&stale_def_id) = stale.get(index) {
+            let mut new_dependents = dependents.remove(&stale_def_id).unwrap_or_default();
+            new_dependents.sort();  // for a deterministic order
+            for ConstId { def_id } in new_dependents {
+                if stale_set.insert(def_id) {
+                    stale.push(def_id);
+                }
+            }
+            index += 1;
+        }
+
+        let is_stale_call = |call: &ConstFuncCall, dependencies: &FastHashMap<ConstFuncCall, Vec<Dependency>>| {
+            stale_set.contains(&call.def_id) || dependencies.get(call).map_or(false, |deps| {
+                deps.iter().any(|dep| stale_set.contains(&dep.def_id()))
+            })
+        };
+        let func_call_dependencies = &self.func_call_dependencies;
+        self.func_results.retain(|call, _| !is_stale_call(call, func_call_dependencies));
+        self.failed_func_calls.retain(|call| !is_stale_call(call, func_call_dependencies));
+        self.func_call_dependencies = self.func_call_dependencies.iter()
+            .filter(|(call, _)| !is_stale_call(call, func_call_dependencies))
+            .map(|(call, deps)| (call.clone(), deps.clone()))
+            .collect();
+
+        let mut invalidated = vec![];
+        for def_id in stale {
+            let const_id = ConstId::from(def_id);
+            let had_value = self.values.shift_remove(&const_id).is_some();
+            let had_error = self.failed.remove(&const_id);
+            self.dependencies.remove(&const_id);
+            if had_value || had_error {
+                self.deferred_ids.push(const_id);
+                invalidated.push(const_id);
+            }
+        }
+        invalidated
+    }
+
+    /// Save the values of all consts that have been successfully evaluated so far, so that they can be reused
+    /// by another [`CompilerContext`][`crate::CompilerContext`].
+    pub fn cache(&self, defs: &Defs, resolutions: &Resolutions) -> ConstCache {
+        let keys = self.def_keys(defs);
+        let mut references = FastHashMap::default();
+        let mut add_references = |dependency: Dependency| -> Option<()> {
+            let key = keys.by_dependency.get(&dependency)?;
+            if !references.contains_key(key) {
+                references.insert(key.clone(), keys.references(dependency, defs, resolutions)?);
+            }
+            Some(())
+        };
+
+        let entries = self.values.iter().filter_map(|(&const_id, value)| {
+            let dependencies = self.dependencies.get(&const_id)?;
+            for &dependency in dependencies.iter().chain([&Dependency::Const(const_id)]) {
+                add_references(dependency)?;
+            }
+            let cached = CachedConst {
+                value: value.clone(),
+                dependencies: dependencies.iter().map(|dependency| keys.by_dependency[dependency].clone()).collect(),
+            };
+            Some((keys.by_dependency[&Dependency::Const(const_id)].clone(), cached))
+        }).collect();
+        ConstCache { entries, references }
+    }
+
+    /// Reuse values from a [`ConstCache`] made by another [`CompilerContext`][`crate::CompilerContext`] during
+    /// the next call to [`crate::passes::evaluate_const_vars`], wherever they are still valid.
+    pub fn reuse_cache(&mut self, cache: ConstCache) {
+        self.reusable = Some(cache);
+        self.reused.clear();
+    }
+
+    /// Get the consts whose values were taken from the cache given to [`Self::reuse_cache`] instead of being
+    /// evaluated.
+    pub fn reused(&self) -> &[ConstId] {
+        &self.reused
+    }
+
+    /// Fill in the values of deferred consts from [`Self::reusable`].
+    fn apply_reusable_cache(&mut self, defs: &Defs, resolutions: &Resolutions) {
+        let cache = match self.reusable.take() {
+            Some(cache) => cache,
+            None => return,
+        };
+
+        let available = self.def_keys(defs);
+        let mut validity = FastHashMap::default();
+        for &const_id in &self.deferred_ids {
+            if self.values.contains_key(&const_id) || self.failed.contains(&const_id) {
+                continue;
+            }
+            let key = match available.by_dependency.get(&Dependency::Const(const_id)) {
+                Some(key) => key,
+                None => continue,
+            };
+            if !cache.is_valid(key, &available, defs, resolutions, &mut validity) {
+                continue;
+            }
+            let cached = &cache.entries[key];
+            let dependencies = cached.dependencies.iter().map(|key| available.by_key[key]).collect();
+            self.values.insert(const_id, cached.value.clone());
+            self.dependencies.insert(const_id, dependencies);
+            self.reused.push(const_id);
+        }
+
+        self.reusable = Some(cache);
+    }
+
+    /// Get the keys of everything defined in this context that a [`ConstCache`] can refer to.
+    fn def_keys(&self, defs: &Defs) -> DefKeys {
+        let const_ids = self.deferred_ids.iter().chain(self.values.keys()).chain(&self.failed);
+        let dependencies = const_ids.map(|&const_id| Dependency::Const(const_id))
+            .chain(defs.const_funcs().map(Dependency::Func));
+
+        let mut by_dependency = FastHashMap::default();
+        let mut by_key = FastHashMap::<DefKey, Vec<Dependency>>::default();
+        for dependency in dependencies {
+            if by_dependency.contains_key(&dependency) {
+                continue;
+            }
+            if let Some(key) = dependency.key(defs) {
+                by_key.entry(key.clone()).or_default().push(dependency);
+                by_dependency.insert(dependency, key);
+            }
+        }
+
+        let mut ambiguous = FastHashSet::default();
+        let by_key = by_key.into_iter().filter_map(|(key, dependencies)| match dependencies[..] {
+            [dependency] => Some((key, dependency)),
+            _ => {
+                ambiguous.extend(dependencies);
+                None
+            },
+        }).collect();
+        by_dependency.retain(|dependency, _| !ambiguous.contains(dependency));
+        DefKeys { by_dependency, by_key, ambiguous }
+    }
+
     pub fn debug_info(&self, defs: &Defs) -> Vec<debug_info::Const> {
         self.values.iter().map(|(const_id, value)| {
             debug_info::Const {
@@ -118,37 +392,192 @@ impl Consts {
     }
 }
 
+impl ConstCache {
+    /// Determine whether a cached value can be used in a context where the given things are defined.
+    fn is_valid(
+        &self,
+        key: &DefKey,
+        available: &DefKeys,
+        defs: &Defs,
+        resolutions: &Resolutions,
+        memo: &mut FastHashMap<DefKey, bool>,
+    ) -> bool {
+        if let Some(&valid) = memo.get(key) {
+            return valid;
+        }
+        // (in case of a cycle, this is what the recursive calls will see; the cache never holds one,
+        //  but a function that calls itself refers to itself)
+        memo.insert(key.clone(), false);
+
+        let valid = available.by_key.get(key).is_some_and(|&dependency| {
+            self.references.get(key) == available.references(dependency, defs, resolutions).as_ref()
+        }) && match key {
+            // a function's own reads are included in the dependencies of the consts that called it
+            DefKey::Func(_) => true,
+            DefKey::Const(_) => self.entries.get(key).map_or(false, |cached| {
+                cached.dependencies.iter().all(|dependency| self.is_valid(dependency, available, defs, resolutions, memo))
+            }),
+        };
+        memo.insert(key.clone(), valid);
+        valid
+    }
+}
+
+impl DefKeys {
+    /// Get the keys of the consts and `const` functions that the names in a definition refer to.
+    ///
+    /// `None` if any of them can't be identified by a key.
+    fn references(&self, dependency: Dependency, defs: &Defs, resolutions: &Resolutions) -> Option<Vec<DefKey>> {
+        let mut visitor = ReferenceVisitor { defs, resolutions, keys: self, out: Some(vec![]) };
+        match dependency {
+            Dependency::Const(ConstId { def_id }) => {
+                let (_, expr) = defs.var_const_expr(def_id)?;
+                visitor.visit_expr(&sp!(expr.clone()));
+            },
+            Dependency::Func(def_id) => {
+                if let Some(code) = &defs.const_func_def(def_id)?.code {
+                    visitor.visit_root_block(code);
+                }
+            },
+        }
+        visitor.out
+    }
+}
+
+struct ReferenceVisitor<'a> {
+    defs: &'a Defs,
+    resolutions: &'a Resolutions,
+    keys: &'a DefKeys,
+    out: Option<Vec<DefKey>>,
+}
+
+impl ReferenceVisitor<'_> {
+    fn add(&mut self, dependency: Dependency) {
+        match self.keys.by_dependency.get(&dependency) {
+            Some(key) => if let Some(out) = &mut self.out {
+                out.push(key.clone());
+            },
+            None => self.out = None,
+        }
+    }
+}
+
+impl Visit for ReferenceVisitor<'_> {
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let ast::VarName::Normal { ident, .. } = &var.name {
+            let def_id = self.resolutions.try_get_def(ident);
+            // locals and parameters of `const` functions are identified by the text around them
+            if let Some(def_id) = def_id.filter(|&def_id| self.defs.var_const_expr(def_id).is_some()) {
+                self.add(Dependency::Const(def_id.into()));
+            }
+        }
+    }
+
+    fn visit_callable_name(&mut self, name: &Sp<ast::CallableName>) {
+        if let ast::CallableName::Normal { ident, .. } = &name.value {
+            let dependency = self.resolutions.try_get_def(ident).map(Dependency::Func);
+            // anything else that can be called is not allowed in a const
+            if let Some(dependency) = dependency.filter(|dependency| {
+                self.keys.by_dependency.contains_key(dependency) || self.keys.ambiguous.contains(dependency)
+            }) {
+                self.add(dependency);
+            }
+        }
+    }
+}
+
+impl Dependency {
+    fn def_id(self) -> DefId {
+        match self {
+            Dependency::Const(ConstId { def_id }) => def_id,
+            Dependency::Func(def_id) => def_id,
+        }
+    }
+
+    /// `None` if this is not a const or `const` function. (e.g. if a const erroneously read a register alias)
+    fn key(self, defs: &Defs) -> Option<DefKey> {
+        match self {
+            Dependency::Const(ConstId { def_id }) => {
+                let (_, expr) = defs.var_const_expr(def_id)?;
+                Some(DefKey::Const(format!("{} = {}", defs.var_name(def_id), stringify(expr))))
+            },
+            Dependency::Func(def_id) => {
+                defs.const_func_def(def_id).map(|func| DefKey::Func(stringify(func)))
+            },
+        }
+    }
+}
+
 /// State object for fully evaluating (not just simplifying) a `const` expression.
 ///
 /// Automatically computes and caches the value of `const` items as their values are needed.
 struct Evaluator<'a> {
-    consts: &'a mut Consts,
+    consts: ConstsAccess<'a>,
     /// Stack of `const` items we're evaluating.  This is used to detect circular dependencies.
     eval_stack: Vec<ConstId>,
+    /// Local variables (including params) of each `const` function call we're inside.
+    locals_stack: Vec<IdMap<DefId, ScalarValue>>,
+    /// Things read so far by each const or `const` function call we're evaluating. (see [`Consts::dependencies`])
+    reads_stack: Vec<FastHashSet<Dependency>>,
     defs: &'a Defs,
     resolutions: &'a Resolutions,
     emitter: &'a RootEmitter,
 }
 
+/// During [`crate::passes::evaluate_const_vars`], the evaluator fills the cache.  Afterwards, it can only read it.
+enum ConstsAccess<'a> {
+    Mut(&'a mut Consts),
+    Shared(&'a Consts),
+}
+
+impl ConstsAccess<'_> {
+    fn get(&self) -> &Consts {
+        match self {
+            ConstsAccess::Mut(consts) => consts,
+            ConstsAccess::Shared(consts) => consts,
+        }
+    }
+
+    fn get_mut(&mut self) -> Option<&mut Consts> {
+        match self {
+            ConstsAccess::Mut(consts) => Some(consts),
+            ConstsAccess::Shared(_) => None,
+        }
+    }
+}
+
+/// How a block in a `const` function finished.
+enum ControlFlow {
+    Normal,
+    Return(Option<ScalarValue>),
+}
+
 impl<'a> Evaluator<'a> {
     // Ensure that the given DefId (and anything else it depends on) is cached.
     fn run_rooted(consts: &mut Consts, id: ConstId, defs: &Defs, resolutions: &Resolutions, emitter: &RootEmitter) -> Result<(), ErrorReported> {
         Evaluator {
-            consts, defs, resolutions, emitter,
-            eval_stack: vec![]
+            consts: ConstsAccess::Mut(consts), defs, resolutions, emitter,
+            eval_stack: vec![],
+            locals_stack: vec![],
+            reads_stack: vec![],
         }._get_or_compute(None, id).map(|_| ())
     }
 
     // Get the cached value for a DefId, or compute one and store it.
     fn _get_or_compute(&mut self, use_span: Option<Span>, const_id: ConstId) -> Result<ScalarValue, ErrorReported> {
         let def_id = const_id.def_id;
-        if let Some(value) = (*(&*self.consts)).values.get(&const_id) {
+        self.record_reads([Dependency::Const(const_id)]);
+        if let Some(value) = self.consts.get().values.get(&const_id) {
             return Ok(value.clone());
         }
+        if self.consts.get().failed.contains(&const_id) {
+            return Err(ErrorReported);  // already reported
+        }
 
         // use_span is None on the outermost call only. (for recursive calls, it holds the span
-        // of the variable where it appeared inside another const's definition)
-        assert_eq!(self.eval_stack.len() > 0, use_span.is_some());
+        // of the variable where it appeared inside another const's definition, or in a const function)
+        assert!(self.eval_stack.len() > 0 || self.locals_stack.len() > 0 || use_span.is_none());
+        assert!(self.eval_stack.len() == 0 || use_span.is_some());
         if self.eval_stack.contains(&const_id) {
             let root_def_span = self.defs.var_decl_span(def_id).expect("consts always have name spans");
             return Err(self.emitter.emit(error!(
@@ -173,17 +602,200 @@ impl<'a> Evaluator<'a> {
         // Also we have to add a span now.
         let expr = sp!(expr_span => expr.clone());
 
+        // a const's value can't depend on the locals of a function that uses it
+        let outer_locals_stack = std::mem::replace(&mut self.locals_stack, vec![]);
         self.eval_stack.push(const_id);
+        self.reads_stack.push(Default::default());
         // FIXME: avoiding recursion here would be nice
         let value_result = self._const_eval(&expr);
+        let reads = self.reads_stack.pop().unwrap();
         self.eval_stack.pop();  // cleanup before possibly diverging with '?'
-        let value = value_result?;
+        self.locals_stack = outer_locals_stack;
+        if let Some(consts) = self.consts.get_mut() {
+            consts.dependencies.insert(const_id, reads.into_iter().collect());
+        }
+        let value = value_result.map_err(|e| {
+            if let Some(consts) = self.consts.get_mut() {
+                consts.failed.insert(const_id);
+            }
+            e
+        })?;
 
         // NOTE: We can't avoid this second lookup because because computing the value can mutate the map.
-        self.consts.values.insert(const_id, value.clone());
+        if let Some(consts) = self.consts.get_mut() {
+            consts.values.insert(const_id, value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Call a `const` function, or get the cached result of an identical call.
+    fn _call_const_func(&mut self, call_span: Span, def_id: DefId, args: Vec<ScalarValue>) -> Result<Option<ScalarValue>, ErrorReported> {
+        let func = self.defs.const_func_def(def_id).ok_or_else(|| self.non_const_error(call_span))?;
+
+        let key = ConstFuncCall { def_id, args: args.iter().map(ConstArgKey::from).collect() };
+        self.record_reads([Dependency::Func(def_id)]);
+        if let Some(reads) = self.consts.get().func_call_dependencies.get(&key) {
+            let reads = reads.clone();
+            self.record_reads(reads);
+        }
+        if let Some(value) = self.consts.get().func_results.get(&key) {
+            return Ok(value.clone());
+        }
+        if self.consts.get().failed_func_calls.contains(&key) {
+            return Err(ErrorReported);  // already reported
+        }
+
+        let code = func.code.as_ref().ok_or_else(|| self.emitter.emit(error!(
+            message("const function '{}' has no body", func.ident),
+            primary(call_span, "called here"),
+            secondary(func.ident, "declared here"),
+        )))?;
+        if self.locals_stack.len() >= MAX_CONST_FUNC_CALL_DEPTH {
+            return Err(self.emitter.emit(error!(
+                message("const function recursion limit exceeded"),
+                primary(call_span, "call nested more than {MAX_CONST_FUNC_CALL_DEPTH} deep"),
+                secondary(func.ident, "in this function"),
+            )));
+        }
+
+        let mut locals = IdMap::default();
+        for (param, value) in func.params.iter().zip(args) {
+            if let Some(ident) = &param.ident {
+                locals.insert(self.resolutions.expect_def(ident), value);
+            }
+        }
+        self.locals_stack.push(locals);
+        self.reads_stack.push(Default::default());
+        let result = self._const_exec_block(&code.0);
+        let reads = self.reads_stack.pop().unwrap().into_iter().collect::<Vec<_>>();
+        self.locals_stack.pop();  // cleanup before possibly diverging with '?'
+        self.record_reads(reads.iter().copied());
+        if let Some(consts) = self.consts.get_mut() {
+            consts.func_call_dependencies.insert(key.clone(), reads);
+        }
+
+        let value = match result {
+            Ok(ControlFlow::Normal) => None,
+            Ok(ControlFlow::Return(value)) => value,
+            Err(e) => {
+                if let Some(consts) = self.consts.get_mut() {
+                    consts.failed_func_calls.insert(key);
+                }
+                return Err(e);
+            },
+        };
+        if let Some(consts) = self.consts.get_mut() {
+            consts.func_results.insert(key, value.clone());
+        }
         Ok(value)
     }
 
+    /// Record that the const or function call currently being evaluated read these things.
+    fn record_reads(&mut self, reads: impl IntoIterator<Item=Dependency>) {
+        if let Some(top) = self.reads_stack.last_mut() {
+            top.extend(reads);
+        }
+    }
+
+    /// Run the statements of a `const` function's body.
+    fn _const_exec_block(&mut self, stmts: &[Sp<ast::Stmt>]) -> Result<ControlFlow, ErrorReported> {
+        for stmt in stmts {
+            match &stmt.kind {
+                | ast::StmtKind::Item(_)
+                | ast::StmtKind::Label(_)
+                | ast::StmtKind::ScopeEnd(_)
+                | ast::StmtKind::NoInstruction
+                => {},
+
+                ast::StmtKind::Block(block) => match self._const_exec_block(&block.0)? {
+                    ControlFlow::Normal => {},
+                    flow@ControlFlow::Return(_) => return Ok(flow),
+                },
+
+                ast::StmtKind::Return { value, .. } => {
+                    let value = value.as_ref().map(|value| self._const_eval(value)).transpose()?;
+                    return Ok(ControlFlow::Return(value));
+                },
+
+                ast::StmtKind::CondChain(ast::StmtCondChain { cond_blocks, else_block }) => {
+                    let mut taken_block = else_block.as_ref();
+                    for ast::CondBlock { keyword, cond, block } in cond_blocks {
+                        if self._const_eval_cond(cond)? == (keyword == &token![if]) {
+                            taken_block = Some(block);
+                            break;
+                        }
+                    }
+                    if let Some(block) = taken_block {
+                        match self._const_exec_block(&block.0)? {
+                            ControlFlow::Normal => {},
+                            flow@ControlFlow::Return(_) => return Ok(flow),
+                        }
+                    }
+                },
+
+                ast::StmtKind::Declaration { vars, .. } => {
+                    for sp_pat![(var, expr)] in vars {
+                        if let Some(expr) = expr {
+                            let value = self._const_eval(expr)?;
+                            let def_id = self.resolutions.expect_def(var.name.expect_ident());
+                            self.locals_stack.last_mut().expect("not in a function").insert(def_id, value);
+                        }
+                    }
+                },
+
+                ast::StmtKind::Assignment { var, op, value } => {
+                    let def_id = match &var.name {
+                        ast::VarName::Normal { ident, .. } => self.resolutions.expect_def(ident),
+                        ast::VarName::Reg { .. } => return Err(self.non_const_error(var.span)),
+                    };
+                    if !self.locals_stack.last().expect("not in a function").contains_key(&def_id) {
+                        return Err(self.non_const_error(var.span));
+                    }
+                    let value = self._const_eval(value)?;
+                    let value = match op.corresponding_binop() {
+                        None => value,
+                        Some(binop) => {
+                            let old_value = self.locals_stack.last().unwrap()[&def_id].clone();
+                            sp!(op.span => binop).const_eval(old_value, value)
+                        },
+                    };
+                    self.locals_stack.last_mut().unwrap().insert(def_id, value);
+                },
+
+                ast::StmtKind::Expr(expr) => match &expr.value {
+                    // allow calls that don't return a value
+                    ast::Expr::Call(call) => { self._const_eval_call(expr.span, call)?; },
+                    _ => { self._const_eval(expr)?; },
+                },
+
+                _ => return Err(self.emitter.emit(error!(
+                    message("{} in const function", stmt.kind.descr()),
+                    primary(stmt, "not supported in const functions"),
+                ))),
+            }
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    fn _const_eval_cond(&mut self, cond: &Sp<ast::Expr>) -> Result<bool, ErrorReported> {
+        match self._const_eval(cond)? {
+            ScalarValue::Int(x) => Ok(x != 0),
+            _ => panic!("uncaught type error"),
+        }
+    }
+
+    fn _const_eval_call(&mut self, call_span: Span, call: &ast::ExprCall) -> Result<Option<ScalarValue>, ErrorReported> {
+        let def_id = match &call.name.value {
+            ast::CallableName::Normal { ident, .. } => self.resolutions.expect_def(ident),
+            ast::CallableName::Ins { .. } => return Err(self.non_const_error(call_span)),
+        };
+        if !call.pseudos.is_empty() {
+            return Err(self.non_const_error(call_span));
+        }
+        let args = call.args.iter().map(|arg| self._const_eval(arg)).collect::<Result<Vec<_>, _>>()?;
+        self._call_const_func(call_span, def_id, args)
+    }
+
     // !!! IMPORTANT !!!
     // This function must be updated in sync with the const simplification pass.
     // (it did not seem possible to factor the shared logic out...)
@@ -196,6 +808,9 @@ impl<'a> Evaluator<'a> {
             ast::Expr::Var(var) => match var.name {
                 ast::VarName::Normal { ref ident, .. } => {
                     let def_id = self.resolutions.expect_def(ident);
+                    if let Some(value) = self.locals_stack.last().and_then(|locals| locals.get(&def_id)) {
+                        return Ok(value.clone().cast_by_ty_sigil(var.ty_sigil).expect("shoulda been type-checked"));
+                    }
                     let const_id = def_id.into();
                     let inherent_value = self._get_or_compute(Some(expr.span), const_id)?;
                     let cast_value = inherent_value.clone().cast_by_ty_sigil(var.ty_sigil).expect("shoulda been type-checked");
@@ -221,6 +836,15 @@ impl<'a> Evaluator<'a> {
                 return Ok(op.const_eval(a_value, b_value));
             },
 
+            ast::Expr::Ternary { cond, left, right, .. } if !self.locals_stack.is_empty() => {
+                // Inside a const function, only evaluate the branch that is taken so that recursion can terminate.
+                // (non-const things like registers were already forbidden by name resolution)
+                return match self._const_eval_cond(cond)? {
+                    true => self._const_eval(left),
+                    false => self._const_eval(right),
+                };
+            },
+
             ast::Expr::Ternary { cond, left, right, .. } => {
                 // NOTE: currently we evaluate both branches so that that we always error on non-const
                 //       subexpressions.  Perhaps in the future we'd like to permit "circular" definitions
@@ -235,6 +859,13 @@ impl<'a> Evaluator<'a> {
                     _ => panic!("uncaught type error"),
                 }
             },
+
+            ast::Expr::Call(call) => {
+                return self._const_eval_call(expr.span, call)?.ok_or_else(|| self.emitter.emit(error!(
+                    message("const function call did not produce a value"),
+                    primary(expr, "no value returned"),
+                )));
+            },
             _ => {}, // fall to error path
         }
 
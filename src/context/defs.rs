@@ -1,18 +1,21 @@
 use enum_map::{EnumMap, enum_map};
 use indexmap::IndexMap;
 use core::fmt;
+use std::sync::Arc;
 
 use crate::raw;
 use crate::ast;
 use crate::context::{self, CompilerContext};
+use crate::diagnostic::Diagnostic;
 use crate::error::{GatherErrorIteratorExt, ErrorReported};
 use crate::pos::{Sp, Span, SourceStr};
-use crate::game::{Game, LanguageKey};
+use crate::game::{Game, LanguageKey, Limit};
 use crate::ident::{Ident, ResIdent};
 use crate::resolve::{RegId, Namespace, DefId, NodeId, LoopId, ConstId, AliasableId, IdMap, id_map, rib};
 use crate::mapfile::Mapfile;
+use crate::io::Encoding;
 use crate::value::{ScalarValue, ScalarType, VarType, ExprType};
-use crate::llir::{InstrAbi, IntrinsicInstrKind};
+use crate::llir::{InstrAbi, IntrinsicInstrKind, LanguageHooks, LabelEncoding, MapfilePaths, HowBadIsIt};
 
 /// Bit representation of the NAN constant in the compiler.
 pub const CANONICAL_NAN_BITS: u32 = 0x7FC0_0000;
@@ -32,6 +35,9 @@ pub const CANONICAL_NAN_BITS: u32 = 0x7FC0_0000;
 pub struct Defs {
     regs: IdMap<(LanguageKey, RegId), RegData>,
     instrs: IdMap<(LanguageKey, raw::Opcode), InsData>,
+    /// Values produced by instructions, from `!ins_rets`.  Kept apart from [`InsData`] because they can be
+    /// given for instructions whose signature is not (yet) known.
+    ins_rets: IdMap<(LanguageKey, raw::Opcode), InsRet>,
 
     vars: IdMap<DefId, VarData>,
     funcs: IdMap<DefId, FuncData>,
@@ -47,6 +53,16 @@ pub struct Defs {
     /// Intrinsics from mapfiles.
     intrinsic_instrs: EnumMap<LanguageKey, Vec<(raw::Opcode, Sp<IntrinsicInstrKind>)>>,
 
+    /// Instructions flagged in mapfiles as making scratch registers unsafe, in addition to those known to the formats.
+    scratch_disabling_instrs: IdMap<(LanguageKey, raw::Opcode), HowBadIsIt>,
+
+    /// Label encodings from mapfiles, overriding those of the formats.
+    label_encodings: EnumMap<LanguageKey, Option<Sp<LabelEncoding>>>,
+    string_encodings: EnumMap<LanguageKey, Option<Sp<Encoding>>>,
+
+    /// Limits from mapfiles, overriding those of the game.
+    limit_overrides: EnumMap<Limit, Option<u32>>,
+
     /// Maps enum const names to their enum if they are unique, or to None if they are shared by
     /// multiple enums.
     unique_enums: IdMap<Ident, Option<Ident>>,
@@ -106,10 +122,10 @@ struct VarData {
 #[derive(Debug, Clone)]
 enum VarKind {
     RegisterAlias {
-        ident: ResIdent,
+        /// The span points into the mapfile.
+        ident: Sp<ResIdent>,
         language: LanguageKey,
         reg: RegId,
-        // TODO: location where alias is defined, follow the example set by InstrAbiLoc
     },
     Local {
         /// NOTE: For auto-generated temporaries, the span may point to their expression instead.
@@ -150,13 +166,49 @@ impl From<Span> for ConstExprLoc {
 struct InsData {
     abi_loc: InstrAbiLoc,
     abi: InstrAbi,
-    sig: Signature,
+    sig: Arc<Signature>,
+}
+
+/// The preferred names of registers and instructions, as returned by [`Defs::alias_names`].
+///
+/// Attaching this to a [`fmt::Config`][crate::fmt::Config] lets diagnostics show `REG[10012]` and
+/// `ins_302` under their names from mapfiles.
+#[derive(Debug, Clone, Default)]
+pub struct AliasNames {
+    regs: IdMap<(LanguageKey, RegId), Ident>,
+    instrs: IdMap<(LanguageKey, raw::Opcode), Ident>,
 }
 
-/// Diagnostic information about where an instruction ABI is defined.
+impl crate::fmt::NamingOracle for AliasNames {
+    fn name_for_reg(&self, language: LanguageKey, reg: RegId) -> Option<Ident> {
+        self.regs.get(&(language, reg)).cloned()
+    }
+
+    fn name_for_opcode(&self, language: LanguageKey, opcode: raw::Opcode) -> Option<Ident> {
+        self.instrs.get(&(language, opcode)).cloned()
+    }
+}
+
+/// The value produced by an instruction declared in `!ins_rets`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct InsRet {
+    pub ty: ScalarType,
+    pub location: InsRetLocation,
+}
+
+/// Where an instruction leaves its value for the code that called it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InsRetLocation {
+    /// The value is written to a fixed register, which the caller reads afterwards.
+    Reg(RegId),
+    /// The value is pushed onto the stack.
+    Stack,
+}
+
+/// Diagnostic information about where an instruction ABI (or other signature) is defined.
 #[derive(Debug, Clone)]
 pub enum InstrAbiLoc {
-    Span(Span),  // string in a mapfile
+    Span(Span),  // string in a mapfile, or name of a user function
     CoreMapfile {
         language: LanguageKey,
         opcode: raw::Opcode,
@@ -164,20 +216,35 @@ pub enum InstrAbiLoc {
     },
 }
 
+impl InstrAbiLoc {
+    /// Point out where the signature was defined, so that the user can tell which mapfile entry
+    /// (or declaration) is responsible for a mismatch.
+    pub fn add_to_diagnostic(&self, diag: &mut Diagnostic) {
+        match self {
+            InstrAbiLoc::Span(span) => { diag.secondary(span, "signature defined here".to_string()); },
+            InstrAbiLoc::CoreMapfile { language, opcode, abi_str } => {
+                diag.note(format!("the built-in signature for {} opcode {opcode} is \"{abi_str}\"", language.descr()));
+            },
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct FuncData {
     kind: FuncKind,
     /// `None` for [`FuncKind::InstructionAlias`].
-    sig: Option<Signature>,
+    sig: Option<Arc<Signature>>,
+    /// A copy of the definition of a `const` function, so that calls to it can be evaluated.
+    const_def: Option<ast::ItemFunc>,
 }
 
 #[derive(Debug, Clone)]
 pub enum FuncKind {
     InstructionAlias {
-        ident: ResIdent,
+        /// The span points into the mapfile.
+        ident: Sp<ResIdent>,
         language: LanguageKey,
         opcode: raw::Opcode,
-        // TODO: location where alias is defined
     },
     User {
         ident: Sp<ResIdent>,
@@ -206,6 +273,7 @@ impl Default for Defs {
         Defs {
             regs: Default::default(),
             instrs: Default::default(),
+            ins_rets: Default::default(),
             vars: Default::default(),
             funcs: Default::default(),
             enums: auto_enum_names::all().into_iter().map(|enum_name| (enum_name, Default::default())).collect(),
@@ -218,6 +286,10 @@ impl Default for Defs {
                 builtin_const_rib: Rib::new(Namespace::Vars, RibKind::BuiltinConsts),
             },
             intrinsic_instrs: Default::default(),
+            scratch_disabling_instrs: Default::default(),
+            label_encodings: Default::default(),
+            string_encodings: Default::default(),
+            limit_overrides: Default::default(),
             unique_enums: Default::default(),
             enum_const_dummy_def_id: None,
         }
@@ -235,7 +307,7 @@ impl CompilerContext<'_> {
         self.defs.regs.insert((language, reg), RegData { ty });
     }
 
-    /// Add an alias for a register from a mapfile.
+    /// Add an alias for a register from a mapfile (or a `#pragma reg`).
     ///
     /// The alias will also become the new preferred alias for decompiling that register.
     pub fn define_global_reg_alias(&mut self, language: LanguageKey, reg: RegId, ident: Sp<Ident>) -> DefId {
@@ -244,16 +316,17 @@ impl CompilerContext<'_> {
 
         self.defs.vars.insert(def_id, VarData {
             ty: None,
-            kind: VarKind::RegisterAlias { language, reg, ident: res_ident },
+            kind: VarKind::RegisterAlias { language, reg, ident: sp!(ident.span => res_ident) },
         });
         self.defs.reg_aliases.insert((language, reg), def_id);
 
-        if let Err(old) = self.defs.global_ribs.reg_alias_ribs[language].insert(sp!(ident.clone()), def_id) {
+        if let Err(old) = self.defs.global_ribs.reg_alias_ribs[language].insert(ident.clone(), def_id) {
             let old_reg = self.defs.var_reg(old.def_id).unwrap().1;
             if old_reg != reg {
                 self.emitter.emit(warning!(
-                    "name '{}' used for multiple registers in mapfiles: {}, {}",
-                    ident, old_reg, reg,
+                    message("name '{}' used for multiple registers: {}, {}", ident, old_reg, reg),
+                    primary(ident, "alias for {}", reg),
+                    secondary(old.def_ident_span, "alias for {}", old_reg),
                 )).ignore();
             }
         }
@@ -272,6 +345,31 @@ impl CompilerContext<'_> {
         def_id
     }
 
+    /// Report an error if a file has more of something than a [`Limit`] allows.
+    ///
+    /// `items` holds the spans of the limited things in order; the error points at the first one beyond the limit.
+    pub fn check_limit(&self, game: Game, limit: Limit, items: impl IntoIterator<Item=Span>) -> Result<(), ErrorReported> {
+        let Some(max) = self.defs.limit(game, limit) else { return Ok(()) };
+        let Some(span) = items.into_iter().nth(max as usize) else { return Ok(()) };
+        let mut diag = error!(
+            message("too many {}", limit.descr_plural()),
+            primary(span, "exceeds the limit of {max}"),
+        );
+        match self.defs.limit_overrides[limit] {
+            Some(_) => diag.note("this limit was set by a mapfile".to_string()),
+            None => diag.note(format!("{} supports at most {max} {}; mapfiles for modded engines can raise this with a '{limit}' entry in !limits", game.abbr(), limit.descr_plural())),
+        };
+        Err(self.emitter.emit(diag))
+    }
+
+    /// Fix the type of a `var` local whose type was inferred after it was declared.
+    pub fn set_local_ty(&mut self, def_id: DefId, ty: ScalarType) {
+        match self.defs.vars.get_mut(&def_id) {
+            Some(VarData { ty: var_ty, kind: VarKind::Local { .. } }) => *var_ty = Some(VarType::Typed(ty)),
+            _ => panic!("(bug!) set_local_ty called on something other than a local"),
+        }
+    }
+
     /// Declare an enum const without a span, creating a brand new [`ConstId`].
     ///
     /// This alternative to [`Self::define_enum_const`] takes simpler arguments and is designed
@@ -358,24 +456,38 @@ impl CompilerContext<'_> {
     /// A high-level [`Signature`] will also be generated from the ABI.
     pub fn set_ins_abi(&mut self, language: LanguageKey, opcode: raw::Opcode, abi: Sp<InstrAbi>, abi_loc: InstrAbiLoc) {
         // also update the high-level signature
-        let sig = abi.create_signature(abi.span, self);
+        let mut sig = abi.create_signature(abi.span, self);
+        if let Some(ret) = self.defs.ins_rets.get(&(language, opcode)) {
+            sig.return_ty = sp!(abi.span => ExprType::Value(ret.ty));
+        }
         sig.validate(self).expect("invalid signature from InstrAbi");
 
-        self.defs.instrs.insert((language, opcode), InsData { abi: abi.value, abi_loc, sig });
+        self.defs.instrs.insert((language, opcode), InsData { abi: abi.value, abi_loc, sig: Arc::new(sig) });
+    }
+
+    /// Declare that an instruction produces a value, changing the return type of its signature.
+    pub fn set_ins_ret(&mut self, language: LanguageKey, opcode: raw::Opcode, ret: InsRet, span: Span) {
+        self.defs.ins_rets.insert((language, opcode), ret);
+        if let Some(InsData { sig, .. }) = self.defs.instrs.get_mut(&(language, opcode)) {
+            Arc::make_mut(sig).return_ty = sp!(span => ExprType::Value(ret.ty));
+        }
     }
 
     /// Add an alias for an instruction from a mapfile.
     ///
-    /// The alias will also become the new preferred alias for decompiling that instruction.
-    pub fn define_global_ins_alias(&mut self, language: LanguageKey, opcode: raw::Opcode, ident: Sp<Ident>) -> DefId {
+    /// If `preferred` is `true`, the alias will also become the new preferred alias for decompiling that instruction.
+    pub fn define_global_ins_alias(&mut self, language: LanguageKey, opcode: raw::Opcode, ident: Sp<Ident>, preferred: bool) -> DefId {
         let res_ident = self.resolutions.attach_fresh_res(ident.value.clone());
         let def_id = self.create_new_def_id(&res_ident);
 
         self.defs.funcs.insert(def_id, FuncData {
             sig: None,
-            kind: FuncKind::InstructionAlias { language, opcode, ident: res_ident },
+            kind: FuncKind::InstructionAlias { language, opcode, ident: sp!(ident.span => res_ident) },
+            const_def: None,
         });
-        self.defs.ins_aliases.insert((language, opcode), def_id);
+        if preferred {
+            self.defs.ins_aliases.insert((language, opcode), def_id);
+        }
 
         if let Err(old) = self.defs.global_ribs.ins_alias_ribs[language].insert(ident.clone(), def_id) {
             let old_opcode = self.defs.func_opcode(old.def_id).unwrap().1;
@@ -390,17 +502,15 @@ impl CompilerContext<'_> {
     }
 
     /// Add a user-defined function, resolving the ident to a brand new [`DefId`]
-    pub fn define_user_func(
-        &mut self,
-        ident: Sp<ResIdent>,
-        qualifier: Option<Sp<ast::FuncQualifier>>,
-        siggy: Signature,
-    ) -> DefId {
-        let def_id = self.create_new_def_id(&ident);
+    pub fn define_user_func(&mut self, func: &ast::ItemFunc) -> DefId {
+        let ast::ItemFunc { ref ident, ty_keyword, ref params, qualifier, code: _ } = *func;
+        let def_id = self.create_new_def_id(ident);
 
+        let const_def = matches!(qualifier, Some(sp_pat![token![const]])).then(|| func.clone());
         self.defs.funcs.insert(def_id, FuncData {
-            sig: Some(siggy),
-            kind: FuncKind::User { ident, qualifier },
+            sig: Some(Arc::new(Signature::from_func_params(ty_keyword, params))),
+            kind: FuncKind::User { ident: ident.clone(), qualifier },
+            const_def,
         });
         def_id
     }
@@ -463,6 +573,21 @@ impl Defs {
     pub fn ins_abi(&self, language: LanguageKey, opcode: raw::Opcode) -> Option<(&InstrAbi, &InstrAbiLoc)> {
         self.instrs.get(&(language, opcode)).map(|x| (&x.abi, &x.abi_loc))
     }
+
+    /// Get the names that registers and instructions are decompiled to, for use by the formatter.
+    ///
+    /// This is a snapshot; names defined afterwards are not included.
+    pub fn alias_names(&self) -> AliasNames {
+        AliasNames {
+            regs: self.reg_aliases.iter().map(|(&key, &def_id)| (key, self.var_name(def_id).as_raw().clone())).collect(),
+            instrs: self.ins_aliases.iter().map(|(&key, &def_id)| (key, self.func_name(def_id).as_raw().clone())).collect(),
+        }
+    }
+
+    /// Get how an instruction produces its value, if it was declared to produce one.
+    pub fn ins_ret(&self, language: LanguageKey, opcode: raw::Opcode) -> Option<InsRet> {
+        self.ins_rets.get(&(language, opcode)).copied()
+    }
 }
 
 /// # Accessing high-level information
@@ -540,6 +665,11 @@ impl Defs {
     ///
     /// Panics if the ID does not correspond to a function.
     pub fn func_signature(&self, def_id: DefId) -> Result<&Signature, InsMissingSigError> {
+        self.shared_func_signature(def_id).map(|sig| &**sig)
+    }
+
+    /// Like [`Self::func_signature`], but the signature can be kept after `self` is no longer borrowed.
+    pub fn shared_func_signature(&self, def_id: DefId) -> Result<&Arc<Signature>, InsMissingSigError> {
         match self.funcs[&def_id] {
             FuncData { kind: FuncKind::InstructionAlias { language, opcode, .. }, .. } => {
                 self.ins_signature(language, opcode)
@@ -549,7 +679,7 @@ impl Defs {
     }
 
     /// Get the high-level signature of an instruction.
-    fn ins_signature(&self, language: LanguageKey, opcode: raw::Opcode) -> Result<&Signature, InsMissingSigError> {
+    fn ins_signature(&self, language: LanguageKey, opcode: raw::Opcode) -> Result<&Arc<Signature>, InsMissingSigError> {
         match self.instrs.get(&(language, opcode)) {
             Some(InsData { sig, .. }) => Ok(sig),
             None => Err(InsMissingSigError { language, opcode }),
@@ -598,6 +728,20 @@ impl Defs {
         }
     }
 
+    /// Get the definition of a `const` function, for evaluating calls to it.  `None` if not a const function.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ID does not correspond to a function.
+    pub fn const_func_def(&self, def_id: DefId) -> Option<&ast::ItemFunc> {
+        self.funcs[&def_id].const_def.as_ref()
+    }
+
+    /// Get all `const` functions.
+    pub fn const_funcs(&self) -> impl Iterator<Item=DefId> + '_ {
+        self.funcs.iter().filter(|(_, data)| data.const_def.is_some()).map(|(&def_id, _)| def_id)
+    }
+
     /// Get the expression assigned to a const var.
     ///
     /// # Panics
@@ -611,6 +755,34 @@ impl Defs {
             _ => None,
         }
     }
+
+    /// Describe where something was defined, for tooling such as "go to definition" in editors.
+    ///
+    /// Unlike [`Self::var_decl_span`] and [`Self::func_decl_span`], this produces spans that
+    /// point into mapfiles for aliases.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the ID does not correspond to something in the given namespace.
+    pub fn definition(&self, ns: Namespace, def_id: DefId) -> crate::navigate::Definition {
+        use crate::navigate::{Definition, DefinitionKind};
+
+        let (span, kind) = match ns {
+            Namespace::Vars => match &self.vars[&def_id].kind {
+                VarKind::RegisterAlias { ident, language, reg } => (Some(ident.span), DefinitionKind::RegisterAlias { language: *language, reg: *reg }),
+                VarKind::Local { ident } => (Some(ident.span), DefinitionKind::Local),
+                VarKind::BuiltinConst { .. } => (None, DefinitionKind::BuiltinConst),
+                VarKind::Const { ident, .. } => (Some(ident.span), DefinitionKind::Const),
+                VarKind::EnumConst { enum_name, ident, .. } => (Some(ident.span), DefinitionKind::EnumConst { enum_name: enum_name.value.clone() }),
+                VarKind::EnumConstDummy => panic!("definition called on enum dummy"),
+            },
+            Namespace::Funcs => match &self.funcs[&def_id].kind {
+                FuncKind::InstructionAlias { ident, language, opcode } => (Some(ident.span), DefinitionKind::InstructionAlias { language: *language, opcode: *opcode }),
+                FuncKind::User { ident, qualifier } => (Some(ident.span), DefinitionKind::Func { qualifier: qualifier.as_ref().map(|x| x.value) }),
+            },
+        };
+        Definition { def_id, span, kind }
+    }
 }
 
 impl CompilerContext<'_> {
@@ -641,7 +813,8 @@ impl CompilerContext<'_> {
 
     /// Add info from a mapfile.
     ///
-    /// Its path (if one is provided) is recorded in order to emit import directives into a decompiled script file.
+    /// Its path (if one is provided) is recorded in order to emit import directives into decompiled script files
+    /// that use its definitions.  See [`Self::used_mapfiles_to_ast`].
     pub fn extend_from_mapfile(
         &mut self,
         path: Option<&std::path::Path>,
@@ -652,15 +825,31 @@ impl CompilerContext<'_> {
         let emitter = self.emitter;
 
         if let Some(path) = path {
-            self.mapfiles.push(path.to_owned());
+            self.mapfiles.push(LoadedMapfile { path: path.to_owned(), source_file: mapfile.source_file });
         }
 
         for (names, signatures, language) in vec![
             (&mapfile.ins_names, &mapfile.ins_signatures, mapfile.language),
             (&mapfile.timeline_ins_names, &mapfile.timeline_ins_signatures, LanguageKey::Timeline),
         ] {
-            for &(opcode, ref ident) in names {
-                self.define_global_ins_alias(language, opcode as u16, ident.clone());
+            // an opcode may have synonyms; the one to decompile into is marked with '*', or else is the first
+            let mut preferred_indices = IndexMap::<i32, usize>::new();
+            for (index, &(opcode, ref name)) in names.iter().enumerate() {
+                let prev_index = *preferred_indices.entry(opcode).or_insert(index);
+                let prev_name = &names[prev_index].1;
+                match (prev_name.canonical, name.canonical) {
+                    (false, true) => { preferred_indices.insert(opcode, index); },
+                    (true, true) if prev_index != index => emitter.emit(warning!(
+                        message("multiple names marked with '*' for opcode {} in {}", opcode, language.descr()),
+                        primary(name.ident, "ignoring this mark"),
+                        secondary(prev_name.ident, "first marked here"),
+                    )).ignore(),
+                    _ => {},
+                }
+            }
+            for (index, &(opcode, ref name)) in names.iter().enumerate() {
+                let preferred = preferred_indices[&opcode] == index;
+                self.define_global_ins_alias(language, opcode as u16, name.ident.clone(), preferred);
             }
 
             signatures.iter().map(|&(opcode, ref abi_str)| {
@@ -702,6 +891,21 @@ impl CompilerContext<'_> {
             self.set_reg_ty(mapfile.language, RegId(reg), ty);
         }
 
+        for &(opcode, ref value) in &mapfile.ins_rets {
+            let ret = match parse_ins_ret(value) {
+                Some(ret) => ret,
+                None => {
+                    emitter.emit(warning!(
+                        message("ignoring invalid return value '{}' for opcode {}", value, opcode),
+                        primary(value, "invalid return value"),
+                        note("expected a type sigil, optionally followed by the register that holds the value (e.g. '$REG[10000]')"),
+                    )).ignore();
+                    continue;
+                },
+            };
+            self.set_ins_ret(mapfile.language, opcode as u16, ret, value.span);
+        }
+
         mapfile.ins_intrinsics.iter().map(|&(opcode, ref kind_str)| {
             // since there's no escape syntax in mapfile values, abi_str exactly matches the source text,
             // so we can construct a SourceStr
@@ -718,6 +922,75 @@ impl CompilerContext<'_> {
                 .map_err(|e| emitter.emit(e))
         }).collect_with_recovery::<()>()?;
 
+        for &(opcode, ref flags_str) in &mapfile.ins_flags {
+            for flag in flags_str.split_whitespace() {
+                let how_bad = match flag {
+                    "disables_scratch" => HowBadIsIt::OhItsJustThisOneFunction,
+                    "disables_scratch_in_file" => HowBadIsIt::ItsWaterElf,
+                    _ => {
+                        emitter.emit(warning!(
+                            message("ignoring unknown flag '{}' for opcode {}", flag, opcode),
+                            primary(flags_str, "unknown flag"),
+                            note("valid flags are: 'disables_scratch', 'disables_scratch_in_file'"),
+                        )).ignore();
+                        continue;
+                    },
+                };
+                let entry = self.defs.scratch_disabling_instrs.entry((mapfile.language, opcode as u16)).or_insert(how_bad);
+                *entry = (*entry).max(how_bad);
+            }
+        }
+
+        for (_, extra_str) in mapfile.label_encoding.iter().skip(1) {
+            emitter.emit(warning!(
+                message("ignoring extra label encoding '{}'", extra_str),
+                primary(extra_str, "only the first one is used"),
+            )).ignore();
+        }
+        if let Some((_, encoding_str)) = mapfile.label_encoding.first() {
+            let encoding = encoding_str.parse().map_err(|valid: String| emitter.emit(error!(
+                message("invalid label encoding '{}'", encoding_str),
+                primary(encoding_str, "invalid encoding"),
+                note("{valid}"),
+            )))?;
+            self.defs.label_encodings[mapfile.language] = Some(sp!(encoding_str.span => encoding));
+        }
+
+        for (_, extra_str) in mapfile.string_encoding.iter().skip(1) {
+            emitter.emit(warning!(
+                message("ignoring extra string encoding '{}'", extra_str),
+                primary(extra_str, "only the first one is used"),
+            )).ignore();
+        }
+        if let Some((_, encoding_str)) = mapfile.string_encoding.first() {
+            let encoding = crate::io::encoding_from_name(encoding_str).ok_or_else(|| emitter.emit(error!(
+                message("invalid string encoding '{}'", encoding_str),
+                primary(encoding_str, "unknown or unsupported encoding"),
+                note("try 'shift_jis' or 'utf-8'"),
+            )))?;
+            self.defs.string_encodings[mapfile.language] = Some(sp!(encoding_str.span => encoding));
+        }
+
+        for &(value, ref limit_str) in &mapfile.limits {
+            let Ok(limit) = limit_str.parse::<Limit>() else {
+                let valid = Limit::iter().map(|limit| format!("'{limit}'")).collect::<Vec<_>>().join(", ");
+                emitter.emit(warning!(
+                    message("ignoring unknown limit '{}'", limit_str),
+                    primary(limit_str, "unknown limit"),
+                    note("valid limits are: {valid}"),
+                )).ignore();
+                continue;
+            };
+            let Ok(value) = u32::try_from(value) else {
+                emitter.emit(warning!(
+                    message("ignoring negative value {} for limit '{}'", value, limit),
+                    primary(limit_str, "negative limit"),
+                )).ignore();
+                continue;
+            };
+            self.defs.limit_overrides[limit] = Some(value);
+        }
+
         for (enum_name, enum_pairs) in &mapfile.enums {
             self.declare_enum(enum_name.clone());
             for &(value, ref const_name) in enum_pairs {
@@ -730,12 +1003,67 @@ impl CompilerContext<'_> {
         Ok(())
     }
 
-    pub fn mapfiles_to_ast(&self) -> Vec<crate::pos::Sp<ast::LitString>> {
-        self.mapfiles.iter().map(|s| {
-            let string = s.to_str().expect("unpaired surrogate not supported!").into();
-            sp!(ast::LitString { string })
-        }).collect()
+    /// Record that a definition at this span was used by a decompiled script, so that the mapfile it
+    /// comes from (if any) will be imported by [`Self::used_mapfiles_to_ast`].
+    pub fn mark_mapfile_span_used(&self, span: Span) {
+        if span.file_id.is_some() {
+            self.mapfile_usage.lock().unwrap().insert(span.file_id);
+        }
     }
+
+    /// Get the paths for `#pragma mapfile` lines, for the loaded mapfiles whose definitions have been used
+    /// since the last call.
+    ///
+    /// This resets the recorded usage, so it should be called once at the end of decompiling each file.
+    pub fn used_mapfiles_to_ast(&self, mapfile_paths: &MapfilePaths) -> Result<Vec<Sp<ast::LitString>>, ErrorReported> {
+        let used_files = std::mem::take(&mut *self.mapfile_usage.lock().unwrap());
+        let fs = crate::io::Fs::new(self.emitter);
+        let base_dir = match mapfile_paths {
+            MapfilePaths::AsGiven => None,
+            MapfilePaths::RelativeTo(dir) => Some(fs.canonicalize(dir).map_err(|e| self.emitter.emit(e))?),
+        };
+
+        self.mapfiles.iter()
+            .filter(|mapfile| mapfile.source_file.is_some() && used_files.contains(&mapfile.source_file))
+            .map(|mapfile| {
+                let path = match &base_dir {
+                    None => mapfile.path.clone(),
+                    Some(base_dir) => {
+                        let full_path = fs.canonicalize(&mapfile.path).map_err(|e| self.emitter.emit(e))?;
+                        path_relative_to(&full_path, base_dir)
+                    },
+                };
+                let string = path.to_str().expect("unpaired surrogate not supported!").into();
+                Ok(sp!(ast::LitString { string }))
+            }).collect()
+    }
+}
+
+/// A mapfile loaded from a path, as recorded by [`CompilerContext::extend_from_mapfile`].
+#[derive(Debug, Clone)]
+pub(super) struct LoadedMapfile {
+    /// The path as it was given.
+    path: std::path::PathBuf,
+    /// See [`Mapfile::source_file`].
+    source_file: crate::pos::FileId,
+}
+
+/// Express a canonical path relative to a canonical directory.
+fn path_relative_to(path: &std::path::Path, base_dir: &std::path::Path) -> std::path::PathBuf {
+    let path_components = path.components().collect::<Vec<_>>();
+    let base_components = base_dir.components().collect::<Vec<_>>();
+    let num_common = path_components.iter().zip(&base_components).take_while(|(a, b)| a == b).count();
+    if num_common == 0 {
+        // e.g. on different drives
+        return path.to_owned();
+    }
+
+    let mut out = std::path::PathBuf::new();
+    for _ in num_common..base_components.len() {
+        out.push("..");
+    }
+    out.extend(&path_components[num_common..]);
+    out
 }
 
 impl Defs {
@@ -765,6 +1093,36 @@ impl Defs {
         self.intrinsic_instrs[language].push((opcode, intrinsic));
     }
 
+    /// How jump destinations are written in a language, as declared by a mapfile or else by its format.
+    pub fn label_encoding(&self, hooks: &dyn LanguageHooks) -> LabelEncoding {
+        self.label_encodings[hooks.language()].map_or_else(|| hooks.label_encoding(), |encoding| encoding.value)
+    }
+
+    /// How string arguments are encoded in a language, as declared by a mapfile or else by its format.
+    pub fn string_encoding(&self, hooks: &dyn LanguageHooks) -> Encoding {
+        self.string_encodings[hooks.language()].map_or_else(|| hooks.string_encoding(), |encoding| encoding.value)
+    }
+
+    /// Spans of the mapfile entries that override the label and string encodings of a language.
+    pub fn encoding_override_spans(&self, language: LanguageKey) -> impl Iterator<Item=Span> + '_ {
+        let label_span = self.label_encodings[language].map(|encoding| encoding.span);
+        let string_span = self.string_encodings[language].map(|encoding| encoding.span);
+        label_span.into_iter().chain(string_span)
+    }
+
+    /// Whether an instruction makes it unsafe to use scratch registers, as flagged by a mapfile or known by its format.
+    ///
+    /// When both say something, the worse of the two is used.
+    pub fn instr_disables_scratch_regs(&self, hooks: &dyn LanguageHooks, opcode: raw::Opcode) -> Option<HowBadIsIt> {
+        let from_mapfile = self.scratch_disabling_instrs.get(&(hooks.language(), opcode)).copied();
+        from_mapfile.max(hooks.instr_disables_scratch_regs(opcode))
+    }
+
+    /// The value of a limit, as declared by a mapfile or else known for the game.
+    pub fn limit(&self, game: Game, limit: Limit) -> Option<u32> {
+        self.limit_overrides[limit].or_else(|| game.limit(limit))
+    }
+
     pub fn iter_intrinsic_instrs(&self, language: LanguageKey) -> impl Iterator<Item=(raw::Opcode, Sp<IntrinsicInstrKind>)> + '_ {
         self.intrinsic_instrs[language].iter().copied()
     }
@@ -826,7 +1184,7 @@ impl CompilerContext<'_> {
     /// Panics if there is `REG` syntax and `language` is `None`; this should be caught in an earlier pass.
     pub fn var_inherent_ty_from_ast(&self, var: &ast::Var) -> VarType {
         match var.name {
-            ast::VarName::Reg { reg, language } => self.defs.reg_inherent_ty(language.expect("must run assign_languages pass!"), reg),
+            ast::VarName::Reg { reg, language, .. } => self.defs.reg_inherent_ty(language.expect("must run assign_languages pass!"), reg),
             ast::VarName::Normal { ref ident, .. } => self.defs.var_inherent_ty(self.resolutions.expect_def(ident)),
         }
     }
@@ -869,15 +1227,31 @@ impl CompilerContext<'_> {
         }
     }
 
+    /// Get the location where the signature of any kind of callable function was defined, for use in diagnostics.
+    ///
+    /// For user-defined functions, this is the function's name at its declaration.  Returns `None`
+    /// if the function is an instruction whose signature is not known.
+    pub fn func_signature_loc_from_ast(&self, name: &ast::CallableName) -> Option<InstrAbiLoc> {
+        match self.func_opcode_from_ast(name) {
+            Ok((language, opcode)) => self.defs.ins_abi(language, opcode).map(|(_, loc)| loc.clone()),
+            Err(def_id) => self.defs.func_decl_span(def_id).map(InstrAbiLoc::Span),
+        }
+    }
+
     /// Get the signature of any kind of callable function. (instructions, inline and const functions...)
     ///
     /// # Panics
     ///
     /// Panics if there is `ins_` syntax and `language` is `None`; this should be caught in an earlier pass.
     pub fn func_signature_from_ast(&self, name: &ast::CallableName) -> Result<&Signature, InsMissingSigError> {
+        self.shared_func_signature_from_ast(name).map(|sig| &**sig)
+    }
+
+    /// Like [`Self::func_signature_from_ast`], but the signature can be kept after `self` is no longer borrowed.
+    pub fn shared_func_signature_from_ast(&self, name: &ast::CallableName) -> Result<&Arc<Signature>, InsMissingSigError> {
         match *name {
-            ast::CallableName::Ins { opcode, language } => self.defs.ins_signature(language.expect("must run assign_languages pass!"), opcode),
-            ast::CallableName::Normal { ref ident, .. } => self.defs.func_signature(self.resolutions.expect_def(ident)),
+            ast::CallableName::Ins { opcode, language, .. } => self.defs.ins_signature(language.expect("must run assign_languages pass!"), opcode),
+            ast::CallableName::Normal { ref ident, .. } => self.defs.shared_func_signature(self.resolutions.expect_def(ident)),
         }
     }
 
@@ -905,7 +1279,7 @@ impl CompilerContext<'_> {
     /// FIXME: free-form function of `reg_to_ast`, feels awkwardly placed
     pub fn reg_to_ast_(language: LanguageKey, reg: RegId, alias: Option<ResIdent>) -> ast::VarName {
         match alias {
-            None => ast::VarName::Reg { reg, language: Some(language) },
+            None => ast::VarName::Reg { reg, language: Some(language), language_prefix: None },
             Some(ident) => ast::VarName::Normal { ident, language_if_reg: Some(language) },
         }
     }
@@ -914,7 +1288,7 @@ impl CompilerContext<'_> {
     /// automatically using an alias if one exists.
     pub fn ins_to_ast(&self, language: LanguageKey, opcode: raw::Opcode) -> ast::CallableName {
         match self.defs.ins_aliases.get(&(language, opcode)) {
-            None => ast::CallableName::Ins { opcode, language: Some(language) },
+            None => ast::CallableName::Ins { opcode, language: Some(language), language_prefix: None },
             Some(&def_id) => {
                 let ident = self.defs.func_name(def_id).clone();
                 ast::CallableName::Normal { ident, language_if_ins: Some(language) }
@@ -922,6 +1296,13 @@ impl CompilerContext<'_> {
         }
     }
 
+    /// Write a piece of the AST for a diagnostic, like [`crate::fmt::stringify`], but with raw registers
+    /// and instructions written under their names from mapfiles.  (see [`Defs::alias_names`])
+    pub fn stringify_for_diagnostic<T: crate::fmt::Format>(&self, value: &T) -> String {
+        let config = crate::fmt::Config::new().max_columns(1000).naming_oracle(self.defs.alias_names());
+        crate::fmt::stringify_with(value, config)
+    }
+
     /// Suppress the type sigil of a variable if it is unnecessary.
     ///
     /// FIXME: free-form function of `var_simplify_ty_sigil`, feels awkwardly placed
@@ -976,8 +1357,8 @@ impl CompilerContext<'_> {
     }
 
     fn all_signatures(&self) -> impl Iterator<Item=&'_ Signature> {
-        let ins_sigs = self.defs.instrs.values().map(|data| &data.sig);
-        let non_ins_sigs = self.defs.funcs.values().filter_map(|func| func.sig.as_ref());
+        let ins_sigs = self.defs.instrs.values().map(|data| &*data.sig);
+        let non_ins_sigs = self.defs.funcs.values().filter_map(|func| func.sig.as_deref());
         ins_sigs.chain(non_ins_sigs)
     }
 
@@ -1159,6 +1540,25 @@ pub struct MatchedArgs<'a> {
     pub positional_pairs: Box<dyn Iterator<Item=(&'a SignatureParam, &'a Sp<ast::Expr>)> + 'a>,
 }
 
+/// Parse a value from `!ins_rets`, which is a sigil for the type, followed by `REG[n]` if the value is
+/// written to a register (e.g. `$REG[10000]`) or by nothing if it is pushed onto the stack (e.g. `%`).
+fn parse_ins_ret(value: &str) -> Option<InsRet> {
+    let value = value.trim();
+    let (ty, rest) = match value.chars().next()? {
+        '$' => (ScalarType::Int, &value[1..]),
+        '%' => (ScalarType::Float, &value[1..]),
+        _ => return None,
+    };
+    let location = match rest.trim() {
+        "" => InsRetLocation::Stack,
+        reg_str => {
+            let reg_str = reg_str.strip_prefix("REG[")?.strip_suffix("]")?;
+            InsRetLocation::Reg(RegId(reg_str.trim().parse().ok()?))
+        },
+    };
+    Some(InsRet { ty, location })
+}
+
 fn signature_from_func_ast(return_ty_keyword: Sp<ast::TypeKeyword>, params: &[Sp<ast::FuncParam>]) -> Signature {
     Signature {
         params: params.iter().map(|sp_pat!(ast::FuncParam { ty_keyword, ident, qualifier })| SignatureParam {
@@ -10,6 +10,8 @@ type FlagIndex = u32;
 #[derive(Debug, Clone)]
 pub struct DiffFlagDefs {
     flag_default_enable: BitSet32,
+    /// Flags that were given a name by a mapfile, rather than only having their digit name.
+    mapfile_named: BitSet32,
     by_name: BTreeMap<char, FlagIndex>,
     by_flag: BTreeMap<FlagIndex, char>,
 }
@@ -23,6 +25,7 @@ impl Default for DiffFlagDefs {
     fn default() -> Self {
         let mut out = DiffFlagDefs {
             flag_default_enable: Default::default(),
+            mapfile_named: Default::default(),
             by_name: Default::default(),
             by_flag: Default::default(),
         };
@@ -37,6 +40,9 @@ impl Default for DiffFlagDefs {
 
 const NUM_BITS: u32 = 8;
 
+/// The number of difficulties (Easy through Lunatic) when no aux flags are defined.
+const DEFAULT_NUM_DIFFICULTIES: usize = 4;
+
 impl DiffFlagDefs {
     pub fn define_flag_from_mapfile(&mut self, index: Sp<i32>, str: Sp<&str>) -> Result<(), Diagnostic> {
         if !(0 <= index.value && index.value < NUM_BITS as i32) {
@@ -67,6 +73,7 @@ impl DiffFlagDefs {
         };
 
         self.define_flag(name, index.value as _, enable);
+        self.mapfile_named.insert(index.value as _);
         Ok(())
     }
 
@@ -111,10 +118,34 @@ impl DiffFlagDefs {
         Ok(sp!(str.span => out))
     }
 
-    pub fn mask_to_diff_label(&self, mask: BitSet32) -> ast::LitString {
+    /// Check that the mask of a numeric difficulty label like `{0x83}:` fits in a difficulty mask.
+    pub fn check_numeric_mask(&self, mask: Sp<BitSet32>) -> Result<(), Diagnostic> {
+        if mask.mask() >> NUM_BITS != 0 {
+            return Err(error!(
+                message("difficulty mask out of range"),
+                primary(mask, "{:#X} does not fit in {} bits", mask.mask(), NUM_BITS),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Produce a difficulty label for decompiled code.
+    ///
+    /// If the mapfile names some of the flags but the mask uses one that it doesn't, a numeric label
+    /// like `{0x83}:` is produced instead of falling back to digit names.
+    pub fn mask_to_diff_label(&self, mask: BitSet32) -> ast::DiffLabel {
         let must_enable = mask & self.difficulty_bits();
         let must_disable = mask.complement(NUM_BITS) & self.aux_bits();
 
+        // flags that would be written by name ('*' covers all difficulties)
+        let written_bits = match must_enable == self.difficulty_bits() {
+            true => must_disable,
+            false => must_enable | must_disable,
+        };
+        if !self.mapfile_named.is_empty() && written_bits & self.mapfile_named != written_bits {
+            return ast::DiffLabel { mask: Some(mask), expr: None };
+        }
+
         let mut out = String::new();
         if must_enable == self.difficulty_bits() {
             out.push('*');  // all difficulties
@@ -131,7 +162,7 @@ impl DiffFlagDefs {
                 out.push(self.by_flag[&bit]);
             }
         }
-        out.into()
+        ast::DiffLabel { mask: Some(mask), expr: Some(sp!(ast::Expr::LitString(out.into()))) }
     }
 
     /// Get the set of flags not enabled by default.  Diff switches expand over these.
@@ -144,6 +175,30 @@ impl DiffFlagDefs {
         self.flag_default_enable
     }
 
+    /// The number of cases a diff switch may have.
+    ///
+    /// Case `i` of a switch uses flag `i`, so this counts the difficulty flags starting from flag 0.
+    pub fn max_diff_switch_cases(&self) -> usize {
+        self.difficulty_bits().mask().trailing_ones() as usize
+    }
+
+    /// The number of cases that every diff switch must have.  (trailing cases can be omitted with `:`)
+    ///
+    /// If the mapfile defines aux flags, this is [`Self::max_diff_switch_cases`].  Otherwise, it is the
+    /// four difficulties of the games.
+    pub fn num_diff_switch_cases(&self) -> usize {
+        match self.all_flags_are_difficulties() {
+            true => DEFAULT_NUM_DIFFICULTIES,
+            false => self.max_diff_switch_cases(),
+        }
+    }
+
+    /// Whether [`Self::max_diff_switch_cases`] is limited by the width of the mask rather than by
+    /// the flag definitions.
+    pub fn all_flags_are_difficulties(&self) -> bool {
+        self.max_diff_switch_cases() == NUM_BITS as usize
+    }
+
     fn currently_known_flags_msg(&self) -> String {
         self.by_name.keys().map(ToString::to_string).collect::<Vec<_>>().join(", ")
     }
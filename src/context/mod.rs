@@ -1,15 +1,18 @@
 //! Structs that carry important global compiler state.
 
-use std::path::PathBuf;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::ident::GensymContext;
+use crate::pos::FileId;
 use crate::resolve::{LoopId, NodeId, Resolutions, UnusedIds};
 use crate::resolve::rib::Rib;
 
 pub use defs::Defs;
 pub mod defs;
 
-pub use consts::Consts;
+pub use consts::{Consts, ConstCache};
 pub mod consts;
 
 pub use diff_flags::DiffFlagDefs;
@@ -51,7 +54,10 @@ pub struct CompilerContext<'ctx> {
     pub emitter: &'ctx RootEmitter,
 
     /// Catalogues all loaded mapfiles for generating imports.
-    mapfiles: Vec<PathBuf>,
+    mapfiles: Vec<defs::LoadedMapfile>,
+    /// Files whose definitions were used by the script currently being decompiled, so that only
+    /// the mapfiles it depends on are imported.
+    mapfile_usage: Mutex<BTreeSet<FileId>>,
     /// Results of name resolution.  Maps [`ResId`]s to [`DefId`]s.
     pub resolutions: Resolutions,
     /// Stores information about [`DefId`]s.
@@ -74,12 +80,54 @@ pub struct CompilerContext<'ctx> {
     /// Records intricate details about the compilation process to be emitted for e.g. a debugger.
     pub script_debug_info: Vec<crate::debug_info::Script>,
 
+    /// Maximum number of threads to use for work that is done separately for each script.
+    /// `1` does everything on the current thread, which can be useful for debugging.
+    pub num_threads: usize,
+
+    /// Whether to warn about gotos whose explicit time differs from the time at their label.
+    /// See [`crate::passes::validate_goto_times`].
+    pub warn_goto_times: bool,
+
+    /// Whether to warn about instructions whose time is lower than that of the previous instruction.
+    pub warn_time_decreases: bool,
+
+    /// Whether to warn about `@blob`s and `@mask`s that disagree with a known signature.
+    pub warn_blob_signatures: bool,
+
+    /// Which `==` and `!=` comparisons of floats to warn about.
+    /// See [`crate::passes::float_equality`].
+    pub warn_float_equality: crate::passes::float_equality::WarnFloatEquality,
+
+    /// Maximum number of iterations in a `times(inline n)` loop.
+    /// See [`crate::passes::unroll_times`].
+    pub max_unroll_count: u32,
+
+    /// Maximum depth of nested brackets, blocks and expressions.
+    /// See [`crate::passes::validate_nesting`].
+    pub max_nesting_depth: u32,
+
+    /// Whether to report peak scratch register usage for each script that uses scratch registers.
+    pub report_register_pressure: bool,
+
+    /// Whether to warn about ANM scripts and sprites that are never used by an instruction in the same file.
+    pub warn_unreferenced_anm_items: bool,
+
+    /// Whether to warn about ANM scripts with a loop that never advances time.
+    pub warn_anm_busy_loops: bool,
+
+    /// Whether to salvage what can be read from a corrupted ANM file, rather than failing on the first problem.
+    /// See [`crate::AnmFile::read_from_stream_lenient`].
+    pub lenient_reading: bool,
+
+    /// Hooks to be notified at various points of compilation.
+    pub(crate) observers: crate::passes::observer::Observers<'ctx>,
+
     /// The location where any data behind a `&'ctx` reference is *actually* stored.
     _scope: &'ctx Scope,
 
     // The lifetime would *probably* eventually have to become invariant if we added arenas (as we
     // may eventually have AST nodes inside a struct inside a RefCell), so let's force this constraint now.
-    _make_invariant: std::marker::PhantomData<*mut &'ctx ()>,
+    _make_invariant: std::marker::PhantomData<fn(&'ctx ()) -> &'ctx ()>,
 }
 
 impl<'ctx> CompilerContext<'ctx> {
@@ -87,6 +135,7 @@ impl<'ctx> CompilerContext<'ctx> {
         let mut ctx = CompilerContext {
             emitter: &scope.emitter,
             mapfiles: Default::default(),
+            mapfile_usage: Default::default(),
             resolutions: Default::default(),
             defs: Default::default(),
             gensym: Default::default(),
@@ -94,6 +143,18 @@ impl<'ctx> CompilerContext<'ctx> {
             initial_ribs: Default::default(),
             diff_flag_defs: Default::default(),
             script_debug_info: Default::default(),
+            observers: Default::default(),
+            num_threads: scope.num_threads,
+            warn_goto_times: true,
+            warn_time_decreases: true,
+            warn_blob_signatures: true,
+            warn_float_equality: Default::default(),
+            max_unroll_count: crate::passes::unroll_times::DEFAULT_MAX_UNROLL_COUNT,
+            max_nesting_depth: crate::passes::validate_nesting::DEFAULT_MAX_NESTING_DEPTH,
+            report_register_pressure: false,
+            warn_unreferenced_anm_items: false,
+            warn_anm_busy_loops: false,
+            lenient_reading: false,
             unused_node_ids: UnusedIds::new(),
             unused_loop_ids: UnusedIds::new(),
             _scope: scope,
@@ -102,6 +163,78 @@ impl<'ctx> CompilerContext<'ctx> {
         ctx.init_special_defs();
         ctx
     }
+
+    /// Call a function on every item, using up to [`Self::num_threads`] threads, and return the
+    /// outputs in the same order as the items.
+    ///
+    /// Each call gets its own emitter, whose diagnostics are only emitted once every call has finished.
+    /// They are sorted by span among themselves (see [`RootEmitter::emit_buffered`]), so that the output
+    /// does not depend on how the work was scheduled.  Diagnostics emitted directly before or after this
+    /// call are not part of that order.
+    pub fn map_in_parallel<T: Sync, R: Send>(
+        &self,
+        items: &[T],
+        func: impl Fn(&T, &RootEmitter) -> R + Sync,
+    ) -> Vec<R> {
+        let emitters = items.iter().map(|_| self.emitter.new_buffer()).collect::<Vec<_>>();
+        let num_threads = self.num_threads.min(items.len());
+
+        let outputs = if num_threads <= 1 {
+            items.iter().zip(&emitters).map(|(item, emitter)| func(item, emitter)).collect()
+        } else {
+            let next_index = AtomicUsize::new(0);
+            let mut indexed_outputs = std::thread::scope(|scope| {
+                let workers = (0..num_threads).map(|_| scope.spawn(|| {
+                    let mut indexed_outputs = vec![];
+                    loop {
+                        let index = next_index.fetch_add(1, Ordering::Relaxed);
+                        match items.get(index) {
+                            Some(item) => indexed_outputs.push((index, func(item, &emitters[index]))),
+                            None => return indexed_outputs,
+                        }
+                    }
+                })).collect::<Vec<_>>();
+
+                workers.into_iter().flat_map(|worker| {
+                    worker.join().unwrap_or_else(|payload| std::panic::resume_unwind(payload))
+                }).collect::<Vec<_>>()
+            });
+            indexed_outputs.sort_by_key(|&(index, _)| index);
+            indexed_outputs.into_iter().map(|(_, output)| output).collect()
+        };
+
+        self.emitter.emit_buffered(emitters);
+        outputs
+    }
+
+    /// Save the definitions made so far (e.g. by mapfiles), so that they can be restored with
+    /// [`Self::restore_defs`] after compiling a file.
+    pub fn save_defs(&self) -> SavedDefs {
+        SavedDefs {
+            defs: self.defs.clone(),
+            resolutions: self.resolutions.clone(),
+            consts: self.consts.clone(),
+        }
+    }
+
+    /// Forget everything defined since [`Self::save_defs`] was called, along with all other state that
+    /// belongs to a single file, so that another file can be compiled as though by a fresh context.
+    pub fn restore_defs(&mut self, saved: &SavedDefs) {
+        self.defs = saved.defs.clone();
+        self.resolutions = saved.resolutions.clone();
+        self.consts = saved.consts.clone();
+        self.unused_node_ids = UnusedIds::new();
+        self.unused_loop_ids = UnusedIds::new();
+        self.script_debug_info.clear();
+    }
+}
+
+/// Definitions saved by [`CompilerContext::save_defs`].
+#[derive(Debug, Clone)]
+pub struct SavedDefs {
+    defs: Defs,
+    resolutions: Resolutions,
+    consts: Consts,
 }
 
 /// The object that the `'ctx` lifetime on [`Truth`] primarily originates from.
@@ -115,10 +248,17 @@ impl<'ctx> CompilerContext<'ctx> {
 #[derive(Debug)]
 pub struct Scope {
     emitter: RootEmitter,
+    num_threads: usize,
 }
 
 impl Scope {
     pub fn new(emitter: RootEmitter) -> Self {
-        Scope { emitter }
+        Scope { emitter, num_threads: 1 }
+    }
+
+    /// Set the value of [`CompilerContext::num_threads`] for contexts created from this scope.
+    pub fn set_num_threads(&mut self, num_threads: usize) {
+        assert!(num_threads > 0);
+        self.num_threads = num_threads;
     }
 }
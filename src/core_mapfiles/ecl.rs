@@ -14,7 +14,7 @@ pub(super) fn core_signatures(game: Game) -> &'static CoreSignatures {
         Th095 => ECL_095,
 
         Th10 | Alcostg | Th11 | Th12 | Th125 | Th128 |
-        Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 => CoreSignatures::EMPTY,
+        Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 | Th19 => CoreSignatures::EMPTY,
     }
 }
 
@@ -23,7 +23,7 @@ pub(super) fn timeline_core_signatures(game: Game) -> &'static CoreSignatures {
         Th06 | Th07 | Th08 | Th09 | Th095 => TIMELINE,
 
         Th10 | Alcostg | Th11 | Th12 | Th125 | Th128 |
-        Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 => CoreSignatures::EMPTY,
+        Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 | Th19 => CoreSignatures::EMPTY,
     }
 }
 
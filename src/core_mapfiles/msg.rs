@@ -10,8 +10,8 @@ pub(super) fn core_signatures(game: Game) -> &'static CoreSignatures {
         => MSG_06_09,
 
         | Th10 | Alcostg | Th11 | Th12 | Th128 | Th13
-        | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18
-        => MSG_10_18,
+        | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 | Th19
+        => MSG_10_19,
     }
 }
 
@@ -70,7 +70,7 @@ static MSG_06_09: &CoreSignatures = &CoreSignatures {
     ],
     var: &[],
 };
-static MSG_10_18: &CoreSignatures = &CoreSignatures {
+static MSG_10_19: &CoreSignatures = &CoreSignatures {
     inherit: &[],
     ins: &[
         (Th10, 0, Some(("", None))),
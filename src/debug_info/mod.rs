@@ -93,6 +93,10 @@ pub struct ScriptExportInfo {
 #[serde(rename_all = "kebab-case")]
 pub struct ScriptRegisterInfo {
     pub locals: Vec<Local>,
+    /// Registers that the source code refers to directly, in increasing order.
+    pub explicit_registers: Vec<raw::Register>,
+    /// Registers that the compiler used for temporaries and locals, in increasing order.
+    pub scratch_registers: Vec<raw::Register>,
 }
 
 #[derive(serde::Serialize)]
@@ -196,6 +200,10 @@ impl From<crate::pos::Span> for Span {
     }
 }
 
+impl Span {
+    pub fn as_span(&self) -> crate::pos::Span { self.span }
+}
+
 impl Serialize for Span {
     fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
         match self.span.file_id {
@@ -226,7 +234,12 @@ pub struct Label {
 pub struct Instr {
     /// Byte offset into script for this instruction.
     pub offset: raw::BytePos,
+    /// Size of this instruction in bytes.
+    pub size: raw::BytePos,
     /// Span that might be suitable for a source-level debugger to point at for this instruction.
+    ///
+    /// For instructions that were generated by the compiler (e.g. the jumps in a loop), this will be
+    /// the span of some part of the statement that produced them.
     pub span: Span,
 }
 
@@ -1,15 +1,15 @@
 
 use std::fmt;
-use std::cell::RefCell;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use codespan_reporting as cs;
 use cs::term::termcolor as tc;
 
 use crate::error::ErrorReported;
 use crate::io::nice_display_path;
-use crate::pos::{Files, FileId, HasSpan};
+use crate::pos::{Files, FileId, HasSpan, Span};
 
 type CsDiagnostic = cs::diagnostic::Diagnostic<FileId>;
 type CsLabel = cs::diagnostic::Label<FileId>;
@@ -65,15 +65,96 @@ impl Diagnostic {
         self.imp.notes.insert(0, message);
         self
     }
+
+    /// The span of the first primary label, if there is one.
+    fn primary_span(&self) -> Option<Span> {
+        self.imp.labels.iter()
+            .find(|label| label.style == cs::diagnostic::LabelStyle::Primary)
+            .map(|label| Span::new(label.file_id, label.range.start as u32, label.range.end as u32))
+    }
+
+    /// Convert into the public, structured representation.
+    fn to_collected(&self) -> CollectedDiagnostic {
+        let message = match self.imp.labels.is_empty() {
+            true => format!("{}{}", self.unspanned_prefix, self.imp.message),
+            false => self.imp.message.clone(),
+        };
+        CollectedDiagnostic {
+            severity: match self.imp.severity {
+                cs::diagnostic::Severity::Bug => Severity::Bug,
+                cs::diagnostic::Severity::Error => Severity::Error,
+                cs::diagnostic::Severity::Warning => Severity::Warning,
+                cs::diagnostic::Severity::Note | cs::diagnostic::Severity::Help => Severity::Info,
+            },
+            code: self.imp.code.clone(),
+            message,
+            labels: self.imp.labels.iter().map(|label| CollectedLabel {
+                style: match label.style {
+                    cs::diagnostic::LabelStyle::Primary => LabelStyle::Primary,
+                    cs::diagnostic::LabelStyle::Secondary => LabelStyle::Secondary,
+                },
+                span: Span::new(label.file_id, label.range.start as u32, label.range.end as u32),
+                message: label.message.clone(),
+            }).collect(),
+            notes: self.imp.notes.clone(),
+        }
+    }
+}
+
+// =============================================================================
+
+/// A diagnostic in a structured form suitable for display by external tools.
+///
+/// These can be obtained by enabling [`crate::Builder::collect_diagnostics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedDiagnostic {
+    pub severity: Severity,
+    pub code: Option<String>,
+    /// The main message.  For diagnostics without labels, this includes any prefix added by
+    /// [`Emitter::chain`] (e.g. the name of the file being read).
+    pub message: String,
+    pub labels: Vec<CollectedLabel>,
+    pub notes: Vec<String>,
+}
+
+/// A label on a source span within a [`CollectedDiagnostic`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CollectedLabel {
+    pub style: LabelStyle,
+    /// Use [`Files`] to obtain the file name and source text.
+    pub span: Span,
+    pub message: String,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity { Info, Warning, Error, Bug }
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LabelStyle { Primary, Secondary }
+
 // =============================================================================
 
+/// The default value of [`RootEmitter::set_max_errors`].
+pub const DEFAULT_MAX_ERRORS: usize = 100;
+
 /// Type that decides where diagnostic messages get written, and that stores the metadata necessary to render them.
+///
+/// This can be shared between threads, but diagnostics emitted from multiple threads will be written
+/// in whatever order they arrive.  See [`Self::new_buffer`] for a way to avoid this.
 pub struct RootEmitter {
-    pub files: Rc<Files>,
+    pub files: Arc<Files>,
     config: cs::term::Config,
-    writer: Box<RefCell<dyn WriteError>>,
+    writer: Box<Mutex<dyn WriteError + Send>>,
+    max_errors: Option<usize>,
+    /// Number of errors that have been rendered.  This is shared with emitters made by [`Self::with_writer`],
+    /// so that they count towards the same limit.
+    num_errors_shown: Arc<AtomicUsize>,
+    /// Number of errors that were suppressed due to `max_errors`, and not yet reported in a summary.
+    num_errors_hidden: AtomicUsize,
+    /// Structured copies of all emitted diagnostics, if enabled.
+    collected: Option<Mutex<Vec<CollectedDiagnostic>>>,
+    /// Diagnostics that are held instead of written, for an emitter made by [`Self::new_buffer`].
+    buffered: Option<Mutex<Vec<Diagnostic>>>,
 }
 
 impl fmt::Debug for RootEmitter {
@@ -82,16 +163,25 @@ impl fmt::Debug for RootEmitter {
             .field("files", &self.files)
             .field("config", &self.config)
             .field("writer", &(..))
+            .field("max_errors", &self.max_errors)
+            .field("num_errors_shown", &self.num_errors_shown)
+            .field("num_errors_hidden", &self.num_errors_hidden)
+            .field("collected", &self.collected)
             .finish()
     }
 }
 
 impl RootEmitter {
-    fn from_writer<W: WriteError + 'static>(writer: W) -> Self {
+    fn from_writer<W: WriteError + Send + 'static>(writer: W) -> Self {
         RootEmitter {
-            files: Rc::new(Files::new()),
+            files: Arc::new(Files::new()),
             config: default_term_config(),
-            writer: Box::new(RefCell::new(writer)),
+            writer: Box::new(Mutex::new(writer)),
+            max_errors: Some(DEFAULT_MAX_ERRORS),
+            num_errors_shown: Arc::new(AtomicUsize::new(0)),
+            num_errors_hidden: AtomicUsize::new(0),
+            collected: None,
+            buffered: None,
         }
     }
 
@@ -116,28 +206,138 @@ impl RootEmitter {
         Self::from_writer(CapturingErrorWriter::new())
     }
 
+    /// Create a [`RootEmitter`] with an empty file database, that does not render diagnostics at all.
+    ///
+    /// This is intended to be used together with [`Self::collect_diagnostics`].
+    pub fn new_silent() -> Self {
+        Self::from_writer(dev_null())
+    }
+
+    /// Enable or disable the collection of [`CollectedDiagnostic`]s, which can be retrieved using
+    /// [`Self::take_collected_diagnostics`].  This happens in addition to any rendering of diagnostics.
+    ///
+    /// Collection is not affected by [`Self::set_max_errors`].
+    pub fn collect_diagnostics(&mut self, collect: bool) {
+        self.collected = match collect {
+            true => Some(self.collected.take().unwrap_or_default()),
+            false => None,
+        };
+    }
+
+    /// Take all [`CollectedDiagnostic`]s emitted since the last call, provided that collection was
+    /// enabled using [`Self::collect_diagnostics`].  (otherwise, returns `None`)
+    pub fn take_collected_diagnostics(&self) -> Option<Vec<CollectedDiagnostic>> {
+        self.collected.as_ref().map(|vec| std::mem::take(&mut *vec.lock().unwrap()))
+    }
+
+    /// Set the maximum number of errors that will be rendered.  `None` means no limit.
+    ///
+    /// Once the limit is reached, further errors are still counted (and still produce [`ErrorReported`]),
+    /// but are not written.  The number of suppressed errors is reported by [`Self::emit_summary`].
+    /// Warnings and bugs are never suppressed.
+    pub fn set_max_errors(&mut self, max_errors: Option<usize>) {
+        self.max_errors = max_errors;
+    }
+
     pub fn emit(&self, errors: impl IntoDiagnostics) -> ErrorReported {
         // NOTE: we don't take an iterator because the iterator could call `.emit()` and lead to a runtime borrow conflict.
         for diag in errors.into_diagnostics() {
-            self.writer.borrow_mut().write_error(&diag, &self.config, &self.files);
+            if let Some(buffered) = &self.buffered {
+                buffered.lock().unwrap().push(diag);
+                continue;
+            }
+            if let Some(collected) = &self.collected {
+                collected.lock().unwrap().push(diag.to_collected());
+            }
+            if diag.imp.severity == cs::diagnostic::Severity::Error {
+                if self.max_errors.map_or(false, |max| self.num_errors_shown.load(Ordering::Relaxed) >= max) {
+                    self.num_errors_hidden.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                self.num_errors_shown.fetch_add(1, Ordering::Relaxed);
+            }
+            self.writer.lock().unwrap().write_error(&diag, &self.config, &self.files);
         }
         ErrorReported
     }
 
+    /// Write a line summarizing the number of errors that were suppressed due to [`Self::set_max_errors`],
+    /// if there were any.
+    ///
+    /// This is automatically called when the [`RootEmitter`] is dropped, but may be called earlier, e.g.
+    /// before the program exits, or before reading captured diagnostics.  Only errors suppressed since the
+    /// last summary are counted.
+    pub fn emit_summary(&self) {
+        let num_hidden = self.num_errors_hidden.swap(0, Ordering::Relaxed);
+        if num_hidden > 0 {
+            let plural = if num_hidden == 1 { "" } else { "s" };
+            let diag = info!("{num_hidden} additional error{plural} not shown");
+            self.writer.lock().unwrap().write_error(&diag, &self.config, &self.files);
+        }
+    }
+
     /// Obtain captured diagnostics written to stderr, provided that this [`RootEmitter`]
     /// was constructed using [`Self::new_captured`]. (otherwise, returns `None`)
     pub fn get_captured_diagnostics(&self) -> Option<String> {
-        self.writer.borrow().get_captured_output()
+        self.writer.lock().unwrap().get_captured_output()
     }
 
     /// Obtain an emitter for a new writer, with shared access to the same [file database][`Files`].
-    pub fn with_writer<W: WriteError + 'static>(&self, writer: W) -> Self {
+    ///
+    /// Errors rendered by either emitter count towards the limit set by [`Self::set_max_errors`] for both.
+    /// Each one reports the errors that it suppressed in its own summary.
+    pub fn with_writer<W: WriteError + Send + 'static>(&self, writer: W) -> Self {
         RootEmitter {
-            files: Rc::clone(&self.files),
+            files: Arc::clone(&self.files),
             config: self.config.clone(),
-            writer: Box::new(RefCell::new(writer)),
+            writer: Box::new(Mutex::new(writer)),
+            max_errors: self.max_errors,
+            num_errors_shown: Arc::clone(&self.num_errors_shown),
+            num_errors_hidden: AtomicUsize::new(0),
+            collected: None,
+            buffered: None,
         }
     }
+
+    /// Obtain an emitter that holds on to everything emitted to it, so that it can later be emitted
+    /// to this one by [`Self::emit_buffered`].
+    ///
+    /// Work that is split across threads uses one of these for each task, so that the diagnostics
+    /// can be written in a deterministic order.
+    pub fn new_buffer(&self) -> Self {
+        let mut buffer = self.with_writer(dev_null());
+        buffer.max_errors = None;
+        buffer.buffered = Some(Default::default());
+        buffer
+    }
+
+    /// Emit all diagnostics held by emitters created using [`Self::new_buffer`], sorted by the start
+    /// of their primary spans.  (diagnostics without spans keep their place relative to the one before them)
+    ///
+    /// Only the given buffers are sorted together.  Anything already emitted to this emitter has been
+    /// written, so these all come after it, wherever their spans are.
+    pub fn emit_buffered(&self, buffers: impl IntoIterator<Item=RootEmitter>) {
+        let mut diags = vec![];
+        let mut last_key = None;
+        for buffer in buffers {
+            let buffered = buffer.buffered.as_ref().expect("not a buffer").lock().unwrap().drain(..).collect::<Vec<_>>();
+            for diag in buffered {
+                let key = diag.primary_span().map(|span| (span.file_id, span.start)).or(last_key);
+                last_key = key;
+                diags.push((key, diag));
+            }
+        }
+        diags.sort_by_key(|&(key, _)| key);  // stable
+        for (_, diag) in diags {
+            self.emit(diag).ignore();
+        }
+    }
+}
+
+impl Drop for RootEmitter {
+    fn drop(&mut self) {
+        self.emit_summary();
+    }
 }
 
 pub trait WriteError {
@@ -280,6 +480,12 @@ pub trait Emitter {
         Label: Fn(&mut fmt::Formatter) -> fmt::Result + 'a,
     { self.get_chained(DisplayFn { func: label }) }
 
+    /// Get an emitter with the same prefix as this one for diagnostics that lack spans, but which
+    /// emits to a different root emitter.  (e.g. one created by [`RootEmitter::new_buffer`])
+    fn with_root<'a>(&self, root: &'a RootEmitter) -> Rerooted<'a> {
+        Rerooted { root, prefix: self._unspanned_prefix() }
+    }
+
     /// Emit any number of diagnostic messages.
     fn emit(&self, diagnostics: impl IntoDiagnostics) -> ErrorReported
     where
@@ -321,6 +527,18 @@ impl Emitter for DummyEmitter {
     fn emit(&self, _: impl IntoDiagnostics) -> ErrorReported { ErrorReported }
 }
 
+/// An [`Emitter`] produced by [`Emitter::with_root`].
+#[derive(Debug, Clone)]
+pub struct Rerooted<'a> {
+    root: &'a RootEmitter,
+    prefix: String,
+}
+
+impl Emitter for Rerooted<'_> {
+    fn _root_emitter(&self) -> &RootEmitter { self.root }
+    fn _unspanned_prefix(&self) -> String { self.prefix.clone() }
+}
+
 #[derive(Clone)]
 pub struct Node<Parent, Label> {
     parent: Parent,
@@ -404,3 +622,98 @@ fn test_unspanned() {
     assert!(stderr.contains("a.txt: thing 3: while eating a sub: blah 20"), "{}", stderr);
     assert_snapshot!(stderr);
 }
+
+#[test]
+fn test_max_errors() {
+    let mut root_emitter = RootEmitter::new_captured();
+    root_emitter.set_max_errors(Some(3));
+
+    for i in 0..5 {
+        root_emitter.emit(error!("error number {}", i)).ignore();
+    }
+    root_emitter.emit(warning!("a warning")).ignore();
+    root_emitter.emit_summary();
+
+    let stderr = root_emitter.get_captured_diagnostics().unwrap();
+    assert_eq!(stderr.matches("error number").count(), 3, "{}", stderr);
+    assert!(!stderr.contains("error number 3"), "{}", stderr);
+    assert!(stderr.contains("a warning"), "{}", stderr);
+    assert!(stderr.contains("2 additional errors not shown"), "{}", stderr);
+
+    // the summary is not repeated
+    root_emitter.emit_summary();
+    assert_eq!(root_emitter.get_captured_diagnostics().unwrap().matches("not shown").count(), 1);
+}
+
+#[test]
+fn test_max_errors_shared_with_writer() {
+    let mut root_emitter = RootEmitter::new_captured();
+    root_emitter.set_max_errors(Some(3));
+    let other_emitter = root_emitter.with_writer(CapturingErrorWriter::new());
+
+    for i in 0..2 {
+        root_emitter.emit(error!("error number {}", i)).ignore();
+    }
+    for i in 2..4 {
+        other_emitter.emit(error!("error number {}", i)).ignore();
+    }
+    root_emitter.emit(error!("error number 4")).ignore();
+    root_emitter.emit_summary();
+    other_emitter.emit_summary();
+
+    let stderr = root_emitter.get_captured_diagnostics().unwrap();
+    assert_eq!(stderr.matches("error number").count(), 2, "{}", stderr);
+    assert!(stderr.contains("1 additional error not shown"), "{}", stderr);
+    let other_stderr = other_emitter.get_captured_diagnostics().unwrap();
+    assert!(other_stderr.contains("error number 2"), "{}", other_stderr);
+    assert!(!other_stderr.contains("error number 3"), "{}", other_stderr);
+    assert!(other_stderr.contains("1 additional error not shown"), "{}", other_stderr);
+}
+
+#[test]
+fn test_collect_diagnostics() {
+    let mut root_emitter = RootEmitter::new_silent();
+    root_emitter.collect_diagnostics(true);
+    root_emitter.set_max_errors(Some(1));
+
+    let (file_id, _) = root_emitter.files.add("<input>", b"abcdef").unwrap();
+    let span = Span::new(file_id, 1, 3);
+
+    root_emitter.while_reading("a.txt").emit(error!("unspanned")).ignore();
+    root_emitter.emit(warning!(
+        message("spanned"),
+        primary(span, "here"),
+        note("a note"),
+    )).ignore();
+    root_emitter.emit(error!("over the limit")).ignore();
+
+    let collected = root_emitter.take_collected_diagnostics().unwrap();
+    assert_eq!(collected.len(), 3);
+    assert_eq!(collected[0].severity, Severity::Error);
+    assert_eq!(collected[0].message, "a.txt: unspanned");
+    assert_eq!(collected[1], CollectedDiagnostic {
+        severity: Severity::Warning,
+        code: None,
+        message: "spanned".to_string(),
+        labels: vec![CollectedLabel { style: LabelStyle::Primary, span, message: "here".to_string() }],
+        notes: vec!["a note".to_string()],
+    });
+    assert_eq!(collected[2].message, "over the limit");
+
+    assert_eq!(root_emitter.take_collected_diagnostics().unwrap(), vec![]);
+}
+
+#[test]
+fn test_max_errors_disabled() {
+    let mut root_emitter = RootEmitter::new_captured();
+    root_emitter.set_max_errors(None);
+
+    for i in 0..(DEFAULT_MAX_ERRORS + 5) {
+        root_emitter.emit(error!("error number {}", i)).ignore();
+    }
+    root_emitter.emit_summary();
+
+    let stderr = root_emitter.get_captured_diagnostics().unwrap();
+    assert_eq!(stderr.matches("error number").count(), DEFAULT_MAX_ERRORS + 5);
+    assert!(!stderr.contains("not shown"), "{}", stderr);
+}
@@ -0,0 +1,17 @@
+//! A fast, non-cryptographic hasher for maps keyed by small IDs.
+//!
+//! The default SipHash in `std` is designed to resist HashDoS attacks, which are not a concern for
+//! a compiler, and it shows up prominently in profiles due to the many maps keyed by [`NodeId`]s
+//! and [`DefId`]s.  `FxHasher` from `rustc-hash` works well for integer keys.
+//!
+//! [`NodeId`]: crate::resolve::NodeId
+//! [`DefId`]: crate::resolve::DefId
+
+use std::hash::BuildHasherDefault;
+
+use indexmap::IndexMap;
+
+pub use rustc_hash::{FxHashMap as FastHashMap, FxHashSet as FastHashSet};
+
+/// An [`IndexMap`] using `FxHasher`.  Construct it with `Default::default()`.
+pub type FastIndexMap<K, V> = IndexMap<K, V, BuildHasherDefault<rustc_hash::FxHasher>>;
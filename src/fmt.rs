@@ -1,8 +1,12 @@
 use thiserror::Error;
 use std::io::{self, Write};
+use std::sync::Arc;
 use crate::ast::{self, meta, Meta};
+use crate::game::LanguageKey;
 use crate::ident::{Ident, ResIdent};
-use crate::pos::Sp;
+use crate::pos::{Sp, Span};
+use crate::raw;
+use crate::resolve::RegId;
 
 /// Trait for pretty-printing truth AST nodes.
 ///
@@ -56,19 +60,42 @@ impl From<io::Error> for Error {
 
 //==============================================================================
 
-#[derive(Debug, Clone)]
+/// Supplies names for registers and instructions written in their raw form.
+///
+/// The formatter has no access to a [`CompilerContext`][crate::context::CompilerContext], so by default it
+/// writes `REG[10012]` and `ins_302` exactly as they appear in the AST.  When an oracle is attached with
+/// [`Config::naming_oracle`], a register or instruction whose language is known is written under the name
+/// given by the oracle instead, if there is one.  This is meant for diagnostics;  see
+/// [`Defs::alias_names`][crate::context::Defs::alias_names] for an oracle built from mapfiles.
+pub trait NamingOracle: Send + Sync {
+    fn name_for_reg(&self, language: LanguageKey, reg: RegId) -> Option<Ident>;
+    fn name_for_opcode(&self, language: LanguageKey, opcode: raw::Opcode) -> Option<Ident>;
+}
+
+#[derive(Clone)]
 pub struct Config {
     target_width: usize,
+    naming_oracle: Option<Arc<dyn NamingOracle>>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
             target_width: 99,
+            naming_oracle: None,
         }
     }
 }
 
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("target_width", &self.target_width)
+            .field("has_naming_oracle", &self.naming_oracle.is_some())
+            .finish()
+    }
+}
+
 impl Config {
     pub fn new() -> Self {
         Default::default()
@@ -85,6 +112,11 @@ impl Config {
         //        without triggering backtracking on the item.
         self.target_width = width - 1; self
     }
+
+    /// Write raw registers and instructions using the names supplied by an oracle, where it has them.
+    pub fn naming_oracle(mut self, oracle: impl NamingOracle + 'static) -> Self {
+        self.naming_oracle = Some(Arc::new(oracle)); self
+    }
 }
 
 //==============================================================================
@@ -112,6 +144,11 @@ mod formatter {
         inline_depth: u32,
         disable_parens: bool,
         suppress_blank_line: bool,
+        /// While measuring something with [`Self::measure_inline`], the length that the current line would have.
+        measured_len: Option<usize>,
+        /// Total length of everything measured by [`Self::measure_inline`].
+        #[cfg(test)]
+        measured_bytes: usize,
         /// Contains state that is not directly managed by Formatter itself, but rather
         /// by various [`Format`] impls.
         pub(super) state: State,
@@ -142,6 +179,9 @@ mod formatter {
                 pending_data: false,
                 disable_parens: false,
                 suppress_blank_line: false,
+                measured_len: None,
+                #[cfg(test)]
+                measured_bytes: 0,
                 // The initial level here is used when writing a Stmt as toplevel.
                 // When parsing items, we mostly use a second level that gets pushed/popped with functions.
                 line_buffer: vec![],
@@ -159,6 +199,12 @@ mod formatter {
             Ok(self.writer.take().unwrap())
         }
 
+        /// Total length of everything that was measured to decide between inline and block formatting.
+        #[cfg(test)]
+        pub(super) fn measured_bytes(&self) -> usize {
+            self.measured_bytes
+        }
+
         fn _flush_incomplete_line(&mut self) -> Result {
             if self.pending_data {
                 self.writer.as_mut().unwrap().write_all(&self.line_buffer)?;
@@ -188,6 +234,7 @@ mod formatter {
                 self.line_buffer.clear(); // strip indent
                 self.fmt(label)?;
                 assert!(self.is_label, "Detected line break in label. This is a bug!");
+                self.fmt_trailing_comments()?;
                 self.next_line()?;
             }
             Ok(())
@@ -204,7 +251,7 @@ mod formatter {
         ) -> Result {
             self.try_inline(|me| {
                 // Reasons the inline formatting may fail:
-                // * A line length check may fail here.
+                // * The line may grow too long during any write.
                 // * One of the list items may unconditionally produce a newline
                 me.fmt(open)?;
                 let mut first = true;
@@ -212,10 +259,8 @@ mod formatter {
                     if !first { me.fmt(", ")?; }
                     first = false;
                     me.fmt(x)?;
-                    me.backtrack_inline_if_long()?;
                 }
-                me.fmt(close)?;
-                me.backtrack_inline_if_long()
+                me.fmt(close)
             }, |me| {
                 // Block formatting
                 me.fmt(open)?;
@@ -309,16 +354,26 @@ mod formatter {
         pub(super) fn append_to_line(&mut self, bytes: &[u8]) -> Result {
             // Catch accidental use of "\n" in output strings where next_line() should be used.
             assert!(!bytes.contains(&b'\n'), "Tried to append newline to line. This is a bug!");
-            self.line_buffer.extend_from_slice(bytes);
+            match &mut self.measured_len {
+                Some(len) => *len += bytes.len(),
+                None => self.line_buffer.extend_from_slice(bytes),
+            }
             self.write_occurred();
-            Ok(())
+            self.backtrack_inline_if_long()
         }
 
         /// Append to the current (not yet written) line using [`std::fmt::Display`].
         pub(super) fn append_display_to_line(&mut self, x: impl std::fmt::Display) -> Result {
-            write!(&mut self.line_buffer, "{}", x)?;
+            match &mut self.measured_len {
+                Some(len) => {
+                    let mut counter = LenCounter(0);
+                    std::fmt::Write::write_fmt(&mut counter, format_args!("{}", x)).expect("counting can't fail");
+                    *len += counter.0;
+                },
+                None => write!(&mut self.line_buffer, "{}", x)?,
+            }
             self.write_occurred();
-            Ok(())
+            self.backtrack_inline_if_long()
         }
 
         fn write_occurred(&mut self) {
@@ -349,35 +404,58 @@ mod formatter {
 
         /// If we're in inline mode and the line is too long, backtrack to the
         /// outermost [`Formatter::try_inline`].
+        ///
+        /// This is checked after every write rather than only between list items, so that measuring
+        /// something that doesn't fit stops after about one line's worth of text, instead of going
+        /// through an entire (possibly huge) subtree first.
         fn backtrack_inline_if_long(&mut self) -> Result {
-            if self.inline_depth > 0 && self.line_buffer.len() > self.config.target_width {
+            let line_len = self.measured_len.unwrap_or(self.line_buffer.len());
+            if self.inline_depth > 0 && line_len > self.config.target_width {
                 return Err(Error(ErrorKind::LineBreakRequired));
             }
             Ok(())
         }
 
         /// Attempt to write something inline, else write block style.
+        ///
+        /// The outermost call first measures the inline form without writing anything, so that
+        /// nothing has to be discarded when it doesn't fit.
         fn try_inline<B>(
             &mut self,
             mut inline_cb: impl FnMut(&mut Self) -> Result<B>,
             mut block_cb: impl FnMut(&mut Self) -> Result<B>,
         ) -> Result<B> {
-            let backtrack_pos = match self.inline_depth {
-                0 => Some(self.line_buffer.len()),
-                _ => None, // don't backtrack if nested in another inline_cb
-            };
+            // if nested in another inline_cb, that one has already decided for us
+            let fits = self.inline_depth > 0 || self.measure_inline(&mut inline_cb)?;
+            match fits {
+                true => {
+                    self.inline_depth += 1;
+                    let result = inline_cb(self);
+                    self.inline_depth -= 1;
+                    result
+                },
+                false => block_cb(self),
+            }
+        }
+
+        /// Check whether something fits on the current line in inline mode, by formatting it without writing it.
+        fn measure_inline<B>(&mut self, inline_cb: &mut impl FnMut(&mut Self) -> Result<B>) -> Result<bool> {
+            // comments written while measuring must still be there to be written for real
+            let saved_flags = (self.pending_data, self.disable_parens, self.state.next_comment);
+            self.measured_len = Some(self.line_buffer.len());
             self.inline_depth += 1;
             let result = inline_cb(self);
             self.inline_depth -= 1;
-            match (result, backtrack_pos) {
-                // If we fail to write inline and this is the outermost `try_inline`,
-                // backtrack and try writing not inline.
-                (Err(Error(ErrorKind::LineBreakRequired)), Some(backtrack_pos)) => {
-                    assert_eq!(self.inline_depth, 0, "Block cb in inline mode. This is a bug!");
-                    self.line_buffer.truncate(backtrack_pos);
-                    block_cb(self)
-                },
-                (result, _) => result,
+            #[cfg(test)] {
+                self.measured_bytes += self.measured_len.unwrap() - self.line_buffer.len();
+            }
+            self.measured_len = None;
+            (self.pending_data, self.disable_parens, self.state.next_comment) = saved_flags;
+
+            match result {
+                Ok(_) => Ok(true),
+                Err(Error(ErrorKind::LineBreakRequired)) => Ok(false),
+                Err(e) => Err(e),
             }
         }
 
@@ -393,6 +471,125 @@ mod formatter {
         }
     }
 
+    /// Counts the length of text written with [`std::fmt::Write`], without storing it.
+    struct LenCounter(usize);
+
+    impl std::fmt::Write for LenCounter {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    /// Methods for reproducing the comments of a parsed [`ast::ScriptFile`].
+    ///
+    /// Comments are tracked by position.  Before writing a statement or item, any comments that
+    /// appear before it in the source are written on their own lines.  Comments that trail after
+    /// a statement or item on the same line are written at the end of its last line.  Comments
+    /// in the middle of one, like inside of an expression, are written before the next thing
+    /// with a span.
+    impl<W: io::Write> Formatter<W> {
+        /// Write all comments that appear before a span, on their own lines.
+        pub(super) fn fmt_comments_before(&mut self, span: Span) -> Result {
+            while let Some(comment) = self.state.comments.get(self.state.next_comment) {
+                if comment.span.file_id != span.file_id || comment.span.start >= span.start {
+                    break;
+                }
+                self.state.next_comment += 1;
+                if self.state.comment_written[self.state.next_comment - 1] {
+                    continue;
+                }
+                let text = comment.text.clone();
+                if self.pending_data {
+                    self.next_line()?;
+                }
+                self._write_comment_text(&text)?;
+                self.next_line()?;
+            }
+            Ok(())
+        }
+
+        /// Write all comments that appear before a span, in the middle of a statement or item.
+        ///
+        /// Block comments are written inline.  Line comments end the line, so anything that was being
+        /// written inline falls back to block formatting.
+        pub(super) fn fmt_comments_within(&mut self, span: Span) -> Result {
+            while let Some(comment) = self.state.comments.get(self.state.next_comment) {
+                if comment.span.file_id != span.file_id || comment.span.start >= span.start {
+                    break;
+                }
+                self.state.next_comment += 1;
+                if self.state.comment_written[self.state.next_comment - 1] {
+                    continue;
+                }
+                let text = comment.text.clone();
+                self._write_comment_text(&text)?;
+                match text.starts_with("//") {
+                    true => self.next_line()?,
+                    false => self.append_to_line(b" ")?,
+                }
+            }
+            Ok(())
+        }
+
+        /// Write all comments that have not been written yet, on their own lines.
+        pub(super) fn fmt_remaining_comments(&mut self) -> Result {
+            let span = self.state.comments.last().map(|comment| comment.span);
+            if let Some(span) = span {
+                self.fmt_comments_before(Span { start: span.end, ..span })?;
+            }
+            Ok(())
+        }
+
+        /// Set aside the comments that trail after something with the given span, so that they
+        /// can be written by [`Self::fmt_trailing_comments`] at the end of its last line.
+        ///
+        /// Returns the comments that were previously set aside, which should be restored with
+        /// [`Self::restore_trailing_comments`] once the thing is written.
+        pub(super) fn take_trailing_comments(&mut self, span: Span) -> Vec<String> {
+            let state = &mut self.state;
+            let mut trailing = vec![];
+            let first_after = state.comments.partition_point(|comment| comment.span.start < span.end);
+            for (index, comment) in state.comments.iter().enumerate().skip(first_after) {
+                if comment.span.file_id != span.file_id || comment.trailing_after != Some(span.end) {
+                    break;
+                }
+                if !state.comment_written[index] {
+                    state.comment_written[index] = true;
+                    trailing.push(comment.text.clone());
+                }
+            }
+            std::mem::replace(&mut state.trailing_comments, trailing)
+        }
+
+        pub(super) fn restore_trailing_comments(&mut self, outer: Vec<String>) {
+            self.state.trailing_comments = outer;
+        }
+
+        /// Write comments set aside by [`Self::take_trailing_comments`].
+        pub(super) fn fmt_trailing_comments(&mut self) -> Result {
+            for text in std::mem::take(&mut self.state.trailing_comments) {
+                if self.pending_data {
+                    self.append_to_line(b" ")?;
+                }
+                self._write_comment_text(&text)?;
+            }
+            Ok(())
+        }
+
+        fn _write_comment_text(&mut self, text: &str) -> Result {
+            let mut lines = text.lines();
+            self.append_to_line(lines.next().unwrap_or("").as_bytes())?;
+            // continuation lines of block comments are written exactly as they were
+            for line in lines {
+                self.next_line()?;
+                self.line_buffer.clear();
+                self.append_to_line(line.trim_end().as_bytes())?;
+            }
+            Ok(())
+        }
+    }
+
     /// Convenience wrapper for [`Formatter::suppress_optional_parens`] so that it can be used
     /// without splitting up a [`Formatter::fmt`] call.
     pub struct SuppressParens<T>(pub T);
@@ -417,6 +614,21 @@ mod formatter {
     }
 }
 
+/// Writes a statement or item together with its comments from the source.
+struct CommentAnchor<'a, T>(&'a Sp<T>);
+
+impl<T: Format> Format for CommentAnchor<'_, T> {
+    fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
+        let CommentAnchor(node) = self;
+        out.fmt_comments_before(node.span)?;
+        let outer = out.take_trailing_comments(node.span);
+        out.fmt(&node.value)?;
+        out.fmt_trailing_comments()?;
+        out.restore_trailing_comments(outer);
+        Ok(())
+    }
+}
+
 enum Either<A, B> { This(A), That(B) }
 
 impl<A: Format, B: Format> Format for Either<A, B> {
@@ -468,6 +680,7 @@ impl<T: Format + ?Sized> Format for Box<T> {
 }
 impl<T: Format + ?Sized> Format for Sp<T> {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
+        out.fmt_comments_within(self.span)?;
         Format::fmt(&**self, out)
     }
 }
@@ -488,13 +701,33 @@ struct State {
 
     /// Used to control grouping of `interrupt[n]:` lines.
     prev_line_was_interrupt: bool,
+
+    /// Comments of the script file being written, sorted by position.
+    comments: Vec<Sp<ast::Comment>>,
+    /// Tracks which of [`Self::comments`] have been written out of order (as trailing comments).
+    comment_written: Vec<bool>,
+    /// Index of the first comment in [`Self::comments`] that has not yet been considered for
+    /// writing on its own line.
+    next_comment: usize,
+    /// Trailing comments for the statement or item currently being written.
+    trailing_comments: Vec<String>,
 }
 
 impl State {
     fn new() -> Self { State {
         time_stack: vec![0],
         prev_line_was_interrupt: false,
+        comments: vec![],
+        comment_written: vec![],
+        next_comment: 0,
+        trailing_comments: vec![],
     }}
+
+    fn set_comments(&mut self, comments: &[Sp<ast::Comment>]) {
+        self.comments = comments.to_vec();
+        self.comment_written = vec![false; comments.len()];
+        self.next_comment = 0;
+    }
 }
 
 //==============================================================================
@@ -530,26 +763,46 @@ impl_tuple_format!(a:A, b:B, c:C, d:D, e:E, f:F, g:G, h:H);
 
 impl Format for ast::ScriptFile {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
-        let ast::ScriptFile { items, mapfiles, image_sources } = self;
+        let ast::ScriptFile { items, mapfiles, image_sources, reg_pragmas, comments } = self;
+        out.state.set_comments(comments);
 
         for file in mapfiles {
+            out.fmt_comments_before(file.span)?;
             out.fmt(("#pragma mapfile ", file))?;
             out.next_line()?;
         }
         for file in image_sources {
+            out.fmt_comments_before(file.span)?;
             out.fmt(("#pragma image_source ", file))?;
             out.next_line()?;
         }
+        for pragma in reg_pragmas {
+            out.fmt_comments_before(pragma.span)?;
+            out.fmt(pragma)?;
+            out.next_line()?;
+        }
 
-        if !(mapfiles.is_empty() && image_sources.is_empty()) {
+        if !(mapfiles.is_empty() && image_sources.is_empty() && reg_pragmas.is_empty()) {
             out.next_line()?;
         }
 
-        out.fmt_separated(items, |out| {
+        out.fmt_separated(items.iter().map(CommentAnchor), |out| {
             // all items end with a newline, so this creates two blank lines to separate them
             out.next_line()?;
             out.next_line()
-        })
+        })?;
+        out.fmt_remaining_comments()
+    }
+}
+
+impl Format for ast::RegPragma {
+    fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
+        let ast::RegPragma { language, ty_sigil, ident, reg } = self;
+        out.fmt(("#pragma reg ", language, " "))?;
+        if let Some(ty_sigil) = ty_sigil {
+            out.fmt(ty_sigil)?;
+        }
+        out.fmt((ident, " = ", reg, ";"))
     }
 }
 
@@ -574,14 +827,28 @@ impl Format for ast::Item {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result<()> {
         match self {
             ast::Item::Func(func) => out.fmt(func),
-            ast::Item::AnmScript { keyword: _, number, ident, code } => {
+            ast::Item::AnmScript { keyword: _, number, ident, meta, code } => {
                 out.fmt("script ")?;
                 if let Some(number) = number {
                     out.fmt((number, " "))?;
                 }
+                out.fmt((ident, " "))?;
+                if let Some(fields) = meta {
+                    out.fmt(("meta ", fields, " "))?;
+                }
                 out.state.time_stack.push(0);
-                out.fmt((ident, " ", code))?;
+                out.fmt(code)?;
                 out.state.time_stack.pop();
+                out.fmt_trailing_comments()?;
+                out.next_line()
+            },
+            ast::Item::AnmScriptAlias { keyword: _, number, ident, target } => {
+                out.fmt("script ")?;
+                if let Some(number) = number {
+                    out.fmt((number, " "))?;
+                }
+                out.fmt((ident, " = ", target, ";"))?;
+                out.fmt_trailing_comments()?;
                 out.next_line()
             },
             ast::Item::Timeline { keyword: _, number, ident, code } => {
@@ -595,10 +862,12 @@ impl Format for ast::Item {
                 out.state.time_stack.push(0);
                 out.fmt(code)?;
                 out.state.time_stack.pop();
+                out.fmt_trailing_comments()?;
                 out.next_line()
             },
             ast::Item::Meta { keyword, fields } => {
                 out.fmt((keyword, " ", fields))?;
+                out.fmt_trailing_comments()?;
                 out.next_line()
             },
             ast::Item::ConstVar { ty_keyword, vars } => {
@@ -629,6 +898,7 @@ impl Format for ast::ItemFunc {
             Some(code) => out.fmt((" ", code))?,
         }
         out.state.time_stack.pop();
+        out.fmt_trailing_comments()?;
         out.next_line()
     }
 }
@@ -661,8 +931,14 @@ impl Format for ast::Stmt {
 
 impl Format for ast::DiffLabel {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
-        let ast::DiffLabel { string, mask: _ } = self;
-        out.fmt(("{", string, "}", ":"))
+        let ast::DiffLabel { expr, mask } = self;
+        match expr {
+            Some(expr) => out.fmt(("{", expr, "}", ":")),
+            None => {
+                let mask = mask.expect("numeric difficulty label without a mask (bug)");
+                out.fmt(("{", format!("{:#04X}", mask.mask()), "}", ":"))
+            },
+        }
     }
 }
 
@@ -701,11 +977,14 @@ impl Format for ast::StmtKind {
                 out.fmt(("while (", SuppressParens(cond), ") ", block))
             },
 
-            ast::StmtKind::Times { clobber, count, block, keyword: _, loop_id: _ } => {
+            ast::StmtKind::Times { clobber, count, block, inline_keyword, keyword: _, loop_id: _ } => {
                 out.fmt("times(")?;
                 if let Some(clobber) = clobber {
                     out.fmt((clobber, " = "))?;
                 }
+                if inline_keyword.is_some() {
+                    out.fmt("inline ")?;
+                }
                 out.fmt((SuppressParens(count), ") ", block))
             },
 
@@ -740,10 +1019,10 @@ impl Format for ast::StmtKind {
                 out.fmt(";")
             },
 
-            ast::StmtKind::CallSub { at_symbol, async_, func, args } => {
+            ast::StmtKind::CallSub { at_symbol, async_, call } => {
                 out.fmt(if *at_symbol { "@" } else { "" })?;
-                out.fmt(func)?;
-                out.fmt_comma_separated("(", ")", args)?;
+                out.fmt(&call.name)?;
+                out.fmt_comma_separated("(", ")", &call.args)?;
                 if let Some(async_) = async_ {
                     out.fmt((" ", async_))?;
                 }
@@ -841,8 +1120,8 @@ impl Format for ast::CondBlock {
 impl Format for ast::CallAsyncKind {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
         match *self {
-            ast::CallAsyncKind::CallAsync => out.fmt("async"),
-            ast::CallAsyncKind::CallAsyncId(ref e) => out.fmt(("async ", e)),
+            ast::CallAsyncKind::CallAsync { keyword: _ } => out.fmt("async"),
+            ast::CallAsyncKind::CallAsyncId { keyword: _, ref id } => out.fmt(("async ", id)),
         }
     }
 }
@@ -854,7 +1133,7 @@ impl Format for ast::Block {
         out.next_line()?;
         out.indent()?;
         for stmt in statements {
-            out.fmt(stmt)?;
+            out.fmt(CommentAnchor(stmt))?;
             out.next_line()?;
         }
         out.dedent()?;
@@ -913,7 +1192,8 @@ impl Format for ast::Expr {
             ast::Expr::LitInt { value, radix: ast::IntRadix::Bin } => out.fmt(format_args!("{:#b}", value)),
             ast::Expr::LitFloat { value } => out.fmt(value),
             ast::Expr::LitString(x) => out.fmt(x),
-            ast::Expr::LabelProperty { label, keyword } => out.fmt((keyword, "(", label, ")")),
+            ast::Expr::LabelProperty { script: None, label, keyword } => out.fmt((keyword, "(", label, ")")),
+            ast::Expr::LabelProperty { script: Some(script), label, keyword } => out.fmt((keyword, "(", script, ".", label, ")")),
             ast::Expr::Var(x) => out.fmt(x),
         }
     }
@@ -921,6 +1201,12 @@ impl Format for ast::Expr {
 
 impl Format for ast::CallableName {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
+        if let ast::CallableName::Ins { opcode, language: Some(language), .. } = *self {
+            let name = out.config.naming_oracle.as_ref().and_then(|oracle| oracle.name_for_opcode(language, opcode));
+            if let Some(name) = name {
+                return out.fmt(&name);
+            }
+        }
         out.append_display_to_line(self)
     }
 }
@@ -943,9 +1229,16 @@ impl Format for ast::Var {
 
 impl Format for ast::VarName {
     fn fmt<W: Write>(&self, out: &mut Formatter<W>) -> Result {
+        if let ast::VarName::Reg { reg, language: Some(language), .. } = *self {
+            let name = out.config.naming_oracle.as_ref().and_then(|oracle| oracle.name_for_reg(language, reg));
+            if let Some(name) = name {
+                return out.fmt(&name);
+            }
+        }
         match self {
             ast::VarName::Normal { ident, language_if_reg: _ } => out.fmt(ident),
-            ast::VarName::Reg { reg, language: _ } => out.fmt(("REG[", reg.0, "]")),
+            ast::VarName::Reg { reg, language: _, language_prefix: None } => out.fmt(("REG[", reg.0, "]")),
+            ast::VarName::Reg { reg, language: _, language_prefix: Some(prefix) } => out.fmt((prefix, ".REG[", reg.0, "]")),
         }
     }
 }
@@ -1112,6 +1405,64 @@ mod tests {
         }}
     }
 
+    #[test]
+    fn naming_oracle() {
+        struct Oracle;
+        impl NamingOracle for Oracle {
+            fn name_for_reg(&self, language: LanguageKey, reg: RegId) -> Option<Ident> {
+                ((language, reg) == (LanguageKey::Ecl, RegId(10012))).then(|| Ident::new_user("COUNTER").unwrap())
+            }
+            fn name_for_opcode(&self, language: LanguageKey, opcode: raw::Opcode) -> Option<Ident> {
+                ((language, opcode) == (LanguageKey::Ecl, 302)).then(|| Ident::new_user("bossSet").unwrap())
+            }
+        }
+
+        let reg = |reg, language| ast::VarName::Reg { reg: RegId(reg), language, language_prefix: None };
+        let ins = |opcode, language| ast::CallableName::Ins { opcode, language, language_prefix: None };
+        let config = || Config::new().naming_oracle(Oracle);
+
+        assert_eq!(stringify_with(&reg(10012, Some(LanguageKey::Ecl)), config()), "COUNTER");
+        assert_eq!(stringify_with(&ins(302, Some(LanguageKey::Ecl)), config()), "bossSet");
+        // no name, or language unknown
+        assert_eq!(stringify_with(&reg(10013, Some(LanguageKey::Ecl)), config()), "REG[10013]");
+        assert_eq!(stringify_with(&reg(10012, None), config()), "REG[10012]");
+        assert_eq!(stringify_with(&ins(302, Some(LanguageKey::Anm)), config()), "ins_302");
+        // no oracle
+        assert_eq!(stringify(&reg(10012, Some(LanguageKey::Ecl))), "REG[10012]");
+    }
+
+    #[test]
+    fn naming_oracle_from_mapfile() {
+        let mut scope = crate::Builder::new().build();
+        let mut truth = scope.truth();
+        let mapfile = "!anmmap\n!gvar_names\n10000 COUNTER\n!ins_names\n1 wait\n400 custom\n";
+        truth.apply_mapfile_str(mapfile, crate::Game::Th12).unwrap();
+        let names = truth.ctx().defs.alias_names();
+
+        let reg = |reg, language| ast::VarName::Reg { reg: RegId(reg), language: Some(language), language_prefix: None };
+        let ins = |opcode, language| ast::CallableName::Ins { opcode, language: Some(language), language_prefix: None };
+        let config = || Config::new().naming_oracle(names.clone());
+
+        assert_eq!(stringify_with(&reg(10000, LanguageKey::Anm), config()), "COUNTER");
+        assert_eq!(stringify_with(&ins(1, LanguageKey::Anm), config()), "wait");
+        assert_eq!(stringify_with(&ins(400, LanguageKey::Anm), config()), "custom");
+        // names are per language
+        assert_eq!(stringify_with(&reg(10000, LanguageKey::Ecl), config()), "REG[10000]");
+        assert_eq!(stringify_with(&ins(400, LanguageKey::Ecl), config()), "ins_400");
+    }
+
+    #[test]
+    fn meta_int_radix() {
+        // user-written radices should survive a reformat
+        let f = reformat::<Meta>;
+        assert_snapshot!(f(40, r#"{
+            path: "a.png",
+            rt_width: 256, rt_height: 0x100,
+            rt_format: 0x9, colorkey: 0xFF00FF00,
+            unknown_0x2c: 0b101, unknown_0x3c: -0x10,
+        }"#).trim());
+    }
+
     #[test]
     fn goto() {
         let f = reformat::<ast::Stmt>;
@@ -1133,6 +1484,13 @@ mod tests {
         }}
     }
 
+    #[test]
+    fn language_prefix() {
+        let f = reformat::<ast::Stmt>;
+        assert_eq!(f(9999, r#"timeline.ins_10($ecl.REG[10000], REG[3]);"#).trim(), r#"timeline.ins_10($ecl.REG[10000], REG[3]);"#);
+        assert_eq!(f(9999, r#"ins_10();"#).trim(), r#"ins_10();"#);
+    }
+
     #[test]
     fn trailing_newline() {
         assert!(reformat::<ast::ScriptFile>(9999, r#"void fooo();"#).ends_with("\n"));
@@ -1141,4 +1499,97 @@ mod tests {
         assert!(reformat::<ast::ScriptFile>(3, r#"meta { x: 25 }"#).ends_with("\n"));
         assert!(reformat::<ast::ScriptFile>(9999, r#"  script  lol { nop(); }"#).ends_with("\n"));
     }
+
+    // Reformat a whole script file, keeping its comments.
+    fn reformat_script(text: &str) -> String {
+        let mut scope = crate::Builder::new().build();
+        let mut truth = scope.truth();
+        truth.reformat("<input>", text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn comments_inside_expressions() {
+        let source = r#"void foo() {
+    ins_1(1, /* x */ 2);
+    ins_2(
+        1,
+        // before second
+        2,
+    );
+    I0 = 1 + /* b */ 2;
+}
+"#;
+        assert_eq!(reformat_script(source), source);
+    }
+
+    #[test]
+    fn placement() {
+        let source = r#"// header
+#pragma mapfile "a.anmm"
+script  main { // after brace
+    // leading
+    ins_1(); // trailing
+    +10: // time
+    loop {
+        ins_2(1, /* inside */ 2);
+    } /* a */ // b
+    // end of block
+}
+// end of file
+"#;
+        let expected = r#"// header
+#pragma mapfile "a.anmm"
+
+script main {
+    // after brace
+    // leading
+    ins_1(); // trailing
++10: // time
+    loop {
+        ins_2(1, /* inside */ 2);
+    } /* a */ // b
+    // end of block
+}
+// end of file
+"#;
+        assert_eq!(reformat_script(source), expected);
+        assert_eq!(reformat_script(expected), expected);
+    }
+
+    #[test]
+    fn multiline_block_comment() {
+        let source = "void foo() {\n        /* one\n   two\n  */\n  nop();\n}\n";
+        assert_eq!(reformat_script(source), "void foo() {\n    /* one\n   two\n  */\n    nop();\n}\n");
+    }
+
+    #[test]
+    fn huge_call_is_not_quadratic() {
+        // Every argument nests calls through its *first* argument down to a long expression, so an
+        // inline attempt at any level has a lot to write before it finishes its first item.  These
+        // used to be rendered over and over again as each enclosing call fell back to block mode.
+        // (the source is 2000 arguments long so that anything quadratic is clearly visible)
+        let arg = |i: usize| {
+            let terms = (0..200).map(|k| format!("x{k}")).collect::<Vec<_>>().join(" + ");
+            let mut arg = format!("h({terms}, {i})");
+            for depth in 0..15 {
+                arg = format!("g{depth}({arg}, {i})");
+            }
+            arg
+        };
+        let source = format!("f({});", (0..2000).map(arg).collect::<Vec<_>>().join(", "));
+
+        let mut scope = crate::Builder::new().build();
+        let mut truth = scope.truth();
+        let stmt = truth.parse::<ast::Stmt>("<input>", source.as_bytes()).unwrap();
+
+        let mut f = Formatter::with_config(vec![], Config::new().max_columns(99));
+        f.fmt(&stmt).unwrap();
+        let measured_bytes = f.measured_bytes();
+        let formatted = String::from_utf8(f.into_inner().unwrap()).unwrap();
+
+        assert!(formatted.starts_with("f(\n    g14(\n        g13(\n"), "{}", &formatted[..200]);
+        assert_eq!(formatted.matches("x199),").count(), 2000);
+        // measuring something that doesn't fit should stop after about a line
+        assert!(measured_bytes < formatted.len(), "{} bytes measured for {} bytes of output", measured_bytes, formatted.len());
+    }
 }
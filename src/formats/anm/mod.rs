@@ -1,3 +1,4 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -12,15 +13,15 @@ use crate::ast::meta::{self, FromMeta, FromMetaError, Meta, ToMeta};
 use crate::io::{BinReader, BinWriter, ReadResult, WriteResult, Fs};
 use crate::diagnostic::{Diagnostic, Emitter};
 use crate::error::{GatherErrorIteratorExt, ErrorReported, ErrorFlag};
-use crate::game::{Game, LanguageKey};
+use crate::game::{Game, LanguageKey, Limit};
 use crate::ident::{Ident, ResIdent};
 use crate::image::ColorFormat;
 use crate::llir::{self, RawInstr, InstrFormat, LanguageHooks, DecompileOptions, HowBadIsIt};
 use crate::pos::{Sp, Span};
 use crate::value::{ScalarValue, ScalarType};
 use crate::context::CompilerContext;
-use crate::context::defs::auto_enum_names;
-use crate::resolve::RegId;
+use crate::context::defs::{auto_enum_names, TypeColor};
+use crate::resolve::{ConstId, RegId};
 use crate::debug_info;
 
 mod read_write;
@@ -48,6 +49,61 @@ pub struct WorkingAnmFile {
     entries: Vec<WorkingEntry>,
     /// Filename of a read binary file, for display purposes only.
     binary_filename: Option<String>,
+    /// How to report sprites that do not fit inside their texture.
+    sprite_bounds: SpriteBoundsCheck,
+    /// Whether scripts with identical instructions should share a body.
+    merge_identical_scripts: bool,
+}
+
+/// How [`WorkingAnmFile::finalize`] reports sprites that do not fit inside their entry's texture,
+/// or that have a non-positive size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum SpriteBoundsCheck {
+    /// Don't check sprites.
+    Allow,
+    /// Emit a warning for each bad sprite.
+    #[default]
+    Warn,
+    /// Emit an error for each bad sprite, failing compilation.
+    Deny,
+}
+
+impl std::str::FromStr for SpriteBoundsCheck {
+    type Err = Diagnostic;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(SpriteBoundsCheck::Allow),
+            "warn" => Ok(SpriteBoundsCheck::Warn),
+            "deny" => Ok(SpriteBoundsCheck::Deny),
+            _ => Err(error!("invalid sprite bounds check: {} (expected allow, warn or deny)", s)),
+        }
+    }
+}
+
+/// The order in which the scripts of each entry are written when decompiling an ANM file.
+///
+/// Scripts always stay in their own entry, and their IDs are always written explicitly, so this does not
+/// affect the IDs of a recompiled file.  (it does however affect their indices)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScriptOrder {
+    /// The order of the script table in the binary file.
+    #[default]
+    File,
+    /// Sorted by script ID.  Scripts with equal IDs keep their order from the script table.
+    Id,
+}
+
+impl std::str::FromStr for ScriptOrder {
+    type Err = Diagnostic;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(ScriptOrder::File),
+            "id" => Ok(ScriptOrder::Id),
+            _ => Err(error!("invalid script order: {} (expected file or id)", s)),
+        }
+    }
 }
 
 impl AnmFile {
@@ -59,7 +115,45 @@ impl AnmFile {
 
 impl WorkingAnmFile {
     pub fn compile_from_ast(game: Game, ast: &ast::ScriptFile, ctx: &mut CompilerContext) -> Result<Self, ErrorReported> {
-        compile(&*game_hooks(game), ast, ctx)
+        compile(game, &*game_hooks(game), ast, ctx)
+    }
+
+    /// Build a file from the `entry` blocks of a script and scripts of raw instructions, without compiling any code.
+    ///
+    /// `scripts` holds the scripts of each entry, in order.  `ast` may contain nothing but entries and consts.
+    pub fn from_raw_scripts(ast: &ast::ScriptFile, scripts: Vec<Vec<(Ident, Script)>>, ctx: &mut CompilerContext) -> Result<Self, ErrorReported> {
+        let emitter = ctx.emitter;
+        for item in &ast.items {
+            match &item.value {
+                ast::Item::Meta { keyword: sp_pat!(ast::MetaKeyword::Entry), .. } => {},
+                ast::Item::ConstVar { .. } => {},
+                _ => return Err(emitter.emit(error!(
+                    message("unexpected item in ANM skeleton"),
+                    primary(item, "only entries are allowed here"),
+                ))),
+            }
+        }
+
+        let mut ast = ast.clone();
+        crate::passes::resolution::assign_languages(&mut ast, LanguageKey::Anm, ctx)?;
+        define_color_format_consts(ctx);
+        crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::const_simplify::run(&mut ast, ctx)?;
+
+        let mut entries = ast.items.iter().filter_map(|item| match &item.value {
+            ast::Item::Meta { fields, .. } => Some(WorkingEntry::from_fields(fields, emitter).map_err(|e| emitter.emit(e))),
+            _ => None,
+        }).collect::<Result<Vec<_>, _>>()?;
+        if entries.len() != scripts.len() {
+            return Err(emitter.emit(error!(
+                "scripts were provided for {} entries, but the skeleton has {}", scripts.len(), entries.len(),
+            )));
+        }
+        for (entry, entry_scripts) in entries.iter_mut().zip(scripts) {
+            entry.scripts = entry_scripts.into_iter().map(|(name, script)| (sp!(name), script)).collect();
+        }
+        Ok(WorkingAnmFile { entries, binary_filename: None, sprite_bounds: Default::default(), merge_identical_scripts: false })
     }
 
     /// Uses `other` as a source for any missing metadata from the entries, as well as for embedded images.
@@ -72,6 +166,19 @@ impl WorkingAnmFile {
     /// If multiple entries have the same path, then the first one with that path in `other` applies to the first
     /// one with that path in `self`, and so on in matching order.  This is to facilitate recompilation of files
     /// like `ascii.anm`, which have multiple `"@R"` entries.
+    /// Set how sprites that do not fit inside their texture are reported.  Defaults to a warning.
+    pub fn set_sprite_bounds_check(&mut self, check: SpriteBoundsCheck) {
+        self.sprite_bounds = check;
+    }
+
+    /// Make each script that compiled to the same instructions as an earlier script in its entry share that
+    /// script's body, so that both IDs point to the same offset in the script table.  Defaults to `false`.
+    ///
+    /// This is done when finalizing, and an `info` diagnostic is emitted for every script that was merged.
+    pub fn set_merge_identical_scripts(&mut self, value: bool) {
+        self.merge_identical_scripts = value;
+    }
+
     pub fn apply_image_source(&mut self, src: ImageSource, fs: &Fs<'_>) -> Result<(), ErrorReported> {
         match src {
             ImageSource::Anm(other) => apply_anm_image_source(self, other).map_err(|d| fs.emitter.emit(d)),
@@ -92,7 +199,17 @@ impl AnmFile {
 
     pub fn read_from_stream(r: &mut BinReader, game: Game, with_images: bool) -> ReadResult<Self> {
         let emitter = r.emitter();
-        read_write::read_anm(r, &emitter, game, with_images)
+        read_write::read_anm(r, &emitter, game, with_images, false)
+    }
+
+    /// Like [`Self::read_from_stream`], but salvages what it can from a corrupted file.
+    ///
+    /// A script whose entry in the script table points past the end of the file, into the entry header, or
+    /// into the middle of another script produces a warning instead of an error, and is read as an empty
+    /// script with [`Script::corrupt`] set.
+    pub fn read_from_stream_lenient(r: &mut BinReader, game: Game, with_images: bool) -> ReadResult<Self> {
+        let emitter = r.emitter();
+        read_write::read_anm(r, &emitter, game, with_images, true)
     }
 
     pub fn generate_thecl_defs(&self) -> Result<String, ErrorReported> {
@@ -105,11 +222,16 @@ impl AnmFile {
 impl WorkingAnmFile {
     /// Finish working on the ANM file, filling in defaults and transcoding image buffer data
     /// as necessary.
-    pub fn finalize(self, fs: &Fs, game: Game, emitter: &impl Emitter) -> Result<AnmFile, ErrorReported> {
+    pub fn finalize(mut self, fs: &Fs, game: Game, emitter: &impl Emitter) -> Result<AnmFile, ErrorReported> {
+        if self.merge_identical_scripts {
+            for entry in &mut self.entries {
+                merge_identical_scripts(entry, emitter);
+            }
+        }
         Ok(AnmFile {
             entries: {
                 self.entries.into_iter()
-                    .map(|entry| finalize_entry(fs, entry, game, emitter))
+                    .map(|entry| finalize_entry(fs, entry, game, self.sprite_bounds, emitter))
                     .collect::<Result<_, _>>()?
             },
             binary_filename: self.binary_filename,
@@ -188,12 +310,23 @@ struct WorkingEntry {
     path_2: Option<Sp<String>>,
     scripts: IndexMap<Sp<Ident>, Script>,
     sprites: IndexMap<Sp<Ident>, Sprite>,
+    /// Locations of the fields of each sprite, for diagnostics.
+    sprite_spans: IndexMap<Sp<Ident>, SpriteSpans>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Script {
     pub id: i32,
     pub instrs: Vec<RawInstr>,
+    /// For a script that shares its body with another script in the same entry, the name of that script.
+    ///
+    /// `instrs` still holds a copy of the shared body.  Both scripts point to the same offset in the
+    /// script table when written.
+    pub alias_of: Option<Sp<Ident>>,
+    /// Set on a script that could not be read because its offset in the script table was past the end of
+    /// the file, inside the entry header, or inside another script.  Lenient reading produces these, and
+    /// they are written and parsed as `meta { corrupt: true }`.  (see [`AnmFile::read_from_stream_lenient`])
+    pub corrupt: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -240,6 +373,10 @@ pub struct EntrySpecs {
     /// e.g. the border around the game.  (not used for things like `ascii.anm` which have dedicated files
     /// for each resolution)
     pub low_res_scale: bool,
+
+    /// Nonzero values of header fields whose purpose is not yet known, keyed by their byte offset in the
+    /// header.  These appear in `meta` as e.g. `unknown_0x28`.
+    pub unknown_header_fields: BTreeMap<u32, u32>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -259,6 +396,7 @@ struct WorkingEntrySpecs {
     offset_y: SoftOption<u32>,
     memory_priority: SoftOption<u32>,
     low_res_scale: SoftOption<bool>,
+    unknown_header_fields: BTreeMap<u32, SoftOption<u32>>,
 }
 
 fn default_memory_priority(version: Version) -> u32 {
@@ -269,7 +407,13 @@ fn default_memory_priority(version: Version) -> u32 {
 }
 
 /// Finishes work on an entry during compilation.
-fn finalize_entry(fs: &Fs, entry: WorkingEntry, game: Game, emitter: &impl Emitter) -> Result<Entry, ErrorReported> {
+fn finalize_entry(
+    fs: &Fs,
+    entry: WorkingEntry,
+    game: Game,
+    sprite_bounds: SpriteBoundsCheck,
+    emitter: &impl Emitter,
+) -> Result<Entry, ErrorReported> {
     let version = Version::from_game(game);
 
     // Fill in defaults to simplify reasoning.
@@ -319,6 +463,9 @@ fn finalize_entry(fs: &Fs, entry: WorkingEntry, game: Game, emitter: &impl Emitt
         }
     }
 
+    validate_sprite_bounds(emitter, &specs, &entry, sprite_bounds)?;
+    let unknown_header_fields = finalize_unknown_header_fields(emitter, &specs, version)?;
+
     Ok(Entry {
         specs: EntrySpecs{
             rt_format: specs.rt_format.into_option().expect("was filled by default"),
@@ -329,6 +476,7 @@ fn finalize_entry(fs: &Fs, entry: WorkingEntry, game: Game, emitter: &impl Emitt
             colorkey: specs.colorkey.into_option().expect("was filled by default"),
             memory_priority: specs.memory_priority.into_option().expect("was filled by default"),
             low_res_scale: specs.low_res_scale.into_option().expect("was filled by default"),
+            unknown_header_fields,
         },
         texture_metadata: texture_data.as_ref().map(|_| TextureMetadata {
             width: specs.img_width.into_option().expect("was filled by default"),
@@ -343,6 +491,135 @@ fn finalize_entry(fs: &Fs, entry: WorkingEntry, game: Game, emitter: &impl Emitt
     })
 }
 
+/// Check that the header fields of unknown purpose set in the script exist in this game's header, and that
+/// their values fit.
+fn finalize_unknown_header_fields(
+    emitter: &impl Emitter,
+    specs: &WorkingEntrySpecs,
+    version: Version,
+) -> Result<BTreeMap<u32, u32>, ErrorReported> {
+    let fields_in_version = read_write::unknown_header_fields(version);
+    let mut errors = ErrorFlag::new();
+    let mut out = BTreeMap::new();
+    for (&offset, &value) in &specs.unknown_header_fields {
+        let name = read_write::unknown_header_field_name(offset);
+        // soft values come from an ANM file for the same game, so only explicit values can be bad
+        if let SoftOption::Explicit(value) = value {
+            match fields_in_version.iter().find(|&&(x, _)| x == offset) {
+                None => errors.set(emitter.emit(error!(
+                    message("'{name}' does not exist in the entry header for this game"),
+                    primary(value, "not in this header"),
+                ))),
+                Some(&(_, size)) => if size < 4 && value.value >> (8 * size) != 0 {
+                    errors.set(emitter.emit(error!(
+                        message("value of '{name}' does not fit in {size} bytes"),
+                        primary(value, "too large"),
+                    )));
+                },
+            }
+        }
+        match value.into_option() {
+            Some(0) | None => {},
+            Some(value) => { out.insert(offset, value); },
+        }
+    }
+    errors.into_result(out)
+}
+
+/// Turn each script whose instructions are identical to those of an earlier script in the same entry into
+/// an alias of that script.
+fn merge_identical_scripts(entry: &mut WorkingEntry, emitter: &impl Emitter) {
+    for index in 0..entry.scripts.len() {
+        let (name, script) = entry.scripts.get_index(index).unwrap();
+        if script.alias_of.is_some() {
+            continue;
+        }
+        let earlier = entry.scripts.iter().take(index).find(|(_, other)| {
+            other.alias_of.is_none() && other.instrs == script.instrs
+        });
+        if let Some((earlier_name, earlier_script)) = earlier {
+            let mut diag = info!(
+                "script '{}' (id {}) merged into identical script '{}' (id {})",
+                name, script.id, earlier_name, earlier_script.id,
+            );
+            // scripts built through the API may not have come from source
+            if name.span.file_id.is_some() && earlier_name.span.file_id.is_some() {
+                diag.primary(name, format!("now shares the body of '{earlier_name}'"));
+                diag.secondary(earlier_name, "identical script".to_string());
+            }
+            emitter.emit(diag).ignore();
+            let earlier_name = earlier_name.clone();
+            entry.scripts[index].alias_of = Some(earlier_name);
+        }
+    }
+}
+
+/// Check that every sprite lies inside the texture of its entry.
+///
+/// Sprite coordinates are measured in pixels of the original image, which begins at `offset_x, offset_y`.
+/// When the image dimensions are known, they are used as the bounds, since `rt_width` and `rt_height` are
+/// rounded up to a power of two and sprites in the padding will sample garbage.  Otherwise (e.g. `has_data: false`
+/// without an image source, or `@` render targets) the `rt_width` and `rt_height` are the best we have.
+fn validate_sprite_bounds(
+    emitter: &impl Emitter,
+    specs: &WorkingEntrySpecs,
+    entry: &WorkingEntry,
+    check: SpriteBoundsCheck,
+) -> Result<(), ErrorReported> {
+    let make_diagnostic = match check {
+        SpriteBoundsCheck::Allow => return Ok(()),
+        SpriteBoundsCheck::Warn => Diagnostic::warning,
+        SpriteBoundsCheck::Deny => Diagnostic::error,
+    };
+
+    let (bounds, bounds_descr) = match (specs.img_width.into_option(), specs.img_height.into_option()) {
+        (Some(img_width), Some(img_height)) => {
+            let offset_x = specs.offset_x.into_option().unwrap_or(0);
+            let offset_y = specs.offset_y.into_option().unwrap_or(0);
+            ([offset_x + img_width, offset_y + img_height], "image")
+        },
+        _ => match (specs.rt_width.into_option(), specs.rt_height.into_option()) {
+            (Some(rt_width), Some(rt_height)) => ([rt_width, rt_height], "runtime texture"),
+            _ => return Ok(()),
+        },
+    };
+
+    let mut errors = ErrorFlag::new();
+    for (name, sprite) in &entry.sprites {
+        let spans = match entry.sprite_spans.get(name) {
+            Some(spans) => spans,
+            None => continue,
+        };
+        let bad_sizes = (0..2).filter(|&axis| sprite.size[axis] <= 0.0).collect::<Vec<_>>();
+        let out_of_bounds = (0..2).filter(|&axis| {
+            let (start, size) = (sprite.offset[axis], sprite.size[axis]);
+            start < 0.0 || start + size > bounds[axis] as f32
+        }).collect::<Vec<_>>();
+
+        let mut diag = make_diagnostic();
+        if !bad_sizes.is_empty() {
+            diag.message(format!("sprite '{name}' has non-positive size"));
+            for axis in bad_sizes {
+                diag.primary(spans.size[axis], format!("{} = {}", ["w", "h"][axis], sprite.size[axis]));
+            }
+        } else if !out_of_bounds.is_empty() {
+            diag.message(format!("sprite '{name}' extends outside of the texture for '{}'", entry.path));
+            for axis in out_of_bounds {
+                let end = sprite.offset[axis] + sprite.size[axis];
+                diag.primary(spans.offset[axis], format!("{} ranges from {} to {}", ["x", "y"][axis], sprite.offset[axis], end));
+            }
+            diag.note(format!("the {bounds_descr} is {}x{}", bounds[0], bounds[1]));
+        } else {
+            continue;
+        }
+        let reported = emitter.emit(diag);
+        if check == SpriteBoundsCheck::Deny {
+            errors.set(reported);
+        }
+    }
+    errors.into_result(())
+}
+
 fn finalize_entry_texture(fs: &Fs, specs: &mut WorkingEntrySpecs, entry_path: &str, loaded_texture: Option<&TextureFromSource>) -> Result<Option<TextureData>, ErrorReported> {
     let emitter = fs.emitter;
 
@@ -533,7 +810,7 @@ const DEFAULT_LOW_RES_SCALE: bool = false;
 const DEFAULT_HAS_DATA: HasData = HasData::True;
 
 impl Entry {
-    fn make_meta(&self, game: Game) -> meta::Fields {
+    fn make_meta(&self, game: Game, decompile_options: &DecompileOptions) -> meta::Fields {
         let version = Version::from_game(game);
 
         // img_* fields are only present if a texture was present
@@ -543,12 +820,17 @@ impl Entry {
             opt_img_width = Some(texture_metadata.width);
             opt_img_height = Some(texture_metadata.height);
         }
-        let has_data = HasData::from(self.texture_metadata.is_some());
+        let has_data = match self.texture_metadata {
+            // the img_* fields are still written so that the dummy data has the right size
+            Some(_) if decompile_options.dummy_images => HasData::Dummy,
+            _ => HasData::from(self.texture_metadata.is_some()),
+        };
 
         // these are always here
         let EntrySpecs {
             rt_width, rt_height, rt_format, colorkey,
             offset_x, offset_y, memory_priority, low_res_scale,
+            ref unknown_header_fields,
         } = self.specs;
 
         // suppress defaults
@@ -571,12 +853,15 @@ impl Entry {
             .field_opt("img_format", Some(img_format).filter(|&x| x != DEFAULT_COLOR_FORMAT as u32).map(format_to_meta))
             .field_opt("offset_x", Some(offset_x).filter(|&x| x != 0))
             .field_opt("offset_y", Some(offset_y).filter(|&x| x != 0))
-            .field_opt("colorkey", Some(colorkey).filter(|&x| x != 0).map(colorkey_to_meta))
+            .field_opt("colorkey", Some(colorkey).filter(|&x| x != 0).map(meta::Hex))
             .field_opt("rt_width", opt_rt_width)
             .field_opt("rt_height", opt_rt_height)
             .field_opt("rt_format", Some(rt_format).filter(|&x| x != img_format).map(format_to_meta))
             .field_opt("memory_priority", Some(memory_priority).filter(|&x| x != default_memory_priority(version)))
             .field_opt("low_res_scale", Some(low_res_scale).filter(|&x| x != DEFAULT_LOW_RES_SCALE))
+            .with_mut(|b| for (&offset, value) in unknown_header_fields {
+                b.field(read_write::unknown_header_field_name(offset), value);
+            })
             .field("sprites", &self.sprites)
             .build_fields()
     }
@@ -585,6 +870,8 @@ impl Entry {
 impl WorkingEntry {
     fn from_fields<'a>(fields: &'a Sp<meta::Fields>, emitter: &impl Emitter) -> Result<Self, FromMetaError<'a>> {
         meta::ParseObject::scope(fields, |m| {
+            m.describe("ANM entry")?;
+
             fn make_explicit<T>(opt: Option<Sp<T>>) -> SoftOption<T> {
                 match opt {
                     None => SoftOption::Missing,
@@ -656,9 +943,16 @@ impl WorkingEntry {
             let memory_priority = make_explicit(m.get_field("memory_priority")?);
             let low_res_scale = make_explicit(m.get_field("low_res_scale")?);
             let has_data = make_explicit(m.get_field("has_data")?);
+            let mut unknown_header_fields = BTreeMap::new();
+            for &(key, offset) in read_write::UNKNOWN_HEADER_FIELD_NAMES {
+                if let Some(value) = m.get_field::<Sp<u32>>(key)? {
+                    unknown_header_fields.insert(offset, SoftOption::Explicit(value));
+                }
+            }
             let path: Sp<String> = m.expect_field("path")?;
             let path_2 = m.get_field("path_2")?;
             let sprites = m.get_field("sprites")?.unwrap_or_default();
+            let sprite_spans = m.get_field("sprites")?.unwrap_or_default();
 
             for (field, rt_value) in vec![
                 ("height", rt_height),
@@ -679,10 +973,11 @@ impl WorkingEntry {
                 img_width, img_height, img_format,
                 colorkey, offset_x, offset_y,
                 memory_priority, has_data, low_res_scale,
+                unknown_header_fields,
             };
             let loaded_texture = None;
             let scripts = Default::default();
-            Ok(WorkingEntry { specs, path, path_2, scripts, sprites, loaded_texture })
+            Ok(WorkingEntry { specs, path, path_2, scripts, sprites, sprite_spans, loaded_texture })
         })
     }
 }
@@ -697,11 +992,7 @@ fn format_to_meta(format_num: u32) -> Meta {
             }))));
         }
     }
-    format_num.to_meta()
-}
-
-fn colorkey_to_meta(colorkey: u32) -> impl ToMeta {
-    ast::Expr::LitInt { value: colorkey as i32, radix: ast::IntRadix::Hex }
+    meta::Hex(format_num).to_meta()
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -800,11 +1091,36 @@ impl ToMeta for Sprite {
 
 impl FromMeta<'_> for Sprite {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
-        meta.parse_object(|m| Ok(Sprite {
-            id: m.get_field("id")?,
-            offset: [m.expect_field("x")?, m.expect_field("y")?],
-            size: [m.expect_field("w")?, m.expect_field("h")?],
-        }))
+        meta.parse_object(|m| {
+            m.describe("ANM sprite")?;
+            m.require_fields(&["x", "y", "w", "h"])?;
+            Ok(Sprite {
+                id: m.get_field("id")?,
+                offset: [m.expect_field("x")?, m.expect_field("y")?],
+                size: [m.expect_field("w")?, m.expect_field("h")?],
+            })
+        })
+    }
+}
+
+/// Spans of the fields of a [`Sprite`], for diagnostics.
+#[derive(Debug, Clone)]
+struct SpriteSpans {
+    offset: [Span; 2],
+    size: [Span; 2],
+}
+
+impl FromMeta<'_> for SpriteSpans {
+    fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
+        meta.parse_object(|m| {
+            let span = |field: Sp<f32>| field.span;
+            let spans = SpriteSpans {
+                offset: [span(m.expect_field("x")?), span(m.expect_field("y")?)],
+                size: [span(m.expect_field("w")?), span(m.expect_field("h")?)],
+            };
+            m.allow_unrecognized_fields()?;
+            Ok(spans)
+        })
     }
 }
 
@@ -812,7 +1128,7 @@ impl FromMeta<'_> for Sprite {
 
 fn decompile(
     anm_file: &AnmFile,
-    emitter: &impl Emitter,
+    emitter: &(impl Emitter + Sync),
     game: Game,
     hooks: &dyn LanguageHooks,
     ctx: &mut CompilerContext,
@@ -829,24 +1145,54 @@ fn decompile(
     }
 
     let const_proof = crate::passes::evaluate_const_vars::run(ctx)?;
-    let mut raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
+    let raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
 
-    for entry in &anm_file.entries {
+    let entry_scripts = anm_file.entries.iter().map(|entry| {
+        let mut scripts = entry.scripts.iter().collect::<Vec<_>>();
+        if decompile_options.script_order == ScriptOrder::Id {
+            scripts.sort_by_key(|&(_, script)| script.id);  // stable
+        }
+        scripts
+    }).collect::<Vec<_>>();
+
+    // scripts that share a body with another are written as aliases, so they don't need to be raised
+    let written_as_alias = |script: &Script| decompile_options.script_aliases && script.alias_of.is_some();
+    let scripts = {
+        entry_scripts.iter().flatten().map(|&(_, script)| script)
+            .filter(|&script| !written_as_alias(script))
+            .collect::<Vec<_>>()
+    };
+    let mut raised_scripts = ctx.map_in_parallel(&scripts, |&&Script { id, ref instrs, .. }, job_emitter| {
+        emitter.with_root(job_emitter).chain_with(|f| write!(f, "in script{}", id), |emitter| {
+            raiser.raise_instrs_to_sub_ast(emitter, instrs, ctx)
+        })
+    }).into_iter();
+
+    for (entry, scripts) in anm_file.entries.iter().zip(&entry_scripts) {
         items.push(sp!(ast::Item::Meta {
             keyword: sp!(ast::MetaKeyword::Entry),
-            fields: sp!(entry.make_meta(game)),
+            fields: sp!(entry.make_meta(game, decompile_options)),
         }));
 
-        entry.scripts.iter().map(|(name, &Script { id, ref instrs })| {
-            let code = emitter.chain_with(|f| write!(f, "in script{}", id), |emitter| {
-                raiser.raise_instrs_to_sub_ast(emitter, instrs, ctx)
-            })?;
-
+        scripts.iter().map(|&(name, script)| {
+            let id = script.id;
+            if let (true, Some(target)) = (written_as_alias(script), &script.alias_of) {
+                items.push(sp!(ast::Item::AnmScriptAlias {
+                    number: Some(sp!(id)),
+                    ident: name.clone(),
+                    target: target.clone(),
+                    keyword: sp!(()),
+                }));
+                return Ok(());
+            }
+            let code = raised_scripts.next().unwrap()?;
             items.push(sp!(ast::Item::AnmScript {
+                // always explicit, so that the IDs don't depend on the order that the scripts are written in
                 number: Some(sp!(id)),
                 ident: name.clone(),
                 code: ast::Block(code),
                 keyword: sp!(()),
+                meta: script.corrupt.then(|| sp!(script_meta_for_corrupt())),
             }));
             Ok(())
         }).collect_with_recovery()?;
@@ -854,14 +1200,17 @@ fn decompile(
 
     let mut out = ast::ScriptFile {
         items,
-        mapfiles: ctx.mapfiles_to_ast(),
+        mapfiles: vec![],  // filled in once we know what was used
         // NOTE: here, we *could* choose to populate this, causing a `#pragma image_source` line
         //       to automatically be added to the file.  However, the big reason we do this for
         //       mapfiles is to encourage people to check their mapfiles into VCS, and I do not
         //       want to encourage people checking in vanilla ANM files.
         image_sources: vec![],
+        reg_pragmas: vec![],
+        comments: vec![],
     };
     crate::passes::postprocess_decompiled(&mut out, ctx, decompile_options)?;
+    out.mapfiles = ctx.used_mapfiles_to_ast(&decompile_options.mapfile_paths)?;
     Ok(out)
 }
 
@@ -902,6 +1251,7 @@ fn update_entry_from_anm_image_source(dest_file: &mut WorkingEntry, src_file: En
         colorkey: src_colorkey, offset_x: src_offset_x, offset_y: src_offset_y,
         memory_priority: src_memory_priority,
         low_res_scale: src_low_res_scale,
+        unknown_header_fields: src_unknown_header_fields,
     } = src_specs;
 
     dest_file.specs.has_data.set_soft(HasData::from(src_texture_data.is_some()));
@@ -925,6 +1275,9 @@ fn update_entry_from_anm_image_source(dest_file: &mut WorkingEntry, src_file: En
     dest_file.specs.offset_y.set_soft(src_offset_y);
     dest_file.specs.memory_priority.set_soft(src_memory_priority);
     dest_file.specs.low_res_scale.set_soft(src_low_res_scale);
+    for (offset, value) in src_unknown_header_fields {
+        dest_file.specs.unknown_header_fields.entry(offset).or_default().set_soft(value);
+    }
 
     Ok(())
 }
@@ -932,40 +1285,64 @@ fn update_entry_from_anm_image_source(dest_file: &mut WorkingEntry, src_file: En
 // =============================================================================
 
 fn compile(
+    game: Game,
     hooks: &dyn LanguageHooks,
     ast: &ast::ScriptFile,
     ctx: &mut CompilerContext,
 ) -> Result<WorkingAnmFile, ErrorReported> {
     let mut ast = ast.clone();
     crate::passes::resolution::assign_languages(&mut ast, hooks.language(), ctx)?;
+    ctx.observers.after_pass("assign_languages", &ast)?;
 
     define_color_format_consts(ctx);
 
     // an early pass to define global constants for sprite and script names
     let mut extra_type_checks = vec![];
     let script_ids = gather_script_ids(&ast, ctx)?;
+    ctx.check_limit(game, Limit::AnmScripts, script_ids.values().map(|(script_name, _)| script_name.span))?;
     for (index, &(ref script_name, _)) in script_ids.values().enumerate() {
         let const_value: Sp<ast::Expr> = sp!(script_name.span => (index as i32).into());
         ctx.define_enum_const(script_name.clone(), const_value, sp!(auto_enum_names::anm_script()));
     }
     let sprite_ids = gather_sprite_id_exprs(&ast, ctx, &mut extra_type_checks)?;
+    ctx.check_limit(game, Limit::AnmSprites, sprite_ids.iter().map(|(sprite_name, _)| sprite_name.span))?;
+    let mut sprite_consts = vec![];
     for (sprite_name, id_expr) in sprite_ids {
-        ctx.define_enum_const(sprite_name, id_expr, sp!(auto_enum_names::anm_sprite()));
+        let const_id = ctx.define_enum_const(sprite_name.clone(), id_expr, sp!(auto_enum_names::anm_sprite()));
+        sprite_consts.push((sprite_name, const_id));
     }
 
     // preprocess
     let ast = {
         let mut ast = ast;
         crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::validate_reg_sigils::run(&ast, ctx);
+        ctx.observers.after_resolution(&ast, &ctx.resolutions)?;
         crate::passes::type_check::run(&ast, ctx)?;
         crate::passes::type_check::extra_checks(&extra_type_checks, ctx)?;
-        crate::passes::validate_difficulty::forbid_difficulty(&ast, ctx)?;
+        crate::passes::float_equality::run(&ast, ctx)?;
+        crate::passes::unreachable_code::run(&ast, ctx)?;
         crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::validate_difficulty::run_if_supported(&mut ast, ctx, hooks)?;
+        crate::passes::inline_funcs::run(&mut ast, ctx)?;
+        crate::passes::explode_diff_switches::run(&mut ast, ctx)?;
         crate::passes::const_simplify::run(&mut ast, ctx)?;
+        ctx.observers.after_pass("const_simplify", &ast)?;
+        crate::passes::unroll_times::run(&mut ast, ctx)?;
         crate::passes::desugar_blocks::run(&mut ast, ctx, hooks.language())?;
+        ctx.observers.after_pass("desugar_blocks", &ast)?;
         ast
     };
 
+    if ctx.warn_unreferenced_anm_items {
+        lint_unreferenced_items(&ast, &script_ids, &sprite_consts, ctx);
+    }
+
+    enum ScriptSource<'a> {
+        Code { code: &'a ast::Block, corrupt: bool },
+        AliasOf(&'a Sp<Ident>),
+    }
+
     // group scripts by entry
     let mut groups = vec![];
     let mut cur_entry = None::<WorkingEntry>;
@@ -980,15 +1357,27 @@ fn compile(
                 cur_entry = Some(WorkingEntry::from_fields(fields, ctx.emitter).map_err(|e| ctx.emitter.emit(e))?);
                 cur_group = vec![];
             },
-            &ast::Item::AnmScript { number: _, ref ident, ref code, .. } => {
+            &ast::Item::AnmScript { number: _, ref ident, ref meta, ref code, .. } => {
                 if cur_entry.is_none() { return Err(ctx.emitter.emit(error!(
                     message("orphaned ANM script with no entry"),
                     primary(item, "orphaned script"),
                     note("at least one `entry` must come before scripts in an ANM file"),
                 )))}
-                cur_group.push((ident, code));
+                let corrupt = match meta {
+                    Some(fields) => script_corrupt_from_fields(fields).map_err(|e| ctx.emitter.emit(e))?,
+                    None => false,
+                };
+                cur_group.push((ident, ScriptSource::Code { code, corrupt }));
                 script_names.push(ident);
             },
+            ast::Item::AnmScriptAlias { ident, target, .. } => {
+                if cur_entry.is_none() { return Err(ctx.emitter.emit(error!(
+                    message("orphaned ANM script with no entry"),
+                    primary(item, "orphaned script"),
+                    note("at least one `entry` must come before scripts in an ANM file"),
+                )))}
+                cur_group.push((ident, ScriptSource::AliasOf(target)));
+            },
             ast::Item::ConstVar { .. } => {},
             _ => return Err(ctx.emitter.emit(error!(
                 message("feature not supported by format"),
@@ -1003,16 +1392,37 @@ fn compile(
     }
 
     let mut errors = ErrorFlag::new();
-    let mut lowerer = llir::Lowerer::new(hooks);
+    let do_debug_info = true;
+    let subs = groups.iter().flat_map(|(_, ast_scripts)| ast_scripts)
+        .filter_map(|(_, source)| match source {
+            ScriptSource::Code { code, .. } => Some((&code.0[..], None)),
+            ScriptSource::AliasOf(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let lint_script_names = script_names.iter().map(|name| name.span).collect();
+    let mut lowerer = llir::Lowerer::new(hooks)
+        .with_script_lints(lint_script_names)
+        .with_script_names(script_names.iter().map(|&name| name.clone()).collect());
+    let mut lowered_subs = lowerer.lower_subs(&subs, ctx, do_debug_info).into_iter();
+
     let mut entries = vec![];
     groups.into_iter().map(|(mut entry, ast_scripts)| {
-        for (name, code) in ast_scripts {
+        let num_bodies = ast_scripts.iter().filter(|(_, source)| matches!(source, ScriptSource::Code { .. })).count();
+        let mut lowered_subs = lowered_subs.by_ref().take(num_bodies).collect::<Vec<_>>().into_iter();
+        for (name, source) in ast_scripts {
             let script_index = script_ids.get_index_of(&name.value).unwrap();
             let (_, sp_pat![id]) = script_ids[script_index];
 
-            let def_id = None;
-            let do_debug_info = true;
-            let (instrs, lowering_info) = lowerer.lower_sub(&code.0, def_id, ctx, do_debug_info)?;
+            if let ScriptSource::AliasOf(target) = source {
+                // the body is filled in once the whole entry is known
+                let script = Script { id, instrs: vec![], alias_of: Some(target.clone()), corrupt: false };
+                entry.scripts.insert(sp!(name.span => name.value.clone()), script);
+                continue;
+            }
+
+            let ScriptSource::Code { corrupt, .. } = source else { unreachable!() };
+            let (instrs, lowering_info) = lowered_subs.next().unwrap()?;
+            ctx.observers.after_lowering(name.as_str(), &instrs)?;
 
             if do_debug_info {
                 let lowering_info = lowering_info.unwrap();
@@ -1024,8 +1434,9 @@ fn compile(
                 ctx.script_debug_info.push(debug_info::Script { export_info, lowering_info });
             }
 
-            entry.scripts.insert(sp!(name.span => name.value.clone()), Script { id, instrs });
+            entry.scripts.insert(sp!(name.span => name.value.clone()), Script { id, instrs, alias_of: None, corrupt });
         }
+        resolve_script_aliases(&mut entry, ctx.emitter)?;
         entries.push(entry);
         Ok::<_, ErrorReported>(())
     }).collect_with_recovery().unwrap_or_else(|e| errors.set(e));
@@ -1033,7 +1444,7 @@ fn compile(
     lowerer.finish(ctx).unwrap_or_else(|e| errors.set(e));
     errors.into_result(())?;
 
-    Ok(WorkingAnmFile { entries, binary_filename: None })
+    Ok(WorkingAnmFile { entries, binary_filename: None, sprite_bounds: Default::default(), merge_identical_scripts: false })
 }
 
 fn write_thecl_defs(
@@ -1130,14 +1541,98 @@ impl<'m> FromMeta<'m> for ProtoSprite<'m> {
     }
 }
 
+/// The `meta { corrupt: true }` written on a script that lenient reading could not read.
+fn script_meta_for_corrupt() -> meta::Fields {
+    Meta::make_object().field("corrupt", &true).build_fields()
+}
+
+fn script_corrupt_from_fields(fields: &Sp<meta::Fields>) -> Result<bool, FromMetaError<'_>> {
+    meta::ParseObject::scope(fields, |m| {
+        m.describe("ANM script meta")?;
+        Ok(m.get_field::<bool>("corrupt")?.unwrap_or(false))
+    })
+}
+
+/// Copies the body of each script alias in an entry from the script it refers to, which must be a script
+/// with code in the same entry.
+fn resolve_script_aliases(entry: &mut WorkingEntry, emitter: &impl Emitter) -> Result<(), ErrorReported> {
+    let mut errors = ErrorFlag::new();
+    for index in 0..entry.scripts.len() {
+        let (name, script) = entry.scripts.get_index(index).unwrap();
+        let target = match &script.alias_of {
+            Some(target) => target,
+            None => continue,
+        };
+        match entry.scripts.get(target) {
+            Some(Script { alias_of: None, instrs, .. }) => {
+                let instrs = instrs.clone();
+                entry.scripts[index].instrs = instrs;
+            },
+            Some(Script { alias_of: Some(_), .. }) => errors.set(emitter.emit(error!(
+                message("script '{}' is an alias of another alias", name),
+                primary(target, "'{}' is an alias", target),
+                note("an alias must name a script that has its own body"),
+            ))),
+            None => errors.set(emitter.emit(error!(
+                message("no script named '{}' in this entry", target),
+                primary(target, "not a script in this entry"),
+                note("an alias can only share the body of a script in the same entry"),
+            ))),
+        }
+    }
+    errors.into_result(())
+}
+
+/// Assigns an ID to every script, and checks that no two scripts share a name or an ID.
+///
+/// A script without a number is numbered one past the highest ID used so far in the file (or 0 for the
+/// first script).  A warning is given if this skips over IDs right after the previous script, as the user
+/// was probably counting up from its explicit number.
 fn gather_script_ids(ast: &ast::ScriptFile, ctx: &mut CompilerContext) -> Result<IndexMap<Ident, (Sp<ResIdent>, Sp<i32>)>, ErrorReported> {
-    let mut next_auto_script = 0;
+    let mut errors = ErrorFlag::new();
+    let mut prev_script_id = None::<Sp<i32>>;
+    let mut max_script_id = None::<i32>;
+    let mut first_use_by_id = BTreeMap::<i32, (Sp<i32>, bool)>::new();  // for duplicate checks; bool is true if automatic
     let mut script_ids = IndexMap::new();
     for item in &ast.items {
         match &item.value {
-            &ast::Item::AnmScript { number, ref ident, .. } => {
-                let script_id = number.unwrap_or(sp!(ident.span => next_auto_script));
-                next_auto_script = script_id.value + 1;
+            | &ast::Item::AnmScript { number, ref ident, .. }
+            | &ast::Item::AnmScriptAlias { number, ref ident, .. }
+            => {
+                let script_id = match number {
+                    Some(number) => number,
+                    None => {
+                        let script_id = sp!(ident.span => max_script_id.map_or(0, |max| max + 1));
+                        if let Some(prev) = prev_script_id {
+                            if script_id.value != prev.value + 1 && !first_use_by_id.contains_key(&(prev.value + 1)) {
+                                ctx.emitter.emit(warning!(
+                                    message("script '{}' automatically numbered {} instead of {}", ident, script_id, prev.value + 1),
+                                    primary(ident, "automatically numbered {}", script_id),
+                                    secondary(prev, "previous script is numbered {}", prev.value),
+                                    note("unnumbered scripts come after the highest number used so far; add an explicit number to fill the gap"),
+                                )).ignore();
+                            }
+                        }
+                        script_id
+                    },
+                };
+                prev_script_id = Some(script_id);
+                max_script_id = Some(max_script_id.map_or(script_id.value, |max| max.max(script_id.value)));
+
+                match first_use_by_id.entry(script_id.value) {
+                    std::collections::btree_map::Entry::Vacant(e) => { e.insert((script_id, number.is_none())); },
+                    std::collections::btree_map::Entry::Occupied(e) => {
+                        let &(prev_use, prev_is_auto) = e.get();
+                        errors.set(ctx.emitter.emit(error!(
+                            message("duplicate script number {}", script_id),
+                            primary(script_id, "redefined here"),
+                            secondary(prev_use, "{}", match prev_is_auto {
+                                true => "automatically numbered here",
+                                false => "previously used here",
+                            }),
+                        )));
+                    },
+                }
 
                 // give a better error on redefinitions than the generic "ambiguous auto const" message
                 match script_ids.entry(ident.value.clone()) {
@@ -1158,7 +1653,76 @@ fn gather_script_ids(ast: &ast::ScriptFile, ctx: &mut CompilerContext) -> Result
             _ => {},
         }
     }
-    Ok(script_ids)
+    errors.into_result(script_ids)
+}
+
+/// Warns about scripts and sprites that no instruction in the file refers to, and about entries with no sprites.
+///
+/// Scripts and sprites are often used by other files or by the game itself, so this is only done when
+/// [`CompilerContext::warn_unreferenced_anm_items`] is set.
+fn lint_unreferenced_items(
+    ast: &ast::ScriptFile,
+    script_ids: &IndexMap<Ident, (Sp<ResIdent>, Sp<i32>)>,
+    sprite_consts: &[(Sp<ResIdent>, ConstId)],
+    ctx: &CompilerContext,
+) {
+    struct Visitor<'a, 'ctx> {
+        ctx: &'a CompilerContext<'ctx>,
+        used_sprites: BTreeSet<i32>,
+        used_script_indices: BTreeSet<i32>,
+    }
+
+    impl ast::Visit for Visitor<'_, '_> {
+        fn visit_call(&mut self, call: &ast::ExprCall) {
+            if let Ok(siggy) = self.ctx.func_signature_from_ast(&call.name) {
+                for (param, arg) in siggy.match_params_to_args(&call.args).positional_pairs {
+                    let used = match param.ty_color.as_ref().map(|color| &color.value) {
+                        Some(TypeColor::Enum(name)) if name == &auto_enum_names::anm_sprite() => &mut self.used_sprites,
+                        Some(TypeColor::Enum(name)) if name == &auto_enum_names::anm_script() => &mut self.used_script_indices,
+                        _ => continue,
+                    };
+                    if let ast::Expr::LitInt { value, .. } = arg.value {
+                        used.insert(value);
+                    }
+                }
+            }
+            ast::walk_call(self, call);
+        }
+    }
+
+    let mut visitor = Visitor { ctx, used_sprites: BTreeSet::new(), used_script_indices: BTreeSet::new() };
+    ast::Visit::visit_file(&mut visitor, ast);
+
+    for (index, (script_name, _)) in script_ids.values().enumerate() {
+        if !visitor.used_script_indices.contains(&(index as i32)) {
+            ctx.emitter.emit(warning!(
+                message("script '{}' is not used by any instruction in this file", script_name),
+                primary(script_name, "unreferenced script"),
+            )).ignore();
+        }
+    }
+
+    for (sprite_name, const_id) in sprite_consts {
+        let id = ctx.consts.get_cached_value(*const_id).expect("sprite ids were evaluated").expect_int();
+        if !visitor.used_sprites.contains(&id) {
+            ctx.emitter.emit(warning!(
+                message("sprite '{}' is not used by any instruction in this file", sprite_name),
+                primary(sprite_name, "unreferenced sprite"),
+            )).ignore();
+        }
+    }
+
+    for item in &ast.items {
+        if let ast::Item::Meta { keyword: keyword @ sp_pat!(ast::MetaKeyword::Entry), fields } = &item.value {
+            let sprites = meta::ParseObject::new(fields).expect_field::<IndexMap<Sp<Ident>, ProtoSprite>>("sprites");
+            if matches!(sprites, Ok(sprites) if sprites.is_empty()) {
+                ctx.emitter.emit(warning!(
+                    message("entry has no sprites"),
+                    primary(keyword, "entry without sprites"),
+                )).ignore();
+            }
+        }
+    }
 }
 
 fn strip_unnecessary_sprite_ids<'a>(entry_sprites: impl IntoIterator<Item=&'a mut IndexMap<Sp<Ident>, Sprite>>) {
@@ -1200,14 +1764,14 @@ impl Version {
             Th08 | Th09 => Version::V3,
             Th095 | Th10 | Alcostg => Version::V4,
             Th11 | Th12 | Th125 | Th128 => Version::V7,
-            Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 => Version::V8,
+            Th13 | Th14 | Th143 | Th15 | Th16 | Th165 | Th17 | Th18 | Th19 => Version::V8,
         }
     }
 
     fn is_old_header(self) -> bool { self < Version::V7 }
 }
 
-fn game_hooks(game: Game) -> Box<dyn LanguageHooks> {
+pub(crate) fn game_hooks(game: Game) -> Box<dyn LanguageHooks> {
     let version = Version::from_game(game);
     let instr_format = read_write::get_instr_format(version);
     match version {
@@ -1230,6 +1794,8 @@ impl LanguageHooks for AnmHooks06 {
         opcode == 0 || opcode == 15
     }
 
+    fn requires_th06_anm_terminating_instr(&self) -> bool { true }
+
     fn instr_format(&self) -> &dyn InstrFormat { &*self.instr_format }
 }
 
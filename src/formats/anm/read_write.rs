@@ -1,12 +1,14 @@
 use std::num::NonZeroU64;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use indexmap::{IndexSet, IndexMap};
 
-use crate::io::{BinRead, BinWrite, BinReader, BinWriter, Encoded, ReadResult, WriteResult, DEFAULT_ENCODING};
+use crate::io::{BinRead, BinWrite, BinReader, BinWriter, Encoded, ReadResult, WriteResult, DEFAULT_ENCODING, fit_field};
 use crate::diagnostic::{Emitter};
 use crate::error::{ErrorReported};
 use crate::game::{Game};
+use crate::ident::{Ident};
+use crate::pos::{Sp};
 use crate::image::ColorFormat;
 use crate::llir::{self, ReadInstr, RawInstr, InstrFormat};
 
@@ -30,6 +32,31 @@ struct EntryHeaderData {
     has_data: u32,
     low_res_scale: u32,
     next_offset: u64,
+    /// Nonzero values of fields of unknown purpose, keyed by byte offset. (see [`unknown_header_fields`])
+    unknowns: BTreeMap<u32, u32>,
+}
+
+/// Meta keys for header fields of unknown purpose, by their byte offset in the entry header of any version.
+pub(super) static UNKNOWN_HEADER_FIELD_NAMES: &[(&str, u32)] = &[
+    ("unknown_0x08", 0x08), ("unknown_0x20", 0x20), ("unknown_0x28", 0x28), ("unknown_0x2c", 0x2c),
+    ("unknown_0x30", 0x30), ("unknown_0x34", 0x34), ("unknown_0x36", 0x36), ("unknown_0x38", 0x38),
+    ("unknown_0x3c", 0x3c),
+];
+
+pub(super) fn unknown_header_field_name(offset: u32) -> &'static str {
+    UNKNOWN_HEADER_FIELD_NAMES.iter().find(|&&(_, x)| x == offset).expect("not an unknown header field").0
+}
+
+/// Header fields of unknown purpose in a given version, as `(byte offset, size in bytes)`.
+///
+/// These are read and written verbatim so that files from new games can round-trip before anything is
+/// known about them.
+pub(super) fn unknown_header_fields(version: Version) -> &'static [(u32, u32)] {
+    if version.is_old_header() {
+        &[(0x08, 4), (0x20, 4), (0x36, 2), (0x3c, 4)]
+    } else {
+        &[(0x08, 2), (0x28, 4), (0x2c, 4), (0x30, 4), (0x34, 4), (0x38, 4), (0x3c, 4)]
+    }
 }
 
 pub fn read_anm(
@@ -37,6 +64,7 @@ pub fn read_anm(
     emitter: &impl Emitter,
     game: Game,
     with_images: bool,
+    lenient: bool,
 ) -> ReadResult<AnmFile> {
     let format = FileFormat::from_game(game);
 
@@ -44,7 +72,7 @@ pub fn read_anm(
     let mut next_script_index = 0;
     let mut entry_positions = Default::default();
     loop {
-        let (entry, control_flow) = read_entry(reader, emitter, &format, with_images, &mut entry_positions, &mut next_script_index)?;
+        let (entry, control_flow) = read_entry(reader, emitter, &format, with_images, lenient, &mut entry_positions, &mut next_script_index)?;
         entries.push(entry);
         match control_flow {
             ControlFlow::Continue => {},
@@ -63,6 +91,7 @@ fn read_entry(
     emitter: &impl Emitter,
     format: &FileFormat,
     with_images: bool,
+    lenient: bool,
     entry_positions: &mut HashSet<crate::raw::BytePos>,
     next_script_index: &mut u32,
 ) -> ReadResult<(Entry, ControlFlow)> {
@@ -76,7 +105,7 @@ fn read_entry(
 
     // 64 byte header regardless of version
     let header_data = emitter.chain_with(|f| write!(f, "in header"), |emitter| {
-        let header_data = format.read_header(reader, emitter)?;
+        let header_data = format.read_header(reader)?;
         if header_data.has_data != header_data.has_data % 2 {
             emitter.emit(warning!("non-boolean value found for 'has_data': {}", header_data.has_data)).ignore();
         }
@@ -90,6 +119,7 @@ fn read_entry(
     let script_ids_and_offsets = (0..header_data.num_scripts).map(|_| {
         Ok((reader.read_i32()?, reader.read_u32()? as u64))
     }).collect::<ReadResult<Vec<_>>>()?;
+    let header_end = reader.pos()? - entry_pos;
     // eprintln!("{:?}", header_data);
     // eprintln!("{:?}", sprite_offsets);
     // eprintln!("{:?}", script_ids_and_offsets);
@@ -127,11 +157,36 @@ fn read_entry(
     all_offsets.extend(sprite_offsets.iter().map(|&offset| offset as u64));
     all_offsets.extend(script_ids_and_offsets.iter().map(|&(_, offset)| offset as u64));
 
+    let bad_script_offsets = find_bad_script_offsets(
+        reader, format, entry_pos, header_end, &script_ids_and_offsets, *next_script_index, &all_offsets,
+    )?;
+    // nothing begins at these, so the scripts around them don't end there
+    all_offsets.retain(|offset| !bad_script_offsets.contains_key(offset));
+
+    let mut scripts_by_offset = HashMap::<u64, (Sp<Ident>, Vec<RawInstr>)>::new();
     let scripts = script_ids_and_offsets.iter().map(|&(id, offset)| {
         let script_index = *next_script_index;
         let key = sp!(super::auto_script_name(script_index));
         *next_script_index += 1;
 
+        if let Some(where_) = bad_script_offsets.get(&offset) {
+            let problem = format!("script {} begins at offset {:#X}, {}", script_index, entry_pos + offset, where_);
+            if !lenient {
+                return Err(emitter.emit(error!(
+                    message("{problem}"),
+                    note("the file may be truncated or corrupted; --lenient will decompile the rest of it"),
+                )));
+            }
+            emitter.emit(warning!("{problem}; it will be decompiled as an empty script")).ignore();
+            return Ok((key, Script { id, instrs: vec![], alias_of: None, corrupt: true }));
+        }
+
+        // several table entries may point to the same body
+        if let Some((first_key, instrs)) = scripts_by_offset.get(&offset) {
+            let script = Script { id, instrs: instrs.clone(), alias_of: Some(first_key.clone()), corrupt: false };
+            return Ok((key, script));
+        }
+
         let end_offset = all_offsets.iter().copied().filter(|&x| x > offset).min();
 
         let instrs = {
@@ -140,7 +195,8 @@ fn read_entry(
                 llir::read_instrs(reader, emitter, instr_format, offset, end_offset)
             })?
         };
-        Ok((key, Script { id, instrs }))
+        scripts_by_offset.insert(offset, (key.clone(), instrs.clone()));
+        Ok((key, Script { id, instrs, alias_of: None, corrupt: false }))
     }).collect::<ReadResult<IndexMap<_, _>>>()?;
 
     let expect_no_texture = header_data.has_data == 0 || path.starts_with("@");
@@ -164,6 +220,7 @@ fn read_entry(
         offset_x: header_data.offset_x, offset_y: header_data.offset_y,
         memory_priority: header_data.memory_priority,
         low_res_scale: header_data.low_res_scale != 0,
+        unknown_header_fields: header_data.unknowns,
     };
 
     let entry = Entry {
@@ -192,21 +249,22 @@ pub fn write_anm(
 ) -> WriteResult {
     let format = FileFormat::from_game(game);
 
-    let mut last_entry_pos = None;
     let mut next_auto_sprite_id = 0;
     for (entry_index, entry) in file.entries.iter().enumerate() {
         let entry_pos = w.pos()?;
-        if let Some(last_entry_pos) = last_entry_pos {
-            w.seek_to(last_entry_pos + format.offset_to_next_offset())?;
-            w.write_u32((entry_pos - last_entry_pos) as _)?;
-            w.seek_to(entry_pos)?;
-        }
-
         emitter.chain_with(|f| write!(f, "in entry {} (for '{}')", entry_index, entry.path), |emitter| {
-            write_entry(w, emitter, &format, entry, &mut next_auto_sprite_id)
+            write_entry(w, emitter, &format, entry, &mut next_auto_sprite_id)?;
+
+            // the next entry begins right after this one
+            if entry_index + 1 < file.entries.len() {
+                let next_entry_pos = w.pos()?;
+                let next_offset = fit_field::<u32, _>(emitter, "next_offset", next_entry_pos - entry_pos)?;
+                w.seek_to(entry_pos + format.offset_to_next_offset())?;
+                w.write_u32(next_offset)?;
+                w.seek_to(next_entry_pos)?;
+            }
+            Ok(())
         })?;
-
-        last_entry_pos = Some(entry_pos);
     }
     Ok(())
 }
@@ -226,18 +284,19 @@ fn write_entry(
     let EntrySpecs {
         rt_width, rt_height, rt_format,
         colorkey, offset_x, offset_y, memory_priority,
-        low_res_scale,
+        low_res_scale, ref unknown_header_fields,
     } = entry.specs;
 
-    file_format.write_header(w, &EntryHeaderData {
+    file_format.write_header(w, emitter, &EntryHeaderData {
         rt_width, rt_height, rt_format, colorkey,
         offset_x, offset_y,
         memory_priority,
         low_res_scale: low_res_scale as u32,
         has_data: entry.texture_data.is_some() as u32,
         version: file_format.version as u32,
-        num_sprites: entry.sprites.len() as u32,
-        num_scripts: entry.scripts.len() as u32,
+        num_sprites: fit_field(emitter, "num_sprites", entry.sprites.len())?,
+        num_scripts: fit_field(emitter, "num_scripts", entry.scripts.len())?,
+        unknowns: unknown_header_fields.clone(),
         // we will overwrite these later
         name_offset: 0, secondary_name_offset: None,
         next_offset: 0, thtx_offset: None,
@@ -264,23 +323,37 @@ fn write_entry(
         *next_auto_sprite_id = sprite_id + 1;
 
         write_sprite(w, sprite_id, sprite)?;
-        Ok(sprite_offset)
+        fit_field::<u32, _>(emitter, "sprite offset", sprite_offset)
     }).collect::<WriteResult<Vec<_>>>()?;
 
+    // aliases are left out here and point to the body of their target in the table
+    let mut body_offsets = HashMap::new();
+    for (name, script) in &entry.scripts {
+        if script.alias_of.is_none() {
+            let script_offset = w.pos()? - entry_pos;
+            let script_offset = emitter.chain_with(|f| write!(f, "in script {} (id {})", name, script.id), |emitter| {
+                llir::write_instrs(w, emitter, instr_format, &script.instrs)?;
+                fit_field::<u32, _>(emitter, "script offset", script_offset)
+            })?;
+            body_offsets.insert(&name.value, script_offset);
+        }
+    }
     let script_ids_and_offsets = entry.scripts.iter().map(|(name, script)| {
-        let script_offset = w.pos()? - entry_pos;
-        emitter.chain_with(|f| write!(f, "in script {} (id {})", name, script.id), |emitter| {
-            llir::write_instrs(w, emitter, instr_format, &script.instrs)
-        })?;
-
-        Ok((script.id, script_offset))
+        let body_name = script.alias_of.as_ref().unwrap_or(name);
+        match body_offsets.get(&body_name.value) {
+            Some(&script_offset) => Ok((script.id, script_offset)),
+            None => Err(emitter.emit(error!(
+                message("script '{}' is an alias of '{}', which is not a script in the same entry", name, body_name),
+                primary(name, "alias"),
+            ))),
+        }
     }).collect::<WriteResult<Vec<_>>>()?;
 
     let mut texture_offset = 0;
     if let Some(texture_data) = &entry.texture_data {
         let texture_metadata = entry.texture_metadata.as_ref().expect("always Some if texture_data is");
         texture_offset = w.pos()? - entry_pos;
-        write_texture(w, texture_data, texture_metadata)?;
+        write_texture(w, emitter, texture_data, texture_metadata)?;
     };
 
     let end_pos = w.pos()?;
@@ -300,25 +373,25 @@ fn write_entry(
     }
 
     w.seek_to(entry_pos + file_format.offset_to_thtx_offset())?;
-    w.write_u32(texture_offset as _)?;
+    w.write_u32(fit_field(emitter, "thtx_offset", texture_offset)?)?;
 
     w.seek_to(entry_pos + file_format.offset_to_path_offset())?;
-    w.write_u32(path_offset as _)?;
+    w.write_u32(fit_field(emitter, "name_offset", path_offset)?)?;
 
     if let Some(offset_to_path_2_offset) = file_format.offset_to_path_2_offset() {
         w.seek_to(entry_pos + offset_to_path_2_offset)?;
-        w.write_u32(path_2_offset as _)?;
+        w.write_u32(fit_field(emitter, "secondary_name_offset", path_2_offset)?)?;
     }
 
     w.seek_to(sprite_offsets_pos)?;
     for sprite_offset in sprite_offsets {
-        w.write_u32(sprite_offset as _)?;
+        w.write_u32(sprite_offset)?;
     }
 
     w.seek_to(script_headers_pos)?;
     for (script_id, script_offset) in script_ids_and_offsets {
         w.write_u32(script_id as _)?;
-        w.write_u32(script_offset as _)?;
+        w.write_u32(script_offset)?;
     }
 
     w.seek_to(end_pos)?;
@@ -374,15 +447,15 @@ fn read_texture(f: &mut BinReader, emitter: &impl Emitter, with_images: bool) ->
 }
 
 #[inline(never)]
-fn write_texture(f: &mut BinWriter, data: &TextureData, metadata: &TextureMetadata) -> WriteResult {
+fn write_texture(f: &mut BinWriter, emitter: &impl Emitter, data: &TextureData, metadata: &TextureMetadata) -> WriteResult {
     f.write_all(b"THTX")?;
 
     f.write_u16(0)?;
-    f.write_u16(metadata.format as _)?;
-    f.write_u16(metadata.width as _)?;
-    f.write_u16(metadata.height as _)?;
+    f.write_u16(fit_field(emitter, "img_format", metadata.format)?)?;
+    f.write_u16(fit_field(emitter, "img_width", metadata.width)?)?;
+    f.write_u16(fit_field(emitter, "img_height", metadata.height)?)?;
 
-    f.write_u32(data.data.len() as _)?;
+    f.write_u32(fit_field(emitter, "image data size", data.data.len())?)?;
     f.write_all(&data.data)?;
     Ok(())
 }
@@ -403,14 +476,13 @@ impl FileFormat {
         FileFormat { version, instr_format }
     }
 
-    fn read_header(&self, f: &mut BinReader, emitter: &dyn Emitter) -> ReadResult<EntryHeaderData> {
-        let emitter = emitter.as_sized();
-
-        macro_rules! warn_if_nonzero {
-            ($name:literal, $expr:expr) => {
+    fn read_header(&self, f: &mut BinReader) -> ReadResult<EntryHeaderData> {
+        let mut unknowns = BTreeMap::new();
+        macro_rules! read_unknown {
+            ($offset:literal, $expr:expr) => {
                 match $expr {
                     0 => {},
-                    x => emitter.emit(warning!("nonzero {} will be lost (value: {})", $name, x)).ignore(),
+                    x => { unknowns.insert($offset, x as u32); },
                 }
             };
         }
@@ -419,21 +491,21 @@ impl FileFormat {
             // old format
             let num_sprites = f.read_u32()? as _;
             let num_scripts = f.read_u32()? as _;
-            warn_if_nonzero!("rt_textureslot", f.read_u32()?);
+            read_unknown!(0x08, f.read_u32()?);  // rt_textureslot?
             let width = f.read_u32()? as _;
             let height = f.read_u32()? as _;
             let format = f.read_u32()? as _;
             let colorkey = f.read_u32()? as _;
             let name_offset = f.read_u32()? as _;
-            warn_if_nonzero!("unused_1", f.read_u32()?);
+            read_unknown!(0x20, f.read_u32()?);
             let secondary_name_offset = NonZeroU64::new(f.read_u32()? as _);
             let version = f.read_u32()? as _;
             let memory_priority = f.read_u32()? as _;
             let thtx_offset = NonZeroU64::new(f.read_u32()? as _) as _;
             let has_data = f.read_u16()? as _;
-            warn_if_nonzero!("unused_2", f.read_u16()?);
+            read_unknown!(0x36, f.read_u16()?);
             let next_offset = f.read_u32()? as _;
-            warn_if_nonzero!("unused_3", f.read_u32()?);
+            read_unknown!(0x3c, f.read_u32()?);
 
             Ok(EntryHeaderData {
                 version, num_sprites, num_scripts,
@@ -441,7 +513,7 @@ impl FileFormat {
                 rt_height: height,
                 rt_format: format, name_offset,
                 next_offset, secondary_name_offset, colorkey,
-                memory_priority, thtx_offset, has_data,
+                memory_priority, thtx_offset, has_data, unknowns,
                 offset_x: 0, offset_y: 0, low_res_scale: 0,
             })
 
@@ -450,7 +522,7 @@ impl FileFormat {
             let version = f.read_u32()? as _;
             let num_sprites = f.read_u16()? as _;
             let num_scripts = f.read_u16()? as _;
-            warn_if_nonzero!("rt_textureslot", f.read_u16()?);
+            read_unknown!(0x08, f.read_u16()?);  // rt_textureslot?
             let width = f.read_u16()? as _;
             let height = f.read_u16()? as _;
             let format = f.read_u16()? as _;
@@ -463,61 +535,66 @@ impl FileFormat {
             let low_res_scale = f.read_u16()? as _;
             let next_offset = f.read_u32()? as _;
 
-            for _ in 0..6 {  // header gets padded to 16 dwords
-            warn_if_nonzero!("header padding", f.read_u32()?);
-            }
+            // header gets padded to 16 dwords
+            read_unknown!(0x28, f.read_u32()?);
+            read_unknown!(0x2c, f.read_u32()?);
+            read_unknown!(0x30, f.read_u32()?);
+            read_unknown!(0x34, f.read_u32()?);
+            read_unknown!(0x38, f.read_u32()?);
+            read_unknown!(0x3c, f.read_u32()?);
             Ok(EntryHeaderData {
                 version, num_sprites, num_scripts,
                 rt_width: width,
                 rt_height: height,
                 rt_format: format, name_offset,
                 offset_x, offset_y, low_res_scale, next_offset,
-                memory_priority, thtx_offset, has_data,
+                memory_priority, thtx_offset, has_data, unknowns,
                 secondary_name_offset: None,
                 colorkey: 0,
             })
         }
     }
 
-    fn write_header(&self, f: &mut BinWriter, header: &EntryHeaderData) -> WriteResult {
+    fn write_header(&self, f: &mut BinWriter, emitter: &impl Emitter, header: &EntryHeaderData) -> WriteResult {
+        let unknown = |offset: u32| header.unknowns.get(&offset).copied().unwrap_or(0);
         if self.version.is_old_header() {
             // old format
             f.write_u32(header.num_sprites as _)?;
             f.write_u32(header.num_scripts as _)?;
-            f.write_u32(0)?;
+            f.write_u32(unknown(0x08))?;
             f.write_u32(header.rt_width as _)?;
             f.write_u32(header.rt_height as _)?;
             f.write_u32(header.rt_format as _)?;
             f.write_u32(header.colorkey as _)?;
             f.write_u32(header.name_offset as _)?;
-            f.write_u32(0)?;
+            f.write_u32(unknown(0x20))?;
             f.write_u32(header.secondary_name_offset.map(NonZeroU64::get).unwrap_or(0) as _)?;
             f.write_u32(header.version)?;
             f.write_u32(header.memory_priority)?;
             f.write_u32(header.thtx_offset.map(NonZeroU64::get).unwrap_or(0) as _)?;
             f.write_u16(header.has_data as _)?;
-            f.write_u16(0)?;
+            f.write_u16(unknown(0x36) as _)?;
             f.write_u32(header.next_offset as _)?;
-            f.write_u32(0)?;
+            f.write_u32(unknown(0x3c))?;
 
         } else {
             // new format
             f.write_u32(header.version as _)?;
-            f.write_u16(header.num_sprites as _)?;
-            f.write_u16(header.num_scripts as _)?;
-            f.write_u16(0)?;
-            f.write_u16(header.rt_width as _)?;
-            f.write_u16(header.rt_height as _)?;
-            f.write_u16(header.rt_format as _)?;
+            f.write_u16(fit_field(emitter, "num_sprites", header.num_sprites)?)?;
+            f.write_u16(fit_field(emitter, "num_scripts", header.num_scripts)?)?;
+            f.write_u16(unknown(0x08) as _)?;
+            f.write_u16(fit_field(emitter, "rt_width", header.rt_width)?)?;
+            f.write_u16(fit_field(emitter, "rt_height", header.rt_height)?)?;
+            f.write_u16(fit_field(emitter, "rt_format", header.rt_format)?)?;
             f.write_u32(header.name_offset as _)?;
-            f.write_u16(header.offset_x as _)?;
-            f.write_u16(header.offset_y as _)?;
+            f.write_u16(fit_field(emitter, "offset_x", header.offset_x)?)?;
+            f.write_u16(fit_field(emitter, "offset_y", header.offset_y)?)?;
             f.write_u32(header.memory_priority as _)?;
             f.write_u32(header.thtx_offset.map(NonZeroU64::get).unwrap_or(0) as _)?;
             f.write_u16(header.has_data as _)?;
             f.write_u16(header.low_res_scale as _)?;
             f.write_u32(header.next_offset as _)?;
-            f.write_u32s(&[0; 6])?;
+            f.write_u32s(&[0x28, 0x2c, 0x30, 0x34, 0x38, 0x3c].map(unknown))?;
         }
         Ok(())
     }
@@ -543,6 +620,76 @@ impl FileFormat {
     }
 }
 
+/// Finds the offsets in an entry's script table at which no script can begin, each with a description of
+/// where it points instead.
+///
+/// Besides offsets past the end of the file or inside the header, this walks the instructions of each script
+/// to find offsets that land in the middle of one of them.
+fn find_bad_script_offsets(
+    reader: &mut BinReader,
+    format: &FileFormat,
+    entry_pos: u64,
+    header_end: u64,
+    script_ids_and_offsets: &[(i32, u64)],
+    first_script_index: u32,
+    all_offsets: &[u64],
+) -> ReadResult<HashMap<u64, String>> {
+    let file_len = reader.stream_len()?;
+    let mut bad_offsets = HashMap::new();
+    for &(_, offset) in script_ids_and_offsets {
+        if entry_pos + offset >= file_len {
+            bad_offsets.insert(offset, "past the end of the file".to_string());
+        } else if offset < header_end {
+            bad_offsets.insert(offset, "inside the entry header".to_string());
+        }
+    }
+
+    // the first script index for each offset, in order of offset
+    let mut script_starts = BTreeMap::new();
+    for (table_index, &(_, offset)) in script_ids_and_offsets.iter().enumerate() {
+        if !bad_offsets.contains_key(&offset) {
+            script_starts.entry(offset).or_insert(first_script_index + table_index as u32);
+        }
+    }
+
+    let header_size = format.instr_format.instr_header_size() as u64;
+    for (&start, &script_index) in &script_starts {
+        if bad_offsets.contains_key(&start) {
+            continue;  // a script before this one already runs through it
+        }
+        // Only the header of each instruction is read, and never past the end of the file, because anything
+        // that goes wrong here is reported more precisely when the script is actually read.
+        let mut pos = start;
+        while entry_pos + pos + header_size <= file_len {
+            reader.seek_to(entry_pos + pos)?;
+            let instr_size = match format.version {
+                Version::V0 => match (reader.read_i16()?, reader.read_i8()?, reader.read_u8()?) {
+                    (0, 0, 0) => break,  // end marker
+                    (_, _, args_size) => header_size + args_size as u64,
+                },
+                _ => match (reader.read_i16()?, reader.read_u16()?) {
+                    (-1, _) => break,  // end marker
+                    (_, size) if (size as u64) < header_size => break,
+                    (_, size) => size as u64,
+                },
+            };
+            let next_pos = pos + instr_size;
+            if entry_pos + next_pos > file_len {
+                break;
+            }
+            for (&other, _) in script_starts.range(pos + 1..next_pos) {
+                bad_offsets.entry(other).or_insert_with(|| format!("inside script {}", script_index));
+            }
+            pos = next_pos;
+            // StB scripts have no end marker, so also stop wherever something else begins
+            if all_offsets.contains(&pos) {
+                break;
+            }
+        }
+    }
+    Ok(bad_offsets)
+}
+
 pub(super) fn get_instr_format(version: Version) -> Box<dyn InstrFormat> {
     match version {
         Version::V0 => Box::new(InstrFormat06),
@@ -553,6 +700,10 @@ pub(super) fn get_instr_format(version: Version) -> Box<dyn InstrFormat> {
 impl InstrFormat for InstrFormat06 {
     fn instr_header_size(&self) -> usize { 4 }
 
+    fn args_blob_size(&self) -> llir::ArgsBlobSize { llir::ArgsBlobSize::MultipleOf(1) }
+
+    fn max_args_blob_len(&self) -> usize { u8::MAX as usize }
+
     fn read_instr(&self, f: &mut BinReader, _: &dyn Emitter) -> ReadResult<ReadInstr> {
         let time = match f.read_i16_or_eof() {
             Ok(Some(time)) => time as i32,
@@ -596,7 +747,7 @@ impl InstrFormat for InstrFormat07 {
         }
 
         let time = f.read_i16()? as _;
-        let param_mask = f.read_u16()?;
+        let param_mask = f.read_u16()? as _;
         let args_size = size.checked_sub(self.instr_header_size()).ok_or_else(|| {
             emitter.as_sized().emit(error!("bad instruction size ({} < {})", size, self.instr_header_size()))
         })?;
@@ -9,22 +9,28 @@ use crate::pos::{Sp, Span};
 use crate::io::{BinRead, BinWrite, BinReader, BinWriter, ReadResult, WriteResult};
 use crate::diagnostic::{Diagnostic, Emitter};
 use crate::error::{ErrorReported, ErrorFlag, GatherErrorIteratorExt};
-use crate::game::{Game, LanguageKey};
+use crate::game::{Game, LanguageKey, Limit};
 use crate::ident::{Ident, ResIdent};
 use crate::value::{ScalarType, ScalarValue, ReadType, VarType};
-use crate::llir::{self, ReadInstr, RawInstr, InstrFormat, LanguageHooks, DecompileOptions, RegisterEncodingStyle, HowBadIsIt};
+use crate::llir::{self, ReadInstr, RawInstr, InstrFormat, LanguageHooks, DecompileOptions, RegisterEncodingStyle, HowBadIsIt, LabelEncoding};
 use crate::resolve::{RegId, DefId, IdMap};
 use crate::context::CompilerContext;
 use crate::context::defs::auto_enum_names;
 use crate::debug_info;
 
+mod ecl_10;
+
 // =============================================================================
 
-/// Game-independent representation of an ANM file.
+/// Game-independent representation of an ECL file.
 #[derive(Debug, Clone)]
 pub struct OldeEclFile {
     pub subs: IndexMap<Ident, Vec<RawInstr>>,
     pub timelines: Vec<Vec<RawInstr>>,
+    /// ANM files included by the file.  Only in modern (TH10+) ECL.
+    pub anim_list: Vec<Sp<String>>,
+    /// ECL files included by the file.  Only in modern (TH10+) ECL.
+    pub ecli_list: Vec<Sp<String>>,
     /// Filename of a read binary file, for display purposes only.
     binary_filename: Option<String>,
 }
@@ -32,21 +38,33 @@ pub struct OldeEclFile {
 impl OldeEclFile {
     pub fn decompile_to_ast(&self, game: Game, ctx: &mut CompilerContext, decompile_options: &DecompileOptions) -> Result<ast::ScriptFile, ErrorReported> {
         let emitter = ctx.emitter.while_decompiling(self.binary_filename.as_deref());
-        decompile(self, &emitter, &game_format(game)?, ctx, decompile_options)
+        match is_modern(game) {
+            true => ecl_10::decompile(self, &emitter, ctx, decompile_options),
+            false => decompile(self, &emitter, &game_format(game, &emitter)?, ctx, decompile_options),
+        }
     }
 
     pub fn compile_from_ast(game: Game, ast: &ast::ScriptFile, ctx: &mut CompilerContext) -> Result<Self, ErrorReported> {
-        compile(&game_format(game)?, ast, ctx)
+        match is_modern(game) {
+            true => ecl_10::compile(ast, ctx),
+            false => compile(&game_format(game, &ctx.emitter)?, ast, ctx),
+        }
     }
 
     pub fn write_to_stream(&self, w: &mut BinWriter, game: Game) -> WriteResult {
         let emitter = w.emitter();
-        write_olde_ecl(w, &emitter, &game_format(game)?, self)
+        match is_modern(game) {
+            true => ecl_10::write_modern_ecl(w, &emitter, self),
+            false => write_olde_ecl(w, &emitter, &game_format(game, &emitter)?, self),
+        }
     }
 
     pub fn read_from_stream(r: &mut BinReader, game: Game) -> ReadResult<Self> {
         let emitter = r.emitter();
-        read_olde_ecl(r, &emitter, &game_format(game)?)
+        match is_modern(game) {
+            true => ecl_10::read_modern_ecl(r, &emitter),
+            false => read_olde_ecl(r, &emitter, &game_format(game, &emitter)?),
+        }
     }
 }
 
@@ -54,7 +72,7 @@ impl OldeEclFile {
 
 fn decompile(
     ecl: &OldeEclFile,
-    emitter: &impl Emitter,
+    emitter: &(impl Emitter + Sync),
     format: &OldeFileFormat,
     ctx: &mut CompilerContext,
     decompile_options: &DecompileOptions,
@@ -72,41 +90,46 @@ fn decompile(
 
     // Generate timelines
     let mut items = vec![];
-    let mut timeline_raiser = llir::Raiser::new(timeline_hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
-    for (index, instrs) in ecl.timelines.iter().enumerate() {
+    let timeline_raiser = llir::Raiser::new(timeline_hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
+    let timeline_codes = ctx.map_in_parallel(&ecl.timelines, |instrs, job_emitter| {
+        timeline_raiser.raise_instrs_to_sub_ast(&emitter.with_root(job_emitter), instrs, ctx)
+    });
+    for (index, code) in timeline_codes.into_iter().enumerate() {
         items.push(sp!(ast::Item::Timeline {
             keyword: sp!(()),
             number: None,
             ident: Some(sp!(ident!("timeline{index}"))),
-            code: ast::Block({
-                timeline_raiser.raise_instrs_to_sub_ast(emitter, instrs, ctx)?
-            }),
+            code: ast::Block(code?),
         }));
     }
 
     let mut sub_raiser = llir::Raiser::new(ecl_hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
     sub_raiser.set_olde_sub_format(sub_format);
+    let subs = ecl.subs.iter().collect::<Vec<_>>();
 
     // Decompile ECL subs only halfway
     let mut sub_middles = IndexMap::new();
-    for (ident, instrs) in ecl.subs.iter() {
-        sub_middles.insert(ident.clone(), {
-            emitter.chain_with(|f| write!(f, "in {}", ident), |emitter| {
-                sub_raiser.raise_instrs_to_middle(emitter, instrs, ctx)
-            })?
-        });
+    let middles = ctx.map_in_parallel(&subs, |&(ident, instrs), job_emitter| {
+        emitter.with_root(job_emitter).chain_with(|f| write!(f, "in {}", ident), |emitter| {
+            sub_raiser.raise_instrs_to_middle(emitter, instrs, ctx)
+        })
+    });
+    for (&(ident, _), middle) in subs.iter().zip(middles) {
+        sub_middles.insert(ident.clone(), middle?);
     }
 
     // In this intermediate form we can easily deduce signatures of exported subs.
     let pcb_call_signatures = sub_raiser.infer_pcb_signatures_and_certify_calls(sub_middles.values_mut(), ctx);
 
     let mut decompiled_subs = IndexMap::new();
-    for (ident, middle) in sub_middles.iter() {
-        decompiled_subs.insert(ident.clone(), ast::Block({
-            emitter.chain_with(|f| write!(f, "in {}", ident), |emitter| {
-                sub_raiser.raise_middle_to_sub_ast(emitter, middle, ctx)
-            })?
-        }));
+    let middles = sub_middles.iter().collect::<Vec<_>>();
+    let codes = ctx.map_in_parallel(&middles, |&(ident, middle), job_emitter| {
+        emitter.with_root(job_emitter).chain_with(|f| write!(f, "in {}", ident), |emitter| {
+            sub_raiser.raise_middle_to_sub_ast(emitter, middle, ctx)
+        })
+    });
+    for (&(ident, _), code) in middles.iter().zip(codes) {
+        decompiled_subs.insert(ident.clone(), ast::Block(code?));
     }
 
     let param_infos = OldeRaiseSubs::from_subs(sub_format, decompiled_subs.iter().map(|(ident, stmts)| (ident, &stmts.0[..])), &pcb_call_signatures);
@@ -128,10 +151,13 @@ fn decompile(
 
     let mut out = ast::ScriptFile {
         items,
-        mapfiles: ctx.mapfiles_to_ast(),
+        mapfiles: vec![],  // filled in once we know what was used
         image_sources: vec![],
+        reg_pragmas: vec![],
+        comments: vec![],
     };
     crate::passes::postprocess_decompiled(&mut out, ctx, decompile_options)?;
+    out.mapfiles = ctx.used_mapfiles_to_ast(&decompile_options.mapfile_paths)?;
     Ok(out)
 }
 
@@ -146,12 +172,13 @@ fn compile(
 
     let mut ast = ast.clone();
     crate::passes::resolution::assign_languages(&mut ast, LanguageKey::Ecl, ctx)?;
-    crate::passes::resolution::compute_diff_label_masks(&mut ast, ctx)?;
+    ctx.observers.after_pass("assign_languages", &ast)?;
 
     // an early pass to define global constants for sub names
     //
     // (these become relevant when using ins_ syntax or instruction aliases, but not call sugar)
     let sub_ids = gather_sub_ids(&ast, ctx)?;
+    ctx.check_limit(format.game, Limit::EclSubs, sub_ids.values().map(|sub_name| sub_name.span))?;
     for (index, sub_name) in sub_ids.values().enumerate() {
         let const_value: Sp<ast::Expr> = sp!(sub_name.span => (index as i32).into());
         ctx.define_enum_const(sub_name.clone(), const_value, sp!(auto_enum_names::ecl_sub()));
@@ -162,6 +189,8 @@ fn compile(
     let ast = {
         let mut ast = ast;
         crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::validate_reg_sigils::run(&ast, ctx);
+        ctx.observers.after_resolution(&ast, &ctx.resolutions)?;
 
         // FIXME: Q: Heeeeey exp, why do you have to make another pass over all the exported functions
         //           when you already made a pass a couple of lines above to define global constants?
@@ -180,11 +209,20 @@ fn compile(
         // gather information about exported subs to use for handling call sugar.
         sub_info = OldeExportedSubs::extract_from_items(sub_format, format.game, &ast.items, ctx)?;
 
-        crate::passes::validate_difficulty::run(&ast, ctx, &*format.ecl_hooks)?;
         crate::passes::type_check::run(&ast, ctx)?;
+        crate::passes::float_equality::run(&ast, ctx)?;
         crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::resolution::compute_diff_label_masks(&mut ast, ctx)?;
+        ctx.observers.after_pass("compute_diff_label_masks", &ast)?;
+        crate::passes::validate_difficulty::run(&ast, ctx, &*format.ecl_hooks)?;
+        crate::passes::unreachable_code::run(&ast, ctx)?;
+        crate::passes::inline_funcs::run(&mut ast, ctx)?;
+        crate::passes::explode_diff_switches::run(&mut ast, ctx)?;
         crate::passes::const_simplify::run(&mut ast, ctx)?;
+        ctx.observers.after_pass("const_simplify", &ast)?;
+        crate::passes::unroll_times::run(&mut ast, ctx)?;
         crate::passes::desugar_blocks::run(&mut ast, ctx, format.ecl_hooks.language())?;
+        ctx.observers.after_pass("desugar_blocks", &ast)?;
         ast
     };
 
@@ -202,6 +240,23 @@ fn compile(
     let mut ecl_lowerer = llir::Lowerer::new(&*format.ecl_hooks).with_export_info(sub_format, &sub_info);
     let mut timeline_lowerer = llir::Lowerer::new(&*format.timeline_hooks);
 
+    // Lower all of the code up front, as this can be done in parallel
+    let mut timeline_jobs = vec![];
+    let mut sub_jobs = vec![];
+    for item in &ast.items {
+        match &item.value {
+            ast::Item::Timeline { code, .. } => timeline_jobs.push((&code.0[..], None)),
+            ast::Item::Func(ast::ItemFunc { qualifier: None, code: Some(code), ident, ty_keyword, .. }) => {
+                if ty_keyword.value == ast::TypeKeyword::Void {
+                    sub_jobs.push((&code.0[..], Some(ctx.resolutions.expect_def(ident))));
+                }
+            },
+            _ => {},
+        }
+    }
+    let mut lowered_timelines = timeline_lowerer.lower_subs(&timeline_jobs, ctx, do_debug_info).into_iter();
+    let mut lowered_subs = ecl_lowerer.lower_subs(&sub_jobs, ctx, do_debug_info).into_iter();
+
     ast.items.iter().map(|item| {
         // eprintln!("{:?}", item);
         match &item.value {
@@ -215,12 +270,16 @@ fn compile(
             ))),
             ast::Item::ConstVar { .. } => {},
             ast::Item::AnmScript { .. } => return Err(emit(unsupported(&item.span))),
+            ast::Item::AnmScriptAlias { .. } => return Err(emit(unsupported(&item.span))),
 
-            ast::Item::Timeline { code, ident, .. } => {
+            ast::Item::Timeline { ident, .. } => {
                 let timeline_index = timeline_indices_in_ast_order.next().unwrap();
 
-                let def_id = None;
-                let (instrs, lowering_info) = timeline_lowerer.lower_sub(&code.0, def_id, ctx, do_debug_info)?;
+                let (instrs, lowering_info) = lowered_timelines.next().unwrap()?;
+                match ident {
+                    Some(ident) => ctx.observers.after_lowering(ident.as_str(), &instrs)?,
+                    None => ctx.observers.after_lowering(&format!("timeline {timeline_index}"), &instrs)?,
+                }
 
                 assert!(compiled_timelines[timeline_index].is_none());
                 compiled_timelines[timeline_index] = Some(instrs);
@@ -243,7 +302,7 @@ fn compile(
                 )));
             },
 
-            ast::Item::Func(ast::ItemFunc { qualifier: None, code: Some(code), ref ident, params: _, ty_keyword }) => {
+            ast::Item::Func(ast::ItemFunc { qualifier: None, code: Some(_), ref ident, params: _, ty_keyword }) => {
                 let sub_index = subs.len();
 
                 // make double sure that the order of the subs we're compiling matches the numbers we assigned them
@@ -258,10 +317,11 @@ fn compile(
                     )));
                 }
 
-                let (instrs, lowering_info) = ecl_lowerer.lower_sub(&code.0, Some(def_id), ctx, do_debug_info).unwrap_or_else(|e| {
+                let (instrs, lowering_info) = lowered_subs.next().unwrap().unwrap_or_else(|e| {
                     errors.set(e);
                     (vec![], None)  // dummy instrs so that we can still insert an item into 'subs' and get the right indices
                 });
+                ctx.observers.after_lowering(ident.as_str(), &instrs).unwrap_or_else(|e| errors.set(e));
                 subs.insert(ident.value.as_raw().clone(), instrs);
 
                 if let Some(lowering_info) = lowering_info {
@@ -274,13 +334,18 @@ fn compile(
                 }
             }
 
-            // TODO: support inline and const
-            ast::Item::Func(ast::ItemFunc { qualifier: Some(_), .. }) => return Err(emit(unsupported(&item.span))),
+            // const funcs are only used at compile time
+            ast::Item::Func(ast::ItemFunc { qualifier: Some(sp_pat![token![const]]), .. }) => {},
+
+            // inline funcs were already expanded into their callers and removed
+            ast::Item::Func(ast::ItemFunc { qualifier: Some(_), .. }) => unreachable!("inline func survived inlining"),
         } // match item
         Ok(())
     }).collect_with_recovery().unwrap_or_else(|e| errors.set(e));
 
     assert_eq!(timeline_indices_in_ast_order.next(), None);
+    assert!(lowered_timelines.next().is_none());
+    assert!(lowered_subs.next().is_none());
 
     ecl_lowerer.finish(ctx).unwrap_or_else(|e| errors.set(e));
     timeline_lowerer.finish(ctx).unwrap_or_else(|e| errors.set(e));
@@ -290,6 +355,8 @@ fn compile(
         subs,
         // each index of compiled_timelines should have been set exactly once
         timelines: compiled_timelines.into_iter().map(|opt| opt.unwrap()).collect(),
+        anim_list: vec![],
+        ecli_list: vec![],
         binary_filename: None,
     })
 }
@@ -513,7 +580,7 @@ fn read_olde_ecl(
     }).collect::<ReadResult<_>>()?;
 
     let binary_filename = Some(reader.display_filename().to_string());
-    Ok(OldeEclFile { subs, timelines, binary_filename })
+    Ok(OldeEclFile { subs, timelines, anim_list: vec![], ecli_list: vec![], binary_filename })
 }
 
 fn auto_sub_name(i: u32) -> Ident {
@@ -615,7 +682,7 @@ fn game_sub_format(game: Game) -> Box<dyn OldeSubFormat> {
     }
 }
 
-pub trait OldeSubFormat {
+pub trait OldeSubFormat: Sync {
     fn param_reg_id(&self, ty: ReadType, number: usize) -> RegId;
 
     /// Info for [`IntrinsicInstrKind::CallReg`] for games that use it.
@@ -892,12 +959,27 @@ impl OldeRaiseSub {
 
 // =============================================================================
 
-fn game_format(game: Game) -> Result<OldeFileFormat, ErrorReported> {
+/// Get the hooks for ECL and timeline instructions in a game, for use by code outside this module.
+///
+/// Games with modern ECL have no timelines.
+pub(crate) fn game_hooks(game: Game, emitter: &impl Emitter) -> Result<(Box<dyn LanguageHooks>, Option<Box<dyn LanguageHooks>>), ErrorReported> {
+    if is_modern(game) {
+        return Ok((Box::new(ecl_10::ModernEclHooks), None));
+    }
+    let OldeFileFormat { ecl_hooks, timeline_hooks, .. } = game_format(game, emitter)?;
+    Ok((ecl_hooks, Some(timeline_hooks)))
+}
+
+fn is_modern(game: Game) -> bool {
+    game >= Game::Th10
+}
+
+fn game_format(game: Game, emitter: &impl Emitter) -> Result<OldeFileFormat, ErrorReported> {
     match game {
         | Game::Th06 | Game::Th07 | Game::Th08 | Game::Th09 | Game::Th095
         => Ok(OldeFileFormat::new(game)),
 
-        _ => unimplemented!("game {game} not yet supported"),
+        _ => Err(emitter.emit(error!("ECL files for {game} are not yet supported"))),
     }
 }
 
@@ -978,14 +1060,7 @@ impl LanguageHooks for OldeEclHooks {
     }
 
     // offsets are written as relative in these files
-    fn encode_label(&self, current_offset: raw::BytePos, dest_offset: raw::BytePos) -> raw::RawDwordBits {
-        let relative = dest_offset as i64 - current_offset as i64;
-        relative as i32 as u32
-    }
-    fn decode_label(&self, current_offset: raw::BytePos, bits: raw::RawDwordBits) -> raw::BytePos {
-        let relative = bits as i32 as i64; // double cast for sign-extension
-        (current_offset as i64 + relative) as u64
-    }
+    fn label_encoding(&self) -> LabelEncoding { LabelEncoding::RelativeByteOffset }
 
     fn register_style(&self) -> RegisterEncodingStyle {
         if self.game == Game::Th06 {
@@ -1085,7 +1160,7 @@ impl InstrFormat for OldeEclHooks {
         let size = f.read_u16()? as usize;
         let before_difficulty = f.read_u8()?;  // according to zero, not referenced in any game
         let difficulty = f.read_u8()?;
-        let param_mask = f.read_u16()?;
+        let param_mask = f.read_u16()? as raw::ParamMask;
 
         if before_difficulty != 0 {
             emitter.as_sized().emit(warning!(
@@ -1102,7 +1177,7 @@ impl InstrFormat for OldeEclHooks {
 
         let instr = RawInstr {
             time, opcode, args_blob,
-            param_mask: param_mask.into(), difficulty: difficulty.into(),
+            param_mask, difficulty: difficulty.into(),
             ..RawInstr::DEFAULTS
         };
 
@@ -1203,6 +1278,8 @@ impl InstrFormat for TimelineFormat06 {
 impl InstrFormat for TimelineFormat08 {
     fn instr_header_size(&self) -> usize { 8 }
 
+    fn max_args_blob_len(&self) -> usize { u8::MAX as usize - self.instr_header_size() }
+
     fn read_instr(&self, f: &mut BinReader, emitter: &dyn Emitter) -> ReadResult<ReadInstr> {
         let time = f.read_i32()? as i32;
         let opcode = f.read_u16()?;
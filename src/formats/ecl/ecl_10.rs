@@ -0,0 +1,596 @@
+//! Modern (TH10 and later) ECL files.
+//!
+//! These begin with an `SCPT` header and lists of the ANM and ECL files that the file includes,
+//! followed by a table of named subs.  There are no timelines.
+
+use indexmap::{IndexMap};
+use std::collections::HashMap;
+
+use crate::raw;
+use crate::ast::{self, meta};
+use crate::pos::{Sp, Span};
+use crate::io::{BinRead, BinWrite, BinReader, BinWriter, Encoded, ReadResult, WriteResult, DEFAULT_ENCODING, fit_field};
+use crate::diagnostic::{Diagnostic, Emitter};
+use crate::error::{ErrorReported, ErrorFlag, GatherErrorIteratorExt};
+use crate::game::LanguageKey;
+use crate::ident::{Ident, ResIdent};
+use crate::llir::{self, ReadInstr, RawInstr, InstrFormat, LanguageHooks, DecompileOptions, LabelEncoding};
+use crate::context::CompilerContext;
+use crate::context::defs::auto_enum_names;
+use crate::debug_info;
+
+use super::{OldeEclFile, auto_sub_name, gather_sub_ids};
+
+const HEADER_SIZE: usize = 0x24;
+/// Size of the header at the beginning of each sub, before its instructions.
+const SUB_HEADER_SIZE: u32 = 0x10;
+
+/// The `anim` and `ecli` lists from the `meta`.
+type IncludeLists = (Vec<Sp<String>>, Vec<Sp<String>>);
+
+// =============================================================================
+
+pub(super) fn decompile(
+    ecl: &OldeEclFile,
+    emitter: &(impl Emitter + Sync),
+    ctx: &mut CompilerContext,
+    decompile_options: &DecompileOptions,
+) -> Result<ast::ScriptFile, ErrorReported> {
+    let hooks = &ModernEclHooks;
+
+    for i in 0..ecl.subs.len() {
+        let name = ecl.subs.get_index(i).unwrap().0.clone();
+        ctx.define_enum_const_fresh(name, i as _, auto_enum_names::ecl_sub());
+    }
+
+    let const_proof = crate::passes::evaluate_const_vars::run(ctx)?;
+
+    let mut items = vec![sp!(ast::Item::Meta {
+        keyword: sp!(ast::MetaKeyword::Meta),
+        fields: sp!(make_meta(ecl)),
+    })];
+
+    let raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
+    let subs = ecl.subs.iter().collect::<Vec<_>>();
+    let codes = ctx.map_in_parallel(&subs, |&(ident, instrs), job_emitter| {
+        emitter.with_root(job_emitter).chain_with(|f| write!(f, "in {}", ident), |emitter| {
+            raiser.raise_instrs_to_sub_ast(emitter, instrs, ctx)
+        })
+    });
+    for (&(ident, _), code) in subs.iter().zip(codes) {
+        items.push(sp!(ast::Item::Func(ast::ItemFunc {
+            qualifier: None,
+            ty_keyword: sp!(ast::TypeKeyword::Void),
+            ident: sp!(ResIdent::new_null(ident.clone())),
+            params: vec![],
+            code: Some(ast::Block(code?)),
+        })));
+    }
+
+    let mut out = ast::ScriptFile {
+        items,
+        mapfiles: vec![],  // filled in once we know what was used
+        image_sources: vec![],
+        reg_pragmas: vec![],
+        comments: vec![],
+    };
+    crate::passes::postprocess_decompiled(&mut out, ctx, decompile_options)?;
+    out.mapfiles = ctx.used_mapfiles_to_ast(&decompile_options.mapfile_paths)?;
+    Ok(out)
+}
+
+fn make_meta(ecl: &OldeEclFile) -> meta::Fields {
+    let strings = |list: &[Sp<String>]| list.iter().map(|s| s.value.clone()).collect::<Vec<_>>();
+    meta::Meta::make_object()
+        .field("anim", &strings(&ecl.anim_list))
+        .field("ecli", &strings(&ecl.ecli_list))
+        .build_fields()
+}
+
+fn include_lists_from_meta(fields: &Sp<meta::Fields>) -> Result<IncludeLists, meta::FromMetaError<'_>> {
+    meta::ParseObject::scope(fields, |m| {
+        m.describe("ECL meta")?;
+
+        let anim_list = m.get_field("anim")?.unwrap_or_default();
+        let ecli_list = m.get_field("ecli")?.unwrap_or_default();
+        Ok((anim_list, ecli_list))
+    })
+}
+
+// =============================================================================
+
+pub(super) fn compile(
+    ast: &ast::ScriptFile,
+    ctx: &mut CompilerContext,
+) -> Result<OldeEclFile, ErrorReported> {
+    let hooks = &ModernEclHooks;
+
+    let mut ast = ast.clone();
+    crate::passes::resolution::assign_languages(&mut ast, LanguageKey::Ecl, ctx)?;
+    ctx.observers.after_pass("assign_languages", &ast)?;
+
+    // an early pass to define global constants for sub names
+    let sub_ids = gather_sub_ids(&ast, ctx)?;
+    for (index, sub_name) in sub_ids.values().enumerate() {
+        let const_value: Sp<ast::Expr> = sp!(sub_name.span => (index as i32).into());
+        ctx.define_enum_const(sub_name.clone(), const_value, sp!(auto_enum_names::ecl_sub()));
+    }
+
+    // preprocess
+    let ast = {
+        let mut ast = ast;
+        crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::validate_reg_sigils::run(&ast, ctx);
+        ctx.observers.after_resolution(&ast, &ctx.resolutions)?;
+        crate::passes::type_check::run(&ast, ctx)?;
+        crate::passes::float_equality::run(&ast, ctx)?;
+        crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::resolution::compute_diff_label_masks(&mut ast, ctx)?;
+        ctx.observers.after_pass("compute_diff_label_masks", &ast)?;
+        crate::passes::validate_difficulty::run(&ast, ctx, hooks)?;
+        crate::passes::unreachable_code::run(&ast, ctx)?;
+        crate::passes::inline_funcs::run(&mut ast, ctx)?;
+        crate::passes::explode_diff_switches::run(&mut ast, ctx)?;
+        crate::passes::const_simplify::run(&mut ast, ctx)?;
+        ctx.observers.after_pass("const_simplify", &ast)?;
+        crate::passes::unroll_times::run(&mut ast, ctx)?;
+        crate::passes::desugar_blocks::run(&mut ast, ctx, hooks.language())?;
+        ctx.observers.after_pass("desugar_blocks", &ast)?;
+        ast
+    };
+
+    // Compilation pass
+    let emitter = ctx.emitter;
+    let emit = |e| emitter.emit(e);
+    let do_debug_info = true;
+    let mut subs = IndexMap::new();
+    let mut found_meta = None;
+
+    // From this point onwards we must be careful about early exits from the function.
+    // Use an ErrorFlag to delay returns for panic bombs.
+    let mut errors = ErrorFlag::new();
+    let mut lowerer = llir::Lowerer::new(hooks);
+
+    // Lower all of the code up front, as this can be done in parallel
+    let mut sub_jobs = vec![];
+    for item in &ast.items {
+        if let ast::Item::Func(ast::ItemFunc { qualifier: None, code: Some(code), ident, ty_keyword, params, .. }) = &item.value {
+            if ty_keyword.value == ast::TypeKeyword::Void && params.is_empty() {
+                sub_jobs.push((&code.0[..], Some(ctx.resolutions.expect_def(ident))));
+            }
+        }
+    }
+    let mut lowered_subs = lowerer.lower_subs(&sub_jobs, ctx, do_debug_info).into_iter();
+
+    ast.items.iter().map(|item| {
+        match &item.value {
+            ast::Item::Meta { keyword: sp_pat![kw_span => token![meta]], fields } => {
+                if let Some((prev_kw_span, _)) = found_meta.replace((kw_span, fields)) {
+                    return Err(emit(error!(
+                        message("'meta' supplied multiple times"),
+                        secondary(prev_kw_span, "previously supplied here"),
+                        primary(kw_span, "duplicate 'meta'"),
+                    )));
+                }
+            },
+            ast::Item::Meta { keyword, .. } => return Err(emit(error!(
+                message("unexpected '{keyword}' in ECL file"),
+                primary(keyword, "not valid in ECL files"),
+            ))),
+            ast::Item::ConstVar { .. } => {},
+            ast::Item::AnmScript { .. } => return Err(emit(unsupported(&item.span))),
+            ast::Item::AnmScriptAlias { .. } => return Err(emit(unsupported(&item.span))),
+            ast::Item::Timeline { .. } => return Err(emit(unsupported(&item.span))),
+
+            ast::Item::Func(ast::ItemFunc { qualifier: None, code: None, ref ident, .. }) => {
+                subs.insert(ident.value.as_raw().clone(), vec![]); // dummy output to preserve sub indices
+                return Err(emit(error!(
+                    message("extern functions are not supported in ECL files"),
+                    primary(item, "unsupported extern function"),
+                )));
+            },
+
+            ast::Item::Func(ast::ItemFunc { qualifier: None, code: Some(_), ref ident, params, ty_keyword }) => {
+                let sub_index = subs.len();
+
+                if ty_keyword.value != ast::TypeKeyword::Void {
+                    subs.insert(ident.value.as_raw().clone(), vec![]); // dummy output to preserve sub indices
+                    return Err(emit(error!(
+                        message("return types are not supported in ECL subs like '{ident}'"),
+                        primary(ty_keyword, "unsupported return type"),
+                    )));
+                }
+                if let Some(first_param) = params.first() {
+                    subs.insert(ident.value.as_raw().clone(), vec![]); // dummy output to preserve sub indices
+                    return Err(emit(error!(
+                        message("parameters are not yet supported in modern ECL subs like '{ident}'"),
+                        primary(first_param, "unsupported parameter"),
+                    )));
+                }
+
+                let (instrs, lowering_info) = lowered_subs.next().unwrap().unwrap_or_else(|e| {
+                    errors.set(e);
+                    (vec![], None)  // dummy instrs so that we can still insert an item into 'subs' and get the right indices
+                });
+                ctx.observers.after_lowering(ident.as_str(), &instrs).unwrap_or_else(|e| errors.set(e));
+                subs.insert(ident.value.as_raw().clone(), instrs);
+
+                if let Some(lowering_info) = lowering_info {
+                    let export_info = debug_info::ScriptExportInfo {
+                        exported_as: debug_info::ScriptType::EclSub { index: sub_index },
+                        name: Some(ident.to_string()),
+                        name_span: ident.span.into(),
+                    };
+                    ctx.script_debug_info.push(debug_info::Script { export_info, lowering_info });
+                }
+            },
+
+            // const funcs are only used at compile time
+            ast::Item::Func(ast::ItemFunc { qualifier: Some(sp_pat![token![const]]), .. }) => {},
+
+            // inline funcs were already expanded into their callers and removed
+            ast::Item::Func(ast::ItemFunc { qualifier: Some(_), .. }) => unreachable!("inline func survived inlining"),
+        } // match item
+        Ok(())
+    }).collect_with_recovery().unwrap_or_else(|e| errors.set(e));
+
+    assert!(lowered_subs.next().is_none());
+
+    let (anim_list, ecli_list) = match found_meta {
+        Some((_, fields)) => {
+            include_lists_from_meta(fields).map_err(|e| emitter.emit(e))
+                .and_then(|lists| validate_include_lists(emitter, fields.span, lists))
+                .unwrap_or_else(|e| {
+                    errors.set(e);
+                    Default::default()
+                })
+        },
+        None => Default::default(),
+    };
+
+    lowerer.finish(ctx).unwrap_or_else(|e| errors.set(e));
+    errors.into_result(())?;
+
+    Ok(OldeEclFile {
+        subs,
+        timelines: vec![],
+        anim_list,
+        ecli_list,
+        binary_filename: None,
+    })
+}
+
+/// Checks that the names in the include lists can be written to the header, and warns about duplicates.
+fn validate_include_lists(
+    emitter: &impl Emitter,
+    meta_span: Span,
+    (anim_list, ecli_list): IncludeLists,
+) -> Result<IncludeLists, ErrorReported> {
+    let mut errors = ErrorFlag::new();
+    let mut list_sizes = vec![];
+    for (key, list) in [("anim", &anim_list), ("ecli", &ecli_list)] {
+        let mut first_spans = HashMap::new();
+        let mut encoded_lens = vec![];
+        for name in list {
+            match Encoded::encode(name, DEFAULT_ENCODING) {
+                Ok(encoded) => encoded_lens.push(encoded.len()),
+                Err(e) => errors.set(emitter.emit(e)),
+            }
+            if name.contains('\0') {
+                errors.set(emitter.emit(error!(
+                    message("include name contains a NUL character"),
+                    primary(name, "names in '{key}' are written as NUL-terminated strings"),
+                )));
+            }
+            if let Some(&first_span) = first_spans.get(&name.value) {
+                emitter.emit(warning!(
+                    message("'{}' appears more than once in '{key}'", name.value),
+                    primary(name, "duplicate include"),
+                    secondary(first_span, "first included here"),
+                )).ignore();
+            } else {
+                first_spans.insert(name.value.clone(), name.span);
+            }
+        }
+        list_sizes.push(include_list_size(encoded_lens));
+    }
+    errors.into_result(())?;
+
+    let include_length = HEADER_SIZE + list_sizes.iter().sum::<usize>();
+    if include_length > u16::MAX as usize {
+        return Err(emitter.emit(error!(
+            message("include lists are too long"),
+            primary(meta_span, "these need {include_length} bytes"),
+            note("the ECL header stores the end of the include lists in a 16-bit field, so they may use at most {} bytes", u16::MAX),
+        )));
+    }
+    Ok((anim_list, ecli_list))
+}
+
+fn unsupported(span: &Span) -> Diagnostic {
+    error!(
+        message("feature not supported by format"),
+        primary(span, "not supported by modern ECL files"),
+    )
+}
+
+// =============================================================================
+
+/// Size in bytes of an include list containing strings of the given encoded lengths.
+fn include_list_size(encoded_lens: impl IntoIterator<Item=usize>) -> usize {
+    // magic and count, then the strings with their NULs, padded to a multiple of 4
+    let size = 8 + encoded_lens.into_iter().map(|len| len + 1).sum::<usize>();
+    size.div_ceil(4) * 4
+}
+
+pub(super) fn read_modern_ecl(
+    reader: &mut BinReader,
+    emitter: &impl Emitter,
+) -> ReadResult<OldeEclFile> {
+    let format = ModernEclHooks.instr_format();
+
+    let start_pos = reader.pos()?;
+    let file_len = reader.stream_len()? - start_pos;
+
+    reader.expect_magic(emitter, b"SCPT")?;
+    let unknown_1 = reader.read_u16()?;
+    let include_length = reader.read_u16()? as u64;
+    let include_offset = reader.read_u32()? as u64;
+    let zero_1 = reader.read_u32()?;
+    let num_subs = reader.read_u32()? as usize;
+    let zero_2 = reader.read_u32s(4)?;
+
+    if unknown_1 != 1 {
+        emitter.emit(warning!("unexpected value in header field at 0x4: {unknown_1:#x} (expected 1)")).ignore();
+    }
+    for (field_offset, value) in [(0xc, zero_1)].into_iter().chain((0x14..).step_by(4).zip(zero_2)) {
+        if value != 0 {
+            emitter.emit(warning!("unexpected nonzero value in header field at {field_offset:#x}: {value:#x}")).ignore();
+        }
+    }
+
+    reader.seek_to(start_pos + include_offset)?;
+    let anim_list = read_include_list(reader, emitter, b"ANIM")?;
+    let anim_end = align_to_4(reader.pos()? - start_pos);
+    reader.seek_to(start_pos + anim_end)?;
+    let ecli_list = read_include_list(reader, emitter, b"ECLI")?;
+    let include_end = align_to_4(reader.pos()? - start_pos);
+    if include_end != include_length {
+        emitter.emit(warning!(
+            "include lists end at offset {include_end:#x}, but the header says {include_length:#x}",
+        )).ignore();
+    }
+
+    reader.seek_to(start_pos + include_end)?;
+    let sub_offsets = reader.read_u32s(num_subs)?;
+    let mut sub_names = vec![];
+    for index in 0..num_subs {
+        let encoded = reader.read_cstring_blockwise(1)?;
+        let name = encoded.decode(DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?;
+        sub_names.push(match Ident::new_user(&name) {
+            Ok(ident) => ident,
+            Err(_) => {
+                let auto_name = auto_sub_name(index as u32);
+                emitter.emit(warning!("sub {index} has name '{name}' which is not an identifier; it will be called '{auto_name}'")).ignore();
+                auto_name
+            },
+        });
+    }
+
+    let mut sorted_offsets = sub_offsets.iter().map(|&offset| offset as u64).collect::<Vec<_>>();
+    sorted_offsets.sort();
+
+    let mut subs = IndexMap::new();
+    for (index, (name, sub_offset)) in sub_names.into_iter().zip(sub_offsets).enumerate() {
+        let sub_offset = sub_offset as u64;
+        let end_offset = sorted_offsets.iter().copied().find(|&offset| offset > sub_offset).unwrap_or(file_len);
+        let instrs = emitter.chain_with(|f| write!(f, "in sub {index} ({name})"), |emitter| {
+            reader.seek_to(start_pos + sub_offset)?;
+            read_sub(reader, emitter, format, end_offset - sub_offset)
+        })?;
+
+        let name = match subs.contains_key(&name) {
+            false => name,
+            true => {
+                let auto_name = auto_sub_name(index as u32);
+                emitter.emit(warning!("sub {index} has the same name as an earlier sub ('{name}'); it will be called '{auto_name}'")).ignore();
+                auto_name
+            },
+        };
+        subs.insert(name, instrs);
+    }
+
+    let binary_filename = Some(reader.display_filename().to_string());
+    Ok(OldeEclFile { subs, timelines: vec![], anim_list, ecli_list, binary_filename })
+}
+
+fn read_include_list(reader: &mut BinReader, emitter: &impl Emitter, magic: &[u8; 4]) -> ReadResult<Vec<Sp<String>>> {
+    reader.expect_magic(emitter, magic)?;
+    let count = reader.read_u32()?;
+    (0..count).map(|_| {
+        let encoded = reader.read_cstring_blockwise(1)?;
+        let string = encoded.decode(DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?;
+        Ok(sp!(string))
+    }).collect()
+}
+
+/// Reads the instructions of a sub, which end where the next sub begins.  (there is no end marker)
+fn read_sub(reader: &mut BinReader, emitter: &impl Emitter, format: &dyn InstrFormat, sub_len: u64) -> ReadResult<Vec<RawInstr>> {
+    let sub_start = reader.pos()?;
+    reader.expect_magic(emitter, b"ECLH")?;
+    let data_offset = reader.read_u32()?;
+    let zeros = reader.read_u32s(2)?;
+    if data_offset != SUB_HEADER_SIZE {
+        emitter.emit(warning!("unexpected instruction offset {data_offset:#x} (expected {SUB_HEADER_SIZE:#x})")).ignore();
+    }
+    if zeros.iter().any(|&x| x != 0) {
+        emitter.emit(warning!("unexpected nonzero data in sub header: {zeros:#x?}")).ignore();
+    }
+
+    let mut instrs = vec![];
+    let mut cur_offset = data_offset as u64;
+    reader.seek_to(sub_start + data_offset as u64)?;
+    for index in 0.. {
+        if cur_offset >= sub_len {
+            if cur_offset > sub_len {
+                return Err(emitter.emit(error!(
+                    "sub read past expected end at offset {:#x} (we're now at offset {:#x}!)",
+                    sub_len, cur_offset,
+                )));
+            }
+            break;
+        }
+
+        let instr = emitter.chain_with(|f| write!(f, "in instruction {}", index), |emitter| {
+            format.read_instr(reader, emitter)
+        })?;
+        match instr {
+            ReadInstr::Instr(instr) => {
+                cur_offset += format.instr_size(&instr) as u64;
+                instrs.push(instr);
+            },
+            _ => unreachable!("modern ECL instructions have no terminal"),
+        }
+    }
+    Ok(instrs)
+}
+
+fn align_to_4(offset: u64) -> u64 {
+    offset.div_ceil(4) * 4
+}
+
+pub(super) fn write_modern_ecl(
+    w: &mut BinWriter,
+    emitter: &impl Emitter,
+    ecl: &OldeEclFile,
+) -> WriteResult {
+    let format = ModernEclHooks.instr_format();
+
+    let start_pos = w.pos()?;
+
+    w.write_all(b"SCPT")?;
+    w.write_u16(1)?;
+    let include_length_pos = w.pos()?;
+    w.write_u16(0)?;  // include_length, filled in later
+    w.write_u32(HEADER_SIZE as u32)?;
+    w.write_u32(0)?;
+    w.write_u32(fit_field(emitter, "sub_count", ecl.subs.len())?)?;
+    w.write_u32s(&[0; 4])?;
+
+    write_include_list(w, emitter, b"ANIM", &ecl.anim_list)?;
+    write_include_list(w, emitter, b"ECLI", &ecl.ecli_list)?;
+    let include_length = w.pos()? - start_pos;
+
+    let sub_offsets_pos = w.pos()?;
+    for _ in 0..ecl.subs.len() {
+        w.write_u32(0)?;
+    }
+
+    let mut names = Encoded(vec![]);
+    for ident in ecl.subs.keys() {
+        names.0.extend(Encoded::encode(&sp!(ident.as_str()), DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?.0);
+        names.0.push(0);
+    }
+    names.0.resize(align_to_4(names.len() as u64) as usize, 0);
+    w.write_all(&names.0)?;
+
+    let mut sub_offsets = vec![];
+    for (index, (ident, instrs)) in ecl.subs.iter().enumerate() {
+        sub_offsets.push(w.pos()? - start_pos);
+        w.write_all(b"ECLH")?;
+        w.write_u32(SUB_HEADER_SIZE)?;
+        w.write_u32s(&[0; 2])?;
+        emitter.chain_with(|f| write!(f, "in sub {ident} (index {index})"), |emitter| {
+            llir::write_instrs(w, emitter, format, instrs)
+        })?;
+    }
+
+    let end_pos = w.pos()?;
+    w.seek_to(include_length_pos)?;
+    w.write_u16(fit_field(emitter, "include_length", include_length)?)?;
+    w.seek_to(sub_offsets_pos)?;
+    for offset in sub_offsets {
+        w.write_u32(fit_field(emitter, "sub_offset", offset)?)?;
+    }
+    w.seek_to(end_pos)?;
+    Ok(())
+}
+
+fn write_include_list(w: &mut BinWriter, emitter: &impl Emitter, magic: &[u8; 4], list: &[Sp<String>]) -> WriteResult {
+    let mut encoded_list = Encoded(magic.to_vec());
+    encoded_list.0.extend(fit_field::<u32, _>(emitter, "include_count", list.len())?.to_le_bytes());
+    for name in list {
+        encoded_list.0.extend(Encoded::encode(name, DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?.0);
+        encoded_list.0.push(0);
+    }
+    encoded_list.0.resize(align_to_4(encoded_list.len() as u64) as usize, 0);
+    w.write_all(&encoded_list.0)
+}
+
+// =============================================================================
+
+pub(super) struct ModernEclHooks;
+
+impl LanguageHooks for ModernEclHooks {
+    fn language(&self) -> LanguageKey { LanguageKey::Ecl }
+
+    fn has_registers(&self) -> bool { true }
+
+    fn has_stack(&self) -> bool { true }
+
+    fn has_arg_count(&self) -> bool { true }
+
+    fn default_difficulty_mask(&self) -> Option<raw::DifficultyMask> {
+        Some(crate::passes::semantics::time_and_difficulty::DEFAULT_DIFFICULTY_MASK_BYTE)
+    }
+
+    // offsets are written as relative in these files
+    fn label_encoding(&self) -> LabelEncoding { LabelEncoding::RelativeByteOffset }
+
+    fn instr_format(&self) -> &dyn InstrFormat { self }
+}
+
+impl InstrFormat for ModernEclHooks {
+    fn instr_header_size(&self) -> usize { 16 }
+
+    fn read_instr(&self, f: &mut BinReader, emitter: &dyn Emitter) -> ReadResult<ReadInstr> {
+        let time = f.read_i32()?;
+        let opcode = f.read_u16()?;
+        let size = f.read_u16()? as usize;
+        let param_mask = f.read_u16()? as raw::ParamMask;
+        let difficulty = f.read_u8()?;
+        let arg_count = f.read_u8()?;
+        let pop = f.read_u32()?;
+
+        if size < self.instr_header_size() {
+            return Err(emitter.as_sized().emit(error!("instruction size {size} is smaller than the instruction header")));
+        }
+        let pop = raw::StackPop::try_from(pop).map_err(|_| {
+            emitter.as_sized().emit(error!("stack pop count is too large: {pop:#x}"))
+        })?;
+
+        let args_blob = f.read_byte_vec(size - self.instr_header_size())?;
+
+        Ok(ReadInstr::Instr(RawInstr {
+            time, opcode, args_blob, param_mask, difficulty, pop, arg_count,
+            ..RawInstr::DEFAULTS
+        }))
+    }
+
+    fn write_instr(&self, f: &mut BinWriter, emitter: &dyn Emitter, instr: &RawInstr) -> WriteResult {
+        f.write_i32(instr.time)?;
+        f.write_u16(instr.opcode)?;
+        f.write_u16(self.instr_size(instr) as _)?;
+        f.write_u16(instr.param_mask as _)?;
+        f.write_u8(instr.difficulty)?;
+        f.write_u8(instr.arg_count)?;
+        f.write_u32(fit_field(emitter.as_sized(), "pop", instr.pop)?)?;
+        f.write_all(&instr.args_blob)?;
+        Ok(())
+    }
+
+    // subs end where the next one begins
+    fn write_terminal_instr(&self, _: &mut BinWriter, _: &dyn Emitter) -> WriteResult {
+        Ok(())
+    }
+}
@@ -107,6 +107,8 @@ impl Entry for Entry095 {
 
     fn from_fields(fields: &Sp<meta::Fields>) -> Result<Self, FromMetaError<'_>> {
         meta::ParseObject::scope(fields, |m| {
+            m.describe("mission entry")?;
+            m.require_fields(&["stage", "scene", "face", "point", "text"])?;
             let stage = m.expect_field::<u32>("stage")? as u16;
             let scene = m.expect_field::<u32>("scene")? as u16;
             let face = m.expect_field("face")?;
@@ -166,6 +168,8 @@ impl Entry for Entry125 {
 
     fn from_fields(fields: &Sp<meta::Fields>) -> Result<Self, FromMetaError<'_>> {
         meta::ParseObject::scope(fields, |m| {
+            m.describe("mission entry")?;
+            m.require_fields(&["stage", "scene", "player", "unknown_1", "unknown_2", "point_1", "point_2", "furigana", "text"])?;
             let stage = m.expect_field::<u32>("stage")? as u16;
             let scene = m.expect_field::<u32>("scene")? as u16;
             let player = m.expect_field::<u32>("player")? as u16;
@@ -240,6 +244,8 @@ fn decompile<E: Entry>(
         }).collect(),
         mapfiles: vec![],
         image_sources: vec![],
+        reg_pragmas: vec![],
+        comments: vec![],
     })
 }
 
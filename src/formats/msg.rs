@@ -108,6 +108,7 @@ impl SparseScriptTable {
 
     fn from_fields(fields: &Sp<meta::Fields>) -> Result<Self, FromMetaError<'_>> {
         meta::ParseObject::scope(fields, |m| {
+            m.describe("MSG meta")?;
             let ident_map: IndexMap<Sp<Ident>, ScriptTableEntry> = m.expect_field("table")?;
 
             let mut default = None;
@@ -146,7 +147,7 @@ impl ToMeta for ScriptTableEntry {
     fn to_meta(&self) -> Meta {
         Meta::make_object()
             .field("script", &self.script.value)
-            .field_default("flags", &self.flags.value, &0)
+            .field_default("flags", &meta::Hex(self.flags.value), &meta::Hex(0))
             .build()
     }
 }
@@ -154,6 +155,7 @@ impl ToMeta for ScriptTableEntry {
 impl FromMeta<'_> for ScriptTableEntry {
     fn from_meta(meta: &'_ Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
         meta.parse_object(|m| {
+            m.describe("MSG script table entry")?;
             Ok(ScriptTableEntry {
                 script: m.expect_field("script")?,
                 flags: m.get_field("flags")?.unwrap_or(sp!(meta.span => 0)),
@@ -185,7 +187,7 @@ impl FromMeta<'_> for ScriptTableOffset {
 
 fn decompile(
     msg: &MsgFile,
-    emitter: &impl Emitter,
+    emitter: &(impl Emitter + Sync),
     format: &FileFormat,
     ctx: &mut CompilerContext,
     decompile_options: &DecompileOptions,
@@ -195,28 +197,37 @@ fn decompile(
     let sparse_script_table = sparsify_script_table(&msg.dense_table);
 
     let const_proof = crate::passes::evaluate_const_vars::run(ctx)?;
-    let mut raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
+    let raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, decompile_options, const_proof)?;
+    let scripts = msg.scripts.iter().collect::<Vec<_>>();
+    let codes = ctx.map_in_parallel(&scripts, |&(_, instrs), job_emitter| {
+        raiser.raise_instrs_to_sub_ast(&emitter.with_root(job_emitter), instrs, ctx)
+    });
+
     let mut items = vec![sp!(ast::Item::Meta {
         keyword: sp!(token![meta]),
         fields: sp!(sparse_script_table.make_meta()),
     })];
-    items.extend(msg.scripts.iter().map(|(ident, instrs)| {
-        let code = raiser.raise_instrs_to_sub_ast(emitter, instrs, ctx)?;
+    items.extend(scripts.iter().zip(codes).map(|(&(ident, _), code)| {
+        let code = code?;
 
         Ok(sp!(ast::Item::AnmScript {
             number: None,
             ident: sp!(ident.clone()),
             code: ast::Block(code),
             keyword: sp!(()),
+            meta: None,
         }))
     }).collect_with_recovery::<Vec<_>>()?);
 
     let mut script = ast::ScriptFile {
-        mapfiles: ctx.mapfiles_to_ast(),
+        mapfiles: vec![],  // filled in once we know what was used
         image_sources: vec![],
+        reg_pragmas: vec![],
         items,
+        comments: vec![],
     };
     crate::passes::postprocess_decompiled(&mut script, ctx, decompile_options)?;
+    script.mapfiles = ctx.used_mapfiles_to_ast(&decompile_options.mapfile_paths)?;
     Ok(script)
 }
 
@@ -276,12 +287,22 @@ fn compile(
         let mut ast = ast.clone();
 
         crate::passes::resolution::assign_languages(&mut ast, hooks.language(), ctx)?;
+        ctx.observers.after_pass("assign_languages", &ast)?;
         crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::validate_reg_sigils::run(&ast, ctx);
+        ctx.observers.after_resolution(&ast, &ctx.resolutions)?;
         crate::passes::type_check::run(&ast, ctx)?;
-        crate::passes::validate_difficulty::forbid_difficulty(&ast, ctx)?;
+        crate::passes::float_equality::run(&ast, ctx)?;
+        crate::passes::unreachable_code::run(&ast, ctx)?;
         crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::validate_difficulty::run_if_supported(&mut ast, ctx, hooks)?;
+        crate::passes::inline_funcs::run(&mut ast, ctx)?;
+        crate::passes::explode_diff_switches::run(&mut ast, ctx)?;
         crate::passes::const_simplify::run(&mut ast, ctx)?;
+        ctx.observers.after_pass("const_simplify", &ast)?;
+        crate::passes::unroll_times::run(&mut ast, ctx)?;
         crate::passes::desugar_blocks::run(&mut ast, ctx, hooks.language())?;
+        ctx.observers.after_pass("desugar_blocks", &ast)?;
         ast
     };
 
@@ -304,6 +325,7 @@ fn compile(
                     message("unexpected '{keyword}' in MSG file"),
                     primary(keyword, "not valid in MSG files"),
                 ))),
+                ast::Item::AnmScript { meta: Some(fields), .. } => return Err(emit(unsupported(&fields.span))),
                 ast::Item::AnmScript { number: Some(number), .. } => return Err(emit(error!(
                     message("unexpected numbered script in MSG file"),
                     primary(number, "unexpected number"),
@@ -319,6 +341,7 @@ fn compile(
                     }
                 },
                 ast::Item::ConstVar { .. } => {},
+                ast::Item::AnmScriptAlias { .. } => return Err(emit(unsupported(&item.span))),
                 ast::Item::Timeline { .. } => return Err(emit(unsupported(&item.span))),
                 ast::Item::Func { .. } => return Err(emit(unsupported(&item.span))),
             }
@@ -340,8 +363,12 @@ fn compile(
     let mut scripts = IndexMap::new();
     let do_debug_info = true;
 
-    script_code.iter().map(|(name, code)| {
-        let (instrs, lowering_info) = lowerer.lower_sub(&code.0, None, ctx, do_debug_info)?;
+    let subs = script_code.iter().map(|(_, code)| (&code.0[..], None)).collect::<Vec<_>>();
+    let lowered_subs = lowerer.lower_subs(&subs, ctx, do_debug_info);
+
+    script_code.iter().zip(lowered_subs).map(|((name, _), lowered)| {
+        let (instrs, lowering_info) = lowered?;
+        ctx.observers.after_lowering(name.as_str(), &instrs)?;
         scripts.insert(name.value.clone(), instrs);
 
         if let Some(lowering_info) = lowering_info {
@@ -586,6 +613,11 @@ fn write_msg(
 
 // =============================================================================
 
+/// Get the hooks for MSG instructions in a game, for use by code outside this module.
+pub(crate) fn game_hooks(game: Game, language: LanguageKey) -> Box<dyn LanguageHooks> {
+    FileFormat { game, language }.language_hooks()
+}
+
 fn game_format(game: Game, language: LanguageKey, emitter: &RootEmitter) -> Result<FileFormat, ErrorReported> {
     match (game, language) {
         | (Game::Th095, LanguageKey::Msg)
@@ -615,7 +647,7 @@ impl FileFormat {
             | Game::Th12 | Game::Th128 | Game::Th13
             | Game::Th14 | Game::Th143 | Game::Th15
             | Game::Th16 | Game::Th165 | Game::Th17
-            | Game::Th18
+            | Game::Th18 | Game::Th19
             => Box::new(MsgHooks { language: self.language }),
 
             | Game::Th095 | Game::Th125
@@ -638,6 +670,10 @@ impl LanguageHooks for MsgHooks {
 impl InstrFormat for MsgHooks {
     fn instr_header_size(&self) -> usize { 4 }
 
+    fn args_blob_size(&self) -> llir::ArgsBlobSize { llir::ArgsBlobSize::MultipleOf(1) }
+
+    fn max_args_blob_len(&self) -> usize { u8::MAX as usize }
+
     fn read_instr(&self, f: &mut BinReader, _: &dyn Emitter) -> ReadResult<ReadInstr> {
         let time = match f.read_i16_or_eof() {
             Ok(Some(time)) => time,
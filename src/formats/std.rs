@@ -1,15 +1,14 @@
 use indexmap::IndexMap;
 
-use crate::raw;
 use crate::ast;
 use crate::ast::meta::{self, FromMeta, FromMetaError, Meta, ToMeta};
-use crate::io::{BinRead, BinWrite, BinReader, BinWriter, Encoded, ReadResult, WriteResult, DEFAULT_ENCODING};
+use crate::io::{BinRead, BinWrite, BinReader, BinWriter, Encoded, ReadResult, WriteResult, DEFAULT_ENCODING, fit_field};
 use crate::diagnostic::{Diagnostic, Emitter};
 use crate::error::{ErrorReported, ErrorFlag};
 use crate::game::{Game, LanguageKey};
 use crate::ident::{Ident};
-use crate::llir::{self, ReadInstr, RawInstr, LanguageHooks, InstrFormat, DecompileOptions};
-use crate::pos::Sp;
+use crate::llir::{self, ReadInstr, RawInstr, LanguageHooks, InstrFormat, DecompileOptions, LabelEncoding};
+use crate::pos::{Sp, Span};
 use crate::context::CompilerContext;
 use crate::debug_info;
 
@@ -46,10 +45,14 @@ pub struct Std06Bgm {
 
 impl FromMeta<'_> for Std06Bgm {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
-        meta.parse_object(|m| Ok(Std06Bgm {
-            path: m.expect_field("path")?,
-            name: m.expect_field("name")?,
-        }))
+        meta.parse_object(|m| {
+            m.describe("STD bgm")?;
+            m.require_fields(&["path", "name"])?;
+            Ok(Std06Bgm {
+                path: m.expect_field("path")?,
+                name: m.expect_field("name")?,
+            })
+        })
     }
 }
 
@@ -72,6 +75,49 @@ impl StdFile {
         compile_std(&*game_format(game), script, ctx)
     }
 
+    /// Build a file from the `meta` block of a script and raw instructions, without compiling any code.
+    ///
+    /// `ast` may contain nothing but the `meta` and consts.
+    pub fn from_raw_script(game: Game, ast: &ast::ScriptFile, script: Vec<RawInstr>, ctx: &mut CompilerContext) -> Result<Self, ErrorReported> {
+        let emitter = ctx.emitter;
+        let mut found_meta = None::<Span>;
+        for item in &ast.items {
+            match &item.value {
+                ast::Item::Meta { keyword: sp_pat![kw_span => token![meta]], .. } => {
+                    if let Some(prev_kw_span) = found_meta.replace(*kw_span) {
+                        return Err(emitter.emit(error!(
+                            message("'meta' supplied multiple times"),
+                            secondary(prev_kw_span, "previously supplied here"),
+                            primary(kw_span, "duplicate 'meta'"),
+                        )));
+                    }
+                },
+                ast::Item::ConstVar { .. } => {},
+                _ => return Err(emitter.emit(error!(
+                    message("unexpected item in STD skeleton"),
+                    primary(item, "only 'meta' is allowed here"),
+                ))),
+            }
+        }
+        if found_meta.is_none() {
+            return Err(emitter.emit(error!("missing 'meta' section")));
+        }
+
+        let mut ast = ast.clone();
+        crate::passes::resolution::assign_languages(&mut ast, LanguageKey::Std, ctx)?;
+        crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::const_simplify::run(&mut ast, ctx)?;
+
+        let fields = ast.items.iter().find_map(|item| match &item.value {
+            ast::Item::Meta { fields, .. } => Some(fields),
+            _ => None,
+        }).expect("checked above");
+        let mut out = StdFile::init_from_meta(&*game_format(game), fields).map_err(|e| emitter.emit(e))?;
+        out.script = script;
+        Ok(out)
+    }
+
     pub fn write_to_stream(&self, w: &mut BinWriter, game: Game) -> WriteResult {
         let emitter = w.emitter();
         write_std(w, &emitter, &*game_format(game), self)
@@ -86,6 +132,8 @@ impl StdFile {
 impl StdFile {
     fn init_from_meta<'m>(file_format: &dyn FileFormat, fields: &'m Sp<meta::Fields>) -> Result<Self, FromMetaError<'m>> {
         let mut m = meta::ParseObject::new(fields);
+        m.describe("STD meta")?;
+        m.require_fields(&["unknown", "objects", "instances"])?;
         let out = StdFile {
             unknown: m.expect_field("unknown")?,
             objects: m.expect_field("objects")?,
@@ -120,12 +168,16 @@ pub struct Object {
 
 impl FromMeta<'_> for Object {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
-        meta.parse_object(|m| Ok(Object {
-            layer: m.expect_renamed_field::<i32>("unknown", "layer")? as u16,
-            pos: m.expect_field("pos")?,
-            size: m.expect_field("size")?,
-            quads: m.expect_field("quads")?,
-        }))
+        meta.parse_object(|m| {
+            m.describe("STD object")?;
+            m.require_fields(&["pos", "size", "quads"])?;
+            Ok(Object {
+                layer: m.expect_renamed_field::<i32>("unknown", "layer")? as u16,
+                pos: m.expect_field("pos")?,
+                size: m.expect_field("size")?,
+                quads: m.expect_field("quads")?,
+            })
+        })
     }
 }
 
@@ -164,21 +216,29 @@ pub enum QuadExtra {
 impl FromMeta<'_> for Quad {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
         meta.parse_variant()?
-            .variant("rect", |m| Ok(Quad {
-                anm_script: m.expect_field::<i32>("anm_script")? as u16,
-                extra: QuadExtra::Rect {
-                    pos: m.expect_field("pos")?,
-                    size: m.expect_field("size")?,
-                },
-            }))
-            .variant("strip", |m| Ok(Quad {
-                anm_script: m.expect_field::<i32>("anm_script")? as u16,
-                extra: QuadExtra::Strip {
-                    start: m.expect_field("start")?,
-                    end: m.expect_field("end")?,
-                    width: m.expect_field("width")?,
-                },
-            }))
+            .variant("rect", |m| {
+                m.describe("STD rect quad")?;
+                m.require_fields(&["anm_script", "pos", "size"])?;
+                Ok(Quad {
+                    anm_script: m.expect_field::<i32>("anm_script")? as u16,
+                    extra: QuadExtra::Rect {
+                        pos: m.expect_field("pos")?,
+                        size: m.expect_field("size")?,
+                    },
+                })
+            })
+            .variant("strip", |m| {
+                m.describe("STD strip quad")?;
+                m.require_fields(&["anm_script", "start", "end", "width"])?;
+                Ok(Quad {
+                    anm_script: m.expect_field::<i32>("anm_script")? as u16,
+                    extra: QuadExtra::Strip {
+                        start: m.expect_field("start")?,
+                        end: m.expect_field("end")?,
+                        width: m.expect_field("width")?,
+                    },
+                })
+            })
             .finish()
     }
 }
@@ -216,11 +276,14 @@ pub struct Instance {
 
 impl FromMeta<'_> for Instance {
     fn from_meta(meta: &Sp<Meta>) -> Result<Self, FromMetaError<'_>> {
-        meta.parse_any_variant(|ident, meta| Ok(Instance {
-            object: ident.clone(),
-            unknown: meta.get_field::<i32>("unknown")?.unwrap_or(256) as u16,
-            pos: meta.expect_field("pos")?,
-        }))
+        meta.parse_any_variant(|ident, meta| {
+            meta.describe("STD instance")?;
+            Ok(Instance {
+                object: ident.clone(),
+                unknown: meta.get_field::<i32>("unknown")?.unwrap_or(256) as u16,
+                pos: meta.expect_field("pos")?,
+            })
+        })
     }
 }
 
@@ -252,8 +315,10 @@ fn decompile_std(
     };
 
     let mut script = ast::ScriptFile {
-        mapfiles: ctx.mapfiles_to_ast(),
+        mapfiles: vec![],  // filled in once we know what was used
         image_sources: vec![],
+        reg_pragmas: vec![],
+        comments: vec![],
         items: vec! [
             sp!(ast::Item::Meta {
                 keyword: sp!(ast::MetaKeyword::Meta),
@@ -264,10 +329,12 @@ fn decompile_std(
                 ident: sp!(ident!("main")),
                 code: ast::Block(code),
                 keyword: sp!(()),
+                meta: None,
             }),
         ],
     };
     crate::passes::postprocess_decompiled(&mut script, ctx, decompile_options)?;
+    script.mapfiles = ctx.used_mapfiles_to_ast(&decompile_options.mapfile_paths)?;
     Ok(script)
 }
 
@@ -288,12 +355,22 @@ fn compile_std(
 
         let language = format.language_hooks().language();
         crate::passes::resolution::assign_languages(&mut ast, language, ctx)?;
+        ctx.observers.after_pass("assign_languages", &ast)?;
         crate::passes::resolution::resolve_names(&ast, ctx)?;
+        crate::passes::validate_reg_sigils::run(&ast, ctx);
+        ctx.observers.after_resolution(&ast, &ctx.resolutions)?;
         crate::passes::type_check::run(&ast, ctx)?;
-        crate::passes::validate_difficulty::forbid_difficulty(&ast, ctx)?;
+        crate::passes::float_equality::run(&ast, ctx)?;
+        crate::passes::unreachable_code::run(&ast, ctx)?;
         crate::passes::evaluate_const_vars::run(ctx)?;
+        crate::passes::validate_difficulty::run_if_supported(&mut ast, ctx, &*format.language_hooks())?;
+        crate::passes::inline_funcs::run(&mut ast, ctx)?;
+        crate::passes::explode_diff_switches::run(&mut ast, ctx)?;
         crate::passes::const_simplify::run(&mut ast, ctx)?;
+        ctx.observers.after_pass("const_simplify", &ast)?;
+        crate::passes::unroll_times::run(&mut ast, ctx)?;
         crate::passes::desugar_blocks::run(&mut ast, ctx, language)?;
+        ctx.observers.after_pass("desugar_blocks", &ast)?;
         ast
     };
 
@@ -316,6 +393,7 @@ fn compile_std(
                     message("unexpected '{keyword}' in STD file"),
                     primary(keyword, "not valid in STD files"),
                 ))),
+                ast::Item::AnmScript { meta: Some(fields), .. } => return Err(emit(unsupported(&fields.span))),
                 ast::Item::AnmScript { number: Some(number), .. } => return Err(emit(error!(
                     message("unexpected numbered script in STD file"),
                     primary(number, "unexpected number"),
@@ -336,6 +414,7 @@ fn compile_std(
                     }
                 },
                 ast::Item::ConstVar { .. } => {},
+                ast::Item::AnmScriptAlias { .. } => return Err(emit(unsupported(&item.span))),
                 ast::Item::Timeline { .. } => return Err(emit(unsupported(&item.span))),
                 ast::Item::Func { .. } => return Err(emit(unsupported(&item.span))),
             }
@@ -358,6 +437,7 @@ fn compile_std(
         errors.set(e);
         (vec![], None) // dummy instructions so we can call lowerer.finish before returning
     });
+    ctx.observers.after_lowering(main_ident.as_str(), &out.script).unwrap_or_else(|e| errors.set(e));
 
     if let Some(lowering_info) = lowering_info {
         let export_info = debug_info::ScriptExportInfo {
@@ -425,8 +505,8 @@ fn write_std(
 ) -> WriteResult {
     let start_pos = f.pos()?;
 
-    f.write_u16(std.objects.len() as u16)?;
-    f.write_u16(std.objects.values().map(|x| x.quads.len()).sum::<usize>() as u16)?;
+    f.write_u16(fit_field(emitter, "num_objects", std.objects.len())?)?;
+    f.write_u16(fit_field(emitter, "num_quads", std.objects.values().map(|x| x.quads.len()).sum::<usize>())?)?;
 
     let instances_offset_pos = f.pos()?;
     f.write_u32(0)?;
@@ -443,29 +523,31 @@ fn write_std(
     }
 
     let mut object_offsets = vec![];
-    for (object_id, object) in std.objects.values().enumerate() {
-        object_offsets.push(f.pos()? - start_pos);
-        write_object(f, emitter, &*format, object_id, object)?;
+    for (object_id, (object_name, object)) in std.objects.iter().enumerate() {
+        emitter.chain_with(|fmt| write!(fmt, "in object {object_name}"), |emitter| {
+            object_offsets.push(fit_field::<u32, _>(emitter, "object offset", f.pos()? - start_pos)?);
+            write_object(f, emitter, &*format, object_id, object)
+        })?;
     }
 
-    let instances_offset = f.pos()? - start_pos;
+    let instances_offset = fit_field::<u32, _>(emitter, "instances_offset", f.pos()? - start_pos)?;
     for instance in &std.instances {
         write_instance(f, emitter, instance, &std.objects)?;
     }
     write_terminal_instance(f)?;
 
-    let script_offset = f.pos()? - start_pos;
+    let script_offset = fit_field::<u32, _>(emitter, "script_offset", f.pos()? - start_pos)?;
     let instr_format = format.language_hooks().instr_format();
     llir::write_instrs(f, emitter, instr_format, &std.script)?;
 
     let end_pos = f.pos()?;
     f.seek_to(instances_offset_pos)?;
-    f.write_u32(instances_offset as u32)?;
+    f.write_u32(instances_offset)?;
     f.seek_to(script_offset_pos)?;
-    f.write_u32(script_offset as u32)?;
+    f.write_u32(script_offset)?;
     f.seek_to(object_offsets_pos)?;
     for offset in object_offsets {
-        f.write_u32(offset as u32)?;
+        f.write_u32(offset)?;
     }
     f.seek_to(end_pos)?;
     Ok(())
@@ -593,17 +675,38 @@ fn read_instance(f: &mut BinReader, emitter: &impl Emitter, objects: &IndexMap<S
 }
 
 fn write_instance(f: &mut BinWriter, emitter: &dyn Emitter, inst: &Instance, objects: &IndexMap<Sp<Ident>, Object>) -> WriteResult {
-    match objects.get_index_of(&inst.object) {
-        Some(object_index) => f.write_u16(object_index as u16)?,
-        None => return Err(emitter.as_sized().emit(error!(
-            message("no object named {}", inst.object),
-            primary(&inst.object, "not an object"),
-        ))),
-    }
+    let object_index = match objects.get_index_of(&inst.object) {
+        Some(object_index) => object_index,
+        // older decompiled files can only refer to objects by their index through auto-generated names
+        None => match auto_object_index(&inst.object).filter(|&index| index < objects.len()) {
+            Some(object_index) => {
+                emitter.as_sized().emit(warning!(
+                    message("no object named {}; using the object at index {object_index}", inst.object),
+                    primary(&inst.object, "refers to an object by index"),
+                    note("this is deprecated; use the name of the object ('{}') instead", objects.get_index(object_index).unwrap().0),
+                )).ignore();
+                object_index
+            },
+            None => return Err(emitter.as_sized().emit(error!(
+                message("no object named {}", inst.object),
+                primary(&inst.object, "not an object"),
+            ))),
+        },
+    };
+    f.write_u16(object_index as u16)?;
     f.write_u16(inst.unknown)?;
     f.write_f32s(&inst.pos)?;
     Ok(())
 }
+/// Get the index from a name like `object3`, as generated by decompilation.
+fn auto_object_index(ident: &Ident) -> Option<usize> {
+    let digits = ident.as_str().strip_prefix("object")?;
+    match digits.starts_with('0') && digits.len() > 1 {
+        true => None,
+        false => digits.parse().ok(),
+    }
+}
+
 fn write_terminal_instance(f: &mut BinWriter) -> WriteResult {
     for _ in 0..4 {
         f.write_i32(-1)?;
@@ -611,6 +714,15 @@ fn write_terminal_instance(f: &mut BinWriter) -> WriteResult {
     Ok(())
 }
 
+/// Get the hooks for STD instructions in a game, for use by code outside this module.
+pub(crate) fn game_hooks(game: Game) -> Box<dyn LanguageHooks> {
+    if Game::Th095 <= game {
+        Box::new(StdHooks10)
+    } else {
+        Box::new(StdHooks06)
+    }
+}
+
 fn game_format(game: Game) -> Box<dyn FileFormat> {
     if Game::Th095 <= game {
         let hooks = StdHooks10;
@@ -734,13 +846,7 @@ impl LanguageHooks for StdHooks06 {
 
     fn has_registers(&self) -> bool { false }
 
-    fn encode_label(&self, _cur: raw::BytePos, dest_offset: raw::BytePos) -> raw::RawDwordBits {
-        assert_eq!(dest_offset % 20, 0);
-        (dest_offset / 20) as u32
-    }
-    fn decode_label(&self, _cur: raw::BytePos, bits: raw::RawDwordBits) -> raw::BytePos {
-        (bits * 20) as u64
-    }
+    fn label_encoding(&self) -> LabelEncoding { LabelEncoding::InstructionIndex }
 
     fn instr_format(&self) -> &dyn InstrFormat { self }
 }
@@ -748,6 +854,8 @@ impl LanguageHooks for StdHooks06 {
 impl InstrFormat for StdHooks06 {
     fn instr_header_size(&self) -> usize { 8 }
 
+    fn args_blob_size(&self) -> llir::ArgsBlobSize { llir::ArgsBlobSize::Exactly(12) }
+
     fn read_instr(&self, f: &mut BinReader, _: &dyn Emitter) -> ReadResult<ReadInstr> {
         let time = f.read_i32()?;
         let opcode = f.read_i16()?;
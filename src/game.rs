@@ -5,48 +5,95 @@ use crate::diagnostic::Diagnostic;
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Game {
     Th06, Th07, Th08, Th09, Th095, Th10, Alcostg, Th11, Th12,
-    Th125, Th128, Th13, Th14, Th143, Th15, Th16, Th165, Th17, Th18,
+    Th125, Th128, Th13, Th14, Th143, Th15, Th16, Th165, Th17, Th18, Th19,
 }
-macro_rules! max_game_str { () => { "th18" }; }
-
 impl std::str::FromStr for Game {
     type Err = Diagnostic;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if s == "alcostg" {
-            return Ok(Game::Alcostg);
+        let lowercase = s.trim().to_ascii_lowercase();
+        if let Some(game) = Game::iter().find(|game| game.named_aliases().any(|alias| alias == lowercase)) {
+            return Ok(game);
         }
 
-        let err_suffix = concat!("(valid games are th06 to ", max_game_str!(), ", or alcostg.  Point titles are written as e.g. th095)");
-        let invalid_game = || error!("game not invalid: {} {}", s, err_suffix);
-        let unsupported_pc98 = || error!("game not supported (PC-98): {} {}", s, err_suffix);
-        let unsupported_fighter = || error!("game not supported (fighter): {} {}", s, err_suffix);
-        let unknown_game = || error!("unknown game: {} {}", s, err_suffix);
+        let err_suffix = || format!("(valid games are: {})", Game::iter().map(|game| {
+            format!("{} [{}]", game.as_str(), game.aliases().into_iter().skip(1).collect::<Vec<_>>().join(", "))
+        }).collect::<Vec<_>>().join(", "));
+        let invalid_game = || error!("game not invalid: {} {}", s, err_suffix());
+        let unsupported_pc98 = || error!("game not supported (PC-98): {} {}", s, err_suffix());
+        let unsupported_fighter = || error!("game not supported (fighter): {} {}", s, err_suffix());
+        let unknown_game = || error!("unknown game: {} {}", s, err_suffix());
 
-        let s = s.strip_prefix("th").unwrap_or(s);
+        let s = lowercase.strip_prefix("th").unwrap_or(&lowercase);
         match s.parse::<u32>().map_err(|_| invalid_game())? {
             1 | 2 | 3 | 4 | 5 => Err(unsupported_pc98()),
             75 | 105 | 135 | 145 | 155 | 175 => Err(unsupported_fighter()),
-            6 => Ok(Game::Th06),
-            7 => Ok(Game::Th07),
-            8 => Ok(Game::Th08),
-            9 => Ok(Game::Th09),
-            95 => Ok(Game::Th095),
-            10 => Ok(Game::Th10),
-            103 => Ok(Game::Alcostg),
-            11 => Ok(Game::Th11),
-            12 => Ok(Game::Th12),
-            125 => Ok(Game::Th125),
-            128 => Ok(Game::Th128),
-            13 => Ok(Game::Th13),
-            14 => Ok(Game::Th14),
-            143 => Ok(Game::Th143),
-            15 => Ok(Game::Th15),
-            16 => Ok(Game::Th16),
-            165 => Ok(Game::Th165),
-            17 => Ok(Game::Th17),
-            18 => Ok(Game::Th18),
-            _ => Err(unknown_game()),
+            number => Game::iter().find(|game| game.as_number() == number).ok_or_else(unknown_game),
+        }
+    }
+}
+
+/// Every [`Game`], in the order of [`Ord`].
+static ALL_GAMES: &[Game] = &[
+    Game::Th06, Game::Th07, Game::Th08, Game::Th09, Game::Th095, Game::Th10, Game::Alcostg, Game::Th11, Game::Th12,
+    Game::Th125, Game::Th128, Game::Th13, Game::Th14, Game::Th143, Game::Th15, Game::Th16, Game::Th165, Game::Th17,
+    Game::Th18, Game::Th19,
+];
+
+/// # Names
+impl Game {
+    /// Iterate over all supported games, from oldest to newest.
+    pub fn iter() -> impl Iterator<Item=Game> + Clone {
+        ALL_GAMES.iter().copied()
+    }
+
+    /// Every identifier accepted by [`Game::from_str`][`std::str::FromStr::from_str`], grouped by game.
+    ///
+    /// Useful for building game pickers and shell completion.  Parsing is case-insensitive, but these are all
+    /// lowercase.  (numbers may also be written with a `th` prefix or extra leading zeros, e.g. `th103` or `th6`)
+    pub fn all_names() -> Vec<(Game, Vec<String>)> {
+        Game::iter().map(|game| (game, game.aliases())).collect()
+    }
+
+    /// All of the lowercase identifiers that parse into this game, starting with [`Self::as_str`].
+    pub fn aliases(self) -> Vec<String> {
+        let mut out = vec![self.as_str().to_string(), self.as_number().to_string()];
+        for alias in self.named_aliases() {
+            if !out.contains(&alias) {
+                out.push(alias);
+            }
+        }
+        out
+    }
+
+    /// Aliases that aren't numbers, in lowercase.
+    fn named_aliases(self) -> impl Iterator<Item=String> {
+        [self.abbr().to_ascii_lowercase(), self.title().to_ascii_lowercase()].into_iter()
+    }
+
+    /// The English title of the game, without the "Touhou" prefix.
+    pub fn title(self) -> &'static str {
+        match self {
+            Game::Alcostg => "Uwabami Breakers",
+            Game::Th06 => "Embodiment of Scarlet Devil",
+            Game::Th07 => "Perfect Cherry Blossom",
+            Game::Th08 => "Imperishable Night",
+            Game::Th09 => "Phantasmagoria of Flower View",
+            Game::Th095 => "Shoot the Bullet",
+            Game::Th10 => "Mountain of Faith",
+            Game::Th11 => "Subterranean Animism",
+            Game::Th12 => "Undefined Fantastic Object",
+            Game::Th125 => "Double Spoiler",
+            Game::Th128 => "Great Fairy Wars",
+            Game::Th13 => "Ten Desires",
+            Game::Th14 => "Double Dealing Character",
+            Game::Th143 => "Impossible Spell Card",
+            Game::Th15 => "Legacy of Lunatic Kingdom",
+            Game::Th16 => "Hidden Star in Four Seasons",
+            Game::Th165 => "Violet Detector",
+            Game::Th17 => "Wily Beast and Weakest Creature",
+            Game::Th18 => "Unconnected Marketeers",
+            Game::Th19 => "Unfinished Dream of All Living Ghost",
         }
     }
 }
@@ -73,6 +120,7 @@ impl Game {
             Game::Th165 => "VD",
             Game::Th17 => "WBaWC",
             Game::Th18 => "UM",
+            Game::Th19 => "UDoALG",
         }
     }
 
@@ -97,6 +145,7 @@ impl Game {
             Game::Th165 => "th165",
             Game::Th17 => "th17",
             Game::Th18 => "th18",
+            Game::Th19 => "th19",
         }
     }
 
@@ -121,6 +170,7 @@ impl Game {
             Game::Th165 => 165,
             Game::Th17 => 17,
             Game::Th18 => 18,
+            Game::Th19 => 19,
         }
     }
 }
@@ -131,6 +181,54 @@ impl fmt::Display for Game {
     }
 }
 
+/// A hard limit of a game, beyond which a compiled file is likely to crash it.
+///
+/// The known values are given by [`Game::limit`].  Mapfiles can override them in a `!limits` section
+/// for modded engines, with lines like `4096 anm_scripts`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(enum_map::Enum, strum::EnumIter, strum::Display, strum::EnumString)]
+pub enum Limit {
+    /// Number of scripts in an ANM file.
+    #[strum(serialize = "anm_scripts")] AnmScripts,
+    /// Number of sprites in an ANM file.
+    #[strum(serialize = "anm_sprites")] AnmSprites,
+    /// Number of subs in an ECL file.
+    #[strum(serialize = "ecl_subs")] EclSubs,
+}
+
+impl Limit {
+    pub fn iter() -> impl Iterator<Item=Limit> {
+        <Self as strum::IntoEnumIterator>::iter()
+    }
+
+    /// A description of the limited things, in plural. (`"ANM scripts"`...)
+    pub fn descr_plural(self) -> &'static str {
+        match self {
+            Limit::AnmScripts => "ANM scripts",
+            Limit::AnmSprites => "ANM sprites",
+            Limit::EclSubs => "ECL subs",
+        }
+    }
+}
+
+impl Game {
+    /// Get the value of a limit in this game, if it is known.
+    pub fn limit(self, limit: Limit) -> Option<u32> {
+        match limit {
+            // EoSD's AnmManager stores all loaded sprites and scripts in arrays of this size
+            Limit::AnmScripts | Limit::AnmSprites => match self {
+                Game::Th06 => Some(2048),
+                _ => None,
+            },
+            // the header of an early ECL file stores the sub count in a u16
+            Limit::EclSubs => match self {
+                Game::Th06 | Game::Th07 | Game::Th08 | Game::Th09 | Game::Th095 => Some(u16::MAX as u32),
+                _ => None,
+            },
+        }
+    }
+}
+
 /// Indicates a distinct instruction set that may appear in a game.
 ///
 /// Used as part of the key for e.g. opcode signatures.
@@ -138,7 +236,7 @@ impl fmt::Display for Game {
 /// This does not correspond 1-1 with truth's filetypes.  For instance, [`Self::Ecl`] and [`Self::Timeline`]
 /// are two distinct instruction sets that both appear in `.ecl` files, while "mission" files (`mission.msg`)
 /// do not have any instruction sets at all.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[derive(enum_map::Enum)]
 pub enum LanguageKey {
     Ecl, Anm, Msg, End, Std, Timeline,
@@ -158,4 +256,77 @@ impl LanguageKey {
             Self::Dummy => "Dummy Test Language",
         }
     }
+
+    /// The name used to explicitly qualify raw syntax with this language, as in `timeline.ins_30()`.
+    pub fn prefix_name(&self) -> &'static str {
+        match self {
+            Self::Ecl => "ecl",
+            Self::Timeline => "timeline",
+            Self::Anm => "anm",
+            Self::Std => "std",
+            Self::Msg => "msg",
+            Self::End => "end",
+            Self::Dummy => "dummy",
+        }
+    }
+
+    /// The names accepted by [`Self::from_prefix_name`].
+    pub const PREFIX_NAMES: &'static [&'static str] = &["ecl", "timeline", "anm", "std", "msg", "end"];
+
+    /// Look up a language by the name returned from [`Self::prefix_name`].
+    pub fn from_prefix_name(name: &str) -> Option<Self> {
+        match name {
+            "ecl" => Some(Self::Ecl),
+            "timeline" => Some(Self::Timeline),
+            "anm" => Some(Self::Anm),
+            "std" => Some(Self::Std),
+            "msg" => Some(Self::Msg),
+            "end" => Some(Self::End),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_are_unambiguous() {
+        let mut seen = std::collections::HashMap::new();
+        for (game, aliases) in Game::all_names() {
+            for alias in aliases {
+                if let Some(other) = seen.insert(alias.clone(), game) {
+                    panic!("alias {:?} is used by both {} and {}", alias, other, game);
+                }
+                assert_eq!(alias.parse::<Game>().unwrap(), game, "{:?}", alias);
+            }
+        }
+        // named aliases must never be mistaken for numbers
+        for game in Game::iter() {
+            for alias in game.named_aliases() {
+                assert!(alias.trim_start_matches("th").parse::<u32>().is_err(), "{:?}", alias);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_aliases() {
+        assert_eq!("17".parse::<Game>().unwrap(), Game::Th17);
+        assert_eq!("th17".parse::<Game>().unwrap(), Game::Th17);
+        assert_eq!("WBaWC".parse::<Game>().unwrap(), Game::Th17);
+        assert_eq!("Wily Beast and Weakest Creature".parse::<Game>().unwrap(), Game::Th17);
+        assert_eq!("th6".parse::<Game>().unwrap(), Game::Th06);
+        assert_eq!("th103".parse::<Game>().unwrap(), Game::Alcostg);
+        assert!("th20".parse::<Game>().is_err());
+        assert!("th075".parse::<Game>().is_err());
+    }
+
+    #[test]
+    fn iter_is_sorted() {
+        let games = Game::iter().collect::<Vec<_>>();
+        let mut sorted = games.clone();
+        sorted.sort();
+        assert_eq!(games, sorted);
+    }
 }
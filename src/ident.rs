@@ -1,5 +1,5 @@
 use std::fmt;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use thiserror::Error;
 
@@ -84,7 +84,7 @@ impl ResIdent {
 /// C-like identifiers.
 #[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Ident {
-    ident: Rc<str>,
+    ident: Arc<str>,
 }
 
 impl Ident {
@@ -253,6 +253,40 @@ impl fmt::Display for Ident {
 
 // =============================================================================
 
+// Both kinds of ident serialize as a plain string.  The ResId is not serialized, because it is only
+// meaningful to the CompilerContext that assigned it.
+#[cfg(feature = "serde")]
+const _: () = {
+    use serde::{Serialize, Serializer, Deserialize, Deserializer, de::Error as _};
+
+    impl Serialize for Ident {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.as_str().serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Ident {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let string = String::deserialize(deserializer)?;
+            Ident::new_system(&string).map_err(D::Error::custom)
+        }
+    }
+
+    impl Serialize for ResIdent {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            self.ident.serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ResIdent {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ident::deserialize(deserializer).map(ResIdent::new_null)
+        }
+    }
+};
+
+// =============================================================================
+
 /// Helper for generating unique identifiers.
 #[derive(Debug, Clone, Default)]
 pub struct GensymContext {
@@ -28,13 +28,28 @@ pub type Encoding = &'static encoding_rs::Encoding;
 
 pub use encoding_rs::SHIFT_JIS as DEFAULT_ENCODING;
 
+/// Look up an encoding by one of its WHATWG labels. (e.g. `"shift_jis"` or `"utf-8"`)
+///
+/// Encodings that [`encoding_rs`] can only decode (such as UTF-16) are not accepted.
+pub fn encoding_from_name(name: &str) -> Option<Encoding> {
+    encoding_rs::Encoding::for_label(name.as_bytes())
+        .filter(|&enc| enc.output_encoding() == enc)
+}
+
 impl Encoded {
     pub fn encode<S: AsRef<str> + ?Sized>(str: &Sp<S>, enc: Encoding) -> Result<Self, Diagnostic> {
         match enc.encode(str.value.as_ref()) {
-            (_, _, true) => Err(error!(
-                message("string encoding error"),
-                primary(str, "cannot be encoded using '{}'", enc.name()),
-            )),
+            (_, _, true) => {
+                let bad_char = str.value.as_ref().chars().find(|&c| enc.encode(c.encode_utf8(&mut [0; 4])).2);
+                let mut diag = error!(
+                    message("string encoding error"),
+                    primary(str, "cannot be encoded using '{}'", enc.name()),
+                );
+                if let Some(c) = bad_char {
+                    diag.note(format!("the character {:?} (U+{:04X}) does not exist in '{}'", c, c as u32, enc.name()));
+                }
+                Err(diag)
+            },
 
             (bytes, _, _) => Ok(Encoded(bytes.into_owned())),
         }
@@ -47,6 +62,14 @@ impl Encoded {
         }
     }
 
+    /// Decode the string, replacing any invalid sequences with `U+FFFD`.
+    ///
+    /// The flag is `true` if anything was replaced.
+    pub fn decode_lossy(&self, enc: Encoding) -> (String, bool) {
+        let (str, had_errors) = enc.decode_without_bom_handling(self.0.as_ref());
+        (str.into_owned(), had_errors)
+    }
+
     /// Encode into a fixed-size buffer.  The encoded bytes will by null-padded up to the specified length,
     /// or an error will be returned if the string is too long to fit with a null terminator.
     pub fn encode_fixed_size<S: AsRef<str> + ?Sized>(str: &Sp<S>, enc: Encoding, buf_size: usize) -> Result<Self, Diagnostic> {
@@ -392,6 +415,13 @@ pub trait BinRead {
         self._bin_read_reader().seek(SeekFrom::Start(offset)).map_err(|e| self._bin_read_io_error(e))?;
         Ok(())
     }
+    /// Get the length of the whole stream, without moving the current position.
+    fn stream_len(&mut self) -> Result<u64, Self::Err> {
+        let pos = self.pos()?;
+        let len = self._bin_read_reader().seek(SeekFrom::End(0)).map_err(|e| self._bin_read_io_error(e))?;
+        self.seek_to(pos)?;
+        Ok(len)
+    }
 }
 
 impl<'a, R: Read + Seek + ?Sized + 'a> BinReader<'a, R> {
@@ -414,6 +444,23 @@ pub fn hexify(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join("")
 }
 
+/// Convert an offset, size or count to the integer type of the binary field that it is written to.
+///
+/// A value that does not fit is an error naming the field, rather than silently wrapping around.
+/// The emitter should already identify the item being written.
+pub fn fit_field<T: BinField, V: TryInto<T> + std::fmt::Display + Copy>(emitter: &impl Emitter, field: &str, value: V) -> WriteResult<T> {
+    value.try_into().map_err(|_| emitter.emit(error!(
+        "value of '{field}' is too large for the file format ({value}, but the maximum is {})", T::MAX_VALUE,
+    )))
+}
+
+/// Integer types of fields in binary files, for [`fit_field`].
+pub trait BinField: Copy {
+    const MAX_VALUE: u64;
+}
+impl BinField for u16 { const MAX_VALUE: u64 = u16::MAX as u64; }
+impl BinField for u32 { const MAX_VALUE: u64 = u32::MAX as u64; }
+
 /// Helper trait to simplify functions that write to Touhou's binary script files.
 ///
 /// All functions read little endian (because all of the game's binary formats are little endian),
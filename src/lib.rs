@@ -35,6 +35,16 @@ pub mod passes;
 
 pub mod debug_info;
 
+pub mod symbols;
+
+pub mod navigate;
+
+pub mod bindiff;
+
+pub mod opcode_stats;
+
+pub mod script_stats;
+
 pub mod raw;
 
 pub use formats::anm::{self, AnmFile, WorkingAnmFile};
@@ -45,6 +55,7 @@ pub use formats::ecl::{self, OldeEclFile as EclFile};
 mod formats;
 
 mod bitset;
+mod fast_hash;
 mod diff_switch_utils;
 
 pub use game::{Game, LanguageKey};
@@ -53,7 +64,7 @@ mod game;
 #[doc(hidden)]
 pub mod cli_def;
 
-pub use resolve::{RegId, DefId};
+pub use resolve::{RegId, DefId, Namespace, Resolutions};
 mod resolve;
 
 mod image;
@@ -61,7 +72,7 @@ mod image;
 pub use io::Fs;
 pub mod io;
 
-pub use llir::DecompileOptions;
+pub use llir::{DecompileOptions, MapfilePaths};
 pub mod llir;
 
 pub mod vm;
@@ -69,7 +80,7 @@ pub mod vm;
 pub use value::{ScalarValue, ScalarType};
 mod value;
 
-pub use api::{Builder, Truth};
+pub use api::{Builder, Truth, FileFormat, BatchJob, BatchJobResult, BatchResults};
 mod api;
 
 pub trait VeclikeIterator: ExactSizeIterator + DoubleEndedIterator { }
@@ -33,14 +33,26 @@ pub struct InstrAbi {
 /// [`ScalarType`] tends to be more relevant for variables.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ArgEncoding {
-    /// `S` or `s` in mapfile. 4-byte or 2-byte integer immediate or register.  Displayed as signed.
+    /// `S`, `s`, `U` or `u` in mapfile. 4-byte integer immediate or register, or 2-byte integer immediate.
+    ///
+    /// `b` in mapfile is a 4-byte boolean, i.e. an `S` of the `bool` enum that decompiles to `true` and `false`.
+    ///
+    /// `s` and `u` are word-sized, and are respectively read as signed and unsigned 16-bit values.  A constant
+    /// that does not fit is an error.  Dword integers are always displayed as signed, even for `U`.
+    ///
+    /// Word-sized integers are never registers in any format.  (in EoSD ECL, their values are never checked for
+    /// register ids, and in formats with a parameter mask, a word-sized arg with its mask bit set can only be
+    /// decompiled using raw instruction syntax)
     ///
     /// May be decompiled as an enum or const based on its value.
     ///
+    /// `radix="hex"` (or `"signed-hex"`, `"bin"`, `"bool"`, `"dec"`) selects how an immediate value is displayed
+    /// when decompiling.  This has no effect on compilation.
+    ///
     /// The first argument may have `arg0` if it is two bytes large.  This indicates that the argument is
     /// stored in the arg0 header field of the instruction in EoSD and PCB ECL. (which is mapped to the
     /// `@arg0` pseudo-argument in raw instruction syntax)
-    Integer { size: u8, ty_color: Option<TypeColor>, arg0: bool },
+    Integer { size: u8, signed: bool, ty_color: Option<TypeColor>, arg0: bool, radix: ast::IntRadix },
     /// `o` in mapfile. Max of one per instruction. Is decoded to a label.
     JumpOffset,
     /// `t` in mapfile. Max of one per instruction, and requires an accompanying `o` arg.
@@ -85,7 +97,23 @@ pub enum StringArgSize {
 }
 
 impl ArgEncoding {
-    pub fn dword() -> Self { ArgEncoding::Integer { size: 4, ty_color: None, arg0: false } }
+    pub fn dword() -> Self { ArgEncoding::Integer { size: 4, signed: true, ty_color: None, arg0: false, radix: ast::IntRadix::Dec } }
+
+    /// Whether a register may be supplied for this argument in a language with registers.
+    pub fn can_be_register(&self) -> bool {
+        matches!(self, Self::Integer { arg0: false, size: 4, .. } | Self::Color | Self::Float)
+    }
+
+    /// The size of the argument in the argument blob, if it is fixed.
+    fn fixed_size(&self) -> Option<usize> {
+        match self {
+            Self::Integer { arg0: true, .. } => Some(0),
+            Self::Integer { size, .. } => Some(*size as usize),
+            Self::JumpOffset | Self::JumpTime | Self::Padding | Self::Color | Self::Float => Some(4),
+            Self::String { size: StringArgSize::Fixed { len, .. }, .. } => Some(*len),
+            Self::String { size: StringArgSize::Block { .. }, .. } => None,
+        }
+    }
 
     pub fn static_descr(&self) -> &'static str {
         match self {
@@ -107,13 +135,14 @@ impl ArgEncoding {
         impl fmt::Display for Impl<'_> {
             fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
                 match &self.0 {
-                    Enc::Integer { arg0: true, ty_color, size } => write!(
+                    Enc::Integer { arg0: true, ty_color, size, signed, radix } => write!(
                         f,
                         "{} (in timeline arg0)",
-                        Enc::Integer { arg0: false, ty_color: ty_color.clone(), size: *size }.descr(),
+                        Enc::Integer { arg0: false, ty_color: ty_color.clone(), size: *size, signed: *signed, radix: *radix }.descr(),
                     ),
                     Enc::Integer { ty_color: Some(en), size: 4, .. } => write!(f, "{}", en.descr()),
                     Enc::Integer { ty_color: Some(en), size, .. } => write!(f, "{size}-byte {}", en.descr()),
+                    Enc::Integer { ty_color: None, size: 2, signed: false, .. } => write!(f, "unsigned word-sized integer"),
                     Enc::Integer { ty_color: None, size: 2, .. } => write!(f, "word-sized integer"),
                     Enc::Integer { ty_color: None, size: 4, .. } => write!(f, "dword integer"),
                     Enc::Integer { ty_color: None, size, .. } => write!(f, "{size}-byte integer"),
@@ -157,6 +186,11 @@ impl InstrAbi {
         self.encodings.iter()
     }
 
+    /// The size of the argument blob produced by this signature, if it has no variable-length strings.
+    pub fn fixed_args_blob_size(&self) -> Option<usize> {
+        self.encodings.iter().map(|enc| enc.fixed_size()).sum()
+    }
+
     pub fn create_signature(&self, abi_span: Span, ctx: &mut CompilerContext) -> defs::Signature {
         abi_to_signature(self, abi_span, ctx)
     }
@@ -225,21 +259,35 @@ fn arg_encoding_from_attrs(param: &abi_ast::Param, emitter: &dyn Emitter) -> Res
 }
 
 fn int_from_attrs(param: &abi_ast::Param, emitter: &dyn Emitter) -> Result<Option<ArgEncoding>, ErrorReported> {
-    let (size, default_ty_color) = match param.format_char.value {
-        // FIXME: Uu should be unsigned but I'm not sure yet if I want  `i(signed)`, `i(unsigned)`, or `i(sign=1)`
-        'S' => (4u8, None),
-        's' => (2, None),
-        'U' => (4, None),
-        'u' => (2, None),
-        'n' => (4, Some(TypeColor::Enum(auto_enum_names::anm_sprite()))),
-        'N' => (4, Some(TypeColor::Enum(auto_enum_names::anm_script()))),
-        'E' => (4, Some(TypeColor::Enum(auto_enum_names::ecl_sub()))),
+    let (size, signed, default_ty_color, default_radix) = match param.format_char.value {
+        'S' => (4u8, true, None, ast::IntRadix::Dec),
+        's' => (2, true, None, ast::IntRadix::Dec),
+        'U' => (4, false, None, ast::IntRadix::Dec),
+        'u' => (2, false, None, ast::IntRadix::Dec),
+        'b' => (4, true, Some(TypeColor::Enum(auto_enum_names::bool())), ast::IntRadix::Bool),
+        'n' => (4, true, Some(TypeColor::Enum(auto_enum_names::anm_sprite())), ast::IntRadix::Dec),
+        'N' => (4, true, Some(TypeColor::Enum(auto_enum_names::anm_script())), ast::IntRadix::Dec),
+        'E' => (4, true, Some(TypeColor::Enum(auto_enum_names::ecl_sub())), ast::IntRadix::Dec),
         _ => return Ok(None),  // not an integer
     };
 
     param.clone().deserialize_attributes(emitter, |de| {
         let user_ty_color = de.accept_value("enum")?.map(|ident| TypeColor::Enum(ident.value));
         let arg0 = de.accept_flag("arg0")?;
+        let radix = match de.accept_value::<String>("radix")? {
+            None => default_radix,
+            Some(radix) => match &radix.value[..] {
+                "dec" => ast::IntRadix::Dec,
+                "hex" => ast::IntRadix::Hex,
+                "signed-hex" => ast::IntRadix::SignedHex,
+                "bin" => ast::IntRadix::Bin,
+                "bool" => ast::IntRadix::Bool,
+                _ => return Err(emitter.as_sized().emit(error!(
+                    message("unknown radix {:?}", radix.value),
+                    primary(radix, "expected one of: dec, hex, signed-hex, bin, bool"),
+                ))),
+            },
+        };
 
         if let Some(arg0_flag) = arg0 {
             if size != 2 {
@@ -252,8 +300,10 @@ fn int_from_attrs(param: &abi_ast::Param, emitter: &dyn Emitter) -> Result<Optio
 
         Ok(Some(ArgEncoding::Integer {
             size,
+            signed,
             ty_color: user_ty_color.or(default_ty_color),
             arg0: arg0.is_some(),
+            radix,
         }))
     })
 }
@@ -339,6 +389,20 @@ fn validate(abi_span: Span, encodings: &[ArgEncoding]) -> Result<(), Diagnostic>
         return err(format!("'z' or 'm' arguments with 'bs=' can only appear at the very end"));
     }
 
+    // dword args must be aligned, as in the game's structs.  (this can only be checked up to the first
+    // string of unknown length; fortunately, such strings are always last)
+    let mut offset = 0;
+    for enc in encodings {
+        let Some(size) = enc.fixed_size() else { break };
+        if size == 4 && offset % 4 != 0 {
+            return err(format!(
+                "{} at offset {offset} is not 4-byte aligned (word-sized arguments must come in pairs)",
+                enc.descr(),
+            ));
+        }
+        offset += size;
+    }
+
     let trailing_pad_count = encodings.iter().rev().take_while(|c| matches!(c, Enc::Padding)).count();
     let total_pad_count = encodings.iter().filter(|c| matches!(c, Enc::Padding)).count();
     if total_pad_count != trailing_pad_count {
@@ -365,10 +429,11 @@ fn abi_to_signature(abi: &InstrAbi, abi_span: Span, ctx: &mut CompilerContext<'_
                 | ArgEncoding::Color
                 => Info { ty: ScalarType::Int, default: None, reg_ok: true, ty_color: None },
 
-                | ArgEncoding::Integer { arg0: false, ref ty_color, .. }
+                | ArgEncoding::Integer { arg0: false, size: 4, ref ty_color, .. }
                 => Info { ty: ScalarType::Int, default: None, reg_ok: true, ty_color: ty_color.clone() },
 
-                | ArgEncoding::Integer { arg0: true, ref ty_color, .. }
+                // word-sized args are never registers
+                | ArgEncoding::Integer { ref ty_color, .. }
                 => Info { ty: ScalarType::Int, default: None, reg_ok: false, ty_color: ty_color.clone() },
 
                 | ArgEncoding::JumpOffset
@@ -436,6 +501,27 @@ mod tests {
         assert!(parse("Sm(bs=4;mask=0,0,0)S").is_err());
     }
 
+    #[test]
+    fn fixed_length_strings() {
+        assert!(parse("z(len=8)").is_ok());
+        assert!(parse("m(len=8;mask=0x77,0,0)").is_ok());
+        assert!(parse("m(len=8;nulless;mask=0x77,0,0)S").is_ok());
+        assert!(parse("m(len=8)").is_err());  // missing mask
+        assert!(parse("z(len=8;bs=4)").is_err());
+    }
+
+    #[test]
+    fn word_alignment() {
+        assert!(parse("ssS").is_ok());
+        assert!(parse("uuf").is_ok());
+        assert!(parse("s(arg0)S").is_ok());
+        assert!(parse("sS").is_err());
+        assert!(parse("Ssf").is_err());
+        assert!(parse("ssss").is_ok());
+        assert!(parse("sz(bs=4)").is_ok());
+        assert!(parse("Ss").is_ok());
+    }
+
     #[test]
     fn timeline_must_be_at_beginning() {
         assert!(parse("s(arg0)S").is_ok());
@@ -1,6 +1,4 @@
-use indexmap::IndexMap;
 use core::fmt;
-use std::collections::HashMap;
 
 use crate::raw;
 use crate::ast;
@@ -13,13 +11,14 @@ use crate::diagnostic::{Diagnostic, Emitter};
 use crate::pos::{Span, Sp, SourceStr};
 use crate::parse::abi::{abi_ast, AttributeDeserializer};
 use crate::value::{ScalarType, ScalarValue};
+use crate::fast_hash::{FastHashMap, FastIndexMap};
 
 /// Maps opcodes to and from intrinsics.
 #[derive(Debug, Default)] // Default is used by --no-intrinsics
 pub struct IntrinsicInstrs {
-    intrinsic_opcodes: IndexMap<IntrinsicInstrKind, raw::Opcode>,
-    intrinsic_abi_props: IndexMap<IntrinsicInstrKind, IntrinsicInstrAbiParts>,
-    opcode_intrinsics: IndexMap<raw::Opcode, Sp<IntrinsicInstrKind>>,
+    intrinsic_opcodes: FastIndexMap<IntrinsicInstrKind, raw::Opcode>,
+    intrinsic_abi_props: FastIndexMap<IntrinsicInstrKind, IntrinsicInstrAbiParts>,
+    opcode_intrinsics: FastIndexMap<raw::Opcode, Sp<IntrinsicInstrKind>>,
     alternatives: AlternativesInfo,
 }
 
@@ -30,8 +29,8 @@ impl IntrinsicInstrs {
     pub fn from_mapfiles(language: LanguageKey, defs: &context::Defs, emitter: &dyn Emitter) -> Result<Self, ErrorReported> {
         let iter_pairs = || defs.iter_intrinsic_instrs(language);
         // duplicates can be many-to-many so we iterate twice instead of making one map from the other
-        let opcode_intrinsics = iter_pairs().collect::<IndexMap<_, _>>();
-        let intrinsic_opcodes = iter_pairs().map(|(k, v)| (v.value, k)).collect::<IndexMap<_, _>>();
+        let opcode_intrinsics = iter_pairs().collect::<FastIndexMap<_, _>>();
+        let intrinsic_opcodes = iter_pairs().map(|(k, v)| (v.value, k)).collect::<FastIndexMap<_, _>>();
 
         let intrinsic_abi_props = {
             opcode_intrinsics.iter()
@@ -125,6 +124,59 @@ pub enum IntrinsicInstrKind {
     /// Args are passed in by setting global "arg" registers prior to this instruction.
     #[strum_discriminants(strum(serialize = "CallReg"))]
     CallReg,
+
+    /// Calls a sub in languages with a stack.
+    ///
+    /// Args are pushed onto the stack prior to this instruction.
+    #[strum_discriminants(strum(serialize = "Call"))]
+    Call,
+
+    /// Starts a sub running alongside the caller, like `foo(args) async;`.
+    ///
+    /// Args are pushed onto the stack prior to this instruction.
+    #[strum_discriminants(strum(serialize = "CallAsync"))]
+    CallAsync,
+
+    /// Like [`Self::CallAsync`] but runs the sub in a numbered slot, like `foo(args) async 3;`.
+    /// Takes the slot id as an int.
+    #[strum_discriminants(strum(serialize = "CallAsyncId"))]
+    CallAsyncId,
+
+    /// Like `return;`.  In languages with a stack, `return x;` pushes `x` first.
+    #[strum_discriminants(strum(serialize = "Return"))]
+    Return,
+
+    /// Reserves space on the stack for the locals of a sub.  Takes the size in bytes.
+    #[strum_discriminants(strum(serialize = "StackAlloc"))]
+    StackAlloc,
+
+    /// Pushes a value onto the stack.
+    #[strum_discriminants(strum(serialize = "Push"))]
+    Push(ScalarType),
+
+    /// Pops a value from the stack into a variable, like `a = <pop>;`.
+    #[strum_discriminants(strum(serialize = "Pop"))]
+    Pop(ScalarType),
+
+    /// Pops two values and pushes `a + b`.
+    #[strum_discriminants(strum(serialize = "StackBinOp"))]
+    StackBinOp(ast::BinOpKind, ScalarType),
+
+    /// Pops a value and pushes `sin(a)`.
+    #[strum_discriminants(strum(serialize = "StackUnOp"))]
+    StackUnOp(ast::UnOpKind, ScalarType),
+
+    /// Pops an int and jumps if it compares to zero using the operator.
+    /// (so `op="!="` is `if (<pop>) goto label @ t;`)
+    #[strum_discriminants(strum(serialize = "StackCondJmp"))]
+    StackCondJmp(ast::BinOpKind),
+
+    /// Sets the difficulty mask for all following instructions, in languages that don't store a mask
+    /// on each instruction.  Takes the mask as an int.
+    ///
+    /// Statements with difficulty labels are compiled into regions delimited by this instruction.
+    #[strum_discriminants(strum(serialize = "DifficultyMask"))]
+    DifficultyMask,
 }
 
 impl IntrinsicInstrKind {
@@ -141,6 +193,17 @@ impl IntrinsicInstrKind {
             Self::CondJmp { .. } => "conditional jump",
             Self::CondJmp2A { .. } => "dedicated cmp",
             Self::CondJmp2B { .. } => "conditional jump after cmp",
+            Self::Call { .. } => "call (stack-based)",
+            Self::CallAsync { .. } => "async call",
+            Self::CallAsyncId { .. } => "async call with id",
+            Self::Return { .. } => "return",
+            Self::StackAlloc { .. } => "stack alloc",
+            Self::Push { .. } => "push",
+            Self::Pop { .. } => "pop",
+            Self::StackBinOp { .. } => "stack binary op",
+            Self::StackUnOp { .. } => "stack unary op",
+            Self::StackCondJmp { .. } => "conditional jump after push",
+            Self::DifficultyMask { .. } => "difficulty mask",
         }
     }
 
@@ -157,6 +220,14 @@ impl IntrinsicInstrKind {
                     | it@I::InterruptLabel { .. }
                     | it@I::CountJmp { .. }
                     | it@I::CondJmp2A { .. }
+                    | it@I::Call { .. }
+                    | it@I::CallAsync { .. }
+                    | it@I::CallAsyncId { .. }
+                    | it@I::Return { .. }
+                    | it@I::StackAlloc { .. }
+                    | it@I::Push { .. }
+                    | it@I::Pop { .. }
+                    | it@I::DifficultyMask { .. }
                     => write!(f, "{}", it.static_descr()),
 
                     I::AssignOp(op, _ty) => write!(f, "{op} op"),
@@ -164,6 +235,9 @@ impl IntrinsicInstrKind {
                     I::UnOp(op, _ty) => write!(f, "unary {op} op"),
                     I::CondJmp(op, _ty) => write!(f, "conditional ({op}) jump"),
                     I::CondJmp2B(op) => write!(f, "conditional ({op}) jump after cmp"),
+                    I::StackBinOp(op, _ty) => write!(f, "stack binary {op} op"),
+                    I::StackUnOp(op, _ty) => write!(f, "stack unary {op} op"),
+                    I::StackCondJmp(op) => write!(f, "conditional ({op}) jump after push"),
                 }
             }
         }
@@ -295,7 +369,7 @@ impl IntrinsicAbiHelper<'_> {
     fn find_and_remove_sub_id(&self, arg_encodings: &mut Vec<(usize, &ArgEncoding)>) -> Result<usize, Diagnostic> {
         let data = Self::remove_first_where(arg_encodings, |&(_, enc)| {
             match enc {
-                ArgEncoding::Integer { size: _, ty_color: Some(TypeColor::Enum(enum_name)), arg0: false } => {
+                ArgEncoding::Integer { ty_color: Some(TypeColor::Enum(enum_name)), arg0: false, .. } => {
                     enum_name == &auto_enum_names::ecl_sub()
                 },
                 _ => false,
@@ -403,6 +477,34 @@ impl IntrinsicInstrAbiParts {
             I::CondJmp2B(_op) => {
                 out.jump = Some(helper.find_and_remove_jump(&mut encodings)?);
             },
+            I::Call => {
+                out.sub_id = Some(helper.find_and_remove_sub_id(&mut encodings)?);
+            },
+            I::CallAsync => {
+                out.sub_id = Some(helper.find_and_remove_sub_id(&mut encodings)?);
+            },
+            I::CallAsyncId => {
+                out.sub_id = Some(helper.find_and_remove_sub_id(&mut encodings)?);
+                out.plain_args.push(helper.remove_plain_arg(&mut encodings, ScalarType::Int)?);
+            },
+            I::Return => {},
+            I::StackAlloc => {
+                out.plain_args.push(helper.remove_plain_arg(&mut encodings, ScalarType::Int)?);
+            },
+            I::Push(ty) => {
+                out.plain_args.push(helper.remove_plain_arg(&mut encodings, ty)?);
+            },
+            I::Pop(ty) => {
+                out.outputs.push(helper.remove_out_arg(&mut encodings, ty)?);
+            },
+            I::StackBinOp(_op, _ty) => {},
+            I::StackUnOp(_op, _ty) => {},
+            I::StackCondJmp(_op) => {
+                out.jump = Some(helper.find_and_remove_jump(&mut encodings)?);
+            },
+            I::DifficultyMask => {
+                out.plain_args.push(helper.remove_plain_arg(&mut encodings, ScalarType::Int)?);
+            },
         };
 
         if let Some(&(index, encoding)) = encodings.get(0) {
@@ -441,7 +543,27 @@ fn intrinsic_from_attrs(
         Tag::Jmp => IKind::Jmp,
         Tag::CallEosd => IKind::CallEosd,
         Tag::CallReg => IKind::CallReg,
+        Tag::Call => IKind::Call,
+        Tag::CallAsync => IKind::CallAsync,
+        Tag::CallAsyncId => IKind::CallAsyncId,
+        Tag::Return => IKind::Return,
+        Tag::StackAlloc => IKind::StackAlloc,
+        Tag::Push => IKind::Push(read_type_attr(&mut deserializer)?),
+        Tag::Pop => IKind::Pop(read_type_attr(&mut deserializer)?),
+        Tag::StackBinOp => IKind::StackBinOp(read_op_attr(&mut deserializer)?, read_type_attr(&mut deserializer)?),
+        Tag::StackUnOp => IKind::StackUnOp(read_op_attr(&mut deserializer)?, read_type_attr(&mut deserializer)?),
+        Tag::StackCondJmp => {
+            let op = read_op_attr(&mut deserializer)?;
+            if !matches!(op, ast::BinOpKind::Ne | ast::BinOpKind::Eq) {
+                return Err(emitter.as_sized().emit(error!(
+                    message("nonsense operator for StackCondJmp"),
+                    primary(intrinsic_name.span, ""),
+                )))
+            }
+            IKind::StackCondJmp(op)
+        },
         Tag::InterruptLabel => IKind::InterruptLabel,
+        Tag::DifficultyMask => IKind::DifficultyMask,
         Tag::AssignOp => IKind::AssignOp(read_op_attr(&mut deserializer)?, read_type_attr(&mut deserializer)?),
         Tag::BinOp => IKind::BinOp(read_op_attr(&mut deserializer)?, read_type_attr(&mut deserializer)?),
         Tag::UnOp => IKind::UnOp(read_op_attr(&mut deserializer)?, read_type_attr(&mut deserializer)?),
@@ -524,6 +646,17 @@ impl std::fmt::Display for IntrinsicInstrKind {
             IKind::CondJmp(op, ty) => write!(f, r#"{tag}(op="{op}"; type="{}")"#, render_ty(ty)),
             IKind::CondJmp2A(ty) => write!(f, r#"{tag}(type="{}")"#, render_ty(ty)),
             IKind::CondJmp2B(op) => write!(f, r#"{tag}(op="{op}")"#),
+            IKind::Call => write!(f, r#"{tag}()"#),
+            IKind::CallAsync => write!(f, r#"{tag}()"#),
+            IKind::CallAsyncId => write!(f, r#"{tag}()"#),
+            IKind::Return => write!(f, r#"{tag}()"#),
+            IKind::StackAlloc => write!(f, r#"{tag}()"#),
+            IKind::Push(ty) => write!(f, r#"{tag}(type="{}")"#, render_ty(ty)),
+            IKind::Pop(ty) => write!(f, r#"{tag}(type="{}")"#, render_ty(ty)),
+            IKind::StackBinOp(op, ty) => write!(f, r#"{tag}(op="{op}"; type="{}")"#, render_ty(ty)),
+            IKind::StackUnOp(op, ty) => write!(f, r#"{tag}(op="{op}"; type="{}")"#, render_ty(ty)),
+            IKind::StackCondJmp(op) => write!(f, r#"{tag}(op="{op}")"#),
+            IKind::DifficultyMask => write!(f, r#"{tag}()"#),
         }
     }
 }
@@ -541,11 +674,11 @@ pub mod alternatives {
     /// down to multiplication by -1; etc.)
     #[derive(Debug, Clone, Default)]
     pub struct AlternativesInfo {
-        pub unops: HashMap<(ast::UnOpKind, ScalarType), UnOp>,
-        pub assign_ops: HashMap<(ast::AssignOpKind, ScalarType), AssignOp>,
-        pub cond_jmps: HashMap<(ast::BinOpKind, ScalarType), CondJmp>,
+        pub unops: FastHashMap<(ast::UnOpKind, ScalarType), UnOp>,
+        pub assign_ops: FastHashMap<(ast::AssignOpKind, ScalarType), AssignOp>,
+        pub cond_jmps: FastHashMap<(ast::BinOpKind, ScalarType), CondJmp>,
         pub preferred_count_jmp: Option<CountJmpKind>,
-        pub count_jmps: HashMap<CountJmpKind, CountJmp>,
+        pub count_jmps: FastHashMap<CountJmpKind, CountJmp>,
     }
 
     #[derive(Debug, Clone)]
@@ -611,21 +744,12 @@ pub mod alternatives {
     }
 
     impl CountJmpKind {
-        pub fn render_suggestion<'a>(&self, var: &'a ast::Var) -> impl fmt::Display + 'a {
-            struct DisplayWrapper<'b>(CountJmpKind, &'b ast::Var);
-
-            impl fmt::Display for DisplayWrapper<'_> {
-                fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-                    let &DisplayWrapper(kind, var) = self;
-                    let var_str = crate::fmt::stringify(var);
-                    match kind {
-                        CountJmpKind::PredecNeZero => write!(f, "--{var_str}"),
-                        CountJmpKind::PredecGtZero => write!(f, "--{var_str} > 0"),
-                    }
-                }
+        pub fn render_suggestion(&self, var: &ast::Var, ctx: &context::CompilerContext<'_>) -> String {
+            let var_str = ctx.stringify_for_diagnostic(var);
+            match self {
+                CountJmpKind::PredecNeZero => format!("--{var_str}"),
+                CountJmpKind::PredecGtZero => format!("--{var_str} > 0"),
             }
-
-            DisplayWrapper(*self, var)
         }
     }
 
@@ -635,17 +759,17 @@ pub mod alternatives {
     }
 
     pub fn discover_alternatives(
-        intrinsic_opcodes: &IndexMap<IntrinsicInstrKind, raw::Opcode>,
+        intrinsic_opcodes: &FastIndexMap<IntrinsicInstrKind, raw::Opcode>,
     ) -> AlternativesInfo {
         use IntrinsicInstrKind as I;
         use ast::UnOpKind as U;
         use ast::BinOpKind as B;
         use ScalarType as Ty;
 
-        let mut unops = HashMap::new();
-        let mut assign_ops = HashMap::new();
-        let mut cond_jmps = HashMap::new();
-        let mut count_jmps = HashMap::new();
+        let mut unops = FastHashMap::default();
+        let mut assign_ops = FastHashMap::default();
+        let mut cond_jmps = FastHashMap::default();
+        let mut count_jmps = FastHashMap::default();
         let mut preferred_count_jmp = None;
 
         // Begin with less preferrable alternatives. They'll get overwritten by later alternatives.
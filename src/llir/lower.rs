@@ -1,26 +1,30 @@
 use indexmap::IndexMap;
 
 use super::{
-    unsupported, SimpleArg, RawInstr, LanguageHooks, IntrinsicInstrs,
-    ArgEncoding, StringArgSize, ScalarType,
+    unsupported, SimpleArg, InstrAbi, RawInstr, LanguageHooks, IntrinsicInstrs,
+    ArgEncoding, StringArgSize, ScalarType, LabelEncoding, ArgsBlobSize, RegisterEncodingStyle,
 };
 
 use crate::raw;
 use crate::ast;
-use crate::diagnostic::Emitter;
+use crate::diagnostic::{Emitter, RootEmitter};
 use crate::error::{GatherErrorIteratorExt, ErrorReported};
-use crate::pos::{Sp};
+use crate::pos::{Sp, Span};
 use crate::resolve::{DefId};
 use crate::ident::{Ident};
 use crate::context::{self, CompilerContext};
-use crate::io::{Encoded, DEFAULT_ENCODING};
+use crate::io::Encoded;
 use crate::value::{ScalarValue};
 use crate::passes::semantics::time_and_difficulty::TimeAndDifficulty;
 use crate::diff_switch_utils as ds_util;
 use crate::debug_info;
 
 mod stackless;
+mod stackful;
 mod intrinsic;
+mod peephole;
+mod difficulty_mask;
+mod lints;
 
 /// An intermediate representation that is only used during lowering.
 ///
@@ -46,7 +50,11 @@ struct LowerInstr {
     /// Value provided by user via an explicit `@arg0=`.
     explicit_extra_arg: Option<raw::ExtraArg>,
     /// Value provided by user via `@mask=`, which will override the automatically-computed param mask.
-    user_param_mask: Option<raw::ParamMask>,
+    user_param_mask: Option<Sp<raw::ParamMask>>,
+    /// Number of stack values consumed by the instruction, from `@pop=` or a stack call.
+    stack_pop: raw::StackPop,
+    /// Value provided by user via `@nargs=`, which will override the automatically-computed argument count.
+    explicit_arg_count: Option<raw::ArgCount>,
     /// Mask of enabled difficulties.
     // difficulty_mask: u8,
     args: LowerArgs,
@@ -80,9 +88,21 @@ enum LowerArg {
     Label(Ident),
     /// A `timeof(label)` that has not yet been converted to an integer argument.
     TimeOf(Ident),
+    /// An `offsetof(script.label)` or `timeof(script.label)`, which can only be converted once every script
+    /// has been laid out.
+    ForeignLabel { script: Sp<Ident>, label: Sp<Ident>, keyword: ast::LabelPropertyKeyword },
 }
 
 impl LowerArg {
+    /// Lower `offsetof(label)`, `timeof(label)`, or their forms for a label in another script.
+    fn from_label_property(script: &Option<Sp<Ident>>, label: &Sp<Ident>, keyword: ast::LabelPropertyKeyword) -> Self {
+        match (script, keyword) {
+            (Some(script), _) => LowerArg::ForeignLabel { script: script.clone(), label: label.clone(), keyword },
+            (None, token![timeof]) => LowerArg::TimeOf(label.value.clone()),
+            (None, token![offsetof]) => LowerArg::Label(label.value.clone()),
+        }
+    }
+
     /// Call this at a time when the arg is known to have a fully resolved value.
     ///
     /// Such times are:
@@ -104,7 +124,8 @@ impl LowerArg {
             LowerArg::Local { def_id, .. } => LowerArg::Local { def_id, storage_ty: ScalarType::Int },
 
             LowerArg::Label { .. } |
-            LowerArg::TimeOf { .. } => panic!("not a register: {:?}", self),
+            LowerArg::TimeOf { .. } |
+            LowerArg::ForeignLabel { .. } => panic!("not a register: {:?}", self),
 
             LowerArg::DiffSwitch { .. } => panic!("difficulty switch should be handled earler!"),
         }
@@ -127,6 +148,10 @@ impl From<ScalarValue> for LowerArg {
 pub struct Lowerer<'a> {
     hooks: &'a dyn LanguageHooks,
     sub_info: Option<SubInfo<'a>>,
+    /// Names of the scripts given to [`Self::lower_subs`], if lints are enabled.
+    lint_script_names: Option<Vec<Span>>,
+    /// Names of the scripts given to [`Self::lower_subs`], if they can refer to each other's labels.
+    script_names: Option<Vec<Sp<Ident>>>,
     // NOTE: later this can become Box<dyn Trait> and just let the implementations downcast
     inner: stackless::PersistentState,
 }
@@ -148,7 +173,23 @@ impl Drop for Lowerer<'_> {
 
 impl<'a> Lowerer<'a> {
     pub fn new(hooks: &'a dyn LanguageHooks) -> Self {
-        Lowerer { hooks, inner: Default::default(), sub_info: None }
+        Lowerer { hooks, inner: Default::default(), sub_info: None, lint_script_names: None, script_names: None }
+    }
+
+    /// Warn about scripts that are likely to hang the game, such as loops that never wait.
+    ///
+    /// `script_names` must hold one span for each item that will be given to [`Self::lower_subs`].
+    pub fn with_script_lints(mut self, script_names: Vec<Span>) -> Self {
+        self.lint_script_names = Some(script_names);
+        self
+    }
+
+    /// Allow the scripts to refer to each other's labels, as in `offsetof(script.label)`.
+    ///
+    /// `script_names` must hold one name for each item that will be given to [`Self::lower_subs`].
+    pub fn with_script_names(mut self, script_names: Vec<Sp<Ident>>) -> Self {
+        self.script_names = Some(script_names);
+        self
     }
 
     /// Add information about exported subroutines, in languages that support calls.
@@ -166,10 +207,57 @@ impl<'a> Lowerer<'a> {
         &mut self,
         code: &[Sp<ast::Stmt>],
         def_id: Option<DefId>,
-        ctx: &mut CompilerContext<'_>,
+        ctx: &CompilerContext<'_>,
+        do_debug_info: bool,
+    ) -> Result<LoweredSub, ErrorReported> {
+        self.lower_subs(&[(code, def_id)], ctx, do_debug_info).pop().unwrap()
+    }
+
+    /// Compile many subs or script bodies, using up to [`CompilerContext::num_threads`] threads.
+    ///
+    /// Each item holds the arguments to [`Self::lower_sub`].  The results are in the same order,
+    /// and the diagnostics from each stage of lowering are emitted in order of their spans regardless of
+    /// the number of threads.
+    pub fn lower_subs(
+        &mut self,
+        subs: &[(&[Sp<ast::Stmt>], Option<DefId>)],
+        ctx: &CompilerContext<'_>,
         do_debug_info: bool,
-    ) -> Result<(Vec<RawInstr>, Option<debug_info::ScriptLoweringInfo>), ErrorReported> {
-        lower_sub_ast_to_instrs(self, code, def_id, ctx, do_debug_info)
+    ) -> Vec<Result<LoweredSub, ErrorReported>> {
+        let (hooks, sub_info) = (self.hooks, self.sub_info.as_ref());
+        let lint_script_names = self.lint_script_names.as_deref();
+        let jobs = subs.iter().enumerate().collect::<Vec<_>>();
+        let outputs = ctx.map_in_parallel(&jobs, |&(index, &(code, def_id)), emitter| {
+            let mut persistent_state = Default::default();
+            let lint_span = lint_script_names.map(|names| names[index]);
+            let result = lower_sub_ast_to_layout(hooks, sub_info, &mut persistent_state, code, def_id, lint_span, ctx, emitter, do_debug_info);
+            (result, persistent_state)
+        });
+        let laid_out_subs = outputs.into_iter().map(|(result, persistent_state)| {
+            self.inner.merge(persistent_state);
+            result
+        }).collect::<Vec<_>>();
+
+        // labels can only be encoded once the offsets in every script are known
+        let foreign_labels = self.script_names.as_ref().map(|names| {
+            let mut scripts = IndexMap::new();
+            for (name, laid_out) in names.iter().zip(&laid_out_subs) {
+                scripts.entry(&name.value).or_insert((name, laid_out.as_ref().map(|sub| &sub.label_info).map_err(|&e| e)));
+            }
+            scripts
+        });
+        let instrs = ctx.map_in_parallel(&laid_out_subs, |laid_out, emitter| {
+            let laid_out = laid_out.as_ref().map_err(|&e| e)?;
+            encode_laid_out_sub(hooks, laid_out, foreign_labels.as_ref(), ctx, emitter)
+        });
+        laid_out_subs.into_iter().zip(instrs).map(|(laid_out, instrs)| {
+            let LaidOutSub { debug_info_registers, debug_info_labels, .. } = laid_out?;
+            let debug_info = do_debug_info.then(|| debug_info::ScriptLoweringInfo {
+                register_info: debug_info_registers.unwrap(),
+                offset_info: debug_info_labels.unwrap(),
+            });
+            Ok((instrs?, debug_info))
+        }).collect()
     }
 
     /// Report any errors that can only be reported once all functions have been compiled.
@@ -180,43 +268,111 @@ impl<'a> Lowerer<'a> {
     }
 }
 
-fn lower_sub_ast_to_instrs(
-    lowerer: &mut Lowerer,
+/// The instructions of a compiled sub or script, and its debug info if requested.
+pub type LoweredSub = (Vec<RawInstr>, Option<debug_info::ScriptLoweringInfo>);
+
+/// A sub or script whose instructions have been laid out, but whose labels are not yet encoded.
+struct LaidOutSub {
+    out: Vec<Sp<LowerStmt>>,
+    label_info: LabelInfoverse,
+    debug_info_registers: Option<debug_info::ScriptRegisterInfo>,
+    debug_info_labels: Option<debug_info::ScriptOffsetInfo>,
+}
+
+/// The labels of every script lowered together, by script name.
+type ForeignLabels<'a> = IndexMap<&'a Ident, (&'a Sp<Ident>, Result<&'a LabelInfoverse, ErrorReported>)>;
+
+fn lower_sub_ast_to_layout(
+    hooks: &dyn LanguageHooks,
+    sub_info: Option<&SubInfo<'_>>,
+    persistent_state: &mut stackless::PersistentState,
     code: &[Sp<ast::Stmt>],
     def_id: Option<DefId>,
-    ctx: &mut CompilerContext<'_>,
+    lint_span: Option<Span>,
+    ctx: &CompilerContext<'_>,
+    emitter: &RootEmitter,
     do_debug_info: bool,
-) -> Result<(Vec<RawInstr>, Option<debug_info::ScriptLoweringInfo>), ErrorReported> {
-    use stackless::{SingleSubLowerer, assign_registers};
-
-    let hooks = lowerer.hooks;
-    let mut sub_lowerer = SingleSubLowerer {
-        out: vec![],
-        intrinsic_instrs: IntrinsicInstrs::from_mapfiles(hooks.language(), &ctx.defs, ctx.emitter)?,
-        stmt_data: crate::passes::semantics::time_and_difficulty::run(code, &ctx.emitter)?,
-        sub_info: lowerer.sub_info.as_ref(),
-        ctx,
-        hooks,
-    };
-    sub_lowerer.lower_sub_ast(code)?;
-    let mut out = sub_lowerer.out;
+) -> Result<LaidOutSub, ErrorReported> {
+    let intrinsic_instrs = IntrinsicInstrs::from_mapfiles(hooks.language(), &ctx.defs, emitter)?;
+    let stmt_data = crate::passes::semantics::time_and_difficulty::run(code, emitter)?;
+    if ctx.warn_goto_times {
+        crate::passes::validate_goto_times::run(code, &stmt_data, emitter);
+    }
 
-    // And now postprocess
-    let debug_info_registers = assign_registers(
-        &mut out, &mut lowerer.inner, hooks, lowerer.sub_info.as_ref(), def_id, &ctx, do_debug_info,
-    )?;
+    let (mut out, intrinsic_instrs, debug_info_registers) = match hooks.has_stack() {
+        true => {
+            let mut sub_lowerer = stackful::SingleSubLowerer {
+                out: vec![],
+                gensym: Default::default(),
+                frame: Default::default(),
+                debug_info: do_debug_info.then(|| debug_info::ScriptRegisterInfo {
+                    locals: vec![],
+                    explicit_registers: vec![],
+                    scratch_registers: vec![],
+                }),
+                intrinsic_instrs, stmt_data, sub_info, ctx, emitter, hooks,
+            };
+            sub_lowerer.lower_sub_ast(code, def_id)?;
+            let mut debug_info = sub_lowerer.debug_info;
+            if let Some(debug_info) = &mut debug_info {
+                let explicitly_used_regs = stackless::get_explicitly_used_regs(&sub_lowerer.out);
+                debug_info.explicit_registers = explicitly_used_regs.keys().map(|reg| reg.0).collect();
+            }
+            (sub_lowerer.out, sub_lowerer.intrinsic_instrs, debug_info)
+        },
+        false => {
+            use stackless::{SingleSubLowerer, Temporaries, assign_registers};
+
+            let mut sub_lowerer = SingleSubLowerer {
+                out: vec![],
+                gensym: Default::default(),
+                temporaries: Temporaries::new(ctx),
+                intrinsic_instrs, stmt_data, sub_info, ctx, emitter, hooks,
+            };
+            sub_lowerer.lower_sub_ast(code)?;
+            let SingleSubLowerer { mut out, temporaries, intrinsic_instrs, .. } = sub_lowerer;
+
+            // And now postprocess
+            peephole::run(&mut out, &intrinsic_instrs, &temporaries);
+            let debug_info_registers = assign_registers(
+                &mut out, persistent_state, hooks, sub_info, def_id, ctx, emitter, &temporaries, do_debug_info,
+            )?;
+            (out, intrinsic_instrs, debug_info_registers)
+        },
+    };
 
     // This can't happen before register assignment or we might allocate something multiple times
     out = elaborate_diff_switches(out, &ctx.diff_flag_defs);
+    out = difficulty_mask::run(out, &intrinsic_instrs, emitter)?;
+
+    let (label_info, debug_info_labels) = gather_label_info(hooks, 0, &out, &ctx.defs, emitter, do_debug_info)?;
 
-    let (label_info, debug_info_labels) = gather_label_info(hooks, 0, &out, &ctx.defs, &ctx.emitter, do_debug_info)?;
-    encode_labels(&mut out, hooks, &label_info, &ctx.emitter)?;
+    // only lint code that lowered successfully, so that errors aren't buried in warnings about half-built code
+    if let Some(script_span) = lint_span {
+        lints::run(hooks, &out, &intrinsic_instrs, script_span, ctx, emitter);
+    }
+    if ctx.warn_time_decreases {
+        lints::check_time_decreases(&out, &intrinsic_instrs, emitter);
+    }
+
+    Ok(LaidOutSub { out, label_info, debug_info_registers, debug_info_labels })
+}
+
+fn encode_laid_out_sub(
+    hooks: &dyn LanguageHooks,
+    laid_out: &LaidOutSub,
+    foreign_labels: Option<&ForeignLabels<'_>>,
+    ctx: &CompilerContext<'_>,
+    emitter: &RootEmitter,
+) -> Result<Vec<RawInstr>, ErrorReported> {
+    let mut out = laid_out.out.clone();
+    encode_labels(&mut out, ctx.defs.label_encoding(hooks), &laid_out.label_info, foreign_labels, emitter)?;
 
     let mut encoding_state = ArgEncodingState::new();
     let instrs = out.into_iter().filter_map(|x| match x.value {
         LowerStmt::Instr(instr) => Some({
             // this is the second time we're using encode_args (first time was to get labels), so suppress warnings
-            let null_emitter = ctx.emitter.with_writer(crate::diagnostic::dev_null());
+            let null_emitter = emitter.with_writer(crate::diagnostic::dev_null());
             encode_args(&mut encoding_state, hooks, &instr, &ctx.defs, &null_emitter)
                 .expect("we encoded this successfully before!")
         }),
@@ -224,12 +380,7 @@ fn lower_sub_ast_to_instrs(
         LowerStmt::RegAlloc { .. } => None,
         LowerStmt::RegFree { .. } => None,
     }).collect();
-    let debug_info = do_debug_info.then(|| debug_info::ScriptLoweringInfo {
-        register_info: debug_info_registers.unwrap(),
-        offset_info: debug_info_labels.unwrap(),
-    });
-
-    Ok((instrs, debug_info))
+    Ok(instrs)
 }
 
 // =============================================================================
@@ -302,6 +453,8 @@ fn select_diff_for_lower_arg(arg: &Sp<LowerArg>, difficulty: u32) -> Sp<LowerArg
 
 struct LabelInfoverse {
     stmt_offsets: Vec<raw::BytePos>,
+    /// Offsets of the statements that are instructions.
+    instr_offsets: Vec<raw::BytePos>,
     labels: IndexMap<Sp<Ident>, RawLabelInfo>,
 }
 struct RawLabelInfo {
@@ -340,8 +493,12 @@ fn gather_label_info(
     let instr_format = hooks.instr_format();
 
     let mut offset = initial_offset;
+    // for instructions with no span of their own (e.g. from an AST built by code); use the nearest
+    // earlier statement that has one
+    let mut enclosing_span = Span::NULL;
     let mut labels = IndexMap::new();
     let mut stmt_offsets = vec![];
+    let mut instr_offsets = vec![];
     let mut debug_info_instrs = do_debug_info.then(|| vec![]);
     let mut debug_info_labels = do_debug_info.then(|| vec![]);
 
@@ -349,16 +506,29 @@ fn gather_label_info(
 
     code.iter().enumerate().map(|(index, stmt)| {
         stmt_offsets.push(offset);
+        if stmt.span != Span::NULL {
+            enclosing_span = stmt.span;
+        }
         match stmt.value {
             LowerStmt::Instr(ref instr) => {
+                instr_offsets.push(offset);
                 emitter.chain_with(|f| write!(f, "in instruction {index}"), |emitter| {
-                    if let Some(debug_info_instrs) = &mut debug_info_instrs {
-                        debug_info_instrs.push(debug_info::Instr { offset, span: stmt.span.into() });
-                    }
                     // encode the instruction with dummy values
                     let same_size_instr = substitute_dummy_args(instr);
                     let raw_instr = encode_args(&mut encoding_state, hooks, &same_size_instr, defs, emitter)?;
-                    offset += instr_format.instr_size(&raw_instr) as u64;
+                    let max_args_len = instr_format.max_args_blob_len();
+                    if raw_instr.args_blob.len() > max_args_len {
+                        return Err(emitter.emit(error!(
+                            message("instruction too large"),
+                            primary(enclosing_span, "arguments take {} bytes", raw_instr.args_blob.len()),
+                            note("in {}, an instruction header can only describe up to {max_args_len} bytes of arguments", hooks.language().descr()),
+                        )));
+                    }
+                    let size = instr_format.instr_size(&raw_instr) as u64;
+                    if let Some(debug_info_instrs) = &mut debug_info_instrs {
+                        debug_info_instrs.push(debug_info::Instr { offset, size, span: enclosing_span.into() });
+                    }
+                    offset += size;
                     Ok(())
                 })?;
             },
@@ -393,7 +563,7 @@ fn gather_label_info(
         labels: debug_info_labels.unwrap(),
         end_offset: offset,
     });
-    let output = LabelInfoverse { labels, stmt_offsets };
+    let output = LabelInfoverse { labels, stmt_offsets, instr_offsets };
 
     Ok((output, debug_info))
 }
@@ -401,11 +571,12 @@ fn gather_label_info(
 /// Eliminates all `LowerArg::Label`s by replacing them with their dword values.
 fn encode_labels(
     code: &mut [Sp<LowerStmt>],
-    hooks: &dyn LanguageHooks,
+    label_encoding: LabelEncoding,
     label_info: &LabelInfoverse,
+    foreign_labels: Option<&ForeignLabels<'_>>,
     emitter: &context::RootEmitter,
 ) -> Result<(), ErrorReported> {
-    let LabelInfoverse { labels, stmt_offsets } = label_info;
+    let LabelInfoverse { labels, stmt_offsets, instr_offsets } = label_info;
 
     assert_eq!(code.len(), stmt_offsets.len());
     code.iter_mut().enumerate().map(|(stmt_index, stmt)| {
@@ -417,7 +588,7 @@ fn encode_labels(
                     | LowerArg::TimeOf(ref label)
                     => match labels.get(label) {
                         Some(info) => match arg.value {
-                            LowerArg::Label(_) => arg.value = LowerArg::Raw((hooks.encode_label(cur_offset, info.offset) as i32).into()),
+                            LowerArg::Label(_) => arg.value = LowerArg::Raw((label_encoding.encode(&instr_offsets, cur_offset, info.offset) as i32).into()),
                             LowerArg::TimeOf(_) => arg.value = LowerArg::Raw(info.time.into()),
                             _ => unreachable!(),
                         },
@@ -427,6 +598,10 @@ fn encode_labels(
                         })),
                     },
 
+                    LowerArg::ForeignLabel { ref script, ref label, keyword } => {
+                        arg.value = encode_foreign_label(script, label, keyword, label_encoding, foreign_labels, emitter)?;
+                    },
+
                     _ => {},
                 } // match arg.value
             } // for arg in args
@@ -435,6 +610,48 @@ fn encode_labels(
     }).collect_with_recovery()
 }
 
+/// Look up the value of an `offsetof(script.label)` or `timeof(script.label)`.
+///
+/// The offset is relative to the beginning of the other script, so this is only possible for label
+/// encodings that don't depend on the offset of the current instruction.
+fn encode_foreign_label(
+    script: &Sp<Ident>,
+    label: &Sp<Ident>,
+    keyword: ast::LabelPropertyKeyword,
+    label_encoding: LabelEncoding,
+    foreign_labels: Option<&ForeignLabels<'_>>,
+    emitter: &context::RootEmitter,
+) -> Result<LowerArg, ErrorReported> {
+    let Some(foreign_labels) = foreign_labels else {
+        return Err(emitter.emit(error!(
+            message("labels in other scripts are not supported in this language"),
+            primary(script, "refers to another script"),
+        )));
+    };
+    let &(script_name, ref label_info) = foreign_labels.get(&script.value).ok_or_else(|| emitter.emit(error!(
+        message("no script named '{script}' in this file"),
+        primary(script, "not a script in this file"),
+    )))?;
+    let LabelInfoverse { labels, instr_offsets, .. } = (*label_info)?;  // errors were already reported
+    let info = labels.get(&label.value).ok_or_else(|| emitter.emit(error!(
+        message("undefined label '{label}' in script '{script}'"),
+        primary(label, "there is no label by this name in '{script}'"),
+        secondary(script_name, "script defined here"),
+    )))?;
+
+    match keyword {
+        token![timeof] => Ok(LowerArg::Raw(info.time.into())),
+        token![offsetof] => match label_encoding {
+            LabelEncoding::RelativeByteOffset => Err(emitter.emit(error!(
+                message("cannot take the offset of a label in another script"),
+                primary(label, "in another script"),
+                note("in this language, label offsets are relative to the instruction that uses them"),
+            ))),
+            _ => Ok(LowerArg::Raw((label_encoding.encode(instr_offsets, 0, info.offset) as i32).into())),
+        },
+    }
+}
+
 /// Replaces special args like Labels and TimeOf with dummy values.
 ///
 /// This preserves the number of bytes in the written instruction.
@@ -445,6 +662,7 @@ fn substitute_dummy_args(instr: &LowerInstr) -> LowerInstr {
         LowerArgs::Known(args) => LowerArgs::Known(args.iter().map(|arg| match arg.value {
             | LowerArg::Label(_)
             | LowerArg::TimeOf(_)
+            | LowerArg::ForeignLabel { .. }
             => sp!(arg.span => LowerArg::Raw(SimpleArg { value: ScalarValue::Int(0), is_reg: false })),
 
             | LowerArg::Local { .. }
@@ -487,16 +705,17 @@ fn encode_args(
     let args = match &instr.args {
         LowerArgs::Known(args) => args,
         LowerArgs::Unknown(blob) => {
-            // Trivial case; a @blob was provided so there's nothing for this function to do.
+            // Trivial case; a @blob was provided so there's nothing for this function to do besides validation.
+            check_user_blob(hooks, blob, instr.user_param_mask, emitter)?;
             return Ok(RawInstr {
                 time: instr.stmt_data.time,
                 opcode: instr.opcode,
-                param_mask: instr.user_param_mask.unwrap_or(0),
+                param_mask: check_user_param_mask(hooks, instr.user_param_mask, emitter)?.unwrap_or(0),
                 args_blob: blob.value.clone(),
                 extra_arg: instr.explicit_extra_arg,
                 difficulty: instr.stmt_data.difficulty_mask.mask() as _,
-                // TODO: ECL pseudo-args whose semantics are not yet implemented
-                pop: 0,
+                pop: instr.stack_pop,
+                arg_count: instr.explicit_arg_count.unwrap_or(0),
             });
         },
     };
@@ -512,7 +731,7 @@ fn encode_args(
         }
     }
 
-    let (abi, _) = {
+    let (abi, abi_loc) = {
         defs.ins_abi(hooks.language(), instr.opcode)
             .expect("(bug!) we already checked sigs for known args")
     };
@@ -534,10 +753,12 @@ fn encode_args(
                 // Explicit @arg0, but also drawn from args.
                 // To keep the type checker's job simpler, we took an argument from the argument list anyways,
                 // but it was "overridden" by the explicit @arg0.
-                emitter.emit(warning!(
+                let mut diag = warning!(
                     message("explicit @arg0 overrides value supplied naturally"),
                     primary(first_normal_arg, "this value will be ignored"),
-                )).ignore();
+                );
+                abi_loc.add_to_diagnostic(&mut diag);
+                emitter.emit(diag).ignore();
             }
         }
         _ => {},
@@ -561,8 +782,24 @@ fn encode_args(
             | ArgEncoding::Integer { size: 4, .. }
             => args_blob.write_i32(arg.expect_raw().expect_int()).expect("Cursor<Vec> failed?!"),
 
-            | ArgEncoding::Integer { size: 2, .. }
-            => args_blob.write_i16(arg.expect_raw().expect_int() as _).expect("Cursor<Vec> failed?!"),
+            | ArgEncoding::Integer { size: 2, signed, .. }
+            => {
+                let value = arg.expect_raw().expect_int();
+                let (min, max) = match signed {
+                    true => (i16::MIN as i32, i16::MAX as i32),
+                    false => (0, u16::MAX as i32),
+                };
+                if !(min..=max).contains(&value) {
+                    let mut diag = error!(
+                        message("value out of range for {}", enc.descr()),
+                        primary(arg, "does not fit in 16 bits"),
+                        note("the value is {value}, but the range is {min} to {max}"),
+                    );
+                    abi_loc.add_to_diagnostic(&mut diag);
+                    return Err(emitter.emit(diag));
+                }
+                args_blob.write_u16(value as u16).expect("Cursor<Vec> failed?!")
+            },
 
             | ArgEncoding::Integer { size, .. }
             => panic!("unexpected integer size: {}", size),
@@ -575,7 +812,7 @@ fn encode_args(
                 let string = arg.expect_raw().expect_string();
 
                 // convert to Shift-JIS or whatever
-                let mut encoded = Encoded::encode(&sp!(arg.span => string), DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?;
+                let mut encoded = Encoded::encode(&sp!(arg.span => string), defs.string_encoding(hooks)).map_err(|e| emitter.emit(e))?;
 
                 // have to append null eagerly to correctly reproduce TH17 Extra files
                 match size_spec {
@@ -602,11 +839,13 @@ fn encode_args(
                     },
                     StringArgSize::Fixed { len, nulless: _ } => {
                         if encoded.len() > len {
-                            return Err(emitter.emit(error!(
+                            let mut diag = error!(
                                 message("string argument too large for buffer"),
                                 primary(arg, "requires {} bytes", encoded.len()),
                                 note("this argument is written to a {len}-byte buffer"),
-                            )))
+                            );
+                            abi_loc.add_to_diagnostic(&mut diag);
+                            return Err(emitter.emit(diag));
                         }
                         encoded.0.resize(len, b'\0');
                     },
@@ -630,23 +869,28 @@ fn encode_args(
     Ok(RawInstr {
         time: instr.stmt_data.time,
         opcode: instr.opcode,
-        param_mask: match instr.user_param_mask {
+        param_mask: match check_user_param_mask(hooks, instr.user_param_mask, emitter)? {
             Some(user_provided_mask) => user_provided_mask,
-            None => compute_param_mask(&args, emitter)?,
+            None => compute_param_mask(hooks, &args, emitter)?,
         },
         args_blob: args_blob.into_inner(),
         extra_arg,
         difficulty: instr.stmt_data.difficulty_mask.mask() as _,
-        // TODO: ECL pseudo-args whose semantics are not yet implemented
-        pop: 0,
+        pop: instr.stack_pop,
+        arg_count: match instr.explicit_arg_count {
+            Some(arg_count) => arg_count,
+            None => if hooks.has_arg_count() { args.len() as _ } else { 0 },
+        },
     })
 }
 
-fn compute_param_mask(args: &[Sp<LowerArg>], emitter: &impl Emitter) -> Result<raw::ParamMask, ErrorReported> {
-    if args.len() > raw::ParamMask::BITS as _ {
+fn compute_param_mask(hooks: &dyn LanguageHooks, args: &[Sp<LowerArg>], emitter: &impl Emitter) -> Result<raw::ParamMask, ErrorReported> {
+    let mask_bits = hooks.instr_format().param_mask_bits();
+    if args.len() > mask_bits as _ {
         return Err(emitter.emit(error!(
             message("too many arguments in instruction!"),
-            primary(args[raw::ParamMask::BITS as usize], "too many arguments"),
+            primary(args[mask_bits as usize], "too many arguments"),
+            note("the parameter mask in this format can only describe {} arguments", mask_bits),
         )));
     }
     let mut mask = 0;
@@ -655,6 +899,7 @@ fn compute_param_mask(args: &[Sp<LowerArg>], emitter: &impl Emitter) -> Result<r
             LowerArg::Raw(raw) => raw.is_reg as raw::ParamMask,
             LowerArg::TimeOf { .. } => 0,
             LowerArg::Label { .. } => 0,
+            LowerArg::ForeignLabel { .. } => 0,
             LowerArg::Local { .. } => 1,
             LowerArg::DiffSwitch { .. } => panic!("should be handled earlier"),
         };
@@ -664,4 +909,103 @@ fn compute_param_mask(args: &[Sp<LowerArg>], emitter: &impl Emitter) -> Result<r
     Ok(mask)
 }
 
+fn check_user_blob(
+    hooks: &dyn LanguageHooks,
+    blob: &Sp<Vec<u8>>,
+    user_param_mask: Option<Sp<raw::ParamMask>>,
+    emitter: &impl Emitter,
+) -> Result<(), ErrorReported> {
+    match hooks.instr_format().args_blob_size() {
+        ArgsBlobSize::MultipleOf(granularity) => if !blob.len().is_multiple_of(granularity) {
+            return Err(emitter.emit(error!(
+                message("number of bytes in blob not divisible by {granularity}"),
+                primary(blob, "blob literal of length {}", blob.len()),
+            )));
+        },
+        ArgsBlobSize::Exactly(size) => if blob.len() != size {
+            return Err(emitter.emit(error!(
+                message("blob must contain exactly {size} bytes in this format"),
+                primary(blob, "blob literal of length {}", blob.len()),
+            )));
+        },
+    }
+
+    // in EoSD ECL, the mask field has nothing to do with the arguments
+    let mask_describes_args = hooks.has_registers() && matches!(hooks.register_style(), RegisterEncodingStyle::ByParamMask);
+    if let (Some(mask), true) = (user_param_mask, mask_describes_args) {
+        let num_dwords = blob.len() / 4;
+        if mask.value.checked_shr(num_dwords as u32).unwrap_or(0) != 0 {
+            let highest_bit = raw::ParamMask::BITS - 1 - mask.value.leading_zeros();
+            return Err(emitter.emit(error!(
+                message("parameter mask has bits beyond the end of the blob"),
+                primary(blob, "blob literal of length {}", blob.len()),
+                secondary(mask, "bit {highest_bit} is set"),
+                note("each bit of the mask corresponds to a dword of the blob"),
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Warn about a `@blob` that looks like it was encoded for a different signature than the one
+/// currently known for its instruction.
+fn check_user_blob_against_abi(
+    hooks: &dyn LanguageHooks,
+    blob: &Sp<Vec<u8>>,
+    user_param_mask: Option<Sp<raw::ParamMask>>,
+    (abi, abi_loc): (&InstrAbi, &context::defs::InstrAbiLoc),
+    emitter: &impl Emitter,
+) {
+    // a blob that doesn't even fit the format will get an error later
+    let fits_format = match hooks.instr_format().args_blob_size() {
+        ArgsBlobSize::MultipleOf(granularity) => blob.len().is_multiple_of(granularity),
+        ArgsBlobSize::Exactly(size) => blob.len() == size,
+    };
+
+    if let (Some(expected_size), true) = (abi.fixed_args_blob_size(), fits_format) {
+        if blob.len() != expected_size {
+            let mut diag = warning!(
+                message("blob size does not match the signature"),
+                primary(blob, "blob literal of length {}", blob.len()),
+                note("the signature would produce {expected_size} bytes of arguments"),
+            );
+            abi_loc.add_to_diagnostic(&mut diag);
+            emitter.emit(diag).ignore();
+        }
+    }
+
+    let mask_describes_args = hooks.has_registers() && matches!(hooks.register_style(), RegisterEncodingStyle::ByParamMask);
+    if let (Some(mask), true) = (user_param_mask, mask_describes_args) {
+        let encodings = abi.arg_encodings().collect::<Vec<_>>();
+        let any_reg_bit = (0..raw::ParamMask::BITS).any(|bit| {
+            mask.value & (1 << bit) != 0
+                && encodings.get(bit as usize).is_some_and(|enc| enc.can_be_register())
+        });
+        if mask.value != 0 && !any_reg_bit {
+            let mut diag = warning!(
+                message("parameter mask only marks immediate arguments"),
+                primary(mask, "no bit here corresponds to an argument that can be a register"),
+                note("this mask may have been written for an older signature"),
+            );
+            abi_loc.add_to_diagnostic(&mut diag);
+            emitter.emit(diag).ignore();
+        }
+    }
+}
+
+fn check_user_param_mask(
+    hooks: &dyn LanguageHooks,
+    user_param_mask: Option<Sp<raw::ParamMask>>,
+    emitter: &impl Emitter,
+) -> Result<Option<raw::ParamMask>, ErrorReported> {
+    let mask_bits = hooks.instr_format().param_mask_bits();
+    match user_param_mask {
+        Some(mask) if mask.value.checked_shr(mask_bits).unwrap_or(0) != 0 => Err(emitter.emit(error!(
+            message("parameter mask too wide"),
+            primary(mask, "does not fit in {} bits", mask_bits),
+        ))),
+        _ => Ok(user_param_mask.map(|mask| mask.value)),
+    }
+}
+
 // =============================================================================
@@ -0,0 +1,94 @@
+//! Compiles difficulty masks into regions, for languages that set the mask with an instruction.
+
+use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg};
+use super::intrinsic::IntrinsicBuilder;
+use crate::raw;
+use crate::bitset::BitSet32;
+use crate::diagnostic::RootEmitter;
+use crate::error::{ErrorReported, ErrorFlag};
+use crate::llir::{IntrinsicInstrKind, IntrinsicInstrs};
+use crate::passes::semantics::time_and_difficulty::{TimeAndDifficulty, DEFAULT_DIFFICULTY_MASK};
+use crate::pos::{Sp, Span};
+
+/// Moves the difficulty masks of instructions into [`IntrinsicInstrKind::DifficultyMask`] instructions.
+///
+/// A mask instruction is emitted wherever the mask changes, so that adjacent instructions with the same
+/// mask share a single region.  The mask is restored to all difficulties before every label and at the end
+/// of the script, so that the mask is always the same when arriving at a label by a jump.  (for this reason,
+/// jumps are not allowed under a difficulty label)
+///
+/// Does nothing if the language has no such instruction.
+pub(in crate::llir::lower) fn run(
+    stmts: Vec<Sp<LowerStmt>>,
+    intrinsic_instrs: &IntrinsicInstrs,
+    emitter: &RootEmitter,
+) -> Result<Vec<Sp<LowerStmt>>, ErrorReported> {
+    let opcode = match intrinsic_instrs.get_opcode_opt(IntrinsicInstrKind::DifficultyMask) {
+        Some(opcode) => opcode,
+        None => return Ok(stmts),
+    };
+    let (_, abi_parts) = intrinsic_instrs.get_intrinsic_and_props(opcode).unwrap();
+    let make_mask_instr = |span: Span, time: raw::Time, mask: BitSet32| -> Result<_, ErrorReported> {
+        let builder = IntrinsicBuilder {
+            plain_args: vec![sp!(span => LowerArg::Raw((mask.mask() as i32).into()))],
+            ..Default::default()
+        };
+        Ok(sp!(span => LowerStmt::Instr(LowerInstr {
+            stmt_data: TimeAndDifficulty { time, difficulty_mask: DEFAULT_DIFFICULTY_MASK },
+            opcode,
+            explicit_extra_arg: None,
+            user_param_mask: None,
+            stack_pop: 0,
+            explicit_arg_count: None,
+            args: LowerArgs::Known(builder.into_vec(abi_parts)?),
+        })))
+    };
+
+    let mut errors = ErrorFlag::new();
+    let mut out = Vec::with_capacity(stmts.len());
+    let mut current_mask = DEFAULT_DIFFICULTY_MASK;
+    let mut last_time_and_span = None;
+    for mut stmt in stmts {
+        let span = stmt.span;
+        match &mut stmt.value {
+            LowerStmt::Label { time, .. } => {
+                if current_mask != DEFAULT_DIFFICULTY_MASK {
+                    out.push(make_mask_instr(span.start_span(), *time, DEFAULT_DIFFICULTY_MASK)?);
+                    current_mask = DEFAULT_DIFFICULTY_MASK;
+                }
+                last_time_and_span = Some((*time, span));
+            },
+            LowerStmt::Instr(instr) => {
+                let TimeAndDifficulty { time, difficulty_mask } = instr.stmt_data;
+                if difficulty_mask != DEFAULT_DIFFICULTY_MASK && is_jump(instr) {
+                    errors.set(emitter.emit(error!(
+                        message("jump inside difficulty label"),
+                        primary(span, "jumps under a difficulty label are not supported in this format"),
+                        note("difficulty masks in this format are set by an instruction, so the mask would still be set at the destination"),
+                    )));
+                }
+                if difficulty_mask != current_mask {
+                    out.push(make_mask_instr(span.start_span(), time, difficulty_mask)?);
+                    current_mask = difficulty_mask;
+                }
+                instr.stmt_data.difficulty_mask = DEFAULT_DIFFICULTY_MASK;
+                last_time_and_span = Some((time, span));
+            },
+            LowerStmt::RegAlloc { .. } | LowerStmt::RegFree { .. } => {},
+        }
+        out.push(stmt);
+    }
+
+    if current_mask != DEFAULT_DIFFICULTY_MASK {
+        let (time, span) = last_time_and_span.expect("mask can only change at an instruction");
+        out.push(make_mask_instr(span.end_span(), time, DEFAULT_DIFFICULTY_MASK)?);
+    }
+    errors.into_result(out)
+}
+
+fn is_jump(instr: &LowerInstr) -> bool {
+    match &instr.args {
+        LowerArgs::Known(args) => args.iter().any(|arg| matches!(arg.value, LowerArg::Label(_))),
+        LowerArgs::Unknown(_) => false,
+    }
+}
@@ -1,20 +1,23 @@
 use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg};
 use crate::ast;
 use crate::raw;
-use crate::llir::{IntrinsicInstrKind};
+use crate::llir::{IntrinsicInstrKind, IntrinsicInstrs};
 use crate::llir::intrinsic::{IntrinsicInstrAbiParts, abi_parts};
+use crate::diagnostic::RootEmitter;
 use crate::error::{ErrorReported};
 use crate::pos::{Sp, Span};
 use crate::passes::semantics::time_and_difficulty::TimeAndDifficulty;
 
-// FIXME: Ideally this module wouldn't depend on this type (so the stackful
-//        lowerer can also use it) but until the other lowerer exists,
-//        doing anything else would be premature abstraction.
-use super::stackless::SingleSubLowerer;
+/// Shared by the stackless and stackful lowerers for emitting intrinsics.
+pub(in crate::llir::lower) trait LowerIntrinsic {
+    fn intrinsic_instrs(&self) -> &IntrinsicInstrs;
+
+    fn emitter(&self) -> &RootEmitter;
+
+    fn push_stmt(&mut self, stmt: Sp<LowerStmt>);
 
-impl SingleSubLowerer<'_, '_> {
     /// Factored out common code for beginning to construct an intrinsic.
-    pub(in crate::llir::lower) fn lower_intrinsic<'a>( // lifetime to prevent forall quantification
+    fn lower_intrinsic<'a>( // lifetime to prevent forall quantification
         &mut self,
         span: Span,
         stmt_data: TimeAndDifficulty,
@@ -22,34 +25,37 @@ impl SingleSubLowerer<'_, '_> {
         descr_if_unsupported: &str,
         declare_args: impl FnOnce(&mut IntrinsicBuilder<'a>),
     ) -> Result<(), ErrorReported> {
-        let opcode = self.intrinsic_instrs.get_opcode_opt(kind)
-            .ok_or_else(|| self.ctx.emitter.emit(self.intrinsic_instrs.missing_intrinsic_error(span, descr_if_unsupported)))?;
+        let opcode = self.intrinsic_instrs().get_opcode_opt(kind)
+            .ok_or_else(|| self.emitter().emit(self.intrinsic_instrs().missing_intrinsic_error(span, descr_if_unsupported)))?;
 
         self.lower_intrinsic_by_opcode(span, stmt_data, opcode, declare_args)
     }
 
-    pub(in crate::llir::lower) fn lower_intrinsic_by_opcode<'a>(
+    fn lower_intrinsic_by_opcode<'a>(
         &mut self,
         span: Span,
         stmt_data: TimeAndDifficulty,
         opcode: raw::Opcode,
         declare_args: impl FnOnce(&mut IntrinsicBuilder<'a>),
     ) -> Result<(), ErrorReported> {
-        let (_, abi_parts) = self.intrinsic_instrs.get_intrinsic_and_props(opcode)
+        let (_, abi_parts) = self.intrinsic_instrs().get_intrinsic_and_props(opcode)
             .expect("(bug!) how did we get this opcode if it isn't an intrinsic?");
 
         // let the caller provide us with various data appropriate to this intrinsic
         let mut builder = IntrinsicBuilder::default();
         declare_args(&mut builder);
+        let stack_pop = builder.stack_pop;
 
         // convert them into a vec using the validated abi info
         let args = LowerArgs::Known(builder.into_vec(abi_parts)?);
 
-        self.out.push(sp!(span => LowerStmt::Instr(LowerInstr {
+        self.push_stmt(sp!(span => LowerStmt::Instr(LowerInstr {
             stmt_data,
             opcode,
             explicit_extra_arg: None,
             user_param_mask: None,
+            stack_pop,
+            explicit_arg_count: None,
             args,
         })));
         Ok(())
@@ -64,11 +70,13 @@ pub(in crate::llir::lower) struct IntrinsicBuilder<'a> {
     pub(in crate::llir::lower) sub_id: Option<Sp<LowerArg>>,
     pub(in crate::llir::lower) plain_args: Vec<Sp<LowerArg>>,
     pub(in crate::llir::lower) outputs: Vec<Sp<LowerArg>>,
+    /// Number of stack values consumed by the instruction.  (not part of the ABI)
+    pub(in crate::llir::lower) stack_pop: raw::StackPop,
 }
 
 impl IntrinsicBuilder<'_> {
     // use the data in self and the indices in abi_parts to populate all of the elements of out_args
-    fn into_vec(
+    pub(in crate::llir::lower) fn into_vec(
         self,
         abi_parts: &IntrinsicInstrAbiParts,
     ) -> Result<Vec<Sp<LowerArg>>, ErrorReported> {
@@ -0,0 +1,165 @@
+//! Warnings about suspicious scripts, checked on [`LowerStmt`]s.
+//!
+//! The ones in [`run`] are about scripts that are likely to hang the game.  These are only enabled for
+//! languages where a stuck script freezes rendering (ANM), via
+//! [`Lowerer::with_script_lints`][`super::Lowerer::with_script_lints`].  The busy loop warning
+//! additionally requires [`CompilerContext::warn_anm_busy_loops`].
+
+use std::collections::{HashMap, HashSet};
+
+use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg};
+use crate::context::CompilerContext;
+use crate::diagnostic::RootEmitter;
+use crate::llir::{IntrinsicInstrKind, IntrinsicInstrs, LanguageHooks};
+use crate::llir::intrinsic::abi_parts;
+use crate::pos::{Sp, Span};
+use crate::raw;
+use crate::value::ScalarValue;
+
+/// Run all lints on a lowered script, reporting them at the script's name.
+pub(in crate::llir::lower) fn run(
+    hooks: &dyn LanguageHooks,
+    out: &[Sp<LowerStmt>],
+    intrinsic_instrs: &IntrinsicInstrs,
+    script_span: Span,
+    ctx: &CompilerContext<'_>,
+    emitter: &RootEmitter,
+) {
+    let busy_loop = ctx.warn_anm_busy_loops.then(|| find_busy_loop(out, intrinsic_instrs)).flatten();
+    if let Some(jump_span) = busy_loop {
+        emitter.emit(warning!(
+            message("infinite loop that never waits"),
+            primary(script_span, "this script can freeze the game"),
+            secondary(jump_span, "jumps back without advancing time"),
+            note("a loop must advance time somewhere in its body, or else the game will never leave it (unless an interrupt does)"),
+        )).ignore();
+    }
+
+    if hooks.requires_th06_anm_terminating_instr() {
+        let has_end = out.iter().any(|stmt| match &stmt.value {
+            LowerStmt::Instr(instr) => hooks.is_th06_anm_terminating_instr(instr.opcode),
+            _ => false,
+        });
+        if !has_end {
+            emitter.emit(warning!(
+                message("script never ends"),
+                primary(script_span, "no terminating instruction"),
+                note("In EoSD ANM, a script should end with opcode 0 or 15, or else the game will run past the end of it"),
+            )).ignore();
+        }
+    }
+}
+
+/// Find an unconditional backwards jump where no time passes between the label and the jump.
+///
+/// Loops containing any other jump are assumed to have a way out.
+fn find_busy_loop(out: &[Sp<LowerStmt>], intrinsic_instrs: &IntrinsicInstrs) -> Option<Span> {
+    let labels = out.iter().enumerate()
+        .filter_map(|(index, stmt)| match &stmt.value {
+            LowerStmt::Label { time, label } => Some((&label.value, (index, *time))),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    out.iter().enumerate().find_map(|(jump_index, stmt)| {
+        let LowerStmt::Instr(instr) = &stmt.value else { return None };
+        let Some((IntrinsicInstrKind::Jmp, abi_parts)) = intrinsic_instrs.get_intrinsic_and_props(instr.opcode) else { return None };
+        let (jump_arg_index, order) = abi_parts.jump?;
+        let LowerArgs::Known(args) = &instr.args else { return None };
+
+        let (label_arg, time_arg) = match order {
+            abi_parts::JumpArgOrder::LocTime => (&args[jump_arg_index], Some(&args[jump_arg_index + 1])),
+            abi_parts::JumpArgOrder::TimeLoc => (&args[jump_arg_index + 1], Some(&args[jump_arg_index])),
+            abi_parts::JumpArgOrder::Loc => (&args[jump_arg_index], None),
+        };
+        let LowerArg::Label(label) = &label_arg.value else { return None };
+        let &(label_index, label_time) = labels.get(label)?;
+        if label_index > jump_index {
+            return None;
+        }
+
+        // the jump sets the script's time, and anything at an earlier time then runs immediately
+        let time_after_jump = match time_arg.map(|arg| &arg.value) {
+            None => label_time,
+            Some(LowerArg::TimeOf(other)) => labels.get(other)?.1,
+            Some(LowerArg::Raw(arg)) if !arg.is_reg => match arg.value {
+                ScalarValue::Int(time) => time,
+                _ => return None,
+            },
+            Some(_) => return None,
+        };
+        let body = &out[label_index..jump_index];
+        let no_time_passes = body.iter().all(|stmt| stmt_time(stmt).is_none_or(|time| time == label_time));
+        let has_exit = body.iter().any(|stmt| is_jump(stmt));
+        (no_time_passes && !has_exit && instr.stmt_data.time == label_time && time_after_jump >= label_time).then_some(stmt.span)
+    })
+}
+
+/// Warn about instructions whose time is lower than that of the previous instruction.
+///
+/// This is almost always a misplaced absolute time label, and the instruction will just run right after
+/// the previous one.  A label that something jumps to (or an interrupt label) in between is assumed to be
+/// a place where the script's time is deliberately rewound, and code after an unconditional jump is not
+/// reached from the instruction before it.
+pub(in crate::llir::lower) fn check_time_decreases(
+    out: &[Sp<LowerStmt>],
+    intrinsic_instrs: &IntrinsicInstrs,
+    emitter: &RootEmitter,
+) {
+    let jump_targets = out.iter()
+        .filter_map(|stmt| match &stmt.value {
+            LowerStmt::Instr(LowerInstr { args: LowerArgs::Known(args), .. }) => Some(args),
+            _ => None,
+        })
+        .flatten()
+        .filter_map(|arg| match &arg.value {
+            LowerArg::Label(label) => Some(label),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut prev_time = None;
+    for stmt in out {
+        match &stmt.value {
+            LowerStmt::Label { label, .. } => if jump_targets.contains(&label.value) {
+                prev_time = None;
+            },
+            LowerStmt::Instr(instr) => {
+                let time = instr.stmt_data.time;
+                let intrinsic = intrinsic_instrs.get_intrinsic_and_props(instr.opcode).map(|(kind, _)| kind);
+                let is_interrupt = matches!(intrinsic, Some(IntrinsicInstrKind::InterruptLabel));
+                if let Some(prev_time) = prev_time.filter(|&prev_time| time < prev_time && !is_interrupt) {
+                    emitter.emit(warning!(
+                        message("instruction is at an earlier time than the previous one"),
+                        primary(stmt, "at time {time}, after an instruction at time {prev_time}"),
+                        note("a time label like `{time}:` cannot rewind the script's time, so this will run at time {prev_time}"),
+                        note("if this is intended, use --no-time-decrease-warnings"),
+                    )).ignore();
+                }
+                prev_time = match intrinsic {
+                    Some(IntrinsicInstrKind::Jmp) => None,
+                    _ => Some(time),
+                };
+            },
+            LowerStmt::RegAlloc { .. } | LowerStmt::RegFree { .. } => {},
+        }
+    }
+}
+
+fn is_jump(stmt: &LowerStmt) -> bool {
+    match stmt {
+        LowerStmt::Instr(instr) => match &instr.args {
+            LowerArgs::Known(args) => args.iter().any(|arg| matches!(arg.value, LowerArg::Label(_))),
+            LowerArgs::Unknown(_) => false,
+        },
+        _ => false,
+    }
+}
+
+fn stmt_time(stmt: &LowerStmt) -> Option<raw::Time> {
+    match stmt {
+        LowerStmt::Instr(instr) => Some(instr.stmt_data.time),
+        LowerStmt::Label { time, .. } => Some(*time),
+        LowerStmt::RegAlloc { .. } | LowerStmt::RegFree { .. } => None,
+    }
+}
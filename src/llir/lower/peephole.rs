@@ -0,0 +1,458 @@
+//! Peephole optimizations on [`LowerStmt`]s.
+//!
+//! These clean up some of the redundant instructions produced when the stackless lowerer splits a complicated
+//! expression into multiple instructions.  They run before register allocation, so that temporaries which are
+//! optimized away never claim a register.
+
+use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg};
+use super::intrinsic::IntrinsicBuilder;
+use super::stackless::Temporaries;
+use crate::ast;
+use crate::llir::{IntrinsicInstrKind, IntrinsicInstrs};
+use crate::llir::intrinsic::abi_parts;
+use crate::pos::Sp;
+use crate::resolve::DefId;
+use crate::value::ScalarType;
+
+/// Apply all peephole optimizations.
+///
+/// Only adjacent instructions are ever combined, so labels between them always prevent a rewrite.
+/// Instructions must also have identical time and difficulty.
+pub(in crate::llir::lower) fn run(out: &mut Vec<Sp<LowerStmt>>, intrinsic_instrs: &IntrinsicInstrs, temporaries: &Temporaries) {
+    let mut index = 0;
+    while index + 1 < out.len() {
+        if forward_temporary_copy(out, index, intrinsic_instrs, temporaries)
+            || retarget_temporary_copy(out, index, intrinsic_instrs, temporaries)
+            || merge_assign_into_binop(out, index, intrinsic_instrs)
+        {
+            // the rewritten instruction may now combine with its neighbors
+            index = index.saturating_sub(1);
+            continue;
+        }
+        index += 1;
+    }
+}
+
+/// Rewrites `tmp = X;  foo(tmp);  free tmp;` into `foo(X);`.
+///
+/// Returns `true` if a rewrite was performed.
+fn forward_temporary_copy(out: &mut Vec<Sp<LowerStmt>>, index: usize, intrinsic_instrs: &IntrinsicInstrs, temporaries: &Temporaries) -> bool {
+    let (copy, user) = match (&out[index].value, &out[index + 1].value) {
+        (LowerStmt::Instr(a), LowerStmt::Instr(b)) => (a, b),
+        _ => return false,
+    };
+    if !is_plain(copy) || !is_plain(user) || copy.stmt_data != user.stmt_data {
+        return false;
+    }
+
+    let (temp_def_id, ty, value) = match intrinsic_instrs.get_intrinsic_and_props(copy.opcode) {
+        Some((IntrinsicInstrKind::AssignOp(ast::AssignOpKind::Assign, ty), abi_parts)) => {
+            let args = known_args(copy);
+            match args[abi_parts.outputs[0].0].value {
+                // user-declared locals are left alone, as the user may have wanted the value in a register
+                LowerArg::Local { def_id, .. } if temporaries.is_temporary(def_id) => (def_id, ty, args[abi_parts.plain_args[0]].clone()),
+                _ => return false,
+            }
+        },
+        _ => return false,
+    };
+
+    // the temporary must die immediately after its only use
+    if !frees_immediately(out, index + 2, temp_def_id) {
+        return false;
+    }
+
+    // every mention of the temporary must be a read of the same type as the copy
+    let user_args = known_args(user);
+    let expected_read = LowerArg::Local { def_id: temp_def_id, storage_ty: ty };
+    if let Some((_, abi_parts)) = intrinsic_instrs.get_intrinsic_and_props(user.opcode) {
+        if abi_parts.outputs.iter().any(|&(output_index, _)| mentions_local(&user_args[output_index], temp_def_id)) {
+            return false;
+        }
+    }
+    if user_args.iter().any(|arg| mentions_local(arg, temp_def_id) && arg.value != expected_read) {
+        return false;
+    }
+
+    let new_args = user_args.iter().map(|arg| match arg.value == expected_read {
+        true => sp!(arg.span => value.value.clone()),
+        false => arg.clone(),
+    }).collect();
+    match &mut out[index + 1].value {
+        LowerStmt::Instr(user) => user.args = LowerArgs::Known(new_args),
+        _ => unreachable!(),
+    }
+
+    out.remove(index);
+    remove_reg_lifetime(out, index, temp_def_id);
+    true
+}
+
+/// Rewrites `tmp = b + c;  a = tmp;  free tmp;` into `a = b + c;`.
+///
+/// Returns `true` if a rewrite was performed.
+fn retarget_temporary_copy(out: &mut Vec<Sp<LowerStmt>>, index: usize, intrinsic_instrs: &IntrinsicInstrs, temporaries: &Temporaries) -> bool {
+    let (producer, copy) = match (&out[index].value, &out[index + 1].value) {
+        (LowerStmt::Instr(a), LowerStmt::Instr(b)) => (a, b),
+        _ => return false,
+    };
+    if !is_plain(producer) || !is_plain(copy) || producer.stmt_data != copy.stmt_data {
+        return false;
+    }
+
+    let (ty, copy_parts) = match intrinsic_instrs.get_intrinsic_and_props(copy.opcode) {
+        Some((IntrinsicInstrKind::AssignOp(ast::AssignOpKind::Assign, ty), abi_parts)) => (ty, abi_parts),
+        _ => return false,
+    };
+    let copy_args = known_args(copy);
+    let (dest_index, dest_mode) = copy_parts.outputs[0];
+    let temp_def_id = match copy_args[copy_parts.plain_args[0]].value {
+        // a read of a different type would be a cast
+        LowerArg::Local { def_id, storage_ty } if storage_ty == ty && temporaries.is_temporary(def_id) => def_id,
+        _ => return false,
+    };
+    if !frees_immediately(out, index + 2, temp_def_id) {
+        return false;
+    }
+
+    // the producer must write the temporary as its only output, with the same type as the copy
+    let producer_parts = match intrinsic_instrs.get_intrinsic_and_props(producer.opcode) {
+        Some((kind, abi_parts)) if output_ty(kind) == Some(ty) && abi_parts.outputs.len() == 1 => abi_parts,
+        _ => return false,
+    };
+    let (producer_out_index, producer_mode) = producer_parts.outputs[0];
+    let producer_args = known_args(producer);
+    if !matches!(producer_args[producer_out_index].value, LowerArg::Local { def_id, .. } if def_id == temp_def_id) {
+        return false;
+    }
+    if !same_output_mode(producer_mode, dest_mode) {
+        return false;
+    }
+    if producer_args.iter().enumerate().any(|(i, arg)| i != producer_out_index && mentions_local(arg, temp_def_id)) {
+        return false;
+    }
+
+    let dest = copy_args[dest_index].clone();
+    match &mut out[index].value {
+        LowerStmt::Instr(LowerInstr { args: LowerArgs::Known(args), .. }) => args[producer_out_index] = dest,
+        _ => unreachable!(),
+    }
+
+    out.remove(index + 1);
+    remove_reg_lifetime(out, index + 1, temp_def_id);
+    true
+}
+
+/// Rewrites `a = b;  a += c;` into `a = b + c;`.
+///
+/// To preserve the output of code that was written this way on purpose (e.g. by the decompiler), this only
+/// applies to instructions that were split from the same statement.
+///
+/// Returns `true` if a rewrite was performed.
+fn merge_assign_into_binop(out: &mut Vec<Sp<LowerStmt>>, index: usize, intrinsic_instrs: &IntrinsicInstrs) -> bool {
+    let (assign, update) = match (&out[index].value, &out[index + 1].value) {
+        (LowerStmt::Instr(a), LowerStmt::Instr(b)) => (a, b),
+        _ => return false,
+    };
+    if !is_plain(assign) || !is_plain(update) || assign.stmt_data != update.stmt_data {
+        return false;
+    }
+    if out[index].span.disjoint(out[index + 1].span) {
+        return false;
+    }
+
+    let (assign_ty, assign_parts) = match intrinsic_instrs.get_intrinsic_and_props(assign.opcode) {
+        Some((IntrinsicInstrKind::AssignOp(ast::AssignOpKind::Assign, ty), abi_parts)) => (ty, abi_parts),
+        _ => return false,
+    };
+    let assign_args = known_args(assign);
+    let (dest_index, dest_mode) = assign_parts.outputs[0];
+    let dest = &assign_args[dest_index];
+    let first_operand = &assign_args[assign_parts.plain_args[0]];
+
+    // `a += c;` may also have been lowered as `a = a + c;` if the format has no intrinsic for the former
+    let update_args = known_args(update);
+    let (binop, second_operand) = match intrinsic_instrs.get_intrinsic_and_props(update.opcode) {
+        Some((IntrinsicInstrKind::AssignOp(op, ty), abi_parts)) if ty == assign_ty => {
+            let binop = match op.corresponding_binop() {
+                Some(binop) => binop,
+                None => return false,
+            };
+            if update_args[abi_parts.outputs[0].0] != *dest {
+                return false;
+            }
+            (binop, &update_args[abi_parts.plain_args[0]])
+        },
+        Some((IntrinsicInstrKind::BinOp(binop, ty), abi_parts)) if ty == assign_ty => {
+            let out_ty = ast::Expr::binop_ty_from_arg_ty(binop, ty);
+            if out_ty != ty || update_args[abi_parts.outputs[0].0] != *dest || update_args[abi_parts.plain_args[0]] != *dest {
+                return false;
+            }
+            (binop, &update_args[abi_parts.plain_args[1]])
+        },
+        _ => return false,
+    };
+    // `a = b;  a += a;` would read the new value of `a`
+    if same_variable(second_operand, dest) || matches!(second_operand.value, LowerArg::DiffSwitch(_)) {
+        return false;
+    }
+
+    let binop_opcode = match intrinsic_instrs.get_opcode_opt(IntrinsicInstrKind::BinOp(binop, assign_ty)) {
+        Some(opcode) => opcode,
+        None => return false,
+    };
+    let (_, binop_parts) = intrinsic_instrs.get_intrinsic_and_props(binop_opcode).unwrap();
+    if !same_output_mode(binop_parts.outputs[0].1, dest_mode) {
+        return false;
+    }
+
+    let builder = IntrinsicBuilder {
+        outputs: vec![dest.clone()],
+        plain_args: vec![first_operand.clone(), second_operand.clone()],
+        ..Default::default()
+    };
+    let args = match builder.into_vec(binop_parts) {
+        Ok(args) => args,
+        Err(_) => return false,
+    };
+    let merged = LowerInstr { opcode: binop_opcode, args: LowerArgs::Known(args), ..assign.clone() };
+
+    let span = out[index].span.merge(out[index + 1].span);
+    out[index] = sp!(span => LowerStmt::Instr(merged));
+    out.remove(index + 1);
+    true
+}
+
+/// Whether a local is freed at the given index, possibly alongside other locals.
+fn frees_immediately(out: &[Sp<LowerStmt>], index: usize, def_id: DefId) -> bool {
+    out[index..].iter()
+        .take_while(|stmt| matches!(stmt.value, LowerStmt::RegFree { .. }))
+        .any(|stmt| stmt.value == LowerStmt::RegFree { def_id })
+}
+
+/// The type of value written by an intrinsic whose output does not depend on the output's previous value.
+fn output_ty(kind: IntrinsicInstrKind) -> Option<ScalarType> {
+    match kind {
+        IntrinsicInstrKind::AssignOp(ast::AssignOpKind::Assign, ty) => Some(ty),
+        IntrinsicInstrKind::BinOp(op, arg_ty) => Some(ast::Expr::binop_ty_from_arg_ty(op, arg_ty)),
+        IntrinsicInstrKind::UnOp(op, arg_ty) => Some(ast::Expr::unop_ty_from_arg_ty(op, arg_ty)),
+        _ => None,
+    }
+}
+
+/// Whether an instruction has nothing special about it that would prevent modifying its arguments.
+fn is_plain(instr: &LowerInstr) -> bool {
+    instr.explicit_extra_arg.is_none()
+        && instr.user_param_mask.is_none()
+        && instr.stack_pop == 0
+        && instr.explicit_arg_count.is_none()
+        && matches!(instr.args, LowerArgs::Known(_))
+}
+
+fn known_args(instr: &LowerInstr) -> &[Sp<LowerArg>] {
+    match &instr.args {
+        LowerArgs::Known(args) => args,
+        LowerArgs::Unknown(_) => unreachable!("checked by is_plain"),
+    }
+}
+
+fn mentions_local(arg: &LowerArg, def_id: DefId) -> bool {
+    match arg {
+        LowerArg::Local { def_id: arg_def_id, .. } => *arg_def_id == def_id,
+        LowerArg::DiffSwitch(cases) => cases.iter().flatten().any(|case| mentions_local(case, def_id)),
+        _ => false,
+    }
+}
+
+/// Whether two arguments refer to the same register or local, regardless of how they are read.
+fn same_variable(a: &LowerArg, b: &LowerArg) -> bool {
+    match (a, b) {
+        (LowerArg::Local { def_id: a, .. }, LowerArg::Local { def_id: b, .. }) => a == b,
+        (LowerArg::Raw(a), LowerArg::Raw(b)) => a.is_reg && b.is_reg && a.get_reg_id() == b.get_reg_id(),
+        _ => false,
+    }
+}
+
+fn same_output_mode(a: abi_parts::OutputArgMode, b: abi_parts::OutputArgMode) -> bool {
+    use abi_parts::OutputArgMode as M;
+    matches!((a, b), (M::Natural, M::Natural) | (M::FloatAsInt, M::FloatAsInt))
+}
+
+/// Remove the `RegAlloc` and `RegFree` of a temporary that is no longer used.
+fn remove_reg_lifetime(out: &mut Vec<Sp<LowerStmt>>, index_after_last_use: usize, def_id: DefId) {
+    let free_index = out[index_after_last_use..].iter()
+        .position(|stmt| stmt.value == LowerStmt::RegFree { def_id })
+        .expect("checked earlier") + index_after_last_use;
+    out.remove(free_index);
+
+    if let Some(alloc_index) = out[..index_after_last_use].iter().rposition(|stmt| stmt.value == LowerStmt::RegAlloc { def_id }) {
+        out.remove(alloc_index);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Game, LanguageKey};
+    use crate::ident::Ident;
+    use crate::llir::SimpleArg;
+    use crate::passes::semantics::time_and_difficulty::{TimeAndDifficulty, DEFAULT_DIFFICULTY_MASK};
+    use crate::pos::Span;
+    use crate::raw;
+    use crate::resolve::RegId;
+    use IntrinsicInstrKind as I;
+
+    const ASSIGN: raw::Opcode = 10;
+    const ADD_ASSIGN: raw::Opcode = 11;
+    const ADD: raw::Opcode = 20;
+    const FOO: raw::Opcode = 100;
+
+    fn mapfile() -> String {
+        format!("\
+!anmmap
+!ins_signatures
+{ASSIGN} SS
+{ADD_ASSIGN} SS
+{ADD} SSS
+{FOO} S
+!ins_intrinsics
+{ASSIGN} {}
+{ADD_ASSIGN} {}
+{ADD} {}
+", I::AssignOp(token![=], ScalarType::Int), I::AssignOp(token![+=], ScalarType::Int), I::BinOp(token![+], ScalarType::Int))
+    }
+
+    fn reg(id: i32) -> LowerArg {
+        LowerArg::Raw(SimpleArg::from_reg(RegId(id), ScalarType::Int))
+    }
+
+    fn imm(value: i32) -> LowerArg {
+        LowerArg::Raw(value.into())
+    }
+
+    fn local(def_id: DefId) -> LowerArg {
+        LowerArg::Local { def_id, storage_ty: ScalarType::Int }
+    }
+
+    fn instr(span: std::ops::Range<u32>, time: raw::Time, opcode: raw::Opcode, args: Vec<LowerArg>) -> Sp<LowerStmt> {
+        sp!(Span::from(span) => LowerStmt::Instr(LowerInstr {
+            stmt_data: TimeAndDifficulty { time, difficulty_mask: DEFAULT_DIFFICULTY_MASK },
+            opcode,
+            explicit_extra_arg: None,
+            user_param_mask: None,
+            stack_pop: 0,
+            explicit_arg_count: None,
+            args: LowerArgs::Known(args.into_iter().map(|arg| sp!(arg)).collect()),
+        }))
+    }
+
+    fn alloc(def_id: DefId) -> Sp<LowerStmt> { sp!(LowerStmt::RegAlloc { def_id }) }
+    fn free(def_id: DefId) -> Sp<LowerStmt> { sp!(LowerStmt::RegFree { def_id }) }
+
+    /// Run the pass on statements that may use a temporary and a user-declared local.
+    fn optimize(build: impl FnOnce(DefId, DefId) -> Vec<Sp<LowerStmt>>) -> Vec<LowerStmt> {
+        let mut scope = crate::Builder::new().capture_diagnostics(true).build();
+        let mut truth = scope.truth();
+        truth.apply_mapfile_str(&mapfile(), Game::Th10).unwrap();
+        let ctx = truth.ctx();
+        let intrinsic_instrs = IntrinsicInstrs::from_mapfiles(LanguageKey::Anm, &ctx.defs, ctx.emitter).unwrap();
+
+        let user_local_ident = sp!(ctx.resolutions.attach_fresh_res(Ident::new_system("local").unwrap()));
+        let user_local = ctx.define_local(user_local_ident, ScalarType::Int.into());
+        let mut temporaries = Temporaries::new(ctx);
+        let (_, temp) = temporaries.define(Ident::new_system("temp").unwrap(), ScalarType::Int);
+
+        let mut out = build(temp, user_local);
+        run(&mut out, &intrinsic_instrs, &temporaries);
+        out.into_iter().map(|stmt| stmt.value).collect()
+    }
+
+    fn unspanned(stmts: Vec<Sp<LowerStmt>>) -> Vec<LowerStmt> {
+        stmts.into_iter().map(|stmt| stmt.value).collect()
+    }
+
+    #[test]
+    fn forward_copy_into_use() {
+        let out = optimize(|temp, _| vec![
+            alloc(temp),
+            instr(0..10, 0, ASSIGN, vec![local(temp), reg(10000)]),
+            instr(0..10, 0, FOO, vec![local(temp)]),
+            free(temp),
+        ]);
+        assert_eq!(out, unspanned(vec![instr(0..10, 0, FOO, vec![reg(10000)])]));
+    }
+
+    #[test]
+    fn forward_copy_from_producer() {
+        let out = optimize(|temp, _| vec![
+            alloc(temp),
+            instr(0..10, 0, ADD, vec![local(temp), reg(10000), imm(3)]),
+            instr(0..10, 0, ASSIGN, vec![reg(10001), local(temp)]),
+            free(temp),
+        ]);
+        assert_eq!(out, unspanned(vec![instr(0..10, 0, ADD, vec![reg(10001), reg(10000), imm(3)])]));
+    }
+
+    #[test]
+    fn forward_copy_needs_dead_temporary() {
+        let stmts = |temp| vec![
+            alloc(temp),
+            instr(0..10, 0, ASSIGN, vec![local(temp), reg(10000)]),
+            instr(0..10, 0, FOO, vec![local(temp)]),
+            instr(0..10, 0, FOO, vec![local(temp)]),
+            free(temp),
+        ];
+        assert_eq!(optimize(|temp, _| stmts(temp)).len(), 5);
+    }
+
+    #[test]
+    fn forward_copy_ignores_user_locals() {
+        let out = optimize(|_, user_local| vec![
+            alloc(user_local),
+            instr(0..10, 0, ASSIGN, vec![local(user_local), reg(10000)]),
+            instr(10..20, 0, FOO, vec![local(user_local)]),
+            free(user_local),
+        ]);
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn merge_assign_and_update() {
+        let out = optimize(|_, _| vec![
+            instr(5..10, 0, ASSIGN, vec![reg(10000), reg(10001)]),
+            instr(0..10, 0, ADD_ASSIGN, vec![reg(10000), imm(4)]),
+        ]);
+        assert_eq!(out, unspanned(vec![instr(0..10, 0, ADD, vec![reg(10000), reg(10001), imm(4)])]));
+    }
+
+    #[test]
+    fn merge_needs_same_statement() {
+        let out = optimize(|_, _| vec![
+            instr(0..10, 0, ASSIGN, vec![reg(10000), reg(10001)]),
+            instr(10..20, 0, ADD_ASSIGN, vec![reg(10000), imm(4)]),
+        ]);
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn merge_blocked_by_label_and_time() {
+        let label = sp!(Ident::new_system("label").unwrap());
+        let out = optimize(|_, _| vec![
+            instr(5..10, 0, ASSIGN, vec![reg(10000), reg(10001)]),
+            sp!(LowerStmt::Label { time: 0, label }),
+            instr(0..10, 0, ADD_ASSIGN, vec![reg(10000), imm(4)]),
+            instr(25..30, 0, ASSIGN, vec![reg(10000), reg(10001)]),
+            instr(20..30, 10, ADD_ASSIGN, vec![reg(10000), imm(4)]),
+        ]);
+        assert_eq!(out.len(), 5);
+    }
+
+    #[test]
+    fn merge_blocked_by_self_reference() {
+        let out = optimize(|_, _| vec![
+            instr(5..10, 0, ASSIGN, vec![reg(10000), reg(10001)]),
+            instr(0..10, 0, ADD_ASSIGN, vec![reg(10000), reg(10000)]),
+        ]);
+        assert_eq!(out.len(), 2);
+    }
+}
@@ -0,0 +1,674 @@
+//! Lowering for languages with a stack.
+//!
+//! Responsible for compilation of expressions into postfix sequences of push and operator instructions,
+//! and for placing locals into slots of the stack frame.
+
+use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg, SimpleArg};
+use super::intrinsic::LowerIntrinsic;
+use crate::raw;
+use crate::llir::{LanguageHooks, IntrinsicInstrKind, IntrinsicInstrs};
+use crate::error::{GatherErrorIteratorExt, ErrorReported};
+use crate::pos::{Sp, Span};
+use crate::ast::{self, pseudo::PseudoArgData};
+use crate::resolve::{DefId, RegId, NodeId, IdMap};
+use crate::game::LanguageKey;
+use crate::value::{ScalarType, ReadType};
+use crate::context::CompilerContext;
+use crate::context::defs::InsRetLocation;
+use crate::diagnostic::RootEmitter;
+use crate::ident::GensymContext;
+use crate::passes::semantics::time_and_difficulty::TimeAndDifficulty;
+use crate::llir::intrinsic::alternatives::CountJmpKind;
+use crate::debug_info;
+
+use IntrinsicInstrKind as IKind;
+
+/// Size in bytes of a single slot in the stack frame.
+const SLOT_SIZE: i32 = 4;
+
+/// Helper responsible for converting an AST into [`LowerStmt`]s, for a language with a stack.
+pub (in crate::llir::lower) struct SingleSubLowerer<'a, 'ctx> {
+    pub out: Vec<Sp<LowerStmt>>,
+    pub intrinsic_instrs: IntrinsicInstrs,
+    pub hooks: &'a dyn LanguageHooks,
+    pub ctx: &'a CompilerContext<'ctx>,
+    /// Emitter for this sub only.  (diagnostics from subs lowered in parallel are buffered and
+    /// emitted in a consistent order afterwards)
+    pub emitter: &'a RootEmitter,
+    pub gensym: GensymContext,
+    pub stmt_data: IdMap<NodeId, TimeAndDifficulty>,
+    pub sub_info: Option<&'a super::SubInfo<'a>>,
+    pub frame: StackFrame,
+    pub debug_info: Option<debug_info::ScriptRegisterInfo>,
+}
+
+/// Tracks which slots of the stack frame are in use.
+///
+/// Locals and temporaries are addressed by their byte offset into the frame, which is written
+/// to the file the same way as a register.
+#[derive(Debug, Default)]
+pub (in crate::llir::lower) struct StackFrame {
+    in_use: Vec<bool>,
+    locals: IdMap<DefId, usize>,
+}
+
+impl StackFrame {
+    fn alloc(&mut self) -> usize {
+        match self.in_use.iter().position(|&used| !used) {
+            Some(index) => {
+                self.in_use[index] = true;
+                index
+            },
+            None => {
+                self.in_use.push(true);
+                self.in_use.len() - 1
+            },
+        }
+    }
+
+    fn free(&mut self, index: usize) {
+        assert!(std::mem::replace(&mut self.in_use[index], false), "(bug!) freed a slot twice");
+    }
+
+    fn slot_reg(index: usize) -> RegId {
+        RegId(index as i32 * SLOT_SIZE)
+    }
+
+    /// Number of bytes that must be reserved for the frame.
+    fn size(&self) -> i32 {
+        self.in_use.len() as i32 * SLOT_SIZE
+    }
+}
+
+impl LowerIntrinsic for SingleSubLowerer<'_, '_> {
+    fn intrinsic_instrs(&self) -> &IntrinsicInstrs { &self.intrinsic_instrs }
+    fn emitter(&self) -> &RootEmitter { self.emitter }
+    fn push_stmt(&mut self, stmt: Sp<LowerStmt>) { self.out.push(stmt) }
+}
+
+impl SingleSubLowerer<'_, '_> {
+    /// Lowers a sub or script body.
+    ///
+    /// `def_id` is used to give the sub's parameters the first slots of the frame.
+    pub fn lower_sub_ast(
+        &mut self,
+        code: &[Sp<ast::Stmt>],
+        def_id: Option<DefId>,
+    ) -> Result<(), ErrorReported> {
+        if let (Some(sub_info), Some(def_id)) = (self.sub_info, def_id) {
+            for &(param_def_id, ty, param_span) in &sub_info.exported_subs.subs[&def_id].params_in_sig {
+                let index = self.frame.alloc();
+                if let Some(param_def_id) = param_def_id {
+                    self.bind_local(param_def_id, index, ty, param_span);
+                }
+            }
+        }
+
+        code.iter().map(|stmt| {
+            let stmt_data = self.stmt_data[&stmt.node_id.expect("stmt_data would've failed if missing")];
+
+            match &stmt.kind {
+                ast::StmtKind::Jump(jump) => {
+                    let goto = expect_simple_goto(jump);
+                    self.lower_uncond_jump(stmt.span, stmt_data, goto)?;
+                },
+
+                ast::StmtKind::Assignment { var, op, value } => {
+                    self.lower_assign_op(stmt.span, stmt_data, var, op, value)?;
+                },
+
+                ast::StmtKind::InterruptLabel(interrupt_id) => {
+                    self.lower_intrinsic(stmt.span, stmt_data, IKind::InterruptLabel, "interrupt label", |bld| {
+                        let value = interrupt_id.as_const_int().expect("const_simplify should have caught this");
+                        let lowered_id = sp!(interrupt_id.span => LowerArg::Raw(value.into()));
+                        bld.plain_args.push(lowered_id);
+                    })?;
+                },
+
+                ast::StmtKind::CondJump { keyword, cond, jump } => {
+                    let goto = expect_simple_goto(jump);
+                    self.lower_cond_jump(stmt.span, stmt_data, keyword, cond, goto)?;
+                },
+
+                ast::StmtKind::Declaration { ty_keyword, vars } => {
+                    self.lower_var_declaration(stmt_data, ty_keyword, vars)?;
+                },
+
+                ast::StmtKind::Return { value, .. } => {
+                    if let Some(value) = value {
+                        self.push_expr(stmt_data, value)?;
+                    }
+                    self.lower_intrinsic(stmt.span, stmt_data, IKind::Return, "return statement", |_| {})?;
+                },
+
+                ast::StmtKind::Expr(expr) => match &expr.value {
+                    ast::Expr::Call(call) => self.lower_call_stmt(expr.span, stmt_data, call)?,
+                    _ => return Err(self.unsupported(expr.span, &format!("{} in {}", expr.descr(), stmt.kind.descr()))),
+                },
+
+                ast::StmtKind::CallSub { async_, call, at_symbol: _ } => match async_ {
+                    Some(async_) => self.lower_async_call_stmt(stmt.span, stmt_data, call, async_)?,
+                    None => self.lower_call_stmt(stmt.span, stmt_data, call)?,
+                },
+
+                ast::StmtKind::Label(ident) => {
+                    self.out.push(sp!(stmt.span => LowerStmt::Label { time: stmt_data.time, label: ident.clone() }));
+                },
+
+                &ast::StmtKind::ScopeEnd(def_id) => {
+                    if let Some(index) = self.frame.locals.remove(&def_id) {
+                        self.frame.free(index);
+                    }
+                },
+
+                ast::StmtKind::NoInstruction => {},
+
+                // handled by semantics pass
+                ast::StmtKind::AbsTimeLabel { .. } => {},
+                ast::StmtKind::RelTimeLabel { .. } => {},
+                ast::StmtKind::Item { .. } => {},
+
+                _ => return Err(self.unsupported(stmt.span, stmt.kind.descr())),
+            }
+            Ok(())
+        }).collect_with_recovery()?;
+
+        self.lower_stack_alloc(code)
+    }
+
+    /// Reserves the frame at the beginning of the sub, if the language has an instruction for it.
+    fn lower_stack_alloc(&mut self, code: &[Sp<ast::Stmt>]) -> Result<(), ErrorReported> {
+        let frame_size = self.frame.size();
+        if frame_size == 0 || self.intrinsic_instrs.get_opcode_opt(IKind::StackAlloc).is_none() {
+            return Ok(());
+        }
+
+        let first_stmt = &code[0];
+        let stmt_data = self.stmt_data[&first_stmt.node_id.unwrap()];
+        self.lower_intrinsic(first_stmt.span.start_span(), stmt_data, IKind::StackAlloc, "stack alloc", |bld| {
+            bld.plain_args.push(sp!(first_stmt.span.start_span() => LowerArg::Raw(frame_size.into())));
+        })?;
+        let stack_alloc = self.out.pop().unwrap();
+        self.out.insert(0, stack_alloc);
+        Ok(())
+    }
+
+    // ------------------
+    // Methods for lowering specific types of statement bodies.
+
+    /// Lowers `func(<ARG1>, <ARG2>, <...>);`
+    fn lower_call_stmt(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        call: &ast::ExprCall,
+    ) -> Result<(), ErrorReported> {
+        match self.ctx.func_opcode_from_ast(&call.name) {
+            Ok((lang, opcode)) => {
+                self.check_instruction_language(lang, &call.name)?;
+                // if the instruction pushes a value, it is left on the stack just like for a raw push
+                self.lower_instruction(stmt_span, stmt_data, opcode as _, call)
+            },
+
+            Err(def_id) => {
+                let sub_info = self.sub_info.unwrap();
+                match self.ctx.defs.user_func_qualifier(def_id).expect("isn't user func?") {
+                    Some(sp_pat!(token![inline])) => Err(self.unsupported(stmt_span, "call to inline func")),
+                    Some(sp_pat!(token![const])) => Err(self.unsupported(stmt_span, "call to const func with non-const arguments")),
+                    None => self.lower_stack_call(stmt_span, stmt_data, call, &sub_info.exported_subs.subs[&def_id]),
+                }
+            },
+        }
+    }
+
+    fn check_instruction_language(&self, lang: LanguageKey, name: &Sp<ast::CallableName>) -> Result<(), ErrorReported> {
+        if lang != self.hooks.language() {
+            return Err(self.emitter.emit(error!(
+                message("{} instruction in {} code", lang.descr(), self.hooks.language().descr()),
+                primary(name, "cannot be used here"),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Lowers a call to an exported sub.  The arguments are pushed in signature order, so that the
+    /// callee finds them in the first slots of its frame, and the call pops them afterwards.
+    fn lower_stack_call(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        call: &ast::ExprCall,
+        sub: &crate::ecl::OldeExportedSub,
+    ) -> Result<(), ErrorReported> {
+        for arg in &call.args {
+            self.push_expr(stmt_data, arg)?;
+        }
+
+        let lowered_sub_id = sp!(call.name.span => LowerArg::Raw(sub.index.into()));
+        self.lower_intrinsic(stmt_span, stmt_data, IKind::Call, "sub call", |bld| {
+            bld.sub_id = Some(lowered_sub_id);
+            bld.stack_pop = call.args.len() as _;
+        })
+    }
+
+    /// Lowers `func(<ARG1>, <ARG2>, <...>) async;`  or  `func(<ARG1>, <ARG2>, <...>) async <ID>;`
+    fn lower_async_call_stmt(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        call: &ast::ExprCall,
+        async_: &ast::CallAsyncKind,
+    ) -> Result<(), ErrorReported> {
+        let (kind, descr) = match async_ {
+            ast::CallAsyncKind::CallAsync { .. } => (IKind::CallAsync, "async sub call"),
+            ast::CallAsyncKind::CallAsyncId { .. } => (IKind::CallAsyncId, "async sub call with id"),
+        };
+        if self.intrinsic_instrs.get_opcode_opt(kind).is_none() {
+            return Err(self.emitter.emit(self.intrinsic_instrs.missing_intrinsic_error(async_.keyword_span(), descr)));
+        }
+
+        let def_id = match self.ctx.func_opcode_from_ast(&call.name) {
+            Ok(_) => return Err(self.emitter.emit(error!(
+                message("async call to an instruction"),
+                primary(call.name, "not a sub"),
+                secondary(async_.keyword_span(), "only subs can be called asynchronously"),
+            ))),
+            Err(def_id) => def_id,
+        };
+        match self.ctx.defs.user_func_qualifier(def_id).expect("isn't user func?") {
+            Some(sp_pat!(token![inline])) => return Err(self.unsupported(stmt_span, "async call to inline func")),
+            Some(sp_pat!(token![const])) => return Err(self.unsupported(stmt_span, "async call to const func")),
+            None => {},
+        }
+        let sub = &self.sub_info.unwrap().exported_subs.subs[&def_id];
+
+        let mut temp_slots = vec![];
+        let lowered_id = match async_ {
+            ast::CallAsyncKind::CallAsync { .. } => None,
+            ast::CallAsyncKind::CallAsyncId { id, .. } => Some(self.lower_plain_arg(stmt_data, id, &mut temp_slots)?),
+        };
+        for arg in &call.args {
+            self.push_expr(stmt_data, arg)?;
+        }
+
+        let lowered_sub_id = sp!(call.name.span => LowerArg::Raw(sub.index.into()));
+        self.lower_intrinsic(stmt_span, stmt_data, kind, descr, |bld| {
+            bld.sub_id = Some(lowered_sub_id);
+            bld.plain_args.extend(lowered_id);
+            bld.stack_pop = call.args.len() as _;
+        })?;
+
+        for index in temp_slots {
+            self.frame.free(index);
+        }
+        Ok(())
+    }
+
+    /// Lowers `func(<ARG1>, <ARG2>, <...>);` where `func` is an instruction alias.
+    fn lower_instruction(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        opcode: raw::Opcode,
+        call: &ast::ExprCall,
+    ) -> Result<(), ErrorReported> {
+        let ast::ExprCall { pseudos, args, .. } = call;
+        let PseudoArgData {
+            // fully unpack because we need to add errors for anything unsupported
+            pop: pseudo_pop, blob: pseudo_blob, param_mask: pseudo_param_mask, extra_arg: pseudo_extra_arg,
+            arg_count: pseudo_arg_count,
+        } = PseudoArgData::from_pseudos(pseudos).map_err(|e| self.emitter.emit(e))?;
+
+        // slots holding the values of complicated arguments
+        let mut temp_slots = vec![];
+
+        let low_level_args = match pseudo_blob {
+            Some(blob) => {
+                assert!(args.is_empty());
+                LowerArgs::Unknown(sp!(blob.span => blob.to_vec()))
+            },
+
+            None => {
+                LowerArgs::Known(args.iter().map(|expr| {
+                    self.lower_plain_arg(stmt_data, expr, &mut temp_slots)
+                }).collect_with_recovery()?)
+            },
+        };
+
+        self.out.push(sp!(stmt_span => LowerStmt::Instr(LowerInstr {
+            stmt_data,
+            opcode: opcode as _,
+            user_param_mask: pseudo_param_mask,
+            explicit_extra_arg: pseudo_extra_arg.map(|x| x.value),
+            stack_pop: pseudo_pop.map_or(0, |x| x.value),
+            explicit_arg_count: pseudo_arg_count.map(|x| x.value),
+            args: low_level_args,
+        })));
+
+        for index in temp_slots {
+            self.frame.free(index);
+        }
+        Ok(())
+    }
+
+    /// Lowers `int x, y = 3, z;`
+    fn lower_var_declaration(
+        &mut self,
+        stmt_data: TimeAndDifficulty,
+        keyword: &Sp<ast::TypeKeyword>,
+        vars: &[Sp<(Sp<ast::Var>, Option<Sp<ast::Expr>>)>],
+    ) -> Result<(), ErrorReported> {
+        for pair in vars {
+            let (var, expr) = &pair.value;
+            let ident = var.name.expect_ident();
+            let def_id = self.ctx.resolutions.expect_def(ident);
+            // 'var' locals are fine if the type checker was able to infer their type
+            if self.ctx.defs.var_inherent_ty(def_id).as_known_ty().is_none() {
+                return Err(self.unsupported(keyword.span, "untyped variables"));
+            }
+            let ty = self.ctx.defs.var_inherent_ty(def_id).as_known_ty().expect("(bug!) untyped in stackful lowerer");
+            let ty = ReadType::from_ty(ty).ok_or_else(|| self.emitter.emit(error!(
+                message("local of non-numeric type"),
+                primary(var.span, "{} cannot be stored on the stack", ty.descr_plural()),
+            )))?;
+
+            let index = self.frame.alloc();
+            self.bind_local(def_id, index, ty, var.span);
+
+            if let Some(expr) = expr {
+                let assign_op = sp!(pair.span => token![=]);
+                self.lower_assign_op(pair.span, stmt_data, var, &assign_op, expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lowers `a = <B>;`  or  `a *= <B>;`
+    fn lower_assign_op(
+        &mut self,
+        span: Span,
+        stmt_data: TimeAndDifficulty,
+        var: &Sp<ast::Var>,
+        assign_op: &Sp<ast::AssignOpKind>,
+        rhs: &Sp<ast::Expr>,
+    ) -> Result<(), ErrorReported> {
+        let (lowered_var, ty_var) = self.lower_var_to_arg(var)?;
+
+        match assign_op.value.corresponding_binop() {
+            // a = <B>;   compiles to   push <B>;  pop a;
+            None => {
+                self.push_expr(stmt_data, rhs)?;
+            },
+            // a *= <B>;  compiles to   push a;  push <B>;  mul;  pop a;
+            Some(binop) => {
+                self.push_arg(var.span, stmt_data, lowered_var.clone(), ty_var)?;
+                self.push_expr(stmt_data, rhs)?;
+                self.lower_intrinsic(assign_op.span, stmt_data, IKind::StackBinOp(binop, ty_var), "update assignment with this operation", |_| {})?;
+            },
+        }
+        self.lower_intrinsic(span, stmt_data, IKind::Pop(ty_var), "assignment", |bld| {
+            bld.outputs.push(lowered_var);
+        })
+    }
+
+    fn lower_uncond_jump(&mut self, stmt_span: Span, stmt_data: TimeAndDifficulty, goto: &ast::StmtGoto) -> Result<(), ErrorReported> {
+        self.lower_intrinsic(stmt_span, stmt_data, IKind::Jmp, "'goto'", |bld| {
+            bld.jump = Some(goto);
+        })
+    }
+
+    /// Lowers `if (<cond>) goto label @ time;`
+    fn lower_cond_jump(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        keyword: &Sp<ast::CondKeyword>,
+        cond: &Sp<ast::Expr>,
+        goto: &ast::StmtGoto,
+    ) -> Result<(), ErrorReported> {
+        // 'if (--x) goto label' compiles to:
+        //
+        //         x -= 1;
+        //         if (x) goto label;
+        if let Some((var, kind)) = CountJmpKind::of_cond(cond) {
+            let one = sp!(cond.span => 1.into());
+            self.lower_assign_op(cond.span, stmt_data, var, &sp!(cond.span => token![-=]), &one)?;
+
+            let var_as_expr = sp!(var.span => ast::Expr::Var(var.clone()));
+            let new_cond = match kind {
+                CountJmpKind::PredecNeZero => var_as_expr,
+                CountJmpKind::PredecGtZero => {
+                    let zero = sp!(cond.span => 0.into());
+                    sp!(cond.span => ast::Expr::BinOp(Box::new(var_as_expr), sp!(cond.span => token![>]), Box::new(zero)))
+                },
+            };
+            return self.lower_cond_jump(stmt_span, stmt_data, keyword, &new_cond, goto);
+        }
+
+        match &cond.value {
+            // 'if (!<B>) goto label'
+            ast::Expr::UnOp(sp_pat!(op_span => token![!]), b) => {
+                let negated_kw = sp!(*op_span => keyword.negate());
+                self.lower_cond_jump(stmt_span, stmt_data, &negated_kw, b, goto)
+            },
+
+            // 'if (<A> || <B>) goto label'
+            ast::Expr::BinOp(a, binop, b) if matches!(binop.value, token![&&] | token![||]) => {
+                self.lower_cond_jump_logic_binop(stmt_span, stmt_data, keyword, a, binop, b, goto)
+            },
+
+            // 'if (<expr>) goto label'  compiles to  push <expr>;  jump if nonzero;
+            _ => {
+                self.push_expr(stmt_data, cond)?;
+                let op = match keyword.value {
+                    token![if] => token![!=],
+                    token![unless] => token![==],
+                };
+                self.lower_intrinsic(stmt_span, stmt_data, IKind::StackCondJmp(op), "this conditional jump", |bld| {
+                    bld.jump = Some(goto);
+                })
+            },
+        }
+    }
+
+    /// Lowers `if (<A> || <B>) goto label @ time;` and similar
+    fn lower_cond_jump_logic_binop(
+        &mut self,
+        stmt_span: Span,
+        stmt_data: TimeAndDifficulty,
+        keyword: &Sp<ast::CondKeyword>,
+        a: &Sp<ast::Expr>,
+        binop: &Sp<ast::BinOpKind>,
+        b: &Sp<ast::Expr>,
+        goto: &ast::StmtGoto,
+    ) -> Result<(), ErrorReported> {
+        let is_easy_case = match (keyword.value, binop.value) {
+            (token![if], token![||]) => true,
+            (token![if], token![&&]) => false,
+            (token![unless], token![&&]) => true,
+            (token![unless], token![||]) => false,
+            _ => unreachable!("non-logic binop in lower_cond_jump_logic_binop: {}", binop)
+        };
+
+        if is_easy_case {
+            // 'if (a || b) ...' can just split up into 'if (a) ...' and 'if (b) ...'.
+            self.lower_cond_jump(stmt_span, stmt_data, keyword, a, goto)?;
+            self.lower_cond_jump(stmt_span, stmt_data, keyword, b, goto)
+        } else {
+            // 'if (a && b) goto label' compiles to:
+            //
+            //         unless (a) goto skip;
+            //         unless (b) goto skip;
+            //         goto label;
+            //      skip:
+            let negated_kw = sp!(keyword.span => keyword.negate());
+            let skip_label = sp!(binop.span => self.gensym.gensym("@unless_predec_skip#"));
+            let skip_goto = ast::StmtGoto { time: None, destination: skip_label.clone() };
+
+            self.lower_cond_jump(stmt_span, stmt_data, &negated_kw, a, &skip_goto)?;
+            self.lower_cond_jump(stmt_span, stmt_data, &negated_kw, b, &skip_goto)?;
+            self.lower_uncond_jump(stmt_span, stmt_data, goto)?;
+            self.out.push(sp!(binop.span => LowerStmt::Label { time: stmt_data.time, label: skip_label }));
+            Ok(())
+        }
+    }
+
+    // ------------------
+    // Expressions.
+
+    /// Emits instructions that leave the value of an expression on top of the stack.
+    fn push_expr(&mut self, stmt_data: TimeAndDifficulty, expr: &Sp<ast::Expr>) -> Result<ScalarType, ErrorReported> {
+        if let Some((lowered, ty)) = self.simple_arg(expr)? {
+            self.push_arg(expr.span, stmt_data, lowered, ty)?;
+            return Ok(ty);
+        }
+
+        match &expr.value {
+            ast::Expr::BinOp(a, binop, b) => {
+                let arg_ty = self.push_expr(stmt_data, a)?;
+                self.push_expr(stmt_data, b)?;
+                self.lower_intrinsic(binop.span, stmt_data, IKind::StackBinOp(binop.value, arg_ty), "this binary operation", |_| {})?;
+                Ok(ast::Expr::binop_ty_from_arg_ty(binop.value, arg_ty))
+            },
+
+            ast::Expr::UnOp(unop, b) => {
+                let arg_ty = self.push_expr(stmt_data, b)?;
+                let out_ty = ast::Expr::unop_ty_from_arg_ty(unop.value, arg_ty);
+
+                // sigils and casts only need an instruction if they change the type
+                let op = match unop.as_ty_sigil_with_auto_cast() {
+                    Some(_) if out_ty == arg_ty => return Ok(out_ty),
+                    Some(_) => match out_ty {
+                        ScalarType::Int => token![unop int],
+                        _ => token![unop float],
+                    },
+                    None => unop.value,
+                };
+                self.lower_intrinsic(unop.span, stmt_data, IKind::StackUnOp(op, arg_ty), "this unary operation", |_| {})?;
+                Ok(out_ty)
+            },
+
+            // instructions that produce a value
+            ast::Expr::Call(call) if self.ctx.func_opcode_from_ast(&call.name).is_ok() => {
+                let Ok((lang, opcode)) = self.ctx.func_opcode_from_ast(&call.name) else { unreachable!() };
+                self.check_instruction_language(lang, &call.name)?;
+                let ret = self.ctx.defs.ins_ret(lang, opcode).expect("(bug!) type-checked as a value");
+
+                self.lower_instruction(expr.span, stmt_data, opcode as _, call)?;
+                if let InsRetLocation::Reg(reg) = ret.location {
+                    let lowered = sp!(expr.span => LowerArg::Raw(SimpleArg::from_reg(reg, ret.ty)));
+                    self.push_arg(expr.span, stmt_data, lowered, ret.ty)?;
+                }
+                Ok(ret.ty)
+            },
+
+            _ => Err(self.unsupported(expr.span, &format!("{} on the stack", expr.descr()))),
+        }
+    }
+
+    /// Lowers an expression used directly as an argument to an instruction.
+    ///
+    /// Complicated expressions are computed into a temporary slot, which is added to `temp_slots`
+    /// so that the caller can free it after the instruction.
+    fn lower_plain_arg(&mut self, stmt_data: TimeAndDifficulty, expr: &Sp<ast::Expr>, temp_slots: &mut Vec<usize>) -> Result<Sp<LowerArg>, ErrorReported> {
+        if let Some((lowered, _)) = self.simple_arg(expr)? {
+            return Ok(lowered);
+        }
+        let ty = self.push_expr(stmt_data, expr)?;
+        let index = self.frame.alloc();
+        let slot = sp!(expr.span => LowerArg::Raw(SimpleArg::from_reg(StackFrame::slot_reg(index), ty)));
+        self.lower_intrinsic(expr.span, stmt_data, IKind::Pop(ty), "pop", |bld| {
+            bld.outputs.push(slot.clone());
+        })?;
+        temp_slots.push(index);
+        Ok(slot)
+    }
+
+    fn push_arg(&mut self, span: Span, stmt_data: TimeAndDifficulty, lowered: Sp<LowerArg>, ty: ScalarType) -> Result<(), ErrorReported> {
+        self.lower_intrinsic(span, stmt_data, IKind::Push(ty), "push", |bld| {
+            bld.plain_args.push(lowered);
+        })
+    }
+
+    /// Returns `Some` if the expression can be used directly as an argument to an instruction.
+    fn simple_arg(&self, expr: &Sp<ast::Expr>) -> Result<Option<(Sp<LowerArg>, ScalarType)>, ErrorReported> {
+        match &expr.value {
+            &ast::Expr::LitInt { value, .. } => Ok(Some((sp!(expr.span => LowerArg::Raw(value.into())), ScalarType::Int))),
+            &ast::Expr::LitFloat { value, .. } => Ok(Some((sp!(expr.span => LowerArg::Raw(value.into())), ScalarType::Float))),
+            ast::Expr::LitString(ast::LitString { string, .. }) => {
+                Ok(Some((sp!(expr.span => LowerArg::Raw(string.clone().into())), ScalarType::String)))
+            },
+            ast::Expr::Var(var) => self.lower_var_to_arg(var).map(Some),
+            ast::Expr::LabelProperty { script, keyword, label } => Ok(Some((
+                sp!(expr.span => LowerArg::from_label_property(script, label, keyword.value)),
+                ScalarType::Int,
+            ))),
+
+            // Reading a variable as the other type.
+            ast::Expr::UnOp(unop, b) if unop.as_ty_sigil().is_some() || self.hooks.has_auto_casts() => {
+                let (sigil, var) = match (unop.as_ty_sigil_with_auto_cast(), &b.value) {
+                    (Some(sigil), ast::Expr::Var(var)) => (sigil, var),
+                    _ => return Ok(None),
+                };
+                let read_var = sp!(var.span => ast::Var { ty_sigil: Some(sigil), ..var.value.clone() });
+                self.lower_var_to_arg(&read_var).map(Some)
+            },
+
+            // Difficulty switches of simple arguments can be used to replicate the instruction.
+            ast::Expr::DiffSwitch(cases) => {
+                let mut lowered_cases = vec![];
+                let mut ty = None;
+                for case in cases {
+                    match case {
+                        None => lowered_cases.push(None),
+                        Some(case) => match self.simple_arg(case)? {
+                            Some((lowered_case, case_ty)) => {
+                                lowered_cases.push(Some(lowered_case));
+                                ty = Some(case_ty);
+                            },
+                            None => return Ok(None),
+                        },
+                    }
+                }
+                let lowered = sp!(expr.span => LowerArg::DiffSwitch(lowered_cases));
+                Ok(Some((lowered, ty.expect("always at least one case"))))
+            },
+
+            _ => Ok(None),
+        }
+    }
+
+    fn lower_var_to_arg(&self, var: &Sp<ast::Var>) -> Result<(Sp<LowerArg>, ScalarType), ErrorReported> {
+        let read_ty = self.ctx.var_read_ty_from_ast(var).as_known_ty().expect("(bug!) untyped in stackful lowerer");
+
+        // Registers are used as is, while locals are found in the stack frame.
+        let reg = match self.ctx.var_reg_from_ast(&var.name) {
+            Ok((_lang, reg)) => reg,
+            Err(def_id) => StackFrame::slot_reg(self.frame.locals[&def_id]),
+        };
+        Ok((sp!(var.span => LowerArg::Raw(SimpleArg::from_reg(reg, read_ty))), read_ty))
+    }
+
+    fn bind_local(&mut self, def_id: DefId, index: usize, ty: ReadType, span: Span) {
+        assert!(self.frame.locals.insert(def_id, index).is_none());
+        if let Some(debug_info) = &mut self.debug_info {
+            debug_info.locals.push(debug_info::Local {
+                name: self.ctx.defs.var_name(def_id).to_string(),
+                name_span: span.into(),
+                r#type: ty.into(),
+                bound_to: StackFrame::slot_reg(index).into(),
+            });
+        }
+    }
+
+    fn unsupported(&self, span: Span, what: &str) -> ErrorReported {
+        self.emitter.emit(super::unsupported(span, what))
+    }
+}
+
+fn expect_simple_goto(jump: &ast::StmtJumpKind) -> &ast::StmtGoto {
+    match jump {
+        ast::StmtJumpKind::Goto(goto) => goto,
+        ast::StmtJumpKind::BreakContinue { .. } => panic!("a break/continue made it to the lowering stage"),
+    }
+}
@@ -2,18 +2,24 @@
 //!
 //! Responsible for compilation of expressions into instructions that use temporary registers.
 
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, BTreeMap, BTreeSet};
+use enum_map::EnumMap;
 
 use crate::raw;
 use super::{LowerStmt, LowerInstr, LowerArgs, LowerArg, SimpleArg};
+use super::intrinsic::LowerIntrinsic;
 use crate::diagnostic::Diagnostic;
 use crate::llir::{LanguageHooks, IntrinsicInstrKind, IntrinsicInstrs, HowBadIsIt, intrinsic::alternatives};
 use crate::error::{GatherErrorIteratorExt, ErrorReported};
 use crate::pos::{Sp, Span};
 use crate::ast::{self, pseudo::PseudoArgData};
-use crate::resolve::{DefId, RegId, NodeId, AliasableId, IdMap};
+use crate::resolve::{DefId, ResId, RegId, NodeId, AliasableId, IdMap};
+use crate::game::LanguageKey;
 use crate::value::{ScalarType, ReadType};
 use crate::context::CompilerContext;
+use crate::context::defs::{InsRet, InsRetLocation};
+use crate::diagnostic::RootEmitter;
+use crate::ident::{Ident, ResIdent, GensymContext};
 use crate::passes::semantics::time_and_difficulty::TimeAndDifficulty;
 use crate::debug_info;
 
@@ -24,11 +30,74 @@ pub (in crate::llir::lower) struct SingleSubLowerer<'a, 'ctx> {
     pub out: Vec<Sp<LowerStmt>>,
     pub intrinsic_instrs: IntrinsicInstrs,
     pub hooks: &'a dyn LanguageHooks,
-    pub ctx: &'a mut CompilerContext<'ctx>,
+    pub ctx: &'a CompilerContext<'ctx>,
+    /// Emitter for this sub only.  (diagnostics from subs lowered in parallel are buffered and
+    /// emitted in a consistent order afterwards)
+    pub emitter: &'a RootEmitter,
+    pub gensym: GensymContext,
+    pub temporaries: Temporaries,
     pub stmt_data: IdMap<NodeId, TimeAndDifficulty>,
     pub sub_info: Option<&'a super::SubInfo<'a>>,
 }
 
+/// Register-allocated temporaries created while lowering a single sub.
+///
+/// These are never recorded in the [`CompilerContext`], so that multiple subs can be lowered at once.
+/// Their [`DefId`]s are only unique within the sub.
+pub (in crate::llir::lower) struct Temporaries {
+    unused_ids: Box<dyn Iterator<Item=(ResId, DefId)> + Send>,
+    defs: IdMap<ResId, DefId>,
+    vars: IdMap<DefId, (Ident, ScalarType)>,
+}
+
+impl Temporaries {
+    pub fn new(ctx: &CompilerContext<'_>) -> Self {
+        Temporaries { unused_ids: Box::new(ctx.resolutions.unrecorded_res_ids()), defs: Default::default(), vars: Default::default() }
+    }
+
+    pub (in crate::llir::lower) fn define(&mut self, ident: Ident, ty: ScalarType) -> (ResIdent, DefId) {
+        let (res, def_id) = self.unused_ids.next().unwrap();
+        self.defs.insert(res, def_id);
+        self.vars.insert(def_id, (ident.clone(), ty));
+        (ResIdent::new(ident, res), def_id)
+    }
+
+    /// Look up the [`DefId`] of a name if it is a temporary.
+    fn def_id(&self, var: &ast::VarName) -> Option<DefId> {
+        match var {
+            ast::VarName::Normal { ident, .. } => self.defs.get(&ident.expect_res()).copied(),
+            ast::VarName::Reg { .. } => None,
+        }
+    }
+
+    /// Whether a [`DefId`] belongs to a temporary rather than a user-declared local.
+    pub (in crate::llir::lower) fn is_temporary(&self, def_id: DefId) -> bool {
+        self.vars.contains_key(&def_id)
+    }
+
+    /// [`crate::context::Defs::var_name`], aware of temporaries.
+    fn var_name<'a>(&'a self, ctx: &'a CompilerContext<'_>, def_id: DefId) -> &'a Ident {
+        match self.vars.get(&def_id) {
+            Some((ident, _)) => ident,
+            None => ctx.defs.var_name(def_id),
+        }
+    }
+
+    /// [`crate::context::Defs::var_inherent_ty`], aware of temporaries.
+    fn var_inherent_ty(&self, ctx: &CompilerContext<'_>, def_id: DefId) -> Option<ScalarType> {
+        match self.vars.get(&def_id) {
+            Some(&(_, ty)) => Some(ty),
+            None => ctx.defs.var_inherent_ty(def_id).as_known_ty(),
+        }
+    }
+}
+
+impl LowerIntrinsic for SingleSubLowerer<'_, '_> {
+    fn intrinsic_instrs(&self) -> &IntrinsicInstrs { &self.intrinsic_instrs }
+    fn emitter(&self) -> &RootEmitter { self.emitter }
+    fn push_stmt(&mut self, stmt: Sp<LowerStmt>) { self.out.push(stmt) }
+}
+
 impl SingleSubLowerer<'_, '_> {
     pub fn lower_sub_ast(
         &mut self,
@@ -37,7 +106,7 @@ impl SingleSubLowerer<'_, '_> {
         let mut th06_anm_end_span = None;
         code.iter().map(|stmt| {
             if let Some(end) = th06_anm_end_span {
-                if !matches!(&stmt.kind, ast::StmtKind::NoInstruction) { return Err(self.ctx.emitter.emit(error!(
+                if !matches!(&stmt.kind, ast::StmtKind::NoInstruction) { return Err(self.emitter.emit(error!(
                     message("statement after end of script"),
                     primary(&stmt, "forbidden statement"),
                     secondary(&end, "marks the end of the script"),
@@ -61,7 +130,8 @@ impl SingleSubLowerer<'_, '_> {
 
                 ast::StmtKind::InterruptLabel(interrupt_id) => {
                     self.lower_intrinsic(stmt.span, stmt_data, IKind::InterruptLabel, "interrupt label", |bld| {
-                        let lowered_id = interrupt_id.sp_map(|value| LowerArg::Raw(value.into()));
+                        let value = interrupt_id.as_const_int().expect("const_simplify should have caught this");
+                        let lowered_id = sp!(interrupt_id.span => LowerArg::Raw(value.into()));
                         bld.plain_args.push(lowered_id);
                     })?;
                 },
@@ -83,6 +153,14 @@ impl SingleSubLowerer<'_, '_> {
                     _ => return Err(self.unsupported(expr.span, &format!("{} in {}", expr.descr(), stmt.kind.descr()))),
                 }, // match expr
 
+                ast::StmtKind::CallSub { async_: Some(async_), .. } => {
+                    return Err(self.unsupported(async_.keyword_span(), "async sub call"));
+                },
+
+                ast::StmtKind::CallSub { async_: None, call, at_symbol: _ } => {
+                    self.lower_call_stmt(&mut th06_anm_end_span, stmt.span, stmt_data, call)?;
+                },
+
                 ast::StmtKind::Label(ident) => {
                     self.out.push(sp!(stmt.span => LowerStmt::Label { time: stmt_data.time, label: ident.clone() }));
                 },
@@ -118,7 +196,7 @@ impl SingleSubLowerer<'_, '_> {
         match self.ctx.func_opcode_from_ast(&call.name) {
             Ok((lang, opcode)) => {
                 // single instruction
-                assert_eq!(lang, self.hooks.language());
+                self.check_instruction_language(lang, &call.name)?;
                 self.lower_instruction(stmt_span, stmt_data, opcode as _, call)?;
 
                 if self.hooks.is_th06_anm_terminating_instr(opcode) {
@@ -132,7 +210,7 @@ impl SingleSubLowerer<'_, '_> {
                 let sub_info = self.sub_info.unwrap();
                 match self.ctx.defs.user_func_qualifier(def_id).expect("isn't user func?") {
                     Some(sp_pat!(token![inline])) => Err(self.unsupported(stmt_span, "call to inline func")),
-                    Some(sp_pat!(token![const])) => panic!("leftover const func call during lowering"),
+                    Some(sp_pat!(token![const])) => Err(self.unsupported(stmt_span, "call to const func with non-const arguments")),
                     None => match sub_info.call_reg_info.as_ref() {
                         None => {
                             self.lower_eosd_call(stmt_span, stmt_data, call, &sub_info.exported_subs.subs[&def_id])
@@ -220,13 +298,19 @@ impl SingleSubLowerer<'_, '_> {
         let PseudoArgData {
             // fully unpack because we need to add errors for anything unsupported
             pop: pseudo_pop, blob: pseudo_blob, param_mask: pseudo_param_mask, extra_arg: pseudo_extra_arg,
-        } = PseudoArgData::from_pseudos(pseudos).map_err(|e| self.ctx.emitter.emit(e))?;
+            arg_count: pseudo_arg_count,
+        } = PseudoArgData::from_pseudos(pseudos).map_err(|e| self.emitter.emit(e))?;
 
         if let Some(pop) = pseudo_pop {
             if pop.value != 0 {
                 return Err(self.unsupported(pop.span, "stack-pop pseudo argument"));
             }
         }
+        if let Some(arg_count) = pseudo_arg_count {
+            if arg_count.value != 0 {
+                return Err(self.unsupported(arg_count.span, "argument count pseudo argument"));
+            }
+        }
 
         // records temporaries for function arguments
         let mut temp_def_ids = vec![];
@@ -234,6 +318,11 @@ impl SingleSubLowerer<'_, '_> {
         let low_level_args = match pseudo_blob {
             Some(blob) => {
                 assert!(args.is_empty());
+                if self.ctx.warn_blob_signatures {
+                    if let Some(abi_and_loc) = self.ctx.defs.ins_abi(self.hooks.language(), opcode) {
+                        super::check_user_blob_against_abi(self.hooks, &blob, pseudo_param_mask, abi_and_loc, self.emitter);
+                    }
+                }
                 LowerArgs::Unknown(sp!(blob.span => blob.to_vec()))
             },
 
@@ -259,8 +348,10 @@ impl SingleSubLowerer<'_, '_> {
         self.out.push(sp!(stmt_span => LowerStmt::Instr(LowerInstr {
             stmt_data,
             opcode: opcode as _,
-            user_param_mask: pseudo_param_mask.map(|x| x.value),
+            user_param_mask: pseudo_param_mask,
             explicit_extra_arg: pseudo_extra_arg.map(|x| x.value),
+            stack_pop: 0,
+            explicit_arg_count: None,
             args: low_level_args,
         })));
 
@@ -279,14 +370,14 @@ impl SingleSubLowerer<'_, '_> {
         keyword: &Sp<ast::TypeKeyword>,
         vars: &[Sp<(Sp<ast::Var>, Option<Sp<ast::Expr>>)>],
     ) -> Result<(), ErrorReported>{
-        if keyword.value == token![var] {
-            return Err(self.unsupported(keyword.span, "untyped variables"));
-        }
-
         for pair in vars {
             let (var, expr) = &pair.value;
             let ident = var.name.expect_ident();
             let def_id = self.ctx.resolutions.expect_def(ident);
+            // 'var' locals are fine if the type checker was able to infer their type
+            if self.ctx.defs.var_inherent_ty(def_id).as_known_ty().is_none() {
+                return Err(self.unsupported(keyword.span, "untyped variables"));
+            }
             self.out.push(sp!(var.span => LowerStmt::RegAlloc { def_id }));
 
             if let Some(expr) = expr {
@@ -346,6 +437,11 @@ impl SingleSubLowerer<'_, '_> {
                 self.lower_assign_diff_switch(span, stmt_data, &data_rhs.tmp_expr, var, assign_op, cases)
             },
 
+            // a = ins_30(<expr>);
+            (ast::AssignOpKind::Assign, ast::Expr::Call(call)) if self.ctx.func_opcode_from_ast(&call.name).is_ok() => {
+                self.lower_assign_direct_call(span, stmt_data, var, assign_op, rhs.span, call)
+            },
+
             // a = <any other expr>;
             // a += <expr>;
             (_, _) => {
@@ -367,6 +463,58 @@ impl SingleSubLowerer<'_, '_> {
         }
     }
 
+    /// Lowers `a = func(<ARG1>, <ARG2>, <...>);` where `func` is an instruction that produces a value.
+    ///
+    /// The instruction writes its value to a register, which is then copied into the destination.
+    fn lower_assign_direct_call(
+        &mut self,
+        span: Span,
+        stmt_data: TimeAndDifficulty,
+        var: &Sp<ast::Var>,
+        assign_op: &Sp<ast::AssignOpKind>,
+        rhs_span: Span,
+        call: &ast::ExprCall,
+    ) -> Result<(), ErrorReported> {
+        let (opcode, ret) = self.value_instruction(call)?;
+        let out_reg = match ret.location {
+            InsRetLocation::Reg(reg) => reg,
+            InsRetLocation::Stack => return Err(self.emitter.emit(error!(
+                message("instruction returns its value on the stack"),
+                primary(call.name, "cannot be used as a value"),
+                note("{} has no stack; the mapfile should give the register that holds the value", self.hooks.language().descr()),
+            ))),
+        };
+        self.lower_instruction(span, stmt_data, opcode as _, call)?;
+
+        // `REG[10000] = ins_30();` needs no copy
+        if let Ok((_, var_reg)) = self.var_reg_from_ast(&var.name) {
+            if var_reg == out_reg {
+                return Ok(());
+            }
+        }
+        let read_ty = ReadType::from_ty(ret.ty).expect("numeric return type");
+        let out_expr = sp!(rhs_span => ast::Expr::Var(self.reg_to_var(sp!(rhs_span => out_reg), read_ty)));
+        self.lower_assign_op(span, stmt_data, var, assign_op, &out_expr)
+    }
+
+    /// Get the opcode of an instruction whose value is used, and how it produces that value.
+    fn value_instruction(&self, call: &ast::ExprCall) -> Result<(raw::Opcode, InsRet), ErrorReported> {
+        let Ok((lang, opcode)) = self.ctx.func_opcode_from_ast(&call.name) else { panic!("not an instruction") };
+        self.check_instruction_language(lang, &call.name)?;
+        let ret = self.ctx.defs.ins_ret(lang, opcode).expect("(bug!) type-checked as a value");
+        Ok((opcode, ret))
+    }
+
+    fn check_instruction_language(&self, lang: LanguageKey, name: &Sp<ast::CallableName>) -> Result<(), ErrorReported> {
+        if lang != self.hooks.language() {
+            return Err(self.emitter.emit(error!(
+                message("{} instruction in {} code", lang.descr(), self.hooks.language().descr()),
+                primary(name, "cannot be used here"),
+            )));
+        }
+        Ok(())
+    }
+
     /// Lowers `a = <atom>;`  or  `a *= <atom>;`
     fn lower_assign_op_intrinsic(
         &mut self,
@@ -377,7 +525,7 @@ impl SingleSubLowerer<'_, '_> {
         rhs: SimpleExpr,
     ) -> Result<(), ErrorReported> {
         let SimpleExpr { lowered: lowered_rhs, ty: ty_rhs } = rhs;
-        let (lowered_var, ty_var) = lower_var_to_arg(var, self.ctx)?;
+        let (lowered_var, ty_var) = self.lower_var_to_arg(var)?;
         assert_eq!(ty_var, ty_rhs, "already type-checked");
 
         match self.intrinsic_instrs.alternatives().assign_ops.get(&(assign_op.value, ty_var)) {
@@ -411,6 +559,7 @@ impl SingleSubLowerer<'_, '_> {
             name: ast::VarName::Reg {
                 reg: reg.value,
                 language: Some(self.hooks.language()),
+                language_prefix: None,
             },
         })
     }
@@ -429,7 +578,7 @@ impl SingleSubLowerer<'_, '_> {
         // Those would've hit an ExprClass::Simple case in another method first...
         match self.classify_expr(whole_expr)? {
             ExprClass::Simple(SimpleExpr { .. }) => {
-                self.ctx.emitter.emit(bug!(
+                self.emitter.emit(bug!(
                     message("unhandled simple diff switch"),
                     note("I didn't think this was possible. You get a cookie!"),
                 )).ignore();
@@ -486,7 +635,7 @@ impl SingleSubLowerer<'_, '_> {
                 //        even prevents some legitimate cases of reuse like reusing `A` in `A = %(I0 + 1) < 1.5`;
                 //        But I'm not 100% sure and I'd rather just wait until we can replace of all of this
                 //        "variable reuse" logic with SSA-based analysis.
-                if data_a.tmp_ty == ty_rhs && data_a.tmp_ty == data_a.read_ty && !expr_uses_var(b, var, self.ctx) {
+                if data_a.tmp_ty == ty_rhs && data_a.tmp_ty == data_a.read_ty && !self.expr_uses_var(b, var) {
                     // we can reuse the output variable!
                     let var_as_expr = self.compute_temporary_expr(stmt_data, var, &data_a)?;
                     self.lower_assign_direct_binop(span, stmt_data, var, eq_sign, rhs_span, &var_as_expr, binop, b)?;
@@ -507,7 +656,7 @@ impl SingleSubLowerer<'_, '_> {
         let simple_b = match self.classify_expr(b)? {
             ExprClass::NeedsElaboration(data_b) => {
                 // similar conditions apply...
-                if data_b.tmp_ty == ty_rhs && data_b.tmp_ty == data_b.read_ty && !expr_uses_var(a, var, self.ctx) {
+                if data_b.tmp_ty == ty_rhs && data_b.tmp_ty == data_b.read_ty && !self.expr_uses_var(a, var) {
                     // we can reuse the output variable!
                     let var_as_expr = self.compute_temporary_expr(stmt_data, var, &data_b)?;
                     self.lower_assign_direct_binop(span, stmt_data, var, eq_sign, rhs_span, a, binop, &var_as_expr)?;
@@ -523,7 +672,7 @@ impl SingleSubLowerer<'_, '_> {
         };
 
         // They're both simple.  Emit a primitive instruction.
-        let (lowered_var, ty_var) = lower_var_to_arg(var, self.ctx)?;
+        let (lowered_var, ty_var) = self.lower_var_to_arg(var)?;
         assert_eq!(ty_var, ty_rhs, "already type-checked");
 
         self.lower_intrinsic(rhs_span, stmt_data, IKind::BinOp(binop.value, simple_a.ty), "this binary operation", |bld| {
@@ -569,7 +718,7 @@ impl SingleSubLowerer<'_, '_> {
             },
 
             ExprClass::Simple(data_b) => {
-                let (lowered_var, ty_var) = lower_var_to_arg(var, self.ctx)?;
+                let (lowered_var, ty_var) = self.lower_var_to_arg(var)?;
                 assert_eq!(ty_var, ty_rhs, "already type-checked");
 
                 self.lower_assign_direct_unop_intrinsic(span, stmt_data, lowered_var, rhs_span, unop, data_b)
@@ -646,10 +795,10 @@ impl SingleSubLowerer<'_, '_> {
             None => {
                 let mut diag = self.intrinsic_instrs.missing_intrinsic_error(stmt_span, "decrement jump of this form");
                 if let Some(existing_kind) = alternatives.preferred_count_jmp {
-                    let suggestion = existing_kind.render_suggestion(var);
+                    let suggestion = existing_kind.render_suggestion(var, self.ctx);
                     diag.note(format!("this language supports a different form of decrement jump; try '{keyword} ({suggestion})'"));
                 }
-                return Err(self.ctx.emitter.emit(diag));
+                return Err(self.emitter.emit(diag));
             },
             Some(&alternatives::CountJmp::Intrinsic { opcode }) => {
                 self.lower_count_jump_intrinsic(stmt_span, stmt_data, keyword, var, opcode, goto)
@@ -669,7 +818,7 @@ impl SingleSubLowerer<'_, '_> {
         match keyword.value {
             // 'if (--var) goto label'
             token![if] => {
-                let (lowered_var, ty_var) = lower_var_to_arg(var, self.ctx)?;
+                let (lowered_var, ty_var) = self.lower_var_to_arg(var)?;
                 assert_eq!(ty_var, ScalarType::Int, "shoulda been type-checked!");
 
                 self.lower_intrinsic_by_opcode(stmt_span, stmt_data, opcode, |bld| {
@@ -686,7 +835,7 @@ impl SingleSubLowerer<'_, '_> {
                 //        goto label
                 //     skip:
 
-                let skip_label = sp!(keyword.span => self.ctx.gensym.gensym("@unless_predec_skip#"));
+                let skip_label = sp!(keyword.span => self.gensym.gensym("@unless_predec_skip#"));
                 let if_keyword = sp!(keyword.span => token![if]);
                 let if_goto = ast::StmtGoto { time: None, destination: skip_label.clone() };
 
@@ -767,12 +916,27 @@ impl SingleSubLowerer<'_, '_> {
             },
 
             // `if (a != b) ...` (or `unless (a != b) ...`)
-            (ExprClass::Simple(data_a), ExprClass::Simple(data_b)) => {
-                let binop = sp!(binop.span => match keyword.value {
-                    token![if] => binop.value,
-                    token![unless] => binop.negate_comparison().expect("lower_cond_jump_comparison called with non-comparison operator"),
-                });
-                self.lower_cond_jump_intrinsic(stmt_span, stmt_data, data_a, &binop, data_b, goto)?;
+            (ExprClass::Simple(data_a), ExprClass::Simple(data_b)) => match keyword.value {
+                token![if] => self.lower_cond_jump_intrinsic(stmt_span, stmt_data, data_a, binop, data_b, goto)?,
+                token![unless] => {
+                    let negated = sp!(binop.span => binop.negate_comparison().expect("lower_cond_jump_comparison called with non-comparison operator"));
+                    let cond_jmps = &self.intrinsic_instrs.alternatives().cond_jmps;
+                    if cond_jmps.contains_key(&(negated.value, data_a.ty)) || !cond_jmps.contains_key(&(binop.value, data_a.ty)) {
+                        self.lower_cond_jump_intrinsic(stmt_span, stmt_data, data_a, &negated, data_b, goto)?;
+                    } else {
+                        // Only the original comparison is available, so jump over an unconditional jump:
+                        //
+                        //         if (a == b) goto skip;
+                        //         goto label;
+                        //      skip:
+                        let skip_label = sp!(binop.span => self.gensym.gensym("@unless_polyfill_skip#"));
+                        let skip_goto = ast::StmtGoto { time: None, destination: skip_label.clone() };
+
+                        self.lower_cond_jump_intrinsic(stmt_span, stmt_data, data_a, binop, data_b, &skip_goto)?;
+                        self.lower_uncond_jump(stmt_span, stmt_data, goto)?;
+                        self.out.push(sp!(binop.span => LowerStmt::Label { time: stmt_data.time, label: skip_label }));
+                    }
+                },
             },
         }
         Ok(())
@@ -852,7 +1016,7 @@ impl SingleSubLowerer<'_, '_> {
             //      skip:
 
             let negated_kw = sp!(keyword.span => keyword.negate());
-            let skip_label = sp!(binop.span => self.ctx.gensym.gensym("@unless_predec_skip#"));
+            let skip_label = sp!(binop.span => self.gensym.gensym("@unless_predec_skip#"));
             let skip_goto = ast::StmtGoto { time: None, destination: skip_label.clone() };
 
             self.lower_cond_jump_non_count(stmt_span, stmt_data, &negated_kw, a, &skip_goto)?;
@@ -894,7 +1058,7 @@ impl SingleSubLowerer<'_, '_> {
         self.lower_assign_op(data.tmp_expr.span, stmt_data, var, &eq_sign, data.tmp_expr)?;
 
         let mut read_var = var.clone();
-        let read_ty_sigil = get_temporary_read_ty(data.read_ty, var.span).map_err(|e| self.ctx.emitter.emit(e))?;
+        let read_ty_sigil = get_temporary_read_ty(data.read_ty, var.span).map_err(|e| self.emitter.emit(e))?;
         read_var.ty_sigil = Some(read_ty_sigil);
         Ok(sp!(var.span => ast::Expr::Var(read_var)))
     }
@@ -915,12 +1079,11 @@ impl SingleSubLowerer<'_, '_> {
         tmp_ty: ScalarType,
     ) -> Result<(DefId, Sp<ast::Var>), ErrorReported> {
         // FIXME: It bothers me that we have to actually allocate an identifier here.
-        let ident = self.ctx.gensym.gensym("temp");
-        let ident = sp!(span => self.ctx.resolutions.attach_fresh_res(ident));
-        let def_id = self.ctx.define_local(ident.clone(), tmp_ty.into());
-        let sigil = get_temporary_read_ty(tmp_ty, span).map_err(|e| self.ctx.emitter.emit(e))?;
+        let ident = self.gensym.gensym("temp");
+        let (ident, def_id) = self.temporaries.define(ident, tmp_ty);
+        let sigil = get_temporary_read_ty(tmp_ty, span).map_err(|e| self.emitter.emit(e))?;
 
-        let var = sp!(span => ast::Var { ty_sigil: Some(sigil), name: ast::VarName::new_non_reg(ident.value) });
+        let var = sp!(span => ast::Var { ty_sigil: Some(sigil), name: ast::VarName::new_non_reg(ident) });
         self.out.push(sp!(span => LowerStmt::RegAlloc { def_id }));
 
         Ok((def_id, var))
@@ -934,7 +1097,7 @@ impl SingleSubLowerer<'_, '_> {
     }
 
     fn unsupported(&self, span: crate::pos::Span, what: &str) -> ErrorReported {
-        self.ctx.emitter.emit(super::unsupported(span, what))
+        self.emitter.emit(super::unsupported(span, what))
     }
 }
 
@@ -1009,14 +1172,11 @@ impl SingleSubLowerer<'_, '_> {
                 ty: ScalarType::String,
             })),
             ast::Expr::Var(var) => {
-                let (lowered, ty) = lower_var_to_arg(var, self.ctx)?;
+                let (lowered, ty) = self.lower_var_to_arg(var)?;
                 Ok(ExprClass::Simple(SimpleExpr { lowered, ty }))
             },
-            ast::Expr::LabelProperty { keyword, label } => Ok(ExprClass::Simple(SimpleExpr {
-                lowered: sp!(arg.span => match keyword.value {
-                    token![timeof] => LowerArg::TimeOf(label.value.clone()),
-                    token![offsetof] => LowerArg::Label(label.value.clone()),
-                }),
+            ast::Expr::LabelProperty { script, keyword, label } => Ok(ExprClass::Simple(SimpleExpr {
+                lowered: sp!(arg.span => LowerArg::from_label_property(script, label, keyword.value)),
                 ty: ScalarType::Int,
             })),
 
@@ -1094,40 +1254,59 @@ impl SingleSubLowerer<'_, '_> {
     }
 }
 
-fn lower_var_to_arg(var: &Sp<ast::Var>, ctx: &CompilerContext) -> Result<(Sp<LowerArg>, ScalarType), ErrorReported> {
-    let read_ty = ctx.var_read_ty_from_ast(var).as_known_ty().expect("(bug!) untyped in stackless lowerer");
-
-    // Up to this point in compilation, register aliases use Var::Named.
-    // But now, we want both registers and their aliases to be resolved to a register
-    let arg = match ctx.var_reg_from_ast(&var.name) {
-        Ok((_lang, reg)) => LowerArg::Raw(SimpleArg::from_reg(reg, read_ty)),
-        Err(def_id) => LowerArg::Local { def_id, storage_ty: read_ty },
-    };
-    Ok((sp!(var.span => arg), read_ty))
-}
+impl SingleSubLowerer<'_, '_> {
+    fn lower_var_to_arg(&self, var: &Sp<ast::Var>) -> Result<(Sp<LowerArg>, ScalarType), ErrorReported> {
+        // temporaries always have a sigil, so this won't need to look them up
+        let read_ty = self.ctx.var_read_ty_from_ast(var).as_known_ty().expect("(bug!) untyped in stackless lowerer");
+
+        // Up to this point in compilation, register aliases use Var::Named.
+        // But now, we want both registers and their aliases to be resolved to a register
+        let arg = match self.var_reg_from_ast(&var.name) {
+            Ok((_lang, reg)) => LowerArg::Raw(SimpleArg::from_reg(reg, read_ty)),
+            Err(def_id) => LowerArg::Local { def_id, storage_ty: read_ty },
+        };
+        Ok((sp!(var.span => arg), read_ty))
+    }
 
-fn expr_uses_var(ast: &Sp<ast::Expr>, var: &ast::Var, ctx: &CompilerContext) -> bool {
-    use ast::Visit;
+    /// [`CompilerContext::var_reg_from_ast`], aware of temporaries.
+    fn var_reg_from_ast(&self, var: &ast::VarName) -> Result<(LanguageKey, RegId), DefId> {
+        match self.temporaries.def_id(var) {
+            Some(def_id) => Err(def_id),
+            None => self.ctx.var_reg_from_ast(var),
+        }
+    }
 
-    struct Visitor<'a, 'ctx> {
-        ctx: &'a CompilerContext<'ctx>,
-        aliasable_id: AliasableId,
-        found: bool,
+    /// [`CompilerContext::var_aliasable_id`], aware of temporaries.
+    fn var_aliasable_id(&self, var: &ast::VarName) -> AliasableId {
+        match self.var_reg_from_ast(var) {
+            Ok((_, reg)) => AliasableId::Reg(reg),
+            Err(def_id) => AliasableId::Var(def_id),
+        }
     }
 
-    impl Visit for Visitor<'_, '_> {
-        fn visit_var(&mut self, var: &Sp<ast::Var>) {
-            let aliasable_id = self.ctx.var_aliasable_id(&var.name);
-            if self.aliasable_id == aliasable_id {
-                self.found = true;
+    fn expr_uses_var(&self, ast: &Sp<ast::Expr>, var: &ast::Var) -> bool {
+        use ast::Visit;
+
+        struct Visitor<'a, 'b, 'ctx> {
+            lowerer: &'a SingleSubLowerer<'b, 'ctx>,
+            aliasable_id: AliasableId,
+            found: bool,
+        }
+
+        impl Visit for Visitor<'_, '_, '_> {
+            fn visit_var(&mut self, var: &Sp<ast::Var>) {
+                let aliasable_id = self.lowerer.var_aliasable_id(&var.name);
+                if self.aliasable_id == aliasable_id {
+                    self.found = true;
+                }
             }
         }
-    }
 
-    let aliasable_id = ctx.var_aliasable_id(&var.name);
-    let mut v = Visitor { aliasable_id, ctx, found: false };
-    v.visit_expr(ast);
-    v.found
+        let aliasable_id = self.var_aliasable_id(&var.name);
+        let mut v = Visitor { aliasable_id, lowerer: self, found: false };
+        v.visit_expr(ast);
+        v.found
+    }
 }
 
 // =============================================================================
@@ -1135,17 +1314,24 @@ fn expr_uses_var(ast: &Sp<ast::Expr>, var: &ast::Var, ctx: &CompilerContext) ->
 #[derive(Default)]
 pub (in crate::llir::lower) struct PersistentState {
     has_used_scratch: Option<Span>,
-    has_anti_scratch_ins: Option<Span>,
+    /// The first instruction that disables scratch registers in the whole file, and the name to call it.
+    has_anti_scratch_ins: Option<(Span, String)>,
 }
 
 impl PersistentState {
+    /// Combine with the state from subs that were lowered after this one.
+    pub (in crate::llir::lower) fn merge(&mut self, later: PersistentState) {
+        self.has_used_scratch = self.has_used_scratch.or(later.has_used_scratch);
+        self.has_anti_scratch_ins = self.has_anti_scratch_ins.take().or(later.has_anti_scratch_ins);
+    }
+
     pub (in crate::llir::lower) fn finish(self, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
-        if let Some(anti_span) = self.has_anti_scratch_ins {
+        if let Some((anti_span, anti_name)) = self.has_anti_scratch_ins {
             if let Some(used_span) = self.has_used_scratch {
                 return Err(ctx.emitter.emit(error!(
                     message("scratch registers are disabled in this entire file"),
                     primary(used_span, "this fancy expression requires a scratch register"),
-                    secondary(anti_span, "Patchouli has tainted this entire file"),
+                    secondary(anti_span, "Patchouli has tainted this entire file with {}", anti_name),
                 )))
             }
         }
@@ -1161,17 +1347,23 @@ pub (in crate::llir::lower) fn assign_registers(
     sub_info: Option<&super::SubInfo>,
     def_id: Option<DefId>,
     ctx: &CompilerContext,
+    emitter: &RootEmitter,
+    temporaries: &Temporaries,
     do_debug_info: bool,
 ) -> Result<Option<debug_info::ScriptRegisterInfo>, ErrorReported> {
     let stringify_reg = |reg| crate::fmt::stringify(&ctx.reg_to_ast(hooks.language(), reg));
+    let stringify_ins = |opcode| crate::fmt::stringify(&ctx.ins_to_ast(hooks.language(), opcode));
 
-    let mut local_regs = IdMap::<DefId, RegId>::new();
+    let mut local_regs = IdMap::<DefId, RegId>::default();
     let mut implicitly_used_regs = HashMap::<RegId, (ScalarType, Span)>::new();
     let mut has_used_scratch: Option<Span> = None;
-    let mut has_anti_scratch_ins: Option<Span> = None;
+    let mut has_anti_scratch_ins: Option<(Span, raw::Opcode)> = None;
     let mut debug_info = do_debug_info.then(|| debug_info::ScriptRegisterInfo {
         locals: vec![],
+        explicit_registers: vec![],
+        scratch_registers: vec![],
     });
+    let mut used_scratch_regs = BTreeSet::new();
 
     // FIXME:  Should this be here?  Stack-based ECL might want this check as well...
     // For detecting multiple names that represent the same register for non-scratch registers;
@@ -1180,7 +1372,7 @@ pub (in crate::llir::lower) fn assign_registers(
     #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
     enum UsedName { RegId(RegId), DefId(DefId) }
     struct UsedNameData<'a> { span: Span, note: Option<&'a str> }
-    let mut clashing_names_for_regs = IdMap::<RegId, IdMap<UsedName, UsedNameData>>::new();
+    let mut clashing_names_for_regs = IdMap::<RegId, IdMap<UsedName, UsedNameData>>::default();
 
     let explicitly_used_regs = get_explicitly_used_regs(code);
 
@@ -1229,24 +1421,29 @@ pub (in crate::llir::lower) fn assign_registers(
         }
     }
 
+    let num_available_regs_by_ty: EnumMap<ScalarType, usize> = enum_map::enum_map!(ty => remaining_scratch_regs_by_ty[ty].len());
+    let mut pressure = RegisterPressure::default();
+
     // assign scratch registers to all variables defined with RegAlloc
     for stmt in code {
         match &mut stmt.value {
             &mut LowerStmt::RegAlloc { def_id } => {
                 has_used_scratch.get_or_insert(stmt.span);
 
-                let required_ty = ctx.defs.var_inherent_ty(def_id).as_known_ty().expect("(bug!) untyped in stackless lowerer");
+                let required_ty = temporaries.var_inherent_ty(ctx, def_id).expect("(bug!) untyped in stackless lowerer");
 
                 let reg = remaining_scratch_regs_by_ty[required_ty].pop().ok_or_else(|| {
-                    script_too_complex(stmt, hooks, required_ty, &explicitly_used_regs, &implicitly_used_regs, &ctx)
+                    script_too_complex(stmt, hooks, required_ty, &explicitly_used_regs, &implicitly_used_regs, ctx, emitter)
                 })?;
+                pressure.alloc(required_ty, stmt.span);
+                used_scratch_regs.insert(reg);
 
                 implicitly_used_regs.insert(reg, (required_ty, stmt.span));
                 assert!(local_regs.insert(def_id, reg).is_none());
                 assert!(!clashing_names_for_regs.contains_key(&reg));
                 if let Some(debug_info) = &mut debug_info {
                     debug_info.locals.push(debug_info::Local {
-                        name: ctx.defs.var_name(def_id).to_string(),
+                        name: temporaries.var_name(ctx, def_id).to_string(),
                         name_span: stmt.span.into(),
                         r#type: ReadType::from_ty(required_ty).expect("string-typed register?!").into(),
                         bound_to: reg.into(),
@@ -1254,20 +1451,21 @@ pub (in crate::llir::lower) fn assign_registers(
                 }
             },
             LowerStmt::RegFree { def_id } => {
-                let inherent_ty = ctx.defs.var_inherent_ty(*def_id).as_known_ty().expect("(bug!) we allocated a reg so it must have a type");
+                let inherent_ty = temporaries.var_inherent_ty(ctx, *def_id).expect("(bug!) we allocated a reg so it must have a type");
                 let reg = local_regs.remove(&def_id).expect("(bug!) RegFree without RegAlloc!");
                 assert!(implicitly_used_regs.remove(&reg).is_some());
 
                 remaining_scratch_regs_by_ty[inherent_ty].push(reg);
+                pressure.free(inherent_ty);
             },
             LowerStmt::Instr(instr) => {
-                if let Some(how_bad) = hooks.instr_disables_scratch_regs(instr.opcode) {
+                if let Some(how_bad) = ctx.defs.instr_disables_scratch_regs(hooks, instr.opcode) {
                     match how_bad {
                         HowBadIsIt::OhItsJustThisOneFunction => {
-                            has_anti_scratch_ins.get_or_insert(stmt.span);
+                            has_anti_scratch_ins.get_or_insert((stmt.span, instr.opcode));
                         },
                         HowBadIsIt::ItsWaterElf => {
-                            global_scratch_results.has_anti_scratch_ins.get_or_insert(stmt.span);
+                            global_scratch_results.has_anti_scratch_ins.get_or_insert_with(|| (stmt.span, stringify_ins(instr.opcode)));
                         },
                     }
                 }
@@ -1287,12 +1485,12 @@ pub (in crate::llir::lower) fn assign_registers(
         }
     }
 
-    if let Some(anti_span) = has_anti_scratch_ins {
+    if let Some((anti_span, anti_opcode)) = has_anti_scratch_ins {
         if let Some(used_span) = has_used_scratch {
-            return Err(ctx.emitter.emit(error!(
+            return Err(emitter.emit(error!(
                 message("scratch registers are disabled in this script"),
                 primary(used_span, "this fancy expression requires a scratch register"),
-                primary(anti_span, "this disables scratch registers"),
+                primary(anti_span, "{} disables scratch registers", stringify_ins(anti_opcode)),
             )))
         }
     }
@@ -1309,17 +1507,85 @@ pub (in crate::llir::lower) fn assign_registers(
                     diag.note(format!("{}", note));
                 }
             }
-            ctx.emitter.emit(diag).ignore();
+            emitter.emit(diag).ignore();
         }
     }
 
     if let Some(span) = has_used_scratch {
         global_scratch_results.has_used_scratch.get_or_insert(span);
+
+        if ctx.report_register_pressure {
+            emitter.emit(pressure.report(hooks, &num_available_regs_by_ty, &explicitly_used_regs, ctx)).ignore();
+        }
     }
 
+    if let Some(debug_info) = &mut debug_info {
+        debug_info.explicit_registers = explicitly_used_regs.keys().map(|reg| reg.0).collect();
+        debug_info.scratch_registers = used_scratch_regs.into_iter().map(|reg| reg.0).collect();
+    }
     Ok(debug_info)
 }
 
+/// Tracks the greatest number of scratch registers simultaneously in use, for `--register-report`.
+#[derive(Default)]
+struct RegisterPressure {
+    in_use: EnumMap<ScalarType, usize>,
+    peak: EnumMap<ScalarType, usize>,
+    /// Spans of the allocations that brought usage up to the peak.
+    peak_spans: EnumMap<ScalarType, Vec<Span>>,
+}
+
+impl RegisterPressure {
+    fn alloc(&mut self, ty: ScalarType, span: Span) {
+        self.in_use[ty] += 1;
+        if self.in_use[ty] > self.peak[ty] {
+            self.peak[ty] = self.in_use[ty];
+            self.peak_spans[ty].clear();
+        }
+        if self.in_use[ty] == self.peak[ty] {
+            self.peak_spans[ty].push(span);
+        }
+    }
+
+    fn free(&mut self, ty: ScalarType) {
+        self.in_use[ty] -= 1;
+    }
+
+    fn report(
+        &self,
+        hooks: &dyn LanguageHooks,
+        num_available_regs_by_ty: &EnumMap<ScalarType, usize>,
+        explicitly_used_regs: &BTreeMap<RegId, Span>,
+        ctx: &CompilerContext,
+    ) -> Diagnostic {
+        let stringify_reg = |reg| crate::fmt::stringify(&ctx.reg_to_ast(hooks.language(), reg));
+
+        let used_tys = ScalarType::iter_numeric().filter(|&ty| self.peak[ty] > 0).collect::<Vec<_>>();
+        let summary = used_tys.iter().map(|&ty| {
+            format!("{} of {} {}", self.peak[ty], num_available_regs_by_ty[ty], ty.descr_plural())
+        }).collect::<Vec<_>>();
+
+        let mut diag = info!("peak scratch register usage: {}", summary.join(", "));
+        for &ty in &used_tys {
+            for &span in &self.peak_spans[ty] {
+                diag.primary(span, format!("{} of {} {} in use here", self.peak[ty], num_available_regs_by_ty[ty], ty.descr_plural()));
+            }
+
+            let unavailable_strs = hooks.general_use_regs()[ty].iter().copied()
+                .filter(|reg| explicitly_used_regs.contains_key(reg))
+                .map(stringify_reg)
+                .collect::<Vec<_>>();
+            if !unavailable_strs.is_empty() {
+                diag.note(format!(
+                    "unavailable for {} due to explicit use: {}",
+                    ty.descr_plural(), unavailable_strs.join(", "),
+                ));
+            }
+        }
+        diag
+    }
+}
+
 fn each_lower_arg(arg: &mut Sp<LowerArg>, func: &mut dyn FnMut(&mut Sp<LowerArg>)) {
     func(arg);
     if let LowerArg::DiffSwitch(cases) = &mut arg.value {
@@ -1339,6 +1605,7 @@ fn script_too_complex(
     explicitly_used_regs: &BTreeMap<RegId, Span>,
     implicitly_used_regs: &HashMap<RegId, (ScalarType, Span)>,
     ctx: &CompilerContext,
+    emitter: &RootEmitter,
 ) -> ErrorReported {
     let stringify_reg = |reg| crate::fmt::stringify(&ctx.reg_to_ast(hooks.language(), reg));
 
@@ -1363,11 +1630,11 @@ fn script_too_complex(
         ));
     }
 
-    ctx.emitter.emit(error)
+    emitter.emit(error)
 }
 
 // Gather all explicitly-used registers in the source. (so that we can avoid using them for scratch)
-fn get_explicitly_used_regs(func_body: &[Sp<LowerStmt>]) -> BTreeMap<RegId, Span> {
+pub(in crate::llir::lower) fn get_explicitly_used_regs(func_body: &[Sp<LowerStmt>]) -> BTreeMap<RegId, Span> {
     func_body.iter()
         .filter_map(|stmt| match &stmt.value {
             LowerStmt::Instr(LowerInstr { args: LowerArgs::Known(args), .. }) => Some(args),
@@ -2,7 +2,7 @@ use enum_map::EnumMap;
 
 use crate::raw;
 use crate::game::LanguageKey;
-use crate::io::{BinReader, BinWriter, ReadResult, WriteResult};
+use crate::io::{BinReader, BinWriter, ReadResult, WriteResult, Encoding, DEFAULT_ENCODING};
 use crate::diagnostic::{Diagnostic, Emitter};
 use crate::value::{ScalarValue, ScalarType, ReadType};
 use crate::resolve::{RegId};
@@ -13,7 +13,8 @@ mod abi;
 pub use lower::Lowerer;
 mod lower;
 
-pub use raise::{Raiser, DecompileOptions, CallRegSignatures};
+pub use raise::{Raiser, DecompileOptions, MapfilePaths, CallRegSignatures};
+pub(crate) use raise::decode_args_by_signature;
 mod raise;
 
 pub use intrinsic::{IntrinsicInstrs, IntrinsicInstrKind, alternatives, AlternativesInfo};
@@ -47,6 +48,8 @@ pub struct RawInstr {
     pub difficulty: raw::DifficultyMask,
     /// Stack change arg.  Only used in modern ECL.  Zero in other languages.
     pub pop: raw::StackPop,
+    /// Number of arguments.  Only used in modern ECL.  Zero in other languages.
+    pub arg_count: raw::ArgCount,
     /// Used by ECL timelines.  `None` elsewhere.
     pub extra_arg: Option<raw::ExtraArg>,
 }
@@ -58,6 +61,7 @@ impl RawInstr {
         param_mask: 0,
         difficulty: crate::passes::semantics::time_and_difficulty::DEFAULT_DIFFICULTY_MASK_BYTE,
         pop: 0,
+        arg_count: 0,
     };
 }
 
@@ -267,7 +271,7 @@ pub fn write_instrs(
 /// It is responsible for mapping language features to instruction opcodes (FIXME: move to core mapfiles?),
 /// and declaratively describing the availability of language features like the stack, jumps, and stack registers.
 /// (this information gets used by the `lower` and `raise` modules to determine how to compile/decompile things)
-pub trait LanguageHooks {
+pub trait LanguageHooks: Sync {
     /// Language key, so that signatures can be looked up for the right type of instruction (e.g. ECL vs timeline).
     fn language(&self) -> LanguageKey;
 
@@ -295,17 +299,22 @@ pub trait LanguageHooks {
     /// scripts set up like `grandparent->parent->child` and this is used by both `parent` and `child`,
     /// then there could be registers that are dangerous to modify in `parent` despite not being
     /// mentioned at all in its script!
+    ///
+    /// Mapfiles can flag more instructions with `!ins_flags`; see [`Defs::instr_disables_scratch_regs`][`crate::context::Defs::instr_disables_scratch_regs`].
     fn instr_disables_scratch_regs(&self, _opcode: raw::Opcode) -> Option<HowBadIsIt> { None }
 
     /// Used by TH06 to indicate that an instruction must be the last instruction in the script.
     fn is_th06_anm_terminating_instr(&self, _opcode: raw::Opcode) -> bool { false }
 
-    // Most formats encode labels as offsets from the beginning of the script, but:
-    // * early STD writes the instruction *index*.
-    // * early (?) ECL writes offsets relative to the current instruction.
-    // both args here are relative to the beginning of the sub.
-    fn encode_label(&self, _cur_offset: raw::BytePos, dest_offset: raw::BytePos) -> raw::RawDwordBits { dest_offset as _ }
-    fn decode_label(&self, _cur_offset: raw::BytePos, bits: raw::RawDwordBits) -> raw::BytePos { bits as _ }
+    /// Used by TH06 to indicate that every script must contain one of the instructions from
+    /// [`Self::is_th06_anm_terminating_instr`].
+    fn requires_th06_anm_terminating_instr(&self) -> bool { false }
+
+    /// How jump destinations are written.  Mapfiles may override this; see [`Defs::label_encoding`][`crate::context::Defs::label_encoding`].
+    fn label_encoding(&self) -> LabelEncoding { LabelEncoding::AbsoluteByteOffset }
+
+    /// How string arguments are encoded.  Mapfiles may override this; see [`Defs::string_encoding`][`crate::context::Defs::string_encoding`].
+    fn string_encoding(&self) -> Encoding { DEFAULT_ENCODING }
 
     /// Initial difficulty mask.  In languages without difficulty, this returns `None.
     fn default_difficulty_mask(&self) -> Option<raw::DifficultyMask> { None }
@@ -328,10 +337,22 @@ pub trait LanguageHooks {
     ///
     /// Only EoSD ECL doesn't do this.
     fn has_auto_casts(&self) -> bool { true }
+
+    /// Should return `true` if expressions are evaluated on a stack (as in modern ECL), rather than
+    /// by instructions that write to registers.
+    ///
+    /// This selects between the stackful and stackless lowering backends.
+    fn has_stack(&self) -> bool { false }
+
+    /// Should return `true` if instruction headers record how many arguments the instruction has.  (as in
+    /// modern ECL)
+    fn has_arg_count(&self) -> bool { false }
 }
 
 /// How bad is the scratch-disabling-ness of this instruction?
-#[derive(Copy, Clone)]
+///
+/// Variants are ordered from least to most bad.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum HowBadIsIt {
     OhItsJustThisOneFunction,
     ItsWaterElf,
@@ -345,6 +366,77 @@ pub enum RegisterEncodingStyle {
     EosdEcl { does_value_look_like_a_register: fn(&ScalarValue) -> bool },
 }
 
+/// How the destination of a jump is written in an instruction's arguments.
+///
+/// Most formats use offsets from the beginning of the script, but:
+/// * early STD writes the instruction *index*.
+/// * early (?) ECL writes offsets relative to the current instruction.
+///
+/// Mapfiles can select one with a `!label_encoding` section, for modded engines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LabelEncoding {
+    AbsoluteByteOffset,
+    InstructionIndex,
+    RelativeByteOffset,
+}
+
+impl LabelEncoding {
+    /// The name used in mapfiles.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LabelEncoding::AbsoluteByteOffset => "absolute_byte_offset",
+            LabelEncoding::InstructionIndex => "instruction_index",
+            LabelEncoding::RelativeByteOffset => "relative_byte_offset",
+        }
+    }
+
+    pub fn iter() -> impl Iterator<Item=LabelEncoding> {
+        [LabelEncoding::AbsoluteByteOffset, LabelEncoding::InstructionIndex, LabelEncoding::RelativeByteOffset].into_iter()
+    }
+
+    // All offsets here are relative to the beginning of the sub.  `instr_offsets` holds the offset of
+    // every instruction in order, and may also include the end of the script.
+
+    pub fn encode(self, instr_offsets: &[raw::BytePos], cur_offset: raw::BytePos, dest_offset: raw::BytePos) -> raw::RawDwordBits {
+        match self {
+            LabelEncoding::AbsoluteByteOffset => dest_offset as _,
+            LabelEncoding::InstructionIndex => {
+                // the end of the script counts as the index after the last instruction
+                instr_offsets.partition_point(|&offset| offset < dest_offset) as _
+            },
+            LabelEncoding::RelativeByteOffset => {
+                let relative = dest_offset as i64 - cur_offset as i64;
+                relative as i32 as u32
+            },
+        }
+    }
+
+    /// An index past the end of the script decodes to an offset that matches no instruction.
+    pub fn decode(self, instr_offsets: &[raw::BytePos], cur_offset: raw::BytePos, bits: raw::RawDwordBits) -> raw::BytePos {
+        match self {
+            LabelEncoding::AbsoluteByteOffset => bits as _,
+            LabelEncoding::InstructionIndex => {
+                instr_offsets.get(bits as usize).copied().unwrap_or(raw::BytePos::MAX)
+            },
+            LabelEncoding::RelativeByteOffset => {
+                let relative = bits as i32 as i64; // double cast for sign-extension
+                (cur_offset as i64 + relative) as u64
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for LabelEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        LabelEncoding::iter().find(|encoding| encoding.as_str() == s).ok_or_else(|| {
+            let valid = LabelEncoding::iter().map(|encoding| encoding.as_str()).collect::<Vec<_>>().join(", ");
+            format!("valid encodings are: {valid}")
+        })
+    }
+}
+
 // =============================================================================
 // For dealing with instructions <-> bytestreams
 
@@ -361,10 +453,24 @@ pub enum RegisterEncodingStyle {
 ///   That is all done in e.g. `format/anm`.
 /// * The actual implementation of the check for where a script ends.
 /// * Converting the byte blobs to/from argument lists.
-pub trait InstrFormat {
+pub trait InstrFormat: Sync {
     /// Get the number of bytes in the binary encoding of an instruction's header (before the arguments).
     fn instr_header_size(&self) -> usize;
 
+    /// Get the number of bits in the parameter mask field of an instruction's header.
+    ///
+    /// This limits the number of arguments an instruction can have when registers are encoded
+    /// using [`RegisterEncodingStyle::ByParamMask`].
+    fn param_mask_bits(&self) -> u32 { 16 }
+
+    /// Get the sizes that an argument blob can have in this format.
+    ///
+    /// This is only used to validate a user-supplied `@blob`.
+    fn args_blob_size(&self) -> ArgsBlobSize { ArgsBlobSize::MultipleOf(4) }
+
+    /// Get the largest argument blob whose length can be represented in the size field of the header.
+    fn max_args_blob_len(&self) -> usize { u16::MAX as usize - self.instr_header_size() }
+
     /// Read a single script instruction from an input stream, which may be a terminal instruction.
     fn read_instr(&self, f: &mut BinReader, emitter: &dyn Emitter) -> ReadResult<ReadInstr>;
 
@@ -379,6 +485,13 @@ pub trait InstrFormat {
     fn instr_size(&self, instr: &RawInstr) -> usize { self.instr_header_size() + instr.args_blob.len() }
 }
 
+/// The allowed sizes of an instruction's argument blob, in bytes.  See [`InstrFormat::args_blob_size`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ArgsBlobSize {
+    MultipleOf(usize),
+    Exactly(usize),
+}
+
 #[derive(Debug)]
 pub enum ReadInstr {
     /// A regular instruction was read that belongs in the script.
@@ -416,6 +529,10 @@ pub struct TestLanguage {
     pub general_use_float_regs: Vec<RegId>,
     /// For simulating the existence of an instruction like ANM `ins_509`
     pub anti_scratch_opcode: Option<raw::Opcode>,
+    /// For testing the stackful lowerer.
+    pub has_stack: bool,
+    /// For testing formats with wide parameter masks.
+    pub param_mask_bits: u32,
 }
 
 impl Default for TestLanguage {
@@ -425,6 +542,8 @@ impl Default for TestLanguage {
             general_use_int_regs: Default::default(),
             general_use_float_regs: Default::default(),
             anti_scratch_opcode: None,
+            has_stack: false,
+            param_mask_bits: 16,
         }
     }
 }
@@ -438,6 +557,8 @@ impl LanguageHooks for TestLanguage {
 
     fn has_registers(&self) -> bool { true }
 
+    fn has_stack(&self) -> bool { self.has_stack }
+
     fn general_use_regs(&self) -> EnumMap<ScalarType, Vec<RegId>> {
         enum_map::enum_map!{
             ScalarType::Int => self.general_use_int_regs.clone(),
@@ -451,6 +572,7 @@ impl LanguageHooks for TestLanguage {
 
 impl InstrFormat for TestLanguage {
     fn instr_header_size(&self) -> usize { 4 }
+    fn param_mask_bits(&self) -> u32 { self.param_mask_bits }
     fn read_instr(&self, _: &mut BinReader, _: &dyn Emitter) -> ReadResult<ReadInstr> { panic!("TestInstrFormat does not implement reading or writing") }
     fn write_instr(&self, _: &mut BinWriter, _: &dyn Emitter, _: &RawInstr) -> WriteResult { panic!("TestInstrFormat does not implement reading or writing") }
     fn write_terminal_instr(&self, _: &mut BinWriter, _: &dyn Emitter) -> WriteResult { panic!("TestInstrFormat does not implement reading or writing")  }
@@ -463,19 +585,19 @@ mod test_reader {
     use crate::error::ErrorReported;
 
     struct SimpleInstrReader {
-        iter: std::cell::RefCell<std::vec::IntoIter<ReadInstr>>
+        iter: std::sync::Mutex<std::vec::IntoIter<ReadInstr>>
     }
 
     impl SimpleInstrReader {
         fn new(vec: Vec<ReadInstr>) -> Self {
-            SimpleInstrReader { iter: std::cell::RefCell::new(vec.into_iter()) }
+            SimpleInstrReader { iter: std::sync::Mutex::new(vec.into_iter()) }
         }
     }
 
     impl InstrFormat for SimpleInstrReader {
         fn instr_header_size(&self) -> usize { 0x10 }
         fn read_instr(&self, _: &mut BinReader, _: &dyn Emitter) -> ReadResult<ReadInstr> {
-            Ok(self.iter.borrow_mut().next().expect("instr reader tried to read too many instrs!"))
+            Ok(self.iter.lock().unwrap().next().expect("instr reader tried to read too many instrs!"))
         }
         fn write_instr(&self, _: &mut BinWriter, _: &dyn Emitter, _: &RawInstr) -> WriteResult { panic!("SimpleInstrReader does not implement reading or writing") }
         fn write_terminal_instr(&self, _: &mut BinWriter, _: &dyn Emitter) -> WriteResult { panic!("SimpleInstrReader does not implement reading or writing")  }
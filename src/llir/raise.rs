@@ -1,4 +1,5 @@
 use std::collections::{BTreeSet};
+use std::sync::Mutex;
 
 use crate::raw;
 use crate::ast;
@@ -7,7 +8,7 @@ use crate::pos::{Sp};
 use crate::diagnostic::{Emitter};
 use crate::error::{ErrorReported};
 use crate::llir::{RawInstr, LanguageHooks, IntrinsicInstrs, IntrinsicInstrKind};
-use crate::context::{self, CompilerContext, defs::ConstNames};
+use crate::context::{self, CompilerContext, defs::{ConstNames, InstrAbiLoc}};
 use crate::game::LanguageKey;
 use crate::passes::semantics::time_and_difficulty::{DEFAULT_DIFFICULTY_MASK_BYTE};
 use crate::bitset::BitSet32;
@@ -27,6 +28,7 @@ macro_rules! warn_unless {
 }
 
 use early::{Label};
+pub(crate) use early::decode_args_by_signature;
 mod early;
 
 mod late;
@@ -36,17 +38,108 @@ mod recognize;
 pub use infer_pcb_signatures::CallRegSignatures;
 mod infer_pcb_signatures;
 
+/// Options that control decompilation, accepted by the `decompile_*` methods of [`crate::Truth`].
+///
+/// The [`Default`] value matches the behavior of the CLI decompile commands when no flags are given.
 #[derive(Debug, Clone)]
 pub struct DecompileOptions {
+    /// Decode instruction arguments.  (default `true`)  When `false`, every instruction is written in
+    /// its most raw form possible.
     pub arguments: bool,
+    /// Recognize intrinsic instructions like jumps and assignments.  (default `true`)
     pub intrinsics: bool,  // invariant: intrinsics implies arguments
+    /// Recover loops and other control flow.  (default `true`)
     pub blocks: bool,
+    /// Combine instructions that differ only by difficulty into diff switches.  (default `true`)
     pub diff_switches: bool,
+    /// Write integer arguments as the names of enum consts and sprites where one is known.  (default `true`)
+    pub const_names: bool,
+    /// Name labels `label_0`, `label_1`, ... in order, instead of after their byte offsets.  (default `false`)
+    pub renumber_labels: bool,
+    /// Line width that the formatter tries to stay within.  (default `100`)
+    pub max_columns: usize,
+    /// EoSD and PCB timelines only.  Write `@arg0=` on every instruction whose signature doesn't use arg0,
+    /// even when it is zero.  (default `false`)
+    pub explicit_arg0: bool,
+    /// Write interrupt labels as the names of consts from this enum where one has that value,
+    /// e.g. `interrupt[CARD_END]:`.  (default `None`)
+    pub interrupt_enum: Option<crate::ident::Ident>,
+    /// ANM only.  Write `has_data: "dummy"` on entries that have an image, for patches that don't care about image data.
+    pub dummy_images: bool,
+    /// ANM only.  The order in which to write the scripts of each entry.
+    pub script_order: crate::anm::ScriptOrder,
+    /// ANM only.  Write a script that shares its body with another as an alias like `script 5 script5 = script2;`,
+    /// rather than as a full copy of the body.  (default `true`)
+    pub script_aliases: bool,
+    /// How to write the paths of `#pragma mapfile` lines.  (default [`MapfilePaths::AsGiven`])
+    ///
+    /// Only mapfiles that provide something used by the decompiled script get a pragma.
+    pub mapfile_paths: MapfilePaths,
+}
+
+/// How [`DecompileOptions`] writes the paths in `#pragma mapfile` lines.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum MapfilePaths {
+    /// Write each path exactly as it was given when the mapfile was loaded.
+    #[default]
+    AsGiven,
+    /// Write each path relative to a directory, typically the one the decompiled script will be written to.
+    RelativeTo(std::path::PathBuf),
 }
 
 impl DecompileOptions {
     /// Construct with all features enabled.
     pub fn new() -> Self { Default::default() }
+
+    /// Set [`Self::arguments`].  Disabling arguments also disables intrinsics.
+    pub fn arguments(mut self, value: bool) -> Self {
+        self.arguments = value;
+        self.intrinsics &= value;
+        self
+    }
+
+    /// Set [`Self::intrinsics`].
+    pub fn intrinsics(mut self, value: bool) -> Self {
+        self.intrinsics = value && self.arguments; self
+    }
+
+    /// Set [`Self::blocks`].
+    pub fn blocks(mut self, value: bool) -> Self { self.blocks = value; self }
+
+    /// Set [`Self::diff_switches`].
+    pub fn diff_switches(mut self, value: bool) -> Self { self.diff_switches = value; self }
+
+    /// Set [`Self::const_names`].
+    pub fn const_names(mut self, value: bool) -> Self { self.const_names = value; self }
+
+    /// Set [`Self::renumber_labels`].
+    pub fn renumber_labels(mut self, value: bool) -> Self { self.renumber_labels = value; self }
+
+    /// Set [`Self::max_columns`].
+    pub fn max_columns(mut self, value: usize) -> Self { self.max_columns = value; self }
+
+    /// Set [`Self::explicit_arg0`].
+    pub fn explicit_arg0(mut self, value: bool) -> Self { self.explicit_arg0 = value; self }
+
+    /// Set [`Self::interrupt_enum`].
+    pub fn interrupt_enum(mut self, value: Option<crate::ident::Ident>) -> Self { self.interrupt_enum = value; self }
+
+    /// Set [`Self::dummy_images`].
+    pub fn dummy_images(mut self, value: bool) -> Self { self.dummy_images = value; self }
+
+    /// Set [`Self::script_order`].
+    pub fn script_order(mut self, value: crate::anm::ScriptOrder) -> Self { self.script_order = value; self }
+
+    /// Set [`Self::script_aliases`].
+    pub fn script_aliases(mut self, value: bool) -> Self { self.script_aliases = value; self }
+
+    /// Set [`Self::mapfile_paths`].
+    pub fn mapfile_paths(mut self, value: MapfilePaths) -> Self { self.mapfile_paths = value; self }
+
+    /// Get the formatter config for writing decompiled output.
+    pub fn fmt_config(&self) -> crate::fmt::Config {
+        crate::fmt::Config::new().max_columns(self.max_columns)
+    }
 }
 
 impl Default for DecompileOptions {
@@ -56,6 +149,15 @@ impl Default for DecompileOptions {
             intrinsics: true,
             blocks: true,
             diff_switches: true,
+            const_names: true,
+            renumber_labels: false,
+            max_columns: 100,
+            explicit_arg0: false,
+            interrupt_enum: None,
+            dummy_images: false,
+            script_order: Default::default(),
+            script_aliases: true,
+            mapfile_paths: Default::default(),
         }
     }
 }
@@ -106,13 +208,18 @@ struct RaisedIntrinsicParts {
     pub pseudo_arg0: Option<ast::Expr>,
     pub pseudo_mask: Option<raw::ParamMask>,
     pub pseudo_blob: Option<Vec<u8>>,
+    /// Number of stack values consumed, if nonzero.  (an `@pop` on raw instructions)
+    pub pseudo_pop: Option<raw::StackPop>,
+    /// Argument count of a blob, if nonzero.  (an `@nargs` on raw instructions)
+    pub pseudo_arg_count: Option<raw::ArgCount>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum RaiseIntrinsicKind {
-    /// A raw instruction call.  Uses `opcode`, `plain_args`, and `pseudo_arg0`.
+    /// A raw instruction call.  Uses `opcode`, `plain_args`, `pseudo_arg0`, and `pseudo_pop`.
     Instruction,
-    /// A raw instruction call of unknown signature.  Uses `opcode` and the `pseudo_*` fields.
+    /// A raw instruction call of unknown signature, or whose argument count doesn't match its signature.
+    /// Uses `opcode` and the `pseudo_*` fields.
     Blob,
     /// A single-instruction intrinsic.  (or the combination of multiple single-instruction
     /// intrinsics into one that behaves identical to another known intrinsic)
@@ -125,6 +232,8 @@ enum RaiseIntrinsicKind {
     /// A format-independent call intrinsic where `plain_args` contains the args in an
     /// order that matches the user-visible function signature.
     CallProper,
+    /// A conditional jump built from several jump intrinsics, whose condition is the sole `plain_arg`.
+    CondJmpExpr(ast::CondKeyword),
     /// End of script. Used to hold labels.
     End,
 }
@@ -135,7 +244,7 @@ enum RaiseIntrinsicKind {
 /// can be given at the end of decompilation.
 pub struct Raiser<'a> {
     hooks: &'a dyn LanguageHooks,
-    opcodes_without_abis: BTreeSet<u16>,
+    opcodes_without_abis: Mutex<BTreeSet<u16>>,
     // Root emitter because we don't want any additional context beyond the filename.
     emitter_for_abi_warnings: &'a context::RootEmitter,
     options: &'a DecompileOptions,
@@ -159,6 +268,17 @@ impl<'a> Raiser<'a> {
         options: &'a DecompileOptions,
         const_proof: crate::passes::evaluate_const_vars::Proof,
     ) -> Result<Self, ErrorReported> {
+        for span in ctx.defs.encoding_override_spans(hooks.language()) {
+            ctx.mark_mapfile_span_used(span);
+        }
+
+        let const_names = ctx.get_const_names(const_proof);
+        if let Some(enum_name) = &options.interrupt_enum {
+            if !const_names.enums.contains_key(enum_name) {
+                emitter.emit(warning!("no enum named '{enum_name}' for interrupt labels; they will be written as numbers")).ignore();
+            }
+        }
+
         Ok(Raiser {
             hooks,
             opcodes_without_abis: Default::default(),
@@ -169,7 +289,7 @@ impl<'a> Raiser<'a> {
                 false => Default::default(),
             },
             options,
-            const_names: ctx.get_const_names(const_proof),
+            const_names,
             call_reg_info: None,
         })
     }
@@ -184,12 +304,15 @@ impl<'a> Raiser<'a> {
             language: self.hooks.language(),
             ctx,
             options: self.options,
+            intrinsic_instrs: &self.intrinsic_instrs,
+            const_names: &self.const_names,
             call_reg_data: self.call_reg_info.as_ref(),
+            has_stack: self.hooks.has_stack(),
         }
     }
 
     pub fn raise_instrs_to_sub_ast(
-        &mut self,
+        &self,
         emitter: &dyn Emitter,
         raw_script: &[RawInstr],
         ctx: &CompilerContext<'_>,
@@ -199,16 +322,39 @@ impl<'a> Raiser<'a> {
     }
 
     pub fn raise_instrs_to_middle(
-        &mut self,
+        &self,
         emitter: &dyn Emitter,
         raw_script: &[RawInstr],
         ctx: &CompilerContext<'_>,
     ) -> Result<RaiseScript, ErrorReported> {
+        self.mark_mapfile_usage(raw_script, ctx);
         Ok(RaiseScript { instrs: _raise_instrs_to_middle(self, &emitter, raw_script, ctx)? })
     }
 
+    /// Record the mapfiles whose signatures and intrinsics are used to raise these instructions.
+    ///
+    /// (names are recorded later by looking at the AST)
+    fn mark_mapfile_usage(&self, raw_script: &[RawInstr], ctx: &CompilerContext<'_>) {
+        let language = self.hooks.language();
+        let opcodes = raw_script.iter().map(|instr| instr.opcode).collect::<BTreeSet<_>>();
+        if self.options.arguments {
+            for &opcode in &opcodes {
+                if let Some((_, InstrAbiLoc::Span(span))) = ctx.defs.ins_abi(language, opcode) {
+                    ctx.mark_mapfile_span_used(*span);
+                }
+            }
+        }
+        if self.options.intrinsics {
+            for (opcode, kind) in ctx.defs.iter_intrinsic_instrs(language) {
+                if opcodes.contains(&opcode) {
+                    ctx.mark_mapfile_span_used(kind.span);
+                }
+            }
+        }
+    }
+
     pub fn raise_middle_to_sub_ast(
-        &mut self,
+        &self,
         emitter: &dyn Emitter,
         middle: &RaiseScript,
         ctx: &CompilerContext<'_>,
@@ -223,23 +369,24 @@ impl<'a> Raiser<'a> {
     }
 
     pub fn generate_warnings(&mut self) {
-        if !self.opcodes_without_abis.is_empty() {
+        let opcodes_without_abis = self.opcodes_without_abis.get_mut().unwrap();
+        if !opcodes_without_abis.is_empty() {
             self.emitter_for_abi_warnings.emit(warning!(
                 message("{} instructions with unknown signatures were decompiled to byte blobs.", self.hooks.language().descr()),
                 note(
                     "The following opcodes were affected: {}",
-                    self.opcodes_without_abis.iter()
+                    opcodes_without_abis.iter()
                         .map(|opcode| opcode.to_string()).collect::<Vec<_>>().join(", ")
                 ),
             )).ignore();
         }
 
-        self.opcodes_without_abis.clear();
+        opcodes_without_abis.clear();
     }
 }
 
 fn _raise_instrs_to_middle(
-    raiser: &mut Raiser,
+    raiser: &Raiser,
     emitter: &impl Emitter,
     raw_script: &[RawInstr],
     ctx: &CompilerContext,
@@ -247,7 +394,8 @@ fn _raise_instrs_to_middle(
     let mut middle_instrs = early::early_raise_instrs(raiser, emitter, raw_script, ctx)?;
 
     let sub_raiser = raiser.sub_raiser(ctx);
-    middle_instrs = sub_raiser.perform_recognition(middle_instrs);
+    middle_instrs = sub_raiser.recognize_difficulty_mask_regions(middle_instrs);
+    middle_instrs = sub_raiser.perform_recognition(emitter, middle_instrs);
 
     Ok(middle_instrs)
 }
@@ -259,7 +407,10 @@ struct SingleSubRaiser<'a, 'ctx> {
     language: LanguageKey,
     ctx: &'a CompilerContext<'ctx>,
     options: &'a DecompileOptions,
+    intrinsic_instrs: &'a IntrinsicInstrs,
+    const_names: &'a ConstNames,
     call_reg_data: Option<&'a crate::ecl::CallRegInfo>,
+    has_stack: bool,
 }
 
 impl SingleSubRaiser<'_, '_> {
@@ -278,10 +429,7 @@ impl SingleSubRaiser<'_, '_> {
             DEFAULT_DIFFICULTY_MASK_BYTE => None,
             mask_byte => {
                 let mask = BitSet32::from_mask(mask_byte as _);
-                Some(sp!(ast::DiffLabel {
-                    mask: Some(mask),
-                    string: sp!(self.ctx.diff_flag_defs.mask_to_diff_label(mask)),
-                }))
+                Some(sp!(self.ctx.diff_flag_defs.mask_to_diff_label(mask)))
             },
         }
     }
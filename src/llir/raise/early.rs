@@ -11,15 +11,15 @@ use crate::pos::Sp;
 use crate::ident::{Ident, ResIdent};
 use crate::diagnostic::{Emitter};
 use crate::error::{ErrorReported, GatherErrorIteratorExt};
-use crate::llir::{RawInstr, LanguageHooks, SimpleArg};
+use crate::llir::{RawInstr, LanguageHooks, SimpleArg, IntrinsicInstrKind};
 use crate::llir::intrinsic::{IntrinsicInstrAbiParts, abi_parts};
 use crate::resolve::{RegId, IdMap};
 use crate::context::{self, Defs, CompilerContext};
 use crate::context::defs::{ConstNames, TypeColor, auto_enum_names};
 use crate::game::LanguageKey;
-use crate::llir::{ArgEncoding, StringArgSize, InstrAbi, RegisterEncodingStyle};
+use crate::llir::{ArgEncoding, StringArgSize, InstrAbi, RegisterEncodingStyle, LabelEncoding};
 use crate::value::{ScalarValue};
-use crate::io::{DEFAULT_ENCODING, Encoded};
+use crate::io::{Encoded, Encoding};
 use crate::llir::raise::{CannotRaiseIntrinsic, RaisedIntrinsicParts};
 use crate::passes::semantics::time_and_difficulty::DEFAULT_DIFFICULTY_MASK_BYTE;
 
@@ -41,6 +41,8 @@ struct EarlyRaiseInstr {
     /// Timeline arg0, only if it should be raised to `@arg0`. (if it should be raised as a standard
     /// argument, it will be in `args`)
     pseudo_arg0: Option<raw::ExtraArg>,
+    /// Number of stack values consumed by the instruction.
+    stack_pop: raw::StackPop,
 }
 
 #[derive(Debug)]
@@ -52,7 +54,7 @@ enum EarlyRaiseArgs {
 }
 
 pub(in crate::llir::raise) fn early_raise_instrs(
-    raiser: &mut super::Raiser,
+    raiser: &super::Raiser,
     emitter: &impl Emitter,
     raw_script: &[RawInstr],
     ctx: &CompilerContext,
@@ -71,35 +73,42 @@ pub(in crate::llir::raise) fn early_raise_instrs(
             .collect::<Result<_, _>>()?
     };
 
-    let ref jump_data = gather_jump_time_args(&instrs, &ctx.defs, hooks)?;
-    let offset_labels = generate_offset_labels(emitter, &instrs, instr_offsets, jump_data)?;
-
-    let &end_offset = instr_offsets.last().expect("n + 1 offsets so there's always at least one");
+    let ref jump_data = gather_jump_time_args(&instrs, instr_offsets, &ctx.defs, hooks)?;
+    let mut offset_labels = generate_offset_labels(emitter, &instrs, instr_offsets, jump_data)?;
+    if raiser.options.renumber_labels {
+        renumber_offset_labels(&mut offset_labels);
+    }
 
-    early_raise_intrinsics(raiser, emitter, &offset_labels, instrs, end_offset, ctx)
+    early_raise_intrinsics(raiser, emitter, &offset_labels, instrs, instr_offsets, ctx)
 }
 
 #[derive(Debug, Clone)]
 struct UnknownArgsData {
     param_mask: raw::ParamMask,
     blob: Vec<u8>,
+    arg_count: raw::ArgCount,
 }
 
 fn early_raise_intrinsics(
-    raiser: &mut super::Raiser,
+    raiser: &super::Raiser,
     emitter: &impl Emitter,
     offset_labels: &OffsetLabels,
     instrs: Vec<EarlyRaiseInstr>,
-    end_offset: raw::BytePos,
+    instr_offsets: &[raw::BytePos],
     ctx: &CompilerContext,
 ) -> Result<Vec<RaiseInstr>, ErrorReported> {
     let atom_raiser = AtomRaiser {
         language: raiser.hooks.language(),
         const_names: &raiser.const_names,
+        use_const_names: raiser.options.const_names,
+        explicit_arg0: raiser.options.explicit_arg0,
         hooks: raiser.hooks,
+        label_encoding: ctx.defs.label_encoding(raiser.hooks),
         offset_labels,
+        instr_offsets,
         ctx,
     };
+    let &end_offset = instr_offsets.last().expect("n + 1 offsets so there's always at least one");
 
     let mut out = instrs.iter().enumerate().map(|(instr_index, instr)| {
         let ref emitter = add_instr_context(emitter, instr_index, instr.opcode, instr.offset);
@@ -114,13 +123,15 @@ fn early_raise_intrinsics(
         // blob?
         let raw_args = match &instr.args {
             EarlyRaiseArgs::Decoded(args) => args,
-            EarlyRaiseArgs::Unknown(UnknownArgsData { param_mask, blob }) => return Ok(make_instr(
+            EarlyRaiseArgs::Unknown(UnknownArgsData { param_mask, blob, arg_count }) => return Ok(make_instr(
                 RaiseIntrinsicKind::Blob,
                 RaisedIntrinsicParts {
                     opcode: Some(instr.opcode),
                     pseudo_blob: Some(blob.clone()),
                     pseudo_mask: Some(param_mask.clone()),
                     pseudo_arg0: instr.pseudo_arg0.map(|x| (x as i32).into()),
+                    pseudo_pop: Some(instr.stack_pop).filter(|&pop| pop != 0),
+                    pseudo_arg_count: Some(*arg_count).filter(|&count| count != 0),
                     ..Default::default()
                 })),
         };
@@ -135,7 +146,19 @@ fn early_raise_intrinsics(
         // intrinsic?
         let abi = atom_raiser.expect_abi(instr.opcode);
         if let Some((intrinsic, abi_info)) = raiser.intrinsic_instrs.get_intrinsic_and_props(instr.opcode) {
-            match atom_raiser.raise_intrinsic_parts(instr, raw_args, abi, abi_info) {
+            // sub calls are the only intrinsics that say how many stack values they consume
+            let pseudo_pop = match (intrinsic, instr.stack_pop) {
+                (_, 0) => Ok(None),
+                | (IntrinsicInstrKind::Call, pop)
+                | (IntrinsicInstrKind::CallAsync, pop)
+                | (IntrinsicInstrKind::CallAsyncId, pop)
+                => Ok(Some(pop)),
+                _ => Err(CannotRaiseIntrinsic),
+            };
+            match pseudo_pop.and_then(|pseudo_pop| {
+                let parts = atom_raiser.raise_intrinsic_parts(instr, raw_args, abi, abi_info)?;
+                Ok(RaisedIntrinsicParts { pseudo_pop, ..parts })
+            }) {
                 Ok(parts) => {
                     let mut intrinsic_instr = make_instr(RaiseIntrinsicKind::Standard(intrinsic), parts);
 
@@ -175,12 +198,17 @@ fn early_raise_intrinsics(
 // Blob-decoding pass.  (RawInstr -> EarlyInstr)
 
 impl Raiser<'_> {
-    fn decode_args(&mut self, emitter: &impl Emitter, instr: &RawInstr, instr_offset: raw::BytePos, defs: &Defs) -> Result<EarlyRaiseInstr, ErrorReported> {
+    fn decode_args(&self, emitter: &impl Emitter, instr: &RawInstr, instr_offset: raw::BytePos, defs: &Defs) -> Result<EarlyRaiseInstr, ErrorReported> {
         if self.options.arguments {
             if let Some((abi, _)) = defs.ins_abi(self.hooks.language(), instr.opcode) {
-                return decode_args_with_abi(emitter, self.hooks, instr, instr_offset, abi);
+                let decoded = decode_args_with_abi(emitter, self.hooks, instr, instr_offset, abi, defs.string_encoding(self.hooks))?;
+                match &decoded.args {
+                    // an argument count that the signature wouldn't produce can only be kept with `@nargs`
+                    EarlyRaiseArgs::Decoded(args) if self.hooks.has_arg_count() && args.len() != instr.arg_count as usize => {},
+                    _ => return Ok(decoded),
+                }
             } else {
-                self.opcodes_without_abis.insert(instr.opcode);
+                self.opcodes_without_abis.lock().unwrap().insert(instr.opcode);
             }
         }
 
@@ -191,20 +219,41 @@ impl Raiser<'_> {
             opcode: instr.opcode,
             difficulty_mask: instr.difficulty,
             pseudo_arg0: instr.extra_arg,
+            stack_pop: instr.pop,
             args: EarlyRaiseArgs::Unknown(UnknownArgsData {
                 param_mask: instr.param_mask,
                 blob: instr.args_blob.to_vec(),
+                arg_count: instr.arg_count,
             }),
         })
     }
 }
 
+/// Decode the arguments of a single instruction using its signature, without performing any of
+/// the other decompilation passes.
+///
+/// Returns `None` if the instruction has no known signature.
+pub(crate) fn decode_args_by_signature(
+    emitter: &impl Emitter,
+    hooks: &dyn LanguageHooks,
+    instr: &RawInstr,
+    instr_offset: raw::BytePos,
+    defs: &Defs,
+) -> Option<Result<Vec<SimpleArg>, ErrorReported>> {
+    let (abi, _) = defs.ins_abi(hooks.language(), instr.opcode)?;
+    Some(decode_args_with_abi(emitter, hooks, instr, instr_offset, abi, defs.string_encoding(hooks)).map(|decoded| match decoded.args {
+        EarlyRaiseArgs::Decoded(args) => args,
+        EarlyRaiseArgs::Unknown(_) => unreachable!("decoded with an abi"),
+    }))
+}
+
 fn decode_args_with_abi(
     emitter: &impl Emitter,
     hooks: &dyn LanguageHooks,
     instr: &RawInstr,
     instr_offset: raw::BytePos,
     siggy: &InstrAbi,
+    string_encoding: Encoding,
 ) -> Result<EarlyRaiseInstr, ErrorReported> {
     use crate::io::BinRead;
 
@@ -238,7 +287,7 @@ fn decode_args_with_abi(
                 ScalarValue::Int(extra_arg as _)
             },
 
-            | ArgEncoding::Integer { arg0: false, size: 4, ty_color: _ }
+            | ArgEncoding::Integer { arg0: false, size: 4, .. }
             | ArgEncoding::Color
             | ArgEncoding::JumpOffset
             | ArgEncoding::JumpTime
@@ -248,10 +297,13 @@ fn decode_args_with_abi(
                 ScalarValue::Int(args_blob.read_u32().expect("already checked len") as i32)
             },
 
-            | ArgEncoding::Integer { arg0: false, size: 2, ty_color: _ }
+            | ArgEncoding::Integer { arg0: false, size: 2, signed, .. }
             => {
                 decrease_len(emitter, &mut remaining_len, 2)?;
-                ScalarValue::Int(args_blob.read_i16().expect("already checked len") as i32)
+                ScalarValue::Int(match signed {
+                    true => args_blob.read_i16().expect("already checked len") as i32,
+                    false => args_blob.read_u16().expect("already checked len") as i32,
+                })
             },
 
             | ArgEncoding::Integer { size, .. }
@@ -284,13 +336,20 @@ fn decode_args_with_abi(
                 let warn_on_trimmed_data = !furibug;  // furibug DOES leave garbage after the null
                 encoded.trim_first_nul(emitter, warn_on_trimmed_data);
 
-                let string = encoded.decode(DEFAULT_ENCODING).map_err(|e| emitter.emit(e))?;
+                let (string, had_errors) = encoded.decode_lossy(string_encoding);
+                if had_errors {
+                    emitter.emit(warning!(
+                        "string is not valid '{}'; invalid bytes were replaced with U+FFFD", string_encoding.name(),
+                    )).ignore();
+                }
                 ScalarValue::String(string)
             },
         };
 
         let is_reg = match reg_style {
             RegisterEncodingStyle::ByParamMask => param_mask_bit,
+            // word-sized args are never registers, regardless of what their values look like
+            RegisterEncodingStyle::EosdEcl { .. } if matches!(enc, ArgEncoding::Integer { size: 2, .. }) => false,
             RegisterEncodingStyle::EosdEcl { does_value_look_like_a_register } => {
                 does_value_look_like_a_register(&value)
             },
@@ -320,6 +379,7 @@ fn decode_args_with_abi(
         opcode: instr.opcode,
         difficulty_mask: instr.difficulty,
         pseudo_arg0,
+        stack_pop: instr.pop,
         args: EarlyRaiseArgs::Decoded(args),
     })
 }
@@ -356,13 +416,14 @@ fn gather_instr_offsets(
 
 fn gather_jump_time_args(
     script: &[EarlyRaiseInstr],
+    instr_offsets: &[raw::BytePos],
     defs: &context::Defs,
     hooks: &dyn LanguageHooks,
 ) -> Result<JumpData, ErrorReported> {
     let mut all_offset_args = BTreeMap::<u64, BTreeSet<Option<i32>>>::new();
 
     for instr in script {
-        if let Some((jump_offset, jump_time)) = extract_jump_args_by_signature(hooks, instr, defs) {
+        if let Some((jump_offset, jump_time)) = extract_jump_args_by_signature(hooks, instr, instr_offsets, defs) {
             all_offset_args.entry(jump_offset).or_default().insert(jump_time);
         }
     }
@@ -398,6 +459,13 @@ fn generate_offset_labels(
     Ok(offset_labels)
 }
 
+/// Replace offset-based label names with `label_0`, `label_1`, ... in order of offset.
+fn renumber_offset_labels(offset_labels: &mut OffsetLabels) {
+    for (index, label) in offset_labels.values_mut().enumerate() {
+        label.label = ident!("label_{index}");
+    }
+}
+
 /// Given all of the different time args used when jumping to `next_offset`,
 /// determine what to call the label at this offset (and what time label to give it).
 fn generate_label_at_offset(
@@ -439,6 +507,7 @@ fn test_generate_label_at_offset() {
 fn extract_jump_args_by_signature(
     hooks: &dyn LanguageHooks,
     instr: &EarlyRaiseInstr,
+    instr_offsets: &[raw::BytePos],
     defs: &context::Defs,
 ) -> Option<(raw::BytePos, Option<raw::Time>)> {
     let mut jump_offset = None;
@@ -453,7 +522,7 @@ fn extract_jump_args_by_signature(
     for (arg, encoding) in zip!(args, abi.arg_encodings()) {
         match encoding {
             ArgEncoding::JumpOffset
-            => jump_offset = Some(hooks.decode_label(instr.offset, arg.expect_immediate_int() as u32)),
+            => jump_offset = Some(defs.label_encoding(hooks).decode(instr_offsets, instr.offset, arg.expect_immediate_int() as u32)),
             ArgEncoding::JumpTime
             => jump_time = Some(arg.expect_immediate_int()),
             _ => {},
@@ -469,8 +538,12 @@ fn extract_jump_args_by_signature(
 struct AtomRaiser<'a, 'ctx> {
     language: LanguageKey,
     const_names: &'a ConstNames,
+    use_const_names: bool,
+    explicit_arg0: bool,
     offset_labels: &'a OffsetLabels,
+    instr_offsets: &'a [raw::BytePos],
     hooks: &'a dyn LanguageHooks,
+    label_encoding: LabelEncoding,
     ctx: &'a CompilerContext<'ctx>,
 }
 
@@ -479,6 +552,10 @@ struct AtomRaiser<'a, 'ctx> {
 struct IllegalOffset;
 
 impl AtomRaiser<'_, '_> {
+    fn decode_label(&self, instr: &EarlyRaiseInstr, bits: raw::RawDwordBits) -> raw::BytePos {
+        self.label_encoding.decode(self.instr_offsets, instr.offset, bits)
+    }
+
     /// Raise an instr to raw `ins_` syntax.
     fn raise_raw_ins_args(
         &self,
@@ -501,7 +578,7 @@ impl AtomRaiser<'_, '_> {
             encodings.iter().zip(args)
                 .find(|(&enc, _)| enc == &ArgEncoding::JumpOffset)
                 .map(|(_, offset_arg)| {
-                    let dest_offset = self.hooks.decode_label(instr.offset, offset_arg.expect_int() as u32);
+                    let dest_offset = self.decode_label(instr, offset_arg.expect_int() as u32);
                     self.offset_labels.get(&dest_offset)
                         .ok_or(IllegalOffset)  // if it was a valid offset, it would have a label
                 })
@@ -514,9 +591,10 @@ impl AtomRaiser<'_, '_> {
 
         // Show an explicit @arg0 if necessary
         let pseudo_arg0 = match instr.pseudo_arg0 {
-            None | Some(0) => None,
+            None => None,
+            Some(0) if !self.explicit_arg0 => None,
             Some(arg0) => {
-                let enc = ArgEncoding::Integer { size: 2, ty_color: None, arg0: true };
+                let enc = ArgEncoding::Integer { size: 2, signed: true, ty_color: None, arg0: true, radix: ast::IntRadix::Dec };
                 let expr = self.raise_arg(emitter, &SimpleArg::from(arg0 as i32), &enc, dest_label)?;
                 Some(expr)
             }
@@ -536,6 +614,7 @@ impl AtomRaiser<'_, '_> {
             opcode: Some(instr.opcode),
             plain_args: raised_args,
             pseudo_arg0,
+            pseudo_pop: Some(instr.stack_pop).filter(|&pop| pop != 0),
             ..Default::default()
         })
     }
@@ -557,6 +636,11 @@ impl AtomRaiser<'_, '_> {
             jump: ref jump_info, plain_args: ref plain_args_info, sub_id: ref sub_id_info,
         } = abi_parts;
 
+        // intrinsic syntax has nowhere to put an arg0 that isn't part of the signature
+        if matches!(instr.pseudo_arg0, Some(arg0) if arg0 != 0) {
+            return Err(CannotRaiseIntrinsic);
+        }
+
         let padding_range = padding_info.index..padding_info.index + padding_info.count;
         if !args[padding_range].iter().all(|arg| arg.is_immediate_zero()) {
             return Err(CannotRaiseIntrinsic);  // data in padding
@@ -570,7 +654,7 @@ impl AtomRaiser<'_, '_> {
                 abi_parts::JumpArgOrder::Loc => (&args[index], None),
             };
 
-            let label_offset = self.hooks.decode_label(instr.offset, offset_arg.expect_immediate_int() as u32);
+            let label_offset = self.decode_label(instr, offset_arg.expect_immediate_int() as u32);
             let label = &self.offset_labels[&label_offset];
             jump = Some(ast::StmtGoto {
                 destination: sp!(label.label.clone()),
@@ -640,12 +724,24 @@ impl AtomRaiser<'_, '_> {
     ) -> Result<ast::Expr, ErrorReported> {
         ensure!(emitter, !raw.is_reg, "expected an immediate, got a register");
 
+        if let ArgEncoding::Integer { ty_color: Some(TypeColor::Enum(enum_name)), .. } = enc {
+            if enum_name == &auto_enum_names::bool() && !matches!(raw.expect_int(), 0 | 1) {
+                emitter.emit(warning!("non-boolean value {} in a boolean argument", raw.expect_int())).ignore();
+            }
+        }
+
         match enc {
             | ArgEncoding::Padding
-            | ArgEncoding::Integer { ty_color: None, .. }
             => Ok(ast::Expr::from(raw.expect_int())),
 
-            | ArgEncoding::Integer { ty_color: Some(ty_color), .. }
+            | ArgEncoding::Integer { ty_color: None, radix, .. }
+            => Ok(ast::Expr::LitInt { value: raw.expect_int(), radix: *radix }),
+
+            | ArgEncoding::Integer { ty_color: Some(_), radix, .. }
+            if !self.use_const_names
+            => Ok(ast::Expr::LitInt { value: raw.expect_int(), radix: *radix }),
+
+            | ArgEncoding::Integer { ty_color: Some(ty_color), radix, .. }
             => {
                 let lookup_table = match ty_color {
                     TypeColor::Enum(ident) => &self.const_names.enums[ident],
@@ -654,6 +750,7 @@ impl AtomRaiser<'_, '_> {
                     &lookup_table,
                     raw.expect_int(),
                     ty_color,
+                    *radix,
                 ))
             }
 
@@ -669,7 +766,7 @@ impl AtomRaiser<'_, '_> {
             | ArgEncoding::JumpTime
             => match dest_label {
                 | Some(Ok(Label { time_label, label })) if *time_label == raw.expect_int()
-                => Ok(ast::Expr::LabelProperty { label: sp!(label.clone()), keyword: sp!(token![timeof]) }),
+                => Ok(ast::Expr::LabelProperty { script: None, label: sp!(label.clone()), keyword: sp!(token![timeof]) }),
 
                 _ => Ok(ast::Expr::from(raw.expect_int())),
             },
@@ -677,7 +774,7 @@ impl AtomRaiser<'_, '_> {
             | ArgEncoding::JumpOffset
             => match dest_label.unwrap() {
                 | Ok(Label { label, .. })
-                => Ok(ast::Expr::LabelProperty { label: sp!(label.clone()), keyword: sp!(token![offsetof]) }),
+                => Ok(ast::Expr::LabelProperty { script: None, label: sp!(label.clone()), keyword: sp!(token![offsetof]) }),
 
                 | Err(IllegalOffset) => {
                     emitter.emit(warning!("invalid offset in a jump instruction")).ignore();
@@ -717,7 +814,7 @@ impl AtomRaiser<'_, '_> {
                 RegId(float_reg as i32)
             },
         };
-        let name = ast::VarName::Reg { reg, language: Some(self.language) };
+        let name = ast::VarName::Reg { reg, language: Some(self.language), language_prefix: None };
         Ok(ast::Var { ty_sigil: Some(ast_ty_sigil), name })
     }
 
@@ -729,7 +826,7 @@ impl AtomRaiser<'_, '_> {
     }
 }
 
-fn raise_to_possibly_named_constant(names: &IdMap<i32, Sp<Ident>>, id: i32, ty_color: &TypeColor) -> ast::Expr {
+pub(super) fn raise_to_possibly_named_constant(names: &IdMap<i32, Sp<Ident>>, id: i32, ty_color: &TypeColor, radix: ast::IntRadix) -> ast::Expr {
     match names.get(&id) {
         Some(ident) => {
             match ty_color {
@@ -741,7 +838,7 @@ fn raise_to_possibly_named_constant(names: &IdMap<i32, Sp<Ident>>, id: i32, ty_c
                 },
             }
         },
-        None => id.into(),
+        None => ast::Expr::LitInt { value: id, radix },
     }
 }
 
@@ -6,6 +6,7 @@ use super::{
 use crate::raw;
 use crate::ast::{self, pseudo};
 use crate::ident::ResIdent;
+use crate::context::defs::TypeColor;
 use crate::pos::{Sp, Span};
 use crate::diagnostic::{Emitter};
 use crate::error::{ErrorReported};
@@ -69,7 +70,7 @@ impl SingleSubRaiser<'_, '_> {
     ) -> Result<(), CannotRaiseIntrinsic> {
         let RaisedIntrinsicParts {
             mut sub_id, mut jump, outputs, plain_args,
-            opcode, pseudo_arg0, pseudo_blob, pseudo_mask,
+            opcode, pseudo_arg0, pseudo_blob, pseudo_mask, pseudo_pop, pseudo_arg_count,
         } = instr.parts.clone();
         let mut outputs = outputs.into_iter();
         let mut plain_args = plain_args.into_iter();
@@ -84,9 +85,10 @@ impl SingleSubRaiser<'_, '_> {
                         value: sp!(extra_arg),
                     }));
                 }
+                pseudos.extend(pseudo_pop.map(make_pop_pseudo));
 
                 emit_stmt(ast::StmtKind::Expr(sp!(ast::Expr::Call(ast::ExprCall {
-                    name: sp!(ast::CallableName::Ins { opcode: opcode.unwrap(), language: Some(self.language) }),
+                    name: sp!(ast::CallableName::Ins { opcode: opcode.unwrap(), language: Some(self.language), language_prefix: None }),
                     pseudos,
                     args: plain_args.map(|expr| sp!(expr)).collect(),
                 }))));
@@ -112,6 +114,14 @@ impl SingleSubRaiser<'_, '_> {
                         value: sp!(extra_arg),
                     }));
                 }
+                pseudos.extend(pseudo_pop.map(make_pop_pseudo));
+                if let Some(arg_count) = pseudo_arg_count {
+                    pseudos.push(sp!(ast::PseudoArg {
+                        at_sign: sp!(()), eq_sign: sp!(()),
+                        kind: sp!(token![nargs]),
+                        value: sp!((arg_count as i32).into()),
+                    }));
+                }
 
                 pseudos.push(sp!(ast::PseudoArg {
                     at_sign: sp!(()), eq_sign: sp!(()),
@@ -120,7 +130,7 @@ impl SingleSubRaiser<'_, '_> {
                 }));
 
                 emit_stmt(ast::StmtKind::Expr(sp!(ast::Expr::Call(ast::ExprCall {
-                    name: sp!(ast::CallableName::Ins { opcode: opcode.unwrap(), language: Some(self.language) }),
+                    name: sp!(ast::CallableName::Ins { opcode: opcode.unwrap(), language: Some(self.language), language_prefix: None }),
                     pseudos,
                     args: vec![],
                 }))));
@@ -158,7 +168,7 @@ impl SingleSubRaiser<'_, '_> {
             RIKind::Standard(IKind::InterruptLabel) => {
                 let interrupt = plain_args.next().unwrap();
                 let interrupt = sp!(Span::NULL => match interrupt.as_const_int() {
-                    Some(interrupt_id) => interrupt_id,
+                    Some(interrupt_id) => self.raise_interrupt_id(interrupt_id),
                     None => {
                         assert!(matches!(interrupt, ast::Expr::Var { .. }));
                         return Err(CannotRaiseIntrinsic);  // register in interrupt label
@@ -226,6 +236,101 @@ impl SingleSubRaiser<'_, '_> {
             => return Err(CannotRaiseIntrinsic),
 
 
+            // A conditional jump whose condition was rebuilt from the stack.
+            RIKind::Standard(IKind::StackCondJmp(op)) => {
+                let goto = jump.take().unwrap();
+                let cond = plain_args.next().ok_or(CannotRaiseIntrinsic)?;
+                let keyword = match op {
+                    ast::BinOpKind::Ne => sp!(token![if]),
+                    ast::BinOpKind::Eq => sp!(token![unless]),
+                    _ => unreachable!(),
+                };
+                emit_stmt(stmt_cond_goto!(rec_sp!(Span::NULL =>
+                    as kind, #keyword #cond goto #(goto.destination) #(goto.time)
+                )));
+            },
+
+
+            // A conditional jump assembled from a jump over a jump.
+            RIKind::CondJmpExpr(keyword) => {
+                let goto = jump.take().unwrap();
+                let cond = plain_args.next().unwrap();
+                let keyword = sp!(keyword);
+                emit_stmt(stmt_cond_goto!(rec_sp!(Span::NULL =>
+                    as kind, #keyword #cond goto #(goto.destination) #(goto.time)
+                )));
+            },
+
+
+            // With no value rebuilt from the stack, this is a plain `return;`.
+            RIKind::Standard(IKind::Return) => {
+                emit_stmt(ast::StmtKind::Return {
+                    keyword: sp!(()),
+                    value: plain_args.next().map(|value| sp!(value)),
+                });
+            },
+
+
+            // A stack call that doesn't pop anything has no args to rebuild.
+            RIKind::Standard(IKind::Call) => {
+                if pseudo_pop.is_some() {
+                    return Err(CannotRaiseIntrinsic);
+                }
+                let ident = ResIdent::new_null(sub_id.take().unwrap());
+                let name = ast::CallableName::Normal { ident, language_if_ins: None };
+
+                emit_stmt(ast::StmtKind::Expr(sp!(ast::Expr::Call(ast::ExprCall {
+                    name: sp!(name),
+                    pseudos: vec![],
+                    args: vec![],
+                }))));
+            },
+
+
+            // An async call is only raised once its args (if any) were rebuilt from the stack.
+            RIKind::Standard(IKind::CallAsync) | RIKind::Standard(IKind::CallAsyncId) => {
+                if pseudo_pop.is_some() {
+                    return Err(CannotRaiseIntrinsic);
+                }
+                let ident = ResIdent::new_null(sub_id.take().unwrap());
+                let name = ast::CallableName::Normal { ident, language_if_ins: None };
+
+                let async_ = match instr.kind {
+                    RIKind::Standard(IKind::CallAsyncId) => ast::CallAsyncKind::CallAsyncId {
+                        keyword: sp!(()),
+                        id: Box::new(sp!(plain_args.next().unwrap())),
+                    },
+                    _ => ast::CallAsyncKind::CallAsync { keyword: sp!(()) },
+                };
+
+                emit_stmt(ast::StmtKind::CallSub {
+                    at_symbol: false,
+                    async_: Some(async_),
+                    call: ast::ExprCall {
+                        name: sp!(name),
+                        pseudos: vec![],
+                        args: plain_args.map(|x| sp!(x)).collect(),
+                    },
+                });
+            },
+
+
+            // The rest of the stack-machine intrinsics only make sense as part of an expression,
+            // which couldn't be rebuilt.
+            | RIKind::Standard(IKind::StackAlloc { .. })
+            | RIKind::Standard(IKind::Push { .. })
+            | RIKind::Standard(IKind::Pop { .. })
+            | RIKind::Standard(IKind::StackBinOp { .. })
+            | RIKind::Standard(IKind::StackUnOp { .. })
+            => return Err(CannotRaiseIntrinsic),
+
+
+            // regions that can be turned into difficulty labels have already been removed;
+            // anything left over is raised as a raw instruction.
+            | RIKind::Standard(IKind::DifficultyMask)
+            => return Err(CannotRaiseIntrinsic),
+
+
             | RIKind::End
             => {},
 
@@ -249,6 +354,30 @@ impl SingleSubRaiser<'_, '_> {
         }
         Ok(())
     }
+
+    /// Write an interrupt id as the name of a member of [`DecompileOptions::interrupt_enum`][super::DecompileOptions::interrupt_enum], when possible.
+    fn raise_interrupt_id(&self, interrupt_id: i32) -> ast::Expr {
+        let enum_name = match (&self.options.interrupt_enum, self.options.const_names) {
+            (Some(enum_name), true) => enum_name,
+            _ => return ast::Expr::from(interrupt_id),
+        };
+        match self.const_names.enums.get(enum_name) {
+            Some(names) => {
+                let ty_color = TypeColor::Enum(enum_name.clone());
+                super::early::raise_to_possibly_named_constant(names, interrupt_id, &ty_color, ast::IntRadix::Dec)
+            },
+            None => ast::Expr::from(interrupt_id),
+        }
+    }
+}
+
+
+fn make_pop_pseudo(pop: raw::StackPop) -> Sp<ast::PseudoArg> {
+    sp!(ast::PseudoArg {
+        at_sign: sp!(()), eq_sign: sp!(()),
+        kind: sp!(token![pop]),
+        value: sp!((pop as i32).into()),
+    })
 }
 
 
@@ -1,12 +1,18 @@
-use super::{RaiseInstr, RaiseIntrinsicKind, RaisedIntrinsicParts, SingleSubRaiser};
+use super::{RaiseInstr, RaiseIntrinsicKind, RaisedIntrinsicParts, SingleSubRaiser, Label};
 
-use crate::ast;
+use std::collections::HashMap;
+
+use crate::raw;
+use crate::ast::{self, Visit};
+use crate::ident::Ident;
+use crate::pos::Sp;
 use crate::llir::{IntrinsicInstrKind};
 use crate::context::{self, CompilerContext};
 use crate::value::{ReadType};
 use crate::passes::semantics::time_and_difficulty::{DEFAULT_DIFFICULTY_MASK_BYTE};
 use crate::diff_switch_utils as ds_util;
 use crate::bitset::BitSet32;
+use crate::diagnostic::Emitter;
 
 use RaiseIntrinsicKind as RIKind;
 use IntrinsicInstrKind as IKind;
@@ -14,11 +20,35 @@ use IntrinsicInstrKind as IKind;
 impl SingleSubRaiser<'_, '_> {
     pub fn perform_recognition(
         &self,
+        emitter: &impl Emitter,
         instrs: Vec<RaiseInstr>,
     ) -> Vec<RaiseInstr> {
         let mut out = vec![];
         let mut remaining = &instrs[..];
         while !remaining.is_empty() {
+            if self.has_stack {
+                match recognize_stack_expr(remaining) {
+                    Some(Ok((new_instr, num_replaced))) => {
+                        out.push(new_instr);
+                        remaining = &remaining[num_replaced..];
+                        continue;
+                    },
+                    Some(Err(UnbalancedStack(num_instrs))) => {
+                        emitter.emit(warning!(
+                            message("could not rebuild a stack expression at time {}", remaining[0].time),
+                            note("\
+                                the values pushed here are still on the stack at a label, jump, or other instruction, \
+                                so these {} instructions were left as raw instructions\
+                            ", num_instrs),
+                        )).ignore();
+                        out.extend(remaining[..num_instrs].iter().cloned());
+                        remaining = &remaining[num_instrs..];
+                        continue;
+                    },
+                    None => {},
+                }
+            }
+
             if let Some((new_instr, num_replaced)) = recognize_double_instr_intrinsic(remaining) {
                 out.push(new_instr);
                 remaining = &remaining[num_replaced..];
@@ -44,8 +74,317 @@ impl SingleSubRaiser<'_, '_> {
             out.push(remaining[0].clone());
             remaining = &remaining[1..];
         }
+
+        if self.options.blocks {
+            out = self.recognize_unless_polyfills(out);
+        }
         out
     }
+
+    /// Remove [`IntrinsicInstrKind::DifficultyMask`] instructions, and give their masks to the instructions
+    /// in each region instead, so that they can be written as difficulty labels.
+    ///
+    /// This is only done if compiling the result would place the mask instructions exactly as they are.
+    /// Otherwise the script is left untouched, and the mask instructions are written as raw instructions.
+    pub fn recognize_difficulty_mask_regions(&self, instrs: Vec<RaiseInstr>) -> Vec<RaiseInstr> {
+        if self.intrinsic_instrs.get_opcode_opt(IKind::DifficultyMask).is_none() {
+            return instrs;
+        }
+        match regions_to_difficulty_masks(&instrs) {
+            Some(out) if difficulty_mask_placements(&out) == mask_instr_placements(&instrs) => out,
+            _ => instrs,
+        }
+    }
+
+    /// Collapse the pattern `if (a) goto skip; if (b) goto skip; goto label; skip:` into a single
+    /// conditional jump.
+    ///
+    /// This is the shape produced by the compiler for `unless` jumps whose negated comparison has
+    /// no intrinsic, and for `&&` conditions.  With a single condition it becomes
+    /// `unless (a) goto label`, otherwise `if (!a && !b) goto label` (with the comparisons negated).
+    ///
+    /// The similar pattern `if (--x) goto skip; goto label; skip:` produced for `unless (--x) goto label`
+    /// is collapsed as well.
+    fn recognize_unless_polyfills(&self, instrs: Vec<RaiseInstr>) -> Vec<RaiseInstr> {
+        let label_refcounts = get_label_refcounts(&instrs);
+        let mut consumed_labels = vec![];
+
+        let mut out = vec![];
+        let mut remaining = &instrs[..];
+        while !remaining.is_empty() {
+            let recognized = {
+                self.recognize_unless_polyfill(remaining, &label_refcounts)
+                    .or_else(|| recognize_unless_count_jmp(remaining, &label_refcounts))
+            };
+            if let Some((new_instr, num_replaced)) = recognized {
+                consumed_labels.push(remaining[num_replaced].labels[0].label.clone());
+                out.push(new_instr);
+                remaining = &remaining[num_replaced..];
+                continue;
+            }
+            out.push(remaining[0].clone());
+            remaining = &remaining[1..];
+        }
+
+        if !consumed_labels.is_empty() {
+            for instr in &mut out {
+                instr.labels.retain(|label| !consumed_labels.contains(&label.label));
+            }
+        }
+        out
+    }
+
+    fn recognize_unless_polyfill(
+        &self,
+        instrs: &[RaiseInstr],
+        label_refcounts: &HashMap<Ident, u32>,
+    ) -> Option<(RaiseInstr, usize)> {
+        let first = &instrs[0];
+        let same_time_and_mask = |instr: &RaiseInstr| (instr.time, instr.difficulty_mask) == (first.time, first.difficulty_mask);
+
+        // the conditional jumps over the unconditional one
+        let mut conds = vec![];
+        let mut skip_label = None;
+        for instr in instrs {
+            let (op, ty) = match instr.kind {
+                RIKind::Standard(IKind::CondJmp(op, ty)) => (op, ty),
+                _ => break,
+            };
+            let jump = instr.parts.jump.as_ref()?;
+            if jump.time.is_some() || !same_time_and_mask(instr) {
+                return None;
+            }
+            if !conds.is_empty() && !instr.labels.is_empty() {
+                return None;
+            }
+            match &skip_label {
+                None => skip_label = Some(jump.destination.value.clone()),
+                Some(skip_label) => if skip_label != &jump.destination.value {
+                    return None;
+                },
+            }
+            conds.push((op, ty, &instr.parts.plain_args));
+        }
+        let skip_label = skip_label?;
+        let num_conds = conds.len();
+
+        // the unconditional jump
+        let jmp_instr = instrs.get(num_conds)?;
+        if jmp_instr.kind != RIKind::Standard(IKind::Jmp) || !jmp_instr.labels.is_empty() || !same_time_and_mask(jmp_instr) {
+            return None;
+        }
+        let goto = jmp_instr.parts.jump.clone()?;
+
+        // the skip label must be right after, at the same time, and used by nothing else
+        let after = instrs.get(num_conds + 1)?;
+        match &after.labels[..] {
+            [label] if label.label == skip_label && label.time_label == first.time => {},
+            _ => return None,
+        }
+        if label_refcounts.get(&skip_label).copied() != Some(num_conds as u32) {
+            return None;
+        }
+
+        let (keyword, cond) = match &conds[..] {
+            &[(op, ty, args)] => {
+                // the compiler would use the negated comparison if it was available
+                let negated_op = op.negate_comparison()?;
+                if self.intrinsic_instrs.alternatives().cond_jmps.contains_key(&(negated_op, ty)) {
+                    return None;
+                }
+                (token![unless], make_binop(args, op))
+            },
+            _ => {
+                let mut negated_conds = conds.iter().map(|&(op, _, args)| {
+                    op.negate_comparison().map(|negated_op| make_binop(args, negated_op))
+                });
+                let first_cond = negated_conds.next().unwrap()?;
+                let cond = negated_conds.try_fold(first_cond, |acc, cond| {
+                    Some(ast::Expr::BinOp(Box::new(sp!(acc)), sp!(token![&&]), Box::new(sp!(cond?))))
+                })?;
+                (token![if], cond)
+            },
+        };
+
+        let combined_instr = RaiseInstr {
+            fallback_expansion: Some(instrs[..num_conds + 1].to_vec()),
+            labels: first.labels.clone(),
+            time: first.time,
+            difficulty_mask: first.difficulty_mask,
+            kind: RIKind::CondJmpExpr(keyword),
+            parts: RaisedIntrinsicParts {
+                plain_args: vec![cond],
+                jump: Some(goto),
+                ..Default::default()
+            },
+        };
+        Some((combined_instr, num_conds + 1))
+    }
+}
+
+/// Recognize `if (--x) goto skip; goto label; skip:`, which is how the compiler writes
+/// `unless (--x) goto label`.
+fn recognize_unless_count_jmp(
+    instrs: &[RaiseInstr],
+    label_refcounts: &HashMap<Ident, u32>,
+) -> Option<(RaiseInstr, usize)> {
+    let [count_jmp_instr, jmp_instr, after, ..] = instrs else { return None };
+    let same_time_and_mask = |instr: &RaiseInstr| {
+        (instr.time, instr.difficulty_mask) == (count_jmp_instr.time, count_jmp_instr.difficulty_mask)
+    };
+
+    let op = match count_jmp_instr.kind {
+        RIKind::Standard(IKind::CountJmp(op)) => op,
+        _ => return None,
+    };
+    let skip_goto = count_jmp_instr.parts.jump.as_ref()?;
+    let var = count_jmp_instr.parts.outputs.first()?;
+    if skip_goto.time.is_some() {
+        return None;
+    }
+
+    // the unconditional jump
+    if jmp_instr.kind != RIKind::Standard(IKind::Jmp) || !jmp_instr.labels.is_empty() || !same_time_and_mask(jmp_instr) {
+        return None;
+    }
+    let goto = jmp_instr.parts.jump.clone()?;
+
+    // the skip label must be right after, at the same time, and used by nothing else
+    match &after.labels[..] {
+        [label] if label.label == skip_goto.destination.value && label.time_label == count_jmp_instr.time => {},
+        _ => return None,
+    }
+    if label_refcounts.get(&skip_goto.destination.value).copied() != Some(1) {
+        return None;
+    }
+
+    let predec = ast::Expr::XcrementOp {
+        op: sp!(token![--]),
+        order: ast::XcrementOpOrder::Pre,
+        var: sp!(var.clone()),
+    };
+    let cond = match op {
+        token![!=] => predec,
+        _ => ast::Expr::BinOp(Box::new(sp!(predec)), sp!(op), Box::new(sp!(ast::Expr::from(0)))),
+    };
+
+    let combined_instr = RaiseInstr {
+        fallback_expansion: Some(instrs[..2].to_vec()),
+        labels: count_jmp_instr.labels.clone(),
+        time: count_jmp_instr.time,
+        difficulty_mask: count_jmp_instr.difficulty_mask,
+        kind: RIKind::CondJmpExpr(token![unless]),
+        parts: RaisedIntrinsicParts {
+            plain_args: vec![cond],
+            jump: Some(goto),
+            ..Default::default()
+        },
+    };
+    Some((combined_instr, 2))
+}
+
+fn make_binop(args: &[ast::Expr], op: ast::BinOpKind) -> ast::Expr {
+    ast::Expr::BinOp(Box::new(sp!(args[0].clone())), sp!(op), Box::new(sp!(args[1].clone())))
+}
+
+/// Get the number of times each label is mentioned in a jump or as an argument.
+fn get_label_refcounts(instrs: &[RaiseInstr]) -> HashMap<Ident, u32> {
+    struct Visitor(HashMap<Ident, u32>);
+    impl Visit for Visitor {
+        fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+            ast::walk_expr(self, expr);
+            if let ast::Expr::LabelProperty { script: None, label, .. } = &expr.value {
+                *self.0.entry(label.value.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+
+    let mut visitor = Visitor(HashMap::new());
+    for instr in instrs {
+        if let Some(jump) = &instr.parts.jump {
+            *visitor.0.entry(jump.destination.value.clone()).or_insert(0) += 1;
+        }
+        for arg in instr.parts.plain_args.iter().chain(&instr.parts.pseudo_arg0) {
+            visitor.visit_expr(&sp!(arg.clone()));
+        }
+    }
+    visitor.0
+}
+
+/// A [`IntrinsicInstrKind::DifficultyMask`] instruction's mask (`None` for other instructions),
+/// together with the time and labels of the instruction.
+type MaskInstrPlacement = (Option<raw::DifficultyMask>, raw::Time, Vec<Label>);
+
+fn difficulty_mask_arg(instr: &RaiseInstr) -> Option<raw::DifficultyMask> {
+    match instr.kind {
+        RIKind::Standard(IKind::DifficultyMask) if instr.difficulty_mask == DEFAULT_DIFFICULTY_MASK_BYTE => {
+            instr.parts.plain_args[0].as_const_int().and_then(|mask| mask.try_into().ok())
+        },
+        _ => None,
+    }
+}
+
+fn mask_instr_placements(instrs: &[RaiseInstr]) -> Vec<MaskInstrPlacement> {
+    instrs.iter().map(|instr| (difficulty_mask_arg(instr), instr.time, instr.labels.clone())).collect()
+}
+
+/// Removes the mask instructions, moving their labels to the next instruction and their masks to every
+/// instruction up to the next mask instruction.
+///
+/// Returns `None` if instructions already have masks of their own, or if a jump would end up with a mask.
+fn regions_to_difficulty_masks(instrs: &[RaiseInstr]) -> Option<Vec<RaiseInstr>> {
+    let mut out = vec![];
+    let mut current_mask = DEFAULT_DIFFICULTY_MASK_BYTE;
+    let mut pending_labels = vec![];
+    for instr in instrs {
+        if let Some(mask) = difficulty_mask_arg(instr) {
+            pending_labels.extend(instr.labels.iter().cloned());
+            current_mask = mask;
+            continue;
+        }
+        if instr.difficulty_mask != DEFAULT_DIFFICULTY_MASK_BYTE {
+            return None;
+        }
+
+        let mut instr = instr.clone();
+        instr.labels.splice(0..0, pending_labels.drain(..));
+        if instr.labels.len() > 1 {
+            return None;
+        }
+        if instr.kind != RIKind::End {
+            let is_jump = instr.parts.jump.is_some() || instr.parts.plain_args.iter().any(|arg| matches!(arg, ast::Expr::LabelProperty { .. }));
+            if is_jump && current_mask != DEFAULT_DIFFICULTY_MASK_BYTE {
+                return None;
+            }
+            instr.difficulty_mask = current_mask;
+        }
+        out.push(instr);
+    }
+    Some(out)
+}
+
+/// Where the compiler would put mask instructions for instructions with these masks.
+///
+/// This mirrors the lowering of difficulty masks: the mask is set wherever it changes, and restored
+/// before every label and at the end of the script.
+fn difficulty_mask_placements(instrs: &[RaiseInstr]) -> Vec<MaskInstrPlacement> {
+    let mut out = vec![];
+    let mut current_mask = DEFAULT_DIFFICULTY_MASK_BYTE;
+    for instr in instrs {
+        let mut labels = instr.labels.clone();
+        if let Some(label) = labels.first() {
+            if current_mask != DEFAULT_DIFFICULTY_MASK_BYTE {
+                out.push((Some(DEFAULT_DIFFICULTY_MASK_BYTE), label.time_label, vec![]));
+                current_mask = DEFAULT_DIFFICULTY_MASK_BYTE;
+            }
+        }
+        if instr.difficulty_mask != current_mask {
+            out.push((Some(instr.difficulty_mask), instr.time, std::mem::take(&mut labels)));
+            current_mask = instr.difficulty_mask;
+        }
+        out.push((None, instr.time, labels));
+    }
+    out
 }
 
 /// Try to raise an intrinsic that is two instructions long.
@@ -92,6 +431,123 @@ fn recognize_double_instr_intrinsic(
     Some((combined_instr, 2))
 }
 
+/// Error from [`recognize_stack_expr`] when the stack depth doesn't match up before an expression
+/// is consumed.  Contains the number of instructions that must be left alone.
+struct UnbalancedStack(usize);
+
+/// Rebuild an expression from a stack-machine sequence of pushes and operations, by symbolically
+/// executing them up to the instruction that consumes the result.
+///
+/// Anything that would leave values on the stack across a label or a change in time or difficulty
+/// is reported as [`UnbalancedStack`], since no expression could represent that.
+fn recognize_stack_expr(
+    instrs: &[RaiseInstr],
+) -> Option<Result<(RaiseInstr, usize), UnbalancedStack>> {
+    let first_instr = &instrs[0];
+    if !matches!(first_instr.kind, RIKind::Standard(IKind::Push(_))) {
+        return None;
+    }
+
+    let mut stack = vec![];
+    for (instr_index, instr) in instrs.iter().enumerate() {
+        let num_instrs_used = instr_index + 1;
+        if (instr.time, instr.difficulty_mask) != (first_instr.time, first_instr.difficulty_mask) {
+            return Some(Err(UnbalancedStack(instr_index)));  // needs time/difficulty label
+        }
+
+        if instr_index > 0 && !instr.labels.is_empty() {
+            return Some(Err(UnbalancedStack(instr_index)));  // needs label
+        }
+
+        let (combined_kind, combined_parts) = match instr.kind {
+            RIKind::Standard(IKind::Push(_)) => {
+                stack.push(instr.parts.plain_args[0].clone());
+                continue;
+            },
+
+            RIKind::Standard(IKind::StackBinOp(op, _)) => {
+                if stack.len() < 2 {
+                    return Some(Err(UnbalancedStack(num_instrs_used)));
+                }
+                let b = stack.pop().unwrap();
+                let a = stack.pop().unwrap();
+                stack.push(ast::Expr::BinOp(Box::new(sp!(a)), sp!(op), Box::new(sp!(b))));
+                continue;
+            },
+
+            RIKind::Standard(IKind::StackUnOp(op, _)) => {
+                let Some(b) = stack.pop() else {
+                    return Some(Err(UnbalancedStack(num_instrs_used)));
+                };
+                stack.push(ast::Expr::UnOp(sp!(op), Box::new(sp!(b))));
+                continue;
+            },
+
+            // everything else consumes the whole stack
+            _ if stack.len() != num_values_consumed(instr) => {
+                return Some(Err(UnbalancedStack(instr_index)));
+            },
+
+            RIKind::Standard(IKind::Pop(ty)) => (RIKind::Standard(IKind::AssignOp(token![=], ty)), RaisedIntrinsicParts {
+                outputs: instr.parts.outputs.clone(),
+                plain_args: stack,
+                ..Default::default()
+            }),
+
+            RIKind::Standard(IKind::StackCondJmp(_)) | RIKind::Standard(IKind::Return) => (instr.kind.clone(), RaisedIntrinsicParts {
+                jump: instr.parts.jump.clone(),
+                plain_args: stack,
+                ..Default::default()
+            }),
+
+            RIKind::Standard(IKind::Call) => (RIKind::CallProper, RaisedIntrinsicParts {
+                sub_id: instr.parts.sub_id.clone(),
+                plain_args: stack,
+                ..Default::default()
+            }),
+
+            // the slot id of an async call (if any) stays in front of the args
+            RIKind::Standard(IKind::CallAsync) | RIKind::Standard(IKind::CallAsyncId) => (instr.kind.clone(), RaisedIntrinsicParts {
+                sub_id: instr.parts.sub_id.clone(),
+                plain_args: instr.parts.plain_args.iter().cloned().chain(stack).collect(),
+                ..Default::default()
+            }),
+
+            _ => unreachable!(),
+        };
+
+        let new_instr = RaiseInstr {
+            fallback_expansion: Some(instrs[..num_instrs_used].iter().cloned().collect()),
+            labels: first_instr.labels.clone(),
+            time: first_instr.time,
+            difficulty_mask: first_instr.difficulty_mask,
+            kind: combined_kind,
+            parts: combined_parts,
+        };
+        return Some(Ok((new_instr, num_instrs_used)));
+    }
+    None  // encountered end of script
+}
+
+/// How many values an instruction takes from the stack when it follows a stack expression.
+///
+/// Zero means that it can't consume an expression.
+fn num_values_consumed(instr: &RaiseInstr) -> usize {
+    match instr.kind {
+        | RIKind::Standard(IKind::Pop(_))
+        | RIKind::Standard(IKind::StackCondJmp(_))
+        | RIKind::Standard(IKind::Return)
+        => 1,
+
+        | RIKind::Standard(IKind::Call)
+        | RIKind::Standard(IKind::CallAsync)
+        | RIKind::Standard(IKind::CallAsyncId)
+        => instr.parts.pseudo_pop.unwrap_or(0) as usize,
+
+        _ => 0,
+    }
+}
+
 /// Recognize a PCB ECL sub call.
 ///
 /// Return value includes number of instructions read.
@@ -191,6 +647,10 @@ fn recognize_diff_switch(
     let mut explicit_difficulties = BitSet32::new();
     let mut first_aux_mask = None;
     for instr in instrs {
+        if next_difficulty as usize == diff_flag_names.num_diff_switch_cases() {
+            break;
+        }
+
         // do a full destructure to remind us to update this when adding a new field
         let &RaiseInstr {
             fallback_expansion: _,
@@ -236,8 +696,8 @@ fn recognize_diff_switch(
     if num_instrs_compressed < 2 {
         return None;  // one case does not a diff switch make!
     }
-    // FIXME: maybe this is overzealous
-    if diff_meta.num_difficulties < 4 {  // check we have values up to at least Lunatic
+    // a switch with any other number of cases would not compile
+    if diff_meta.num_difficulties != diff_flag_names.num_diff_switch_cases() {
         return None;
     }
 
@@ -266,7 +726,7 @@ fn diff_switchify_parts(
 
     let mut explicit_plain_args_by_index = vec![vec![]; first_instr.plain_args.len()];  // [arg_index] -> [instr_index] -> arg
     for instr in explicit_instrs {
-        let RaisedIntrinsicParts { jump, sub_id, outputs, plain_args, opcode, pseudo_blob, pseudo_mask, pseudo_arg0 } = instr;
+        let RaisedIntrinsicParts { jump, sub_id, outputs, plain_args, opcode, pseudo_blob, pseudo_mask, pseudo_arg0, pseudo_pop, pseudo_arg_count } = instr;
 
         // things that can't be diff-switchified
         macro_rules! check_eq {
@@ -278,6 +738,8 @@ fn diff_switchify_parts(
         check_eq!(opcode, &first_instr.opcode);
         check_eq!(pseudo_blob, &first_instr.pseudo_blob);
         check_eq!(pseudo_mask, &first_instr.pseudo_mask);
+        check_eq!(pseudo_pop, &first_instr.pseudo_pop);
+        check_eq!(pseudo_arg_count, &first_instr.pseudo_arg_count);
         // FIXME: technically arg0 could be decompiled to a diff switch, but I had trouble implementing
         //        this in a way that wasn't doomed to create bugs for `T(_)` args in the future
         check_eq!(pseudo_arg0, &first_instr.pseudo_arg0);
@@ -324,6 +786,8 @@ fn diff_switchify_parts(
         pseudo_mask: first_instr.pseudo_mask.clone(),
         pseudo_arg0: first_instr.pseudo_arg0.clone(),
         pseudo_blob: first_instr.pseudo_blob.clone(),
+        pseudo_pop: first_instr.pseudo_pop.clone(),
+        pseudo_arg_count: first_instr.pseudo_arg_count,
         plain_args: compressed_plain_args,
     })
 }
@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap};
 use std::borrow::Cow;
 
+use crate::ast;
 use crate::resolve::IdMap;
 use crate::pos::{Sp, FileId};
 use crate::game::{Game, LanguageKey};
@@ -13,18 +14,27 @@ use crate::error::{ErrorReported, GatherErrorIteratorExt};
 #[derive(Debug)]
 pub struct Mapfile {
     pub language: LanguageKey,
-    pub ins_names: Vec<(i32, Sp<Ident>)>,
+    pub ins_names: Vec<(i32, InsName)>,
     pub ins_signatures: Vec<(i32, Sp<String>)>,
     pub ins_rets: Vec<(i32, Sp<String>)>,
     pub gvar_names: Vec<(i32, Sp<Ident>)>,
     pub gvar_types: Vec<(i32, Sp<String>)>,
     pub ins_intrinsics: Vec<(i32, Sp<String>)>,
+    /// Special properties of instructions, as whitespace-separated flags.  (e.g. `disables_scratch`)
+    pub ins_flags: Vec<(i32, Sp<String>)>,
     /// For historic reasons, [`InstrLanguage::Timeline`] has dedicated sections.
     /// When these are seen in a file, they will always define things for timelines
     /// instead of [`Self::language`].
-    pub timeline_ins_names: Vec<(i32, Sp<Ident>)>,
+    pub timeline_ins_names: Vec<(i32, InsName)>,
     pub timeline_ins_signatures: Vec<(i32, Sp<String>)>,
     pub difficulty_flags: Vec<(i32, Sp<String>)>,
+    /// Overrides how the language encodes jump destinations.  Only one entry is allowed, and its key
+    /// is ignored.
+    pub label_encoding: Vec<(i32, Sp<String>)>,
+    /// Overrides the encoding of string arguments.  Only one entry is allowed, and its key is ignored.
+    pub string_encoding: Vec<(i32, Sp<String>)>,
+    /// Overrides [`crate::game::Limit`]s of the game.  The key is the value of the limit.
+    pub limits: Vec<(i32, Sp<String>)>,
     pub enums: IdMap<Sp<Ident>, Vec<(i32, Sp<Ident>)>>,
 
     /// Indicates that this mapfile contains builtin definitions.
@@ -32,6 +42,22 @@ pub struct Mapfile {
     /// This flag is intended for use by diagnostics to avoid displaying spans from core mapfiles
     /// (or at least to avoid giving them primary level labels, since they aren't user-actionable).
     pub is_core_mapfile: bool,
+
+    /// The file that this mapfile was parsed from, if any.  (for a gamemap, this is the map that it points to)
+    ///
+    /// Spans of definitions in this file are used to tell which mapfiles a decompiled script depends on.
+    pub source_file: FileId,
+}
+
+/// An entry of `!ins_names` or `!timeline_ins_names`.
+///
+/// An opcode may have several names, all of which can be used when compiling.  The decompiler uses the one
+/// written with a leading `*`, or else the first one.
+#[derive(Debug, Clone)]
+pub struct InsName {
+    pub ident: Sp<Ident>,
+    /// `true` if the name was marked with a `*`.
+    pub canonical: bool,
 }
 
 impl Mapfile {
@@ -46,9 +72,14 @@ impl Mapfile {
             timeline_ins_names: Default::default(),
             timeline_ins_signatures: Default::default(),
             difficulty_flags: Default::default(),
+            label_encoding: Default::default(),
+            string_encoding: Default::default(),
+            limits: Default::default(),
             ins_intrinsics: Default::default(),
+            ins_flags: Default::default(),
             enums: Default::default(),
             is_core_mapfile: true,
+            source_file: None,
         }
     }
 
@@ -141,6 +172,30 @@ impl Mapfile {
         mapfile_from_seqmap(seqmap, emitter)
     }
 
+    /// Gather the `#pragma reg` lines of a script file into one mapfile for each language that they name,
+    /// with entries in `!gvar_names` and `!gvar_types`.
+    pub fn from_reg_pragmas(pragmas: &[Sp<ast::RegPragma>], emitter: &impl Emitter) -> Result<Vec<Mapfile>, ErrorReported> {
+        let mut mapfiles = BTreeMap::<LanguageKey, Mapfile>::new();
+        pragmas.iter().map(|pragma| {
+            let language = LanguageKey::from_prefix_name(pragma.language.as_str()).ok_or_else(|| emitter.emit(error!(
+                message("unknown language '{}'", pragma.language),
+                primary(pragma.language, "not a language"),
+                note("valid languages are: {}", LanguageKey::PREFIX_NAMES.iter().map(|name| format!("'{name}'")).collect::<Vec<_>>().join(", ")),
+            )))?;
+            let mapfile = mapfiles.entry(language).or_insert_with(|| Mapfile {
+                is_core_mapfile: false,
+                source_file: pragma.span.file_id,
+                ..Mapfile::new_core_mapfile(language)
+            });
+            mapfile.gvar_names.push((pragma.reg.value, pragma.ident.clone()));
+            if let Some(sigil) = pragma.ty_sigil {
+                mapfile.gvar_types.push((pragma.reg.value, sp!(sigil.span => sigil.to_string())));
+            }
+            Ok(())
+        }).collect_with_recovery::<()>()?;
+        Ok(mapfiles.into_values().collect())
+    }
+
     /// Generate a Seqmap.  Could be useful for writing a generated file.
     pub fn to_borrowed_seqmap(&self) -> SeqmapRaw<'_> {
         borrowed_seqmap_from_mapfile(self)
@@ -158,6 +213,7 @@ const ENUM_SECT_END: &'static str = "\")";
 
 fn mapfile_from_seqmap(seqmap: SeqmapRaw<'_>, emitter: &impl Emitter) -> Result<Mapfile, ErrorReported> {
     let SeqmapRaw { magic, sections } = seqmap;
+    let source_file = magic.span.file_id;
     let GatheredSeqmaps { mut maps, enum_maps } = gather_seqmaps(sections);
 
     // NOTE: Experimental.  We have two options for deciding the language:
@@ -196,6 +252,17 @@ fn mapfile_from_seqmap(seqmap: SeqmapRaw<'_>, emitter: &impl Emitter) -> Result<
     macro_rules! pop_ident_map {
         ($name:literal) => { parse_idents($name, pop_map($name)) }
     }
+    let parse_ins_names = |section: &str, m: Vec<(i32, Sp<String>)>| -> Result<Vec<(i32, InsName)>, ErrorReported> {
+        let (m, canonical): (Vec<_>, Vec<_>) = m.into_iter().map(|(key, value)| match value.strip_prefix("*") {
+            Some(name) => ((key, sp!(value.span => name.to_string())), true),
+            None => ((key, value), false),
+        }).unzip();
+        let idents = parse_idents(section, m)?;
+        Ok(idents.into_iter().zip(canonical).map(|((key, ident), canonical)| (key, InsName { ident, canonical })).collect())
+    };
+    macro_rules! pop_ins_name_map {
+        ($name:literal) => { parse_ins_names($name, pop_map($name)) }
+    }
 
     let enums = enum_maps.into_iter().map(|(enum_name, data)| {
         let enum_ident = Ident::new_user(&enum_name).map_err(|e| {
@@ -210,17 +277,22 @@ fn mapfile_from_seqmap(seqmap: SeqmapRaw<'_>, emitter: &impl Emitter) -> Result<
 
     let out = Mapfile {
         language,
-        ins_names: pop_ident_map!("ins_names")?,
+        ins_names: pop_ins_name_map!("ins_names")?,
         ins_signatures: pop_map("ins_signatures"),
         ins_rets: pop_map("ins_rets"),
         gvar_names: pop_ident_map!("gvar_names")?,
         gvar_types: pop_map("gvar_types"),
-        timeline_ins_names: pop_ident_map!("timeline_ins_names")?,
+        timeline_ins_names: pop_ins_name_map!("timeline_ins_names")?,
         timeline_ins_signatures: pop_map("timeline_ins_signatures"),
         ins_intrinsics: pop_map("ins_intrinsics"),
+        ins_flags: pop_map("ins_flags"),
         difficulty_flags: pop_map("difficulty_flags"),
+        label_encoding: pop_map("label_encoding"),
+        string_encoding: pop_map("string_encoding"),
+        limits: pop_map("limits"),
         enums,
         is_core_mapfile: false,
+        source_file,
     };
     for (key, _) in maps {
         emitter.emit(warning!(
@@ -276,8 +348,8 @@ fn mapify_section(header: &str, section: Vec<(i32, Sp<String>)>, emitter: &impl
 fn borrowed_seqmap_from_mapfile(mapfile: &Mapfile) -> SeqmapRaw<'_> {
     let Mapfile {
         language, ins_names, ins_signatures, ins_rets, gvar_names, gvar_types, enums,
-        timeline_ins_names, timeline_ins_signatures, difficulty_flags, ins_intrinsics,
-        is_core_mapfile: _,
+        timeline_ins_names, timeline_ins_signatures, difficulty_flags, ins_intrinsics, ins_flags, label_encoding,
+        string_encoding, limits, is_core_mapfile: _, source_file: _,
     } = mapfile;
 
     let magic = match language {
@@ -295,6 +367,18 @@ fn borrowed_seqmap_from_mapfile(mapfile: &Mapfile) -> SeqmapRaw<'_> {
             lines: section.iter().map(|&(num, ref ident)| (sp!(num), sp!(ident.span => Cow::Borrowed(ident.as_str())))).collect()
         }
     }
+    fn ins_name_section<'a>(header: &'static str, section: &'a [(i32, InsName)]) -> SeqmapRawSection<'a> {
+        SeqmapRawSection {
+            header: sp!(Cow::Borrowed(header)),
+            lines: section.iter().map(|&(num, InsName { ref ident, canonical })| {
+                let name = match canonical {
+                    true => Cow::Owned(format!("*{ident}")),
+                    false => Cow::Borrowed(ident.as_str()),
+                };
+                (sp!(num), sp!(ident.span => name))
+            }).collect()
+        }
+    }
     fn string_section<'a>(header: &'static str, section: &'a [(i32, Sp<String>)]) -> SeqmapRawSection<'a> {
         SeqmapRawSection {
             header: sp!(Cow::Borrowed(header)),
@@ -309,15 +393,19 @@ fn borrowed_seqmap_from_mapfile(mapfile: &Mapfile) -> SeqmapRaw<'_> {
     SeqmapRaw {
         magic: sp!(Cow::Borrowed(magic)),
         sections: vec![
-            ident_section("ins_names", ins_names),
+            ins_name_section("ins_names", ins_names),
             string_section("ins_signatures", ins_signatures),
             string_section("ins_rets", ins_rets),
             ident_section("gvar_names", gvar_names),
             string_section("gvar_types", gvar_types),
             string_section("ins_intrinsics", ins_intrinsics),
-            ident_section("timeline_ins_names", timeline_ins_names),
+            string_section("ins_flags", ins_flags),
+            ins_name_section("timeline_ins_names", timeline_ins_names),
             string_section("timeline_ins_signatures", timeline_ins_signatures),
             string_section("difficulty_flags", difficulty_flags),
+            string_section("label_encoding", label_encoding),
+            string_section("string_encoding", string_encoding),
+            string_section("limits", limits),
         ],
     }
 }
@@ -0,0 +1,159 @@
+//! Queries relating names to their definitions, for use by tools such as editors.
+//!
+//! See [`Truth::index_names`][crate::Truth::index_names].
+
+use crate::ast::{self, Visit};
+use crate::context::CompilerContext;
+use crate::game::LanguageKey;
+use crate::ident::{Ident, ResIdent};
+use crate::pos::{BytePos, FileId, Sp, Span};
+use crate::raw;
+use crate::resolve::{DefId, Namespace, RegId};
+
+/// An occurrence of a name in a script file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameUse {
+    /// Span of the identifier.  (this excludes the type sigil of a variable)
+    pub span: Span,
+    pub ident: ResIdent,
+    pub ns: Namespace,
+    /// `None` if name resolution failed for this name.
+    pub def_id: Option<DefId>,
+    /// `true` if this is the name at the definition site (e.g. in a variable declaration).
+    pub is_definition: bool,
+}
+
+/// An index of every name in a script file, sorted by position.
+///
+/// Built by [`Truth::index_names`][crate::Truth::index_names].
+#[derive(Debug, Clone, Default)]
+pub struct NameIndex {
+    uses: Vec<NameUse>,
+}
+
+impl NameIndex {
+    /// Find the name at a position, e.g. under an editor's cursor.
+    ///
+    /// A position immediately after a name is considered to be within it.
+    pub fn name_at(&self, file_id: FileId, pos: BytePos) -> Option<&NameUse> {
+        let index = self.uses.partition_point(|name| (name.span.file_id, name.span.start) <= (file_id, pos));
+        self.uses[..index].iter().rev()
+            .take_while(|name| name.span.file_id == file_id)
+            .find(|name| pos <= name.span.end)
+    }
+
+    /// Get all occurrences of names that resolve to the given definition, including the definition
+    /// itself if it is in this file.
+    pub fn references(&self, def_id: DefId) -> impl Iterator<Item=&NameUse> + '_ {
+        self.uses.iter().filter(move |name| name.def_id == Some(def_id))
+    }
+
+    /// Iterate over all names in the file, in order of position.
+    pub fn iter(&self) -> impl Iterator<Item=&NameUse> + '_ {
+        self.uses.iter()
+    }
+}
+
+/// Information about where something was defined.  See [`crate::context::Defs::definition`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Definition {
+    pub def_id: DefId,
+    /// Span of the name at the definition site.  For aliases, this points into the mapfile.
+    ///
+    /// `None` for things built into the compiler.
+    pub span: Option<Span>,
+    pub kind: DefinitionKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefinitionKind {
+    /// A local variable or function parameter.
+    Local,
+    /// A user-defined `const` variable.
+    Const,
+    /// A `const` that is built into the compiler.
+    BuiltinConst,
+    /// A variant of an enum from a mapfile.
+    EnumConst { enum_name: Ident },
+    /// A user-defined function.  `qualifier` is `None` for exported functions.
+    Func { qualifier: Option<ast::FuncQualifier> },
+    /// A register alias from a mapfile.
+    RegisterAlias { language: LanguageKey, reg: RegId },
+    /// An instruction alias from a mapfile.
+    InstructionAlias { language: LanguageKey, opcode: raw::Opcode },
+}
+
+/// Implementation of [`Truth::index_names`][crate::Truth::index_names].
+pub(crate) fn index_names(language: LanguageKey, ast: &ast::ScriptFile, ctx: &mut CompilerContext<'_>) -> NameIndex {
+    // Fresh ResIds, so that the caller may still compile the original AST with this context.
+    //
+    // Names that fail to resolve are still indexed, just without a DefId.
+    let mut ast = ast.clone();
+    let _ = (|| {
+        crate::passes::resolution::refresh_res_ids(&mut ast, ctx)?;
+        crate::passes::resolution::assign_languages(&mut ast, language, ctx)?;
+        crate::passes::resolution::resolve_names(&ast, ctx)
+    })();
+
+    let mut visitor = IndexVisitor { ctx, uses: vec![] };
+    visitor.visit_file(&ast);
+
+    let mut uses = visitor.uses;
+    uses.sort_by_key(|name| (name.span.file_id, name.span.start));
+    NameIndex { uses }
+}
+
+struct IndexVisitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+    uses: Vec<NameUse>,
+}
+
+impl IndexVisitor<'_, '_> {
+    fn record(&mut self, span: Span, ident: &ResIdent, ns: Namespace) {
+        if ident.res.is_none() {
+            return;
+        }
+        // the span of a var includes its sigil; the ident is always written exactly as it appears
+        let span = Span::new(span.file_id, BytePos(span.end.0 - ident.as_str().len() as u32), span.end);
+        self.uses.push(NameUse {
+            span,
+            ident: ident.clone(),
+            ns,
+            def_id: self.ctx.resolutions.try_get_def(ident),
+            is_definition: self.ctx.resolutions.is_definition(ident),
+        });
+    }
+}
+
+impl Visit for IndexVisitor<'_, '_> {
+    fn visit_item(&mut self, item: &Sp<ast::Item>) {
+        if let ast::Item::Func(func) = &item.value {
+            self.record(func.ident.span, &func.ident, Namespace::Funcs);
+            for param in &func.params {
+                if let Some(ident) = &param.ident {
+                    self.record(ident.span, ident, Namespace::Vars);
+                }
+            }
+        }
+        ast::walk_item(self, item);
+    }
+
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        if let ast::Expr::EnumConst { ident, .. } = &expr.value {
+            self.record(ident.span, ident, Namespace::Vars);
+        }
+        ast::walk_expr(self, expr);
+    }
+
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let ast::VarName::Normal { ident, .. } = &var.name {
+            self.record(var.span, ident, Namespace::Vars);
+        }
+    }
+
+    fn visit_callable_name(&mut self, name: &Sp<ast::CallableName>) {
+        if let ast::CallableName::Normal { ident, .. } = &name.value {
+            self.record(name.span, ident, Namespace::Funcs);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+//! Aggregate statistics about which instructions appear in binary files.
+//!
+//! See [`Truth::opcode_stats_from_bytes`][crate::Truth::opcode_stats_from_bytes].
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::context::CompilerContext;
+use crate::game::LanguageKey;
+use crate::ident::Ident;
+use crate::llir::RawInstr;
+use crate::raw;
+
+/// How often each opcode appears in a collection of scripts.
+///
+/// Instructions are not decoded, so this works even for opcodes that have no signature.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OpcodeStats {
+    /// Sorted by language, then opcode.
+    pub opcodes: BTreeMap<(LanguageKey, raw::Opcode), OpcodeUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeUsage {
+    pub language: LanguageKey,
+    pub opcode: raw::Opcode,
+    /// Alias of the instruction from a mapfile, if there is one.
+    pub name: Option<Ident>,
+    /// Whether the loaded mapfiles provide a signature for this opcode.
+    pub has_signature: bool,
+    /// Number of instructions with this opcode.
+    pub count: usize,
+    /// For each observed size in bytes of the argument blob, the number of instructions with that size.
+    pub arg_sizes: BTreeMap<usize, usize>,
+}
+
+impl OpcodeStats {
+    pub fn new() -> Self { Self::default() }
+
+    /// Count the instructions of a script, looking up names and signatures in `ctx`.
+    pub fn add_instrs(&mut self, ctx: &CompilerContext<'_>, language: LanguageKey, instrs: &[RawInstr]) {
+        for instr in instrs {
+            let usage = self.opcodes.entry((language, instr.opcode)).or_insert_with(|| OpcodeUsage {
+                language,
+                opcode: instr.opcode,
+                name: match ctx.ins_to_ast(language, instr.opcode) {
+                    crate::ast::CallableName::Normal { ident, .. } => Some(ident.as_raw().clone()),
+                    crate::ast::CallableName::Ins { .. } => None,
+                },
+                has_signature: ctx.defs.ins_abi(language, instr.opcode).is_some(),
+                count: 0,
+                arg_sizes: BTreeMap::new(),
+            });
+            usage.count += 1;
+            *usage.arg_sizes.entry(instr.args_blob.len()).or_insert(0) += 1;
+        }
+    }
+
+    /// Add the counts from another set of statistics into this one.
+    pub fn merge(&mut self, other: &OpcodeStats) {
+        for (&key, other_usage) in &other.opcodes {
+            match self.opcodes.get_mut(&key) {
+                None => { self.opcodes.insert(key, other_usage.clone()); },
+                Some(usage) => {
+                    usage.count += other_usage.count;
+                    for (&size, &count) in &other_usage.arg_sizes {
+                        *usage.arg_sizes.entry(size).or_insert(0) += count;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Opcodes that have no signature in the loaded mapfiles, and therefore cannot be decompiled
+    /// into anything better than a blob.
+    pub fn unknown(&self) -> impl Iterator<Item=&OpcodeUsage> + '_ {
+        self.opcodes.values().filter(|usage| !usage.has_signature)
+    }
+
+    /// Opcodes that have no name in the loaded mapfiles.
+    pub fn unnamed(&self) -> impl Iterator<Item=&OpcodeUsage> + '_ {
+        self.opcodes.values().filter(|usage| usage.name.is_none())
+    }
+}
+
+// =============================================================================
+// Gathering the scripts of each format
+
+pub(crate) fn add_anm(stats: &mut OpcodeStats, ctx: &CompilerContext, file: &crate::AnmFile) {
+    for entry in &file.entries {
+        for script in entry.scripts.values() {
+            stats.add_instrs(ctx, LanguageKey::Anm, &script.instrs);
+        }
+    }
+}
+
+pub(crate) fn add_std(stats: &mut OpcodeStats, ctx: &CompilerContext, file: &crate::StdFile) {
+    stats.add_instrs(ctx, LanguageKey::Std, &file.script);
+}
+
+pub(crate) fn add_msg(stats: &mut OpcodeStats, ctx: &CompilerContext, language: LanguageKey, file: &crate::MsgFile) {
+    for instrs in file.scripts.values() {
+        stats.add_instrs(ctx, language, instrs);
+    }
+}
+
+pub(crate) fn add_ecl(stats: &mut OpcodeStats, ctx: &CompilerContext, file: &crate::EclFile) {
+    for instrs in &file.timelines {
+        stats.add_instrs(ctx, LanguageKey::Timeline, instrs);
+    }
+    for instrs in file.subs.values() {
+        stats.add_instrs(ctx, LanguageKey::Ecl, instrs);
+    }
+}
+
+// =============================================================================
+// Display
+
+/// Prints a table with one row per opcode.
+impl fmt::Display for OpcodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rows = self.opcodes.values().map(|usage| {
+            let name = match &usage.name {
+                Some(name) => name.to_string(),
+                None => format!("ins_{}", usage.opcode),
+            };
+            let signature = match usage.has_signature {
+                true => "yes",
+                false => "no",
+            };
+            let sizes = usage.arg_sizes.iter().map(|(size, count)| match usage.arg_sizes.len() {
+                1 => format!("{size}"),
+                _ => format!("{size} (x{count})"),
+            }).collect::<Vec<_>>().join(", ");
+            [usage.language.descr().to_string(), usage.opcode.to_string(), name, usage.count.to_string(), signature.to_string(), sizes]
+        }).collect::<Vec<_>>();
+
+        let header = ["language", "opcode", "name", "count", "signature", "arg sizes"];
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let write_row = |f: &mut fmt::Formatter, row: [&str; 6]| {
+            let [language, opcode, name, count, signature, sizes] = row;
+            let [language_w, opcode_w, name_w, count_w, signature_w, _] = widths;
+            let line = format!("{language:<language_w$}  {opcode:>opcode_w$}  {name:<name_w$}  {count:>count_w$}  {signature:<signature_w$}  {sizes}");
+            writeln!(f, "{}", line.trim_end())
+        };
+        write_row(f, header)?;
+        for row in &rows {
+            write_row(f, row.each_ref().map(String::as_str))?;
+        }
+        Ok(())
+    }
+}
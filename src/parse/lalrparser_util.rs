@@ -28,6 +28,15 @@ pub fn parse_u32_literal(
     ))
 }
 
+/// Get the radix an integer literal was written in, so that the formatter can preserve it.
+pub fn int_literal_radix(string: &str) -> crate::ast::IntRadix {
+    match &string[..usize::min(string.len(), 2)] {
+        "0x" | "0X" => crate::ast::IntRadix::Hex,
+        "0b" | "0B" => crate::ast::IntRadix::Bin,
+        _ => crate::ast::IntRadix::Dec,
+    }
+}
+
 /// Parse a string literal, including surrounding quotes
 pub fn parse_string_literal(
     string: &str,
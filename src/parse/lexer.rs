@@ -6,7 +6,8 @@
 //! I don't see a satisfactory alternative for how to embed the virtual token
 
 use crate::diagnostic::Diagnostic;
-use crate::pos::{FileId, BytePos, SourceStr};
+use crate::ast;
+use crate::pos::{FileId, BytePos, SourceStr, Sp, Span};
 
 define_token_enum! {
     #[derive(logos::Logos, Clone, Copy, Debug, PartialEq)]
@@ -96,6 +97,7 @@ define_token_enum! {
         #[token("pragma")] Pragma,
         #[token("mapfile")] Mapfile,
         #[token("image_source")] ImageSource,
+        #[token("reg")] RegPragma,
         #[token("offsetof")] OffsetOf,
         #[token("timeof")] TimeOf,
         #[token("sin")] Sin,
@@ -115,8 +117,8 @@ define_token_enum! {
 
         #[error]
         #[regex(r##"\s+"##, skip_comment_or_ws)] // whitespace
-        #[regex(r##"//[^\n\r]*[\n\r]*"##, skip_comment_or_ws)] // line comment
-        #[regex(r##"/\*([^*]|\**[^*/])*\*+/"##, skip_comment_or_ws)] // block comment
+        #[regex(r##"//[^\n\r]*[\n\r]*"##, skip_comment)] // line comment
+        #[regex(r##"/\*([^*]|\**[^*/])*\*+/"##, skip_comment)] // block comment
         #[regex(r##"/\*([^*]|\*+[^*/])*\*?"##)] // unclosed block comment
         #[doc(hidden)]
         /// Implementation detail. Basically, [`logos`] requires an error variant.
@@ -136,6 +138,8 @@ pub type Lexer<'input> = GenericLexer<'input, Token<'input>>;
 pub struct LexerExtras {
     /// Records whether a comment or whitespace was ever encountered.
     had_comment_or_ws: bool,
+    /// Byte ranges of all comments, relative to the beginning of the lexer's input.
+    comments: Vec<std::ops::Range<usize>>,
 }
 
 fn skip_comment_or_ws<'a>(lexer: &mut logos::Lexer<'a, Token<'a>>) -> logos::Skip {
@@ -143,6 +147,14 @@ fn skip_comment_or_ws<'a>(lexer: &mut logos::Lexer<'a, Token<'a>>) -> logos::Ski
     logos::Skip
 }
 
+fn skip_comment<'a>(lexer: &mut logos::Lexer<'a, Token<'a>>) -> logos::Skip {
+    let range = lexer.span();
+    // the line comment regex also eats the line ending
+    let text_len = lexer.slice().trim_end_matches(&['\r', '\n'][..]).len();
+    lexer.extras.comments.push(range.start..range.start + text_len);
+    skip_comment_or_ws(lexer)
+}
+
 /// truth's lexer.
 ///
 /// You should not need to use this type; the primary API for parsing code in truth
@@ -187,6 +199,32 @@ impl<'input> Lexer<'input> {
     pub fn had_comment_or_ws(&self) -> bool {
         self.imp.extras.had_comment_or_ws
     }
+
+    /// Take all of the comments that the lexer has skipped over so far.
+    pub fn take_comments(&mut self) -> Vec<Sp<ast::Comment>> {
+        let source = self.imp.source();
+        let mut out = Vec::<Sp<ast::Comment>>::new();
+        for range in std::mem::take(&mut self.imp.extras.comments) {
+            let code_before = source[..range.start].trim_end_matches(&[' ', '\t'][..]);
+            let trailing_after = match code_before.chars().next_back() {
+                None | Some('\n') | Some('\r') => None,
+                Some(_) => {
+                    let code_end = self.location_from_logos_offset(code_before.len() as u32).1;
+                    match out.last() {
+                        // if the code is another comment, this belongs to the same thing as that one
+                        Some(prev) if prev.span.end == code_end => prev.trailing_after,
+                        _ => Some(code_end),
+                    }
+                },
+            };
+            let (start, end) = (self.location_from_logos_offset(range.start as u32), self.location_from_logos_offset(range.end as u32));
+            out.push(sp!(Span::from_locs(start, end) => ast::Comment {
+                text: source[range].to_owned(),
+                trailing_after,
+            }));
+        }
+        out
+    }
 }
 
 /// The location type reported to LALRPOP.
@@ -196,7 +234,7 @@ impl<'input> Lexer<'input> {
 /// as reasonably possible.
 pub type Location = (FileId, BytePos);
 
-impl<'a, Tok: logos::Logos<'a> + PartialEq> Iterator for GenericLexer<'a, Tok> {
+impl<'a, Tok: logos::Logos<'a, Source=str> + PartialEq> Iterator for GenericLexer<'a, Tok> {
     type Item = Result<(Location, Tok, Location), Diagnostic>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -205,10 +243,7 @@ impl<'a, Tok: logos::Logos<'a> + PartialEq> Iterator for GenericLexer<'a, Tok> {
             let start = self.location_from_logos_offset(range.start as _);
             let end = self.location_from_logos_offset(range.end as _);
             if token == Tok::ERROR {
-                Err(error!(
-                    message("invalid token"),
-                    primary(crate::pos::Span::from_locs(start, end), "invalid token"),
-                ))
+                Err(self.invalid_token_error(range))
             } else {
                 Ok((start, token, end))
             }
@@ -216,6 +251,38 @@ impl<'a, Tok: logos::Logos<'a> + PartialEq> Iterator for GenericLexer<'a, Tok> {
     }
 }
 
+impl<'a, Tok: logos::Logos<'a, Source=str>> GenericLexer<'a, Tok> {
+    fn invalid_token_error(&self, range: std::ops::Range<usize>) -> Diagnostic {
+        let span_of = |start: usize, end: usize| Span::from_locs(
+            self.location_from_logos_offset(start as _),
+            self.location_from_logos_offset(end as _),
+        );
+
+        // An unclosed string or block comment extends as far as the lexer was willing to look for the end,
+        // which is a terrible place to point an error.  Point at the opening delimiter instead.
+        let text = &self.imp.source()[range.clone()];
+        let (what, delimiter_len) = if text.starts_with("/*") {
+            ("block comment", 2)
+        } else if text.starts_with('"') && text.len() > 1 {
+            ("string literal", 1)
+        } else {
+            return error!(
+                message("invalid token"),
+                primary(span_of(range.start, range.end), "invalid token"),
+            );
+        };
+        let gave_up_label = match range.end == self.imp.source().len() {
+            true => "reached the end of the input while looking for the end",
+            false => "gave up looking for the end here",
+        };
+        error!(
+            message("unterminated {}", what),
+            primary(span_of(range.start, range.start + delimiter_len), "{} opened here is never closed", what),
+            secondary(span_of(range.end, range.end), "{}", gave_up_label),
+        )
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -268,6 +335,19 @@ mod tests {
         );
     }
 
+    #[test]
+    fn nested_comment() {
+        // block comments do not nest; the first `*/` ends the comment
+        let p = BytePos;
+        assert_eq!(
+            tokenize("/* /* */ 1 */"), vec![
+                (p(9), Token::LitInt("1".as_ref()), p(10)),
+                (p(11), Token::Star, p(12)),
+                (p(12), Token::Slash, p(13)),
+            ],
+        );
+    }
+
     #[test]
     fn ins() {
         let p = BytePos;
@@ -279,4 +359,22 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn comments() {
+        let p = BytePos;
+        let source = "// a\r\nx; /* b */ // c\n  /* d\n */ y";
+        let mut lexer = Lexer::new(SourceStr::new_null(source));
+        assert_eq!(lexer.by_ref().count(), 3);
+
+        let comments = lexer.take_comments().into_iter()
+            .map(|sp| (sp.span.start, sp.value.text, sp.value.trailing_after))
+            .collect::<Vec<_>>();
+        assert_eq!(comments, vec![
+            (p(0), "// a".to_owned(), None),
+            (p(9), "/* b */".to_owned(), Some(p(8))),
+            (p(17), "// c".to_owned(), Some(p(8))),
+            (p(24), "/* d\n */".to_owned(), None),
+        ]);
+    }
 }
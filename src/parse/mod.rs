@@ -50,13 +50,35 @@ pub type Error<'input> = GenericError<'input, Token<'input>>;
 pub struct State {
     mapfiles: Vec<Sp<ast::LitString>>,
     image_sources: Vec<Sp<ast::LitString>>,
+    reg_pragmas: Vec<Sp<ast::RegPragma>>,
+    /// Syntax errors that the parser skipped past in order to look for more errors.
+    recovered_errors: Vec<Diagnostic>,
+    /// Maximum depth of nested brackets, blocks and expressions.
+    pub max_nesting_depth: u32,
 }
 
 impl State {
     pub fn new() -> State { State {
         mapfiles: vec![],
         image_sources: vec![],
+        reg_pragmas: vec![],
+        recovered_errors: vec![],
+        max_nesting_depth: crate::passes::validate_nesting::DEFAULT_MAX_NESTING_DEPTH,
     }}
+
+    /// Take all syntax errors after the first.
+    ///
+    /// When the parser recovers from a syntax error, parsing still fails, and the error returned is only
+    /// the first one.  The rest can be retrieved here.
+    pub fn take_recovered_errors(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.recovered_errors)
+    }
+
+    fn recover_from_error<Tok: Display>(&mut self, recovery: lalrpop_util::ErrorRecovery<lexer::Location, Tok, Diagnostic>) {
+        use crate::diagnostic::IntoDiagnostics;
+
+        self.recovered_errors.extend(recovery.error.into_diagnostics());
+    }
 }
 
 impl<Tok: Display> crate::diagnostic::IntoDiagnostics for GenericError<'_, Tok> {
@@ -144,12 +166,75 @@ fn call_anything_parser<'input>(
 ) -> Result<Sp<AnythingValue>, Error<'input>> {
     let start = lexer.location();
     let lexer = std::iter::once(Ok((start, Token::VirtualDispatch(tag), start))).chain(lexer);
-    lalrparser::AnythingParser::new().parse(state, lexer)
+    let lexer = limit_bracket_depth(lexer, state.max_nesting_depth);
+    let result = lalrparser::AnythingParser::new().parse(state, lexer);
+
+    // a parse that had to recover from errors must still fail, as the AST is missing whatever was skipped.
+    // The first error is returned, and the rest are kept in the state.
+    if state.recovered_errors.is_empty() {
+        return result;
+    }
+    if let Err(error) = result {
+        use crate::diagnostic::IntoDiagnostics;
+        state.recovered_errors.extend(error.into_diagnostics());
+    }
+    Err(lalrpop_util::ParseError::User { error: state.recovered_errors.remove(0) })
 }
 
 
+/// Fail at the first bracket that is nested too deeply, before the parser has a chance to build anything deep.
+fn limit_bracket_depth<'input>(
+    lexer: impl Iterator<Item=Result<(lexer::Location, Token<'input>, lexer::Location), Diagnostic>>,
+    max_depth: u32,
+) -> impl Iterator<Item=Result<(lexer::Location, Token<'input>, lexer::Location), Diagnostic>> {
+    let mut depth = 0;
+    lexer.map(move |item| {
+        match item {
+            Ok((start, Token::ParenOpen | Token::BracketOpen | Token::BraceOpen, end)) => {
+                depth += 1;
+                if depth > max_depth {
+                    let span = Span::from_locs(start, end);
+                    return Err(crate::passes::validate_nesting::nesting_error(span, max_depth));
+                }
+            },
+            Ok((_, Token::ParenClose | Token::BracketClose | Token::BraceClose, _)) => {
+                depth = u32::saturating_sub(depth, 1);
+            },
+            _ => {},
+        }
+        item
+    })
+}
+
+/// Check a successfully parsed AST for expressions that are nested too deeply.  (e.g. a sum of thousands of terms)
+fn check_nesting_depth<'input, A>(state: &State, ast: Sp<A>) -> Result<Sp<A>, Error<'input>>
+where
+    Sp<A>: ast::Visitable,
+{
+    match crate::passes::validate_nesting::run(&ast, state.max_nesting_depth) {
+        Ok(()) => Ok(ast),
+        Err(error) => {
+            // dropping the AST normally would recurse just as deeply
+            crate::passes::validate_nesting::drop_deep(ast);
+            Err(lalrpop_util::ParseError::User { error })
+        },
+    }
+}
+
 macro_rules! impl_parse {
     ($AstType:ty, $TagName:ident) => {
+        impl Parse for $AstType {
+            fn parse_stream<'input>(state: &mut State, lexer: &mut Lexer<'input>) -> Result<Sp<Self>, Error<'input>> {
+                let sp = call_anything_parser(AnythingTag::$TagName, state, lexer)?;
+                check_nesting_depth(state, sp!(sp.span => match sp.value {
+                    AnythingValue::$TagName(x) => x,
+                    _ => unreachable!(),
+                }))
+            }
+        }
+    };
+    // for things that can't contain expressions
+    ($AstType:ty, $TagName:ident, shallow) => {
         impl Parse for $AstType {
             fn parse_stream<'input>(state: &mut State, lexer: &mut Lexer<'input>) -> Result<Sp<Self>, Error<'input>> {
                 let sp = call_anything_parser(AnythingTag::$TagName, state, lexer)?;
@@ -159,15 +244,27 @@ macro_rules! impl_parse {
                 }))
             }
         }
+    };
+}
+
+impl Parse for ast::ScriptFile {
+    fn parse_stream<'input>(state: &mut State, lexer: &mut Lexer<'input>) -> Result<Sp<Self>, Error<'input>> {
+        let sp = call_anything_parser(AnythingTag::ScriptFile, state, lexer)?;
+        let mut script = match sp.value {
+            AnythingValue::ScriptFile(x) => x,
+            _ => unreachable!(),
+        };
+        // only whole files keep their comments, as only they have somewhere to put them
+        script.comments = lexer.take_comments();
+        check_nesting_depth(state, sp!(sp.span => script))
     }
 }
 
-impl_parse!(ast::ScriptFile, ScriptFile);
 impl_parse!(ast::Item, Item);
 impl_parse!(ast::Block, Block);
 impl_parse!(ast::Stmt, Stmt);
 impl_parse!(ast::Expr, Expr);
-impl_parse!(ast::Var, Var);
-impl_parse!(ast::LitString, LitString);
+impl_parse!(ast::Var, Var, shallow);
+impl_parse!(ast::LitString, LitString, shallow);
 impl_parse!(ast::Meta, Meta);
-impl_parse!(Ident, Ident);
+impl_parse!(Ident, Ident, shallow);
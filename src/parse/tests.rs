@@ -67,7 +67,7 @@ fn parse_color() {
     // which often show up in colors.
     assert_eq!(
         simplify_expr(parse::<ast::Expr>("0xff000000").unwrap()).unwrap(),
-        ast::Expr::from(0xff000000_u32 as i32),
+        ast::Expr::LitInt { value: 0xff000000_u32 as i32, radix: ast::IntRadix::Hex },
     );
 }
 
@@ -97,7 +97,7 @@ fn var_parse() {
     use crate::resolve::RegId;
 
     fn reg(reg: i32) -> ast::VarName {
-        ast::VarName::Reg { reg: RegId(reg), language: None }
+        ast::VarName::Reg { reg: RegId(reg), language: None, language_prefix: None }
     }
 
     assert_eq!(parse::<Var>("$REG[244]").unwrap(), Var { ty_sigil: Some(VarSigil::Int), name: reg(244) });
@@ -105,11 +105,30 @@ fn var_parse() {
     assert_eq!(parse::<Var>("REG[244]").unwrap(), Var { ty_sigil: None, name: reg(244) });
     assert_eq!(parse::<Var>("%REG[-99998]").unwrap(), Var { ty_sigil: Some(VarSigil::Float), name: reg(-99998) });
     assert!(parse::<Var>("REG[-99998999999]").is_err());
+    assert!(matches!(
+        parse::<Var>("$timeline.REG[244]").unwrap().name,
+        ast::VarName::Reg { reg: RegId(244), language_prefix: Some(ref prefix), .. } if prefix == "timeline",
+    ));
     assert!(matches!(parse::<Var>("lmao").unwrap(), Var { ty_sigil: None, .. }));
     assert!(matches!(parse::<Var>("$lmao").unwrap(), Var { ty_sigil: Some(VarSigil::Int), .. }));
     assert!(matches!(parse::<Var>("%lmao").unwrap(), Var { ty_sigil: Some(VarSigil::Float), .. }));
 }
 
+#[test]
+fn recovered_errors() {
+    let mut lexer = super::Lexer::new(crate::pos::SourceStr::new_null("{ a = ; b = 1 1; c = 2; }"));
+    let mut state = super::State::new();
+    let result = <ast::Block as Parse>::parse_stream(&mut state, &mut lexer);
+    // the first error is returned, and the rest are kept
+    assert!(matches!(result, Err(lalrpop_util::ParseError::User { .. })));
+    assert_eq!(state.take_recovered_errors().len(), 1);
+
+    let mut lexer = super::Lexer::new(crate::pos::SourceStr::new_null("{ a = 1; }"));
+    let mut state = super::State::new();
+    assert!(<ast::Block as Parse>::parse_stream(&mut state, &mut lexer).is_ok());
+    assert!(state.take_recovered_errors().is_empty());
+}
+
 #[test]
 fn string_escape() {
     use ast::LitString;
@@ -148,12 +167,13 @@ parse_error_snapshot_test!(unexpected_token, expect("unexpected"), <ast::Stmt> "
 parse_error_snapshot_test!(unexpected_eof, expect("EOF"), <ast::Stmt> "int x = 3");
 parse_error_snapshot_test!(big_reg, expect("too large"), <ast::Stmt> "float x = %REG[1234258905623];");
 parse_error_snapshot_test!(bad_escape, expect("escape"), <ast::Expr> r#" "abc\jefg" "#);
-parse_error_snapshot_test!(bad_escape_end, expect("token"), <ast::Expr> r#" "abcefg\"#);
+parse_error_snapshot_test!(bad_escape_end, expect("unterminated string"), <ast::Expr> r#" "abcefg\"#);
 parse_error_snapshot_test!(bad_ins_identifier, expect("instruction"), <ast::Expr> r#" ins_04() "#);
 parse_error_snapshot_test!(bad_ins_identifier_2, expect("instruction"), <ast::Expr> r#" ins_a() "#);
 parse_error_snapshot_test!(bad_ins_empty, expect("instruction"), <ast::Expr> r#" ins_() "#);
 parse_error_snapshot_test!(bad_ins_overflow, expect("instruction"), <ast::Expr> r#" ins_99999999999999() "#);
-parse_error_snapshot_test!(unclosed_comment, expect("token"), <ast::ScriptFile> r#" /* comment "#);
+parse_error_snapshot_test!(unclosed_comment, expect("unterminated block comment"), <ast::ScriptFile> r#" /* comment "#);
+parse_error_snapshot_test!(unclosed_string, expect("unterminated string"), <ast::ScriptFile> "script main {\n    ins_1(\"abc);\n}\n");
 parse_error_snapshot_test!(duplicate_meta_key, expect("duplicate"), <ast::Meta> r#"{
   a: {
     thing: 100,
@@ -65,7 +65,7 @@ impl ast::UnOpKind {
                 token![unop sin] => Some(ScalarValue::Float(x.sin())),
                 token![unop cos] => Some(ScalarValue::Float(x.cos())),
                 token![unop sqrt] => Some(ScalarValue::Float(x.sqrt())),
-                token![unop int] => Some(ScalarValue::Int(x as i32)),
+                token![unop int] => Some(ScalarValue::Int(crate::value::f32_to_i32_truncating(x))),
                 token![unop float] => Some(ScalarValue::Float(x)),
                 token![unop $] |
                 token![unop %] => None,
@@ -189,6 +189,19 @@ struct Visitor<'a, 'ctx> {
 }
 
 impl ast::VisitMut for Visitor<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &mut Sp<ast::Stmt>) {
+        ast::walk_stmt_mut(self, stmt);
+
+        if let ast::StmtKind::InterruptLabel(id) = &stmt.kind {
+            if id.as_const_int().is_none() {
+                self.errors.set(self.ctx.emitter.emit(error!(
+                    message("interrupt label is not a compile-time constant"),
+                    primary(id, "not a compile-time constant"),
+                )));
+            }
+        }
+    }
+
     // !!! IMPORTANT !!!
     // This function must be updated in sync with the DFS const evaluator.
     // (it did not seem possible to factor the shared logic out...)
@@ -240,6 +253,13 @@ impl ast::VisitMut for Visitor<'_, '_> {
             ast::Expr::Call(call) => {
                 // FIXME is this the right place to do this?
                 validate_call_const_args(call, self.ctx).unwrap_or_else(|e| self.errors.set(e));
+                validate_call_const_pseudos(call, self.ctx).unwrap_or_else(|e| self.errors.set(e));
+
+                match simplify_const_func_call(e.span, call, self.ctx) {
+                    Ok(Some(value)) => e.value = value.into(),
+                    Ok(None) => {},
+                    Err(e) => self.errors.set(e),
+                }
             },
 
             _ => return, // can't simplify other expressions
@@ -247,6 +267,35 @@ impl ast::VisitMut for Visitor<'_, '_> {
     }
 }
 
+/// Evaluate a call to a `const` function if all of its arguments are constant.
+fn simplify_const_func_call(call_span: crate::pos::Span, call: &ast::ExprCall, ctx: &CompilerContext<'_>) -> Result<Option<ScalarValue>, ErrorReported> {
+    let def_id = match &call.name.value {
+        ast::CallableName::Normal { ident, .. } => ctx.resolutions.expect_def(ident),
+        ast::CallableName::Ins { .. } => return Ok(None),
+    };
+    if ctx.defs.const_func_def(def_id).is_none() || !call.pseudos.is_empty() {
+        return Ok(None);
+    }
+    let arg_values = match call.args.iter().map(|arg| arg.to_const()).collect::<Option<Vec<_>>>() {
+        Some(values) => values,
+        None => return Ok(None),
+    };
+    ctx.consts.eval_const_func_call(call_span, def_id, arg_values, &ctx.defs, &ctx.resolutions, ctx.emitter)
+}
+
+fn validate_call_const_pseudos(call: &ast::ExprCall, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
+    call.pseudos.iter().map(|pseudo| {
+        let value = &pseudo.value.value;
+        if value.to_const().is_none() {
+            return Err(ctx.emitter.emit(error!(
+                message("pseudo-arg must be a compile-time constant"),
+                primary(value, "not constant"),
+            )));
+        }
+        Ok(())
+    }).collect_with_recovery()
+}
+
 fn validate_call_const_args(call: &ast::ExprCall, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
     use crate::context::defs::{MatchedArgs, ConstArgReason};
 
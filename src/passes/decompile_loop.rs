@@ -207,6 +207,9 @@ fn _gather_cond_chain(start: usize, context: &BlockContext) -> Result<CondChainI
             return Err(NoCondChain);
         }
         let (if_keyword, if_binop_expr) = if_jmp.kind.as_binop_cond().ok_or(NoCondChain)?;  // screw predecrement
+        if matches!(if_binop_expr.value.0.value, ast::Expr::XcrementOp { .. }) {
+            return Err(NoCondChain);  // '--x > 0' can't be negated into something we can compile
+        }
 
         chain.push({
             // 'if (<expr>) goto skip' becomes 'if (!<expr>) { <block> }'
@@ -565,7 +568,8 @@ impl JmpInfo {
 
             ast::StmtKind::CondJump { keyword, ref cond, ref jump } => match keyword.value {
                 ast::CondKeyword::If => (jump, JmpKind::Cond { keyword, cond: cond.clone() }),
-                ast::CondKeyword::Unless => unimplemented!(),  // not present in decompiled code
+                // only produced from jumps over jumps, which are left alone
+                ast::CondKeyword::Unless => return None,
             }
 
             _ => return None,
@@ -614,7 +618,7 @@ impl JmpKind {
         match *self {
             JmpKind::Uncond => ast::StmtKind::Loop { loop_id: Some(loop_id), block, keyword: sp!(()) },
 
-            JmpKind::Cond { keyword: sp_pat![token![unless]], .. } => unimplemented!(),  // not present in decompiled code
+            JmpKind::Cond { keyword: sp_pat![token![unless]], .. } => unreachable!(),  // never constructed
             JmpKind::Cond { keyword: sp_pat![kw_span => token![if]], ref cond } => ast::StmtKind::While {
                 loop_id: Some(loop_id),
                 do_keyword: Some(sp!(kw_span => ())),
@@ -223,10 +223,15 @@ struct Desugarer<'a, 'ctx> {
 impl Desugarer<'_, '_> {
     pub fn desugar_block(&mut self, outer_diff_label: Option<&Sp<ast::DiffLabel>>, mut outer_block: ast::Block) {
         for mut outer_stmt in outer_block.0.drain(..) {
-            let diff_label = {
-                outer_stmt.value.diff_label.as_ref().or(outer_diff_label)
-                    .filter(|label| label.mask != Some(DEFAULT_DIFFICULTY_MASK))
+            // an inner label can only further restrict the outer one
+            let combined_diff_label = match (outer_stmt.value.diff_label.as_ref(), outer_diff_label) {
+                (Some(inner), Some(outer)) => Some(sp!(inner.span => ast::DiffLabel {
+                    mask: inner.mask.zip(outer.mask).map(|(inner, outer)| inner & outer),
+                    expr: inner.expr.clone(),
+                })),
+                (inner, outer) => inner.or(outer).cloned(),
             };
+            let diff_label = combined_diff_label.as_ref().filter(|label| label.mask != Some(DEFAULT_DIFFICULTY_MASK));
             match outer_stmt.value.kind {
                 ast::StmtKind::Block(block) => {
                     self.desugar_block(diff_label, block)
@@ -322,9 +327,8 @@ impl Desugarer<'_, '_> {
         inner: impl FnOnce(&mut Self),
     ) {
         let skip_label = self.ctx.gensym.gensym("@cond#");
-        self.out.push(rec_sp!(condjmp_span =>
-            stmt_cond_goto!(#(keyword.negate()) #cond goto #(skip_label.clone()))
-        ));
+        let negated_keyword = sp!(keyword.span => keyword.negate());
+        self.make_goto(diff_label, condjmp_span, Some((negated_keyword, cond)), skip_label.clone());
 
         inner(self);
 
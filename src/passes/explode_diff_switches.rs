@@ -0,0 +1,161 @@
+//! See [`run`].
+
+use crate::error::{ErrorReported, ErrorFlag};
+use crate::ast::{self, Visit, VisitMut};
+use crate::context::CompilerContext;
+use crate::diff_switch_utils as ds_util;
+use crate::passes::semantics::time_and_difficulty::DEFAULT_DIFFICULTY_MASK;
+use crate::pos::Sp;
+
+/// Duplicates statements containing difficulty switches that can't be encoded as instruction arguments.
+///
+/// A switch that is an entire argument of a call, or the entire right hand side of an assignment, can be
+/// compiled by giving each difficulty its own copy of the instruction.  Anything else, such as
+/// `pos(10.0 + (1.0:2.0:3.0:4.0), 0.0)` or a condition like `I0 == (1:2:3:4)`, would otherwise need a
+/// temporary to be computed at runtime.  Instead, the whole statement is copied once for each explicit case,
+/// with every switch in it replaced by that case, and with a difficulty label for the difficulties of that case
+/// intersected with the statement's own label.  (so that e.g. `10.0 + 2.0` can then be simplified to a constant)
+///
+/// This only applies to calls, assignments and conditional jumps.  It is an error if no case of the switch
+/// applies to any difficulty of the statement's label.
+///
+/// This must be run after [type checking](crate::passes::type_check) and before
+/// [const simplification](crate::passes::const_simplify).
+pub fn run<V: ast::Visitable>(ast: &mut V, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut visitor = Visitor { ctx, errors: ErrorFlag::new() };
+    ast.visit_mut_with(&mut visitor);
+    visitor.errors.into_result(())
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+    errors: ErrorFlag,
+}
+
+impl VisitMut for Visitor<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &mut Sp<ast::Stmt>) {
+        ast::walk_stmt_mut(self, stmt);
+
+        if needs_explosion(&stmt.kind) {
+            match self.explode(stmt) {
+                Ok(kind) => {
+                    stmt.kind = kind;
+                    stmt.diff_label = None;
+                },
+                Err(e) => self.errors.set(e),
+            }
+        }
+    }
+}
+
+impl Visitor<'_, '_> {
+    fn explode(&mut self, stmt: &Sp<ast::Stmt>) -> Result<ast::StmtKind, ErrorReported> {
+        let mut switch_props = ds_util::DiffSwitchMeta::new();
+        let mut switch_span = None;
+        for_each_switch(&stmt.kind, |switch, cases| {
+            switch_props.update(cases);
+            switch_span.get_or_insert(switch.span);
+        });
+
+        let stmt_mask = match &stmt.diff_label {
+            Some(label) => label.mask.expect("compute_diff_label_masks pass was not run!"),
+            None => DEFAULT_DIFFICULTY_MASK,
+        };
+        let stmt_diff_mask = stmt_mask & self.ctx.diff_flag_defs.difficulty_bits();
+        let stmt_aux_mask = stmt_mask & self.ctx.diff_flag_defs.aux_bits();
+
+        let mut copies = vec![];
+        for case_diff_mask in switch_props.explicit_case_bitmasks() {
+            let new_diff_mask = stmt_diff_mask & case_diff_mask;
+            if new_diff_mask.is_empty() {
+                continue;
+            }
+            let case_first_difficulty = case_diff_mask.into_iter().next().unwrap();
+
+            let mut copy = stmt.clone();
+            SelectCaseVisitor { difficulty: case_first_difficulty }.visit_stmt(&mut copy);
+            crate::passes::resolution::refresh_node_ids(&mut copy, &self.ctx.unused_node_ids)?;
+            let label_span = stmt.diff_label.as_ref().map_or(stmt.span, |label| label.span);
+            copy.diff_label = Some(sp!(label_span => ast::DiffLabel {
+                mask: Some(new_diff_mask | stmt_aux_mask),
+                expr: None,
+            }));
+            copies.push(copy);
+        }
+
+        if copies.is_empty() {
+            let label = stmt.diff_label.as_ref().expect("an unlabeled statement has every difficulty");
+            return Err(self.ctx.emitter.emit(error!(
+                message("difficulty switch has no case for this statement's difficulties"),
+                primary(switch_span.unwrap(), "difficulty switch"),
+                secondary(label, "difficulty label"),
+            )));
+        }
+        Ok(ast::StmtKind::Block(ast::Block(copies)))
+    }
+}
+
+/// Whether a statement contains a difficulty switch that can't be compiled as an instruction argument.
+fn needs_explosion(kind: &ast::StmtKind) -> bool {
+    match kind {
+        ast::StmtKind::Expr(sp_pat![ast::Expr::Call(call)]) => {
+            call.args.iter().any(|arg| !is_direct_switch_or_no_switch(arg))
+                || call.pseudos.iter().any(|pseudo| contains_switch(&pseudo.value.value))
+        },
+        ast::StmtKind::Expr(expr) => contains_switch(expr),
+        ast::StmtKind::Assignment { value, .. } => !is_direct_switch_or_no_switch(value),
+        ast::StmtKind::CondJump { cond, .. } => contains_switch(cond),
+        _ => false,
+    }
+}
+
+fn is_direct_switch_or_no_switch(expr: &Sp<ast::Expr>) -> bool {
+    match &expr.value {
+        // the lowerer can compute complicated cases into a temporary for each difficulty
+        ast::Expr::DiffSwitch(cases) => cases.iter().flatten().all(|case| !contains_switch(case)),
+        _ => !contains_switch(expr),
+    }
+}
+
+fn contains_switch(expr: &Sp<ast::Expr>) -> bool {
+    let mut found = false;
+    SwitchVisitor(|_: &_, _: &_| found = true).visit_expr(expr);
+    found
+}
+
+fn for_each_switch(kind: &ast::StmtKind, func: impl FnMut(&Sp<ast::Expr>, &[Option<Sp<ast::Expr>>])) {
+    let mut visitor = SwitchVisitor(func);
+    match kind {
+        ast::StmtKind::Expr(expr) => visitor.visit_expr(expr),
+        ast::StmtKind::Assignment { value, .. } => visitor.visit_expr(value),
+        ast::StmtKind::CondJump { cond, .. } => visitor.visit_expr(cond),
+        _ => unreachable!(),
+    }
+}
+
+struct SwitchVisitor<F>(F);
+
+impl<F: FnMut(&Sp<ast::Expr>, &[Option<Sp<ast::Expr>>])> Visit for SwitchVisitor<F> {
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        if let ast::Expr::DiffSwitch(cases) = &expr.value {
+            (self.0)(expr, cases);
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+/// Replaces every difficulty switch with its case for a single difficulty.
+struct SelectCaseVisitor {
+    difficulty: u32,
+}
+
+impl VisitMut for SelectCaseVisitor {
+    fn visit_expr(&mut self, expr: &mut Sp<ast::Expr>) {
+        if let ast::Expr::DiffSwitch(cases) = &expr.value {
+            *expr = ds_util::select_diff_switch_case(cases, self.difficulty).clone();
+            // the case might itself contain switches
+            return self.visit_expr(expr);
+        }
+        ast::walk_expr_mut(self, expr);
+    }
+}
@@ -0,0 +1,74 @@
+//! See [`run`].
+
+use crate::error::ErrorReported;
+use crate::ast::{self, Visit};
+use crate::context::CompilerContext;
+use crate::diagnostic::Diagnostic;
+use crate::pos::Sp;
+use crate::value::ScalarType;
+
+/// Which float comparisons are warned about by [`run`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum WarnFloatEquality {
+    /// No warnings.
+    #[default]
+    Never,
+    /// Warn about every `==` and `!=` on floats, except for comparisons against a literal `0.0`.
+    ExceptZero,
+    /// Warn about every `==` and `!=` on floats.
+    Always,
+}
+
+impl std::str::FromStr for WarnFloatEquality {
+    type Err = Diagnostic;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(WarnFloatEquality::Never),
+            "nonzero" => Ok(WarnFloatEquality::ExceptZero),
+            "all" => Ok(WarnFloatEquality::Always),
+            _ => Err(error!("invalid float equality warning setting: {} (expected never, nonzero or all)", s)),
+        }
+    }
+}
+
+/// Warns about `==` and `!=` comparisons between floats, as selected by [`CompilerContext::warn_float_equality`].
+///
+/// Floats produced by interpolation or accumulated over time are almost never exactly equal to a
+/// given value, so a condition like `if (%x == 1.0)` may never be true in-game.
+///
+/// This only produces warnings; it must be run after [type checking](crate::passes::type_check).
+pub fn run<V: ast::Visitable>(ast: &V, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
+    if ctx.warn_float_equality == WarnFloatEquality::Never {
+        return Ok(());
+    }
+    let mut visitor = Visitor { ctx };
+    ast.visit_with(&mut visitor);
+    Ok(())
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+}
+
+impl Visit for Visitor<'_, '_> {
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        if let ast::Expr::BinOp(a, op @ sp_pat![token![==] | token![!=]], b) = &expr.value {
+            if a.compute_ty(self.ctx).as_value_ty() == Some(ScalarType::Float) && !self.is_exempt(a, b) {
+                self.ctx.emitter.emit(warning!(
+                    message("comparing floats with '{}'", op),
+                    primary(op, "floats are rarely exactly equal"),
+                    note("a float that is interpolated or accumulated over time may never hit an exact value; consider checking a range instead, e.g. 'x >= 1.0'"),
+                )).ignore();
+            }
+        }
+        ast::walk_expr(self, expr);
+    }
+}
+
+impl Visitor<'_, '_> {
+    fn is_exempt(&self, a: &ast::Expr, b: &ast::Expr) -> bool {
+        let is_zero = |expr: &ast::Expr| matches!(expr, ast::Expr::LitFloat { value } if *value == 0.0);
+        self.ctx.warn_float_equality == WarnFloatEquality::ExceptZero && (is_zero(a) || is_zero(b))
+    }
+}
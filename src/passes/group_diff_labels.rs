@@ -0,0 +1,76 @@
+//! See [`run`].
+
+use crate::error::ErrorReported;
+use crate::ast::{self, VisitMut};
+use crate::context::CompilerContext;
+use crate::pos::Sp;
+
+/// Wraps runs of consecutive statements with identical difficulty labels into a single labeled block.
+///
+/// E.g. `{"EN"}: foo(); {"EN"}: bar();` becomes `{"EN"}: { foo(); bar(); }`.  This is only valid because
+/// a difficulty label on a block applies to every statement inside it.
+///
+/// To use this, you must call a method whose scope is at least as large as [`VisitMut::visit_root_block`].
+pub fn run<V: ast::Visitable>(ast: &mut V, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut visitor = Visitor { ctx };
+    ast.visit_mut_with(&mut visitor);
+    Ok(())
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+}
+
+impl VisitMut for Visitor<'_, '_> {
+    fn visit_block(&mut self, outer_block: &mut ast::Block) {
+        ast::walk_block_mut(self, outer_block);
+
+        let mut new_stmts = Vec::with_capacity(outer_block.0.len());
+        let mut stmt_iter = outer_block.0.drain(..).peekable();
+        while let Some(first) = stmt_iter.next() {
+            let mut run = vec![first];
+            if can_be_grouped(&run[0]) {
+                while let Some(next) = stmt_iter.peek() {
+                    if !(can_be_grouped(next) && next.diff_label.as_ref().map(|d| d.mask) == run[0].diff_label.as_ref().map(|d| d.mask)) {
+                        break;
+                    }
+                    run.push(stmt_iter.next().unwrap());
+                }
+            }
+
+            if run.len() < 2 {
+                new_stmts.extend(run);
+                continue;
+            }
+
+            let diff_label = run[0].diff_label.clone();
+            for stmt in &mut run {
+                stmt.diff_label = None;
+            }
+
+            let span = run[0].span.merge(run.last().unwrap().span);
+            let make_bookend = |ctx: &mut CompilerContext<'_>, span| sp!(span => ast::Stmt {
+                node_id: Some(ctx.next_node_id()),
+                diff_label: None,
+                kind: ast::StmtKind::NoInstruction,
+            });
+            run.insert(0, make_bookend(self.ctx, span.start_span()));
+            run.push(make_bookend(self.ctx, span.end_span()));
+
+            new_stmts.push(sp!(span => ast::Stmt {
+                node_id: Some(self.ctx.next_node_id()),
+                diff_label,
+                kind: ast::StmtKind::Block(ast::Block(run)),
+            }));
+        }
+        drop(stmt_iter);
+
+        outer_block.0 = new_stmts;
+    }
+}
+
+fn can_be_grouped(stmt: &Sp<ast::Stmt>) -> bool {
+    let has_mask = matches!(&stmt.diff_label, Some(diff_label) if diff_label.mask.is_some());
+    // declarations would become scoped to the new block
+    has_mask && !matches!(stmt.kind, ast::StmtKind::Declaration { .. } | ast::StmtKind::Item { .. })
+}
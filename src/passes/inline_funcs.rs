@@ -0,0 +1,675 @@
+//! See [`run`].
+
+use std::collections::{HashMap, HashSet};
+
+use indexmap::IndexMap;
+
+use crate::error::{ErrorReported, ErrorFlag};
+use crate::ast::{self, Visit, VisitMut};
+use crate::context::CompilerContext;
+use crate::ident::{Ident, ResIdent};
+use crate::pos::{Sp, Span};
+use crate::resolve::DefId;
+use crate::value::VarType;
+
+/// Expands calls to `inline` functions into copies of their bodies.
+///
+/// A call in statement position is replaced with a block containing the body.  A call that produces
+/// a value may also appear in the expressions of a statement; in this case the body is placed before
+/// the statement and stores the result in a temporary, which takes the place of the call.  (this means
+/// that such calls are evaluated before the rest of the statement)  This is only possible for expressions
+/// that are evaluated exactly once before the statement runs, so e.g. a call in a `while` condition or on
+/// the right hand side of `&&` is an error.
+///
+/// An argument is substituted directly for its parameter if it is something simple like a constant and the
+/// parameter is never assigned.  Otherwise, the parameter becomes a local initialized to the argument.
+/// Each copy of the body gets fresh locals and labels, and a `return` becomes a `goto` to the end of the copy.
+///
+/// Recursive inline functions are an error.  Definitions of inline functions are removed from the AST.
+///
+/// This must be run after [type checking](crate::passes::type_check) and before
+/// [const simplification](crate::passes::const_simplify).
+pub fn run<V: ast::Visitable>(ast: &mut V, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut collector = CollectVisitor { ctx, funcs: IndexMap::new() };
+    ast.visit_with(&mut collector);
+    let funcs = collector.funcs;
+
+    check_recursion(&funcs, ctx)?;
+
+    let mut visitor = ExpandVisitor { ctx, funcs: &funcs, errors: ErrorFlag::new() };
+    ast.visit_mut_with(&mut visitor);
+    visitor.errors.into_result(())
+}
+
+struct InlineFunc {
+    func: ast::ItemFunc,
+    /// Params and locals declared in the body.
+    locals: Vec<DefId>,
+    /// Locals that are written to or read as a different type, and therefore cannot be replaced by an expression.
+    unsubstitutable: HashSet<DefId>,
+    /// Calls to user functions in the body, in source order.
+    calls: Vec<(DefId, Span)>,
+}
+
+fn is_inline_func(item: &ast::Item) -> bool {
+    matches!(item, ast::Item::Func(ast::ItemFunc { qualifier: Some(sp_pat![token![inline]]), .. }))
+}
+
+// =============================================================================
+
+struct CollectVisitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+    funcs: IndexMap<DefId, InlineFunc>,
+}
+
+impl Visit for CollectVisitor<'_, '_> {
+    fn visit_item(&mut self, item: &Sp<ast::Item>) {
+        if let ast::Item::Func(func @ ast::ItemFunc { code: Some(code), params, ident, .. }) = &item.value {
+            if is_inline_func(item) {
+                let mut analysis = AnalyzeBodyVisitor {
+                    ctx: self.ctx,
+                    locals: vec![],
+                    unsubstitutable: HashSet::new(),
+                    calls: vec![],
+                };
+                for param in params {
+                    if let Some(ident) = &param.ident {
+                        analysis.locals.push(self.ctx.resolutions.expect_def(ident));
+                    }
+                }
+                analysis.visit_block(code);
+
+                let def_id = self.ctx.resolutions.expect_def(ident);
+                self.funcs.insert(def_id, InlineFunc {
+                    func: func.clone(),
+                    locals: analysis.locals,
+                    unsubstitutable: analysis.unsubstitutable,
+                    calls: analysis.calls,
+                });
+            }
+        }
+        ast::walk_item(self, item);
+    }
+}
+
+struct AnalyzeBodyVisitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+    locals: Vec<DefId>,
+    unsubstitutable: HashSet<DefId>,
+    calls: Vec<(DefId, Span)>,
+}
+
+impl AnalyzeBodyVisitor<'_, '_> {
+    fn local_def(&self, var: &ast::Var) -> Option<DefId> {
+        match &var.name {
+            ast::VarName::Normal { ident, .. } => Some(self.ctx.resolutions.expect_def(ident)),
+            ast::VarName::Reg { .. } => None,
+        }
+    }
+}
+
+impl Visit for AnalyzeBodyVisitor<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        match &stmt.kind {
+            ast::StmtKind::Declaration { vars, .. } => {
+                for sp_pat![(var, _)] in vars {
+                    self.locals.extend(self.local_def(var));
+                }
+            },
+            ast::StmtKind::Assignment { var, .. } |
+            ast::StmtKind::Times { clobber: Some(var), .. } => {
+                self.unsubstitutable.extend(self.local_def(var));
+            },
+            _ => {},
+        }
+        ast::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        match &expr.value {
+            ast::Expr::XcrementOp { var, .. } => {
+                self.unsubstitutable.extend(self.local_def(var));
+            },
+            ast::Expr::Call(call) => {
+                if let Err(def_id) = self.ctx.func_opcode_from_ast(&call.name) {
+                    self.calls.push((def_id, call.name.span));
+                }
+            },
+            _ => {},
+        }
+        ast::walk_expr(self, expr);
+    }
+
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let Some(def_id) = self.local_def(var) {
+            if let Some(sigil) = var.ty_sigil {
+                if self.ctx.defs.var_inherent_ty(def_id) != VarType::Typed(sigil.into()) {
+                    self.unsubstitutable.insert(def_id);
+                }
+            }
+        }
+    }
+
+    fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+}
+
+// =============================================================================
+
+fn check_recursion(funcs: &IndexMap<DefId, InlineFunc>, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum State { InProgress, Done }
+
+    struct Search<'a, 'ctx> {
+        funcs: &'a IndexMap<DefId, InlineFunc>,
+        ctx: &'a CompilerContext<'ctx>,
+        states: HashMap<DefId, State>,
+        /// Functions currently being searched, each paired with the span of the call that led to it.
+        stack: Vec<(DefId, Option<Span>)>,
+        errors: ErrorFlag,
+    }
+
+    impl Search<'_, '_> {
+        fn search(&mut self, def_id: DefId, call_span: Option<Span>) {
+            self.states.insert(def_id, State::InProgress);
+            self.stack.push((def_id, call_span));
+
+            for &(callee, span) in &self.funcs[&def_id].calls {
+                match self.states.get(&callee) {
+                    _ if !self.funcs.contains_key(&callee) => {},
+                    None => self.search(callee, Some(span)),
+                    Some(State::Done) => {},
+                    Some(State::InProgress) => self.report(callee, span),
+                }
+            }
+
+            self.stack.pop();
+            self.states.insert(def_id, State::Done);
+        }
+
+        fn report(&mut self, callee: DefId, span: Span) {
+            let start = self.stack.iter().position(|&(def_id, _)| def_id == callee).unwrap();
+            let cycle = &self.stack[start..];
+            let name = |def_id| self.ctx.defs.func_name(def_id).to_string();
+
+            let mut diag = error!(
+                message("recursive inline function '{}'", name(callee)),
+                primary(span, "recursive call"),
+            );
+            for &(def_id, call_span) in &cycle[1..] {
+                diag.secondary(call_span.unwrap(), format!("calls '{}'", name(def_id)));
+            }
+            let chain = cycle.iter().map(|&(def_id, _)| name(def_id)).chain(std::iter::once(name(callee)));
+            diag.note(format!("call chain: {}", chain.collect::<Vec<_>>().join(" -> ")));
+            self.errors.set(self.ctx.emitter.emit(diag));
+        }
+    }
+
+    let mut search = Search { funcs, ctx, states: HashMap::new(), stack: vec![], errors: ErrorFlag::new() };
+    for &def_id in funcs.keys() {
+        if !search.states.contains_key(&def_id) {
+            search.search(def_id, None);
+        }
+    }
+    search.errors.into_result(())
+}
+
+// =============================================================================
+
+struct ExpandVisitor<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+    funcs: &'a IndexMap<DefId, InlineFunc>,
+    errors: ErrorFlag,
+}
+
+impl VisitMut for ExpandVisitor<'_, '_> {
+    fn visit_file(&mut self, file: &mut ast::ScriptFile) {
+        file.items.retain(|item| !is_inline_func(item));
+        ast::walk_file_mut(self, file);
+    }
+
+    fn visit_block(&mut self, block: &mut ast::Block) {
+        let mut out = vec![];
+        for stmt in std::mem::take(&mut block.0) {
+            match &stmt.kind {
+                ast::StmtKind::Item(item) if is_inline_func(item) => {},
+                _ => self.expand_stmt(stmt, &mut out).unwrap_or_else(|e| self.errors.set(e)),
+            }
+        }
+        block.0 = out;
+    }
+}
+
+impl ExpandVisitor<'_, '_> {
+    fn inline_callee(&self, call: &ast::ExprCall) -> Option<DefId> {
+        match self.ctx.func_opcode_from_ast(&call.name) {
+            Err(def_id) if self.funcs.contains_key(&def_id) => Some(def_id),
+            _ => None,
+        }
+    }
+
+    fn make_stmt(&self, span: Span, kind: ast::StmtKind) -> Sp<ast::Stmt> {
+        sp!(span => ast::Stmt { node_id: Some(self.ctx.next_node_id()), diff_label: None, kind })
+    }
+
+    fn expand_stmt(&mut self, mut stmt: Sp<ast::Stmt>, out: &mut Vec<Sp<ast::Stmt>>) -> Result<(), ErrorReported> {
+        // a call in statement position simply becomes a block, and so does one whose value is directly
+        // assigned to a variable  (there's no need for a temporary, as nothing can happen after the assignment)
+        match &stmt.kind {
+            ast::StmtKind::Expr(sp_pat![ast::Expr::Call(call)]) |
+            ast::StmtKind::Assignment { op: sp_pat![token![=]], value: sp_pat![ast::Expr::Call(call)], .. } => {
+                if let Some(def_id) = self.inline_callee(call) {
+                    let result = match &stmt.kind {
+                        ast::StmtKind::Assignment { var, .. } => Some(var),
+                        _ => None,
+                    };
+                    let block = self.expand_call(def_id, call, result)?;
+                    stmt.kind = ast::StmtKind::Block(block);
+                    out.push(stmt);
+                    return Ok(());
+                }
+            },
+            _ => {},
+        }
+
+        // each variable of a declaration may use the ones before it, so give them separate statements
+        if let ast::StmtKind::Declaration { ty_keyword, vars } = &stmt.kind {
+            if vars.len() > 1 && vars.iter().any(|var| var.1.as_ref().is_some_and(|value| self.contains_inline_call(value))) {
+                for var in vars {
+                    let mut split = self.make_stmt(var.span, ast::StmtKind::Declaration {
+                        ty_keyword: *ty_keyword,
+                        vars: vec![var.clone()],
+                    });
+                    split.diff_label = stmt.diff_label.clone();
+                    self.expand_stmt(split, out)?;
+                }
+                return Ok(());
+            }
+        }
+
+        // the variable of a declaration must remain visible to the rest of the block, so turn the
+        // initialization into an assignment
+        if let ast::StmtKind::Declaration { vars, .. } = &mut stmt.kind {
+            let (var, value) = &mut vars[0].value;
+            if value.as_ref().is_some_and(|value| self.contains_inline_call(value)) {
+                let value = value.take().unwrap();
+                let mut assignment = self.make_stmt(value.span, ast::StmtKind::Assignment {
+                    var: var.clone(),
+                    op: sp!(value.span => token![=]),
+                    value,
+                });
+                assignment.diff_label = stmt.diff_label.clone();
+                out.push(stmt);
+                return self.expand_stmt(assignment, out);
+            }
+        }
+
+        let mut prelude = vec![];
+        self.hoist_calls_in_stmt(&mut stmt.kind, &mut prelude)?;
+        ast::walk_stmt_mut(self, &mut stmt);
+
+        if prelude.is_empty() {
+            out.push(stmt);
+            return Ok(());
+        }
+
+        // put the temporaries in a block so that they are freed afterwards
+        let span = stmt.span;
+        let diff_label = stmt.diff_label.take();
+        prelude.push(stmt);
+        let mut block_stmt = self.make_stmt(span, ast::StmtKind::Block(ast::Block(prelude)));
+        block_stmt.diff_label = diff_label;
+        out.push(block_stmt);
+        Ok(())
+    }
+
+    /// Move calls out of the expressions that are evaluated before the statement runs.
+    fn hoist_calls_in_stmt(&mut self, kind: &mut ast::StmtKind, prelude: &mut Vec<Sp<ast::Stmt>>) -> Result<(), ErrorReported> {
+        match kind {
+            ast::StmtKind::Expr(expr) |
+            ast::StmtKind::Assignment { value: expr, .. } |
+            ast::StmtKind::Return { value: Some(expr), .. } |
+            ast::StmtKind::CondJump { cond: expr, .. } |
+            ast::StmtKind::Times { count: expr, .. } => {
+                self.hoist_calls(expr, prelude, false)?;
+            },
+            ast::StmtKind::CallSub { call, async_, .. } => {
+                for arg in &mut call.args {
+                    self.hoist_calls(arg, prelude, false)?;
+                }
+                if let Some(ast::CallAsyncKind::CallAsyncId { id, .. }) = async_ {
+                    self.hoist_calls(id, prelude, false)?;
+                }
+            },
+            ast::StmtKind::CondChain(chain) => {
+                for (index, cond_block) in chain.cond_blocks.iter_mut().enumerate() {
+                    self.hoist_calls(&mut cond_block.cond, prelude, index > 0)?;
+                }
+            },
+            ast::StmtKind::While { cond, .. } => {
+                self.hoist_calls(cond, prelude, true)?;
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    /// Replace each call to an inline function with a temporary holding its result, adding the code
+    /// that computes it to the prelude.
+    ///
+    /// `conditional` indicates that the expression is not always evaluated exactly once before the
+    /// statement, in which case any call is an error.
+    fn hoist_calls(&mut self, expr: &mut Sp<ast::Expr>, prelude: &mut Vec<Sp<ast::Stmt>>, conditional: bool) -> Result<(), ErrorReported> {
+        match &mut expr.value {
+            ast::Expr::BinOp(a, op, b) => {
+                let short_circuits = matches!(op.value, ast::BinOpKind::LogicAnd | ast::BinOpKind::LogicOr);
+                self.hoist_calls(a, prelude, conditional)?;
+                self.hoist_calls(b, prelude, conditional || short_circuits)?;
+            },
+            ast::Expr::Ternary { cond, left, right, .. } => {
+                self.hoist_calls(cond, prelude, conditional)?;
+                self.hoist_calls(left, prelude, true)?;
+                self.hoist_calls(right, prelude, true)?;
+            },
+            ast::Expr::DiffSwitch(cases) => {
+                for case in cases.iter_mut().flatten() {
+                    self.hoist_calls(case, prelude, true)?;
+                }
+            },
+            ast::Expr::UnOp(_, x) => self.hoist_calls(x, prelude, conditional)?,
+            ast::Expr::Call(call) => match self.inline_callee(call) {
+                None => {
+                    for arg in &mut call.args {
+                        self.hoist_calls(arg, prelude, conditional)?;
+                    }
+                },
+                Some(_) if conditional => return Err(self.ctx.emitter.emit(error!(
+                    message("cannot inline call to '{}' here", call.name),
+                    primary(expr, "not always evaluated exactly once"),
+                    note("a call to an inline function may only appear where it is evaluated once before the statement runs, e.g. not in a loop condition or after '&&'"),
+                ))),
+                Some(def_id) => {
+                    let func = &self.funcs[&def_id].func;
+                    let temp_ty_keyword = sp!(expr.span => func.ty_keyword.value);
+                    let temp_ident = self.ctx.gensym.gensym("@inline_result#");
+                    let (temp_decl, temp_var) = self.declare_local(temp_ty_keyword, temp_ident, None);
+
+                    let block = self.expand_call(def_id, call, Some(&temp_var))?;
+                    prelude.push(temp_decl);
+                    prelude.push(self.make_stmt(expr.span, ast::StmtKind::Block(block)));
+                    expr.value = ast::Expr::Var(temp_var);
+                },
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+
+    fn contains_inline_call(&self, expr: &Sp<ast::Expr>) -> bool {
+        struct Finder<'a, 'b, 'ctx>(&'a ExpandVisitor<'b, 'ctx>, bool);
+        impl Visit for Finder<'_, '_, '_> {
+            fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+                if let ast::Expr::Call(call) = &expr.value {
+                    self.1 |= self.0.inline_callee(call).is_some();
+                }
+                ast::walk_expr(self, expr);
+            }
+        }
+
+        let mut finder = Finder(self, false);
+        finder.visit_expr(expr);
+        finder.1
+    }
+
+    /// Whether an argument can be substituted for a parameter without changing the meaning of the code.
+    ///
+    /// Locals of the caller are fine, as the body of an inline function cannot modify them.
+    fn is_pure(&self, expr: &Sp<ast::Expr>) -> bool {
+        struct Finder<'a, 'ctx>(&'a CompilerContext<'ctx>, bool);
+        impl Visit for Finder<'_, '_> {
+            fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+                match &expr.value {
+                    ast::Expr::Call(_) | ast::Expr::XcrementOp { .. } => self.1 = false,
+                    _ => ast::walk_expr(self, expr),
+                }
+            }
+
+            fn visit_var(&mut self, var: &Sp<ast::Var>) {
+                let is_register = match &var.name {
+                    ast::VarName::Normal { ident, .. } => {
+                        let def_id = self.0.resolutions.expect_def(ident);
+                        self.0.defs.var_reg(def_id).is_some()
+                    },
+                    ast::VarName::Reg { .. } => true,
+                };
+                self.1 &= !is_register;
+            }
+        }
+
+        let mut finder = Finder(self.ctx, true);
+        finder.visit_expr(expr);
+        finder.1
+    }
+
+    /// Create a declaration of a brand new local.
+    fn declare_local(&mut self, ty_keyword: Sp<ast::TypeKeyword>, ident: Ident, value: Option<Sp<ast::Expr>>) -> (Sp<ast::Stmt>, Sp<ast::Var>) {
+        let span = ty_keyword.span;
+        let ident = self.ctx.resolutions.attach_fresh_res(ident);
+        self.ctx.define_local(sp!(span => ident.clone()), ty_keyword.value.var_ty());
+        let var = sp!(span => ast::Var { ty_sigil: None, name: ast::VarName::new_non_reg(ident) });
+        let stmt = self.make_stmt(span, ast::StmtKind::Declaration {
+            ty_keyword,
+            vars: vec![sp!(span => (var.clone(), value))],
+        });
+        (stmt, var)
+    }
+
+    /// Produce a copy of the body of an inline function for a single call.
+    ///
+    /// If `result` is provided, the returned value is assigned to it.
+    fn expand_call(&mut self, def_id: DefId, call: &ast::ExprCall, result: Option<&Sp<ast::Var>>) -> Result<ast::Block, ErrorReported> {
+        let funcs = self.funcs;
+        let InlineFunc { func, locals, unsubstitutable, calls: _ } = &funcs[&def_id];
+        let mut body = func.code.clone().expect("inline func without body");
+        crate::passes::resolution::refresh_node_ids(&mut body, &self.ctx.unused_node_ids)?;
+        crate::passes::unroll_times::refresh_loop_ids(&mut body, self.ctx);
+
+        // decide what to do with each argument.
+        // (calls in the arguments are expanded first, so that their results can be substituted)
+        let mut out = vec![];
+        let mut substitutions = HashMap::new();
+        let mut arg_decls = vec![];
+        for (param, arg) in func.params.iter().zip(&call.args) {
+            let mut arg = arg.clone();
+            self.hoist_calls(&mut arg, &mut out, false)?;
+
+            let param_def = param.ident.as_ref().map(|ident| self.ctx.resolutions.expect_def(ident));
+            let pure = self.is_pure(&arg);
+            match param_def {
+                Some(param_def) if pure && !unsubstitutable.contains(&param_def) => {
+                    substitutions.insert(param_def, arg);
+                },
+                None if pure => {},  // unused
+                _ => arg_decls.push((param, param_def, arg)),
+            }
+        }
+
+        // give the copy its own locals
+        let mut new_locals = HashMap::new();
+        for &old_def in locals {
+            if substitutions.contains_key(&old_def) {
+                continue;
+            }
+            let old_ident = self.ctx.defs.var_name(old_def).as_raw().clone();
+            let span = self.ctx.defs.var_decl_span(old_def).expect("local without span");
+            let new_ident = self.ctx.resolutions.attach_fresh_res(old_ident);
+            let ty = self.ctx.defs.var_inherent_ty(old_def);
+            self.ctx.define_local(sp!(span => new_ident.clone()), ty);
+            new_locals.insert(old_def, new_ident);
+        }
+
+        for (param, param_def, arg) in arg_decls {
+            let var = match param_def {
+                Some(param_def) => sp!(arg.span => ast::Var { ty_sigil: None, name: ast::VarName::new_non_reg(new_locals[&param_def].clone()) }),
+                None => {
+                    let ident = self.ctx.gensym.gensym("@inline_arg#");
+                    let ident = self.ctx.resolutions.attach_fresh_res(ident);
+                    self.ctx.define_local(sp!(arg.span => ident.clone()), param.ty_keyword.value.var_ty());
+                    sp!(arg.span => ast::Var { ty_sigil: None, name: ast::VarName::new_non_reg(ident) })
+                },
+            };
+            out.push(self.make_stmt(arg.span, ast::StmtKind::Declaration {
+                ty_keyword: sp!(arg.span => param.ty_keyword.value),
+                vars: vec![sp!(arg.span => (var, Some(arg)))],
+            }));
+        }
+
+        // a return at the very end doesn't need a goto
+        let final_return = body.0.iter().rposition(|stmt| !matches!(stmt.kind, ast::StmtKind::NoInstruction))
+            .filter(|&index| matches!(body.0[index].value, ast::Stmt { diff_label: None, kind: ast::StmtKind::Return { .. }, .. }));
+
+        let end_label = self.ctx.gensym.gensym("@inline_end#");
+        let mut visitor = RewriteBodyVisitor {
+            ctx: self.ctx,
+            substitutions: &substitutions,
+            new_locals: &new_locals,
+            new_labels: HashMap::new(),
+            result,
+            end_label: &end_label,
+            found_return: false,
+        };
+        visitor.rename_labels(&body);
+        for (index, stmt) in body.0.iter_mut().enumerate() {
+            visitor.visit_stmt(stmt);
+            let span = stmt.span;
+            if let ast::StmtKind::Return { value, .. } = &mut stmt.kind {
+                stmt.kind = visitor.rewrite_return(span, value.take(), Some(index) != final_return);
+            }
+        }
+
+        out.extend(body.0);
+        if visitor.found_return {
+            let end_span = out.last().unwrap().span.end_span();
+            out.push(self.make_stmt(end_span, ast::StmtKind::Label(sp!(end_span => end_label))));
+        }
+
+        // the body may contain further calls
+        let mut block = ast::Block(out);
+        self.visit_block(&mut block);
+        Ok(block)
+    }
+}
+
+/// Renames locals and labels in a copy of a function body, and replaces `return`s.
+struct RewriteBodyVisitor<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+    substitutions: &'a HashMap<DefId, Sp<ast::Expr>>,
+    new_locals: &'a HashMap<DefId, ResIdent>,
+    new_labels: HashMap<Ident, Ident>,
+    result: Option<&'a Sp<ast::Var>>,
+    end_label: &'a Ident,
+    found_return: bool,
+}
+
+impl RewriteBodyVisitor<'_, '_> {
+    fn rename_labels(&mut self, body: &ast::Block) {
+        struct LabelFinder<'a, 'b, 'ctx>(&'a mut RewriteBodyVisitor<'b, 'ctx>);
+        impl Visit for LabelFinder<'_, '_, '_> {
+            fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+                if let ast::StmtKind::Label(label) = &stmt.kind {
+                    let new_label = self.0.ctx.gensym.gensym(&format!("@{label}#"));
+                    self.0.new_labels.insert(label.value.clone(), new_label);
+                }
+                ast::walk_stmt(self, stmt);
+            }
+
+            fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+        }
+
+        LabelFinder(self).visit_block(body);
+    }
+
+    fn rename_label(&self, label: &mut Sp<Ident>) {
+        if let Some(new_label) = self.new_labels.get(&label.value) {
+            label.value = new_label.clone();
+        }
+    }
+
+    fn rewrite_return(&mut self, span: Span, value: Option<Sp<ast::Expr>>, needs_goto: bool) -> ast::StmtKind {
+        let mut stmts = vec![];
+        if let (Some(value), Some(result)) = (value, self.result) {
+            stmts.push(self.make_stmt(value.span, ast::StmtKind::Assignment {
+                var: result.clone(),
+                op: sp!(value.span => token![=]),
+                value,
+            }));
+        }
+        if needs_goto {
+            self.found_return = true;
+            stmts.push(self.make_stmt(span, ast::StmtKind::Jump(ast::StmtJumpKind::Goto(ast::StmtGoto {
+                destination: sp!(span => self.end_label.clone()),
+                time: None,
+            }))));
+        }
+        match stmts.len() {
+            0 => ast::StmtKind::NoInstruction,
+            _ => ast::StmtKind::Block(ast::Block(stmts)),
+        }
+    }
+
+    fn make_stmt(&self, span: Span, kind: ast::StmtKind) -> Sp<ast::Stmt> {
+        sp!(span => ast::Stmt { node_id: Some(self.ctx.next_node_id()), diff_label: None, kind })
+    }
+}
+
+impl VisitMut for RewriteBodyVisitor<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &mut Sp<ast::Stmt>) {
+        ast::walk_stmt_mut(self, stmt);
+        if let ast::StmtKind::Label(label) = &mut stmt.kind {
+            self.rename_label(label);
+        }
+    }
+
+    fn visit_block(&mut self, block: &mut ast::Block) {
+        ast::walk_block_mut(self, block);
+        for stmt in &mut block.0 {
+            let span = stmt.span;
+            if let ast::StmtKind::Return { value, .. } = &mut stmt.kind {
+                stmt.kind = self.rewrite_return(span, value.take(), true);
+            }
+        }
+    }
+
+    fn visit_jump(&mut self, jump: &mut ast::StmtJumpKind) {
+        if let ast::StmtJumpKind::Goto(goto) = jump {
+            self.rename_label(&mut goto.destination);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &mut Sp<ast::Expr>) {
+        match &mut expr.value {
+            ast::Expr::Var(sp_pat![ast::Var { name: ast::VarName::Normal { ident, .. }, .. }]) => {
+                let def_id = self.ctx.resolutions.expect_def(ident);
+                if let Some(arg) = self.substitutions.get(&def_id) {
+                    // don't walk into it; it belongs to the caller
+                    expr.value = arg.value.clone();
+                    return;
+                }
+            },
+            // labels in other scripts aren't affected by inlining
+            ast::Expr::LabelProperty { script: None, label, .. } => self.rename_label(label),
+            _ => {},
+        }
+        ast::walk_expr_mut(self, expr);
+    }
+
+    fn visit_var(&mut self, var: &mut Sp<ast::Var>) {
+        if let ast::VarName::Normal { ident, .. } = &mut var.name {
+            let def_id = self.ctx.resolutions.expect_def(ident);
+            if let Some(new_ident) = self.new_locals.get(&def_id) {
+                *ident = new_ident.clone();
+            }
+        }
+    }
+
+    fn visit_item(&mut self, _: &mut Sp<ast::Item>) {}
+}
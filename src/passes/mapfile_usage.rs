@@ -0,0 +1,59 @@
+//! See [`run`].
+
+use crate::ast::{self, Visit};
+use crate::context::CompilerContext;
+use crate::ident::ResIdent;
+use crate::pos::Sp;
+use crate::resolve::Namespace;
+
+/// Records the mapfiles that define the aliases and enum consts used by a decompiled script.
+///
+/// See [`CompilerContext::used_mapfiles_to_ast`].
+pub fn run<V: ast::Visitable>(ast: &V, ctx: &CompilerContext) {
+    let mut visitor = Visitor { ctx };
+    ast.visit_with(&mut visitor);
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+}
+
+impl Visitor<'_, '_> {
+    fn record(&self, ident: &ResIdent, ns: Namespace) {
+        // some names in decompiled output (e.g. builtin consts in meta) are never resolved
+        if ident.res.is_none() {
+            return;
+        }
+        if let Some(def_id) = self.ctx.resolutions.try_get_def(ident) {
+            if let Some(span) = self.ctx.defs.definition(ns, def_id).span {
+                self.ctx.mark_mapfile_span_used(span);
+            }
+        }
+    }
+}
+
+impl Visit for Visitor<'_, '_> {
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        if let ast::Expr::EnumConst { enum_name, ident } = &expr.value {
+            // decompiled enum consts aren't resolved
+            if let Some(def_id) = self.ctx.defs.enum_const_def_id(enum_name, ident) {
+                if let Some(span) = self.ctx.defs.definition(Namespace::Vars, def_id).span {
+                    self.ctx.mark_mapfile_span_used(span);
+                }
+            }
+        }
+        ast::walk_expr(self, expr);
+    }
+
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let ast::VarName::Normal { ident, .. } = &var.name {
+            self.record(ident, Namespace::Vars);
+        }
+    }
+
+    fn visit_callable_name(&mut self, name: &Sp<ast::CallableName>) {
+        if let ast::CallableName::Normal { ident, .. } = &name.value {
+            self.record(ident, Namespace::Funcs);
+        }
+    }
+}
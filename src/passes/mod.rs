@@ -5,11 +5,22 @@ use crate::llir::DecompileOptions;
 
 pub mod const_simplify;
 pub mod unused_labels;
+pub mod mapfile_usage;
+pub mod group_diff_labels;
 pub mod desugar_blocks;
 pub mod decompile_loop;
 pub mod resolution;
 pub mod type_check;
 pub mod validate_difficulty;
+pub mod validate_goto_times;
+pub mod float_equality;
+pub mod validate_reg_sigils;
+pub mod validate_nesting;
+pub mod unreachable_code;
+pub mod unroll_times;
+pub mod inline_funcs;
+pub mod explode_diff_switches;
+pub mod observer;
 pub mod debug {
     //! Passes that exist for **debugging/testing purposes only.**
     pub mod make_idents_unique;
@@ -53,9 +64,11 @@ pub fn postprocess_decompiled<V: ast::Visitable + std::fmt::Debug>(
         decompile_loop::decompile_if_else(script, ctx)?;
         decompile_loop::decompile_break(script, ctx)?;
         unused_labels::run(script)?;
+        group_diff_labels::run(script, ctx)?;
     }
 
     resolution::check_loop_id_integrity(script, ctx)?;
+    mapfile_usage::run(script, ctx);
 
     Ok(())
 }
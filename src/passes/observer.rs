@@ -0,0 +1,132 @@
+//! Hooks for inspecting the state of compilation at various points, for debugging purposes.
+//!
+//! See [`PassObserver`].  Observers are added using [`Truth::add_pass_observer`][crate::Truth::add_pass_observer].
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::ast;
+use crate::error::ErrorReported;
+use crate::io::Fs;
+use crate::llir::RawInstr;
+use crate::resolve::Resolutions;
+
+/// Trait for observing the compiler at defined points during compilation.
+///
+/// Observers only ever receive shared references, so they cannot influence the output of compilation
+/// (except by returning an error to abort it).  All methods do nothing by default.
+///
+/// Each method is called once for every observer, in the order that they were added.
+///
+/// Observers must be [`Send`] because the [`CompilerContext`][crate::CompilerContext] that holds them
+/// is shared between the threads that lower scripts, but the methods are always called from the thread
+/// that is driving compilation.
+pub trait PassObserver: Send {
+    /// Called with the AST given to one of the `compile_*` methods on [`Truth`][crate::Truth],
+    /// before any passes have run on it.  (for a script read from text, this is the output of the parser)
+    fn after_parse(&mut self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        let _ = ast;
+        Ok(())
+    }
+
+    /// Called after each pass that can modify the AST, with the name of the pass.
+    ///
+    /// The names are those of the functions in [`crate::passes`], e.g. `"const_simplify"`.
+    fn after_pass(&mut self, pass: &'static str, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        let _ = (pass, ast);
+        Ok(())
+    }
+
+    /// Called after name resolution, which is the point where every identifier in the AST resolves
+    /// to something in [`Resolutions`].
+    fn after_resolution(&mut self, ast: &ast::ScriptFile, resolutions: &Resolutions) -> Result<(), ErrorReported> {
+        let _ = (ast, resolutions);
+        Ok(())
+    }
+
+    /// Called after each script is lowered to instructions.  `script_name` is the name of the script
+    /// as written in the source, or e.g. `"timeline 0"` for a script with no name.
+    fn after_lowering(&mut self, script_name: &str, instrs: &[RawInstr]) -> Result<(), ErrorReported> {
+        let _ = (script_name, instrs);
+        Ok(())
+    }
+}
+
+/// The list of observers stored on [`CompilerContext`][crate::CompilerContext].
+#[derive(Default)]
+pub struct Observers<'ctx> {
+    // (the Mutex is only there to make this Sync; it is never locked since all access is through &mut)
+    observers: Mutex<Vec<Box<dyn PassObserver + 'ctx>>>,
+}
+
+impl fmt::Debug for Observers<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({} observers)", self.observers.lock().unwrap().len())
+    }
+}
+
+impl<'ctx> Observers<'ctx> {
+    pub(crate) fn push(&mut self, observer: Box<dyn PassObserver + 'ctx>) {
+        self.observers.get_mut().unwrap().push(observer);
+    }
+
+    pub(crate) fn after_parse(&mut self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.observers.get_mut().unwrap().iter_mut().try_for_each(|obs| obs.after_parse(ast))
+    }
+
+    pub(crate) fn after_pass(&mut self, pass: &'static str, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.observers.get_mut().unwrap().iter_mut().try_for_each(|obs| obs.after_pass(pass, ast))
+    }
+
+    pub(crate) fn after_resolution(&mut self, ast: &ast::ScriptFile, resolutions: &Resolutions) -> Result<(), ErrorReported> {
+        self.observers.get_mut().unwrap().iter_mut().try_for_each(|obs| obs.after_resolution(ast, resolutions))
+    }
+
+    pub(crate) fn after_lowering(&mut self, script_name: &str, instrs: &[RawInstr]) -> Result<(), ErrorReported> {
+        self.observers.get_mut().unwrap().iter_mut().try_for_each(|obs| obs.after_lowering(script_name, instrs))
+    }
+}
+
+// =============================================================================
+
+/// A [`PassObserver`] that writes the formatted AST to a directory after parsing and after each pass.
+///
+/// Files are named after the pass and numbered in the order they were written,
+/// e.g. `00-parse.txt`, `01-assign_languages.txt`, ...  Lowered scripts are written in a
+/// debug format to e.g. `07-lowered-sub0.txt`.
+pub struct DumpAstObserver<'ctx> {
+    fs: Fs<'ctx>,
+    directory: PathBuf,
+    next_index: u32,
+}
+
+impl<'ctx> DumpAstObserver<'ctx> {
+    /// Create the observer, creating the directory if it does not exist.
+    pub fn new(fs: Fs<'ctx>, directory: impl Into<PathBuf>) -> Result<Self, ErrorReported> {
+        let directory = directory.into();
+        fs.create_dir_all(&directory)?;
+        Ok(DumpAstObserver { fs, directory, next_index: 0 })
+    }
+
+    fn write(&mut self, name: &str, contents: String) -> Result<(), ErrorReported> {
+        let path = self.directory.join(format!("{:02}-{}.txt", self.next_index, name));
+        self.next_index += 1;
+        self.fs.write(&path, contents)
+    }
+}
+
+impl PassObserver for DumpAstObserver<'_> {
+    fn after_parse(&mut self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.write("parse", crate::fmt::stringify(ast))
+    }
+
+    fn after_pass(&mut self, pass: &'static str, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.write(pass, crate::fmt::stringify(ast))
+    }
+
+    fn after_lowering(&mut self, script_name: &str, instrs: &[RawInstr]) -> Result<(), ErrorReported> {
+        let contents = instrs.iter().map(|instr| format!("{:?}\n", instr)).collect();
+        self.write(&format!("lowered-{}", script_name.replace(' ', "_")), contents)
+    }
+}
@@ -8,8 +8,10 @@ use crate::ast;
 use crate::pos::{Sp};
 use crate::context::{CompilerContext};
 use crate::error::{ErrorReported, ErrorFlag};
+use crate::diagnostic::Diagnostic;
+use crate::bitset::BitSet32;
 use crate::game::LanguageKey;
-use crate::ident::{ResIdent};
+use crate::ident::{Ident, ResIdent};
 use crate::value::VarType;
 use crate::resolve::{NodeId, LoopId, UnusedIds, RegId};
 
@@ -32,7 +34,17 @@ pub fn refresh_node_ids<V: ast::Visitable + ?Sized>(ast: &mut V, unused_node_ids
 /// This is an extremely early preprocessing pass, preferably done immediately after parsing.
 /// (it can't be done during parsing because parsing should not require access to [`CompilerContext`])
 pub fn assign_res_ids<A: ast::Visitable + ?Sized>(ast: &mut A, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
-    let mut v = AssignResIdsVisitor { ctx };
+    let mut v = AssignResIdsVisitor { ctx, only_missing: true };
+    ast.visit_mut_with(&mut v);
+    Ok(())
+}
+
+/// Generate brand new [`ResId`]s for every name in an AST node.
+///
+/// This allows name resolution to be performed on a copy of an AST node without affecting the original.
+/// (see the note on [`resolve_names`])
+pub fn refresh_res_ids<A: ast::Visitable + ?Sized>(ast: &mut A, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut v = AssignResIdsVisitor { ctx, only_missing: false };
     ast.visit_mut_with(&mut v);
     Ok(())
 }
@@ -48,6 +60,8 @@ pub fn assign_res_ids<A: ast::Visitable + ?Sized>(ast: &mut A, ctx: &mut Compile
 /// * Tokens inside `timeline` items will be painted with [`InstrLanguage::Timeline`] instead.
 /// * Tokens inside `const` exprs and `const` functions will not be painted with any language.
 ///   Any raw syntax (`ins_23`, `REG[10004]`) in these locations will produce errors.
+/// * Raw syntax with an explicit language prefix (`timeline.ins_23`, `ecl.REG[10004]`) is painted with that language
+///   instead, provided the prefix names a language of this file.
 ///
 /// If called directly on [`ast::Block`] instead of a script file, it is assumed to be the body of a `script` and thus paints
 /// with the specified language.  (this behavior is for use by tests)
@@ -147,7 +161,11 @@ pub fn check_loop_id_integrity<A: ast::Visitable + ?Sized>(ast: &A, _: &mut Comp
     Ok(())
 }
 
-/// Compute masks for [`ast::StmtKind::DiffLabel`]s from their strings by resolving diff flag names.
+/// Compute masks for [`ast::DiffLabel`]s from their strings by resolving diff flag names.
+///
+/// The expression in each label is first simplified, so that it may use `const` variables; this requires
+/// [type checking](crate::passes::type_check) and [const evaluation](crate::passes::evaluate_const_vars).
+/// Numeric labels already have a mask, which is only checked to fit in a difficulty byte.
 pub fn compute_diff_label_masks<A: ast::Visitable + ?Sized>(ast: &mut A, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
     let mut v = FillDiffLabelsVisitor { errors: ErrorFlag::new(), ctx };
     ast.visit_mut_with(&mut v);
@@ -158,11 +176,16 @@ pub fn compute_diff_label_masks<A: ast::Visitable + ?Sized>(ast: &mut A, ctx: &C
 
 struct AssignResIdsVisitor<'a, 'ctx> {
     ctx: &'a mut CompilerContext<'ctx>,
+    only_missing: bool,
 }
 
 impl ast::VisitMut for AssignResIdsVisitor<'_, '_> {
     fn visit_res_ident(&mut self, ident: &mut ResIdent) {
-        ident.res.get_or_insert_with(|| self.ctx.resolutions.fresh_res());
+        if self.only_missing {
+            ident.res.get_or_insert_with(|| self.ctx.resolutions.fresh_res());
+        } else {
+            ident.res = Some(self.ctx.resolutions.fresh_res());
+        }
     }
 }
 
@@ -174,7 +197,7 @@ impl ast::VisitMut for AliasesToRawVisitor<'_, '_> {
     fn visit_var(&mut self, var: &mut Sp<ast::Var>) {
         if let ast::VarName::Normal { .. } = &var.name {
             if let Ok((language, reg)) = self.ctx.var_reg_from_ast(&var.name) {
-                var.name = ast::VarName::Reg { reg, language: Some(language) };
+                var.name = ast::VarName::Reg { reg, language: Some(language), language_prefix: None };
             }
         }
     }
@@ -182,7 +205,7 @@ impl ast::VisitMut for AliasesToRawVisitor<'_, '_> {
     fn visit_expr(&mut self, expr: &mut Sp<ast::Expr>) {
         if let ast::Expr::Call(ast::ExprCall { name, .. }) = &mut expr.value {
             if let Ok((language, opcode)) = self.ctx.func_opcode_from_ast(name) {
-                name.value = ast::CallableName::Ins { opcode, language: Some(language) };
+                name.value = ast::CallableName::Ins { opcode, language: Some(language), language_prefix: None };
             }
         }
         ast::walk_expr_mut(self, expr);
@@ -235,33 +258,27 @@ struct AssignLanguagesVisitor<'a, 'ctx> {
 
 impl ast::VisitMut for AssignLanguagesVisitor<'_, '_> {
     fn visit_var(&mut self, var: &mut Sp<ast::Var>) {
+        let inferred = self.inferred_language();
         let language_dest = match &mut var.name {
-            ast::VarName::Reg { language, .. } => language,
+            ast::VarName::Reg { language, language_prefix, .. } => {
+                *language = self.language_from_prefix(inferred, language_prefix.as_ref());
+                return self.check_raw_language(*language, var, "raw register");
+            },
             ast::VarName::Normal { language_if_reg, .. } => language_if_reg,
         };
-        *language_dest = self.language_stack.last().expect("empty stack?!").clone();
-
-        if let ast::VarName::Reg { language: None, .. } = var.name {
-            self.errors.set(self.ctx.emitter.emit(error!(
-                message("raw register in const context"),
-                primary(var, "forbidden in this context"),
-            )));
-        }
+        *language_dest = inferred;
     }
 
     fn visit_callable_name(&mut self, name: &mut Sp<ast::CallableName>) {
+        let inferred = self.inferred_language();
         let language_dest = match &mut name.value {
-            ast::CallableName::Ins { language, .. } => language,
+            ast::CallableName::Ins { language, language_prefix, .. } => {
+                *language = self.language_from_prefix(inferred, language_prefix.as_ref());
+                return self.check_raw_language(*language, name, "raw instruction");
+            },
             ast::CallableName::Normal { language_if_ins, .. } => language_if_ins,
         };
-        *language_dest = self.language_stack.last().expect("empty stack?!").clone();
-
-        if let ast::CallableName::Ins { language: None, .. } = name.value {
-            self.errors.set(self.ctx.emitter.emit(error!(
-                message("raw instruction in const context"),
-                primary(name, "forbidden in this context"),
-            )));
-        }
+        *language_dest = inferred;
     }
 
     fn visit_item(&mut self, item: &mut Sp<ast::Item>) {
@@ -291,6 +308,52 @@ impl ast::VisitMut for AssignLanguagesVisitor<'_, '_> {
     }
 }
 
+impl AssignLanguagesVisitor<'_, '_> {
+    fn inferred_language(&self) -> Option<LanguageKey> {
+        *self.language_stack.last().expect("empty stack?!")
+    }
+
+    /// Languages that raw syntax may be explicitly qualified with in this file.
+    fn valid_prefix_languages(&self) -> Vec<LanguageKey> {
+        match self.primary_language {
+            LanguageKey::Ecl => vec![LanguageKey::Ecl, LanguageKey::Timeline],
+            language => vec![language],
+        }
+    }
+
+    /// Get the language for raw syntax with an optional explicit prefix.
+    ///
+    /// In const contexts this is always `None`, so that the prefix cannot be used to sneak raw syntax into them.
+    fn language_from_prefix(&mut self, inferred: Option<LanguageKey>, prefix: Option<&Sp<Ident>>) -> Option<LanguageKey> {
+        let (inferred, prefix) = match (inferred, prefix) {
+            (Some(inferred), Some(prefix)) => (inferred, prefix),
+            _ => return inferred,
+        };
+        let valid_languages = self.valid_prefix_languages();
+        match valid_languages.iter().find(|language| language.prefix_name() == prefix.as_str()) {
+            Some(&language) => Some(language),
+            None => {
+                let valid_names = valid_languages.iter().map(|language| format!("'{}'", language.prefix_name()));
+                self.errors.set(self.ctx.emitter.emit(error!(
+                    message("unknown language '{}'", prefix),
+                    primary(prefix, "not a language of this file"),
+                    note("valid languages here are: {}", valid_names.collect::<Vec<_>>().join(", ")),
+                )));
+                Some(inferred)
+            },
+        }
+    }
+
+    fn check_raw_language<T>(&mut self, language: Option<LanguageKey>, node: &Sp<T>, what: &str) {
+        if language.is_none() {
+            self.errors.set(self.ctx.emitter.emit(error!(
+                message("{} in const context", what),
+                primary(node, "forbidden in this context"),
+            )));
+        }
+    }
+}
+
 // =============================================================================
 
 struct AssignNodeIdsVisitor<'a> {
@@ -313,14 +376,43 @@ struct FillDiffLabelsVisitor<'a, 'ctx> {
 }
 
 impl ast::VisitMut for FillDiffLabelsVisitor<'_, '_> {
-    fn visit_stmt(&mut self, stmt: &mut Sp<ast::Stmt>) {
-        ast::walk_stmt_mut(self, stmt);
+    fn visit_diff_label(&mut self, label: &mut Sp<ast::DiffLabel>) {
+        let sp_pat!(label_span => ast::DiffLabel { mask, expr }) = label;
+        let result = match expr {
+            Some(expr) => {
+                if let Err(e) = crate::passes::const_simplify::run(expr, self.ctx) {
+                    return self.errors.set(e);
+                }
+                self.mask_from_simplified_expr(expr).map(|computed_mask| *mask = Some(computed_mask))
+            },
+            // numeric labels come with their mask
+            None => {
+                let numeric_mask = mask.expect("numeric difficulty label without a mask (bug)");
+                self.ctx.diff_flag_defs.check_numeric_mask(sp!(*label_span => numeric_mask))
+            },
+        };
+        if let Err(diag) = result {
+            self.errors.set(self.ctx.emitter.emit(diag));
+        }
+    }
+}
 
-        if let Some(ast::DiffLabel { mask, string }) = stmt.diff_label.as_mut().map(|d| &mut d.value) {
-            match self.ctx.diff_flag_defs.parse_diff_string(sp!(string.span => string.string.as_str())) {
-                Ok(computed_mask) => *mask = Some(computed_mask.value),
-                Err(diag) => self.errors.set(self.ctx.emitter.emit(diag)),
-            }
+impl FillDiffLabelsVisitor<'_, '_> {
+    fn mask_from_simplified_expr(&self, expr: &Sp<ast::Expr>) -> Result<BitSet32, Diagnostic> {
+        match &expr.value {
+            ast::Expr::LitString(string) => {
+                self.ctx.diff_flag_defs.parse_diff_string(sp!(expr.span => string.string.as_str()))
+                    .map(|computed_mask| computed_mask.value)
+            },
+            &ast::Expr::LitInt { value, .. } => {
+                let numeric_mask = BitSet32::from_mask(value as u32);
+                self.ctx.diff_flag_defs.check_numeric_mask(sp!(expr.span => numeric_mask))?;
+                Ok(numeric_mask)
+            },
+            _ => Err(error!(
+                message("difficulty label is not a compile-time constant"),
+                primary(expr, "not a compile-time constant"),
+            )),
         }
     }
 }
@@ -101,6 +101,9 @@ impl TimeAndDifficultyHelper {
     }
 
     /// Set the time and difficulty appropriately for the current statement.
+    ///
+    /// A difficulty label is intersected with the labels of any blocks that contain it, so an inner label
+    /// can only further restrict an outer one.
     pub fn enter_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
         // time labels should affect their own attributes,
         // so perform a shallow visit before storing data.
@@ -108,7 +111,8 @@ impl TimeAndDifficultyHelper {
 
         if let Some(&sp_pat!(label_span => ast::DiffLabel { mask, .. })) = stmt.diff_label.as_ref() {
             let mask = mask.expect("compute_diff_label_masks pass was not run!");
-            self.difficulty_stack.push(sp!(label_span => mask));
+            let outer_mask = self.difficulty_mask();
+            self.difficulty_stack.push(sp!(label_span => outer_mask & mask));
         }
     }
 
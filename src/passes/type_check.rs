@@ -5,8 +5,11 @@ use crate::error::{GatherErrorIteratorExt, ErrorReported, ErrorFlag};
 use crate::pos::{Sp, Span};
 use crate::value::{ScalarType, VarType, ExprType};
 use crate::context::CompilerContext;
+use crate::context::defs::{TypeColor, auto_enum_names};
+use crate::diagnostic::Diagnostic;
 use crate::resolve::DefId;
 use crate::ast::TypeKeyword;
+use crate::resolve::{IdMap, Namespace};
 
 /// Performs type-checking on the whole AST.
 ///
@@ -16,10 +19,18 @@ use crate::ast::TypeKeyword;
 /// error about this?"  Ideally, all errors that can be classified as type errors are reported
 /// to the user during this pass.  Having run this pass, one can feel comfortable simply panicking
 /// when bad types are encountered in other passes like lowering.
+///
+/// Before checking, this also fixes the types of `var` locals that can be inferred from their
+/// initializer or from the first assignment to them.
 pub fn run<A: ast::Visitable>(ast: &A, ctx: &mut CompilerContext) -> Result<(), ErrorReported> {
+    let mut inferrer = LocalTypeInferrer { ctx, undetermined: Default::default(), errors: ErrorFlag::new() };
+    ast.visit_with(&mut inferrer);
+    let inference_errors = inferrer.errors;
+
     let checker = ExprTypeChecker { ctx };
     let mut v = Visitor { checker, errors: ErrorFlag::new(), cur_func_stack: vec![] };
     ast.visit_with(&mut v);
+    inference_errors.into_result(())?;
     v.errors.into_result(())
 }
 
@@ -42,6 +53,123 @@ type ImplResult<T = ()> = Result<T, ErrorReported>;
 
 // =============================================================================
 
+/// Visitor that determines the types of `var` locals, in the order that statements appear.
+///
+/// A `var` with an initializer takes the type of the initializer.  Otherwise, the first plain assignment to
+/// it (one without a sigil on the variable) determines the type.  A `var` that never gets a type this way
+/// remains untyped, and can only be used with sigils.
+struct LocalTypeInferrer<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+    /// `var` locals without a type yet, and the places where they were read without a sigil.
+    undetermined: IdMap<DefId, Vec<Span>>,
+    errors: ErrorFlag,
+}
+
+impl ast::Visit for LocalTypeInferrer<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        match &stmt.value.kind {
+            ast::StmtKind::Declaration { ty_keyword, vars } if ty_keyword.value == token![var] => {
+                for sp_pat!((var, value)) in vars {
+                    if let Some(value) = value {
+                        self.visit_expr(value);
+                    }
+                    let def_id = self.ctx.resolutions.expect_def(var.name.expect_ident());
+                    if self.ctx.defs.var_inherent_ty(def_id).as_known_ty().is_some() {
+                        continue;  // this pass has already been run on this code
+                    }
+                    match value {
+                        Some(value) => self.infer_from_value(def_id, value),
+                        None => { self.undetermined.insert(def_id, vec![]); },
+                    }
+                }
+            },
+
+            ast::StmtKind::Assignment { var, op, value } if op.value == token![=] && var.ty_sigil.is_none() => {
+                self.visit_expr(value);
+                if let ast::VarName::Normal { ident, .. } = &var.name {
+                    let def_id = self.ctx.resolutions.expect_def(ident);
+                    if let Some(early_reads) = self.undetermined.remove(&def_id) {
+                        for read_span in early_reads {
+                            self.errors.set(self.ctx.emitter.emit(error!(
+                                message("type of '{}' is not known yet", ident),
+                                primary(read_span, "read before its type is known"),
+                                secondary(var, "type is determined here"),
+                            )));
+                        }
+                        self.infer_from_value(def_id, value);
+                    }
+                }
+            },
+
+            _ => ast::walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let (None, ast::VarName::Normal { ident, .. }) = (var.ty_sigil, &var.name) {
+            let def_id = self.ctx.resolutions.expect_def(ident);
+            if let Some(early_reads) = self.undetermined.get_mut(&def_id) {
+                early_reads.push(var.span);
+            }
+        }
+        ast::walk_var(self, var);
+    }
+}
+
+impl LocalTypeInferrer<'_, '_> {
+    fn infer_from_value(&mut self, def_id: DefId, value: &Sp<ast::Expr>) {
+        // if this fails, the type checker will report the problem with the value
+        if let Some(ty) = self.try_compute_ty(value) {
+            self.ctx.set_local_ty(def_id, ty);
+        }
+    }
+
+    /// Like [`ast::Expr::compute_ty`], but for code that has not been type-checked yet.
+    ///
+    /// Returns `None` if the type of the expression is not known.
+    fn try_compute_ty(&self, expr: &ast::Expr) -> Option<ScalarType> {
+        match expr {
+            ast::Expr::Var(var) => self.ctx.var_read_ty_from_ast(var).as_known_ty(),
+
+            ast::Expr::BinOp(a, op, b) => {
+                let a_ty = self.try_compute_ty(a)?;
+                self.try_compute_ty(b)?;
+                Some(ast::Expr::binop_ty_from_arg_ty(op.value, a_ty))
+            },
+
+            ast::Expr::UnOp(op, x) => {
+                let x_ty = self.try_compute_ty(x)?;
+                Some(ast::Expr::unop_ty_from_arg_ty(op.value, x_ty))
+            },
+
+            ast::Expr::Ternary { left, right, .. } => {
+                self.try_compute_ty(right)?;
+                self.try_compute_ty(left)
+            },
+
+            ast::Expr::DiffSwitch(cases) => {
+                let first = cases[0].as_ref().expect("empty first woulda been parse error");
+                self.try_compute_ty(first)
+            },
+
+            ast::Expr::Call(call) => match call.blob() {
+                Some(_) => None,
+                None => self.ctx.func_signature_from_ast(&call.name).ok()?.return_ty.value.as_value_ty(),
+            },
+
+            | ast::Expr::LitInt { .. }
+            | ast::Expr::LitFloat { .. }
+            | ast::Expr::LitString { .. }
+            | ast::Expr::EnumConst { .. }
+            | ast::Expr::XcrementOp { .. }
+            | ast::Expr::LabelProperty { .. }
+            => expr.compute_ty(self.ctx).as_value_ty(),
+        }
+    }
+}
+
+// =============================================================================
+
 struct ExprTypeChecker<'a, 'ctx> {
     ctx: &'a CompilerContext<'ctx>,
 }
@@ -115,6 +243,12 @@ impl ast::Visit for Visitor<'_, '_> {
     }
 
     fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        if let Some(diff_label) = &stmt.diff_label {
+            if let Err(e) = self.check_diff_label(diff_label) {
+                self.errors.set(e);
+            }
+        }
+
         match &stmt.value.kind {
             // statement types where there's nothing additional to check beyond what
             // would already be done by recursively walking the node
@@ -145,7 +279,7 @@ impl ast::Visit for Visitor<'_, '_> {
                 }
             },
 
-            ast::StmtKind::Times { clobber, count, block, keyword: _, loop_id: _ } => {
+            ast::StmtKind::Times { clobber, count, block, keyword: _, inline_keyword: _, loop_id: _ } => {
                 if let Err(e) = self.check_stmt_times(clobber, count) {
                     self.errors.set(e);
                 }
@@ -158,10 +292,19 @@ impl ast::Visit for Visitor<'_, '_> {
                 }
             },
 
-            ast::StmtKind::CallSub { .. } => unimplemented!("need to check arg types against signature"),
+            ast::StmtKind::CallSub { call, async_, at_symbol: _ } => {
+                if let Err(e) = self.check_stmt_call_sub(call, async_) {
+                    self.errors.set(e);
+                }
+            },
+
+            ast::StmtKind::InterruptLabel(id) => {
+                if let Err(e) = self.check_stmt_interrupt_label(id) {
+                    self.errors.set(e);
+                }
+            },
 
             ast::StmtKind::Block { .. } => {},
-            ast::StmtKind::InterruptLabel { .. } => {},
             ast::StmtKind::AbsTimeLabel { .. } => {},
             ast::StmtKind::RelTimeLabel { .. } => {},
             ast::StmtKind::Label { .. } => {},
@@ -170,6 +313,9 @@ impl ast::Visit for Visitor<'_, '_> {
         }
     }
 
+    // (checked in visit_stmt, because not every statement is walked)
+    fn visit_diff_label(&mut self, _: &Sp<ast::DiffLabel>) {}
+
     fn visit_jump(&mut self, jump: &ast::StmtJumpKind) {
         ast::walk_jump(self, jump);
 
@@ -224,9 +370,31 @@ impl Visitor<'_, '_> {
         expr: &Sp<ast::Expr>,
     ) -> ImplResult {
         let ty = self.check_expr(expr)?;
+        // an instruction that produces a value may still be called just for its side effects
+        if let ast::Expr::Call(call) = &expr.value {
+            if self.ctx.func_opcode_from_ast(&call.name).is_ok() {
+                return Ok(());
+            }
+        }
         self.require_void(ty, expr.span, "expression statements must be of void type")
     }
 
+    fn check_stmt_call_sub(
+        &self,
+        call: &ast::ExprCall,
+        async_: &Option<ast::CallAsyncKind>,
+    ) -> ImplResult {
+        let call_result = self.check_expr_call(call).map(|_| ());
+        let id_result = match async_ {
+            Some(ast::CallAsyncKind::CallAsyncId { keyword, id }) => {
+                self.check_expr_as_value(id, keyword.span)
+                    .and_then(|id_ty| self.require_int(id_ty, keyword.span, id.span))
+            },
+            _ => Ok(()),
+        };
+        call_result.and(id_result)
+    }
+
     fn check_stmt_times(
         &self,
         clobber: &Option<Sp<ast::Var>>,
@@ -242,6 +410,26 @@ impl Visitor<'_, '_> {
         Ok(())
     }
 
+    fn check_stmt_interrupt_label(&self, id: &Sp<ast::Expr>) -> ImplResult {
+        let ty = self.check_expr_as_value(id, id.span)?;
+        self.require_int(ty, id.span, id.span)?;
+        Ok(())
+    }
+
+    fn check_diff_label(&self, diff_label: &Sp<ast::DiffLabel>) -> ImplResult {
+        if let Some(expr) = &diff_label.expr {
+            let ty = self.check_expr_as_value(expr, expr.span)?;
+            if ty == ScalarType::Float {
+                return Err(self.emit(error!(
+                    message("type error"),
+                    primary(expr, "{}", ty.descr()),
+                    note("a difficulty label must be a string of difficulty flags or an integer mask"),
+                )));
+            }
+        }
+        Ok(())
+    }
+
     fn check_stmt_return(
         &mut self,
         return_keyword: ast::TokenSpan,
@@ -377,7 +565,7 @@ impl ExprTypeChecker<'_, '_> {
                 ExprType::Value(first_ty?)
             },
 
-            ast::Expr::LabelProperty { keyword: _, label: _ }
+            ast::Expr::LabelProperty { .. }
             => ExprType::Value(ScalarType::Int),
 
             ast::Expr::Call(ref call)
@@ -435,7 +623,14 @@ impl ExprTypeChecker<'_, '_> {
                 Err(_) => { err.note(format!("consider adding an explicit type to its declaration")); },
                 Ok((lang, reg)) => {
                     if matches!(self.ctx.defs.reg_inherent_ty(lang, reg), VarType::Untyped { explicit: false }) {
+                        if let ast::VarName::Normal { ident, .. } = &var.name {
+                            let def_id = self.ctx.resolutions.expect_def(ident);
+                            if let Some(alias_span) = self.ctx.defs.definition(Namespace::Vars, def_id).span {
+                                err.secondary(alias_span, format!("alias of {} {reg} defined here", lang.descr()));
+                            }
+                        }
                         err.note(format!("consider adding {reg} to !gvar_types in your mapfile"));
+                        err.note(format!("e.g. '{reg} $' for an integer or '{reg} %' for a float"));
                     }
                 },
             };
@@ -498,21 +693,30 @@ impl ExprTypeChecker<'_, '_> {
                 true => format!("{}", min_args),
                 false => format!("{} to {}", min_args, max_args),
             };
-            return Err(self.emit(error!(
-                message("wrong number of arguments to '{}'", name),
+            let mut diag = error!(
+                message("wrong number of arguments to '{}'", self.ctx.stringify_for_diagnostic(&name.value)),
                 primary(name, "expects {} arguments, got {}", range_str, args.len()),
-            )));
+            );
+            self.add_signature_loc(&mut diag, name);
+            return Err(self.emit(diag));
         }
 
         zip!(1.., args, &siggy.params).map(|(param_num, arg, param)| {
             let arg_ty = self.check_expr_as_value(arg, name.span)?;
             if let VarType::Typed(param_ty) = param.ty.value {
                 if arg_ty != param_ty {
-                    return Err(self.emit(error!(
+                    let mut diag = error!(
                         message("type error"),
                         primary(arg.span, "{}", arg_ty.descr()),
                         secondary(name, "expects {} for parameter {}", param_ty.descr(), param_num),
-                    )));
+                    );
+                    self.add_signature_loc(&mut diag, name);
+                    return Err(self.emit(diag));
+                }
+            }
+            if let Some(TypeColor::Enum(enum_name)) = param.ty_color.as_ref().map(|color| &color.value) {
+                if enum_name == &auto_enum_names::bool() {
+                    self.check_bool_arg(arg, name, param_num);
                 }
             }
             Ok(())
@@ -522,6 +726,31 @@ impl ExprTypeChecker<'_, '_> {
 
         Ok(siggy.return_ty.value)
     }
+
+    /// Warn about a constant boolean argument that isn't `0` or `1`.
+    fn check_bool_arg(&self, arg: &Sp<ast::Expr>, name: &Sp<ast::CallableName>, param_num: usize) {
+        let value = match arg.value {
+            ast::Expr::LitInt { value, .. } => value,
+            _ => return,  // not a constant, or not known yet
+        };
+        if !matches!(value, 0 | 1) {
+            let mut diag = warning!(
+                message("non-boolean value {value} for boolean parameter"),
+                primary(arg, "not 0 or 1"),
+                secondary(name, "expects a bool for parameter {param_num}"),
+            );
+            self.add_signature_loc(&mut diag, name);
+            self.emit(diag).ignore();
+        }
+    }
+
+    /// Point out where the signature of a function was defined, so that the user can tell which
+    /// mapfile entry (or declaration) is responsible for an argument mismatch.
+    fn add_signature_loc(&self, diag: &mut Diagnostic, name: &Sp<ast::CallableName>) {
+        if let Some(abi_loc) = self.ctx.func_signature_loc_from_ast(name) {
+            abi_loc.add_to_diagnostic(diag);
+        }
+    }
 }
 
 impl ast::Expr {
@@ -697,6 +926,7 @@ impl ExprTypeChecker<'_, '_> {
         match kind.value {
             token![pop] |
             token![arg0] |
+            token![nargs] |
             token![mask] => self.require_int(value_ty, kind.span, value_span),
             token![blob] => self.require_string(value_ty, kind.span, value_span),
         }
@@ -0,0 +1,136 @@
+//! See [`run`].
+
+use crate::error::ErrorReported;
+use crate::ast::{self, Visit};
+use crate::context::CompilerContext;
+use crate::diagnostic::Emitter;
+use crate::pos::Sp;
+
+/// Warns about statements that can never execute.
+///
+/// Within each block, everything after an unconditional `goto`, `return`, or `break` (or a statement that
+/// can't finish, like a `loop` with no `break`) is unreachable up until the next label, time label, or
+/// interrupt label.  Only the first unreachable statement of each such run is reported.
+///
+/// This only produces warnings; it does not remove any code.
+///
+/// To use this, you must call a method whose scope is at least as large as [`Visit::visit_root_block`].
+pub fn run<V: ast::Visitable>(ast: &V, ctx: &CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut visitor = Visitor { emitter: ctx.emitter };
+    ast.visit_with(&mut visitor);
+    Ok(())
+}
+
+struct Visitor<'a, E> {
+    emitter: &'a E,
+}
+
+impl<E: Emitter> Visit for Visitor<'_, E> {
+    fn visit_block(&mut self, block: &ast::Block) {
+        let mut diverged_at = None::<&Sp<ast::Stmt>>;
+        for stmt in &block.0 {
+            if is_reentry_point(stmt) {
+                diverged_at = None;
+            }
+
+            if let Some(diverging_stmt) = diverged_at {
+                if !is_ignorable(stmt) {
+                    self.emitter.emit(warning!(
+                        message("unreachable {}", stmt.kind.descr()),
+                        primary(stmt, "this can never execute"),
+                        secondary(diverging_stmt, "any code after this {} is unreachable", diverging_stmt.kind.descr()),
+                    )).ignore();
+                    // only report the first statement of each unreachable run
+                    diverged_at = None;
+                    continue;
+                }
+            }
+
+            if stmt_diverges(stmt) {
+                diverged_at = Some(stmt);
+            }
+        }
+
+        ast::walk_block(self, block);
+    }
+}
+
+/// Statements that are not code, and therefore should not be reported as unreachable.
+fn is_ignorable(stmt: &ast::Stmt) -> bool {
+    matches!(stmt.kind, ast::StmtKind::Item(_) | ast::StmtKind::ScopeEnd(_) | ast::StmtKind::NoInstruction)
+}
+
+/// Statements after which control can resume despite the previous statement diverging.
+///
+/// A statement that contains a label (e.g. a block with a label inside) counts, as something may jump there.
+fn is_reentry_point(stmt: &Sp<ast::Stmt>) -> bool {
+    struct LabelFinder(bool);
+    impl Visit for LabelFinder {
+        fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+            match stmt.kind {
+                ast::StmtKind::Label(_) |
+                ast::StmtKind::AbsTimeLabel(_) |
+                ast::StmtKind::RelTimeLabel { .. } |
+                ast::StmtKind::InterruptLabel(_) => self.0 = true,
+                _ => ast::walk_stmt(self, stmt),
+            }
+        }
+
+        // labels in nested functions are unrelated
+        fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+    }
+
+    let mut finder = LabelFinder(false);
+    finder.visit_stmt(stmt);
+    finder.0
+}
+
+/// Determine whether control can never proceed past the end of this statement.
+fn stmt_diverges(stmt: &Sp<ast::Stmt>) -> bool {
+    if stmt.diff_label.is_some() {
+        return false;  // it may be skipped on some difficulties
+    }
+
+    match &stmt.kind {
+        ast::StmtKind::Jump(_) => true,
+        ast::StmtKind::Return { .. } => true,
+        ast::StmtKind::Block(block) => block_diverges(block),
+        ast::StmtKind::CondChain(chain) => {
+            chain.else_block.as_ref().is_some_and(block_diverges)
+                && chain.cond_blocks.iter().all(|cond_block| block_diverges(&cond_block.block))
+        },
+        ast::StmtKind::Loop { block, .. } => !contains_break(block),
+        _ => false,
+    }
+}
+
+fn block_diverges(block: &ast::Block) -> bool {
+    let mut diverged = false;
+    for stmt in &block.0 {
+        if is_reentry_point(stmt) {
+            diverged = false;
+        }
+        if stmt_diverges(stmt) {
+            diverged = true;
+        }
+    }
+    diverged
+}
+
+/// Check for a `break` anywhere in a loop body.  (even one that belongs to an inner loop, to be conservative)
+fn contains_break(block: &ast::Block) -> bool {
+    struct BreakFinder(bool);
+    impl Visit for BreakFinder {
+        fn visit_jump(&mut self, jump: &ast::StmtJumpKind) {
+            if let ast::StmtJumpKind::BreakContinue { .. } = jump {
+                self.0 = true;
+            }
+        }
+
+        fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+    }
+
+    let mut finder = BreakFinder(false);
+    finder.visit_block(block);
+    finder.0
+}
@@ -0,0 +1,178 @@
+//! See [`run`].
+
+use std::collections::HashMap;
+
+use crate::error::{ErrorReported, ErrorFlag};
+use crate::ast::{self, Visit, VisitMut};
+use crate::context::CompilerContext;
+use crate::ident::Ident;
+use crate::pos::Sp;
+use crate::resolve::LoopId;
+
+/// The default value of [`CompilerContext::max_unroll_count`].
+pub const DEFAULT_MAX_UNROLL_COUNT: u32 = 32;
+
+/// Unrolls `times(inline n)` loops.
+///
+/// The loop is replaced with `n` copies of its body, so that no counter variable or jumps are needed.
+/// (any clobber variable is simply never written to)  A `break` inside the loop becomes a `goto` to just
+/// after the final copy.
+///
+/// The count must be a compile-time constant no larger than [`CompilerContext::max_unroll_count`],
+/// and the body may not contain labels (as they would be duplicated).  Otherwise an error is reported.
+///
+/// This must be run after [const simplification](crate::passes::const_simplify) and before
+/// [block desugaring](crate::passes::desugar_blocks).
+pub fn run<V: ast::Visitable>(ast: &mut V, ctx: &mut CompilerContext<'_>) -> Result<(), ErrorReported> {
+    let mut visitor = Visitor { ctx, errors: ErrorFlag::new() };
+    ast.visit_mut_with(&mut visitor);
+    visitor.errors.into_result(())
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a mut CompilerContext<'ctx>,
+    errors: ErrorFlag,
+}
+
+impl VisitMut for Visitor<'_, '_> {
+    fn visit_stmt(&mut self, stmt: &mut Sp<ast::Stmt>) {
+        // unroll inner loops first so that their copies get cloned along with everything else
+        ast::walk_stmt_mut(self, stmt);
+
+        if let ast::StmtKind::Times { inline_keyword: Some(_), .. } = stmt.kind {
+            match self.unroll(stmt) {
+                Ok(kind) => stmt.kind = kind,
+                Err(e) => self.errors.set(e),
+            }
+        }
+    }
+}
+
+impl Visitor<'_, '_> {
+    fn unroll(&mut self, stmt: &Sp<ast::Stmt>) -> Result<ast::StmtKind, ErrorReported> {
+        let (count, block, loop_id) = match &stmt.kind {
+            ast::StmtKind::Times { count, block, loop_id, .. } => (count, block, loop_id.expect("loop has no loop id")),
+            _ => unreachable!(),
+        };
+
+        let count_value = match count.as_const_int() {
+            Some(value) => value,
+            None => return Err(self.ctx.emitter.emit(error!(
+                message("cannot unroll loop with non-constant count"),
+                primary(count, "not a compile-time constant"),
+            ))),
+        };
+        if count_value > 0 && count_value as u32 > self.ctx.max_unroll_count {
+            return Err(self.ctx.emitter.emit(error!(
+                message("too many iterations to unroll"),
+                primary(count, "{} iterations", count_value),
+                note("the limit is {}", self.ctx.max_unroll_count),
+            )));
+        }
+        if let Some(label) = find_label(block) {
+            return Err(self.ctx.emitter.emit(error!(
+                message("cannot unroll loop containing a label"),
+                primary(label, "would be duplicated"),
+            )));
+        }
+
+        let mut body = block.clone();
+        let end_label = self.ctx.gensym.gensym("@unroll_end#");
+        let mut break_visitor = BreakToGotoVisitor { loop_id, end_label: &end_label, found_break: false };
+        break_visitor.visit_block(&mut body);
+        let found_break = break_visitor.found_break;
+
+        let mut out = vec![];
+        for _ in 0..count_value.max(0) {
+            let mut copy = body.clone();
+            crate::passes::resolution::refresh_node_ids(&mut copy, &self.ctx.unused_node_ids)?;
+            refresh_loop_ids(&mut copy, self.ctx);
+
+            out.push(sp!(stmt.span => ast::Stmt {
+                node_id: Some(self.ctx.next_node_id()),
+                diff_label: None,
+                kind: ast::StmtKind::Block(copy),
+            }));
+        }
+
+        if found_break {
+            let end_span = block.end_span();
+            out.push(sp!(end_span => ast::Stmt {
+                node_id: Some(self.ctx.next_node_id()),
+                diff_label: None,
+                kind: ast::StmtKind::Label(sp!(end_span => end_label)),
+            }));
+        }
+        Ok(ast::StmtKind::Block(ast::Block(out)))
+    }
+}
+
+fn find_label(block: &ast::Block) -> Option<Sp<Ident>> {
+    struct LabelFinder(Option<Sp<Ident>>);
+    impl Visit for LabelFinder {
+        fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+            match &stmt.kind {
+                ast::StmtKind::Label(ident) => if self.0.is_none() {
+                    self.0 = Some(ident.clone());
+                },
+                _ => ast::walk_stmt(self, stmt),
+            }
+        }
+
+        fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+    }
+
+    let mut finder = LabelFinder(None);
+    finder.visit_block(block);
+    finder.0
+}
+
+/// Replaces each `break` belonging to the unrolled loop with a `goto`.
+struct BreakToGotoVisitor<'a> {
+    loop_id: LoopId,
+    end_label: &'a Ident,
+    found_break: bool,
+}
+
+impl VisitMut for BreakToGotoVisitor<'_> {
+    fn visit_jump(&mut self, jump: &mut ast::StmtJumpKind) {
+        if let ast::StmtJumpKind::BreakContinue { keyword: sp_pat![kw_span => token![break]], loop_id: Some(loop_id) } = *jump {
+            if loop_id == self.loop_id {
+                self.found_break = true;
+                *jump = ast::StmtJumpKind::Goto(ast::StmtGoto {
+                    destination: sp!(kw_span => self.end_label.clone()),
+                    time: None,
+                });
+            }
+        }
+    }
+
+    fn visit_item(&mut self, _: &mut Sp<ast::Item>) {}
+}
+
+/// Gives every loop inside a copy of some code a fresh [`LoopId`], as later passes require them to be unique.
+pub(crate) fn refresh_loop_ids(block: &mut ast::Block, ctx: &CompilerContext<'_>) {
+    RefreshLoopIdsVisitor { ctx, new_ids: HashMap::new() }.visit_block(block);
+}
+
+struct RefreshLoopIdsVisitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+    new_ids: HashMap<LoopId, LoopId>,
+}
+
+impl VisitMut for RefreshLoopIdsVisitor<'_, '_> {
+    fn visit_loop_begin(&mut self, loop_id: &mut Option<LoopId>) {
+        let old_id = loop_id.expect("loop has no loop id");
+        let new_id = self.ctx.next_loop_id();
+        self.new_ids.insert(old_id, new_id);
+        *loop_id = Some(new_id);
+    }
+
+    fn visit_jump(&mut self, jump: &mut ast::StmtJumpKind) {
+        if let ast::StmtJumpKind::BreakContinue { loop_id: Some(loop_id), .. } = jump {
+            *loop_id = self.new_ids[loop_id];
+        }
+    }
+
+    fn visit_item(&mut self, _: &mut Sp<ast::Item>) {}
+}
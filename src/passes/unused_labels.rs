@@ -62,7 +62,7 @@ pub fn get_label_refcounts(block: &[Sp<ast::Stmt>]) -> HashMap<Ident, u32> {
             ast::walk_expr(self, expr);
             match &expr.value {
                 // count offsetof(label) and timeof(label)
-                ast::Expr::LabelProperty { label, .. } => {
+                ast::Expr::LabelProperty { script: None, label, .. } => {
                     *self.0.entry(label.value.clone()).or_insert(0) += 1;
                 },
                 _ => {},
@@ -1,11 +1,11 @@
 //! See [`run`].
 
-use crate::context::CompilerContext;
+use crate::context::{CompilerContext, DiffFlagDefs};
 use crate::error::{ErrorFlag, ErrorReported};
 use crate::ast::{self, Visit};
 use crate::diagnostic::Emitter;
 use crate::passes::semantics::time_and_difficulty::{TimeAndDifficultyHelper};
-use crate::llir::LanguageHooks;
+use crate::llir::{LanguageHooks, IntrinsicInstrKind};
 use crate::pos::Sp;
 
 /// Scans the AST for improper usage of difficulty-related constructs.
@@ -26,6 +26,23 @@ pub fn forbid_difficulty<V: ast::Visitable>(ast: &V, ctx: &CompilerContext<'_>)
     visitor.errors.into_result(())
 }
 
+/// Handles difficulty in a language whose instructions have no difficulty mask of their own.
+///
+/// Such a language can only use difficulty labels if its mapfile declares a
+/// [`DifficultyMask`](crate::llir::IntrinsicInstrKind::DifficultyMask) intrinsic.  If it does, this computes
+/// the masks of difficulty labels and then behaves like [`run`]; otherwise it behaves like [`forbid_difficulty`].
+pub fn run_if_supported<V: ast::Visitable>(ast: &mut V, ctx: &CompilerContext<'_>, hooks: &dyn LanguageHooks) -> Result<(), ErrorReported> {
+    let has_mask_instr = ctx.defs.iter_intrinsic_instrs(hooks.language())
+        .any(|(_, kind)| kind.value == IntrinsicInstrKind::DifficultyMask);
+    match has_mask_instr {
+        true => {
+            crate::passes::resolution::compute_diff_label_masks(ast, ctx)?;
+            run(ast, ctx, hooks)
+        },
+        false => forbid_difficulty(ast, ctx),
+    }
+}
+
 struct Visitor<'a, 'ctx> {
     helper: TimeAndDifficultyHelper,
     errors: ErrorFlag,
@@ -35,10 +52,30 @@ struct Visitor<'a, 'ctx> {
 
 impl Visit for Visitor<'_, '_> {
     fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        let outer_mask = self.helper.difficulty_mask_if_nontrivial();
         self.helper.enter_stmt(stmt);
 
-        // check diff labels
-        if has_unintuitive_interaction_with_difficulty(&stmt.kind) {
+        // an inner label that shares no difficulties with the outer label
+        if let (Some(outer_mask), Some(diff_label)) = (outer_mask, &stmt.diff_label) {
+            let difficulty_bits = self.ctx.diff_flag_defs.difficulty_bits();
+            let own_mask = diff_label.mask.expect("compute_diff_label_masks pass was not run!");
+            if !(own_mask & difficulty_bits).is_empty() && (self.helper.difficulty_mask() & difficulty_bits).is_empty() {
+                self.ctx.emitter.emit(warning!(
+                    message("this statement can never execute"),
+                    primary(diff_label, "no difficulties in common with the outer label"),
+                    secondary(outer_mask, "outer difficulty label"),
+                )).ignore();
+            }
+        }
+
+        // statements that produce no code of their own can't be filtered by difficulty
+        let unsupported_label = stmt.diff_label.as_ref().filter(|_| !supports_difficulty_label(&stmt.kind));
+        if let Some(diff_label) = unsupported_label {
+            self.errors.set(self.ctx.emitter.emit(error!(
+                message("difficulty label not supported on {}", stmt.kind.descr()),
+                primary(diff_label, "difficulty label"),
+            )));
+        } else if has_unintuitive_interaction_with_difficulty(&stmt.kind) {
             if let Some(diff_label_mask) = self.helper.difficulty_mask_if_nontrivial() {
                 let mut diag = warning!(
                     message("{} inside difficulty label may have surprising behavior", stmt.kind.descr()),
@@ -60,7 +97,7 @@ impl Visit for Visitor<'_, '_> {
         // fail on bad diff switches
         let mut switch_checker = SwitchLenChecker::default();
         switch_checker.visit_stmt(stmt);
-        if let Err(e) = switch_checker.into_result(self.ctx.emitter) {
+        if let Err(e) = switch_checker.into_result(self.ctx.emitter, &self.ctx.diff_flag_defs) {
             self.errors.set(e)
         }
 
@@ -83,6 +120,38 @@ impl Visit for Visitor<'_, '_> {
     }
 }
 
+/// Whether a difficulty label can be written directly on a statement.
+///
+/// Statements that lower to a single instruction apply the mask to that instruction, while structured
+/// statements apply it to everything they expand into.  The rest don't compile to anything that a
+/// mask could apply to.
+fn supports_difficulty_label(stmt: &ast::StmtKind) -> bool {
+    match stmt {
+        | ast::StmtKind::Jump { .. }
+        | ast::StmtKind::CondJump { .. }
+        | ast::StmtKind::Return { .. }
+        | ast::StmtKind::Expr { .. }
+        | ast::StmtKind::Assignment { .. }
+        | ast::StmtKind::Declaration { .. }
+        | ast::StmtKind::CallSub { .. }
+        | ast::StmtKind::InterruptLabel { .. }
+        | ast::StmtKind::Block { .. }
+        | ast::StmtKind::CondChain { .. }
+        | ast::StmtKind::Loop { .. }
+        | ast::StmtKind::While { .. }
+        | ast::StmtKind::Times { .. }
+        => true,
+
+        | ast::StmtKind::Item { .. }
+        | ast::StmtKind::Label { .. }
+        | ast::StmtKind::AbsTimeLabel { .. }
+        | ast::StmtKind::RelTimeLabel { .. }
+        | ast::StmtKind::ScopeEnd { .. }
+        | ast::StmtKind::NoInstruction { .. }
+        => false,
+    }
+}
+
 fn has_unintuitive_interaction_with_difficulty(stmt: &ast::StmtKind) -> bool {
     match stmt {
         | ast::StmtKind::Item { .. }
@@ -106,7 +175,7 @@ fn has_unintuitive_interaction_with_difficulty(stmt: &ast::StmtKind) -> bool {
         => true,
 
         // control flow can enter even an `if (false)` block!
-        // this is observable if an inner difficulty label re-enables a flag.
+        // this is observable if the block contains time labels.
         | ast::StmtKind::CondChain { .. }
         => true,
 
@@ -121,7 +190,7 @@ fn has_unintuitive_interaction_with_difficulty(stmt: &ast::StmtKind) -> bool {
 
 // =============================================================================
 
-/// For checking that switches have equal length within a
+/// For checking that switches within a statement have equal length, and that it matches the number of difficulties.
 #[derive(Default)]
 struct SwitchLenChecker {
     first_len: Option<Sp<usize>>,
@@ -144,7 +213,7 @@ impl Visit for SwitchLenChecker {
 }
 
 impl SwitchLenChecker {
-    fn into_result(self, emitter: &dyn Emitter) -> Result<(), ErrorReported> {
+    fn into_result(self, emitter: &dyn Emitter, diff_flag_defs: &DiffFlagDefs) -> Result<(), ErrorReported> {
         if let Some(second_len) = self.second_len {
             let first_len = self.first_len.unwrap();
             return Err(emitter.as_sized().emit(error!(
@@ -155,12 +224,30 @@ impl SwitchLenChecker {
         }
 
         if let Some(first_len) = self.first_len {
-            if first_len > 8 {
-                return Err(emitter.as_sized().emit(error!(
+            let max_len = diff_flag_defs.max_diff_switch_cases();
+            if first_len.value > max_len {
+                let mut diag = error!(
                     message("too many cases in diff switch"),
                     primary(first_len, "{} cases", first_len),
-                    note("difficulty masks are only 8 bits wide"),
-                )));
+                );
+                match diff_flag_defs.all_flags_are_difficulties() {
+                    true => diag.note("difficulty masks are only 8 bits wide".to_string()),
+                    false => diag.note(format!("only {max_len} difficulty levels are defined by the `!difficulty_flags` in the mapfile")),
+                };
+                return Err(emitter.as_sized().emit(diag));
+            }
+
+            let expected_len = diff_flag_defs.num_diff_switch_cases();
+            if first_len.value != expected_len {
+                let mut diag = error!(
+                    message("wrong number of cases in diff switch"),
+                    primary(first_len, "{} cases, expected {}", first_len, expected_len),
+                );
+                match first_len.value < expected_len {
+                    true => diag.note("trailing cases can be left empty to reuse the last case, as in `(1:2::)`".to_string()),
+                    false => diag.note(format!("there are {expected_len} difficulty levels")),
+                };
+                return Err(emitter.as_sized().emit(diag));
             }
         }
 
@@ -0,0 +1,67 @@
+//! See [`run`].
+
+use std::collections::HashMap;
+
+use crate::Ident;
+use crate::ast::{self, Visit};
+use crate::diagnostic::Emitter;
+use crate::passes::semantics::time_and_difficulty::TimeAndDifficulty;
+use crate::pos::{Sp, Span};
+use crate::resolve::{NodeId, IdMap};
+
+/// Warns about gotos whose explicit time differs from the time at their destination label.
+///
+/// `goto label @ 30;` where `label:` is at time 60 will run the statements between times 30 and 60
+/// again, which is rarely intended.  Decompiled code sometimes does this on purpose, so this is only
+/// a warning.  Gotos without an explicit time use the label's time, so they always match.
+///
+/// This should be called on the body of a single function, after [time assignment](crate::passes::semantics::time_and_difficulty).
+/// Nested functions are not checked.
+pub fn run(code: &[Sp<ast::Stmt>], stmt_data: &IdMap<NodeId, TimeAndDifficulty>, emitter: &impl Emitter) {
+    let mut label_visitor = LabelTimeVisitor { stmt_data, label_times: HashMap::new() };
+    code.iter().for_each(|stmt| label_visitor.visit_stmt(stmt));
+
+    let mut goto_visitor = GotoVisitor { label_times: &label_visitor.label_times, emitter };
+    code.iter().for_each(|stmt| goto_visitor.visit_stmt(stmt));
+}
+
+struct LabelTimeVisitor<'a> {
+    stmt_data: &'a IdMap<NodeId, TimeAndDifficulty>,
+    label_times: HashMap<Ident, (Span, i32)>,
+}
+
+impl Visit for LabelTimeVisitor<'_> {
+    fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        if let ast::StmtKind::Label(ident) = &stmt.kind {
+            let time = self.stmt_data[&stmt.node_id.unwrap()].time;
+            self.label_times.insert(ident.value.clone(), (ident.span, time));
+        }
+        ast::walk_stmt(self, stmt);
+    }
+
+    fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+}
+
+struct GotoVisitor<'a, E> {
+    label_times: &'a HashMap<Ident, (Span, i32)>,
+    emitter: &'a E,
+}
+
+impl<E: Emitter> Visit for GotoVisitor<'_, E> {
+    fn visit_jump(&mut self, jump: &ast::StmtJumpKind) {
+        if let ast::StmtJumpKind::Goto(ast::StmtGoto { destination, time: Some(goto_time) }) = jump {
+            if let Some(&(label_span, label_time)) = self.label_times.get(&destination.value) {
+                if goto_time.value != label_time {
+                    self.emitter.emit(warning!(
+                        message("goto time does not match the time at its label"),
+                        primary(goto_time, "jumps with time {}", goto_time),
+                        secondary(label_span, "label is at time {}", label_time),
+                    )).ignore();
+                }
+            }
+        }
+        ast::walk_jump(self, jump);
+    }
+
+    fn visit_item(&mut self, _: &Sp<ast::Item>) {}
+}
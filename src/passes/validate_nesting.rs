@@ -0,0 +1,137 @@
+//! See [`run`].
+
+use crate::ast::{self, Visit, VisitMut};
+use crate::diagnostic::Diagnostic;
+use crate::pos::{Sp, Span};
+
+/// The default value of [`CompilerContext::max_nesting_depth`](crate::context::CompilerContext::max_nesting_depth).
+pub const DEFAULT_MAX_NESTING_DEPTH: u32 = 256;
+
+/// Checks that no block or expression is nested more than `max_depth` levels deep.
+///
+/// Most passes recurse through the AST, so something like a sum of many thousands of terms would overflow
+/// the stack.  This pass recurses only through blocks, and stops at the first one that is too deep, so it
+/// can be run on any AST.  The parser runs it on everything it parses; ASTs constructed in other ways get
+/// it from [`Truth::fill_missing_ids`](crate::Truth::fill_missing_ids).
+pub fn run<V: ast::Visitable>(ast: &V, max_depth: u32) -> Result<(), Diagnostic> {
+    let mut visitor = Visitor { max_depth, block_depth: 0, stmt_span: Span::NULL, too_deep: None };
+    ast.visit_with(&mut visitor);
+    match visitor.too_deep {
+        Some(span) => Err(nesting_error(span, max_depth)),
+        None => Ok(()),
+    }
+}
+
+/// Drops an AST that may be too deeply nested to be dropped recursively, such as one rejected by [`run`].
+pub fn drop_deep<V: ast::Visitable>(mut ast: V) {
+    ast.visit_mut_with(&mut Dropper);
+}
+
+/// The error for something nested more than `max_depth` levels deep.
+pub fn nesting_error(span: Span, max_depth: u32) -> Diagnostic {
+    error!(
+        message("nesting limit exceeded"),
+        primary(span, "nested more than {max_depth} levels deep"),
+    )
+}
+
+struct Visitor {
+    max_depth: u32,
+    block_depth: u32,
+    /// Span of the statement containing the current block.
+    stmt_span: Span,
+    too_deep: Option<Span>,
+}
+
+impl Visit for Visitor {
+    fn visit_block(&mut self, block: &ast::Block) {
+        if self.too_deep.is_some() {
+            return;
+        }
+        self.block_depth += 1;
+        if self.block_depth > self.max_depth {
+            self.too_deep = Some(self.stmt_span);
+        } else {
+            ast::walk_block(self, block);
+        }
+        self.block_depth -= 1;
+    }
+
+    fn visit_stmt(&mut self, stmt: &Sp<ast::Stmt>) {
+        let outer_span = std::mem::replace(&mut self.stmt_span, stmt.span);
+        ast::walk_stmt(self, stmt);
+        self.stmt_span = outer_span;
+    }
+
+    fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
+        if self.too_deep.is_some() {
+            return;
+        }
+        // use an explicit stack instead of walking the expression
+        let mut stack = vec![(expr, 1)];
+        while let Some((expr, depth)) = stack.pop() {
+            if depth > self.max_depth {
+                self.too_deep = Some(operator_span(expr));
+                return;
+            }
+            match &expr.value {
+                ast::Expr::Ternary { cond, left, right, .. } => stack.extend([&**cond, &**left, &**right].map(|x| (x, depth + 1))),
+                ast::Expr::BinOp(a, _, b) => stack.extend([&**a, &**b].map(|x| (x, depth + 1))),
+                ast::Expr::UnOp(_, x) => stack.push((x, depth + 1)),
+                ast::Expr::DiffSwitch(cases) => stack.extend(cases.iter().flatten().map(|x| (x, depth + 1))),
+                ast::Expr::Call(ast::ExprCall { args, pseudos, .. }) => {
+                    stack.extend(args.iter().map(|x| (x, depth + 1)));
+                    stack.extend(pseudos.iter().map(|pseudo| (&pseudo.value.value, depth + 1)));
+                },
+                ast::Expr::XcrementOp { .. } |
+                ast::Expr::LitInt { .. } |
+                ast::Expr::LitFloat { .. } |
+                ast::Expr::LitString(_) |
+                ast::Expr::LabelProperty { .. } |
+                ast::Expr::EnumConst { .. } |
+                ast::Expr::Var(_) => {},
+            }
+        }
+    }
+}
+
+struct Dropper;
+
+impl VisitMut for Dropper {
+    fn visit_expr(&mut self, expr: &mut Sp<ast::Expr>) {
+        // move the children of each expression out into an explicit stack, so that each one is
+        // dropped with no children left
+        let placeholder = ast::Expr::LitInt { value: 0, radix: ast::IntRadix::Dec };
+        let mut stack = vec![std::mem::replace(&mut expr.value, placeholder)];
+        while let Some(expr) = stack.pop() {
+            match expr {
+                ast::Expr::Ternary { cond, left, right, .. } => stack.extend([cond, left, right].map(|x| x.value)),
+                ast::Expr::BinOp(a, _, b) => stack.extend([a, b].map(|x| x.value)),
+                ast::Expr::UnOp(_, x) => stack.push(x.value),
+                ast::Expr::DiffSwitch(cases) => stack.extend(cases.into_iter().flatten().map(|x| x.value)),
+                ast::Expr::Call(ast::ExprCall { args, pseudos, .. }) => {
+                    stack.extend(args.into_iter().map(|x| x.value));
+                    stack.extend(pseudos.into_iter().map(|pseudo| pseudo.value.value.value));
+                },
+                ast::Expr::XcrementOp { .. } |
+                ast::Expr::LitInt { .. } |
+                ast::Expr::LitFloat { .. } |
+                ast::Expr::LitString(_) |
+                ast::Expr::LabelProperty { .. } |
+                ast::Expr::EnumConst { .. } |
+                ast::Expr::Var(_) => {},
+            }
+        }
+    }
+}
+
+/// Get a span for the error that is no larger than one line, even for a large expression.
+fn operator_span(expr: &Sp<ast::Expr>) -> Span {
+    match &expr.value {
+        ast::Expr::Ternary { question, .. } => question.span,
+        ast::Expr::BinOp(_, op, _) => op.span,
+        ast::Expr::UnOp(op, _) => op.span,
+        ast::Expr::Call(ast::ExprCall { name, .. }) => name.span,
+        _ => expr.span,
+    }
+}
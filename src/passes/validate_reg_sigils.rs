@@ -0,0 +1,64 @@
+//! See [`run`].
+
+use std::collections::BTreeMap;
+
+use crate::ast::{self, Visit};
+use crate::context::CompilerContext;
+use crate::game::LanguageKey;
+use crate::pos::{Sp, Span};
+use crate::resolve::RegId;
+use crate::value::VarType;
+
+/// Warns about registers with no declared type that are accessed with both `$` and `%`.
+///
+/// Reading the same register as both an integer and a float is occasionally done on purpose (bit tricks),
+/// but is more often a mistyped sigil.  Registers with a type in `!gvar_types` are exempt, since for those
+/// a sigil is a cast.  (this includes `?`, which marks a register as deliberately untyped)  At most one warning is produced per register.
+///
+/// Requires [name resolution](crate::passes::resolution::resolve_names).
+pub fn run<V: ast::Visitable>(ast: &V, ctx: &CompilerContext<'_>) {
+    let mut visitor = Visitor { ctx, accesses: BTreeMap::new() };
+    ast.visit_with(&mut visitor);
+
+    for ((language, reg), accesses) in visitor.accesses {
+        if let (Some(int_span), Some(float_span)) = (accesses.int, accesses.float) {
+            ctx.emitter.emit(warning!(
+                message("{} register {reg} is accessed as both an integer and a float", language.descr()),
+                primary(int_span, "accessed as an integer"),
+                secondary(float_span, "accessed as a float"),
+                note("if this is intentional, add '{reg} ?' to !gvar_types in a mapfile to silence this warning"),
+            )).ignore();
+        }
+    }
+}
+
+/// The first span where a register was accessed with each sigil.
+#[derive(Default)]
+struct SigilAccesses {
+    int: Option<Span>,
+    float: Option<Span>,
+}
+
+struct Visitor<'a, 'ctx> {
+    ctx: &'a CompilerContext<'ctx>,
+    // BTreeMap for consistent ordering of warnings
+    accesses: BTreeMap<(LanguageKey, RegId), SigilAccesses>,
+}
+
+impl Visit for Visitor<'_, '_> {
+    fn visit_var(&mut self, var: &Sp<ast::Var>) {
+        if let Some(sigil) = var.ty_sigil {
+            if let Ok((language, reg)) = self.ctx.var_reg_from_ast(&var.name) {
+                if self.ctx.defs.reg_inherent_ty(language, reg) == (VarType::Untyped { explicit: false }) {
+                    let accesses = self.accesses.entry((language, reg)).or_default();
+                    let first_span = match sigil {
+                        ast::VarSigil::Int => &mut accesses.int,
+                        ast::VarSigil::Float => &mut accesses.float,
+                    };
+                    first_span.get_or_insert(var.span);
+                }
+            }
+        }
+        ast::walk_var(self, var);
+    }
+}
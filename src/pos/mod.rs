@@ -6,9 +6,11 @@ pub type FileId = Option<std::num::NonZeroU32>;
 pub use codespan::{ByteIndex as BytePos, ByteOffset, RawIndex, RawOffset};
 
 pub use span::{Sp, Span, HasSpan};
+#[cfg(feature = "serde")]
+pub(crate) use span::serde_opt_byte_pos;
 #[macro_use] mod span;
 
-pub use source_map::Files;
+pub use source_map::{Files, LineCol, ColumnUnit};
 mod source_map;
 
 pub use source_str::SourceStr;
@@ -1,7 +1,6 @@
 use std::borrow::Cow;
 use std::num::NonZeroU32;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 use codespan_reporting::{files as cs_files};
 
@@ -13,21 +12,50 @@ use crate::debug_info;
 ///
 /// This is the type responsible for keeping track of source text so that snippets can be displayed
 /// in diagnostic error messages.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct Files {
-    inner: RefCell<FilesImpl>,
+    inner: Mutex<FilesImpl>,
+}
+
+impl Clone for Files {
+    fn clone(&self) -> Self {
+        Files { inner: Mutex::new(self.inner.lock().unwrap().clone()) }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct FilesImpl {
-    files: cs_files::SimpleFiles<String, Rc<str>>,
+    files: cs_files::SimpleFiles<String, Arc<str>>,
     max_file_id: FileId,
+    /// Start offsets of lines in each file, built on first use by [`Files::line_col`] and friends.
+    ///
+    /// (codespan has its own line index, but it doesn't recognize a lone `\r` as a line ending)
+    line_starts: Vec<Option<Arc<[usize]>>>,
+}
+
+/// A position in a source file in terms of lines and columns, as used by e.g. text editors.
+///
+/// Both fields are zero-based.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize,
+}
+
+/// The unit used to measure the column of a [`LineCol`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColumnUnit {
+    /// Bytes of UTF-8.
+    Utf8,
+    /// UTF-16 code units.  (this is what the Language Server Protocol uses by default)
+    Utf16,
 }
 
 impl Files {
-    pub fn new() -> Self { Files { inner: RefCell::new(FilesImpl {
+    pub fn new() -> Self { Files { inner: Mutex::new(FilesImpl {
         files: cs_files::SimpleFiles::new(),
         max_file_id: None,
+        line_starts: vec![],
     }) } }
 
     /// Add a piece of source text to the database, and give it a name (usually a filepath)
@@ -35,11 +63,11 @@ impl Files {
     ///
     /// The name does not need to be a valid path or even unique; for instance, it is common to use
     /// the name `"<input>"` for source text not associated with any file.
-    pub fn add(&self, name: &str, source: &[u8]) -> Result<(FileId, Rc<str>), Diagnostic> {
+    pub fn add(&self, name: &str, source: &[u8]) -> Result<(FileId, Arc<str>), Diagnostic> {
         let utf8_cow = prepare_diagnostic_text_source(source);
-        let rc_source: Rc<str> = utf8_cow[..].into();
+        let rc_source: Arc<str> = utf8_cow[..].into();
 
-        let mut inner = self.inner.borrow_mut();
+        let mut inner = self.inner.lock().unwrap();
         let file_id = Self::shift_file_id(inner.files.add(name.to_owned(), rc_source.clone()));
         inner.max_file_id = file_id;
 
@@ -68,10 +96,77 @@ impl Files {
     }
 
     fn file_ids(&self) -> impl Iterator<Item=FileId> {
-        let max_file_id = self.inner.borrow().max_file_id.map_or(0, |x| x.get());
+        let max_file_id = self.inner.lock().unwrap().max_file_id.map_or(0, |x| x.get());
         (1..=max_file_id).map(|x| Some(NonZeroU32::new(x).unwrap()))
     }
 
+    /// Get the source text of a span.
+    ///
+    /// Returns `None` if the span does not belong to a file, or does not lie on character boundaries.
+    pub fn span_text(&self, span: Span) -> Option<String> {
+        let source = cs_files::Files::source(self, span.file_id).ok()?;
+        source.get(span.start.0 as usize..span.end.0 as usize).map(Into::into)
+    }
+
+    /// Convert a byte position in a file into a line and column.
+    ///
+    /// Lines may end in any of `\n`, `\r\n` or `\r`.  Returns `None` if the position is past the end
+    /// of the file or is not on a character boundary.
+    pub fn line_col(&self, file_id: FileId, pos: BytePos, unit: ColumnUnit) -> Option<LineCol> {
+        let (source, line_starts) = self.source_and_line_starts(file_id)?;
+        let pos = pos.0 as usize;
+        let line = line_starts.partition_point(|&start| start <= pos) - 1;
+        let line_prefix = source.get(line_starts[line]..pos)?;
+        Some(LineCol { line, col: unit.len_of(line_prefix) })
+    }
+
+    /// Convert a line and column in a file into a byte position.
+    ///
+    /// Like in the Language Server Protocol, a column past the end of a line refers to the end of that line.
+    /// Returns `None` if the line does not exist, or if the column is in the middle of a character.
+    pub fn byte_pos(&self, file_id: FileId, line_col: LineCol, unit: ColumnUnit) -> Option<BytePos> {
+        let (source, line_starts) = self.source_and_line_starts(file_id)?;
+        let line_start = *line_starts.get(line_col.line)?;
+        let line_end = line_starts.get(line_col.line + 1).copied().unwrap_or(source.len());
+        let line_text = source[line_start..line_end].trim_end_matches(&['\r', '\n'][..]);
+
+        let mut col = 0;
+        for (byte_offset, char) in line_text.char_indices() {
+            if col >= line_col.col {
+                // a column that falls inside of a character is not a valid position
+                return (col == line_col.col).then(|| BytePos((line_start + byte_offset) as _));
+            }
+            col += unit.len_of_char(char);
+        }
+        // past the end of the line (unless the last character was split)
+        (line_col.col >= col).then(|| BytePos((line_start + line_text.len()) as _))
+    }
+
+    /// Convert a span into the line and column of its start and end.  See [`Self::line_col`].
+    pub fn span_to_line_cols(&self, span: Span, unit: ColumnUnit) -> Option<(LineCol, LineCol)> {
+        let start = self.line_col(span.file_id, span.start, unit)?;
+        let end = self.line_col(span.file_id, span.end, unit)?;
+        Some((start, end))
+    }
+
+    /// Construct a span from the line and column of its start and end.  See [`Self::byte_pos`].
+    pub fn span_from_line_cols(&self, file_id: FileId, start: LineCol, end: LineCol, unit: ColumnUnit) -> Option<Span> {
+        let start = self.byte_pos(file_id, start, unit)?;
+        let end = self.byte_pos(file_id, end, unit)?;
+        (start <= end).then(|| Span::new(file_id, start, end))
+    }
+
+    fn source_and_line_starts(&self, file_id: FileId) -> Option<(Arc<str>, Arc<[usize]>)> {
+        let index = Self::unshift_file_id(file_id).ok()?;
+        let mut inner = self.inner.lock().unwrap();
+        let source = inner.files.get(index).ok()?.source().clone();
+        if inner.line_starts.len() <= index {
+            inner.line_starts.resize(index + 1, None);
+        }
+        let line_starts = inner.line_starts[index].get_or_insert_with(|| compute_line_starts(&source).into());
+        Some((source, line_starts.clone()))
+    }
+
     pub fn debug_info(&self) -> Vec<debug_info::SourceFile> {
         self.file_ids().map(|file_id| {
             debug_info::SourceFile {
@@ -87,25 +182,54 @@ impl Files {
 impl<'a> cs_files::Files<'a> for Files {
     type FileId = FileId;
     type Name = String;
-    type Source = Rc<str>;
+    type Source = Arc<str>;
 
     // Just delegate everything
     fn name(&self, file_id: FileId) -> Result<String, cs_files::Error> {
-        self.inner.borrow().files.name(Self::unshift_file_id(file_id)?)
+        self.inner.lock().unwrap().files.name(Self::unshift_file_id(file_id)?)
     }
 
-    fn source(&self, file_id: FileId) -> Result<Rc<str>, cs_files::Error> {
-        Ok(self.inner.borrow().files.get(Self::unshift_file_id(file_id)?)?.source().clone())
+    fn source(&self, file_id: FileId) -> Result<Arc<str>, cs_files::Error> {
+        Ok(self.inner.lock().unwrap().files.get(Self::unshift_file_id(file_id)?)?.source().clone())
     }
 
     fn line_index(&self, file_id: FileId, byte_index: usize) -> Result<usize, cs_files::Error> {
-        self.inner.borrow().files.line_index(Self::unshift_file_id(file_id)?, byte_index)
+        self.inner.lock().unwrap().files.line_index(Self::unshift_file_id(file_id)?, byte_index)
     }
     fn line_range(&self, file_id: FileId, line_index: usize) -> Result<std::ops::Range<usize>, cs_files::Error> {
-        self.inner.borrow().files.line_range(Self::unshift_file_id(file_id)?, line_index)
+        self.inner.lock().unwrap().files.line_range(Self::unshift_file_id(file_id)?, line_index)
     }
 }
 
+impl ColumnUnit {
+    fn len_of(self, s: &str) -> usize {
+        match self {
+            ColumnUnit::Utf8 => s.len(),
+            ColumnUnit::Utf16 => s.encode_utf16().count(),
+        }
+    }
+
+    fn len_of_char(self, c: char) -> usize {
+        match self {
+            ColumnUnit::Utf8 => c.len_utf8(),
+            ColumnUnit::Utf16 => c.len_utf16(),
+        }
+    }
+}
+
+fn compute_line_starts(source: &str) -> Vec<usize> {
+    let bytes = source.as_bytes();
+    let mut out = vec![0];
+    for (index, &byte) in bytes.iter().enumerate() {
+        match byte {
+            b'\n' => out.push(index + 1),
+            b'\r' if bytes.get(index + 1) != Some(&b'\n') => out.push(index + 1),
+            _ => {},
+        }
+    }
+    out
+}
+
 /// Obtain a UTF-8 version of the source that is suitable for rendering spans in error messages
 /// for potentially non-UTF8 text.
 fn prepare_diagnostic_text_source(s: &[u8]) -> Cow<'_, str> {
@@ -116,3 +240,67 @@ fn prepare_diagnostic_text_source(s: &[u8]) -> Cow<'_, str> {
     // is an error at the FIRST appearance of non-UTF8 data; thus the byte offsets will be just fine.
     String::from_utf8_lossy(s)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lc(line: usize, col: usize) -> LineCol { LineCol { line, col } }
+
+    #[test]
+    fn line_col_multibyte() {
+        let files = Files::new();
+        let (file_id, _) = files.add("<input>", "aé😀b\nx".as_bytes()).unwrap();
+
+        let b_pos = BytePos("aé😀".len() as _);
+        assert_eq!(files.line_col(file_id, b_pos, ColumnUnit::Utf8), Some(lc(0, 7)));
+        assert_eq!(files.line_col(file_id, b_pos, ColumnUnit::Utf16), Some(lc(0, 4)));
+        assert_eq!(files.byte_pos(file_id, lc(0, 7), ColumnUnit::Utf8), Some(b_pos));
+        assert_eq!(files.byte_pos(file_id, lc(0, 4), ColumnUnit::Utf16), Some(b_pos));
+
+        // inside of a character
+        assert_eq!(files.line_col(file_id, BytePos(2), ColumnUnit::Utf8), None);
+        assert_eq!(files.byte_pos(file_id, lc(0, 2), ColumnUnit::Utf8), None);
+        assert_eq!(files.byte_pos(file_id, lc(0, 3), ColumnUnit::Utf16), None);
+
+        // past the end of a line is clamped, past the end of the file is not
+        assert_eq!(files.byte_pos(file_id, lc(0, 100), ColumnUnit::Utf16), Some(BytePos(8)));
+        assert_eq!(files.byte_pos(file_id, lc(1, 100), ColumnUnit::Utf16), Some(BytePos(10)));
+        assert_eq!(files.byte_pos(file_id, lc(2, 0), ColumnUnit::Utf16), None);
+        assert_eq!(files.line_col(file_id, BytePos(11), ColumnUnit::Utf16), None);
+    }
+
+    #[test]
+    fn line_col_mixed_line_endings() {
+        let files = Files::new();
+        let source = "ab\r\ncd\ref\n\ngh";
+        let (file_id, _) = files.add("<input>", source.as_bytes()).unwrap();
+
+        for (text, expected) in [("a", lc(0, 0)), ("c", lc(1, 0)), ("f", lc(2, 1)), ("\ngh", lc(3, 0)), ("h", lc(4, 1))] {
+            let pos = BytePos(source.find(text).unwrap() as _);
+            assert_eq!(files.line_col(file_id, pos, ColumnUnit::Utf8), Some(expected), "{:?}", text);
+            assert_eq!(files.byte_pos(file_id, expected, ColumnUnit::Utf8), Some(pos), "{:?}", text);
+        }
+        // the end of a line is before its line terminator
+        assert_eq!(files.byte_pos(file_id, lc(0, 10), ColumnUnit::Utf8), Some(BytePos(2)));
+    }
+
+    #[test]
+    fn span_round_trip() {
+        let files = Files::new();
+        files.add("<other>", b"unrelated").unwrap();
+        let (file_id, _) = files.add("<input>", "x = \"日本\";\r\ny = 2;".as_bytes()).unwrap();
+
+        let span = files.span_from_line_cols(file_id, lc(0, 4), lc(1, 1), ColumnUnit::Utf16).unwrap();
+        assert_eq!(files.span_text(span).unwrap(), "\"日本\";\r\ny");
+        assert_eq!(files.span_to_line_cols(span, ColumnUnit::Utf16), Some((lc(0, 4), lc(1, 1))));
+        assert_eq!(files.span_to_line_cols(span, ColumnUnit::Utf8), Some((lc(0, 4), lc(1, 1))));
+
+        let span = files.span_from_line_cols(file_id, lc(0, 5), lc(0, 7), ColumnUnit::Utf16).unwrap();
+        assert_eq!(files.span_text(span).unwrap(), "日本");
+        assert_eq!(files.span_to_line_cols(span, ColumnUnit::Utf8), Some((lc(0, 5), lc(0, 11))));
+
+        assert_eq!(files.span_from_line_cols(file_id, lc(1, 0), lc(0, 0), ColumnUnit::Utf8), None);
+        assert_eq!(files.span_text(Span::NULL), None);
+    }
+}
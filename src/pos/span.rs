@@ -76,6 +76,54 @@ pub struct Span {
     pub file_id: FileId,
 }
 
+/// Spans are serialized as `{file_id, start, end}`, with `file_id` being `null` for [`Span::NULL`] and
+/// other generated code.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeSpan {
+    file_id: Option<u32>,
+    start: RawIndex,
+    end: RawIndex,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Span {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let file_id = self.file_id.map(|x| x.get());
+        SerdeSpan { file_id, start: self.start.0, end: self.end.0 }.serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Span {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let SerdeSpan { file_id, start, end } = SerdeSpan::deserialize(deserializer)?;
+        if end < start {
+            return Err(serde::de::Error::custom(format_args!("span ends before it starts ({start}..{end})")));
+        }
+        let file_id = match file_id {
+            None => None,
+            Some(id) => Some(std::num::NonZeroU32::new(id).ok_or_else(|| serde::de::Error::custom("file_id cannot be 0"))?),
+        };
+        Ok(Span::new(file_id, start, end))
+    }
+}
+
+/// For fields holding a lone `Option<BytePos>`, which is serialized as the raw index.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_opt_byte_pos {
+    use super::*;
+    use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+    pub fn serialize<S: Serializer>(pos: &Option<BytePos>, serializer: S) -> Result<S::Ok, S::Error> {
+        pos.map(|pos| pos.0).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<BytePos>, D::Error> {
+        Ok(Option::<RawIndex>::deserialize(deserializer)?.map(BytePos))
+    }
+}
+
 impl Span {
     /// A dummy span for generated code during decompilation.
     ///
@@ -316,6 +364,7 @@ mod test {
 /// of a [`Var`], even though the span will be identical to the span on the surrounding `Sp<Expr>`.
 /// Currently, however, there's only a rare few instances of this sort of problem.
 #[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Sp<T: ?Sized> {
     pub span: Span,
     pub value: T,
@@ -28,7 +28,10 @@ pub type Opcode = u16;
 pub type Register = i32;
 
 /// The preferred type for representing the parameter mask field of an instruction.
-pub type ParamMask = u16;
+///
+/// This is wide enough for any format; the number of bits actually stored in a given format is
+/// given by [`InstrFormat::param_mask_bits`](crate::llir::InstrFormat::param_mask_bits).
+pub type ParamMask = u32;
 
 /// The preferred type for representing the extra argument of an ECL timeline instruction.
 pub type ExtraArg = i16;
@@ -43,6 +46,9 @@ pub type RawDwordBits = u32;
 /// The preferred type for representing the stack pop field of a modern ECL instruction.
 pub type StackPop = i16;
 
+/// The preferred type for representing the argument count field of a modern ECL instruction.
+pub type ArgCount = u8;
+
 /// The preferred type for representing an instruction's unsigned byte position relative to
 /// the beginning of a compiled subroutine.
 pub type BytePos = u64;
@@ -1,16 +1,17 @@
 use std::fmt;
 use std::num::NonZeroU32;
-use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use crate::raw;
 use crate::game::LanguageKey;
 use crate::ident::{Ident, ResIdent};
 use crate::context::CompilerContext;
+use crate::fast_hash::FastHashMap;
 
 #[cfg(test)]
 mod tests;
 
-pub type IdMap<K, V> = HashMap<K, V>; // probably want to get FxHashMap
+pub type IdMap<K, V> = crate::fast_hash::FastHashMap<K, V>;
 pub use std::collections::hash_map as id_map;
 
 newtype_id!{
@@ -61,6 +62,7 @@ newtype_id! {
     /// [`DefId`]s are created by the methods on [`CompilerContext`], and can be obtained after creation
     /// from [`Resolutions`].
     #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct DefId(pub NonZeroU32);
 }
 
@@ -90,6 +92,7 @@ newtype_id! {
     ///
     /// For instance, in TH17 ECL, the `TIME` register has an id of `-9988`.
     #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct RegId(pub raw::Register);
 }
 
@@ -128,27 +131,25 @@ impl Namespace {
 
 /// Node ID allocator.
 ///
-/// This can't be passed between threads, but uses internal mutability
-/// as the single-threadedness eliminates most bugs related to misuse.
+/// This uses internal mutability so that IDs can be allocated from behind a shared reference,
+/// even from multiple threads. (e.g. when raising scripts in parallel)
 #[derive(Debug)]
 pub struct UnusedIds<T> {
-    next: std::cell::Cell<u32>,
-    _covariant: std::marker::PhantomData<T>,
-    _explicitly_single_threaded: std::marker::PhantomData<*mut ()>,
+    next: AtomicU32,
+    _covariant: std::marker::PhantomData<fn() -> T>,
 }
 
 impl<T: From<NonZeroU32>> UnusedIds<T> {
     pub fn new() -> Self {
         UnusedIds {
-            next: 1.into(),
+            next: AtomicU32::new(1),
             _covariant: Default::default(),
-            _explicitly_single_threaded: Default::default(),
         }
     }
 
     pub fn next(&self) -> T {
-        self.next.set(self.next.get().checked_add(1).expect("too many node ids!"));
-        std::num::NonZeroU32::new(self.next.get() - 1).unwrap().into()
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        std::num::NonZeroU32::new(id).expect("too many node ids!").into()
     }
 }
 
@@ -193,6 +194,8 @@ pub mod node_id_helpers {
 
 pub use resolve_names::Visitor as ResolveNamesVisitor;
 mod resolve_names {
+    use std::sync::Arc;
+
     use super::*;
     use crate::ast::{self, Visit};
     use crate::pos::{Sp, Span};
@@ -282,6 +285,7 @@ mod resolve_names {
 
                 | ast::Item::Timeline { .. }
                 | ast::Item::AnmScript { .. }
+                | ast::Item::AnmScriptAlias { .. }
                 | ast::Item::Meta { .. }
                 => ast::walk_item(self, item),
             }
@@ -357,9 +361,6 @@ mod resolve_names {
 
         fn visit_expr(&mut self, expr: &Sp<ast::Expr>) {
             match &expr.value {
-                ast::Expr::Call(call)
-                => self.visit_call_(call),
-
                 // qualified enum consts bypass the rib stack so that `const`s can't shadow them
                 ast::Expr::EnumConst { enum_name, ident }
                 => self.resolve_qualified_enum_const(expr.span, enum_name, ident),
@@ -367,25 +368,12 @@ mod resolve_names {
                 _ => ast::walk_expr(self, expr),
             }
         }
-    }
 
-    impl Visitor<'_, '_> {
-        fn visit_callable_name_(&mut self, name: &Sp<ast::CallableName>) -> Result<(), ErrorReported> {
-            if let ast::CallableName::Normal { ref ident, language_if_ins, .. } = name.value {
-                match self.rib_stacks.resolve(Namespace::Funcs, name.span, language_if_ins, ident) {
-                    Err(e) => return Err(self.ctx.emitter.emit(e)),
-                    Ok(def_id) => self.ctx.resolutions.record_resolution(ident, def_id),
-                }
-            }
-            Ok(())
-        }
-
-        // TODO: make this override Visit::visit_call if that ever gets added to the trait
-        fn visit_call_(&mut self, call: &ast::ExprCall) {
+        fn visit_call(&mut self, call: &ast::ExprCall) {
             use crate::context::defs::{InsMissingSigError};
 
             // full destructure here because we have to reimplement walking.
-            // (if we call `ast::walk_expr` it is too easy to emit extraneous diagnostics on an arg)
+            // (if we call `ast::walk_call` it is too easy to emit extraneous diagnostics on an arg)
             let ast::ExprCall { name: func_name, args: _, pseudos } = call;
             for pseudo in pseudos {
                 self.visit_expr(&pseudo.value.value);
@@ -396,9 +384,11 @@ mod resolve_names {
 
             // use the signature to get enhanced type information for function args.
             // (but don't try to access the signature if resolving the function name failed!)
+            //
+            // (this is an Arc so that we can keep using the signature while `ctx` is mutated)
             let siggy = match resolve_func_result {
-                Ok(()) => match self.ctx.func_signature_from_ast(&call.name) {
-                    Ok(siggy) => Some(siggy),
+                Ok(()) => match self.ctx.shared_func_signature_from_ast(&call.name) {
+                    Ok(siggy) => Some(Arc::clone(siggy)),
                     Err(InsMissingSigError { .. }) => {
                         // continue without type info, and let the type checker complain about the
                         // missing signature later
@@ -412,9 +402,19 @@ mod resolve_names {
                     None
                 },
             };
-            // FIXME: this clone is to stop borrowing ctx, we need a better borrowing story here
-            let siggy = siggy.cloned();
-            self.visit_call_args_with_signature_info(call, siggy.as_ref());
+            self.visit_call_args_with_signature_info(call, siggy.as_deref());
+        }
+    }
+
+    impl Visitor<'_, '_> {
+        fn visit_callable_name_(&mut self, name: &Sp<ast::CallableName>) -> Result<(), ErrorReported> {
+            if let ast::CallableName::Normal { ref ident, language_if_ins, .. } = name.value {
+                match self.rib_stacks.resolve(Namespace::Funcs, name.span, language_if_ins, ident) {
+                    Err(e) => return Err(self.ctx.emitter.emit(e)),
+                    Ok(def_id) => self.ctx.resolutions.record_resolution(ident, def_id),
+                }
+            }
+            Ok(())
         }
 
         fn visit_call_args_with_signature_info(&mut self, call: &ast::ExprCall, siggy: Option<&Signature>) {
@@ -473,11 +473,10 @@ mod resolve_names {
         /// This is called extremely early on items in a block, allowing items to be defined after they are used.
         fn add_item_to_scope<'b>(&mut self, item: &Sp<ast::Item>) {
             match item.value {
-                ast::Item::Func(ast::ItemFunc { ref ident, ty_keyword, ref params, qualifier, code: _ }) => {
-                    let siggy = crate::context::defs::Signature::from_func_params(ty_keyword, params);
-                    let def_id = self.ctx.define_user_func(ident.clone(), qualifier, siggy);
+                ast::Item::Func(ref func) => {
+                    let def_id = self.ctx.define_user_func(func);
                     self.add_to_rib_with_redefinition_check(
-                        Namespace::Funcs, RibKind::Items, ident.clone(), def_id,
+                        Namespace::Funcs, RibKind::Items, func.ident.clone(), def_id,
                     );
                 },
 
@@ -496,6 +495,7 @@ mod resolve_names {
                 },
 
                 ast::Item::AnmScript { .. } => {}
+                ast::Item::AnmScriptAlias { .. } => {},
                 ast::Item::Timeline { .. } => {},
                 ast::Item::Meta { .. } => {},
             } // match item.value
@@ -584,7 +584,7 @@ pub mod rib {
     pub struct Rib {
         pub ns: Namespace,
         pub kind: RibKind,
-        defs: HashMap<Ident, RibEntry>,
+        defs: FastHashMap<Ident, RibEntry>,
     }
 
     #[derive(Debug, Clone)]
@@ -764,8 +764,48 @@ pub mod rib {
                 };
                 diag.note(format!("there is a '{}' defined in {}{}", cur_ident, other_language.descr(), extra));
             }
+
+            let similar_names = self.find_similar_names(ns, alias_language, cur_ident);
+            match &similar_names[..] {
+                [] => {},
+                [name] => { diag.note(format!("did you mean '{}'?", name)); },
+                names => {
+                    let names = names.iter().map(|name| format!("'{}'", name)).collect::<Vec<_>>();
+                    diag.note(format!("did you mean one of: {}?", names.join(", ")));
+                },
+            }
             Err(diag)
         }
+
+        /// Find the names visible from the current scope that are closest to a name that failed to resolve.
+        /// (e.g. a misspelled sprite)
+        fn find_similar_names(&self, ns: Namespace, alias_language: Option<LanguageKey>, input: &Ident) -> Vec<&Ident> {
+            const MAX_SUGGESTIONS: usize = 3;
+            let max_distance = input.as_str().len() / 3;
+
+            let mut crossed_local_border = false;
+            let mut candidates = vec![];
+            for rib in self.ribs[ns].iter().rev() {
+                crossed_local_border |= rib.kind.local_barrier_cause().is_some();
+                if rib.kind.holds_locals() && crossed_local_border {
+                    continue;
+                }
+                if let RibKind::Mapfile { language } = rib.kind {
+                    if alias_language != Some(language) {
+                        continue;
+                    }
+                }
+                for candidate in rib.defs.keys() {
+                    let distance = strsim::osa_distance(input.as_str(), candidate.as_str());
+                    if distance <= max_distance {
+                        candidates.push((distance, candidate));
+                    }
+                }
+            }
+            candidates.sort();
+            candidates.dedup_by_key(|&mut (_, candidate)| candidate);
+            candidates.into_iter().take(MAX_SUGGESTIONS).map(|(_, candidate)| candidate).collect()
+        }
     }
 
     impl FromIterator<Rib> for RibStacks {
@@ -837,7 +877,8 @@ impl Resolutions {
 
     fn _record_resolution(&mut self, ident: &ResIdent, def: DefId, is_self_resolution: bool) {
         let res = ident.expect_res();
-        let dest = &mut self.map[res.0.get() as usize];
+        let index = self.index(res);
+        let dest = &mut self.map[index];
 
         let already_has_matching_definition = *dest == Some(def);
 
@@ -865,7 +906,12 @@ impl Resolutions {
     /// This is only useful during the name resolution passes themselves; the majority of code
     /// probably wants [`Self::expect_def`] instead, as all idents should be resolved.
     pub fn try_get_def(&self, ident: &ResIdent) -> Option<DefId> {
-        self.map[ident.expect_res().0.get() as usize]
+        self.map[self.index(ident.expect_res())]
+    }
+
+    /// Determine whether an ident is the name at the definition site of the thing it resolves to.
+    pub fn is_definition(&self, ident: &ResIdent) -> bool {
+        self.try_get_def(ident) == Some(Self::synthesize_def_id_from_res_id(ident.expect_res()))
     }
 
     pub fn expect_def(&self, ident: &ResIdent) -> DefId {
@@ -873,6 +919,27 @@ impl Resolutions {
             .unwrap_or_else(|| panic!("(bug!) name '{ident}' has not yet been resolved!"))
     }
 
+    /// Get an endless supply of [`ResId`]s past all of those allocated so far, each paired with
+    /// the [`DefId`] it would have if it were a definition.
+    ///
+    /// Nothing is recorded, so every call produces the same ids until [`Self::fresh_res`] is next
+    /// called.  This lets work that only has shared access to the context (e.g. lowering many
+    /// scripts in parallel) create names that are private to a single unit of that work.
+    /// It is up to the caller to resolve these names, and they must never escape that unit.
+    /// (looking one of them up here fails a debug assertion, at least until the next [`Self::fresh_res`])
+    pub fn unrecorded_res_ids(&self) -> impl Iterator<Item=(ResId, DefId)> {
+        (self.map.len() as u32..).map(|index| {
+            let res = ResId(NonZeroU32::new(index).unwrap());
+            (res, Self::synthesize_def_id_from_res_id(res))
+        })
+    }
+
+    fn index(&self, res: ResId) -> usize {
+        let index = res.0.get() as usize;
+        debug_assert!(index < self.map.len(), "(bug!) {res:?} was never allocated; did a name from unrecorded_res_ids escape?");
+        index
+    }
+
     fn synthesize_def_id_from_res_id(res: ResId) -> DefId {
         // no need to invent new numbers
         DefId(res.0)
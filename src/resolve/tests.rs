@@ -567,3 +567,15 @@ fn panics_on_cloned_res() {
 
     crate::passes::resolution::resolve_names(&block, ctx).unwrap();
 }
+
+#[cfg(debug_assertions)]
+#[should_panic(expected = "never allocated")]
+#[test]
+fn panics_on_escaped_unrecorded_res() {
+    let mut resolutions = super::Resolutions::new();
+    let _ = resolutions.fresh_res();
+    let (res, _) = resolutions.unrecorded_res_ids().next().unwrap();
+
+    let ident = crate::ident::ResIdent::new(crate::ident::Ident::new_user("temp").unwrap(), res);
+    resolutions.try_get_def(&ident);
+}
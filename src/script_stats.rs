@@ -0,0 +1,90 @@
+//! Statistics about the size and register usage of compiled scripts.
+//!
+//! See [`Truth::script_stats`][crate::Truth::script_stats].
+
+use std::fmt;
+
+use crate::debug_info;
+use crate::raw;
+
+/// Size and register usage of each script compiled so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptStats {
+    /// In the order that the scripts were compiled.
+    pub scripts: Vec<ScriptUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptUsage {
+    /// The name of the script, or a description such as `timeline 0` if it has none.
+    pub name: String,
+    /// Size of the compiled instructions in bytes.
+    pub size: usize,
+    pub num_instrs: usize,
+    /// Registers that the source code refers to directly, in increasing order.
+    pub explicit_registers: Vec<raw::Register>,
+    /// Registers that the compiler used for temporaries and locals, in increasing order.
+    pub scratch_registers: Vec<raw::Register>,
+}
+
+impl ScriptStats {
+    pub fn new() -> Self { Self::default() }
+
+    /// Gather the statistics of scripts from their debug info.
+    pub fn from_debug_info(scripts: &[debug_info::Script]) -> Self {
+        let scripts = scripts.iter().map(|script| {
+            let debug_info::Script { export_info, lowering_info } = script;
+            ScriptUsage {
+                name: match (&export_info.name, &export_info.exported_as) {
+                    (Some(name), _) => name.clone(),
+                    (None, debug_info::ScriptType::Timeline { index }) => format!("timeline {index}"),
+                    (None, debug_info::ScriptType::AnmScript { index }) => format!("script{index}"),
+                    (None, debug_info::ScriptType::MsgScript { indices }) => format!("script {indices:?}"),
+                    (None, debug_info::ScriptType::StdScript) => format!("main"),
+                    (None, debug_info::ScriptType::EclSub { index }) => format!("sub {index}"),
+                },
+                size: lowering_info.offset_info.end_offset as usize,
+                num_instrs: lowering_info.offset_info.instrs.len(),
+                explicit_registers: lowering_info.register_info.explicit_registers.clone(),
+                scratch_registers: lowering_info.register_info.scratch_registers.clone(),
+            }
+        }).collect();
+        ScriptStats { scripts }
+    }
+}
+
+// =============================================================================
+// Display
+
+/// Prints a table with one row per script.
+impl fmt::Display for ScriptStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fmt_regs = |regs: &[raw::Register]| regs.iter().map(|reg| reg.to_string()).collect::<Vec<_>>().join(", ");
+        let rows = self.scripts.iter().map(|usage| {
+            [
+                usage.name.clone(), usage.size.to_string(), usage.num_instrs.to_string(),
+                fmt_regs(&usage.explicit_registers), fmt_regs(&usage.scratch_registers),
+            ]
+        }).collect::<Vec<_>>();
+
+        let header = ["script", "size", "instrs", "registers", "scratch"];
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+
+        let write_row = |f: &mut fmt::Formatter, row: [&str; 5]| {
+            let [name, size, num_instrs, registers, scratch] = row;
+            let [name_w, size_w, num_instrs_w, registers_w, _] = widths;
+            let line = format!("{name:<name_w$}  {size:>size_w$}  {num_instrs:>num_instrs_w$}  {registers:<registers_w$}  {scratch}");
+            writeln!(f, "{}", line.trim_end())
+        };
+        write_row(f, header)?;
+        for row in &rows {
+            write_row(f, row.each_ref().map(String::as_str))?;
+        }
+        Ok(())
+    }
+}
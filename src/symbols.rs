@@ -0,0 +1,147 @@
+//! Listing of the items defined by a script file, for use by tools such as editors.
+//!
+//! See [`Truth::list_symbols`][crate::Truth::list_symbols].
+
+use crate::ast;
+use crate::context::CompilerContext;
+use crate::error::ErrorReported;
+use crate::ident::Ident;
+use crate::pos::Span;
+use crate::raw;
+use crate::value::ScalarValue;
+
+/// An item defined at the top level of a script file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    /// `None` for items that have no name (`meta`s, and timelines without names).
+    pub name: Option<Ident>,
+    /// Span of the name, or of the keyword for items that have no name.
+    pub span: Span,
+    /// Span of the entire item.
+    pub item_span: Span,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    /// A `script` in ANM, STD, or MSG.
+    Script { number: Option<raw::LangInt> },
+    /// A `timeline` in ECL.
+    Timeline { number: Option<raw::LangInt> },
+    /// A function definition or declaration.  `qualifier` is `None` for exported functions.
+    Func {
+        qualifier: Option<ast::FuncQualifier>,
+        return_ty: ast::TypeKeyword,
+        params: Vec<SymbolParam>,
+    },
+    /// A global `const` variable.  `value` is `None` if it could not be evaluated.
+    Const {
+        ty: ast::TypeKeyword,
+        value: Option<ScalarValue>,
+    },
+    /// A `meta` or `entry`.
+    Meta { keyword: ast::MetaKeyword },
+}
+
+/// A parameter of a [`SymbolKind::Func`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolParam {
+    pub ty: ast::TypeKeyword,
+    pub name: Option<Ident>,
+}
+
+/// Implementation of [`Truth::list_symbols`][crate::Truth::list_symbols].
+pub(crate) fn list_symbols(ast: &ast::ScriptFile, ctx: &mut CompilerContext<'_>) -> Vec<Symbol> {
+    let mut const_values = evaluate_global_consts(ast, ctx).into_iter();
+
+    let mut out = vec![];
+    for item in &ast.items {
+        match &item.value {
+            ast::Item::Func(func) => out.push(Symbol {
+                name: Some(func.ident.as_raw().clone()),
+                span: func.ident.span,
+                item_span: item.span,
+                kind: SymbolKind::Func {
+                    qualifier: func.qualifier.as_ref().map(|x| x.value),
+                    return_ty: func.ty_keyword.value,
+                    params: func.params.iter().map(|param| SymbolParam {
+                        ty: param.ty_keyword.value,
+                        name: param.ident.as_ref().map(|ident| ident.as_raw().clone()),
+                    }).collect(),
+                },
+            }),
+            | ast::Item::AnmScript { number, ident, .. }
+            | ast::Item::AnmScriptAlias { number, ident, .. }
+            => out.push(Symbol {
+                name: Some(ident.value.clone()),
+                span: ident.span,
+                item_span: item.span,
+                kind: SymbolKind::Script { number: number.map(|x| x.value) },
+            }),
+            ast::Item::Timeline { keyword, number, ident, .. } => out.push(Symbol {
+                name: ident.as_ref().map(|ident| ident.value.clone()),
+                span: ident.as_ref().map_or(keyword.span, |ident| ident.span),
+                item_span: item.span,
+                kind: SymbolKind::Timeline { number: number.map(|x| x.value) },
+            }),
+            ast::Item::Meta { keyword, .. } => out.push(Symbol {
+                name: None,
+                span: keyword.span,
+                item_span: item.span,
+                kind: SymbolKind::Meta { keyword: keyword.value },
+            }),
+            ast::Item::ConstVar { ty_keyword, vars } => {
+                for sp_pat![(var, _)] in vars {
+                    let value = const_values.next().expect("one value per const");
+                    let name = match &var.name {
+                        ast::VarName::Normal { ident, .. } => Some(ident.as_raw().clone()),
+                        ast::VarName::Reg { .. } => None,  // not valid, but no need to complain here
+                    };
+                    out.push(Symbol {
+                        name,
+                        span: var.span,
+                        item_span: item.span,
+                        kind: SymbolKind::Const { ty: ty_keyword.value, value },
+                    });
+                }
+            },
+        }
+    }
+    out
+}
+
+/// Evaluate all global consts, returning a value for each one in order of appearance.
+///
+/// This is done on a copy that only contains the const items, so that errors elsewhere in the file
+/// do not prevent evaluation.
+fn evaluate_global_consts(ast: &ast::ScriptFile, ctx: &mut CompilerContext<'_>) -> Vec<Option<ScalarValue>> {
+    let mut consts_only = ast::ScriptFile {
+        mapfiles: vec![],
+        image_sources: vec![],
+        reg_pragmas: vec![],
+        items: ast.items.iter().filter(|item| matches!(item.value, ast::Item::ConstVar { .. })).cloned().collect(),
+        comments: vec![],
+    };
+
+    // Fresh ResIds, so that the caller may still compile the original AST with this context.
+    //
+    // Errors are reported as usual; whatever could be evaluated before them is still cached.
+    let _ = (|| -> Result<_, ErrorReported> {
+        crate::passes::resolution::refresh_res_ids(&mut consts_only, ctx)?;
+        crate::passes::resolution::resolve_names(&consts_only, ctx)?;
+        crate::passes::type_check::run(&consts_only, ctx)?;
+        crate::passes::evaluate_const_vars::run(ctx)
+    })();
+
+    let vars = consts_only.items.iter().flat_map(|item| match &item.value {
+        ast::Item::ConstVar { vars, .. } => &vars[..],
+        _ => unreachable!(),
+    });
+    vars.map(|sp_pat![(var, _)]| match &var.name {
+        ast::VarName::Normal { ident, .. } => {
+            let def_id = ctx.resolutions.try_get_def(ident)?;
+            ctx.consts.get_cached_value(def_id.into()).cloned()
+        },
+        ast::VarName::Reg { .. } => None,
+    }).collect()
+}
@@ -74,6 +74,22 @@ macro_rules! string_enum {
                 out.fmt(format_args!("{}", self))
             }
         }
+
+        // serialized using the same strings that appear in source code
+        #[cfg(feature = "serde")]
+        impl serde::Serialize for $Enum {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $Enum {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let string = <String as serde::Deserialize>::deserialize(deserializer)?;
+                string.parse().map_err(serde::de::Error::custom)
+            }
+        }
     }
 }
 
@@ -49,7 +49,7 @@ impl ScalarValue {
     pub fn read_as_int(&self) -> Option<i32> {
         match self {
             &ScalarValue::Int(x) => Some(x),
-            &ScalarValue::Float(x) => Some(x as i32),
+            &ScalarValue::Float(x) => Some(f32_to_i32_truncating(x)),
             &ScalarValue::String(_) => None,
         }
     }
@@ -73,6 +73,18 @@ impl ScalarValue {
     }
 }
 
+/// Convert a float to an integer the way the games do.
+///
+/// This truncates towards zero, like Rust's `as`.  However, unlike Rust's saturating `as`, NaN and
+/// out-of-range values produce `i32::MIN`, matching the x86 `cvttss2si` instruction.
+pub(crate) fn f32_to_i32_truncating(x: f32) -> i32 {
+    if -2147483648.0 <= x && x < 2147483648.0 {
+        x as i32
+    } else {
+        i32::MIN
+    }
+}
+
 /// `Display` impl, for ease of use in error messages.
 impl fmt::Display for ScalarValue {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -1,18 +1,19 @@
 use std::collections::HashMap;
 use std::fmt;
 use crate::ast;
-use crate::pos::Sp;
+use crate::pos::{Sp, Span};
 use crate::context::CompilerContext;
-use crate::resolve::{DefId, RegId, Resolutions, NodeId, IdMap};
+use crate::resolve::{DefId, RegId, ConstId, NodeId, IdMap};
 use crate::passes::semantics::time_and_difficulty::{self, TimeAndDifficulty};
 use crate::value::ScalarValue;
+use crate::ident::Ident;
+use crate::error::ErrorReported;
 
 /// A VM that runs on the AST, which can be used to help verify the validity of AST transforms
 /// in unit tests.
 ///
-/// Because it runs on a nested datastructure, it has no concept of a persistent instruction
-/// pointer and cannot be paused or resumed.  It will always run the code until it falls off
-/// past the last statement or hits a return.
+/// [`Self::run`] runs the code until it falls off past the last statement or hits a return.
+/// To pause in between statements (e.g. to debug a miscompilation), use [`Self::stepper`] instead.
 ///
 /// **Important:** The VM has no interaction with the type system.  This means that it cannot resolve
 /// aliases of registers; you should [convert them to raw registers](`crate::passes::resolution::aliases_to_regs`)
@@ -26,6 +27,10 @@ pub struct AstVm {
     pub time: i32,
     /// Difficulty. (0 = easy, 1 = normal, ...)
     ///
+    /// This selects the case of each diff switch (with omitted cases like in `(1::3:)` using the nearest
+    /// explicit case before them), and statements whose difficulty label excludes it are skipped
+    /// (though time labels still advance the clock), mirroring what you get after compilation.
+    ///
     /// If not set, difficulty-related syntax will cause a panic.
     pub difficulty: Option<u32>,
     /// Total amount of time the VM has been running.
@@ -35,6 +40,8 @@ pub struct AstVm {
     /// Log of all opaque instructions that have executed.
     /// (anything using special syntax like operators, assignments and control flow are NOT logged)
     pub instr_log: Vec<LoggedCall>,
+    /// A more detailed log of calls, jumps, waits and time labels, if enabled by [`Self::with_trace`].
+    pub trace: Option<Vec<TraceEvent>>,
     iterations: u32,
     max_iterations: Option<u32>,
     var_values: HashMap<VarId, VarValue>,
@@ -68,6 +75,34 @@ pub struct LoggedCall {
     pub args: Vec<ScalarValue>,
 }
 
+/// An entry in [`AstVm::trace`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    /// The statement responsible for the event.
+    pub span: Span,
+    /// Script time after the event.
+    pub time: i32,
+    /// Total time after the event.
+    pub real_time: i32,
+    pub kind: TraceEventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEventKind {
+    /// An instruction was called with these evaluated arguments.
+    Call { opcode: u16, args: Vec<ScalarValue> },
+    /// Time advanced in order to reach a statement, typically due to a time label.
+    Wait { duration: i32 },
+    /// A time label was reached.  This happens even if it does not advance the time.
+    TimeLabel,
+    /// A `goto` was taken.  (conditional ones included)  `time` is the explicit time of `goto label @ time`.
+    Goto { destination: Ident, time: Option<i32> },
+    /// A `break` exited a loop.
+    Break,
+    /// A loop went back to the start of its body.
+    Repeat,
+}
+
 impl fmt::Display for AstVm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "-----------------------------------------")?;
@@ -112,33 +147,108 @@ impl fmt::Display for AstVm {
     }
 }
 
-#[must_use]
-enum RunResult {
-    /// Nothing is out of the ordinary.
-    Nominal,
-    /// A `break` was encountered
-    Breaking,
-    /// A `return` was encountered.
-    Return(Option<ScalarValue>),
-    /// A nested run call is jumping to an outer label.
+/// A point at which [`AstVmStepper::resume`] should pause.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Pause before the label with this name.
+    Label(Ident),
+    /// Pause before the statement with this [`NodeId`].
+    Stmt(NodeId),
+}
+
+/// The reason that an [`AstVmStepper`] returned control to the caller.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// A single statement was executed.
+    Step,
+    /// The next statement has a breakpoint on it.
+    Breakpoint(Breakpoint),
+    /// The VM fell off past the last statement or hit a `return`.  Contains the `return`ed value.
+    Finished(Option<ScalarValue>),
+}
+
+/// Runs an [`AstVm`] on some code one statement at a time.
+///
+/// Created by [`AstVm::stepper`].  In between steps, the state of the VM can be inspected and modified
+/// through [`Self::vm`] and [`Self::vm_mut`].
+///
+/// For the sake of this type, entering a block (such as the body of a loop) counts as executing the
+/// statement that owns it.  Statements that are skipped due to difficulty still count as a step.
+pub struct AstVmStepper<'a, 'ctx> {
+    vm: &'a mut AstVm,
+    ctx: &'a CompilerContext<'ctx>,
+    stmt_data: IdMap<NodeId, TimeAndDifficulty>,
+    /// Blocks that are currently executing, from outermost to innermost.
     ///
-    /// (for technical reasons, you can't jump to an inner label)
-    Jumping(ast::StmtGoto),
+    /// Whenever control is returned to the caller, the innermost frame is pointing at the next
+    /// statement to run, or the stack is empty because the VM has finished.
+    frames: Vec<Frame<'a>>,
+    breakpoints: Vec<Breakpoint>,
+    stopped_at_breakpoint: bool,
+    result: Option<Option<ScalarValue>>,
+}
+
+struct Frame<'a> {
+    stmts: &'a [Sp<ast::Stmt>],
+    index: usize,
+    kind: FrameKind<'a>,
+    /// Span of the statement that owns the block.
+    span: Span,
+}
+
+/// What should happen once a [`Frame`] runs past its last statement.
+enum FrameKind<'a> {
+    /// The statements given to [`AstVm::stepper`].
+    Root,
+    /// A free-standing block.
+    Block,
+    /// The branch that was taken in a conditional chain.
+    CondChain(&'a ast::StmtCondChain),
+    Loop(&'a ast::Block),
+    While { cond: &'a Sp<ast::Expr>, block: &'a ast::Block },
+    Times { block: &'a ast::Block, remaining: i32 },
+    TimesClobber { clobber: &'a Sp<ast::Var>, block: &'a ast::Block },
+}
+
+impl FrameKind<'_> {
+    /// Get the body of the loop, if this is one that `break` can exit.
+    fn loop_block(&self) -> Option<&ast::Block> {
+        match *self {
+            FrameKind::Root | FrameKind::Block | FrameKind::CondChain(_) => None,
+            FrameKind::Loop(block) |
+            FrameKind::While { block, .. } |
+            FrameKind::Times { block, .. } |
+            FrameKind::TimesClobber { block, .. } => Some(block),
+        }
+    }
 }
 
 impl AstVm {
+    /// Default limit on the number of statements executed before the VM panics, so that an accidental
+    /// infinite loop in a test fails instead of hanging.
+    pub const DEFAULT_MAX_ITERATIONS: u32 = 1_000_000;
+
     pub fn new() -> Self {
         AstVm {
             time: 0,
             difficulty: None,
             real_time: 0,
             instr_log: vec![],
+            trace: None,
             var_values: Default::default(),
             iterations: 0,
-            max_iterations: None,
+            max_iterations: Some(Self::DEFAULT_MAX_ITERATIONS),
         }
     }
 
+    /// Record a [`TraceEvent`] for every call, jump, wait, and time label.
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(vec![]);
+        self
+    }
+
+    /// Set the number of statements that can be executed before the VM panics.
+    /// (by default, [`Self::DEFAULT_MAX_ITERATIONS`])
     pub fn with_max_iterations(mut self, n: u32) -> Self {
         self.max_iterations = Some(n);
         self
@@ -154,322 +264,70 @@ impl AstVm {
     /// **Important reminder:** Please be certain that name resolution has been performed, and that
     /// additionally all register aliases have been [converted to raw registers](`crate::passes::resolution::aliases_to_regs`).
     pub fn run(&mut self, stmts: &[Sp<ast::Stmt>], ctx: &CompilerContext<'_>) -> Option<ScalarValue> {
-        let stmt_data = time_and_difficulty::run(stmts, &ctx.emitter).expect("unexpected analysis failure");
-        match self._run(stmts, &ctx.resolutions, &stmt_data) {
-            RunResult::Nominal => None,
-            RunResult::Return(value) => value,
-            RunResult::Breaking => panic!("AST VM tried to break out of a loop, but wasn't in one!"),
-            RunResult::Jumping(goto) => panic!(
-                "AST VM tried to jump to {} but this label did not exist within the same or outer scopes! \
-                (note: for technical reasons, labels in inner scopes cannot be jumped to by this VM)",
-                goto.destination,
-            ),
+        match self.stepper(stmts, ctx).resume() {
+            StopReason::Finished(value) => value,
+            reason => unreachable!("no breakpoints were set, but stopped for {reason:?}"),
         }
     }
 
-    fn _run(&mut self, stmts: &[Sp<ast::Stmt>], resolutions: &Resolutions, stmt_data: &IdMap<NodeId, TimeAndDifficulty>) -> RunResult {
-        let mut stmt_index = 0;
-
-        'stmt: while stmt_index < stmts.len() {
-            // eprintln!("{:?}", stmts[stmt_index]);
-            if let Some(max_iterations) = self.max_iterations {
-                if self.iterations >= max_iterations {
-                    panic!("iteration limit exceeded!");
-                }
-            }
-            self.iterations += 1;
-
-            if stmts[stmt_index].diff_label.is_some() {
-                assert!(self.difficulty.is_some(), "difficulty of VM was not set!");
-            }
-
-            // Handle early returns for a goto.
-            // (this bubbles up to the block that contains the label and then resumes)
-            macro_rules! handle_goto {
-                ($goto:expr) => { match $goto {
-                    // is the label defined at this nesting level?
-                    goto => match self.try_goto(stmts, goto, stmt_data) {
-                        Some(index) => {
-                            stmt_index = index;
-                            continue 'stmt;
-                        },
-                        // check an outer scope; all recursive callsites are wrapped in handle_block!,
-                        // which will trigger this macro again at the next level up.
-                        None => return RunResult::Jumping(goto.clone()),
-                    },
-                }};
-            }
-
-            // Handle early returns for a control-flow keyword.
-            macro_rules! handle_jump {
-                ($jump:expr) => { match $jump {
-                    | ast::StmtJumpKind::BreakContinue { keyword: sp_pat![token![break]], ..}
-                    => return RunResult::Breaking,
-
-                    | ast::StmtJumpKind::Goto(goto)
-                    => handle_goto!(goto),
-                }};
-            }
-
-            // "Wait" until this statement's time, even if it is the wrong difficulty.
-            let stmt_node_id = stmts[stmt_index].node_id.unwrap();
-            let stmt_time = stmt_data[&stmt_node_id].time;
-            if self.time < stmt_time {
-                let time_diff = stmt_time - self.time;
-                self.time += time_diff;
-                self.real_time += time_diff;
-            }
-
-            let start_time = |block: &ast::Block| stmt_data[&block.start_node_id()].time;
-            let end_time = |block: &ast::Block| stmt_data[&block.end_node_id()].time;
-
-            macro_rules! to_next_stmt {
-                () => {
-                    stmt_index += 1;
-                    continue 'stmt;
-                };
-            }
-
-            // Skip statements for the wrong difficulty
-            if let Some(difficulty) = self.difficulty {
-                // HACK: We DO enter free-standing blocks.  They can have time labels or overriding
-                //       difficulty labels inside.
-                let is_always_run = matches!(stmts[stmt_index].kind, ast::StmtKind::Block { .. });
-
-                if !is_always_run && !stmt_data[&stmt_node_id].difficulty_mask.contains(difficulty) {
-                    to_next_stmt!();
-                }
-            }
-
-            // Recurse into a block, handling any form of early return.
-            macro_rules! handle_block {
-                ($block:expr) => {
-                    match self._run(&$block.0, resolutions, stmt_data) {
-                        RunResult::Nominal => {},
-                        RunResult::Breaking => return RunResult::Breaking,
-                        RunResult::Return(value) => return RunResult::Return(value),
-                        RunResult::Jumping(goto) => handle_goto!(&goto),
-                    }
-                };
-            }
-
-            // Recurse into a block, propagating most early returns but catching the `break` keyword.
-            macro_rules! handle_block_of_breakable_stmt {
-                ($block:expr) => {
-                    match self._run(&$block.0, resolutions, stmt_data) {
-                        RunResult::Nominal => {},
-                        RunResult::Breaking => {
-                            // this macro gets used at the nesting level outside the loop,
-                            // so exit the loop by simply advancing to the next statement.
-                            self.time = end_time($block);
-                            stmt_index += 1;
-                            continue 'stmt;
-                        },
-                        RunResult::Return(value) => return RunResult::Return(value),
-                        RunResult::Jumping(goto) => handle_goto!(&goto),
-                    }
-                };
-            }
-
-            // N.B. this can't easily be factored out into a method because it would require
-            //      some way of storing or communicating a "instruction pointer", which
-            //      doesn't exist due to the nested nature of the AST.
-            match &stmts[stmt_index].kind {
-                ast::StmtKind::Item(_) => {},
-
-                ast::StmtKind::Block(block) => {
-                    handle_block!(block);
-                },
-
-                ast::StmtKind::Jump(jump) => handle_jump!(jump),
-
-                ast::StmtKind::CondJump { keyword, cond, jump } => {
-                    if self.eval_cond(cond, resolutions) == (keyword == &token![if]) {
-                        handle_jump!(jump);
-                    }
-                },
-
-                ast::StmtKind::Return { value, .. } => {
-                    return RunResult::Return(value.as_ref().map(|x| self.eval(x, resolutions)));
-                },
-
-                ast::StmtKind::CondChain(chain) => {
-                    let ast::StmtCondChain { cond_blocks, else_block } = chain;
-
-                    let mut branch_taken = false;
-                    for ast::CondBlock { keyword, cond, block } in cond_blocks {
-                        if self.eval_cond(cond, resolutions) == (keyword == &token![if]) {
-                            branch_taken = true;
-                            self.time = start_time(block);
-                            handle_block!(block);
-                            break;
-                        }
-                    }
-
-                    if !branch_taken {
-                        if let Some(else_block) = else_block {
-                            self.time = start_time(else_block);
-                            handle_block!(else_block);
-                        }
-                    }
-                    self.time = end_time(chain.last_block());
-                },
-
-                ast::StmtKind::Loop { block, .. } => {
-                    loop {
-                        handle_block_of_breakable_stmt!(block);
-                        self.time = start_time(block);
-                    }
-                },
-
-                ast::StmtKind::While { do_keyword, cond, block, .. } => {
-                    if do_keyword.is_some() || self.eval_cond(cond, resolutions) {
-                        loop {
-                            handle_block_of_breakable_stmt!(block);
-                            if self.eval_cond(cond, resolutions) {
-                                self.time = start_time(block);
-                                continue;
-                            }
-                            break;
-                        }
-                    } else {
-                        // nasty: in the zero-iterations case only, we jump over the loop
-                        //    and therefore need to fix the time!
-                        self.time = end_time(block);
-                    }
-                },
-
-                ast::StmtKind::Times { clobber: None, count, block, .. } => {
-                    self.time = end_time(block);
-                    match self.eval_int(count, resolutions) {
-                        0 => {},
-                        count => {
-                            for _ in 0..count {
-                                self.time = start_time(block);
-                                handle_block_of_breakable_stmt!(block);
-                            }
-                        },
-                    }
-                },
-
-                // when a clobber is specified we have to treat it pretty differently
-                // as the loop counter now has an observable presence
-                ast::StmtKind::Times { clobber: Some(clobber), count, block, .. } => {
-                    let count = self.eval_int(count, resolutions);
-                    self.write_var_by_ast(clobber, ScalarValue::Int(count), resolutions);
-
-                    self.time = end_time(block);
-                    if count != 0 {
-                        loop {
-                            self.time = start_time(block);
-                            handle_block_of_breakable_stmt!(block);
-
-                            match self.read_var_by_ast(clobber, resolutions) {
-                                ScalarValue::Float(x) => panic!("float count {}", x),
-                                ScalarValue::String(x) => panic!("string count {}", x),
-                                ScalarValue::Int(x) => {
-                                    let predecremented = x - 1;
-                                    self.write_var_by_ast(clobber, ScalarValue::Int(predecremented), resolutions);
-                                    if predecremented == 0 { break; }
-                                },
-                            }
-                        } // loop
-                    }
-                },
-
-                ast::StmtKind::Expr(expr) => {
-                    match &expr.value {
-                        ast::Expr::Call(ast::ExprCall { name, pseudos, args }) => {
-                            if pseudos.len() > 0 {
-                                unimplemented!("VM pseudo-args");  // TODO: we'd have to let LoggedCall potentially hold a blob
-                            }
-
-                            let arg_values = args.iter().map(|arg| self.eval(arg, resolutions)).collect::<Vec<_>>();
-                            match name.value {
-                                ast::CallableName::Ins { opcode, .. } => self.log_instruction(opcode, &arg_values),
-                                ast::CallableName::Normal { .. } => unimplemented!("non-instr function in VM"),
-                            }
-                        },
-                        _ => unimplemented!("VM statement expression: {:?}", expr)
-                    }
-                },
-
-                ast::StmtKind::Assignment { var, op, value } => {
-                    match op.value {
-                        ast::AssignOpKind::Assign => {
-                            let value = self.eval(value, resolutions);
-                            self.write_var_by_ast(var, value, resolutions);
-                        },
-                        _ => {
-                            let binop = op.corresponding_binop().expect("only Assign has no binop");
-                            let value = sp!(op.span => binop).const_eval(
-                                self.read_var_by_ast(var, resolutions),
-                                self.eval(value, resolutions),
-                            );
-                            self.write_var_by_ast(var, value, resolutions);
-                        },
-                    }
-                },
-
-                ast::StmtKind::Declaration { vars, .. } => {
-                    for pair in vars.iter() {
-                        let (var, expr) = &pair.value;
-                        if let Some(expr) = expr {
-                            let value = self.eval(expr, resolutions);
-                            self.write_var_by_ast(var, value, resolutions);
-                        }
-                    }
-                },
-
-                ast::StmtKind::CallSub { .. } => unimplemented!("CallSub for AST VM"),
-
-                ast::StmtKind::Label(_) => {},
-
-                ast::StmtKind::InterruptLabel(_) => {},
-
-                ast::StmtKind::AbsTimeLabel { .. } => { },
-                ast::StmtKind::RelTimeLabel { .. } => { },
-
-                ast::StmtKind::ScopeEnd(_) => {},
-
-                ast::StmtKind::NoInstruction => {},
-            }
-
-            // Notice: logic for advancing to the next statement is factored out into a macro
-            // because it must also occur when e.g. difficulty doesn't match.
-            to_next_stmt!();
-        }
-        RunResult::Nominal
+    /// Prepare to run the statements one at a time.  See [`AstVmStepper`].
+    ///
+    /// The same preconditions apply as for [`Self::run`].
+    pub fn stepper<'a, 'ctx>(&'a mut self, stmts: &'a [Sp<ast::Stmt>], ctx: &'a CompilerContext<'ctx>) -> AstVmStepper<'a, 'ctx> {
+        let stmt_data = time_and_difficulty::run(stmts, &ctx.emitter).expect("unexpected analysis failure");
+        let mut stepper = AstVmStepper {
+            vm: self,
+            ctx,
+            stmt_data,
+            frames: vec![Frame { stmts, index: 0, kind: FrameKind::Root, span: Span::NULL }],
+            breakpoints: vec![],
+            stopped_at_breakpoint: false,
+            result: None,
+        };
+        stepper.finish_blocks();
+        stepper
     }
 
-    pub fn eval(&mut self, expr: &ast::Expr, resolutions: &Resolutions) -> ScalarValue {
+    pub fn eval(&mut self, expr: &ast::Expr, ctx: &CompilerContext<'_>) -> ScalarValue {
         match expr {
             ast::Expr::Ternary { cond, left, right, .. } => {
-                match self.eval_int(cond, resolutions) {
-                    0 => self.eval(right, resolutions),
-                    _ => self.eval(left, resolutions),
+                match self.eval_int(cond, ctx) {
+                    0 => self.eval(right, ctx),
+                    _ => self.eval(left, ctx),
                 }
             },
 
-            ast::Expr::BinOp(a, op, b) => op.const_eval(self.eval(a, resolutions), self.eval(b, resolutions)),
+            ast::Expr::BinOp(a, op, b) => op.const_eval(self.eval(a, ctx), self.eval(b, ctx)),
 
-            ast::Expr::Call(ast::ExprCall { .. }) => unimplemented!("func calls in VM exprs"),
+            ast::Expr::Call(ast::ExprCall { name, pseudos, args }) => {
+                // only const funcs can be called in an expression; they are evaluated the same way as in consts
+                let def_id = match &name.value {
+                    ast::CallableName::Normal { ident, .. } if pseudos.is_empty() => ctx.resolutions.expect_def(ident),
+                    _ => unimplemented!("non-const func calls in VM exprs"),
+                };
+                let arg_values = args.iter().map(|arg| self.eval(arg, ctx)).collect();
+                match ctx.consts.eval_const_func_call(name.span, def_id, arg_values, &ctx.defs, &ctx.resolutions, ctx.emitter) {
+                    Ok(Some(value)) => value,
+                    Ok(None) => panic!("const func call did not return a value: {name}"),
+                    Err(ErrorReported) => panic!("error while evaluating const func call: {name}"),
+                }
+            },
 
             ast::Expr::UnOp(op, x) => match op.as_ty_sigil() {
                 // sigils are invalid in const eval, but we'll let the VM share the behavior held by
                 // the majority of languages, where a sigil means type-cast
                 Some(ty_sigil) => {
-                    self.eval(x, resolutions).cast_by_ty_sigil(Some(ty_sigil))
+                    self.eval(x, ctx).cast_by_ty_sigil(Some(ty_sigil))
                         .unwrap_or_else(|| panic!("vm cannot evaluate unop {op:?}"))
                 },
                 None => {
-                    op.const_eval(self.eval(x, resolutions))
+                    op.const_eval(self.eval(x, ctx))
                         .unwrap_or_else(|| panic!("vm cannot evaluate unop {op:?}"))
                 },
             },
 
             ast::Expr::XcrementOp { op, order, var } => {
-                let old_value = match self.read_var_by_ast(var, resolutions) {
+                let old_value = match self.read_var_by_ast(var, ctx) {
                     ScalarValue::Float(x) => panic!("type error: {:?}", x),
                     ScalarValue::String(x) => panic!("type error: {:?}", x),
                     ScalarValue::Int(value) => value,
@@ -478,7 +336,7 @@ impl AstVm {
                     ast::XcrementOpKind::Inc => i32::wrapping_add(old_value, 1),
                     ast::XcrementOpKind::Dec => i32::wrapping_add(old_value, -1),
                 };
-                self.write_var_by_ast(var, ScalarValue::Int(new_value), resolutions);
+                self.write_var_by_ast(var, ScalarValue::Int(new_value), ctx);
 
                 let out_value = match order {
                     ast::XcrementOpOrder::Post => old_value,
@@ -490,7 +348,7 @@ impl AstVm {
             ast::Expr::DiffSwitch(cases) => {
                 let difficulty = self.difficulty.expect("difficulty not set for VM!");
                 let case = crate::diff_switch_utils::select_diff_switch_case(cases, difficulty);
-                self.eval(case, resolutions)
+                self.eval(case, ctx)
             },
 
             ast::Expr::LitInt { value, .. } => ScalarValue::Int(*value),
@@ -503,7 +361,7 @@ impl AstVm {
 
             ast::Expr::EnumConst { .. } => unimplemented!("enum const in VM"),
 
-            ast::Expr::Var(var) => self.read_var_by_ast(var, resolutions),
+            ast::Expr::Var(var) => self.read_var_by_ast(var, ctx),
         }
     }
 
@@ -515,9 +373,16 @@ impl AstVm {
         })
     }
 
+    fn log_trace_event(&mut self, span: Span, kind: TraceEventKind) {
+        let (time, real_time) = (self.time, self.real_time);
+        if let Some(trace) = &mut self.trace {
+            trace.push(TraceEvent { span, time, real_time, kind });
+        }
+    }
+
     #[track_caller]
-    pub fn eval_cond(&mut self, cond: &ast::Expr, resolutions: &Resolutions) -> bool {
-        match self.eval(cond, resolutions) {
+    pub fn eval_cond(&mut self, cond: &ast::Expr, ctx: &CompilerContext<'_>) -> bool {
+        match self.eval(cond, ctx) {
             ScalarValue::Float(x) => panic!("type error: {:?}", x),
             ScalarValue::String(x) => panic!("type error: {:?}", x),
             ScalarValue::Int(value) => value != 0,
@@ -525,18 +390,18 @@ impl AstVm {
     }
 
     #[track_caller]
-    pub fn eval_int(&mut self, expr: &ast::Expr, resolutions: &Resolutions) -> i32 {
-        match self.eval(expr, resolutions) {
+    pub fn eval_int(&mut self, expr: &ast::Expr, ctx: &CompilerContext<'_>) -> i32 {
+        match self.eval(expr, ctx) {
             ScalarValue::Int(x) => x,
             ScalarValue::Float(x) => panic!("type error: {:?}", x),
             ScalarValue::String(x) => panic!("type error: {:?}", x),
         }
     }
 
-    fn var_id_from_name(&self, var: &ast::VarName, resolutions: &Resolutions) -> VarId {
+    fn var_id_from_name(&self, var: &ast::VarName, ctx: &CompilerContext<'_>) -> VarId {
         match *var {
-            ast::VarName::Normal { ref ident, language_if_reg: _ } => VarId::Other(resolutions.expect_def(ident)),
-            ast::VarName::Reg { reg, language: _ } => VarId::Reg(reg),
+            ast::VarName::Normal { ref ident, language_if_reg: _ } => VarId::Other(ctx.resolutions.expect_def(ident)),
+            ast::VarName::Reg { reg, .. } => VarId::Reg(reg),
         }
     }
 
@@ -557,15 +422,22 @@ impl AstVm {
     /// Convenience wrapper of [`Self::get_var`] for simple variables in test code.
     pub fn get_reg(&self, reg: RegId) -> Option<ScalarValue> { self.get_var(VarId::Reg(reg)) }
 
-    fn write_var_by_ast(&mut self, var: &ast::Var, value: ScalarValue, resolutions: &Resolutions) {
-        let key = self.var_id_from_name(&var.name, resolutions);
+    fn write_var_by_ast(&mut self, var: &ast::Var, value: ScalarValue, ctx: &CompilerContext<'_>) {
+        let key = self.var_id_from_name(&var.name, ctx);
         self.var_values.insert(key, value.into());
     }
 
-    fn read_var_by_ast(&mut self, var: &ast::Var, resolutions: &Resolutions) -> ScalarValue {
-        let var_id = self.var_id_from_name(&var.name, resolutions);
+    fn read_var_by_ast(&mut self, var: &ast::Var, ctx: &CompilerContext<'_>) -> ScalarValue {
+        let var_id = self.var_id_from_name(&var.name, ctx);
         match self.var_values.get_mut(&var_id) {
-            None => panic!("read of uninitialized var: {:?}", var.name),
+            None => match var_id {
+                // consts that were not given a value in the VM use their compiled value
+                VarId::Other(def_id) => match ctx.consts.get_cached_value(ConstId { def_id }) {
+                    Some(value) => value.clone().cast_by_ty_sigil(var.ty_sigil).expect("shoulda been type-checked"),
+                    None => panic!("read of uninitialized var: {:?}", var.name),
+                },
+                VarId::Reg(_) => panic!("read of uninitialized var: {:?}", var.name),
+            },
             Some(VarValue::Value(value)) => {
                 value.clone()
                     .cast_by_ty_sigil(var.ty_sigil)
@@ -590,6 +462,380 @@ impl AstVm {
     }
 }
 
+impl<'a, 'ctx> AstVmStepper<'a, 'ctx> {
+    pub fn vm(&self) -> &AstVm { self.vm }
+    pub fn vm_mut(&mut self) -> &mut AstVm { self.vm }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) { self.breakpoints.push(breakpoint) }
+
+    /// Returns `false` if there was no such breakpoint.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let len_before = self.breakpoints.len();
+        self.breakpoints.retain(|x| x != breakpoint);
+        self.breakpoints.len() != len_before
+    }
+
+    /// The statement that will run on the next step, or `None` if the VM has finished.
+    pub fn next_stmt(&self) -> Option<&'a Sp<ast::Stmt>> {
+        self.frames.last().map(|frame| &frame.stmts[frame.index])
+    }
+
+    pub fn is_finished(&self) -> bool { self.result.is_some() }
+
+    /// Execute a single statement.
+    pub fn step(&mut self) -> StopReason {
+        self.stopped_at_breakpoint = false;
+        if self.result.is_none() {
+            self.execute_next_stmt();
+            self.finish_blocks();
+        }
+        match &self.result {
+            Some(value) => StopReason::Finished(value.clone()),
+            None => StopReason::Step,
+        }
+    }
+
+    /// Run until the next statement has a breakpoint, or until the VM finishes.
+    ///
+    /// If the stepper is currently stopped at a breakpoint, the statement there will run first.
+    pub fn resume(&mut self) -> StopReason {
+        let mut check_breakpoints = !self.stopped_at_breakpoint;
+        loop {
+            if check_breakpoints {
+                if let Some(breakpoint) = self.breakpoint_at_next_stmt() {
+                    self.stopped_at_breakpoint = true;
+                    return StopReason::Breakpoint(breakpoint);
+                }
+            }
+            check_breakpoints = true;
+
+            match self.step() {
+                StopReason::Step => {},
+                reason => return reason,
+            }
+        }
+    }
+
+    fn breakpoint_at_next_stmt(&self) -> Option<Breakpoint> {
+        let stmt = self.next_stmt()?;
+        self.breakpoints.iter().find(|breakpoint| match (breakpoint, &stmt.kind) {
+            (Breakpoint::Label(name), ast::StmtKind::Label(label)) => &label.value == name,
+            (Breakpoint::Label(_), _) => false,
+            (Breakpoint::Stmt(node_id), _) => stmt.node_id == Some(*node_id),
+        }).cloned()
+    }
+
+    fn start_time(&self, block: &ast::Block) -> i32 { self.stmt_data[&block.start_node_id()].time }
+    fn end_time(&self, block: &ast::Block) -> i32 { self.stmt_data[&block.end_node_id()].time }
+
+    fn enter_block(&mut self, stmt: &Sp<ast::Stmt>, block: &'a ast::Block, kind: FrameKind<'a>) {
+        self.frames.push(Frame { stmts: &block.0, index: 0, kind, span: stmt.span });
+    }
+
+    /// Exit the innermost block and advance past the statement that owned it.
+    fn exit_block(&mut self) {
+        self.frames.pop();
+        self.frames.last_mut().expect("exited root frame").index += 1;
+    }
+
+    fn count_iteration(&mut self) {
+        if let Some(max_iterations) = self.vm.max_iterations {
+            if self.vm.iterations >= max_iterations {
+                let location = match self.next_stmt() {
+                    Some(stmt) => format!("before {} at {:?}", stmt.kind.descr(), stmt.span),
+                    None => "at the end of the code".to_owned(),
+                };
+                panic!(
+                    "AST VM exceeded its iteration limit of {} (time {}, {}); is there an infinite loop?",
+                    max_iterations, self.vm.time, location,
+                );
+            }
+        }
+        self.vm.iterations += 1;
+    }
+
+    fn execute_next_stmt(&mut self) {
+        let ctx = self.ctx;
+        let stmt = self.next_stmt().expect("VM has already finished");
+
+        self.count_iteration();
+
+        if stmt.diff_label.is_some() {
+            assert!(self.vm.difficulty.is_some(), "difficulty of VM was not set!");
+        }
+
+        // "Wait" until this statement's time, even if it is the wrong difficulty.
+        let stmt_node_id = stmt.node_id.unwrap();
+        let stmt_time = self.stmt_data[&stmt_node_id].time;
+        if self.vm.time < stmt_time {
+            let time_diff = stmt_time - self.vm.time;
+            self.vm.time += time_diff;
+            self.vm.real_time += time_diff;
+            self.vm.log_trace_event(stmt.span, TraceEventKind::Wait { duration: time_diff });
+        }
+
+        // Skip statements for the wrong difficulty
+        if let Some(difficulty) = self.vm.difficulty {
+            // HACK: We DO enter free-standing blocks.  They can have time labels inside.
+            let is_always_run = matches!(stmt.kind, ast::StmtKind::Block { .. });
+
+            if !is_always_run && !self.stmt_data[&stmt_node_id].difficulty_mask.contains(difficulty) {
+                self.frames.last_mut().unwrap().index += 1;
+                return;
+            }
+        }
+
+        // Statements that enter a block or jump return early; everything else falls through to
+        // advance to the next statement.
+        match &stmt.kind {
+            ast::StmtKind::Item(_) => {},
+
+            ast::StmtKind::Block(block) => return self.enter_block(stmt, block, FrameKind::Block),
+
+            ast::StmtKind::Jump(jump) => return self.jump(stmt.span, jump),
+
+            ast::StmtKind::CondJump { keyword, cond, jump } => {
+                if self.vm.eval_cond(cond, ctx) == (keyword == &token![if]) {
+                    return self.jump(stmt.span, jump);
+                }
+            },
+
+            ast::StmtKind::Return { value, .. } => {
+                let value = value.as_ref().map(|x| self.vm.eval(x, ctx));
+                self.frames.clear();
+                self.result = Some(value);
+                return;
+            },
+
+            ast::StmtKind::CondChain(chain) => {
+                let ast::StmtCondChain { cond_blocks, else_block } = chain;
+
+                for ast::CondBlock { keyword, cond, block } in cond_blocks {
+                    if self.vm.eval_cond(cond, ctx) == (keyword == &token![if]) {
+                        self.vm.time = self.start_time(block);
+                        return self.enter_block(stmt, block, FrameKind::CondChain(chain));
+                    }
+                }
+
+                if let Some(else_block) = else_block {
+                    self.vm.time = self.start_time(else_block);
+                    return self.enter_block(stmt, else_block, FrameKind::CondChain(chain));
+                }
+                self.vm.time = self.end_time(chain.last_block());
+            },
+
+            ast::StmtKind::Loop { block, .. } => return self.enter_block(stmt, block, FrameKind::Loop(block)),
+
+            ast::StmtKind::While { do_keyword, cond, block, .. } => {
+                if do_keyword.is_some() || self.vm.eval_cond(cond, ctx) {
+                    return self.enter_block(stmt, block, FrameKind::While { cond, block });
+                } else {
+                    // nasty: in the zero-iterations case only, we jump over the loop
+                    //    and therefore need to fix the time!
+                    self.vm.time = self.end_time(block);
+                }
+            },
+
+            ast::StmtKind::Times { clobber: None, count, block, .. } => {
+                self.vm.time = self.end_time(block);
+                let count = self.vm.eval_int(count, ctx);
+                if count > 0 {
+                    self.vm.time = self.start_time(block);
+                    return self.enter_block(stmt, block, FrameKind::Times { block, remaining: count });
+                }
+            },
+
+            // when a clobber is specified we have to treat it pretty differently
+            // as the loop counter now has an observable presence
+            ast::StmtKind::Times { clobber: Some(clobber), count, block, .. } => {
+                let count = self.vm.eval_int(count, ctx);
+                self.vm.write_var_by_ast(clobber, ScalarValue::Int(count), ctx);
+
+                self.vm.time = self.end_time(block);
+                if count != 0 {
+                    self.vm.time = self.start_time(block);
+                    return self.enter_block(stmt, block, FrameKind::TimesClobber { clobber, block });
+                }
+            },
+
+            ast::StmtKind::Expr(expr) => {
+                match &expr.value {
+                    ast::Expr::Call(ast::ExprCall { name, pseudos, args }) => {
+                        if pseudos.len() > 0 {
+                            unimplemented!("VM pseudo-args");  // TODO: we'd have to let LoggedCall potentially hold a blob
+                        }
+
+                        let arg_values = args.iter().map(|arg| self.vm.eval(arg, ctx)).collect::<Vec<_>>();
+                        match name.value {
+                            ast::CallableName::Ins { opcode, .. } => {
+                                self.vm.log_instruction(opcode, &arg_values);
+                                self.vm.log_trace_event(stmt.span, TraceEventKind::Call { opcode, args: arg_values });
+                            },
+                            ast::CallableName::Normal { .. } => unimplemented!("non-instr function in VM"),
+                        }
+                    },
+                    _ => unimplemented!("VM statement expression: {:?}", expr)
+                }
+            },
+
+            ast::StmtKind::Assignment { var, op, value } => {
+                match op.value {
+                    ast::AssignOpKind::Assign => {
+                        let value = self.vm.eval(value, ctx);
+                        self.vm.write_var_by_ast(var, value, ctx);
+                    },
+                    _ => {
+                        let binop = op.corresponding_binop().expect("only Assign has no binop");
+                        let value = sp!(op.span => binop).const_eval(
+                            self.vm.read_var_by_ast(var, ctx),
+                            self.vm.eval(value, ctx),
+                        );
+                        self.vm.write_var_by_ast(var, value, ctx);
+                    },
+                }
+            },
+
+            ast::StmtKind::Declaration { vars, .. } => {
+                for pair in vars.iter() {
+                    let (var, expr) = &pair.value;
+                    if let Some(expr) = expr {
+                        let value = self.vm.eval(expr, ctx);
+                        self.vm.write_var_by_ast(var, value, ctx);
+                    }
+                }
+            },
+
+            ast::StmtKind::CallSub { .. } => unimplemented!("CallSub for AST VM"),
+
+            ast::StmtKind::Label(_) => {},
+
+            ast::StmtKind::InterruptLabel(_) => {},
+
+            ast::StmtKind::AbsTimeLabel { .. } |
+            ast::StmtKind::RelTimeLabel { .. } => {
+                self.vm.log_trace_event(stmt.span, TraceEventKind::TimeLabel);
+            },
+
+            ast::StmtKind::ScopeEnd(_) => {},
+
+            ast::StmtKind::NoInstruction => {},
+        }
+        self.frames.last_mut().unwrap().index += 1;
+    }
+
+    /// Handle the end of any blocks that have run past their last statement, repeating loops as necessary,
+    /// until we are at a statement that can run.
+    fn finish_blocks(&mut self) {
+        let ctx = self.ctx;
+        while let Some(frame) = self.frames.last() {
+            if frame.index < frame.stmts.len() {
+                return;
+            }
+            match self.frames.last().unwrap().kind {
+                FrameKind::Root => {
+                    self.frames.pop();
+                    self.result = Some(None);
+                },
+
+                FrameKind::Block => self.exit_block(),
+
+                FrameKind::CondChain(chain) => {
+                    self.vm.time = self.end_time(chain.last_block());
+                    self.exit_block();
+                },
+
+                FrameKind::Loop(block) => self.repeat_block(block),
+
+                FrameKind::While { cond, block } => {
+                    if self.vm.eval_cond(cond, ctx) {
+                        self.repeat_block(block);
+                    } else {
+                        self.exit_block();
+                    }
+                },
+
+                FrameKind::Times { block, remaining } => {
+                    if remaining > 1 {
+                        self.frames.last_mut().unwrap().kind = FrameKind::Times { block, remaining: remaining - 1 };
+                        self.repeat_block(block);
+                    } else {
+                        self.exit_block();
+                    }
+                },
+
+                FrameKind::TimesClobber { clobber, block } => {
+                    match self.vm.read_var_by_ast(clobber, ctx) {
+                        ScalarValue::Float(x) => panic!("float count {}", x),
+                        ScalarValue::String(x) => panic!("string count {}", x),
+                        ScalarValue::Int(x) => {
+                            let predecremented = x - 1;
+                            self.vm.write_var_by_ast(clobber, ScalarValue::Int(predecremented), ctx);
+                            if predecremented == 0 {
+                                self.exit_block();
+                            } else {
+                                self.repeat_block(block);
+                            }
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    fn repeat_block(&mut self, block: &ast::Block) {
+        self.vm.time = self.start_time(block);
+        let frame = self.frames.last_mut().unwrap();
+        frame.index = 0;
+        let span = frame.span;
+        self.vm.log_trace_event(span, TraceEventKind::Repeat);
+    }
+
+    fn jump(&mut self, span: Span, jump: &ast::StmtJumpKind) {
+        match jump {
+            ast::StmtJumpKind::BreakContinue { keyword: sp_pat![token![break]], .. } => {
+                self.break_loop();
+                self.vm.log_trace_event(span, TraceEventKind::Break);
+            },
+            ast::StmtJumpKind::Goto(goto) => {
+                self.goto(goto);
+                let time = goto.time.map(|time| time.value);
+                self.vm.log_trace_event(span, TraceEventKind::Goto { destination: goto.destination.value.clone(), time });
+            },
+        }
+    }
+
+    fn break_loop(&mut self) {
+        loop {
+            let frame = self.frames.pop().expect("no frames");
+            if let FrameKind::Root = frame.kind {
+                panic!("AST VM tried to break out of a loop, but wasn't in one!");
+            }
+            if let Some(block) = frame.kind.loop_block() {
+                // exit the loop by simply advancing to the next statement.
+                self.vm.time = self.end_time(block);
+                self.frames.last_mut().unwrap().index += 1;
+                return;
+            }
+        }
+    }
+
+    /// Jump to a label in the current block or one of the blocks containing it.
+    fn goto(&mut self, goto: &ast::StmtGoto) {
+        while let Some(frame) = self.frames.last_mut() {
+            if let Some(index) = self.vm.try_goto(frame.stmts, goto, &self.stmt_data) {
+                frame.index = index;
+                return;
+            }
+            self.frames.pop();
+        }
+        panic!(
+            "AST VM tried to jump to {} but this label did not exist within the same or outer scopes! \
+            (note: for technical reasons, labels in inner scopes cannot be jumped to by this VM)",
+            goto.destination,
+        );
+    }
+}
+
 /// Funky variables for funky test cases.
 #[derive(Debug, Clone)]
 pub enum SpecialVarKind {
@@ -666,6 +912,25 @@ mod tests {
         });
     }
 
+    #[test]
+    fn const_func_call() {
+        TestSpec {
+            globals: vec![("Y", RegId(-999), Ty::Int)],
+            source: r#"{
+                const int triangle(int n) {
+                    if (n <= 0) { return 0; }
+                    return n + triangle(n - 1);
+                }
+                return triangle($Y) * 2;
+            }"#,
+        }.check(|ast, ctx| {
+            let mut vm = new_test_vm();
+            vm.set_reg(RegId(-999), Int(4));
+
+            assert_eq!(vm.run(&ast.0, &ctx), Some(Int(20)));
+        });
+    }
+
     #[test]
     fn basic_instrs_and_time() {
         TestSpec {
@@ -957,6 +1222,140 @@ mod tests {
         });
     }
 
+    #[test]
+    fn step_and_modify() {
+        TestSpec {
+            globals: vec![("X", RegId(30), Ty::Int)],
+            source: r#"{
+                X = 1;
+            +5:
+                X = X * 10;
+                ins_11(X);
+            }"#,
+        }.check(|ast, ctx| {
+            let mut vm = new_test_vm();
+            let mut stepper = vm.stepper(&ast.0, &ctx);
+            while !matches!(stepper.next_stmt().unwrap().kind, ast::StmtKind::Assignment { .. }) {
+                assert_eq!(stepper.step(), StopReason::Step);
+            }
+            assert_eq!(stepper.step(), StopReason::Step);
+            assert_eq!(stepper.vm().get_reg(RegId(30)), Some(Int(1)));
+
+            stepper.vm_mut().set_reg(RegId(30), Int(4));
+            while stepper.vm().time < 5 {
+                stepper.step();
+            }
+            stepper.vm_mut().time = 20;
+            assert_eq!(stepper.resume(), StopReason::Finished(None));
+            assert!(stepper.is_finished());
+            assert_eq!(stepper.next_stmt(), None);
+
+            assert_eq!(vm.instr_log, vec![LoggedCall { real_time: 5, opcode: 11, args: vec![Int(40)] }]);
+            assert_eq!(vm.time, 20);
+        });
+    }
+
+    #[test]
+    fn breakpoints() {
+        TestSpec {
+            globals: vec![("X", RegId(30), Ty::Int)],
+            source: r#"{
+                X = 0;
+                times(3) {
+                    X += 1;
+                hit:
+                    ins_11(X);
+                }
+                return X;
+            }"#,
+        }.check(|ast, ctx| {
+            let return_node_id = ast.0.iter().rev()
+                .find(|stmt| matches!(stmt.kind, ast::StmtKind::Return { .. }))
+                .unwrap().node_id.unwrap();
+
+            let mut vm = new_test_vm();
+            let mut stepper = vm.stepper(&ast.0, &ctx);
+            stepper.add_breakpoint(Breakpoint::Label(ident!("hit")));
+            stepper.add_breakpoint(Breakpoint::Stmt(return_node_id));
+            for expected in 1..=3 {
+                assert_eq!(stepper.resume(), StopReason::Breakpoint(Breakpoint::Label(ident!("hit"))));
+                assert_eq!(stepper.vm().get_reg(RegId(30)), Some(Int(expected)));
+            }
+            assert_eq!(stepper.resume(), StopReason::Breakpoint(Breakpoint::Stmt(return_node_id)));
+            assert!(stepper.remove_breakpoint(&Breakpoint::Label(ident!("hit"))));
+            assert!(!stepper.remove_breakpoint(&Breakpoint::Label(ident!("hit"))));
+            assert_eq!(stepper.resume(), StopReason::Finished(Some(Int(3))));
+            assert_eq!(stepper.step(), StopReason::Finished(Some(Int(3))));
+        });
+    }
+
+    #[test]
+    fn trace() {
+        TestSpec {
+            globals: vec![("X", RegId(30), Ty::Int)],
+            source: r#"{
+                X = 0;
+            +3:
+                loop {
+                    ins_11(X);
+                    X += 1;
+                    if (X == 2) goto done;
+                    +1:
+                }
+            done:
+                ins_12();
+            }"#,
+        }.check(|ast, ctx| {
+            let mut vm = new_test_vm().with_trace();
+            vm.run(&ast.0, &ctx);
+
+            let trace = vm.trace.unwrap();
+            assert!(trace.iter().all(|event| event.span != Span::NULL));
+
+            let events = trace.into_iter().map(|event| (event.real_time, event.kind)).collect::<Vec<_>>();
+            assert_eq!(events, vec![
+                (3, TraceEventKind::Wait { duration: 3 }),
+                (3, TraceEventKind::TimeLabel),
+                (3, TraceEventKind::Call { opcode: 11, args: vec![Int(0)] }),
+                (4, TraceEventKind::Wait { duration: 1 }),
+                (4, TraceEventKind::TimeLabel),
+                (4, TraceEventKind::Repeat),
+                (4, TraceEventKind::Call { opcode: 11, args: vec![Int(1)] }),
+                (4, TraceEventKind::Goto { destination: ident!("done"), time: None }),
+                (4, TraceEventKind::Call { opcode: 12, args: vec![] }),
+            ]);
+        });
+    }
+
+    #[test]
+    fn trace_time_changes() {
+        TestSpec {
+            globals: vec![],
+            source: r#"{
+                ins_11(0);
+            0:
+            +2:
+                goto skip @ 10;
+                ins_11(1);
+            skip:
+                ins_12();
+            }"#,
+        }.check(|ast, ctx| {
+            let mut vm = new_test_vm().with_trace();
+            vm.run(&ast.0, &ctx);
+
+            let events = vm.trace.unwrap().into_iter().map(|event| (event.time, event.kind)).collect::<Vec<_>>();
+            assert_eq!(events, vec![
+                (0, TraceEventKind::Call { opcode: 11, args: vec![Int(0)] }),
+                (0, TraceEventKind::TimeLabel),
+                (2, TraceEventKind::Wait { duration: 2 }),
+                (2, TraceEventKind::TimeLabel),
+                (10, TraceEventKind::Goto { destination: ident!("skip"), time: Some(10) }),
+                (10, TraceEventKind::Call { opcode: 12, args: vec![] }),
+            ]);
+        });
+    }
+
     #[test]
     fn math_funcs() {
         TestSpec {
@@ -1007,6 +1406,68 @@ mod tests {
         });
     }
 
+    #[test]
+    fn cast_edge_cases() {
+        TestSpec {
+            globals: vec![
+                ("F", RegId(30), Ty::Float),
+                ("TRUNC", RegId(31), Ty::Int), ("SIGIL", RegId(32), Ty::Int),
+            ],
+            source: r#"{
+                TRUNC = int(F);
+                SIGIL = $F;
+            }"#,
+        }.check(|ast, ctx| {
+            // the games use cvttss2si, which truncates and produces i32::MIN for unrepresentable values
+            for &(f, expected) in &[
+                (-2.7, -2), (2.7, 2), (-0.5, 0),
+                (3e9, i32::MIN), (-3e9, i32::MIN), (f32::NAN, i32::MIN), (f32::INFINITY, i32::MIN),
+            ] {
+                let mut vm = new_test_vm();
+                vm.set_reg(RegId(30), Float(f));
+                vm.run(&ast.0, &ctx);
+
+                assert_eq!(vm.get_reg(RegId(31)).unwrap(), Int(expected), "{}", f);
+                assert_eq!(vm.get_reg(RegId(32)).unwrap(), Int(expected), "{}", f);
+            }
+        });
+    }
+
+    #[test]
+    fn unops() {
+        TestSpec {
+            globals: vec![
+                ("I", RegId(30), Ty::Int), ("F", RegId(31), Ty::Float),
+                ("NEG_I", RegId(32), Ty::Int), ("NEG_F", RegId(33), Ty::Float),
+                ("NOT", RegId(34), Ty::Int), ("BITNOT", RegId(35), Ty::Int),
+                ("TO_F", RegId(36), Ty::Float), ("SQRT_NEG", RegId(37), Ty::Float),
+            ],
+            source: r#"{
+                NEG_I = -I;
+                NEG_F = -F;
+                NOT = !I + !(I - I);
+                BITNOT = ~I;
+                TO_F = float(I) * 0.5;
+                SQRT_NEG = sqrt(-F);
+            }"#,
+        }.check(|ast, ctx| {
+            let mut vm = new_test_vm();
+            vm.set_reg(RegId(30), Int(i32::MIN));
+            vm.set_reg(RegId(31), Float(1.5));
+            vm.run(&ast.0, &ctx);
+
+            assert_eq!(vm.get_reg(RegId(32)).unwrap(), Int(i32::MIN));
+            assert_eq!(vm.get_reg(RegId(33)).unwrap(), Float(-1.5));
+            assert_eq!(vm.get_reg(RegId(34)).unwrap(), Int(1));
+            assert_eq!(vm.get_reg(RegId(35)).unwrap(), Int(i32::MAX));
+            assert_eq!(vm.get_reg(RegId(36)).unwrap(), Float(i32::MIN as f32 * 0.5));
+            match vm.get_reg(RegId(37)).unwrap() {
+                Float(x) => assert!(x.is_nan()),
+                other => panic!("{:?}", other),
+            }
+        });
+    }
+
     #[test]
     fn string_arg() {
         TestSpec {
@@ -1042,6 +1503,32 @@ mod tests {
         });
     }
 
+    #[test]
+    fn diff_switch_and_labels_at_each_difficulty() {
+        TestSpec {
+            globals: vec![("A", RegId(30), Ty::Int), ("B", RegId(31), Ty::Int), ("C", RegId(32), Ty::Int)],
+            source: r#"{
+                A = (10::30:);
+                B = 0;
+                {"23"}: B = (1:2:3:4);
+                C = 0;
+                {"0"}: C += 1;
+                {"01"}: {
+                    C += 10;
+                    {"1"}: C += 100;
+                }
+            }"#,
+        }.check(|ast, ctx| {
+            for &(difficulty, expected) in &[(0, [10, 0, 11]), (1, [10, 0, 110]), (2, [30, 3, 0]), (3, [30, 4, 0])] {
+                let mut vm = new_test_vm().with_difficulty(difficulty);
+                vm.run(&ast.0, &ctx);
+
+                let actual = [RegId(30), RegId(31), RegId(32)].map(|reg| vm.get_reg(reg).unwrap().expect_int());
+                assert_eq!(actual, expected, "difficulty {}", difficulty);
+            }
+        });
+    }
+
     #[test]
     fn counter() {
         TestSpec {
@@ -0,0 +1,53 @@
+//! Utilities shared by the tests that call the library API in memory, like `tests/bindiff.rs`.
+//!
+//! Each test file that uses this gets its own copy, and may not use everything.
+#![allow(dead_code)]
+
+use truth::{Game, FileFormat, Truth};
+
+/// A mapfile for the ANM sources built by [`anm_entry`].
+pub const ANM_MAPFILE: &str = "!anmmap\n!ins_names\n48 pos\n";
+
+/// Source for a TH12 ANM entry without an image or sprites, followed by some scripts.
+pub fn anm_entry(path: &str, scripts: &str) -> String {
+    format!(r#"
+entry {{
+    path: "{path}",
+    has_data: false,
+    img_width: 16,
+    img_height: 16,
+    img_format: 3,
+    offset_x: 0,
+    offset_y: 0,
+    colorkey: 0,
+    memory_priority: 0,
+    low_res_scale: false,
+    sprites: {{}},
+}}
+{scripts}
+"#)
+}
+
+/// Call a function with a [`Truth`] that captures its diagnostics, and get the diagnostics that were
+/// emitted along with the function's output.
+pub fn with_truth<R>(func: impl FnOnce(&mut Truth) -> R) -> (R, String) {
+    with_truth_from(&mut truth::Builder::new(), func)
+}
+
+/// [`with_truth`], for a [`Truth`] with other options.
+pub fn with_truth_from<R>(builder: &mut truth::Builder, func: impl FnOnce(&mut Truth) -> R) -> (R, String) {
+    let mut scope = builder.capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let result = func(&mut truth);
+    truth.emit_diagnostic_summary();
+    (result, truth.get_captured_diagnostics().unwrap())
+}
+
+/// Compile TH12 ANM source using [`ANM_MAPFILE`], panicking on errors.
+pub fn compile_anm(source: &str) -> Vec<u8> {
+    let (bytes, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("<mapfile>", ANM_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "<input>", source, &[mapfile])
+    });
+    bytes.expect(&stderr)
+}
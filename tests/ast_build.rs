@@ -0,0 +1,58 @@
+//! Tests for constructing an AST using only the public builder functions in `truth::ast::build`.
+
+use truth::ast::{self, build};
+use truth::{Game, Truth};
+
+const MAPFILE: &str = r#"!eclmap
+!ins_names
+1000 foo
+!ins_signatures
+1000 Sf
+1001 S
+"#;
+
+fn compile(truth: &mut Truth, mut script: ast::ScriptFile) -> truth::EclFile {
+    let mapfile = truth.parse_mapfile("<mapfile>", MAPFILE).unwrap();
+    truth.apply_mapfile(&mapfile, Game::Th06).unwrap();
+
+    truth.fill_missing_ids(&mut script).unwrap();
+    let mut truth = truth.validate_defs().unwrap();
+    truth.compile_ecl(Game::Th06, &script).unwrap()
+}
+
+#[test]
+fn compile_built_function() {
+    use ast::TypeKeyword as Ty;
+
+    // void sub0() {
+    //     foo(3, 1.5);
+    //     +10:
+    //     const int X = 7;
+    //     ins_1001(X * 2);
+    // }
+    let script = build::script_file([
+        build::timeline(Some(0), build::block([])),
+        build::func(Ty::Void, "sub0", [], build::block([
+            build::expr_stmt(build::call("foo", [build::int(3), build::float(1.5)])),
+            build::rel_time_label(10),
+            build::item_stmt(build::const_var(Ty::Int, "X", build::int(7))),
+            build::expr_stmt(build::ins(1001, [
+                build::binop(build::var("X"), ast::BinOpKind::Mul, build::int(2)),
+            ])),
+        ])),
+    ]);
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let ecl = compile(&mut truth, script);
+    assert_eq!(truth.get_captured_diagnostics().unwrap(), "");
+
+    let instrs = &ecl.subs[0];
+    assert_eq!(instrs.len(), 2);
+
+    assert_eq!((instrs[0].time, instrs[0].opcode), (0, 1000));
+    assert_eq!(instrs[0].args_blob, [&3i32.to_le_bytes()[..], &1.5f32.to_le_bytes()[..]].concat());
+
+    assert_eq!((instrs[1].time, instrs[1].opcode), (10, 1001));
+    assert_eq!(instrs[1].args_blob, 14i32.to_le_bytes());
+}
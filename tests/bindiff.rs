@@ -0,0 +1,105 @@
+//! Tests for [`truth::Truth::diff_from_bytes`].
+
+use truth::bindiff::{BinDiff, Change};
+use truth::{Game, FileFormat};
+
+mod api_impl;
+use api_impl::{ANM_MAPFILE, anm_entry, compile_anm, with_truth};
+
+fn diff_anm(old: &str, new: &str) -> BinDiff {
+    let (old, new) = (compile_anm(old), compile_anm(new));
+    let (diff, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("<mapfile>", ANM_MAPFILE).unwrap();
+        truth.diff_from_bytes(FileFormat::Anm, Game::Th12, ("old.anm", &old), ("new.anm", &new), &[mapfile])
+    });
+    let diff = diff.expect(&stderr);
+    assert_eq!(stderr, "");
+    diff
+}
+
+#[test]
+fn identical() {
+    let source = anm_entry("a.png", "script main { ins_0(); ins_1(); }");
+    assert!(diff_anm(&source, &source).is_empty());
+}
+
+#[test]
+fn first_differing_instruction() {
+    let old = anm_entry("a.png", "script main { ins_0(); pos(1.0, 2.0, 3.0); +10: ins_1(); }");
+    let new = anm_entry("a.png", "script main { ins_0(); pos(1.0, 5.0, 3.0); +10: ins_1(); }");
+    let diff = diff_anm(&old, &new);
+    assert_eq!(diff.changes.len(), 1, "{diff}");
+
+    let script_diff = match &diff.changes[0] {
+        Change::ScriptModified(script_diff) => script_diff,
+        _ => panic!("{diff}"),
+    };
+    assert_eq!(script_diff.hunks.len(), 1);
+    let (old_instr, new_instr) = script_diff.first_difference();
+    let (old_instr, new_instr) = (old_instr.unwrap(), new_instr.unwrap());
+    assert_eq!(old_instr.index, 1);
+    assert_eq!(old_instr.name.as_ref().unwrap(), "pos");
+    assert_eq!(old_instr.args.as_ref().unwrap()[1].value, truth::ScalarValue::Float(2.0));
+    assert_eq!(new_instr.args.as_ref().unwrap()[1].value, truth::ScalarValue::Float(5.0));
+    assert_eq!(diff.to_string(), "\
+script modified: script0
+  at old #1, new #1:
+  - #1 @0x8 t=0: pos(1.0, 2.0, 3.0)
+  + #1 @0x8 t=0: pos(1.0, 5.0, 3.0)
+");
+}
+
+#[test]
+fn resync_after_insertion() {
+    // the loop's jump is after the inserted instruction; its offset changes, but it should still compare equal
+    let old = anm_entry("a.png", r#"
+script main {
+    ins_0();
+    loop {
+        pos(1.0, 2.0, 3.0);
+        +1:
+    }
+    ins_1();
+}"#);
+    let new = old.replace("ins_0();", "ins_0();\n    $REG[10000] = 3;");
+    let diff = diff_anm(&old, &new);
+    let script_diff = match &diff.changes[..] {
+        [Change::ScriptModified(script_diff)] => script_diff,
+        _ => panic!("{diff}"),
+    };
+    assert_eq!(script_diff.hunks.len(), 1, "{diff}");
+    let hunk = &script_diff.hunks[0];
+    assert_eq!((hunk.old_index, hunk.new_index), (1, 1));
+    assert_eq!(hunk.old.len(), 0);
+    assert_eq!(hunk.new.len(), 1);
+}
+
+#[test]
+fn scripts_and_entries() {
+    let old = [
+        anm_entry("a.png", "script a0 { ins_0(); }"),
+        anm_entry("b.png", "script b0 { ins_1(); }"),
+    ].concat();
+    let new = [
+        anm_entry("a.png", "script added { ins_61(); } script a0 { ins_0(); }"),
+        anm_entry("b2.png", "script b0 { ins_1(); }"),
+        anm_entry("c.png", ""),
+    ].concat();
+    let diff = diff_anm(&old, &new);
+    assert_eq!(diff.changes, vec![
+        Change::EntryRenamed { old_name: "b.png".into(), new_name: "b2.png".into() },
+        Change::EntryAdded { name: "c.png".into() },
+        Change::ScriptAdded { name: "script0".into() },
+        Change::ScriptRenamed { old_name: "script0".into(), new_name: "script1".into() },
+        Change::ScriptRenamed { old_name: "script1".into(), new_name: "script2".into() },
+    ]);
+}
+
+#[test]
+fn mission_is_unsupported() {
+    let (result, stderr) = with_truth(|truth| {
+        truth.diff_from_bytes(FileFormat::Mission, Game::Th125, ("a", b""), ("b", b""), &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("cannot be diffed"), "{stderr}");
+}
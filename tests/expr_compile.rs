@@ -6,7 +6,7 @@ extern crate truth;
 use truth::{ast, llir, vm::AstVm};
 use truth::{Truth, ScalarValue, ScalarType as Ty, RegId};
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 #[derive(Clone)]
 struct Var {
@@ -23,6 +23,7 @@ const COUNT_JUMP_OPCODE: u16 = 2;
 const SINE_OPCODE: u16 = 3;
 const COSINE_OPCODE: u16 = 4;
 const LT_FLOAT_OPCODE: u16 = 5;
+const SQRT_OPCODE: u16 = 6;
 const ASSIGN_OPS_OPCODE: u16 = 10;
 const BINARY_OPS_OPCODE: u16 = 30;
 const COND_JUMPS_OPCODE: u16 = 40;
@@ -63,6 +64,7 @@ fn load_mapfile(truth: &mut Truth, vars: &[Var]) {
         format!("{} ff", SINE_OPCODE),
         format!("{} ff", COSINE_OPCODE),
         format!("{} Sff", LT_FLOAT_OPCODE),
+        format!("{} ff", SQRT_OPCODE),
         format!("{}", ANTI_SCRATCH_OPCODE),
         format!("{}", NOP_OPCODE),
         format!("{}", OTHER_OPCODE),
@@ -74,6 +76,7 @@ fn load_mapfile(truth: &mut Truth, vars: &[Var]) {
         format!("{} {}", SINE_OPCODE, I::UnOp(U::Sin, Ty::Float)),
         format!("{} {}", COSINE_OPCODE, I::UnOp(U::Cos, Ty::Float)),
         format!("{} {}", LT_FLOAT_OPCODE, I::BinOp(ast::BinOpKind::Lt, Ty::Float)),
+        format!("{} {}", SQRT_OPCODE, I::UnOp(U::Sqrt, Ty::Float)),
     ];
 
     let mut oper_opcodes = ASSIGN_OPS_OPCODE..;
@@ -154,7 +157,7 @@ fn make_language(vars: &[Var]) -> impl llir::LanguageHooks {
     format
 }
 
-fn make_randomized_vm(vars: &[Var]) -> AstVm {
+fn make_randomized_vm(vars: &[Var], difficulty: u32) -> AstVm {
     let mut vm = AstVm::new().with_max_iterations(10000);
     for var in vars {
         if let Some(special) = &var.special {
@@ -164,7 +167,6 @@ fn make_randomized_vm(vars: &[Var]) -> AstVm {
         }
     }
 
-    let difficulty = rand::thread_rng().gen_range(0, 4);
     vm.with_difficulty(difficulty)
 }
 
@@ -253,13 +255,28 @@ const SPECIAL_VARS: &'static [Var] = &[
 /// This function will automatically check that the `time`, `real_time`, and `call_log`
 /// match.  Then it returns the VMs so that the caller can check the equality of any registers
 /// that it knows should not have been used for scratch.
+///
+/// The VMs run at a random difficulty.
 #[track_caller]
 fn run_randomized_test(vars: &[Var], text: &str) -> Result<TestResult, String> {
+    let difficulty = rand::thread_rng().gen_range(0, 4);
+    run_randomized_test_at_difficulty(vars, text, difficulty)
+}
+
+/// Like [`run_randomized_test`], but runs the same script at each of the
+/// four difficulties.  Useful for diff switches and difficulty labels.
+#[track_caller]
+fn run_randomized_test_at_each_difficulty(vars: &[Var], text: &str) -> Result<Vec<TestResult>, String> {
+    (0..4).map(|difficulty| run_randomized_test_at_difficulty(vars, text, difficulty)).collect()
+}
+
+#[track_caller]
+fn run_randomized_test_at_difficulty(vars: &[Var], text: &str, difficulty: u32) -> Result<TestResult, String> {
     truth::setup_for_test_harness();
 
     let mut scope = truth::Builder::new().capture_diagnostics(true).build();
     let mut truth = scope.truth();
-    _run_randomized_test(&mut truth, vars, text)
+    _run_randomized_test(&mut truth, vars, text, difficulty)
         .map_err(|e| {
             e.ignore();
             truth.get_captured_diagnostics().unwrap()
@@ -267,14 +284,14 @@ fn run_randomized_test(vars: &[Var], text: &str) -> Result<TestResult, String> {
 }
 
 #[track_caller]
-fn _run_randomized_test(truth: &mut Truth, plain_vars: &[Var], text: &str) -> Result<TestResult, truth::ErrorReported> {
+fn _run_randomized_test(truth: &mut Truth, plain_vars: &[Var], text: &str, difficulty: u32) -> Result<TestResult, truth::ErrorReported> {
     // all tests have the special vars since they're relatively harmless when not used
     let ref vars = plain_vars.iter().chain(SPECIAL_VARS).cloned().collect::<Vec<_>>();
 
     load_mapfile(truth, vars);
 
     let hooks = make_language(vars);
-    let base_vm = make_randomized_vm(vars);
+    let base_vm = make_randomized_vm(vars, difficulty);
 
     let parsed_block = {
         let mut block = truth.parse::<ast::Block>("<input>", text.as_ref())?.value;
@@ -309,7 +326,7 @@ fn _run_randomized_test(truth: &mut Truth, plain_vars: &[Var], text: &str) -> Re
     let new_block = {
         let options = Default::default();
         let const_proof = truth::passes::evaluate_const_vars::run(ctx)?;
-        let mut raiser = llir::Raiser::new(&hooks, ctx.emitter, ctx, &options, const_proof)?;
+        let raiser = llir::Raiser::new(&hooks, ctx.emitter, ctx, &options, const_proof)?;
         let mut stmts = raiser.raise_instrs_to_sub_ast(&emitter, &instrs, &ctx)?;
         truth::passes::resolution::aliases_to_raw(&mut stmts[..], ctx)?;
         ast::Block(stmts)
@@ -941,8 +958,8 @@ fn loop_break() {
 
 #[test]
 fn loop_diff_switch() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             A = 5;
             B = 0;
             times(A+2:A+3:B+2:B+1) {
@@ -950,64 +967,70 @@ fn loop_diff_switch() {
                 nop();
                 +5:  // if the loop body got replicated we'll have some time label memes...
             }
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_B, REG_C]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_B, REG_C]);
+        }
     }
 }
 
 #[test]
 fn cast_diff_switch() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             A = int(X::Y:);
             A = int(X::Y:) + 2;
             B = int(X*Y::Y*Y:);
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_B]);
-        vms.check_regs(&[REG_X, REG_Y]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_B]);
+            vms.check_regs(&[REG_X, REG_Y]);
+        }
     }
 }
 
 #[test]
 fn assign_op_diff_switch() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             A += A+B:C::;
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_B, REG_C]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_B, REG_C]);
+        }
     }
 }
 
 #[test]
 fn mismatched_diff_switch_cases() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             A = (B::C:) * (A:C::);
             X = (Y+Z::Z:) * (Y+X:Z::);
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_B, REG_C]);
-        vms.check_regs(&[REG_X, REG_Y, REG_Z]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_B, REG_C]);
+            vms.check_regs(&[REG_X, REG_Y, REG_Z]);
+        }
     }
 }
 
 #[test]
 fn nested_diff_switch_cases() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             A = (A+((A:2:3:4):2:3:4):(A+1:2:3:4):C:);
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_Z]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_Z]);
+        }
     }
 }
 
 #[test]
 fn difficulty_label_and_diff_switch() {
-    for _ in 0..10 {
-        let vms = run_randomized_test(SIMPLE_FOUR_VAR_SPEC, r#"{
+    for _ in 0..3 {
+        for vms in run_randomized_test_at_each_difficulty(SIMPLE_FOUR_VAR_SPEC, r#"{
             {"12"}: A = 300::400:;
             {"12"}: B = 300:400::;
-        }"#).unwrap();
-        vms.check_regs(&[REG_A, REG_B]);
+        }"#).unwrap() {
+            vms.check_regs(&[REG_A, REG_B]);
+        }
     }
 }
 
@@ -1063,3 +1086,97 @@ fn trivial_cast() {
         vms.check_no_scratch_of_ty(Ty::Float);
     }
 }
+
+const REG_IN_INT: RegId = RegId(1021);
+const REG_IN_FLOAT: RegId = RegId(1022);
+const REG_OUT_INT: RegId = RegId(1023);
+const REG_OUT_FLOAT: RegId = RegId(1024);
+
+const FUZZ_SPEC: &'static [Var] = &[
+    Var { reg: REG_A, ty: Some(Ty::Int), name: Some("A"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_B, ty: Some(Ty::Int), name: Some("B"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_C, ty: Some(Ty::Int), name: Some("C"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_D, ty: Some(Ty::Int), name: Some("D"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_X, ty: Some(Ty::Float), name: Some("X"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_Y, ty: Some(Ty::Float), name: Some("Y"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_Z, ty: Some(Ty::Float), name: Some("Z"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_W, ty: Some(Ty::Float), name: Some("W"), scratch: true, in_mapfile: true, special: None },
+    Var { reg: REG_IN_INT, ty: Some(Ty::Int), name: Some("I"), scratch: false, in_mapfile: true, special: None },
+    Var { reg: REG_IN_FLOAT, ty: Some(Ty::Float), name: Some("F"), scratch: false, in_mapfile: true, special: None },
+    Var { reg: REG_OUT_INT, ty: Some(Ty::Int), name: Some("OUT_I"), scratch: false, in_mapfile: true, special: None },
+    Var { reg: REG_OUT_FLOAT, ty: Some(Ty::Float), name: Some("OUT_F"), scratch: false, in_mapfile: true, special: None },
+];
+
+/// Generates random expressions that only read the non-scratch inputs of [`FUZZ_SPEC`].
+struct ExprGen<R> {
+    rng: R,
+}
+
+impl<R: Rng> ExprGen<R> {
+    fn int_expr(&mut self, depth: u32) -> String {
+        if depth == 0 || self.rng.gen_range(0, 4) == 0 {
+            return match self.rng.gen_range(0, 3) {
+                0 => format!("I"),
+                1 => format!("$F"),
+                _ => format!("({})", self.rng.gen_range(-7, 7+1)),
+            };
+        }
+        match self.rng.gen_range(0, 7) {
+            0 => format!("({} + {})", self.int_expr(depth - 1), self.int_expr(depth - 1)),
+            1 => format!("({} - {})", self.int_expr(depth - 1), self.int_expr(depth - 1)),
+            2 => format!("({} * {})", self.int_expr(depth - 1), self.int_expr(depth - 1)),
+            3 => format!("(-{})", self.int_expr(depth - 1)),
+            4 => format!("(~{})", self.int_expr(depth - 1)),
+            5 => format!("$({})", self.float_expr(depth - 1)),
+            _ => format!("int({})", self.float_expr(depth - 1)),
+        }
+    }
+
+    fn float_expr(&mut self, depth: u32) -> String {
+        if depth == 0 || self.rng.gen_range(0, 4) == 0 {
+            return match self.rng.gen_range(0, 4) {
+                0 => format!("F"),
+                1 => format!("%I"),
+                // large enough that casting it back to int is out of range
+                2 => format!("({:?})", [3e9_f32, -3e9][self.rng.gen_range(0, 2)]),
+                _ => format!("({:?})", self.rng.gen_range(-8, 8+1) as f32 * 0.375),
+            };
+        }
+        match self.rng.gen_range(0, 10) {
+            0 => format!("({} + {})", self.float_expr(depth - 1), self.float_expr(depth - 1)),
+            1 => format!("({} - {})", self.float_expr(depth - 1), self.float_expr(depth - 1)),
+            2 => format!("({} * {})", self.float_expr(depth - 1), self.float_expr(depth - 1)),
+            3 => format!("({} / {})", self.float_expr(depth - 1), self.float_expr(depth - 1)),
+            4 => format!("(-{})", self.float_expr(depth - 1)),
+            5 => format!("sin({})", self.float_expr(depth - 1)),
+            6 => format!("cos({})", self.float_expr(depth - 1)),
+            7 => format!("sqrt({})", self.float_expr(depth - 1)),
+            8 => format!("%({})", self.int_expr(depth - 1)),
+            _ => format!("float({})", self.int_expr(depth - 1)),
+        }
+    }
+}
+
+#[test]
+fn fuzz_unops_and_casts() {
+    // Seeded so that a failure can be reproduced.  (the VM's initial register values are still random,
+    // but the source text of a failing case is printed)
+    let mut gen = ExprGen { rng: rand::rngs::StdRng::seed_from_u64(0x7275_7468) };
+    for _ in 0..200 {
+        let source = format!("{{\n    OUT_I = {};\n    OUT_F = {};\n}}", gen.int_expr(4), gen.float_expr(4));
+        let vms = run_randomized_test(FUZZ_SPEC, &source).unwrap_or_else(|e| panic!("{}\n{}", source, e));
+
+        assert_eq!(vms.old.get_reg(REG_OUT_INT), vms.new.get_reg(REG_OUT_INT), "{}", source);
+        // compare floats bitwise, except that any NaN is as good as any other
+        match (vms.old.get_reg(REG_OUT_FLOAT).unwrap(), vms.new.get_reg(REG_OUT_FLOAT).unwrap()) {
+            (ScalarValue::Float(old), ScalarValue::Float(new)) => assert!(
+                old.to_bits() == new.to_bits() || (old.is_nan() && new.is_nan()),
+                "{} != {}\n{}", old, new, source,
+            ),
+            other => panic!("{:?}", other),
+        }
+        for &reg in &[REG_IN_INT, REG_IN_FLOAT] {
+            vms.check_reg_with_msg(reg, format_args!("reg {}\n{}", reg, source));
+        }
+    }
+}
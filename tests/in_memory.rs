@@ -0,0 +1,385 @@
+//! Tests for the API functions that compile and decompile without touching the filesystem.
+
+use truth::{Game, FileFormat, DecompileOptions};
+
+mod api_impl;
+use api_impl::with_truth;
+
+const STD_SOURCE: &str = r#"
+meta {
+    unknown: 0,
+    stage_name: "dm",
+    bgm: [
+        {path: "bgm/th08_08.mid", name: "dm"},
+        {path: "bgm/th08_09.mid", name: "dm"},
+        {path: " ", name: " "},
+        {path: " ", name: " "},
+    ],
+    objects: {},
+    instances: [],
+}
+
+script main {
+    cameraFacing(1.0, 2.0, 3.0);
+}
+"#;
+
+const STD_MAPFILE: &str = r#"!stdmap
+!ins_names
+2 cameraFacing
+"#;
+
+const ANM_HEAD: &str = r#"
+entry {
+    path: "subdir/file.png",
+    has_data: HAS_DATA,
+    img_width: 16,
+    img_height: 16,
+    img_format: 3,
+    offset_x: 0,
+    offset_y: 0,
+    colorkey: 0,
+    memory_priority: 0,
+    low_res_scale: false,
+    sprites: {
+        sprite0: {id: 0, x: 0.0, y: 0.0, w: 16.0, h: 16.0},
+    },
+}
+
+script script0 {
+    ins_3(sprite0);
+}
+"#;
+
+#[test]
+fn std_round_trip() {
+    let (bytes, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "virtual.std.txt", STD_SOURCE, &[mapfile])
+    });
+    let bytes = bytes.expect(&stderr);
+    assert!(!bytes.is_empty());
+
+    let (decompiled, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.decompile_from_bytes(FileFormat::Std, Game::Th06, "virtual.std", &bytes, &[mapfile], &Default::default())
+    });
+    let decompiled = decompiled.expect(&stderr);
+    assert!(decompiled.contains("cameraFacing(1.0, 2.0, 3.0);"), "{decompiled}");
+
+    // and it should compile again to the exact same thing
+    let (recompiled, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "decompiled.std.txt", &decompiled, &[mapfile])
+    });
+    assert_eq!(recompiled.expect(&stderr), bytes);
+}
+
+#[test]
+fn diagnostics_use_display_names() {
+    let (result, stderr) = with_truth(|truth| {
+        let source = STD_SOURCE.replace("1.0, 2.0, 3.0", "1, 2.0, 3.0");
+        let mapfile = truth.parse_mapfile("virtual/names.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "virtual/stage.std.txt", &source, &[mapfile])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("virtual/stage.std.txt:"), "{stderr}");
+
+    let (result, stderr) = with_truth(|truth| {
+        truth.decompile_from_bytes(FileFormat::Std, Game::Th06, "virtual/garbage.std", b"lol", &[], &Default::default())
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("virtual/garbage.std"), "{stderr}");
+}
+
+#[test]
+fn mapfile_pragma_is_rejected() {
+    let (result, stderr) = with_truth(|truth| {
+        let source = format!("#pragma mapfile \"map/any.stdm\"\n{STD_SOURCE}");
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "<input>", &source, &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("unexpected mapfile pragma"), "{stderr}");
+}
+
+#[test]
+fn anm_image_source_callback() {
+    // generate an ANM file with image data to use as an image source
+    let (source_bytes, stderr) = with_truth(|truth| {
+        let source = ANM_HEAD.replace("HAS_DATA", r#""dummy""#);
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "source.anm.txt", &source, &[])
+    });
+    let source_bytes = source_bytes.expect(&stderr);
+
+    let source = format!("#pragma image_source \"source.anm\"\n{}", ANM_HEAD.replace("HAS_DATA", "true"));
+
+    let (result, stderr) = with_truth(|truth| {
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "main.anm.txt", &source, &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("image source 'source.anm' was not provided"), "{stderr}");
+
+    let mut requested = vec![];
+    let (result, stderr) = with_truth(|truth| {
+        truth.compile_from_str_with_image_sources(FileFormat::Anm, Game::Th12, "main.anm.txt", &source, &[], |name| {
+            requested.push(name.to_owned());
+            Some(source_bytes.clone())
+        })
+    });
+    result.expect(&stderr);
+    assert_eq!(requested, vec!["source.anm".to_owned()]);
+}
+
+#[test]
+fn check_without_image_sources() {
+    // this fails to compile only because the image source isn't available
+    let source = format!("#pragma image_source \"source.anm\"\n{}", ANM_HEAD.replace("HAS_DATA", "true"));
+    let (result, stderr) = with_truth(|truth| {
+        truth.check(FileFormat::Anm, Game::Th12, "main.anm.txt", &source, &[])
+    });
+    result.expect(&stderr);
+
+    let source = source.replace("ins_3(sprite0);", "ins_3(sprite0 + 1.0);");
+    let (result, stderr) = with_truth(|truth| {
+        truth.check(FileFormat::Anm, Game::Th12, "main.anm.txt", &source, &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("type error"), "{stderr}");
+
+    let (result, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.check(FileFormat::Std, Game::Th06, "virtual.std.txt", STD_SOURCE, &[mapfile])
+    });
+    result.expect(&stderr);
+
+    // signatures from mapfiles are still checked
+    let (result, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.check(FileFormat::Std, Game::Th06, "virtual.std.txt", &STD_SOURCE.replace("1.0, 2.0, 3.0", "1.0"), &[mapfile])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("expects 3 arguments"), "{stderr}");
+}
+
+#[test]
+fn decompile_options_builder() {
+    let (bytes, stderr) = with_truth(|truth| {
+        let source = ANM_HEAD.replace("HAS_DATA", r#""dummy""#);
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "source.anm.txt", &source, &[])
+    });
+    let bytes = bytes.expect(&stderr);
+
+    let decompile = |options: DecompileOptions| {
+        let (decompiled, stderr) = with_truth(|truth| {
+            truth.decompile_from_bytes(FileFormat::Anm, Game::Th12, "a.anm", &bytes, &[], &options)
+        });
+        decompiled.expect(&stderr)
+    };
+    assert!(decompile(DecompileOptions::new()).contains("ins_3(sprite0);"));
+    assert!(decompile(DecompileOptions::new().const_names(false)).contains("ins_3(0);"));
+
+    // disabling arguments must also disable intrinsics
+    let options = DecompileOptions::new().arguments(false);
+    assert!(!options.intrinsics);
+    assert!(!options.intrinsics(true).intrinsics);
+}
+
+#[test]
+fn alcostg_shares_th10_format() {
+    for name in ["alcostg", "th103", "103"] {
+        assert_eq!(name.parse::<Game>().unwrap(), Game::Alcostg);
+    }
+    assert_eq!("th10".parse::<Game>().unwrap(), Game::Th10);
+
+    let source = ANM_HEAD.replace("HAS_DATA", "false");
+    let compile = |game| with_truth(|truth| {
+        truth.compile_from_str(FileFormat::Anm, game, "<input>", &source, &[])
+    });
+    let (th10_bytes, stderr) = compile(Game::Th10);
+    let th10_bytes = th10_bytes.expect(&stderr);
+    let (alcostg_bytes, stderr) = compile(Game::Alcostg);
+    assert_eq!(alcostg_bytes.expect(&stderr), th10_bytes);
+}
+
+#[test]
+fn source_map() {
+    let source = STD_SOURCE.replace("cameraFacing(1.0, 2.0, 3.0);", "cameraFacing(1.0, 2.0, 3.0);\n    ins_5();");
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+    let result = truth.compile_from_str(FileFormat::Std, Game::Th06, "<input>", &source, &[mapfile]);
+    result.expect(&truth.get_captured_diagnostics().unwrap());
+
+    let scripts = truth.script_debug_info();
+    assert_eq!(scripts.len(), 1);
+    assert_eq!(scripts[0].export_info.name.as_deref(), Some("main"));
+
+    let offset_info = &scripts[0].lowering_info.offset_info;
+    let source_text = |instr: &truth::debug_info::Instr| {
+        let span = instr.span.as_span();
+        &source[span.start.0 as usize..span.end.0 as usize]
+    };
+    let instrs = &offset_info.instrs;
+    assert_eq!(instrs.len(), 2);
+    assert_eq!(source_text(&instrs[0]), "cameraFacing(1.0, 2.0, 3.0)");
+    assert_eq!(source_text(&instrs[1]), "ins_5()");
+    assert_eq!(instrs[0].offset, 0);
+    assert_eq!(instrs[1].offset, instrs[0].size);
+    assert_eq!(offset_info.end_offset, instrs[1].offset + instrs[1].size);
+}
+
+#[test]
+fn reformat_keeps_comments_and_output() {
+    let source = r#"
+// Stage 1 background.
+meta {
+    unknown: 0,
+    stage_name: "dm",
+    bgm: [
+        {path: "bgm/th08_08.mid", name: "dm"},  // main theme
+        {path: "bgm/th08_09.mid", name: "dm"},
+        {path: " ", name: " "},
+        {path: " ", name: " "},
+    ],
+    objects: {},
+    instances: [],
+}
+
+/* The camera
+   script. */
+script   main {
+    // look ahead
+    cameraFacing(1.0,2.0,   3.0); // initial
++30:  // half a second
+    {
+        cameraFacing(1.0, 2.0, 4.0);  /* a */  // b
+    +1:
+    }
+}
+// end
+"#;
+    let compile = |text: &str| with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "virtual.std.txt", text, &[mapfile])
+    });
+
+    let (reformatted, stderr) = with_truth(|truth| truth.reformat("virtual.std.txt", source.as_bytes()));
+    let reformatted = reformatted.expect(&stderr);
+    let comments = [
+        "// Stage 1 background.", "// main theme", "/* The camera\n   script. */", "// look ahead",
+        "// initial", "// half a second", "/* a */ // b", "// end",
+    ];
+    for comment in comments {
+        assert!(reformatted.contains(comment), "missing {comment:?} in:\n{reformatted}");
+    }
+
+    let (original_bytes, stderr) = compile(source);
+    let original_bytes = original_bytes.expect(&stderr);
+    let (reformatted_bytes, stderr) = compile(&reformatted);
+    assert_eq!(reformatted_bytes.expect(&stderr), original_bytes);
+}
+
+#[test]
+fn std_from_raw_instrs() {
+    let skeleton = STD_SOURCE.split("script main").next().unwrap();
+    let instr = truth::llir::RawInstr {
+        opcode: 2,
+        args_blob: [1.0f32, 2.0, 3.0].iter().flat_map(|x| x.to_le_bytes()).collect(),
+        ..truth::llir::RawInstr::DEFAULTS
+    };
+    let (built, stderr) = with_truth(|truth| truth.build_std_from_raw(Game::Th06, "raw.std", skeleton, vec![instr.clone()]));
+    let built = built.expect(&stderr);
+
+    // should be identical to compiling the same instruction from source
+    let (compiled, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "virtual.std.txt", STD_SOURCE, &[mapfile])
+    });
+    assert_eq!(built, compiled.expect(&stderr));
+
+    let (read, stderr) = with_truth(|truth| truth.read_std_from_bytes(Game::Th06, "raw.std", &built));
+    assert_eq!(read.expect(&stderr).script, vec![instr]);
+}
+
+#[test]
+fn anm_from_raw_instrs_round_trip() {
+    let source = ANM_HEAD.replace("HAS_DATA", "false");
+    let (compiled, stderr) = with_truth(|truth| truth.compile_from_str(FileFormat::Anm, Game::Th12, "virtual.anm.txt", &source, &[]));
+    let compiled = compiled.expect(&stderr);
+
+    let (anm, stderr) = with_truth(|truth| truth.read_anm_from_bytes(Game::Th12, "virtual.anm", &compiled));
+    let anm = anm.expect(&stderr);
+    let scripts = anm.entries.iter().map(|entry| {
+        entry.scripts.iter().map(|(name, script)| (name.value.clone(), script.clone())).collect()
+    }).collect();
+
+    let skeleton = source.split("script script0").next().unwrap();
+    let (rebuilt, stderr) = with_truth(|truth| truth.build_anm_from_raw(Game::Th12, "raw.anm", skeleton, scripts));
+    assert_eq!(rebuilt.expect(&stderr), compiled);
+}
+
+#[test]
+fn raw_skeleton_rejects_scripts() {
+    let (result, stderr) = with_truth(|truth| truth.build_std_from_raw(Game::Th06, "raw.std", STD_SOURCE, vec![]));
+    assert!(result.is_err());
+    assert!(stderr.contains("unexpected item in STD skeleton"), "{stderr}");
+}
+
+#[test]
+fn modern_ecl_include_lists_too_long() {
+    // the end of the include lists is stored in a u16
+    let names = (0..300).map(|i| format!("\"{i:0>250}\"")).collect::<Vec<_>>();
+    let source = format!("meta {{ anim: [{}] }}\nvoid main() {{}}", names.join(", "));
+    let (result, stderr) = with_truth(|truth| {
+        truth.compile_from_str(FileFormat::Ecl, Game::Th10, "<input>", &source, &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("include lists are too long"), "{stderr}");
+
+    let (result, stderr) = with_truth(|truth| {
+        truth.decompile_from_bytes(FileFormat::Ecl, Game::Th10, "virtual.ecl", b"SCPT", &[], &Default::default())
+    });
+    assert!(result.is_err(), "{stderr}");
+}
+
+#[test]
+fn batch_compile() {
+    let mut scope = truth::Builder::new().capture_diagnostics(true).collect_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+
+    // each file gets a fresh set of items, so the same names can be defined in each of them,
+    // and a file can't see items from a previous one
+    let with_const = format!("const int FOO = 3;\n{STD_SOURCE}");
+    let uses_const = STD_SOURCE.replace("cameraFacing(1.0, 2.0, 3.0);", "cameraFacing(1.0, 2.0, FOO);");
+    let with_reg_pragma = format!("#pragma reg std $counter = 10000;\n{STD_SOURCE}");
+    let jobs = [
+        truth::BatchJob { format: FileFormat::Std, display_name: "a.std.txt", text: &with_const },
+        truth::BatchJob { format: FileFormat::Std, display_name: "b.std.txt", text: &with_const },
+        truth::BatchJob { format: FileFormat::Std, display_name: "c.std.txt", text: &uses_const },
+        truth::BatchJob { format: FileFormat::Std, display_name: "d.std.txt", text: &with_reg_pragma },
+    ];
+    let results = truth.compile_batch(Game::Th06, &jobs, &[mapfile]).unwrap();
+    let stderr = truth.get_captured_diagnostics().unwrap();
+    assert_eq!((results.num_ok(), results.num_failed()), (3, 1), "{stderr}");
+
+    assert!(results.jobs[2].output.is_err());
+    assert!(stderr.contains("c.std.txt"), "{stderr}");
+    let errors = &results.jobs[2].diagnostics;
+    assert_eq!(errors.len(), 1, "{errors:?}");
+    assert!(errors[0].message.contains("FOO"), "{errors:?}");
+    assert!(results.jobs[0].diagnostics.is_empty());
+    assert_eq!(results.jobs[0].script_stats.scripts.len(), 1);
+    assert!(results.jobs[2].script_stats.scripts.is_empty());
+
+    // same as compiling each file on its own
+    let (expected, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("virtual.stdm", STD_MAPFILE).unwrap();
+        truth.compile_from_str(FileFormat::Std, Game::Th06, "a.std.txt", &with_const, &[mapfile])
+    });
+    let expected = expected.expect(&stderr);
+    assert_eq!(results.jobs[0].output.as_ref().unwrap(), &expected);
+    assert_eq!(results.jobs[1].output.as_ref().unwrap(), &expected);
+    assert_eq!(results.jobs[3].output.as_ref().unwrap(), &expected);
+}
@@ -13,10 +13,10 @@ entry {
     path: "subdir/file1.png",
     has_data: false,
     sprites: {
-        valueA: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: valueC + 2 - valueC},
-        valueB: {x: 0.0, y: 0.0, w: 0.0, h: 0.0},
-        valueC: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 26 * 2 + 1},
-        valueD: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: valueE - 1},
+        valueA: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: valueC + 2 - valueC},
+        valueB: {x: 0.0, y: 0.0, w: 4.0, h: 4.0},
+        valueC: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 26 * 2 + 1},
+        valueD: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: valueE - 1},
     },
 }
 
@@ -24,8 +24,8 @@ entry {
     path: "subdir/file2.png",
     has_data: false,
     sprites: {
-        valueE: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 401},
-        valueF: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: int(%valueE + 2.4) + 1},
+        valueE: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 401},
+        valueF: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: int(%valueE + 2.4) + 1},
     },
 }
 
@@ -85,7 +85,7 @@ entry {
     path: "subdir/file1.png",
     has_data: false,
     sprites: {
-        sprite23: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 23},
+        sprite23: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 23},
     },
 }
 
@@ -98,6 +98,50 @@ script script0 {
     },
 );
 
+source_test!(
+    ANM_12, decompile_no_const_names,
+    full_source: r#"
+#pragma image_source "./tests/integration/resources/th12-multiple-match-source.anm"
+
+entry {
+    path: "subdir/file1.png",
+    has_data: false,
+    sprites: {
+        sprite23: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 23},
+    },
+}
+
+script script0 {
+    ins_3(sprite23);
+}
+    "#,
+    decompile_args: &["--no-const-names"],
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("(23)"));
+        assert!(!decompiled.contains("(sprite23)"));
+    },
+);
+
+source_test!(
+    ANM_12, sprite_name_typo,
+    full_source: r#"
+#pragma image_source "./tests/integration/resources/th12-multiple-match-source.anm"
+
+entry {
+    path: "subdir/file1.png",
+    has_data: false,
+    sprites: {
+        bulletRed: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 23},
+        bulletBlue: {x: 0.0, y: 0.0, w: 4.0, h: 4.0},
+    },
+}
+
+script script0 {
+    ins_3(bulletRde);  //~ ERROR unknown
+}
+    "#,
+);
+
 // It is okay for two sprites to have the same name (this occurs in decompiled output),
 // but they must also have the same ID.
 source_test!(
@@ -109,7 +153,7 @@ entry {
     path: "subdir/file1.png",
     has_data: false,
     sprites: {
-        valueA: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 26 * 2 + 1},
+        valueA: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 26 * 2 + 1},
     },
 }
 
@@ -117,7 +161,7 @@ entry {
     path: "subdir/file2.png",
     has_data: false,
     sprites: {
-        xyzzyx: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 24},
+        xyzzyx: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 24},
         valueA: {x: 1.0, y: 1.0, w: 1.0, h: 1.0, id: 53},
     },
 }
@@ -144,7 +188,7 @@ entry {
     path: "subdir/file1.png",
     has_data: false,
     sprites: {
-        valueA: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 26 * 2 + 1},
+        valueA: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 26 * 2 + 1},
     },
 }
 
@@ -152,7 +196,7 @@ entry {
     path: "subdir/file2.png",
     has_data: false,
     sprites: {
-        xyzzyx: {x: 0.0, y: 0.0, w: 0.0, h: 0.0, id: 52},
+        xyzzyx: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 52},
         valueA: {x: 1.0, y: 1.0, w: 1.0, h: 1.0},   // dupe, but has same id as above
     },
 }
@@ -302,7 +346,7 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        sprite0: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: 10},
+        sprite0: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 10},
     },
 }
 
@@ -328,7 +372,7 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        sprite: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: B},
+        sprite: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: B},
     },
 }
 
@@ -354,8 +398,8 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        B: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: 42},
-        C: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: B + 2},
+        B: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 42},
+        C: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: B + 2},
     },
 }
 
@@ -385,8 +429,8 @@ entry {
     path: FILEPATH,
     has_data: false,
     sprites: {
-        sprite0: {x: POS_X, y: 0.0, w: 512.0, h: 480.0},
-        sprite1: {x: POS_X + 3.0, y: 0.0, w: 512.0, h: 480.0},
+        sprite0: {x: POS_X, y: 0.0, w: 4.0, h: 4.0},
+        sprite1: {x: POS_X + 3.0, y: 0.0, w: 4.0, h: 4.0},
     },
 }
 
@@ -411,7 +455,7 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        wild: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: 22 * 2 - 2},
+        wild: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 22 * 2 - 2},
     },
 }
 
@@ -441,7 +485,7 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        RAND: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: 22 * 2 - 2},
+        RAND: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 22 * 2 - 2},
     },
 }
 
@@ -501,6 +545,53 @@ script 101 another {}
     },
 );
 
+source_test!(
+    ANM_12, script_ids_interleaved,
+    items: r#"
+script a {}
+script 5 b {}
+script c {}
+script 2 d {}
+script e {}  //~ WARNING automatically numbered 7
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let ids = anm.entries[0].scripts.values().map(|script| script.id).collect::<Vec<_>>();
+        assert_eq!(ids[..5], [0, 5, 6, 2, 7]);  // (followed by the main script)
+    },
+);
+
+source_test!(
+    ANM_12, script_ids_duplicate,
+    items: r#"
+script 3 a {}
+script 3 b {}  //~ ERROR duplicate script number
+"#,
+);
+
+source_test!(
+    ANM_12, script_ids_duplicate_auto,
+    items: r#"
+script a {}
+script 0 b {}  //~ ERROR duplicate script number
+"#,
+);
+
+source_test!(
+    ANM_12, decompile_script_order_id,
+    items: r#"
+script 5 a {}
+script 2 b {}
+script 4 c {}
+"#,
+    decompile_args: &["--script-order", "id"],
+    require_roundtrip: false,  // the script table gets reordered
+    check_decompiled: |decompiled| {
+        let positions = ["script 2 ", "script 4 ", "script 5 ", "script 6 "].map(|s| decompiled.find(s).unwrap());
+        assert!(positions.windows(2).all(|w| w[0] < w[1]), "{}", decompiled);
+    },
+);
+
 source_test!(
     ANM_12, decompile_nonexistent_ids,
     full_source: r#"
@@ -569,7 +660,7 @@ entry {
     path: "lmao.png",
     has_data: false,
     sprites: {
-        imASprite: {x: 0.0, y: 0.0, w: 512.0, h: 480.0, id: 42},
+        imASprite: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 42},
     },
 }
 
@@ -584,3 +675,93 @@ script script1 {
 }
 "#,
 );
+
+source_test!(
+    ANM_12, consts_in_entry_meta,
+    mapfile: r#"!anmmap
+!enum(name="Offset")
+12 OffX
+"#,
+    full_source: r#"
+const int SIZE = 256;
+
+entry {
+    path: "lmao.png",
+    has_data: false,
+    rt_width: SIZE * 2,
+    rt_height: SIZE,
+    offset_x: OffX,
+    offset_y: Offset.OffX + 1,
+    sprites: {
+        sprite0: {x: 6.0 * 2.0, y: 0.0, w: 4.0, h: 4.0},
+    },
+}
+
+script script0 {}
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].specs.rt_width, 512);
+        assert_eq!(anm.entries[0].specs.rt_height, 256);
+        assert_eq!(anm.entries[0].specs.offset_x, 12);
+        assert_eq!(anm.entries[0].specs.offset_y, 13);
+        assert_eq!(anm.entries[0].sprites[0].offset[0], 12.0);
+    },
+);
+
+source_test!(
+    ANM_12, const_in_script,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+#pragma image_source "tests/integration/resources/th12-embedded-image-source.anm"
+
+entry {
+    path: "lmao.png",
+    has_data: false,
+    sprites: {
+        sprite0: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 10},
+    },
+}
+
+script script0 {
+    const int B = sprite0 + OFFSET;
+    const int OFFSET = 3;
+    ins_3(B);
+}
+
+script script1 {
+    const int B = 20;
+    ins_3(B);
+}
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[0].args_blob, blobify![13]);
+        assert_eq!(anm.entries[0].scripts[1].instrs[0].args_blob, blobify![20]);
+    },
+);
+
+source_test!(
+    ANM_12, const_in_script_not_visible_elsewhere,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+#pragma image_source "tests/integration/resources/th12-embedded-image-source.anm"
+
+entry {
+    path: "lmao.png",
+    has_data: false,
+    sprites: {
+        sprite0: {x: 0.0, y: 0.0, w: 4.0, h: 4.0, id: 10},
+    },
+}
+
+script script0 {
+    const int B = 20;
+    ins_3(B);
+}
+
+script script1 {
+    ins_3(B);  //~ ERROR not found
+}
+    "#,
+);
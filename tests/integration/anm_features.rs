@@ -1,6 +1,6 @@
 #[allow(unused)]
 use crate::integration_impl::{expected, formats::*};
-use crate::integration_impl::TestFile;
+use crate::integration_impl::{Format, TestFile};
 
 // =============================================================================
 
@@ -79,6 +79,23 @@ source_test!(
     check_compiled: |_, _| {},
 );
 
+source_test!(
+    ANM_12, anti_scratch_from_mapfile,
+    mapfile: r#"!anmmap
+!ins_names
+1000 copyVarsMod
+!ins_signatures
+1000
+!ins_flags
+1000 disables_scratch
+"#,
+    main_body: r#"
+        F3 = (F0 + 1.0) * ((F0 + 2.0) * (F0 + 3.0));
+        //~^ ERROR scratch registers are disabled
+        copyVarsMod();
+    "#,
+);
+
 // =============================================================================
 
 source_test!(
@@ -112,6 +129,43 @@ source_test!(
 
 // =============================================================================
 
+source_test!(
+    ANM_12, label_in_other_script,
+    items: r#"
+script other {
+    ins_3(0);
++10:
+label:
+    ins_3(1);
+}
+    "#,
+    main_body: r#"
+    ins_3(offsetof(other.label));
+    ins_3(timeof(other.label));
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let main = anm.entries[0].scripts.last().unwrap().1;
+        assert_eq!(main.instrs[0].args_blob, blobify![12]);
+        assert_eq!(main.instrs[1].args_blob, blobify![10]);
+    },
+);
+
+source_test!(
+    ANM_12, label_in_other_script_missing,
+    items: r#"
+script other {
+    ins_3(0);
+}
+    "#,
+    main_body: r#"
+    ins_3(offsetof(other.label));  //~ ERROR undefined label
+    ins_3(timeof(nonexistent.label));  //~ ERROR no script named
+    "#,
+);
+
+// =============================================================================
+
 // NOTE: This isn't a source test because it reads the contents of --output-thecl-defs.
 #[test]
 fn thecl_defs() {
@@ -147,3 +201,615 @@ global howyado = 2;
     "#;
     assert_eq!(actual.trim(), expected.trim());
 }
+
+// NOTE: These aren't source tests because the input is a binary file that can't be produced by compiling.
+//       The fixture has three scripts, and the file is truncated right where the body of the last one begins.
+const TRUNCATED_SCRIPTS_ANM: &str = "tests/integration/resources/th12-truncated-scripts.anm";
+
+#[test]
+fn script_past_eof_strict() {
+    let format = &ANM_12;
+    let mapfile = TestFile::from_path("map/any.anmm");
+    let result = format.decompile(&TestFile::from_path(TRUNCATED_SCRIPTS_ANM), &[], &[mapfile]);
+
+    assert!(result.output.is_none());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("script 2 begins at offset 0xA8, past the end of the file"), "{}", stderr);
+    assert!(stderr.contains("--lenient"), "{}", stderr);
+}
+
+#[test]
+fn script_past_eof_lenient() {
+    let format = &ANM_12;
+    let mapfile = TestFile::from_path("map/any.anmm");
+    let result = format.decompile(&TestFile::from_path(TRUNCATED_SCRIPTS_ANM), &["--lenient".as_ref()], &[mapfile]);
+
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("decompiled as an empty script"), "{}", stderr);
+
+    let decompiled = result.output.unwrap().read_to_string();
+    assert!(decompiled.contains("sprite(sprite0);"), "{}", decompiled);
+    assert!(decompiled.contains("wait(10);"), "{}", decompiled);
+    assert!(decompiled.contains("script 2 script2 meta {corrupt: true} {\n}"), "{}", decompiled);
+
+    // the other scripts should still be usable
+    let recompiled = format.compile(&TestFile::from_content("decompiled", &decompiled), &[], &[]);
+    let anm = recompiled.output.unwrap().read_anm(format);
+    assert_eq!(anm.entries[0].scripts.len(), 3);
+}
+
+/// Compiles an entry with two scripts and points the second one's script table entry somewhere else.
+fn anm_with_second_script_at(format: &Format, offset_from_first: i64) -> TestFile {
+    let compiled = format.compile(&TestFile::from_content("input", r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 64,
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 40.0, h: 60.0}},
+}
+
+script script0 {
+    pos(1.0, 2.0, 3.0);
+}
+
+script script1 {
+    wait(10);
+}
+"#), &[], &[]);
+    let mut bytes = compiled.output.unwrap().read();
+
+    // 64 byte header, then one sprite offset, then the script table
+    let first_script_offset = u32::from_le_bytes(bytes[0x48..0x4c].try_into().unwrap());
+    let new_offset = (first_script_offset as i64 + offset_from_first) as u32;
+    bytes[0x50..0x54].copy_from_slice(&new_offset.to_le_bytes());
+    TestFile::from_content("corrupted.anm", bytes)
+}
+
+#[test]
+fn script_inside_header() {
+    let format = &ANM_12;
+    let mapfile = TestFile::from_path("map/any.anmm");
+    let corrupted = anm_with_second_script_at(format, -0x38);
+
+    let result = format.decompile(&corrupted, &[], std::slice::from_ref(&mapfile));
+    assert!(result.output.is_none());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("script 1 begins at offset 0x40, inside the entry header"), "{}", stderr);
+
+    let result = format.decompile(&corrupted, &["--lenient".as_ref()], std::slice::from_ref(&mapfile));
+    let decompiled = result.output.unwrap().read_to_string();
+    assert!(decompiled.contains("pos(1.0, 2.0, 3.0);"), "{}", decompiled);
+    assert!(decompiled.contains("script 1 script1 meta {corrupt: true} {\n}"), "{}", decompiled);
+}
+
+#[test]
+fn script_inside_another_script() {
+    let format = &ANM_12;
+    let mapfile = TestFile::from_path("map/any.anmm");
+    // in the middle of the arguments to `pos`
+    let corrupted = anm_with_second_script_at(format, 12);
+
+    let result = format.decompile(&corrupted, &[], std::slice::from_ref(&mapfile));
+    assert!(result.output.is_none());
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("inside script 0"), "{}", stderr);
+
+    let result = format.decompile(&corrupted, &["--lenient".as_ref()], std::slice::from_ref(&mapfile));
+    let stderr = String::from_utf8_lossy(&result.stderr);
+    assert!(stderr.contains("decompiled as an empty script"), "{}", stderr);
+    let decompiled = result.output.unwrap().read_to_string();
+    assert!(decompiled.contains("pos(1.0, 2.0, 3.0);"), "{}", decompiled);
+    assert!(decompiled.contains("script 1 script1 meta {corrupt: true} {\n}"), "{}", decompiled);
+
+    // the marker survives a trip through the compiler
+    let recompiled = format.compile(&TestFile::from_content("decompiled", &decompiled), &[], &[]);
+    assert_eq!(recompiled.output.unwrap().read_anm(format).entries[0].scripts.len(), 2);
+}
+
+source_test!(
+    ANM_12, script_meta_corrupt,
+    items: r#"
+script 10 corrupted meta { corrupt: true } {
+    wait(10);
+}
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts.len(), 2);
+    },
+);
+
+source_test!(
+    ANM_12, script_meta_unknown_field,
+    items: r#"
+script 10 corrupted meta { corrupt: true, bogus: 1 } {}  //~ ERROR bogus
+"#,
+);
+
+// =============================================================================
+
+source_test!(
+    ANM_16, sprite_outside_of_image,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {
+        okay: {x: 32.0, y: 0.0, w: 32.0, h: 32.0},
+        tooWide: {x: 48.0, y: 0.0, w: 32.0, h: 32.0},  //~ WARNING outside of the texture
+        negative: {x: 0.0, y: -1.0, w: 32.0, h: 32.0},  //~ WARNING outside of the texture
+        empty: {x: 0.0, y: 0.0, w: 0.0, h: 32.0},  //~ WARNING non-positive size
+    },
+}
+
+script script0 {}
+"#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_16, sprite_outside_of_rt_texture,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 64,
+    rt_height: 64,
+    sprites: {
+        okay: {x: 0.0, y: 0.0, w: 64.0, h: 64.0},
+        tooTall: {x: 0.0, y: 32.0, w: 64.0, h: 64.0},  //~ WARNING outside of the texture
+    },
+}
+
+script script0 {}
+"#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_12, header_field_overflow,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 70000,
+    rt_height: 64,
+    sprites: {},
+}
+"#,
+    expect_error: "value of 'rt_width' is too large",
+);
+
+source_test!(
+    ANM_12, texture_field_overflow,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: "dummy",
+    img_format: 3,
+    img_width: 70000,
+    img_height: 1,
+    rt_width: 64,
+    rt_height: 64,
+    sprites: {},
+}
+"#,
+    expect_error: "value of 'img_width' is too large",
+);
+
+source_test!(
+    ANM_16, entry_field_typo,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_widht: 64,  //~ ERROR did you mean `img_width`
+    sprites: {},
+}
+"#,
+);
+
+source_test!(
+    ANM_16, sprite_missing_fields,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    sprites: {
+        sprite0: {x: 0.0, y: 0.0, hh: 32.0},  //~ ERROR 'w', 'h'
+    },
+}
+"#,
+);
+
+source_test!(
+    ANM_16, sprite_bounds_deny,
+    compile_args: &["--sprite-bounds", "deny"],
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {
+        tooWide: {x: 48.0, y: 0.0, w: 32.0, h: 32.0},  //~ ERROR outside of the texture
+    },
+}
+
+script script0 {}
+"#,
+);
+
+source_test!(
+    ANM_16, sprite_bounds_allow,
+    compile_args: &["--sprite-bounds", "allow"],
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {
+        tooWide: {x: 48.0, y: 0.0, w: 32.0, h: 32.0},
+    },
+}
+
+script script0 {}
+"#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_12, warn_unreferenced,
+    compile_args: &["--warn-unreferenced"],
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {
+        used: {x: 0.0, y: 0.0, w: 32.0, h: 32.0},
+        unused: {x: 32.0, y: 0.0, w: 32.0, h: 32.0},  //~ WARNING not used by any instruction
+    },
+}
+
+entry {  //~ WARNING no sprites
+    path: "subdir/file2.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {},
+}
+
+script main {  //~ WARNING not used by any instruction
+    sprite(used);
+    ins_88(child);
+}
+
+script child {
+    sprite(-1);
+}
+"#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_12, unreferenced_without_flag,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {
+        unused: {x: 32.0, y: 0.0, w: 32.0, h: 32.0},
+    },
+}
+
+script main {}
+"#,
+    check_compiled: |_, _| {},
+);
+
+// =============================================================================
+
+source_test!(
+    ANM_12, merge_identical_scripts,
+    compile_args: &["--merge-identical-scripts"],
+    items: r#"
+script a { wait(2); }
+script b { wait(2); }  //~ INFO merged into identical script 'a'
+script c { wait(3); }
+script d { wait(2); }  //~ INFO merged into identical script 'a'
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let aliases = anm.entries[0].scripts.values().map(|script| script.alias_of.as_ref().map(|x| x.as_str().to_owned())).collect::<Vec<_>>();
+        assert_eq!(aliases[..4], [None, Some("script0".to_owned()), None, Some("script0".to_owned())]);
+        assert_eq!(anm.entries[0].scripts[1].instrs, anm.entries[0].scripts[0].instrs);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("script 1 script1 = script0;"), "{}", decompiled);
+        assert!(decompiled.contains("script 3 script3 = script0;"), "{}", decompiled);
+    },
+);
+
+source_test!(
+    ANM_12, identical_scripts_not_merged_by_default,
+    items: r#"
+script a { wait(2); }
+script b { wait(2); }
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert!(anm.entries[0].scripts.values().all(|script| script.alias_of.is_none()));
+    },
+);
+
+source_test!(
+    ANM_12, script_alias,
+    items: r#"
+script 10 early = a;  // may come before the script it names
+script a { wait(2); }
+script b = a;
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let scripts = anm.entries[0].scripts.values().collect::<Vec<_>>();
+        assert_eq!(scripts.iter().map(|script| script.id).collect::<Vec<_>>()[..3], [10, 11, 12]);
+        // the binary file doesn't say which one was the original, so the first in the table gets the body
+        assert_eq!(scripts[0].alias_of, None);
+        assert_eq!(scripts[1].alias_of.as_ref().unwrap(), "script0");
+        assert_eq!(scripts[2].alias_of.as_ref().unwrap(), "script0");
+        assert!(!scripts[0].instrs.is_empty());
+        assert_eq!(scripts[2].instrs, scripts[0].instrs);
+    },
+);
+
+source_test!(
+    ANM_12, decompile_duplicate_shared_scripts,
+    items: r#"
+script a { wait(2); }
+script b = a;
+"#,
+    decompile_args: &["--duplicate-shared-scripts"],
+    require_roundtrip: false,  // the copy gets its own body
+    check_decompiled: |decompiled| {
+        assert!(!decompiled.contains(" = script0;"), "{}", decompiled);
+        assert_eq!(decompiled.matches("ins_75(2);").count(), 2, "{}", decompiled);
+    },
+);
+
+source_test!(
+    ANM_12, script_alias_other_entry,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {},
+}
+
+script a {}
+
+entry {
+    path: "subdir/file2.png",
+    has_data: false,
+    img_width: 64,
+    img_height: 32,
+    sprites: {},
+}
+
+script b = a;  //~ ERROR no script named 'a' in this entry
+"#,
+);
+
+source_test!(
+    ANM_12, script_alias_of_alias,
+    items: r#"
+script a {}
+script b = a;
+script c = b;  //~ ERROR alias of another alias
+"#,
+);
+
+// =============================================================================
+
+// Header fields of unknown purpose must survive a round trip. (source_test checks that recompiling the
+// decompiled output produces the same bytes)
+source_test!(
+    ANM_12, unknown_header_fields_new,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 16,
+    rt_height: 16,
+    unknown_0x08: 3,
+    unknown_0x2c: 0x12345,
+    unknown_0x3c: -1,
+    sprites: {},
+}
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].specs.unknown_header_fields.get(&0x08), Some(&3));
+        assert_eq!(anm.entries[0].specs.unknown_header_fields.get(&0x2c), Some(&0x12345));
+        assert_eq!(anm.entries[0].specs.unknown_header_fields.get(&0x3c), Some(&0xffffffff));
+
+        let bytes = output.read();
+        assert_eq!(&bytes[0x08..0x0a], &[3, 0]);
+        assert_eq!(&bytes[0x2c..0x30], &[0x45, 0x23, 0x01, 0x00]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("unknown_0x08: 3"));
+    },
+);
+
+// Fields that are only readable in hex should decompile as hex.
+source_test!(
+    ANM_06, hex_header_fields,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 16,
+    rt_height: 16,
+    rt_format: 9,
+    colorkey: 4278255360,
+    sprites: {},
+}
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].specs.rt_format, 9);
+        assert_eq!(anm.entries[0].specs.colorkey, 0xff00ff00);
+    },
+    check_decompiled: |decompiled| {
+        assert_snapshot!(decompiled);
+    },
+);
+
+source_test!(
+    ANM_06, unknown_header_fields_old,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 16,
+    rt_height: 16,
+    unknown_0x20: 7,
+    unknown_0x36: 0x100,
+    sprites: {},
+}
+"#,
+    check_compiled: |output, _format| {
+        let bytes = output.read();
+        assert_eq!(&bytes[0x20..0x24], &[7, 0, 0, 0]);
+        assert_eq!(&bytes[0x36..0x38], &[0, 1]);
+    },
+);
+
+source_test!(
+    ANM_12, unknown_header_fields_errors,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 16,
+    rt_height: 16,
+    unknown_0x20: 7,  //~ ERROR does not exist
+    unknown_0x08: 0x10000,  //~ ERROR does not fit
+    sprites: {},
+}
+"#,
+);
+
+// =============================================================================
+// Scripts that hang the game
+
+source_test!(
+    ANM_10, busy_loop,
+    compile_args: &["--warn-busy-loops"],
+    items: r#"
+script busy {  //~ WARNING never waits
+    I0 = 0;
+label:
+    I0 += 1;
+    goto label;
+}
+    "#,
+    main_body: "",
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ANM_10, busy_loop_not_for_waiting_loops,
+    compile_args: &["--warn-busy-loops"],
+    items: r#"
+script exits {
+    loop {
+        if (I0 > 3) {
+            break;
+        }
+        I0 += 1;
+    }
+}
+    "#,
+    main_body: r#"
+    loop {
+        I0 += 1;
+    +1:
+    }
+    "#,
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ANM_10, busy_loop_without_flag,
+    items: r#"
+script busy {
+label:
+    I0 += 1;
+    goto label;
+}
+    "#,
+    main_body: "",
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ANM_06, missing_end_of_script,
+    items: r#"
+script noEnd {  //~ WARNING never ends
+    ins_1(1);
+}
+    "#,
+    main_body: "ins_0();",
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
@@ -69,10 +69,14 @@ b2b_test!(STD_08, "map/any.stdm", std08_instance_order, "th08-instance-order.std
 b2b_test!(STD_08, "map/any.stdm", std08_nonzero_padding, "th08-nonzero-padding.std");
 b2b_test!(STD_08, "map/any.stdm", std08_empty_script, "th08-empty-script.std");
 b2b_test!(STD_06, "map/any.stdm", std06_general, "th06-general.std");
+b2b_test!(STD_07, "map/any.stdm", std07_general, "th07-general.std", expected="interrupt[1]:", expected="goto label_");
+b2b_test!(STD_09, "map/any.stdm", std09_general, "th09-general.std", expected="strip {", expected="loop {");
 b2b_test!(STD_12, "map/any.stdm", std12_general, "th12-general.std");
+b2b_test!(STD_19, "map/any.stdm", std19_general, "th19-general.std");
 
 // ANM metadata
 b2b_test!(ANM_12, "map/any.anmm", anm12_weird_color_format, "th12-weird-color-format.anm");
+b2b_test!(ANM_19, "map/any.anmm", anm19_general, "th19-general.anm");
 
 // Test that named regs use are named in the output.
 b2b_test!(
@@ -119,3 +123,6 @@ b2b_test!(MSG_11, "map/any.msgm", msg11_furibug_not_applicable, "th11-furibug-no
 b2b_test!(MSG_12, "map/any.msgm", msg12_furibug, "th12-furibug.msg");
 // Example with particular string length that caused a bug in TH17 Extra.
 b2b_test!(MSG_17, "map/any.msgm", msg17_furibug_ex_regression, "th17-furibug-ex-regression.msg");
+
+// ECL metadata
+b2b_test!(ECL_12, "tests/integration/resources/th12-include-lists.eclm", ecl12_include_lists, "th12-include-lists.ecl", expected="anim: [\"enemy.anm\", \"エフェクト.anm\"]");
@@ -56,7 +56,7 @@ source_test!(
     label:
         pos(0.0, 4984.0, 20.0);
     +1024:
-        goto label @ 10;
+        goto label @ 10;  //~ WARNING goto time
     -1:
         posKeyframe(0.0, 0.0, 0.0);
     "#,
@@ -78,7 +78,7 @@ source_test!(
     label:
         pos(0.0, 4984.0, 20.0);
     +1024:
-        goto label @ 200;
+        goto label @ 200;  //~ WARNING goto time
     -1:
         posKeyframe(0.0, 0.0, 0.0);
     "#,
@@ -93,7 +93,7 @@ source_test!(
     label:
         up(0.0, 1.0, -1.0);
         goto label;
-        up(0.0, 1.0, -1.0);
+        up(0.0, 1.0, -1.0);  //~ WARNING unreachable
         goto label;
     "#,
     check_decompiled: |decompiled| {
@@ -110,7 +110,7 @@ source_test!(
         posKeyframe(0.0, 0.0, 0.0);
     label:
         goto label;
-        posKeyframe(0.0, 0.0, 0.0);
+        posKeyframe(0.0, 0.0, 0.0);  //~ WARNING unreachable
     "#,
     check_decompiled: |decompiled| {
         assert!(decompiled.contains("loop {"));
@@ -124,8 +124,8 @@ source_test!(
         posKeyframe(0.0, 0.0, 0.0);
     +5:
     label:
-        goto label @ 10;
-        posKeyframe(0.0, 0.0, 0.0);
+        goto label @ 10;  //~ WARNING goto time
+        posKeyframe(0.0, 0.0, 0.0);  //~ WARNING unreachable
     "#,
     check_decompiled: |decompiled| {
         // This should decompile to something like 'loop { +5: }'
@@ -178,7 +178,7 @@ source_test!(
         up(0.0, 1.0, -1.0);
     +10: // 10
         goto label_100;
-        up(0.0, 1.0, -1.0);
+        up(0.0, 1.0, -1.0);  //~ WARNING unreachable
     +1024: // 1034
     label_100:
         pos(0.0, 5240.0, 20.0);
@@ -350,7 +350,7 @@ source_test!(
         $I0 = RAND % 3;
         if (I0 != 0) goto not0;
         goto not1;
-        goto end;
+        goto end;  //~ WARNING unreachable
     not0:
         if (I0 != 1) goto not1;
         sprite(3);
@@ -371,7 +371,7 @@ source_test!(
         I0 = I0 + 1;
         if (--I0) goto not0;
         goto not1;
-        goto end;
+        goto end;  //~ WARNING unreachable
     not0:
         if (--I0) goto not1;
         sprite(3);
@@ -395,7 +395,7 @@ source_test!(
         sprite(2);
         goto end;
     not0:
-        if (I0 != 1) goto not1 @ 10;
+        if (I0 != 1) goto not1 @ 10;  //~ WARNING goto time
         sprite(3);
         goto end;
     not1:
@@ -419,7 +419,7 @@ source_test!(
     not0:
         if (I0 != 1) goto not1;
         sprite(3);
-        goto end @ 10;
+        goto end @ 10;  //~ WARNING goto time
     not1:
         sprite(1);
     end:
@@ -793,6 +793,114 @@ source_test!(
 
 // =============================================================================
 
+source_test!(
+    ECL_06, and_jump_over_jump,
+    main_body: r#"
+        ins_0();
+        if ($I0 == 1 && $I1 < 2) goto end;
+        ins_0();
+    end:
+        ins_0();
+    "#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("&&"));
+        assert_eq!(decompiled.matches("goto").count(), 1);
+    },
+);
+
+source_test!(
+    ECL_06, and_jump_over_jump_in_loop,
+    main_body: r#"
+        do {
+            ins_0();
+        } while ($I0 == 1 && $I1 < 2);
+    "#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("do {"));
+        assert!(decompiled.contains("&&"));
+        assert!(!decompiled.contains("goto"));
+    },
+);
+
+source_test!(
+    ECL_06, and_jump_over_jump_skip_label_used,
+    main_body: r#"
+        if ($I0 == 1) goto skip;
+        if ($I1 == 2) goto skip;
+        goto end;
+    skip:
+        ins_0();
+        goto skip;
+    end:
+        ins_0();
+    "#,
+    check_decompiled: |decompiled| {
+        // the skip label is also used by the loop, so it must remain
+        assert!(!decompiled.contains("&&"));
+    },
+);
+
+source_test!(
+    // only '!=' exists, so 'unless' has to jump over a jump
+    ANM_06, unless_without_negated_intrinsic,
+    mapfile: r#"!anmmap
+!ins_signatures
+50 SSot
+!ins_intrinsics
+50 CondJmp(op="!="; type="int")
+"#,
+    main_body: r#"
+        sprite(0);
+        unless (0 != 0) goto end;
+        sprite(1);
+    end:
+        sprite(2);
+        ins_0();
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        // sprite, cond jump, jump, sprite, sprite, end
+        assert_eq!(anm.entries[0].scripts[0].instrs.len(), 6);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("unless (0 != 0) goto"));
+    },
+);
+
+source_test!(
+    ECL_06, unless_predecrement_jump_over_jump,
+    main_body: r#"
+        ins_0();
+        unless (--$I0 > 0) goto end;
+        ins_0();
+    end:
+        ins_0();
+    "#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("unless (--"));
+        assert_eq!(decompiled.matches("goto").count(), 1);
+    },
+);
+
+source_test!(
+    ECL_06, unless_predecrement_skip_label_used,
+    main_body: r#"
+        if (--$I0 > 0) goto skip;
+        goto end;
+    skip:
+        ins_0();
+        goto skip;
+    end:
+        ins_0();
+    "#,
+    check_decompiled: |decompiled| {
+        // the skip label is also used by the loop, so it must remain
+        assert!(!decompiled.contains("unless"));
+    },
+);
+
+// =============================================================================
+
 source_test!(
     ECL_06, label_created_by_offsetof_timeof,
     main_body: r#"
@@ -817,7 +925,7 @@ source_test!(
     +20:
     label0:
         nop();
-        goto label0 @ 30;
+        goto label0 @ 30;  //~ WARNING goto time
     "#,
     decompile_args: &["--no-intrinsics"],
     check_decompiled: |decompiled| {
@@ -15,11 +15,60 @@ source_test!(
         let ecl = output.read_ecl(format);
         assert_eq!(ecl.subs[0][0].difficulty, 0xFF);
         assert_eq!(ecl.subs[0][1].difficulty, 0b111);
-        assert_eq!(ecl.subs[0][2].difficulty, 0b1100);
+        assert_eq!(ecl.subs[0][2].difficulty, 0b100);  // intersection of ENH and HL
         assert_eq!(ecl.subs[0][3].difficulty, 0b111);
     },
 );
 
+source_test!(
+    ECL_06, diff_label_block_applies_to_nested_blocks,
+    main_body: r#"
+    {"ENH"}: {
+        {
+            nop();
+            {"EL"}: nop();
+        }
+    }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].difficulty, 0b111);
+        assert_eq!(ecl.subs[0][1].difficulty, 0b001);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_nesting_never_executes,
+    main_body: r#"
+    {"EN"}: {
+        nop();
+        {"HL"}: nop();  //~ WARNING never execute
+    }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][1].difficulty, 0);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_block_decompile,
+    main_body: r#"
+    {"EN"}: I0 = 1;
+    {"EN"}: I1 = 2;
+    {"EN"}: I2 = 3;
+    {"HL"}: I3 = 4;
++10:
+    {"HL"}: I3 = 5;
+"#,
+    check_decompiled: |decompiled| {
+        // (no mapfile is used for decompilation, so the labels use flag numbers)
+        assert_eq!(decompiled.matches(r#"{"01"}"#).count(), 1);
+        // a run of one statement is left alone, and time labels split runs
+        assert_eq!(decompiled.matches(r#"{"23"}"#).count(), 2);
+    },
+);
+
 source_test!(
     ECL_06, diff_label_with_time_label_bad_1,
     main_body: r#"
@@ -38,7 +87,7 @@ source_test!(
     ECL_06, diff_label_with_time_label_bad_2,
     main_body: r#"
     {"ENH"}: if (I0 == 0) {  //~ WARNING surprising
-        {"L"}: nop();
+        {"L"}: nop();  //~ WARNING never execute
     }
 "#,
 );
@@ -50,6 +99,177 @@ source_test!(
 "#,
 );
 
+// Difficulty labels on every kind of statement.
+//
+// Statements that compile to a single instruction apply the mask to that instruction;
+// structured statements apply it to everything they expand into.
+source_test!(
+    ECL_06, diff_label_on_goto,
+    main_body: r#"
+    {"EN"}: goto label;
+label:
+    nop();
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].difficulty, 0b11);
+        assert_eq!(ecl.subs[0][1].difficulty, 0xFF);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_cond_goto,
+    main_body: r#"
+    {"EN"}: if (I0 == 1) goto label;
+label:
+    nop();
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.subs[0][..ecl.subs[0].len() - 1].iter().all(|instr| instr.difficulty == 0b11));
+        assert_eq!(ecl.subs[0].last().unwrap().difficulty, 0xFF);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_break,
+    main_body: r#"
+    loop {
+        {"EN"}: break;
+        {"EN"}: if (I0 == 1) break;
+    }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        let (loop_jump, body) = ecl.subs[0].split_last().unwrap();
+        assert!(body.iter().all(|instr| instr.difficulty == 0b11));
+        assert_eq!(loop_jump.difficulty, 0xFF);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_return,
+    main_body: r#"
+    {"EN"}: return;  //~ ERROR not supported
+"#,
+);
+
+source_test!(
+    ECL_06, diff_label_on_cond_chain,
+    main_body: r#"
+    {"EN"}: if (I0 == 1) {  //~ WARNING surprising
+        nop();
+    } else if (I0 == 2) {
+        I1 = 3;
+    } else {
+        nop();
+    }
+    nop();
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        let (last, rest) = ecl.subs[0].split_last().unwrap();
+        assert!(rest.iter().all(|instr| instr.difficulty == 0b11));
+        assert_eq!(last.difficulty, 0xFF);
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_loop,
+    main_body: r#"
+    {"EN"}: loop {  //~ WARNING surprising
+        nop();
+    }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.subs[0].iter().all(|instr| instr.difficulty == 0b11));
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_while,
+    main_body: r#"
+    {"EN"}: while (I0 == 1) {  //~ WARNING surprising
+        nop();
+    }
+    {"EN"}: do {  //~ WARNING surprising
+        nop();
+    } while (I0 == 1);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.subs[0].iter().all(|instr| instr.difficulty == 0b11));
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_times,
+    main_body: r#"
+    {"EN"}: times(I0) {  //~ WARNING surprising
+        nop();
+    }
+    {"EN"}: times(inline 2) {  //~ WARNING surprising
+        nop();
+    }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.subs[0].iter().all(|instr| instr.difficulty == 0b11));
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_simple_stmts,
+    items: r#"
+    void foo(int x) {}
+    "#,
+    main_body: r#"
+    {"EN"}: nop();
+    {"EN"}: I0 = 2;
+    {"EN"}: I0 += I1 * 3;
+    {"EN"}: int x = 3;
+    {"EN"}: @foo(2);
+    {"EN"}: foo(2);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.subs[0].iter().all(|instr| instr.difficulty == 0b11));
+    },
+);
+
+source_test!(
+    ECL_06, diff_label_on_interrupt_label,
+    main_body: r#"
+    {"EN"}: interrupt[2]:  //~ ERROR not supported
+"#,
+);
+
+source_test!(
+    ECL_06, diff_label_on_label,
+    main_body: r#"
+    {"EN"}: label:  //~ ERROR not supported on label
+    nop();
+"#,
+);
+
+source_test!(
+    ECL_06, diff_label_on_time_label,
+    main_body: r#"
+    {"EN"}: 30:  //~ ERROR not supported on time label
+    {"EN"}: +30:  //~ ERROR not supported on time label
+    nop();
+"#,
+);
+
+source_test!(
+    ECL_06, diff_label_on_item,
+    main_body: r#"
+    {"EN"}: const int x = 3;  //~ ERROR not supported on const definition
+    nop();
+"#,
+);
+
 source_test!(
     ECL_06, eosd_diff_switch_compile,
     main_body: r#"
@@ -115,31 +335,77 @@ label:
 
 source_test!(
     ECL_06, diff_switch_in_const_fn_call,
-    // Eventually const funcs will be supported, at which point this should hopefully
-    // get caught by another error
+    // a diff switch is not const, but the statement gets copied for each difficulty
     items: r#"
-const int foo(int a) {  //~ ERROR not supported
+const int foo(int a) {
     return 2 * a;
 }
 
 void bar() {
-    I0 = foo(2:3:4:5);  //~ ERROR not supported
+    I0 = foo(2:3:4:5);
 }
 "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0].len(), 4);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![-10001, 4]);
+        assert_eq!(ecl.subs[0][3].args_blob, blobify![-10001, 10]);
+    },
 );
 
 source_test!(
     ECL_06, diff_switch_in_inline_fn_call,
-    // Eventually inline funcs will be supported.
-    // I'm not sure what this should do in that case...
+    // the argument is substituted for the parameter, so this behaves just like a diff switch in the caller
     items: r#"
-inline void foo(int a) {  //~ ERROR not supported
+inline void foo(int a) {
     I1 = 2 * a;
 }
 
 void bar() {
-    foo(2:3:4:5);  //~ ERROR not supported
+    foo(2:3:4:5);
 }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0].len(), 4);
+        assert!(ecl.subs[0].iter().all(|instr| instr.difficulty != 0xFF));
+    },
+);
+
+source_test!(
+    ECL_06, diff_switch_in_subexpression,
+    main_body: r#"
+    ins_6(3 + (1:2:3:4), (5:6::) * 2);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0].len(), 4);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![4, 10]);
+        assert_eq!(ecl.subs[0][1].args_blob, blobify![5, 12]);
+        assert_eq!(ecl.subs[0][3].args_blob, blobify![7, 12]);
+        assert_eq!(ecl.subs[0][3].difficulty, 0b1000);
+    },
+);
+
+source_test!(
+    ECL_06, diff_switch_in_subexpression_with_label,
+    main_body: r#"
+    {"HL"}: ins_6(3 + (1:2:3:4), (5:6::) * 2);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0].len(), 2);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![6, 12]);
+        assert_eq!(ecl.subs[0][0].difficulty, 0b100);
+        assert_eq!(ecl.subs[0][1].args_blob, blobify![7, 12]);
+        assert_eq!(ecl.subs[0][1].difficulty, 0b1000);
+    },
+);
+
+source_test!(
+    ECL_06, diff_switch_in_subexpression_empty_intersection,
+    main_body: r#"
+    {0x80}: ins_6(3 + (1:2:3:4), 0);  //~ ERROR no case
 "#,
 );
 
@@ -147,8 +413,8 @@ source_test!(
     ECL_06, diff_switch_significance_of_num_cases,
     main_body: r#"
     I0 = (2:3:4:5);
-    I1 = (2:3:4);
-    I2 = (2:3);
+    I1 = (2:3:4:);
+    I2 = (2:3::);
 "#,
     check_compiled: |output, format| {
         let ecl = output.read_ecl(format);
@@ -163,6 +429,36 @@ source_test!(
 "#,
 );
 
+source_test!(
+    ECL_06, diff_switch_too_few_cases,
+    main_body: r#"
+    I0 = (2:3:4);  //~ ERROR wrong number of cases
+"#,
+);
+
+source_test!(
+    ECL_06, diff_switch_more_cases_than_game_difficulties,
+    main_body: r#"
+    I0 = (2:3:4:5:6);  //~ ERROR wrong number of cases
+"#,
+);
+
+source_test!(
+    ECL_06, diff_switch_decomp_extra_flag_after_lunatic,
+    main_body: r#"
+    {"0"}: I0 = 2;
+    {"1"}: I0 = 3;
+    {"2"}: I0 = 4;
+    {"3"}: I0 = 5;
+    {"4"}: I0 = 6;
+"#,
+    check_decompiled: |decompiled| {
+        // the fifth instruction is not part of the switch
+        assert!(decompiled.contains("2 : 3 : 4 : 5;"));
+        assert!(decompiled.contains("6;"));
+    },
+);
+
 source_test!(
     ECL_06, diff_switch_mismatched_number_of_cases,
     main_body: r#"
@@ -175,7 +471,7 @@ source_test!(
     main_body: r#"
     {"ENHL"}: {
         I0 = (2:3:4:5);
-        I1 = (2:3:4);
+        I1 = (2:3:4:);
     }
 "#,
     check_compiled: |output, format| {
@@ -340,7 +636,7 @@ label1:
 label2:
     {"HL"}: I0 = I1 + 3;
     goto label1;
-    goto label2;
+    goto label2;  //~ WARNING unreachable
 "#,
     check_decompiled: |_decompiled| {
         // just roundtrip
@@ -477,3 +773,236 @@ source_test!(
     int x = (1:2:3:4);  //~ ERROR not supported
 "#,
 );
+
+const ECL_FIVE_DIFFICULTIES_MAPFILE: &'static str = r#"!eclmap
+!difficulty_flags
+0 E-
+1 N-
+2 H-
+3 L-
+4 X-
+5 F+
+6 U+
+7 7+
+"#;
+
+source_test!(
+    ECL_06_NO_DEFAULT_MAP, diff_switch_five_difficulties,
+    mapfile: ECL_FIVE_DIFFICULTIES_MAPFILE,
+    main_body: r#"
+    ins_4($REG[-10001], (1 : 2 : 3 : 4 : 5));
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+
+        assert_eq!(ecl.subs[0].len(), 5);
+        assert_eq!(ecl.subs[0][3].difficulty, 0b1110_1000);
+        assert_eq!(ecl.subs[0][4].difficulty, 0b1111_0000);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("= 1 : 2 : 3 : 4 : 5;"));
+    },
+);
+
+source_test!(
+    ECL_06_NO_DEFAULT_MAP, diff_switch_more_cases_than_difficulties,
+    mapfile: ECL_IN_DIFFICULTY_MAPFILE,
+    main_body: r#"
+    ins_4($REG[-10001], (1 : 2 : 3 : 4 : 5));  //~ ERROR too many
+"#,
+);
+
+const ECL_FOUR_NAMED_DIFFICULTIES_MAPFILE: &'static str = r#"!eclmap
+!difficulty_flags
+0 E-
+1 N-
+2 H-
+3 L-
+"#;
+
+source_test!(
+    ECL_06_NO_DEFAULT_MAP, numeric_diff_label,
+    mapfile: ECL_FOUR_NAMED_DIFFICULTIES_MAPFILE,
+    main_body: r#"
+    {0x83}: ins_4($REG[-10001], 1);
+    {"EN"}: ins_4($REG[-10001], 2);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].difficulty, 0x83);
+        assert_eq!(ecl.subs[0][1].difficulty, 0x03);
+    },
+    check_decompiled: |decompiled| {
+        // flag 7 has no name in the mapfile
+        assert!(decompiled.contains("{0x83}:"));
+        assert!(decompiled.contains(r#"{"EN"}:"#));
+    },
+);
+
+source_test!(
+    ECL_06, numeric_diff_label_without_mapfile_names,
+    main_body: r#"
+    {0x83}: nop();
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].difficulty, 0x83);
+    },
+);
+
+source_test!(
+    ECL_06, numeric_diff_label_out_of_range,
+    main_body: r#"
+    {0x100}: nop();  //~ ERROR out of range
+"#,
+);
+
+source_test!(
+    ECL_06_NO_DEFAULT_MAP, const_diff_label,
+    mapfile: ECL_FOUR_NAMED_DIFFICULTIES_MAPFILE,
+    items: r#"
+const string MIDBOSS_DIFFS = "HL";
+const int EASY_MASK = 0x81;
+"#,
+    main_body: r#"
+    {MIDBOSS_DIFFS}: ins_4($REG[-10001], 1);
+    {EASY_MASK}: ins_4($REG[-10001], 2);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].difficulty, 0x0C);
+        assert_eq!(ecl.subs[0][1].difficulty, 0x81);
+    },
+);
+
+source_test!(
+    ECL_06, const_diff_label_float,
+    items: r#"
+const float DIFFS = 1.0;
+"#,
+    main_body: r#"
+    {DIFFS}: nop();  //~ ERROR type error
+"#,
+);
+
+source_test!(
+    ECL_06, const_diff_label_not_const,
+    main_body: r#"
+    {$I0}: nop();  //~ ERROR compile-time constant
+"#,
+);
+
+source_test!(
+    ECL_06, const_diff_label_bad_flag,
+    items: r#"
+const string DIFFS = "EQ";
+"#,
+    main_body: r#"
+    {DIFFS}: nop();  //~ ERROR unknown difficulty flag
+"#,
+);
+
+const STD_DIFFICULTY_MASK_MAPFILE: &'static str = r#"!stdmap
+!ins_signatures
+400 S
+401 S
+!ins_intrinsics
+400 DifficultyMask()
+!difficulty_flags
+0 E-
+1 N-
+2 H-
+3 L-
+"#;
+
+source_test!(
+    STD_12, difficulty_mask_intrinsic_regions,
+    mapfile: STD_DIFFICULTY_MASK_MAPFILE,
+    main_body: r#"
+    ins_401(1);
+    {"EN"}: ins_401(2);
+    {"EN"}: ins_401(3);
+    {"HL"}: ins_401(4);
++10:
+    ins_401((5:6::));
+label:
+    {"L"}: ins_401(7);
+    goto label;
+"#,
+    check_compiled: |output, format| {
+        let std = output.read_std(format);
+        let instrs = std.script.iter().map(|instr| (instr.opcode, instr.time, instr.args_blob.clone())).collect::<Vec<_>>();
+        assert_eq!(&instrs[..11], &[
+            (401, 0, blobify![1]),
+            (400, 0, blobify![0b0011]),
+            (401, 0, blobify![2]),
+            (401, 0, blobify![3]),
+            (400, 0, blobify![0b1100]),
+            (401, 0, blobify![4]),
+            (400, 10, blobify![0b0001]),
+            (401, 10, blobify![5]),
+            (400, 10, blobify![0b1110]),
+            (401, 10, blobify![6]),
+            (400, 10, blobify![0xFF]),  // restore before label
+        ][..]);
+        assert_eq!(&instrs[11..14], &[
+            (400, 10, blobify![0b1000]),
+            (401, 10, blobify![7]),
+            (400, 10, blobify![0xFF]),
+        ][..]);
+        assert_eq!(instrs[14].0, 1);  // jump
+        assert_eq!(instrs.len(), 15);
+    },
+    check_decompiled: |decompiled| {
+        assert!(!decompiled.contains("ins_400"));
+        assert!(decompiled.contains(r#"{"EN"}:"#));
+        assert!(decompiled.contains(r#"{"L"}:"#));
+    },
+);
+
+source_test!(
+    STD_12, difficulty_mask_intrinsic_irreproducible,
+    mapfile: STD_DIFFICULTY_MASK_MAPFILE,
+    main_body: r#"
+    ins_400(3);
+    ins_401(2);
+    ins_400(3);  // redundant, so this can't be written as labels
+    ins_401(3);
+    ins_400(255);
+"#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("ins_400(3);"));
+        assert!(!decompiled.contains(r#"{"EN"}:"#));
+    },
+);
+
+source_test!(
+    STD_12, difficulty_mask_intrinsic_jump,
+    mapfile: STD_DIFFICULTY_MASK_MAPFILE,
+    main_body: r#"
+label:
+    {"EN"}: goto label;  //~ ERROR jump inside difficulty label
+"#,
+);
+
+source_test!(
+    STD_12, difficulty_without_mask_intrinsic,
+    main_body: r#"
+    {"EN"}: ins_0();  //~ ERROR not supported
+"#,
+);
+
+source_test!(
+    ECL_TIMELINE_08, const_in_timeline_diff_switch,
+    main_body: r#"
+        const int EASY = 10;
+        const int REST = EASY * 2;
+        ins_2(1, 0.0, 0.0, 0.0, (EASY:REST:REST:REST), 0, 0);
+    "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        let spawns = ecl.timelines[0].iter().filter(|instr| instr.opcode == 2).collect::<Vec<_>>();
+        let values = spawns.iter().map(|instr| instr.args_blob[16..20].to_vec()).collect::<Vec<_>>();
+        assert_eq!(values, vec![blobify![10], blobify![20], blobify![20], blobify![20]]);
+    },
+);
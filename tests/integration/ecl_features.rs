@@ -17,6 +17,22 @@ void externFunc();  //~ ERROR unsupported extern function
 "#,
 );
 
+source_test!(
+    ECL_06, eosd_word_arg_is_never_reg,
+    mapfile: r#"!eclmap
+!ins_signatures
+900 ssS
+"#,
+    main_body: r#"
+    ins_900(-10001, 0, -10001);
+    "#,
+    check_decompiled: |decompiled| {
+        // the dword looks like a register, but the word is never one
+        assert!(decompiled.contains("ins_900(-10001, 0, "));
+        assert!(!decompiled.contains("ins_900(-10001, 0, -10001)"));
+    },
+);
+
 source_test!(
     ECL_06, decompile_eosd_cmp_jmp_success,
     main_body: r#"
@@ -329,6 +345,50 @@ source_test!(
     check_compiled: |_, _| {},
 );
 
+source_test!(
+    ECL_06, eosd_at_call,
+    items: EOSD_CALL_TEST_FUNCS,
+    main_body: r#"
+    @i_f(30, 1.0);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs.last().unwrap().1[0].args_blob, blobify![0, 30, 1.0]);
+    },
+);
+
+source_test!(
+    ECL_06, eosd_async_call_unsupported,
+    items: EOSD_CALL_TEST_FUNCS,
+    main_body: r#"
+    i(50) async;  //~ ERROR not supported
+"#,
+);
+
+source_test!(
+    ECL_07, pcb_async_call_id_unsupported,
+    items: EOSD_CALL_TEST_FUNCS,
+    main_body: r#"
+    @i(50) async 2;  //~ ERROR not supported
+"#,
+);
+
+source_test!(
+    ECL_06, eosd_async_call_id_type_error,
+    items: EOSD_CALL_TEST_FUNCS,
+    main_body: r#"
+    i(50) async 2.0;  //~ ERROR type error
+"#,
+);
+
+source_test!(
+    ECL_06, eosd_async_call_arg_type_error,
+    items: EOSD_CALL_TEST_FUNCS,
+    main_body: r#"
+    i(2.0) async;  //~ ERROR type error
+"#,
+);
+
 // -------------
 // param use in function bodies
 
@@ -643,6 +703,107 @@ pcb_funky_call_decomp_rt_test!(pcb_param_decomp_call_funky_mismatch, r#"
 "#);
 
 
+source_test!(
+    ECL_12, modern_include_lists_roundtrip,
+    items: r#"
+meta {
+    anim: ["enemy.anm", "エフェクト.anm"],
+    ecli: ["sub.ecl"],
+}
+"#,
+    main_body: r#"
+    ins_10(@nargs=2, @blob="01000000 02000000");
+    "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.anim_list.iter().map(|s| &s.value[..]).collect::<Vec<_>>(), ["enemy.anm", "エフェクト.anm"]);
+        assert_eq!(ecl.ecli_list.iter().map(|s| &s.value[..]).collect::<Vec<_>>(), ["sub.ecl"]);
+        assert_eq!(ecl.subs[0][0].arg_count, 2);
+    },
+    expect_decompile_warning: "byte blobs",
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains(r#"anim: ["enemy.anm", "エフェクト.anm"]"#));
+        assert!(decompiled.contains(r#"ecli: ["sub.ecl"]"#));
+        assert!(decompiled.contains("@nargs=2"));
+    },
+);
+
+source_test!(
+    ECL_12, modern_include_lists_missing_meta,
+    main_body: "",
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert!(ecl.anim_list.is_empty());
+        assert!(ecl.ecli_list.is_empty());
+    },
+);
+
+source_test!(
+    ECL_12, modern_include_lists_duplicate,
+    items: r#"
+meta {
+    anim: ["enemy.anm", "effect.anm", "enemy.anm"],  //~ WARNING more than once
+    ecli: ["enemy.anm"],
+}
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_include_lists_not_shift_jis,
+    items: r#"
+meta {
+    anim: ["enemy😃.anm"],  //~ ERROR Shift_JIS
+}
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_include_lists_nul,
+    items: r#"
+meta {
+    ecli: ["sub\0.ecl"],  //~ ERROR NUL
+}
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_include_lists_bad_type,
+    items: r#"
+meta {
+    anim: "enemy.anm",  //~ ERROR expected
+}
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_multiple_metas,
+    items: r#"
+meta { anim: ["enemy.anm"] }
+meta { ecli: ["sub.ecl"] }  //~ ERROR multiple times
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_timeline,
+    items: r#"
+timeline 0 {}  //~ ERROR not supported
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_12, modern_sub_params,
+    items: r#"
+void foo(int x) {}  //~ ERROR parameters
+"#,
+    main_body: "",
+);
+
 // =============================================================================
 
 #[test]
@@ -222,7 +222,7 @@ source_test!(
     main_body: r#"
         ins_400(FooEnum.Name);
         ins_400(2 + FooEnum.Name * 2);
-        ins_999(@mask=FooEnum.Name, @blob="01000000");
+        ins_999(@mask=FooEnum.Name, @blob="01000000 02000000 03000000 04000000 05000000");
     "#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -369,7 +369,7 @@ source_test!(
         }
     "#,
     main_body: r#"
-        const y = ConstFn(Red);
+        const y = ConstFn(Red);  //~ ERROR token
         ins_400(y);
     "#,
 );
@@ -383,8 +383,9 @@ source_test!(
     main_body: r#"
         ins_10(true);
         ins_10(false);
-        ins_10(2);
+        ins_10(2);  //~ WARNING non-boolean
     "#,
+    expect_decompile_warning: "non-boolean value",
     check_compiled: |output, format| {
         let std = output.read_std(format);
         assert_eq!(std.script[0].args_blob, blobify![1]);
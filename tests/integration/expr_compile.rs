@@ -73,3 +73,131 @@ source_test!(
     float x = -%F0;  //~ ERROR not supported
 "#,
 );
+
+const INS_RETS_MAPFILE: &str = r#"!anmmap
+!ins_names
+1000 rand_int
+1001 rand_float
+1002 stack_int
+!ins_signatures
+1000 S
+1001 f
+1002
+!ins_rets
+1000 $REG[10000]
+1001 %REG[10004]
+1002 $
+"#;
+
+source_test!(
+    ANM_10, ins_ret_assign,
+    mapfile: INS_RETS_MAPFILE,
+    main_body: r#"
+    $I1 = rand_int(5);
+    %F1 = rand_float(2.0) * 3.0;
+    $I0 = rand_int(6);
+    rand_int(7);
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let instrs = &anm.entries[0].scripts[0].instrs;
+
+        // call, then copy the output register
+        assert_eq!(instrs[0].opcode, 1000);
+        assert_eq!(instrs[0].args_blob, blobify![5]);
+        assert_eq!(instrs[1].args_blob, blobify![10001, 10000]);
+        // the result can be used in a larger expression
+        assert_eq!(instrs[2].opcode, 1001);
+        assert_eq!(instrs[3].args_blob, blobify![10005.0, 10004.0, 3.0]);
+        // no copy needed
+        assert_eq!(instrs[4].opcode, 1000);
+        assert_eq!(instrs[4].args_blob, blobify![6]);
+        // value is discarded
+        assert_eq!(instrs[5].opcode, 1000);
+        assert_eq!(instrs[5].args_blob, blobify![7]);
+    },
+);
+
+// The lowerer puts the result of an instruction call in a temporary before using it, and instruction
+// arguments that need computing go through a temporary too.  The peephole pass removes these copies.
+source_test!(
+    ANM_10, peephole_forwards_ins_ret_into_arg,
+    mapfile: INS_RETS_MAPFILE,
+    main_body: r#"
+    sprite(rand_int(5));
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let instrs = &anm.entries[0].scripts[0].instrs;
+
+        // no copy of the output register before it is used
+        assert_eq!(instrs.len(), 2);
+        assert_eq!(instrs[0].opcode, 1000);
+        assert_eq!(instrs[1].opcode, 3);
+        assert_eq!(instrs[1].args_blob, blobify![10000]);
+        assert_eq!(instrs[1].param_mask, 0b1);
+    },
+);
+
+source_test!(
+    ANM_10, peephole_merges_ins_ret_into_binop,
+    mapfile: INS_RETS_MAPFILE,
+    main_body: r#"
+    $I1 = rand_int(5) + $I2;
+"#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let instrs = &anm.entries[0].scripts[0].instrs;
+
+        // a single 3-operand add, rather than a copy followed by '+='
+        assert_eq!(instrs.len(), 2);
+        assert_eq!(instrs[0].opcode, 1000);
+        assert_eq!(instrs[1].args_blob, blobify![10001, 10000, 10002]);
+    },
+);
+
+source_test!(
+    ECL_06, peephole_retargets_temporary,
+    main_body: r#"
+    ins_4($REG[-10001], 3 + $REG[-10003]);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+
+        // the sum is written straight to the destination of 'ins_4' instead of a temporary
+        assert_eq!(ecl.subs[0].len(), 1);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![-10001, 3, -10003]);
+    },
+);
+
+source_test!(
+    ANM_10, ins_ret_type_error,
+    mapfile: INS_RETS_MAPFILE,
+    main_body: r#"
+    %F1 = rand_int(5);  //~ ERROR type error
+"#,
+);
+
+source_test!(
+    ANM_10, ins_ret_on_stack_in_stackless_lang,
+    mapfile: INS_RETS_MAPFILE,
+    main_body: r#"
+    $I1 = stack_int();  //~ ERROR on the stack
+"#,
+);
+
+source_test!(
+    ANM_10, ins_ret_invalid,
+    mapfile: r#"!anmmap
+!ins_signatures
+1000 S
+!ins_rets
+1000 $REG[abc]  //~ WARNING invalid return value
+"#,
+    main_body: r#"
+    ins_1000(5);
+"#,
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
@@ -179,6 +179,93 @@ source_test!(
     "#,
 );
 
+source_test!(
+    ECL_06, const_func_call,
+    items: r#"
+        const int fib(int n) {
+            if (n < 2) {
+                return n;
+            }
+            return fib(n - 1) + fib(n - 2);
+        }
+
+        const float half(float x) {
+            float y = x;
+            y /= 2.0;
+            return y;
+        }
+    "#,
+    main_body: r#"
+        // fib(40) would take forever without caching
+        const int konst = fib(40);
+        ins_1(konst);
+        ins_1(fib(10));
+        ins_1(int(half(3.0) * 4.0));
+    "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![102334155]);
+        assert_eq!(ecl.subs[0][1].args_blob, blobify![55]);
+        assert_eq!(ecl.subs[0][2].args_blob, blobify![6]);
+    },
+);
+
+source_test!(
+    ECL_06, const_func_ternary_recursion,
+    items: r#"
+        const int fact(int n) { return n <= 1 ? 1 : n * fact(n - 1); }
+    "#,
+    main_body: r#"
+        ins_1(fact(5));
+    "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].args_blob, blobify![120]);
+    },
+);
+
+source_test!(
+    ECL_06, const_func_recursion_limit,
+    items: r#"
+        const int forever(int n) { return forever(n + 1); }  //~ ERROR recursion limit
+    "#,
+    main_body: r#"
+        const int konst = forever(0);
+    "#,
+);
+
+source_test!(
+    ECL_06, const_func_no_return,
+    items: r#"
+        const int nothing(int n) { n = 2; }  //~ WARNING without a return
+    "#,
+    main_body: r#"
+        const int konst = nothing(0);  //~ ERROR did not produce a value
+    "#,
+);
+
+source_test!(
+    ECL_06, const_func_non_const_stmt,
+    items: r#"
+        const int looping(int n) {
+            loop { return n; }  //~ ERROR not supported
+        }
+    "#,
+    main_body: r#"
+        const int konst = looping(0);
+    "#,
+);
+
+source_test!(
+    ECL_06, const_func_non_const_arg,
+    items: r#"
+        const int double(int n) { return 2 * n; }
+    "#,
+    main_body: r#"
+        ins_1(double($REG[-10001]));  //~ ERROR not supported
+    "#,
+);
+
 source_test!(
     ECL_08, const_sigil,
     main_body: r#"
@@ -222,6 +309,94 @@ source_test!(
     "#,
 );
 
+source_test!(
+    STD_12, func_inline_stmt,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 SS
+    "#,
+    items: r#"
+        inline void foo(int a, int b) {
+            ins_400(a, b + 1);
+        }
+    "#,
+    main_body: r#"
+        foo(1, 2);
+        foo(3, 4);
+    "#,
+    check_compiled: |output, format| {
+        // STD has no variables, so this only works because the arguments are substituted
+        let std = output.read_std(format);
+        assert_eq!(std.script[0].args_blob, blobify![1, 3]);
+        assert_eq!(std.script[1].args_blob, blobify![3, 5]);
+    },
+);
+
+source_test!(
+    ECL_06, func_inline_value,
+    items: r#"
+        inline int sign(int x) {
+            if (x < 0) {
+                return -1;
+            }
+            return 1;
+        }
+    "#,
+    main_body: r#"
+        I0 = sign(I1) + sign(-3);
+    "#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ECL_06, func_inline_nested,
+    items: r#"
+        inline int double(int x) { return 2 * x; }
+        inline int quadruple(int x) { return double(double(x)); }
+    "#,
+    main_body: r#"
+        I0 = quadruple(3);
+    "#,
+    check_compiled: |output, format| {
+        // one for the inner call (which gets folded to 6), and one that writes the result of the outer call to I0
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0].len(), 2);
+    },
+);
+
+source_test!(
+    ANM_10, func_inline_label,
+    items: r#"
+        inline void foo() {
+        label:
+            if (I0 > 0) { goto label; }
+        }
+    "#,
+    main_body: r#"
+        foo();
+        foo();
+    "#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ECL_06, func_inline_recursive,
+    items: r#"
+        inline void foo() { bar(); }
+        inline void bar() { foo(); }  //~ ERROR recursive
+    "#,
+);
+
+source_test!(
+    ECL_06, func_inline_in_loop_cond,
+    items: r#"
+        inline int foo() { return 1; }
+    "#,
+    main_body: r#"
+        while (foo() > 0) {}  //~ ERROR cannot inline
+    "#,
+);
+
 source_test!(
     ANM_10, func_const_reg,
     items: r#"
@@ -269,6 +444,33 @@ source_test!(
     "#,
 );
 
+source_test!(
+    ANM_10, syntax_error_recovery,
+    main_body: r#"
+        I0 = 1 +;  //~ ERROR unexpected token `;`
+        ins_1(1 2);  //~ ERROR unexpected token `2`
+        int = 3;  //~ ERROR unexpected token `=`
+        I1 = 4;
+    "#,
+);
+
+source_test!(
+    ANM_10, syntax_error_recovery_items,
+    items: r#"
+        scrpt foo {  //~ ERROR unexpected token `scrpt`
+            I0 = 1;
+        }
+
+        void bar(int x {  //~ ERROR unexpected token `{`
+            I0 = 2;
+        }
+
+        void baz() {
+            I0 = 3 3;  //~ ERROR unexpected token `3`
+        }
+    "#,
+);
+
 source_test!(
     ANM_10, builtin_consts_decomp,
     main_body: r#"
@@ -364,6 +566,88 @@ source_test!(
     },
 );
 
+const INTERRUPT_ENUM_MAPFILE: &'static str = r#"!anmmap
+!enum(name="Interrupt")
+3 CARD_END
+7 BOSS_DEAD
+"#;
+
+source_test!(
+    ANM_12, interrupt_label_const_expr,
+    mapfile: INTERRUPT_ENUM_MAPFILE,
+    items: r#"
+        const int BASE = 5;
+    "#,
+    main_body: r#"
+        interrupt[Interrupt.CARD_END]:
+        nop();
+        interrupt[BASE * 2 + 1]:
+        nop();
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let labels = anm.entries[0].scripts[0].instrs.iter().filter(|instr| instr.opcode == 64);
+        let ids = labels.map(|instr| instr.args_blob.clone()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![blobify![3], blobify![11]]);
+    },
+);
+
+source_test!(
+    ANM_12, interrupt_label_non_const,
+    main_body: r#"
+        interrupt[$I0]:  //~ ERROR compile-time constant
+    "#,
+);
+
+source_test!(
+    ANM_12, interrupt_label_non_int,
+    main_body: r#"
+        interrupt[1.0]:  //~ ERROR type error
+    "#,
+);
+
+source_test!(
+    ANM_12, decompile_interrupt_enum,
+    mapfile: INTERRUPT_ENUM_MAPFILE,
+    main_body: r#"
+        interrupt[3]:
+        nop();
+        interrupt[4]:
+        nop();
+    "#,
+    decompile_args: &["--interrupt-enum", "Interrupt"],
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("interrupt[CARD_END]:"), "{:?}", decompiled);
+        // no name for this one
+        assert!(decompiled.contains("interrupt[4]:"), "{:?}", decompiled);
+    },
+);
+
+source_test!(
+    ANM_12, decompile_interrupt_enum_default_off,
+    mapfile: INTERRUPT_ENUM_MAPFILE,
+    main_body: r#"
+        interrupt[3]:
+        nop();
+    "#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("interrupt[3]:"), "{:?}", decompiled);
+    },
+);
+
+source_test!(
+    ANM_12, decompile_interrupt_enum_missing,
+    main_body: r#"
+        interrupt[3]:
+        nop();
+    "#,
+    decompile_args: &["--interrupt-enum", "Interrupt"],
+    expect_decompile_warning: "no enum named",
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("interrupt[3]:"), "{:?}", decompiled);
+    },
+);
+
 source_test!(
     STD_08, arg_count_range,
     main_body: r#"
@@ -381,10 +665,62 @@ source_test!(
 source_test!(
     MSG_06, reg_in_unsupported_lang,
     main_body: r#"
-        textSet(0, $REG[0], "cheese");  //~ ERROR constant
+        textPause($REG[0]);  //~ ERROR constant
+    "#,
+);
+
+source_test!(
+    ANM_12, word_args_roundtrip,
+    mapfile: r#"!anmmap
+!ins_signatures
+777 su
+"#,
+    main_body: r#"
+        ins_777(-32768, 65535);
+        ins_777(32767, 0);
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[0].args_blob, vec![0x00, 0x80, 0xff, 0xff]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("ins_777(-32768, 65535)"));
+        assert!(decompiled.contains("ins_777(32767, 0)"));
+    },
+);
+
+source_test!(
+    ANM_12, word_args_out_of_range,
+    mapfile: r#"!anmmap
+!ins_signatures
+777 su
+"#,
+    main_body: r#"
+        ins_777(32768, 0);  //~ ERROR out of range
+        ins_777(0, -1);  //~ ERROR out of range
+    "#,
+);
+
+source_test!(
+    ANM_12, word_args_not_reg,
+    mapfile: r#"!anmmap
+!ins_signatures
+777 ss
+"#,
+    main_body: r#"
+        ins_777($REG[10000], 0);  //~ ERROR constant
     "#,
 );
 
+source_test!(
+    ANM_12, word_args_misaligned,
+    mapfile: r#"!anmmap
+!ins_signatures
+777 sS  //~ ERROR aligned
+"#,
+    main_body: "",
+);
+
 source_test!(
     ANM_12, decompile_missing_signature,
     compile_mapfile: r#"!anmmap
@@ -395,6 +731,77 @@ source_test!(
     expect_decompile_warning: expected::DECOMP_UNKNOWN_SIG,
 );
 
+source_test!(
+    ANM_12, decompile_pragma_for_used_mapfile,
+    decompile_mapfile: r#"!anmmap
+!ins_names
+0 useThisInstr
+"#,
+    main_body: r#"  ins_0();  "#,
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("useThisInstr"));
+        assert!(decompiled.contains("#pragma mapfile"));
+        assert!(decompiled.contains("Xx_mapfile-1_xX"));
+    },
+);
+
+source_test!(
+    ANM_12, decompile_no_pragma_for_unused_mapfile,
+    decompile_mapfile: r#"!anmmap
+!ins_names
+777 neverUsed
+!gvar_names
+10000 ALSO_NEVER_USED
+"#,
+    main_body: r#"  ins_0();  "#,
+    check_decompiled: |decompiled| {
+        assert!(!decompiled.contains("#pragma mapfile"));
+    },
+);
+
+source_test!(
+    ANM_06, instr_args_too_large,
+    // EoSD ANM stores the size of the arguments in a single byte
+    main_body: format!(r#"
+    ins_2(@blob="{}");  //~ ERROR too large
+    //~^ WARNING does not match
+    "#, "00".repeat(256)),
+);
+
+source_test!(
+    ANM_12, nesting_limit_parens,
+    // the error should be at the first bracket past the limit (counting the script's braces)
+    main_body: format!(r#"
+    I0 = {}(  //~ ERROR nesting limit
+    {}1{};
+    "#, "(\n".repeat(255), "(\n".repeat(100_000), ")".repeat(100_256)),
+);
+
+source_test!(
+    ANM_12, nesting_limit_operators,
+    // no brackets, but still a very deep expression
+    main_body: format!(r#"
+    I0 = {}I1 +  //~ ERROR nesting limit
+    {}1;
+    "#, "I1 +\n".repeat(100_000), "I1 +\n".repeat(254)),
+);
+
+source_test!(
+    ANM_12, nesting_limit_blocks,
+    main_body: format!(r#"
+    {}{{  //~ ERROR nesting limit
+    {}}}
+    "#, "{\n".repeat(255), "}".repeat(255)),
+);
+
+source_test!(
+    ANM_12, nesting_below_limit,
+    main_body: format!(r#"
+    I0 = {}1{};
+    "#, "(I1 + ".repeat(200), ")".repeat(200)),
+    check_compiled: |_, _| {},
+);
+
 // TODO: STD script requirements (single sub called main...)
 
 // A snippet to try decompiling with several decreasing levels of features.
@@ -445,6 +852,27 @@ source_test!(
     },
 );
 
+source_test!(
+    ANM_12, decompile_renumber_labels,
+    main_body: SNIPPET_WITH_SEVERAL_INTRINSICS,
+    decompile_args: &["--no-blocks", "--renumber-labels"],
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("label_0:"));
+        assert!(decompiled.contains("goto label_0"));
+    },
+);
+
+source_test!(
+    ANM_12, decompile_max_columns,
+    main_body: SNIPPET_WITH_SEVERAL_INTRINSICS,
+    decompile_args: &["--no-intrinsics", "--max-columns", "24"],
+    check_decompiled: |decompiled| {
+        // the call from 'decompile_no_intrinsics' no longer fits on one line
+        assert!(decompiled.contains("ins_18("));
+        assert!(!decompiled.contains("ins_18($REG[10000], $REG[10002], 3)"));
+    },
+);
+
 source_test!(
     ANM_10, bad_instr_alias_expr_ordering,
     mapfile: r#"!anmmap
@@ -467,14 +895,349 @@ source_test!(
 source_test!(
     ANM_10, jump_to_end_of_script_at_different_time,
     main_body: r#"
-        goto label @ 100;
+        goto label @ 100;  //~ WARNING goto time
     label:
     "#,
     check_decompiled: |_| { /* just roundtrip */ },
 );
 
+source_test!(
+    ANM_10, goto_time_mismatch,
+    main_body: r#"
+    60:
+    label:
+        ins_0();
+        goto label @ 30;  //~ WARNING goto time
+    "#,
+);
+
+source_test!(
+    ANM_10, goto_time_match,
+    main_body: r#"
+    60:
+    label:
+        ins_0();
+    +10:
+        if (I0 == 0) goto label @ 60;
+        if (I0 == 1) goto label;
+        goto label @ 60;
+    "#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, goto_time_mismatch_conditional,
+    main_body: r#"
+    label:
+        ins_0();
+    +10:
+        if (I0 == 0) goto label @ 10;  //~ WARNING goto time
+    "#,
+);
+
+source_test!(
+    ANM_10, goto_time_mismatch_suppressed,
+    main_body: r#"
+    60:
+    label:
+        ins_0();
+        goto label @ 30;
+    "#,
+    compile_args: &["--no-goto-time-warnings"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, time_decrease,
+    main_body: r#"
+    10:
+        ins_0();
+    5:
+        ins_0();  //~ WARNING earlier time
+    "#,
+);
+
+source_test!(
+    ANM_10, time_decrease_at_jump_target,
+    main_body: r#"
+    10:
+        ins_0();
+    5:
+    label:
+        ins_0();
+    +10:
+        if (I0 == 0) goto label;
+    "#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, time_decrease_suppressed,
+    main_body: r#"
+    10:
+        ins_0();
+    5:
+        ins_0();
+    "#,
+    compile_args: &["--no-time-decrease-warnings"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, unreachable_after_goto,
+    main_body: r#"
+    label:
+        ins_0();
+        goto label;
+        I0 = 1;  //~ WARNING unreachable
+        I0 = 2;
+    label2:
+        I0 = 3;
+        goto label2;
+    +10:
+        I0 = 4;
+    "#,
+);
+
+source_test!(
+    ANM_10, unreachable_in_nested_blocks,
+    main_body: r#"
+        loop {
+            break;
+            I0 = 1;  //~ WARNING unreachable
+        }
+        if (I0 == 0) {
+            goto end;
+        } else {
+            I0 = 5;
+            goto end;
+        }
+        I0 = 2;  //~ WARNING unreachable
+    end:
+        loop {
+            I0 = 3;
+        }
+        I0 = 4;  //~ WARNING unreachable
+    "#,
+);
+
+source_test!(
+    ECL_06, reachable_after_conditional_divergence,
+    main_body: r#"
+        if (I0 == 0) {
+            goto end;
+        }
+        {"E"}: goto end;
+        loop {
+            if (I0 == 1) { break; }
+        }
+        I0 = 2;
+        {
+            goto end;
+        inside:
+            I0 = 3;
+        }
+    end:
+    "#,
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, unroll_times,
+    items: r#"
+        const int N = 2;
+    "#,
+    main_body: r#"
+        times(inline N + 1) {
+            I0 += 1;
+        }
+        times(I2 = inline 2) {
+            I1 += 1;
+        }
+        times(inline 0) {
+            I3 += 1;
+        }
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        let instrs = &anm.entries[0].scripts[0].instrs;
+        // no counter, no jumps, and the clobber is never written
+        assert_eq!(instrs.len(), 5);
+        assert!(instrs[..3].iter().all(|instr| instr.args_blob == instrs[0].args_blob));
+        assert!(instrs[3..].iter().all(|instr| instr.args_blob == instrs[3].args_blob));
+        assert_ne!(instrs[0].args_blob, instrs[3].args_blob);
+    },
+);
+
+source_test!(
+    ANM_10, unroll_times_break,
+    main_body: r#"
+        times(inline 3) {
+            if (I0 == 5) {
+                break;
+            }
+            times(2) {
+                if (I1 == 2) {
+                    break;
+                }
+                int x = 4;
+                I1 += x;
+            }
+        }
+        I2 = 3;
+    "#,
+    // each copy gets its own loop IDs and labels for the inner loop
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, unroll_times_non_const,
+    main_body: r#"
+        times(inline I0) {  //~ ERROR non-constant
+            I1 += 1;
+        }
+    "#,
+);
+
+source_test!(
+    ANM_10, unroll_times_too_many,
+    main_body: r#"
+        times(inline 100) {  //~ ERROR too many
+            I1 += 1;
+        }
+    "#,
+);
+
+source_test!(
+    ANM_10, unroll_times_raised_limit,
+    main_body: r#"
+        times(inline 100) {
+            I1 += 1;
+        }
+    "#,
+    compile_args: &["--max-unroll", "100"],
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs.len(), 100);
+    },
+);
+
+source_test!(
+    ANM_10, unroll_times_label,
+    main_body: r#"
+        times(inline 2) {
+        label:  //~ ERROR containing a label
+            I1 += 1;
+            if (I1 == 3) goto label;
+        }
+    "#,
+);
+
+source_test!(
+    ANM_10, register_report,
+    main_body: r#"
+        float x = 1.0;
+        {
+            float y = 2.0;  //~ INFO 1 of 6 integers, 2 of 4 floats
+        }
+        float z = 3.0;
+        int i = 3;
+    "#,
+    compile_args: &["--register-report"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, register_report_explicit_use,
+    main_body: r#"
+        %REG[10006] = 1.0;
+        float x = 1.0;  //~ INFO unavailable for floats due to explicit use
+    "#,
+    compile_args: &["--register-report"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, warn_float_equality_all,
+    main_body: r#"
+        if (F0 == 1.0) {  //~ WARNING comparing floats
+            I0 = 1;
+        }
+        if (F1 != 0.0) {  //~ WARNING comparing floats
+            I0 = 2;
+        }
+        if (I1 == 1) {
+            I0 = 3;
+        }
+    "#,
+    compile_args: &["--warn-float-equality", "all"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, warn_float_equality_nonzero,
+    main_body: r#"
+        if (F0 == 1.0) {  //~ WARNING comparing floats
+            I0 = 1;
+        }
+        if (F1 != 0.0) {
+            I0 = 2;
+        }
+        if (F1 < 1.0) {
+            I0 = 3;
+        }
+    "#,
+    compile_args: &["--warn-float-equality", "nonzero"],
+    check_compiled: |_, _| {},
+);
+
+source_test!(
+    ANM_10, warn_float_equality_off_by_default,
+    main_body: r#"
+        if (F0 == 1.0) {
+            I0 = 1;
+        }
+    "#,
+    check_compiled: |_, _| {},
+);
+
 source_test!(
     ANM_12, decompile_negative_zero,
     main_body: r#"  ins_11(3.0, -0.0);  "#,
     check_decompiled: |decompiled| { assert!(decompiled.contains("-0.0")); },
 );
+
+source_test!(
+    ANM_10, offsetof_plain_arg,
+    // offsetof can be given to instructions that aren't jumps, and even used in expressions
+    main_body: r#"
+        ins_0();
+    label:
+        wait(offsetof(label));
+        I1 = offsetof(label) + 4;
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[1].args_blob, blobify![8]);
+        assert_eq!(anm.entries[0].scripts[0].instrs[2].args_blob, blobify![10001, 8, 4]);
+    },
+);
+
+source_test!(
+    ANM_10, offsetof_plain_arg_label_encoding,
+    // the label encoding is applied even though the instruction isn't a jump
+    mapfile: r#"!anmmap
+!label_encoding
+0 relative_byte_offset
+"#,
+    main_body: r#"
+        ins_0();
+    label:
+        ins_0();
+        wait(offsetof(label));
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[2].args_blob, blobify![-8]);
+    },
+);
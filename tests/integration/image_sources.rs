@@ -194,6 +194,35 @@ entry {
             assert_eq!(&data[..4], &data[4..8]); // all pixels are the same (unlike the original "hai" image)
         },
     );
+
+    // Decompiling with --dummy-images should produce something that compiles back without an image source.
+    source_test!(
+        ANM_12, decompile_dummy_images,
+        full_source: r#"
+#pragma mapfile "map/any.anmm"
+#pragma image_source "./tests/integration/resources/dir-with-images"
+
+entry {
+    path: "subdir/hai-10x18.png",
+    sprites: {},
+}
+
+entry {
+    path: "@R",
+    has_data: false,
+    rt_width: 16,
+    rt_height: 16,
+    sprites: {},
+}"#,
+        decompile_args: &["--dummy-images"],
+        require_roundtrip: false,  // the image is replaced with magenta
+        check_decompiled: |decompiled| {
+            assert_eq!(decompiled.matches(r#"has_data: "dummy""#).count(), 1);
+            assert!(decompiled.contains("has_data: false"));
+            assert!(decompiled.contains("img_width: 10"));
+            assert!(decompiled.contains("img_height: 18"));
+        },
+    );
 }
 
 // Tests with no image source.
@@ -430,7 +459,7 @@ entry {
     img_width: 8,  // set different dimensions that conflict with the original
     img_height: 16,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -451,7 +480,7 @@ entry {
     img_width: 8,  // set different dimensions that conflict with the original
     img_height: 16,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -481,7 +510,7 @@ mod conflicts {
 entry {
     path: "subdir/modified-size.png",
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -500,7 +529,7 @@ entry {
 entry {
     path: "subdir/modified-size.png",
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -519,7 +548,7 @@ entry {
 entry {
     path: "subdir/modified-size.png",
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -538,7 +567,7 @@ entry {
 entry {
     path: "subdir/modified-size.png",
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -560,7 +589,7 @@ entry {
 entry {
     path: "subdir/modified-size.png",
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -588,7 +617,7 @@ entry {
     path: "teeny.png",
     has_data: true,
     img_format: 8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }
         "#,
         check_compiled: |output, format| {
@@ -613,7 +642,7 @@ entry {
     path: "subdir/hai-10x18+105+9.png",
     has_data: true,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -632,7 +661,7 @@ entry {
     path: "teeny.png",
     has_data: true,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "from unknown color format",
     );
@@ -647,7 +676,7 @@ entry {
     path: "lmao.png",
     has_data: true,
     img_format: 8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "into unknown color format",
     );
@@ -661,7 +690,7 @@ entry {
     img_width: 27,
     img_height: 25,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -680,7 +709,7 @@ entry {
     img_width: 27,
     img_height: 25,
     img_format: 8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "unknown color format",
     );
@@ -696,7 +725,7 @@ entry {
 entry {
     path: "teeny.png",
     has_data: "dummy",
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "unknown color format",
     );
@@ -710,7 +739,7 @@ entry {
     path: "subdir/hai-10x18.png",
     has_data: true,
     img_format: FORMAT_GRAY_8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         check_compiled: |output, format| {
             let anm = output.read_anm(format);
@@ -728,7 +757,7 @@ entry {
     path: "subdir/hi-7x20.png",
     has_data: true,
     img_format: 8,
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "into unknown color format",
     );
@@ -745,7 +774,7 @@ entry {
 
 entry {
     path: "teeny.png",
-    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 10.0, h: 10.0}},
+    sprites: {sprite0: {id: 0, x: 0.0, y: 0.0, w: 4.0, h: 4.0}},
 }"#,
         expect_error: "into unknown color format",
     );
@@ -786,7 +815,7 @@ source_test!(
 entry {
     path: "subdir/hi-32x16.png",
     has_data: true,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -808,7 +837,7 @@ source_test!(
 entry {
     path: "subdir/hi-7x20.png",
     has_data: true,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -833,7 +862,7 @@ entry {
     offset_y: 9,
     has_data: true,
     img_format: FORMAT_ARGB_8888,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -929,7 +958,7 @@ source_test!(
 entry {
     path: "subdir/hi-32x16.png",
     has_data: false,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -949,7 +978,7 @@ source_test!(
 entry {
     path: "subdir/hi-7x20.png",
     has_data: false,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -972,7 +1001,7 @@ entry {
     offset_y: 9,
     has_data: false,
     img_format: FORMAT_ARGB_8888,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -996,7 +1025,7 @@ entry {
     rt_width: 128,
     rt_height: 256,
     rt_format: 3,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -1021,7 +1050,7 @@ entry {
     path: "subdir/hi-32x16.png",
     has_data: true,
     img_format: 3,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -1050,7 +1079,7 @@ entry {
     path: "subdir/hi-32x16.png",
     has_data: false,
     img_format: 3,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     check_compiled: |output, format| {
         let anm = output.read_anm(format);
@@ -1135,7 +1164,7 @@ entry {
     has_data: false,
     rt_width: 7,     //~ WARNING not a power of two
     rt_height: 21,   //~ WARNING not a power of two
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
 );
 
@@ -1148,7 +1177,7 @@ entry {
     path: "subdir/hi-32x16.png",
     rt_height: 16,
     rt_width: 16,
-    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 111.0, h: 111.0}},
+    sprites: {sprite0: {id: 0, x: 1.0, y: 1.0, w: 4.0, h: 4.0}},
 }"#,
     expect_warning: "too small for",
 );
@@ -48,9 +48,47 @@ source_test!(
     bloo(7);
     "#,
     check_decompiled: |decompiled| {
-        // prefer the most recent name
-        assert!(decompiled.contains("bloo"));
-        assert!(!decompiled.contains("blue"));
+        // prefer the first name
+        assert!(decompiled.contains("blue"));
+        assert!(!decompiled.contains("bloo"));
+    },
+);
+
+source_test!(
+    ANM_10, ins_name_synonyms_canonical,
+    mapfile: r#"!anmmap
+!ins_signatures
+99 S
+!ins_names
+99 oldName
+99 *newName
+99 otherName
+"#,
+    main_body: r#"
+    oldName(5);
+    otherName(7);
+    "#,
+    check_decompiled: |decompiled| {
+        assert_eq!(decompiled.matches("newName(").count(), 2);
+        assert!(!decompiled.contains("oldName"));
+        assert!(!decompiled.contains("otherName"));
+    },
+);
+
+source_test!(
+    ANM_10, ins_name_synonyms_multiple_marks,
+    mapfile: r#"!anmmap
+!ins_signatures
+99 S
+!ins_names
+99 *blue
+99 *bloo  //~ WARNING multiple names
+"#,
+    main_body: r#"
+    bloo(5);
+    "#,
+    check_compiled: |_, _| {
+        // just need it to succeed...
     },
 );
 
@@ -315,3 +353,272 @@ source_test!(
 "#,
     check_compiled: |_, _| {}, // just expecting no warnings/errors
 );
+
+// The jump is the fourth instruction and the label is on the third.  Each instruction is 8 bytes.
+const LABEL_ENCODING_MAIN_BODY: &str = r#"
+    ins_0();
+    ins_0();
+label:
+    ins_0();
+    goto label @ 0;
+"#;
+
+source_test!(
+    ANM_10, label_encoding_default,
+    main_body: LABEL_ENCODING_MAIN_BODY,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[3].args_blob, blobify![16, 0]);
+    },
+);
+
+source_test!(
+    ANM_10, label_encoding_absolute_byte_offset,
+    mapfile: r#"!anmmap
+!label_encoding
+0 absolute_byte_offset
+"#,
+    main_body: LABEL_ENCODING_MAIN_BODY,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[3].args_blob, blobify![16, 0]);
+    },
+);
+
+source_test!(
+    ANM_10, label_encoding_instruction_index,
+    mapfile: r#"!anmmap
+!label_encoding
+0 instruction_index
+"#,
+    main_body: LABEL_ENCODING_MAIN_BODY,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[3].args_blob, blobify![2, 0]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("ins_0();\n    ins_0();\n    loop {\n        ins_0();\n    }"));
+    },
+);
+
+source_test!(
+    ANM_10, label_encoding_relative_byte_offset,
+    mapfile: r#"!anmmap
+!label_encoding
+0 relative_byte_offset
+"#,
+    main_body: LABEL_ENCODING_MAIN_BODY,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[3].args_blob, blobify![-8, 0]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("ins_0();\n    ins_0();\n    loop {\n        ins_0();\n    }"));
+    },
+);
+
+source_test!(
+    ANM_10, label_encoding_bad_name,
+    mapfile: r#"!anmmap
+!label_encoding
+0 relative  //~ ERROR invalid label encoding
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ANM_10, limit_anm_scripts,
+    mapfile: r#"!anmmap
+!limits
+1 anm_scripts
+"#,
+    items: r#"
+script aaa {}
+script bbb {}  //~ ERROR too many ANM scripts
+    "#,
+    main_body: "",
+);
+
+source_test!(
+    ANM_10, limit_anm_sprites,
+    mapfile: r#"!anmmap
+!limits
+1 anm_sprites
+"#,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 512,
+    img_height: 512,
+    sprites: {
+        sprite0: {id: 0, x: 0.0, y: 0.0, w: 512.0, h: 480.0},
+        sprite1: {id: 1, x: 0.0, y: 0.0, w: 512.0, h: 480.0},  //~ ERROR too many ANM sprites
+    },
+}
+
+script script0 {}
+"#,
+);
+
+source_test!(
+    ECL_06, limit_ecl_subs,
+    mapfile: r#"!eclmap
+!limits
+1 ecl_subs
+"#,
+    items: r#"
+void sub1() {}
+void sub2() {}  //~ ERROR too many ECL subs
+    "#,
+    main_body: "",
+);
+
+source_test!(
+    ANM_10, limit_unknown,
+    mapfile: r#"!anmmap
+!limits
+1 anm_sprtes  //~ WARNING unknown limit
+"#,
+    main_body: "",
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ANM_12, ins_flags_unknown,
+    mapfile: r#"!anmmap
+!ins_signatures
+1000
+!ins_flags
+1000 disables_scrach  //~ WARNING unknown flag
+"#,
+    main_body: r#"
+        F3 = (F0 + 1.0) * ((F0 + 2.0) * (F0 + 3.0));
+        ins_1000();
+    "#,
+    check_compiled: |_, _| {
+        // the unknown flag is ignored, so scratch registers can still be used
+    },
+);
+
+source_test!(
+    ANM_10, reg_pragma,
+    items: r#"
+#pragma reg anm $my_counter = 10005;
+#pragma reg anm %my_float = 10006;
+    "#,
+    main_body: r#"
+        my_counter = 3;
+        my_float = 2.0;
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[0].args_blob, blobify![10005, 3]);
+        assert_eq!(anm.entries[0].scripts[0].instrs[1].args_blob, blobify![10006.0f32, 2.0f32]);
+    },
+);
+
+source_test!(
+    ANM_10, reg_pragma_conflicts_with_mapfile,
+    mapfile: r#"!anmmap
+!gvar_names
+10004 my_counter
+"#,
+    items: r#"
+#pragma reg anm $my_counter = 10005;  //~ WARNING multiple registers
+    "#,
+    main_body: r#"
+        my_counter = 3;
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[0].args_blob, blobify![10005, 3]);
+    },
+);
+
+source_test!(
+    ANM_10, reg_pragma_bad_language,
+    items: r#"
+#pragma reg amn $my_counter = 10005;  //~ ERROR unknown language
+    "#,
+    main_body: "",
+);
+
+source_test!(
+    STD_12, abi_radix,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 S(radix="hex")S(radix="signed-hex")S(radix="bin")S(radix="bool")S(radix="bool")S(radix="dec")
+"#,
+    main_body: r#"
+        ins_400(-10, -10, 5, 1, 5, 0x20);
+    "#,
+    check_compiled: |output, format| {
+        let std = output.read_std(format);
+        assert_eq!(std.script[0].args_blob, blobify![-10, -10, 5, 1, 5, 32]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("(0xfffffff6, -0xa, 0b101, true, 5, 32)"));
+    },
+);
+
+source_test!(
+    STD_12, abi_radix_unknown,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 S(radix="oct")  //~ ERROR unknown radix
+"#,
+    main_body: "",
+);
+
+source_test!(
+    STD_12, abi_bool,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 bbS
+"#,
+    main_body: r#"
+        ins_400(true, 0, 1);
+    "#,
+    check_compiled: |output, format| {
+        let std = output.read_std(format);
+        assert_eq!(std.script[0].args_blob, blobify![1, 0, 1]);
+    },
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("(true, false, 1)"));
+    },
+);
+
+source_test!(
+    STD_12, abi_bool_non_boolean_const,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 b
+"#,
+    main_body: r#"
+        ins_400(2);  //~ WARNING non-boolean
+    "#,
+    check_compiled: |output, format| {
+        let std = output.read_std(format);
+        assert_eq!(std.script[0].args_blob, blobify![2]);
+    },
+);
+
+source_test!(
+    STD_12, abi_bool_decompile_non_boolean,
+    mapfile: r#"!stdmap
+!ins_signatures
+400 b
+"#,
+    main_body: r#"
+        ins_400(@blob="02000000");
+    "#,
+    expect_decompile_warning: "non-boolean value",
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("ins_400(2)"));
+    },
+);
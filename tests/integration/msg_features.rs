@@ -38,6 +38,31 @@ script script10 {}
     },
 );
 
+source_test!(
+    MSG_06, consts_in_meta,
+    full_source: r#"
+const int LEN = 5;
+const string MAIN = "script0";
+
+meta {
+    table_len: LEN * 3,
+    table: {
+        0: {script: MAIN},
+        5: {script: "script5"},
+    },
+}
+
+script script0 {}
+script script5 {}
+"#,
+    check_compiled: |output, format| {
+        let msg = output.read_msg(format);
+        assert_eq!(msg.dense_table.len(), 15);
+        assert_eq!(msg.dense_table[0].script.value.to_meta(), "script0".to_meta());
+        assert_eq!(msg.dense_table[5].script.value.to_meta(), "script1".to_meta());
+    },
+);
+
 source_test!(
     MSG_06, table_default,
     full_source: r#"
@@ -86,3 +86,135 @@ source_test!(
         wait(@mask=I0, @blob="10270000");  //~ ERROR const
     "#,
 );
+
+source_test!(
+    ANM_10, pseudo_const_expr,
+    mapfile: r#"!anmmap
+!enum(name="Mask")
+1 FirstArg
+"#,
+    items: r#"
+        const int THIRD = 0b100;
+    "#,
+    main_body: r#"
+        color(@mask=THIRD | 0, I2, 10, 20);
+        color(@mask=Mask.FirstArg * 4, I2, 10, 20);
+    "#,
+    check_compiled: |output, format| {
+        let anm = output.read_anm(format);
+        assert_eq!(anm.entries[0].scripts[0].instrs[0].param_mask, 0b100);
+        assert_eq!(anm.entries[0].scripts[0].instrs[1].param_mask, 0b100);
+    },
+);
+
+source_test!(
+    ANM_10, pseudo_non_const_expr,
+    main_body: r#"
+        I0 = 1;
+        wait(@mask=I0 + 1, @blob="10270000");  //~ ERROR const
+    "#,
+);
+
+source_test!(
+    ANM_10, pseudo_blob_space_in_byte,
+    main_body: r#"
+        wait(@blob="0f0 00000");  //~ ERROR middle of a byte
+    "#,
+);
+
+source_test!(
+    ANM_10, pseudo_blob_odd_digits,
+    main_body: r#"
+        wait(@blob="0f00000");  //~ ERROR odd number
+    "#,
+);
+
+source_test!(
+    ANM_10, pseudo_mask_beyond_blob,
+    main_body: r#"
+        ins_999(@mask=0b100, @blob="01000000 02000000");  //~ ERROR beyond the end
+    "#,
+);
+
+source_test!(
+    ECL_06, pseudo_mask_beyond_blob_eosd,
+    // the mask field in EoSD ECL does not describe the arguments, so anything goes
+    main_body: r#"
+        ins_999(@mask=0b11111111, @blob="01000000");
+    "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].param_mask, 0b11111111);
+    },
+    expect_decompile_warning: expected::DECOMP_UNKNOWN_SIG,
+);
+
+source_test!(
+    MSG_06, pseudo_blob_any_length_in_msg,
+    full_source: r#"
+meta {
+    table: {
+        0: {script: "script0"},
+    },
+}
+
+script script0 {
+    ins_99(@blob="010203");
+}
+"#,
+    check_compiled: |output, format| {
+        let msg = output.read_msg(format);
+        assert_eq!(msg.scripts[0][0].args_blob, vec![1, 2, 3]);
+    },
+    expect_decompile_warning: expected::DECOMP_UNKNOWN_SIG,
+);
+
+source_test!(
+    STD_06, pseudo_blob_wrong_size_in_early_std,
+    main_body: r#"
+        ins_99(@blob="01000000 02000000");  //~ ERROR exactly 12
+    "#,
+);
+
+source_test!(
+    ANM_10, pseudo_blob_size_disagrees_with_core_signature,
+    main_body: r#"
+        anchor(@blob="01000000 02000000");  //~ WARNING does not match
+    "#,
+    expect_decompile_warning: "leftover bytes",
+    require_roundtrip: false,  // the extra bytes are lost
+);
+
+source_test!(
+    ANM_10, pseudo_blob_size_disagrees_with_mapfile_signature,
+    mapfile: r#"!anmmap
+!ins_signatures
+999 Sf
+"#,
+    main_body: r#"
+        ins_999(@blob="01000000 0000803f 02000000");  //~ WARNING does not match
+    "#,
+    expect_decompile_warning: "leftover bytes",
+    require_roundtrip: false,  // the extra bytes are lost
+);
+
+source_test!(
+    ANM_10, pseudo_mask_only_immediates,
+    mapfile: r#"!anmmap
+!ins_signatures
+999 ssf
+"#,
+    main_body: r#"
+        ins_999(@mask=0b11, @blob="01000200 0000803f");  //~ WARNING only marks immediate
+    "#,
+);
+
+source_test!(
+    ANM_10, pseudo_blob_signature_warnings_disabled,
+    compile_args: &["--no-blob-signature-warnings"],
+    main_body: r#"
+        anchor(@blob="01000000 02000000");
+    "#,
+    expect_decompile_warning: "leftover bytes",
+    require_roundtrip: false,  // the extra bytes are lost
+);
@@ -44,6 +44,39 @@ script main {}
 "#,
 );
 
+source_test!(
+    STD_12, object_by_auto_name,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+meta {
+    unknown: 0,
+    anm_path: "stage01.anm",
+    objects: {
+        blurb: {
+            layer: 0,
+            pos: [-320.0, -128.0, -12.0],
+            size: [768.0, 384.0, 0.0],
+            quads: [],
+        },
+        blorb: {
+            layer: 1,
+            pos: [10.0, 20.0, 30.0],
+            size: [10.0, 20.0, 30.0],
+            quads: [],
+        },
+    },
+    instances: [
+        blurb {pos: [-192.0, 6600.0, 0.0]},
+        object1 {pos: [320.0, 4296.0, 0.0]},  //~ WARNING using the object at index 1
+        object2 {pos: [320.0, 4296.0, 0.0]},  //~ ERROR no object named
+    ],
+}
+
+script main {}
+"#,
+);
+
 source_test!(
     STD_12, strip_in_bad_game,
     full_source: r#"
@@ -102,6 +135,44 @@ script main {}
     check_compiled: |_, _| {},
 );
 
+source_test!(
+    STD_12, consts_in_meta,
+    mapfile: r#"!stdmap
+!enum(name="Layer")
+3 Background
+"#,
+    full_source: r#"
+#pragma mapfile "map/any.anmm"
+
+const float SIZE = 16.0;
+const string ANM_PATH = "stage01.anm";
+
+meta {
+    unknown: 2 * 3,
+    anm_path: ANM_PATH,
+    objects: {
+        thing: {
+            layer: Layer.Background,
+            pos: [-SIZE, 0.0, 0.0],
+            size: [SIZE * 2.0, SIZE, 0.0],
+            quads: [],
+        },
+    },
+    instances: [],
+}
+
+script main {}
+"#,
+    check_compiled: |output, format| {
+        let std = output.read_std(format);
+        assert_eq!(std.unknown, 6);
+        let object = std.objects.values().next().unwrap();
+        assert_eq!(object.layer, 3);
+        assert_eq!(object.pos, [-16.0, 0.0, 0.0]);
+        assert_eq!(object.size, [32.0, 16.0, 0.0]);
+    },
+);
+
 source_test!(
     STD_12, renamed_layer_missing,
     full_source: r#"
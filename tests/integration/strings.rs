@@ -104,10 +104,12 @@ const STRING_ABI_TEST_SIGNATURES: &'static str = r#"!eclmap
 444 block
 555 fixed
 666 nulless
+777 masked
 !ins_signatures
 444  z(bs=4)
 555  z(len=8)
 666  z(len=8;nulless)
+777  m(len=8;mask=0x77,0,0)
 "#;
 
 source_test!(
@@ -184,6 +186,74 @@ source_test!(
     check_decompiled: |text| assert!(text.contains("\"@@@@@@@@\"")),
 );
 
+// "こんにちは" in Shift-JIS and UTF-8.  In Shift-JIS, the null terminator makes it 11 bytes, so it gets
+// padded to 12.  In UTF-8 the null alone makes it 16.
+const HELLO_SHIFT_JIS: &[u8] = b"\x82\xB1\x82\xF1\x82\xC9\x82\xBF\x82\xCD\0\0";
+const HELLO_UTF_8: &[u8] = b"\xE3\x81\x93\xE3\x82\x93\xE3\x81\xAB\xE3\x81\xA1\xE3\x81\xAF\0";
+
+source_test!(
+    ECL_06, string_arg_shift_jis_by_default,
+    mapfile: STRING_ABI_TEST_SIGNATURES,
+    main_body: r#" block("こんにちは"); "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].args_blob, HELLO_SHIFT_JIS);
+    },
+    check_decompiled: |text| assert!(text.contains("\"こんにちは\"")),
+);
+
+source_test!(
+    ECL_06, string_arg_utf_8,
+    mapfile: r#"!eclmap
+!ins_names
+444 block
+!ins_signatures
+444  z(bs=4)
+!string_encoding
+0 utf-8
+"#,
+    main_body: r#" block("こんにちは"); "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].args_blob, HELLO_UTF_8);
+    },
+    check_decompiled: |text| assert!(text.contains("\"こんにちは\"")),
+);
+
+source_test!(
+    ECL_06, string_arg_utf_8_character_not_in_shift_jis,
+    mapfile: r#"!eclmap
+!ins_names
+444 block
+!ins_signatures
+444  z(bs=4)
+!string_encoding
+0 utf-8
+"#,
+    main_body: r#" block("⏄"); "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.subs[0][0].args_blob, b"\xE2\x8F\x84\0");
+    },
+);
+
+source_test!(
+    ECL_06, bad_string_encoding_name,
+    mapfile: r#"!eclmap
+!string_encoding
+0 utf-16  //~ ERROR invalid string encoding
+"#,
+    main_body: "",
+);
+
+source_test!(
+    ECL_06, decompile_string_arg_invalid_shift_jis,
+    mapfile: STRING_ABI_TEST_SIGNATURES,
+    main_body: r#" block(@blob="4082FF00"); "#,
+    expect_decompile_warning: "invalid bytes were replaced",
+    recompile: false,
+);
+
 source_test!(
     ECL_06, compile_string_arg_too_big_eq,
     mapfile: STRING_ABI_TEST_SIGNATURES,
@@ -219,3 +289,23 @@ source_test!(
     nulless("abcdefghi");  //~ ERROR too large
     "#,
 );
+
+source_test!(
+    ECL_06, compile_string_arg_fixed_masked,
+    mapfile: STRING_ABI_TEST_SIGNATURES,
+    main_body: r#" masked("abc"); "#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        let expected = b"abc\0\0\0\0\0".iter().map(|&b| b ^ 0x77).collect::<Vec<_>>();
+        assert_eq!(ecl.subs[0][0].args_blob, expected);
+    },
+    check_decompiled: |text| assert!(text.contains("masked(\"abc\")")),
+);
+
+source_test!(
+    ECL_06, compile_string_arg_fixed_masked_too_big,
+    mapfile: STRING_ABI_TEST_SIGNATURES,
+    main_body: r#"
+    masked("abcdefgh");  //~ ERROR too large
+    "#,
+);
@@ -17,6 +17,7 @@ source_test!(
 
 source_test!(
     ECL_06, general_2,
+    compile_args: &["--no-time-decrease-warnings"],
     main_body: r#"
     5: nop();
     3: nop();
@@ -33,6 +34,7 @@ source_test!(
 source_test!(
     ECL_06, after_neg,
     // negative label followed by 0 or positive.
+    compile_args: &["--no-time-decrease-warnings"],
     main_body: r#"
     -1: nop();
     0: nop();
@@ -50,6 +52,7 @@ source_test!(
 source_test!(
     ECL_06, neg_neg,
     // increasing or decreasing negative labels
+    compile_args: &["--no-time-decrease-warnings"],
     main_body: r#"
     -1: nop();
     -2: nop();
@@ -63,6 +66,7 @@ source_test!(
 source_test!(
     ECL_06, compression,
     // compression of identical time labels, regardless of sign
+    compile_args: &["--no-time-decrease-warnings"],
     main_body: r#"
     0: nop(); nop();
     6: nop(); nop();
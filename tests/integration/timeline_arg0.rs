@@ -106,6 +106,21 @@ source_test!(
     },
 );
 
+source_test!(
+    ECL_TIMELINE_06, const_expr_arg0,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    items: r#"
+const int SUB_ID = 4;
+"#,
+    main_body: r#"
+    hasUnusedArg0(@arg0=SUB_ID + 1, 3, 3);
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.timelines[0][0].extra_arg, Some(5));
+    },
+);
+
 source_test!(
     ECL_TIMELINE_06, bad_arity_with_required_arg0,
     mapfile: TIMELINE_DEBUGGING_ECLMAP,
@@ -165,6 +180,23 @@ source_test!(
     check_decompiled: |decompiled| assert!(decompiled.contains("(@arg0=5, 3, 3)")),
 );
 
+source_test!(
+    ECL_TIMELINE_06, explicit_arg0_option,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    main_body: r#"
+    hasUnusedArg0(3, 3);
+    hasUnusedArg0(@arg0=5, 4, 4);
+    hasMsgArg0(6, 5, 5);
+"#,
+    decompile_args: &["--explicit-arg0"],
+    check_decompiled: |decompiled| {
+        assert!(decompiled.contains("(@arg0=0, 3, 3)"));
+        assert!(decompiled.contains("(@arg0=5, 4, 4)"));
+        // an arg0 in the signature is still written as a positional arg
+        assert!(decompiled.contains("(6, 5, 5)"));
+    },
+);
+
 source_test!(
     ECL_TIMELINE_06, unused_arg0_padding_edge_case,
     mapfile: r#"!eclmap
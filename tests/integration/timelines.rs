@@ -140,3 +140,68 @@ timeline { ins_10(100); }
     expect_error: "too many timelines",
 );
 
+
+source_test!(
+    ECL_08, qualified_ins_in_inline_func,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+inline void spawn(int x) {
+    timeline.ins_10(x);
+}
+
+timeline 0 { spawn(100); }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.timelines[0][0].opcode, 10);
+        assert_eq!(ecl.timelines[0][0].args_blob, blobify![100]);
+    },
+);
+
+source_test!(
+    ECL_08, qualified_ins_redundant,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+timeline 0 { timeline.ins_10(100); }
+"#,
+    check_compiled: |output, format| {
+        let ecl = output.read_ecl(format);
+        assert_eq!(ecl.timelines[0][0].args_blob, blobify![100]);
+    },
+);
+
+source_test!(
+    ECL_08, qualified_ins_wrong_language,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+void main() {
+    timeline.ins_10(100);  //~ ERROR Timeline instruction
+}
+"#,
+);
+
+source_test!(
+    ECL_08, qualified_ins_unknown_language,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+timeline 0 { anm.ins_10(100); }  //~ ERROR unknown language
+"#,
+);
+
+source_test!(
+    ECL_08, qualified_reg_unknown_language,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+void main() {
+    int x = $std.REG[10000];  //~ ERROR unknown language
+}
+"#,
+);
+
+source_test!(
+    ECL_08, qualified_in_const_context,
+    mapfile: TIMELINE_DEBUGGING_ECLMAP,
+    full_source: r#"
+const int X = timeline.REG[10000];  //~ ERROR const context
+"#,
+);
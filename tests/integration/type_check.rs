@@ -81,12 +81,86 @@ source_test!(
 10056 ?
     "#,
     main_body: r#"
-        var untyped_local = 2;  //~ ERROR variable requires a type prefix
+        var untyped_local;  // only ever used with sigils, so it never gets a type
+        $untyped_local = 2;
+        I2 = untyped_local;  //~ ERROR variable requires a type prefix
         I0 = RAND_RANGE;  //~ ERROR variable requires a type prefix
         I1 = GVAR_WITH_NO_TYPE;  //~ ERROR variable requires a type prefix
     "#,
 );
 
+source_test!(
+    ECL_07, reg_mixed_sigils,
+    mapfile: r#"!eclmap
+!gvar_names
+42069 GVAR_WITH_NO_TYPE
+42070 GVAR_DECLARED_UNTYPED
+!gvar_types
+42070 ?
+    "#,
+    main_body: r#"
+        $REG[42069] = 1;  //~ WARNING both an integer and a float
+        F0 = %GVAR_WITH_NO_TYPE;
+        I0 = $GVAR_WITH_NO_TYPE;  // only one warning per register
+
+        // registers with a declared type are exempt
+        $GVAR_DECLARED_UNTYPED = 1;
+        F1 = %GVAR_DECLARED_UNTYPED;
+        F2 = %I2;
+    "#,
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ECL_07, var_inferred_types,
+    main_body: r#"
+        var a = 3.0;
+        var b = I0 * 2;
+        var c;
+        c = a + 1.0;
+        F0 = a + c;
+        I1 = b;
+    "#,
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ANM_10, var_inferred_types_stackless,
+    main_body: r#"
+        var a = I0 + 1;
+        var b;
+        b = F1;
+        I1 = a * 2;
+        F2 = b;
+    "#,
+    check_compiled: |_, _| {
+        // just need it to succeed
+    },
+);
+
+source_test!(
+    ECL_07, var_read_before_type_known,
+    main_body: r#"
+        var a;
+        I0 = a + 1;  //~ ERROR not known yet
+        a = 2;
+    "#,
+);
+
+source_test!(
+    ECL_07, var_inferred_type_conflicts,
+    main_body: r#"
+        var a = 2;
+        a = 3.0;  //~ ERROR type error
+        var s = "text";
+        I0 = $s;  //~ ERROR type error
+    "#,
+);
+
 // =========================
 // jumps
 
@@ -234,11 +308,13 @@ inline void foo() { return 0; }  //~ ERROR type error
 
 source_test!(
     ANM_10, return__none_from_void,
-    // FIXME: Inline funcs should be supported eventually.
-    //        Once they are, this should become a compile-succeed test.
     items: r#"
-inline void foo() { return; }  //~ ERROR not supported
+inline void foo() { return; }
+    "#,
+    main_body: r#"
+foo();
     "#,
+    check_compiled: |_, _| {},
 );
 
 // (if we want to allow this to compile, then each lowerer will need tests to check that this
@@ -274,18 +350,63 @@ inline float foo() { return sprite(0); }  //~ ERROR type error
 source_test!(
     ANM_10, return__missing_from_value,
     items: r#"
-inline int foo() { }
-//~^ WARNING has no return
-//~| ERROR not supported
+inline int foo() { }  //~ WARNING has no return
     "#,
 );
 
 source_test!(
     ANM_10, return__missing_from_void__stackless,
-    // FIXME: Inline funcs should be supported eventually.
-    //        Once they are, this should become a compile-succeed test.
     items: r#"
-inline void foo() { }  //~ ERROR not supported
+inline void foo() { }
     "#,
+    main_body: r#"
+foo();
+    "#,
+    check_compiled: |_, _| {},
     // FIXME: This needs a stackful version
 );
+
+source_test!(
+    ANM_10, signature_from_mapfile,
+    mapfile: r#"!anmmap
+!ins_signatures
+1000 Sf
+!ins_names
+1000 myInstr
+"#,
+    main_body: r#"
+        myInstr(3);  //~ ERROR wrong number of arguments
+        myInstr(3.0, 2.0);  //~ ERROR type error
+    "#,
+);
+
+source_test!(
+    ANM_10, signature_from_user_func,
+    items: r#"
+        const int foo(int x) { return x; }
+    "#,
+    main_body: r#"
+        I0 = foo(2, 3);  //~ ERROR wrong number of arguments
+        I0 = foo(2.0);  //~ ERROR type error
+    "#,
+);
+
+source_test!(
+    ANM_10, arg_count_error_uses_alias,
+    mapfile: r#"!anmmap
+!ins_signatures
+1000 Sf
+!ins_names
+1000 myInstr
+"#,
+    main_body: r#"
+        ins_1000(3);  //~ ERROR wrong number of arguments to 'myInstr'
+    "#,
+);
+
+source_test!(
+    ANM_10, signature_from_core_mapfile,
+    main_body: r#"
+        ins_1(1, 2, 3);  //~ ERROR wrong number of arguments
+    "#,
+);
@@ -60,6 +60,13 @@ pub const ANM_16: Format = Format {
     make_main: ANM_06.make_main,
 };
 
+pub const ANM_19: Format = Format {
+    cmd: "truanm",
+    game: Game::Th19,
+    script_head: ANM_06.script_head,
+    make_main: ANM_06.make_main,
+};
+
 pub const STD_06: Format = Format {
     cmd: "trustd",
     game: Game::Th06,
@@ -86,6 +93,13 @@ script main {{
 "#, body),
 };
 
+pub const STD_07: Format = Format {
+    cmd: "trustd",
+    game: Game::Th07,
+    script_head: STD_06.script_head,
+    make_main: STD_06.make_main,
+};
+
 pub const STD_08: Format = Format {
     cmd: "trustd",
     game: Game::Th08,
@@ -93,6 +107,13 @@ pub const STD_08: Format = Format {
     make_main: STD_06.make_main,
 };
 
+pub const STD_09: Format = Format {
+    cmd: "trustd",
+    game: Game::Th09,
+    script_head: STD_06.script_head,
+    make_main: STD_06.make_main,
+};
+
 pub const STD_12: Format = Format {
     cmd: "trustd",
     game: Game::Th12,
@@ -116,6 +137,13 @@ meta {
     make_main: STD_06.make_main,
 };
 
+pub const STD_19: Format = Format {
+    cmd: "trustd",
+    game: Game::Th19,
+    script_head: STD_12.script_head,
+    make_main: STD_12.make_main,
+};
+
 pub const MSG_06: Format = Format {
     cmd: "trumsg",
     game: Game::Th06,
@@ -217,6 +245,17 @@ pub const ECL_08: Format = Format {
     ..ECL_06
 };
 
+pub const ECL_12: Format = Format {
+    cmd: "truecl",
+    game: Game::Th12,
+    script_head: r#""#,
+    make_main: |body| format!(r#"
+void main() {{
+    {}
+}}
+"#, body),
+};
+
 /// Variant of ECL_06 where main_body is inserted into a timeline instead.
 /// There are no subs by default.
 pub const ECL_TIMELINE_06: Format = Format {
@@ -60,6 +60,7 @@ pub fn parse_diagnostics(text: &[u8]) -> Vec<ParsedDiagnostic> {
         (&b"error: "[..], DiagnosticKind::Error),
         (&b"warning: "[..], DiagnosticKind::Warning),
         (&b"info: "[..], DiagnosticKind::Info),
+        (&b"note: "[..], DiagnosticKind::Info),  // codespan's name for Severity::Info
     ];
 
     let mut cur_diagnostic = None;
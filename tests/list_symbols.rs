@@ -0,0 +1,261 @@
+//! Tests for [`truth::Truth::list_symbols`].
+
+use truth::ast::{self, FuncQualifier, MetaKeyword, TypeKeyword as Ty};
+use truth::symbols::{Symbol, SymbolKind, SymbolParam};
+use truth::navigate::DefinitionKind;
+use truth::{Game, FileFormat, Namespace, ScalarValue};
+
+mod api_impl;
+use api_impl::{anm_entry, compile_anm};
+
+fn list_symbols(source: &str) -> (Vec<Symbol>, String) {
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+    let symbols = truth.list_symbols(&ast);
+    (symbols, truth.get_captured_diagnostics().unwrap())
+}
+
+fn summary(symbols: &[Symbol]) -> Vec<(Option<&str>, &SymbolKind)> {
+    symbols.iter().map(|sym| (sym.name.as_ref().map(|x| x.as_str()), &sym.kind)).collect()
+}
+
+fn param(ty: Ty, name: &str) -> SymbolParam {
+    SymbolParam { ty, name: Some(truth::Ident::new_user(name).unwrap()) }
+}
+
+#[test]
+fn all_item_kinds() {
+    let (symbols, stderr) = list_symbols(r#"
+meta {}
+const int A = B * 2, B = 3;
+const string S = "hi";
+script 10 foo {}
+timeline 0 {}
+timeline bar {}
+void sub0(int x, float y) {}
+inline float lerp(float a, float b);
+"#);
+    assert_eq!(stderr, "");
+    assert_eq!(summary(&symbols), vec![
+        (None, &SymbolKind::Meta { keyword: MetaKeyword::Meta }),
+        (Some("A"), &SymbolKind::Const { ty: Ty::Int, value: Some(ScalarValue::Int(6)) }),
+        (Some("B"), &SymbolKind::Const { ty: Ty::Int, value: Some(ScalarValue::Int(3)) }),
+        (Some("S"), &SymbolKind::Const { ty: Ty::String, value: Some(ScalarValue::String("hi".into())) }),
+        (Some("foo"), &SymbolKind::Script { number: Some(10) }),
+        (None, &SymbolKind::Timeline { number: Some(0) }),
+        (Some("bar"), &SymbolKind::Timeline { number: None }),
+        (Some("sub0"), &SymbolKind::Func {
+            qualifier: None,
+            return_ty: Ty::Void,
+            params: vec![param(Ty::Int, "x"), param(Ty::Float, "y")],
+        }),
+        (Some("lerp"), &SymbolKind::Func {
+            qualifier: Some(FuncQualifier::Inline),
+            return_ty: Ty::Float,
+            params: vec![param(Ty::Float, "a"), param(Ty::Float, "b")],
+        }),
+    ]);
+}
+
+#[test]
+fn tolerates_errors() {
+    let (symbols, stderr) = list_symbols(r#"
+const int A = 1 + 2;
+const int BAD = BAD + 1;
+const int ALSO_BAD = BAD * 2;
+void sub0() {
+    int x = "type error in a function";
+    undefined_function();
+}
+"#);
+    assert_eq!(stderr.matches("cycle in const definition").count(), 1, "{stderr}");
+    // errors in function bodies are not reported
+    assert!(!stderr.contains("undefined_function"), "{stderr}");
+
+    assert_eq!(summary(&symbols)[..3], [
+        (Some("A"), &SymbolKind::Const { ty: Ty::Int, value: Some(ScalarValue::Int(3)) }),
+        (Some("BAD"), &SymbolKind::Const { ty: Ty::Int, value: None }),
+        (Some("ALSO_BAD"), &SymbolKind::Const { ty: Ty::Int, value: None }),
+    ]);
+    assert_eq!(symbols[3].name.as_ref().unwrap(), "sub0");
+}
+
+#[test]
+fn spans() {
+    let source = "const int A = 1;\nscript main {}\n";
+    let (symbols, _) = list_symbols(source);
+    let text = |span: truth::Span| &source[span.start.0 as usize..span.end.0 as usize];
+    assert_eq!(text(symbols[0].span), "A");
+    assert_eq!(text(symbols[0].item_span), "const int A = 1;");
+    assert_eq!(text(symbols[1].span), "main");
+}
+
+#[test]
+fn compile_after_listing() {
+    let source = r#"
+meta {
+    unknown: 0,
+    stage_name: "dm",
+    bgm: [
+        {path: "bgm/th08_08.mid", name: "dm"},
+        {path: "bgm/th08_09.mid", name: "dm"},
+        {path: " ", name: " "},
+        {path: " ", name: " "},
+    ],
+    objects: {},
+    instances: [],
+}
+
+const float X = 1.0;
+
+script main {
+    ins_2(X, 2.0, 3.0);
+}
+"#;
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    truth.apply_mapfile_str("!stdmap\n!ins_signatures\n2 fff\n", Game::Th06).unwrap();
+    let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+    let symbols = truth.list_symbols(&ast);
+    assert_eq!(symbols.len(), 3);
+
+    // the same AST should still be usable for compilation
+    let result = truth.validate_defs().and_then(|mut truth| truth.compile_std(Game::Th06, &ast));
+    result.expect(&truth.get_captured_diagnostics().unwrap());
+}
+
+#[test]
+fn reuse_const_cache() {
+    let const_values = |symbols: &[Symbol]| symbols.iter().map(|sym| match &sym.kind {
+        SymbolKind::Const { value, .. } => value.clone(),
+        _ => panic!("{:?}", sym),
+    }).collect::<Vec<_>>();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let cache = {
+        let mut truth = scope.truth();
+        let source = "const int A = 1;\nconst int B = A + 1;\nconst int C = 7;\n";
+        let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+        truth.list_symbols(&ast);
+        truth.const_cache()
+    };
+    assert!(!cache.is_empty());
+
+    // B must not reuse its old value, because A changed
+    let mut truth = scope.truth();
+    truth.reuse_const_cache(cache);
+    let source = "const int A = 2;\nconst int B = A + 1;\nconst int C = 7;\n";
+    let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+    let symbols = truth.list_symbols(&ast);
+    assert_eq!(truth.get_captured_diagnostics().unwrap(), "");
+    assert_eq!(const_values(&symbols), vec![
+        Some(ScalarValue::Int(2)),
+        Some(ScalarValue::Int(3)),
+        Some(ScalarValue::Int(7)),
+    ]);
+    assert_eq!(reused_const_names(&mut truth), vec!["C"]);
+}
+
+/// Names of the user-defined consts whose values were taken from the cache.  (builtins like `PI` and
+/// enum consts are cached too)
+fn reused_const_names(truth: &mut truth::Truth) -> Vec<String> {
+    reused_consts(truth).into_iter().map(|(name, _)| name).collect()
+}
+
+fn reused_consts(truth: &mut truth::Truth) -> Vec<(String, truth::DefId)> {
+    let reused = truth.reused_consts();
+    let defs = &truth.ctx().defs;
+    reused.into_iter()
+        .filter(|&def_id| defs.definition(Namespace::Vars, def_id).kind == DefinitionKind::Const)
+        .map(|def_id| (defs.var_name(def_id).to_string(), def_id))
+        .collect()
+}
+
+#[test]
+fn const_cache_same_text_in_different_scopes() {
+    let source = anm_entry("a.png", r#"
+script script0 {
+    const int Y = 1;
+    const int X = Y;
+    $REG[10000] = X;
+}
+script script1 {
+    const int Y = 2;
+    const int X = Y;
+    $REG[10000] = X;
+}
+"#);
+    let expected = compile_anm(&source);
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let compile = |truth: &mut truth::Truth| {
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "<input>", &source, &[])
+            .unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()))
+    };
+    let cache = {
+        let mut truth = scope.truth();
+        compile(&mut truth);
+        truth.const_cache()
+    };
+
+    let mut truth = scope.truth();
+    truth.reuse_const_cache(cache);
+    assert_eq!(compile(&mut truth), expected);
+    // each X could have come from either script, so neither is reused
+    assert_eq!(reused_const_names(&mut truth), vec!["Y", "Y"]);
+}
+
+#[test]
+fn const_cache_checks_what_names_refer_to() {
+    let cache = {
+        let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+        let mut truth = scope.truth();
+        let source = "const int Y = 1;\nconst int X = Y;\n";
+        let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+        truth.list_symbols(&ast);
+        truth.const_cache()
+    };
+
+    // the X in the script has the same text, but its Y is a different one
+    let source = anm_entry("a.png", r#"
+const int Y = 1;
+script script0 {
+    const int Y = 2;
+    const int X = Y;
+    $REG[10000] = X;
+}
+"#);
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    truth.reuse_const_cache(cache);
+    let output = truth.compile_from_str(FileFormat::Anm, Game::Th12, "<input>", &source, &[]);
+    let output = output.unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert_eq!(output, compile_anm(&source));
+    assert_eq!(reused_const_names(&mut truth), vec!["Y"]);
+}
+
+#[test]
+fn invalidate_const() {
+    let source = "const int A = 1;\nconst int B = A + 1;\nconst int C = B * 2;\nconst int D = 7;\n";
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let cache = {
+        let mut truth = scope.truth();
+        let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+        truth.list_symbols(&ast);
+        truth.const_cache()
+    };
+
+    // get the DefIds by reusing every value
+    let mut truth = scope.truth();
+    truth.reuse_const_cache(cache);
+    let ast = truth.parse::<ast::ScriptFile>("<input>", source.as_bytes()).unwrap().value;
+    truth.list_symbols(&ast);
+    assert_eq!(reused_const_names(&mut truth), vec!["A", "B", "C", "D"]);
+    let (_, a) = reused_consts(&mut truth)[0];
+
+    let invalidated = truth.invalidate_const(a);
+    let defs = &truth.ctx().defs;
+    let names = invalidated.into_iter().map(|def_id| defs.var_name(def_id).to_string()).collect::<Vec<_>>();
+    assert_eq!(names, vec!["A", "B", "C"]);
+}
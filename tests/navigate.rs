@@ -0,0 +1,121 @@
+//! Tests for [`truth::Truth::index_names`] and [`truth::Truth::definition`].
+
+use truth::ast;
+use truth::navigate::{DefinitionKind, NameIndex};
+use truth::pos::{BytePos, FileId};
+use truth::{Game, LanguageKey, Namespace, RegId};
+
+const MAPFILE: &str = "!anmmap\n!gvar_names\n10000 COUNTER\n!ins_names\n1 wait\n";
+
+const SOURCE: &str = r#"
+const int LIMIT = 3;
+
+void helper(int x) {
+    int y = x + LIMIT;
+    $COUNTER = y;
+}
+
+script main {
+    helper(LIMIT);
+    wait(%COUNTER);
+    undefined_thing();
+}
+"#;
+
+struct Indexed<'a> {
+    truth: truth::Truth<'a>,
+    index: NameIndex,
+    file_id: FileId,
+}
+
+impl Indexed<'_> {
+    /// Position of the `nth` occurrence of `needle` in the source, plus an offset.
+    fn pos(&self, needle: &str, nth: usize, offset: usize) -> BytePos {
+        let (start, _) = SOURCE.match_indices(needle).nth(nth).unwrap();
+        BytePos((start + offset) as u32)
+    }
+
+    fn text(&mut self, span: truth::Span) -> String {
+        self.truth.ctx().emitter.files.span_text(span).unwrap()
+    }
+}
+
+fn with_index(test: impl FnOnce(Indexed<'_>)) {
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    truth.apply_mapfile_str(MAPFILE, Game::Th12).unwrap();
+    let ast = truth.parse::<ast::ScriptFile>("<input>", SOURCE.as_bytes()).unwrap();
+    let file_id = ast.span.file_id;
+    let index = truth.index_names(LanguageKey::Anm, &ast.value);
+
+    let stderr = truth.get_captured_diagnostics().unwrap();
+    assert!(stderr.contains("undefined_thing"), "{stderr}");
+    test(Indexed { truth, index, file_id });
+}
+
+#[test]
+fn go_to_local_definition() {
+    with_index(|mut t| {
+        let name = t.index.name_at(t.file_id, t.pos("x + LIMIT", 0, 0)).unwrap().clone();
+        assert_eq!(name.ident.as_str(), "x");
+        assert_eq!(name.ns, Namespace::Vars);
+        assert!(!name.is_definition);
+
+        let def = t.truth.definition(&name).unwrap();
+        assert_eq!(def.kind, DefinitionKind::Local);
+        assert_eq!(def.span.unwrap().start, t.pos("int x", 0, 4));
+        assert_eq!(t.text(def.span.unwrap()), "x");
+    });
+}
+
+#[test]
+fn find_references() {
+    with_index(|mut t| {
+        // the end of an ident counts as part of it
+        let name = t.index.name_at(t.file_id, t.pos("LIMIT =", 0, 5)).unwrap().clone();
+        assert!(name.is_definition);
+        let def = t.truth.definition(&name).unwrap();
+        assert_eq!(def.kind, DefinitionKind::Const);
+
+        let refs = t.index.references(def.def_id).map(|name| name.span.start).collect::<Vec<_>>();
+        assert_eq!(refs, [t.pos("LIMIT", 0, 0), t.pos("LIMIT", 1, 0), t.pos("LIMIT", 2, 0)]);
+
+        let func = t.index.name_at(t.file_id, t.pos("helper(LIMIT)", 0, 2)).unwrap().clone();
+        assert_eq!(func.ns, Namespace::Funcs);
+        let def = t.truth.definition(&func).unwrap();
+        assert_eq!(def.kind, DefinitionKind::Func { qualifier: None });
+        assert_eq!(t.text(def.span.unwrap()), "helper");
+        assert_eq!(t.index.references(def.def_id).count(), 2);
+    });
+}
+
+#[test]
+fn mapfile_aliases() {
+    with_index(|mut t| {
+        let reg = t.index.name_at(t.file_id, t.pos("%COUNTER", 0, 3)).unwrap().clone();
+        assert_eq!(t.text(reg.span), "COUNTER");  // without the sigil
+        let def = t.truth.definition(&reg).unwrap();
+        assert_eq!(def.kind, DefinitionKind::RegisterAlias { language: LanguageKey::Anm, reg: RegId(10000) });
+        assert_eq!(t.text(def.span.unwrap()), "COUNTER");
+        assert_ne!(def.span.unwrap().file_id, t.file_id);
+        assert_eq!(t.index.references(def.def_id).count(), 2);
+
+        let ins = t.index.name_at(t.file_id, t.pos("wait", 0, 0)).unwrap().clone();
+        let def = t.truth.definition(&ins).unwrap();
+        assert_eq!(def.kind, DefinitionKind::InstructionAlias { language: LanguageKey::Anm, opcode: 1 });
+        assert_eq!(t.text(def.span.unwrap()), "wait");
+    });
+}
+
+#[test]
+fn unresolved_and_missing() {
+    with_index(|t| {
+        let name = t.index.name_at(t.file_id, t.pos("undefined_thing", 0, 1)).unwrap();
+        assert_eq!(name.def_id, None);
+        assert!(t.truth.definition(name).is_none());
+
+        // whitespace and keywords
+        assert!(t.index.name_at(t.file_id, t.pos("script", 0, 1)).is_none());
+        assert!(t.index.name_at(None, t.pos("LIMIT", 0, 0)).is_none());
+    });
+}
@@ -0,0 +1,71 @@
+//! Tests for [`truth::Truth::opcode_stats_from_bytes`].
+
+use truth::opcode_stats::OpcodeStats;
+use truth::{Game, FileFormat, LanguageKey};
+
+mod api_impl;
+use api_impl::{ANM_MAPFILE, anm_entry, compile_anm, with_truth};
+
+fn anm_stats(sources: &[&str]) -> OpcodeStats {
+    let files = sources.iter().map(|source| compile_anm(source)).collect::<Vec<_>>();
+    let files = files.iter().map(|bytes| ("in.anm", &bytes[..])).collect::<Vec<_>>();
+    let (stats, stderr) = with_truth(|truth| {
+        let mapfile = truth.parse_mapfile("<mapfile>", ANM_MAPFILE).unwrap();
+        truth.opcode_stats_from_bytes(FileFormat::Anm, Game::Th12, &files, &[mapfile])
+    });
+    let stats = stats.expect(&stderr);
+    assert_eq!(stderr, "");
+    stats
+}
+
+#[test]
+fn counts_and_sizes() {
+    let stats = anm_stats(&[
+        &anm_entry("a.png", "script a { ins_0(); pos(1.0, 2.0, 3.0); ins_0(); }"),
+        &anm_entry("b.png", r#"script b { pos(4.0, 5.0, 6.0); ins_1000(@blob="01000000"); ins_1000(@blob=""); }"#),
+    ]);
+
+    let pos = &stats.opcodes[&(LanguageKey::Anm, 48)];
+    assert_eq!(pos.name.as_ref().unwrap(), "pos");
+    assert!(pos.has_signature);
+    assert_eq!(pos.count, 2);
+    assert_eq!(pos.arg_sizes.iter().collect::<Vec<_>>(), vec![(&12, &2)]);
+
+    let unknown = stats.unknown().collect::<Vec<_>>();
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(unknown[0].opcode, 1000);
+    assert_eq!(unknown[0].name, None);
+    assert_eq!(unknown[0].count, 2);
+    assert_eq!(unknown[0].arg_sizes.iter().collect::<Vec<_>>(), vec![(&0, &1), (&4, &1)]);
+
+    assert_eq!(stats.to_string(), "\
+language  opcode  name      count  signature  arg sizes
+ANM            0  ins_0         2  yes        0
+ANM           48  pos           2  yes        12
+ANM         1000  ins_1000      2  no         0 (x1), 4 (x1)
+");
+}
+
+#[test]
+fn merge() {
+    let a = anm_stats(&[&anm_entry("a.png", "script a { ins_0(); pos(1.0, 2.0, 3.0); }")]);
+    let b = anm_stats(&[&anm_entry("b.png", r#"script b { ins_0(); ins_1000(@blob="01000000"); }"#)]);
+    let both = anm_stats(&[
+        &anm_entry("a.png", "script a { ins_0(); pos(1.0, 2.0, 3.0); }"),
+        &anm_entry("b.png", r#"script b { ins_0(); ins_1000(@blob="01000000"); }"#),
+    ]);
+
+    let mut merged = a.clone();
+    merged.merge(&b);
+    assert_eq!(merged, both);
+    assert_eq!(merged.opcodes[&(LanguageKey::Anm, 0)].count, 2);
+}
+
+#[test]
+fn mission_is_error() {
+    let (result, stderr) = with_truth(|truth| {
+        truth.opcode_stats_from_bytes(FileFormat::Mission, Game::Th08, &[("mission.msg", &[])], &[])
+    });
+    assert!(result.is_err());
+    assert!(stderr.contains("contain no instructions"), "{stderr}");
+}
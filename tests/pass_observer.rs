@@ -0,0 +1,117 @@
+//! Tests for [`truth::passes::observer`].
+
+use std::sync::{Arc, Mutex};
+
+use truth::ast;
+use truth::llir::RawInstr;
+use truth::passes::observer::{PassObserver, DumpAstObserver};
+use truth::{ErrorReported, FileFormat, Game, Resolutions};
+
+const SOURCE: &str = r#"
+meta {
+    unknown: 0,
+    stage_name: "dm",
+    bgm: [
+        {path: "bgm/th08_08.mid", name: "dm"},
+        {path: "bgm/th08_09.mid", name: "dm"},
+        {path: " ", name: " "},
+        {path: " ", name: " "},
+    ],
+    objects: {},
+    instances: [],
+}
+
+const float X = 1.0 + 2.0;
+
+script main {
+    loop {
+        ins_0(X, 2.0, 3.0);
+    }
+}
+"#;
+
+#[derive(Default)]
+struct Recorder {
+    events: Arc<Mutex<Vec<String>>>,
+}
+
+impl PassObserver for Recorder {
+    fn after_parse(&mut self, ast: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.events.lock().unwrap().push(format!("parse {}", ast.items.len()));
+        Ok(())
+    }
+
+    fn after_pass(&mut self, pass: &'static str, _: &ast::ScriptFile) -> Result<(), ErrorReported> {
+        self.events.lock().unwrap().push(pass.to_owned());
+        Ok(())
+    }
+
+    fn after_resolution(&mut self, _: &ast::ScriptFile, _: &Resolutions) -> Result<(), ErrorReported> {
+        self.events.lock().unwrap().push("resolution".to_owned());
+        Ok(())
+    }
+
+    fn after_lowering(&mut self, script_name: &str, instrs: &[RawInstr]) -> Result<(), ErrorReported> {
+        self.events.lock().unwrap().push(format!("lowering {script_name} {}", instrs.len()));
+        Ok(())
+    }
+}
+
+fn compile_std_with(add_observers: impl FnOnce(&mut truth::Truth)) {
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    add_observers(&mut truth);
+
+    let result = truth.compile_from_str(FileFormat::Std, Game::Th08, "<input>", SOURCE, &[]);
+    if result.is_err() {
+        panic!("{}", truth.get_captured_diagnostics().unwrap());
+    }
+}
+
+#[test]
+fn observer_call_order() {
+    let events = Arc::new(Mutex::new(vec![]));
+    compile_std_with(|truth| truth.add_pass_observer(Recorder { events: events.clone() }));
+
+    assert_eq!(&events.lock().unwrap()[..], &[
+        "parse 3",
+        "assign_languages",
+        "resolution",
+        "const_simplify",
+        "desugar_blocks",
+        "lowering main 2",  // the instruction and the jump
+    ]);
+}
+
+#[test]
+fn dump_ast() {
+    let dir = tempfile::tempdir().unwrap();
+    let dump_dir = dir.path().join("passes");
+    compile_std_with(|truth| {
+        let observer = DumpAstObserver::new(truth.fs(), &dump_dir).unwrap();
+        truth.add_pass_observer(observer);
+    });
+
+    let mut names = std::fs::read_dir(&dump_dir).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, [
+        "00-parse.txt",
+        "01-assign_languages.txt",
+        "02-const_simplify.txt",
+        "03-desugar_blocks.txt",
+        "04-lowered-main.txt",
+    ]);
+
+    let parsed = std::fs::read_to_string(dump_dir.join("00-parse.txt")).unwrap();
+    assert!(parsed.contains("ins_0(X, 2.0, 3.0);"), "{parsed}");
+    assert!(parsed.contains("loop {"), "{parsed}");
+
+    let simplified = std::fs::read_to_string(dump_dir.join("02-const_simplify.txt")).unwrap();
+    assert!(simplified.contains("ins_0(3.0, 2.0, 3.0);"), "{simplified}");
+
+    let desugared = std::fs::read_to_string(dump_dir.join("03-desugar_blocks.txt")).unwrap();
+    assert!(!desugared.contains("loop {"), "{desugared}");
+    assert!(desugared.contains("goto "), "{desugared}");
+}
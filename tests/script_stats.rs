@@ -0,0 +1,53 @@
+//! Tests for [`truth::Truth::script_stats`].
+
+use truth::script_stats::ScriptStats;
+use truth::{Game, FileFormat};
+
+mod api_impl;
+use api_impl::{anm_entry, with_truth};
+
+fn anm_stats(scripts: &str) -> ScriptStats {
+    let source = anm_entry("a.png", scripts);
+    let (stats, stderr) = with_truth(|truth| {
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "<input>", &source, &[])?;
+        Ok::<_, truth::ErrorReported>(truth.script_stats())
+    });
+    stats.expect(&stderr)
+}
+
+#[test]
+fn size_and_registers() {
+    let stats = anm_stats(r#"
+script a {
+    $REG[10002] = 3;
+    int x = $REG[10002];
+    x = x * 2;
+}
+script b {
+    ins_0();
+}
+"#);
+    assert_eq!(stats.scripts.len(), 2);
+
+    let a = &stats.scripts[0];
+    assert_eq!(a.name, "a");
+    assert_eq!(a.num_instrs, 3);
+    assert_eq!(a.size, 16 + 16 + 20);
+    assert_eq!(a.explicit_registers, vec![10002]);
+    assert_eq!(a.scratch_registers, vec![10000]);
+
+    let b = &stats.scripts[1];
+    assert_eq!((b.name.as_str(), b.num_instrs, b.size), ("b", 1, 8));
+    assert!(b.explicit_registers.is_empty());
+    assert!(b.scratch_registers.is_empty());
+}
+
+#[test]
+fn report() {
+    let stats = anm_stats("script a { ins_0(); }\nscript long_name { ins_0(); ins_0(); }");
+    assert_eq!(stats.to_string(), "\
+script     size  instrs  registers  scratch
+a             8       1
+long_name    16       2
+");
+}
@@ -0,0 +1,94 @@
+//! Tests for serialization of the AST.  (requires the `serde` feature)
+
+#![cfg(feature = "serde")]
+
+use truth::ast;
+use truth::pos::Sp;
+
+const SOURCE: &str = r#"
+#pragma mapfile "map/any.anmm"
+
+entry {
+    path: "subdir/file.png",
+    has_data: false,
+    img_width: 512,
+    offset_x: 0x10,
+    sprites: {
+        sprite0: {id: 0, x: 0.0, y: 0.0, w: 512.0, h: 480.0},
+        5: {x: 1.0, y: 2.0, w: 3.0, h: 4.0},
+    },
+}
+
+meta {
+    list: [1, "two", 3.0, variant {a: 1}],
+}
+
+const int FOO = 3 + sprite0;
+
+void sub0(int x, float y) {
+    int z = x * 2;
+    $z++;
+    if (z > 3) {
+        ins_23(z, %z);
+    } else unless (y == 2.0) {
+        goto label @ 10;
+    }
+    loop {
+        break;
+    }
+    times(3) {
+        F0 = sin(y) + (1.0:2.0:3.0);
+    }
+    {"EN"}: I0 = FOO ? 1 : 2;
+label:
++10: // comment
+    wait(offsetof(label));
+    ins_2(@mask=0b101, @blob="0000ffff");
+    return;
+}
+"#;
+
+fn round_trip(ast: &ast::ScriptFile) -> ast::ScriptFile {
+    let json = serde_json::to_string(ast).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn format_after_round_trip() {
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+
+    let parsed = match truth.parse::<ast::ScriptFile>("<input>", SOURCE.as_bytes()) {
+        Ok(parsed) => parsed.value,
+        Err(_) => panic!("{}", truth.get_captured_diagnostics().unwrap()),
+    };
+    let mut deserialized = round_trip(&parsed);
+    assert_eq!(truth::fmt::stringify(&deserialized), truth::fmt::stringify(&parsed));
+
+    // spans are preserved
+    let original_span = parsed.items[3].span;
+    assert_ne!(original_span, truth::Span::NULL);
+    assert_eq!(deserialized.items[3].span, original_span);
+
+    // so are the positions that comments trail after
+    assert!(parsed.comments[0].value.trailing_after.is_some());
+    assert_eq!(deserialized.comments, parsed.comments);
+
+    // the AST should be usable by passes after filling in the IDs
+    truth.fill_missing_ids(&mut deserialized).unwrap();
+    let mut scope_2 = truth::Builder::new().build();
+    let mut truth_2 = scope_2.truth();
+    let mut reparsed = round_trip(&deserialized);
+    truth_2.fill_missing_ids(&mut reparsed).unwrap();
+    assert_eq!(reparsed, parsed);
+}
+
+#[test]
+fn span_format() {
+    let value = truth::sp!(truth::Span::NULL => ast::Expr::from(3));
+    let json = serde_json::to_value(&value).unwrap();
+    assert_eq!(json["span"], serde_json::json!({"file_id": null, "start": 0, "end": 0}));
+
+    let parsed: Sp<ast::Expr> = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed, value);
+}
@@ -0,0 +1,483 @@
+//! Tests that perform AST lowering for languages that evaluate expressions on a stack (like modern ECL).
+//!
+//! Each test compiles a block and compares it against a hand-assembled instruction sequence.  The output is
+//! then decompiled and compiled again to make sure that it round-trips.  The `raise_` tests check what the
+//! decompiled expressions look like.
+
+use truth::{ast, llir, Truth};
+use truth::{ScalarType as Ty, RegId};
+
+const REG_A: RegId = RegId(1000);
+const REG_B: RegId = RegId(1001);
+const REG_X: RegId = RegId(1004);
+const REG_Y: RegId = RegId(1005);
+
+const JUMP_OPCODE: u16 = 1;
+const PUSH_INT_OPCODE: u16 = 10;
+const PUSH_FLOAT_OPCODE: u16 = 11;
+const POP_INT_OPCODE: u16 = 12;
+const POP_FLOAT_OPCODE: u16 = 13;
+const ADD_INT_OPCODE: u16 = 20;
+const ADD_FLOAT_OPCODE: u16 = 21;
+const SUB_INT_OPCODE: u16 = 22;
+const MUL_INT_OPCODE: u16 = 24;
+const LT_INT_OPCODE: u16 = 26;
+const NEG_FLOAT_OPCODE: u16 = 30;
+const SIN_OPCODE: u16 = 31;
+const CAST_FLOAT_OPCODE: u16 = 32;
+const JUMP_NE_OPCODE: u16 = 40;
+const JUMP_EQ_OPCODE: u16 = 41;
+const STACK_ALLOC_OPCODE: u16 = 50;
+const CALL_ASYNC_OPCODE: u16 = 60;
+const CALL_ASYNC_ID_OPCODE: u16 = 61;
+const FOO_OPCODE: u16 = 100;
+const WIDE_OPCODE: u16 = 101;
+const RAND_OPCODE: u16 = 102;
+const FETCH_OPCODE: u16 = 103;
+
+fn load_mapfile(truth: &mut Truth) {
+    use truth::ast::BinOpKind as B;
+    use truth::ast::UnOpKind as U;
+    use truth::llir::IntrinsicInstrKind as I;
+
+    let lines = vec![
+        format!("!anmmap"),
+        format!("!gvar_types"),
+        format!("{REG_A} $"),
+        format!("{REG_B} $"),
+        format!("{REG_X} %"),
+        format!("{REG_Y} %"),
+        format!("!gvar_names"),
+        format!("{REG_A} A"),
+        format!("{REG_B} B"),
+        format!("{REG_X} X"),
+        format!("{REG_Y} Y"),
+        format!("!ins_names"),
+        format!("{FOO_OPCODE} foo"),
+        format!("{WIDE_OPCODE} wide"),
+        format!("{RAND_OPCODE} rand"),
+        format!("{FETCH_OPCODE} fetch"),
+        format!("!ins_signatures"),
+        format!("{JUMP_OPCODE} ot"),
+        format!("{PUSH_INT_OPCODE} S"),
+        format!("{PUSH_FLOAT_OPCODE} f"),
+        format!("{POP_INT_OPCODE} S"),
+        format!("{POP_FLOAT_OPCODE} f"),
+        format!("{ADD_INT_OPCODE}"),
+        format!("{ADD_FLOAT_OPCODE}"),
+        format!("{SUB_INT_OPCODE}"),
+        format!("{MUL_INT_OPCODE}"),
+        format!("{LT_INT_OPCODE}"),
+        format!("{NEG_FLOAT_OPCODE}"),
+        format!("{SIN_OPCODE}"),
+        format!("{CAST_FLOAT_OPCODE}"),
+        format!("{JUMP_NE_OPCODE} ot"),
+        format!("{JUMP_EQ_OPCODE} ot"),
+        format!("{STACK_ALLOC_OPCODE} S"),
+        format!("{CALL_ASYNC_OPCODE} E"),
+        format!("{CALL_ASYNC_ID_OPCODE} ES"),
+        format!("{FOO_OPCODE} SS"),
+        format!("{WIDE_OPCODE} {}", "S".repeat(20)),
+        format!("{RAND_OPCODE} S"),
+        format!("{FETCH_OPCODE}"),
+        format!("!ins_rets"),
+        format!("{RAND_OPCODE} $"),
+        format!("{FETCH_OPCODE} %REG[{REG_Y}]"),
+        format!("!ins_intrinsics"),
+        format!("{JUMP_OPCODE} {}", I::Jmp),
+        format!("{PUSH_INT_OPCODE} {}", I::Push(Ty::Int)),
+        format!("{PUSH_FLOAT_OPCODE} {}", I::Push(Ty::Float)),
+        format!("{POP_INT_OPCODE} {}", I::Pop(Ty::Int)),
+        format!("{POP_FLOAT_OPCODE} {}", I::Pop(Ty::Float)),
+        format!("{ADD_INT_OPCODE} {}", I::StackBinOp(B::Add, Ty::Int)),
+        format!("{ADD_FLOAT_OPCODE} {}", I::StackBinOp(B::Add, Ty::Float)),
+        format!("{SUB_INT_OPCODE} {}", I::StackBinOp(B::Sub, Ty::Int)),
+        format!("{MUL_INT_OPCODE} {}", I::StackBinOp(B::Mul, Ty::Int)),
+        format!("{LT_INT_OPCODE} {}", I::StackBinOp(B::Lt, Ty::Int)),
+        format!("{NEG_FLOAT_OPCODE} {}", I::StackUnOp(U::Neg, Ty::Float)),
+        format!("{SIN_OPCODE} {}", I::StackUnOp(U::Sin, Ty::Float)),
+        format!("{CAST_FLOAT_OPCODE} {}", I::StackUnOp(U::CastF, Ty::Int)),
+        format!("{JUMP_NE_OPCODE} {}", I::StackCondJmp(B::Ne)),
+        format!("{JUMP_EQ_OPCODE} {}", I::StackCondJmp(B::Eq)),
+        format!("{STACK_ALLOC_OPCODE} {}", I::StackAlloc),
+        format!("{CALL_ASYNC_OPCODE} {}", I::CallAsync),
+        format!("{CALL_ASYNC_ID_OPCODE} {}", I::CallAsyncId),
+        format!("!enum(name=\"EclSub\")"),
+        format!("0 worker"),
+    ];
+    truth.apply_mapfile_str(&lines.join("\n"), truth::Game::Th10)
+        .unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+}
+
+fn make_language() -> llir::TestLanguage {
+    let mut format = llir::TestLanguage::default();
+    format.language = truth::LanguageKey::Anm;
+    format.has_stack = true;
+    format
+}
+
+/// An instruction reduced to the parts that these tests care about.
+#[derive(Debug, Clone, PartialEq)]
+struct Instr {
+    opcode: u16,
+    param_mask: u32,
+    args_blob: Vec<u8>,
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Arg {
+    Int(i32),
+    Float(f32),
+    IntReg(RegId),
+    FloatReg(RegId),
+}
+
+fn instr(opcode: u16, args: &[Arg]) -> Instr {
+    let mut args_blob = vec![];
+    let mut param_mask = 0;
+    for (index, &arg) in args.iter().enumerate() {
+        let bytes = match arg {
+            Arg::Int(x) => x.to_le_bytes(),
+            Arg::Float(x) => x.to_le_bytes(),
+            Arg::IntReg(reg) => reg.0.to_le_bytes(),
+            Arg::FloatReg(reg) => (reg.0 as f32).to_le_bytes(),
+        };
+        if matches!(arg, Arg::IntReg(_) | Arg::FloatReg(_)) {
+            param_mask |= 1 << index;
+        }
+        args_blob.extend(bytes);
+    }
+    Instr { opcode, param_mask, args_blob }
+}
+
+#[track_caller]
+fn compile(truth: &mut Truth, hooks: &llir::TestLanguage, text: &str) -> Result<Vec<llir::RawInstr>, truth::ErrorReported> {
+    let mut block = truth.parse::<ast::Block>("<input>", text.as_ref())?.value;
+
+    let ctx = truth.ctx();
+    truth::passes::resolution::assign_languages(&mut block, truth::LanguageKey::Anm, ctx)?;
+    truth::passes::resolution::resolve_names(&block, ctx)?;
+    truth::passes::type_check::run(&block, ctx)?;
+    truth::passes::resolution::aliases_to_raw(&mut block, ctx)?;
+    truth::passes::resolution::compute_diff_label_masks(&mut block, ctx)?;
+    truth::passes::desugar_blocks::run(&mut block, ctx, truth::LanguageKey::Anm)?;
+
+    let mut errors = truth::error::ErrorFlag::new();
+    let mut lowerer = llir::Lowerer::new(hooks);
+    let (def_id, do_debug_info) = (None, false);
+    let (instrs, _) = lowerer.lower_sub(&block.0, def_id, ctx, do_debug_info).unwrap_or_else(|e| {
+        errors.set(e);
+        (vec![], None)  // dummy instructions so we can call lowerer.finish before returning
+    });
+    lowerer.finish(ctx).unwrap_or_else(|e| errors.set(e));
+    errors.into_result(instrs)
+}
+
+#[track_caller]
+fn decompile(truth: &mut Truth, hooks: &llir::TestLanguage, instrs: &[llir::RawInstr]) -> Result<String, truth::ErrorReported> {
+    let emitter = truth.emitter();
+    let ctx = truth.ctx();
+    let options = Default::default();
+    let const_proof = truth::passes::evaluate_const_vars::run(ctx)?;
+    let raiser = llir::Raiser::new(hooks, ctx.emitter, ctx, &options, const_proof)?;
+    let stmts = raiser.raise_instrs_to_sub_ast(&emitter, instrs, &ctx)?;
+    Ok(truth::fmt::stringify(&ast::Block(stmts)))
+}
+
+fn simplify(instrs: &[llir::RawInstr]) -> Vec<Instr> {
+    instrs.iter().map(|instr| Instr {
+        opcode: instr.opcode,
+        param_mask: instr.param_mask,
+        args_blob: instr.args_blob.clone(),
+    }).collect()
+}
+
+/// Compile the text, check it against the expected instructions, then check that it round-trips.
+#[track_caller]
+fn check_compiled(text: &str, expected: &[Instr]) {
+    truth::setup_for_test_harness();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let hooks = make_language();
+    load_mapfile(&mut truth);
+
+    let instrs = compile(&mut truth, &hooks, text).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert_eq!(simplify(&instrs), expected);
+
+    let decompiled = decompile(&mut truth, &hooks, &instrs).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    let recompiled = compile(&mut truth, &hooks, &decompiled).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert_eq!(simplify(&recompiled), expected, "{}", decompiled);
+}
+
+/// Compile the text, then check that decompiling it rebuilds the given statement and that it round-trips.
+///
+/// Returns the captured diagnostics.
+#[track_caller]
+fn check_raised(text: &str, expected: &str) -> String {
+    truth::setup_for_test_harness();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let hooks = make_language();
+    load_mapfile(&mut truth);
+
+    let instrs = compile(&mut truth, &hooks, text).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    let decompiled = decompile(&mut truth, &hooks, &instrs).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert!(decompiled.contains(expected), "{}", decompiled);
+
+    let recompiled = compile(&mut truth, &hooks, &decompiled).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert_eq!(simplify(&recompiled), simplify(&instrs), "{}", decompiled);
+    truth.get_captured_diagnostics().unwrap()
+}
+
+/// Checks that compiling this fails with an error containing the given string.
+#[track_caller]
+fn expect_error(text: &str, expected: &str) {
+    truth::setup_for_test_harness();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let hooks = make_language();
+    load_mapfile(&mut truth);
+
+    compile(&mut truth, &hooks, text).unwrap_err().ignore();
+    let err_s = truth.get_captured_diagnostics().unwrap();
+    assert!(err_s.contains(expected), "{}", err_s);
+}
+
+use Arg::{Int, Float, IntReg, FloatReg};
+
+#[test]
+fn postfix_order() {
+    check_compiled("{ A = (B + 3) * A; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_B)]),
+        instr(PUSH_INT_OPCODE, &[Int(3)]),
+        instr(ADD_INT_OPCODE, &[]),
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(MUL_INT_OPCODE, &[]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+    ]);
+}
+
+#[test]
+fn unops() {
+    check_compiled("{ X = -sin(Y) + 1.5; }", &[
+        instr(PUSH_FLOAT_OPCODE, &[FloatReg(REG_Y)]),
+        instr(SIN_OPCODE, &[]),
+        instr(NEG_FLOAT_OPCODE, &[]),
+        instr(PUSH_FLOAT_OPCODE, &[Float(1.5)]),
+        instr(ADD_FLOAT_OPCODE, &[]),
+        instr(POP_FLOAT_OPCODE, &[FloatReg(REG_X)]),
+    ]);
+}
+
+#[test]
+fn update_assignment() {
+    check_compiled("{ A -= B; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_B)]),
+        instr(SUB_INT_OPCODE, &[]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+    ]);
+}
+
+#[test]
+fn casts() {
+    // a cast of a variable is just a read
+    check_compiled("{ X = %A; }", &[
+        instr(PUSH_FLOAT_OPCODE, &[FloatReg(REG_A)]),
+        instr(POP_FLOAT_OPCODE, &[FloatReg(REG_X)]),
+    ]);
+    // a cast of anything else needs an instruction
+    check_compiled("{ X = float(A + 1); }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[Int(1)]),
+        instr(ADD_INT_OPCODE, &[]),
+        instr(CAST_FLOAT_OPCODE, &[]),
+        instr(POP_FLOAT_OPCODE, &[FloatReg(REG_X)]),
+    ]);
+}
+
+#[test]
+fn locals_in_stack_slots() {
+    check_compiled("{ int x = A; float y = 2.0; A = x; }", &[
+        instr(STACK_ALLOC_OPCODE, &[Int(8)]),
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(POP_INT_OPCODE, &[IntReg(RegId(0))]),
+        instr(PUSH_FLOAT_OPCODE, &[Float(2.0)]),
+        instr(POP_FLOAT_OPCODE, &[FloatReg(RegId(4))]),
+        instr(PUSH_INT_OPCODE, &[IntReg(RegId(0))]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+    ]);
+}
+
+#[test]
+fn stack_slots_are_reused() {
+    check_compiled("{ { int x = 1; } { int y = 2; } }", &[
+        instr(STACK_ALLOC_OPCODE, &[Int(4)]),
+        instr(PUSH_INT_OPCODE, &[Int(1)]),
+        instr(POP_INT_OPCODE, &[IntReg(RegId(0))]),
+        instr(PUSH_INT_OPCODE, &[Int(2)]),
+        instr(POP_INT_OPCODE, &[IntReg(RegId(0))]),
+    ]);
+}
+
+#[test]
+fn instruction_args() {
+    // simple args are used as is, while others are computed into a stack slot
+    check_compiled("{ foo(A + 1, B); }", &[
+        instr(STACK_ALLOC_OPCODE, &[Int(4)]),
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[Int(1)]),
+        instr(ADD_INT_OPCODE, &[]),
+        instr(POP_INT_OPCODE, &[IntReg(RegId(0))]),
+        instr(FOO_OPCODE, &[IntReg(RegId(0)), IntReg(REG_B)]),
+    ]);
+}
+
+#[test]
+fn value_instructions() {
+    // the value is either left on the stack...
+    check_compiled("{ A = rand(3) + 1; }", &[
+        instr(RAND_OPCODE, &[Int(3)]),
+        instr(PUSH_INT_OPCODE, &[Int(1)]),
+        instr(ADD_INT_OPCODE, &[]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+    ]);
+    // ...or written to a register, which is then pushed
+    check_compiled("{ X = fetch(); }", &[
+        instr(FETCH_OPCODE, &[]),
+        instr(PUSH_FLOAT_OPCODE, &[FloatReg(REG_Y)]),
+        instr(POP_FLOAT_OPCODE, &[FloatReg(REG_X)]),
+    ]);
+}
+
+#[test]
+fn value_instruction_as_statement() {
+    // like a raw push, the value stays on the stack for whatever comes next
+    check_compiled("{ rand(3); ins_12(A); }", &[
+        instr(RAND_OPCODE, &[Int(3)]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+    ]);
+}
+
+#[test]
+fn cond_jumps() {
+    check_compiled("{ label: if (A < 3) goto label; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[Int(3)]),
+        instr(LT_INT_OPCODE, &[]),
+        instr(JUMP_NE_OPCODE, &[Int(0), Int(0)]),
+    ]);
+    check_compiled("{ label: unless (A) goto label; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(JUMP_EQ_OPCODE, &[Int(0), Int(0)]),
+    ]);
+    check_compiled("{ label: if (!A) goto label; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(JUMP_EQ_OPCODE, &[Int(0), Int(0)]),
+    ]);
+}
+
+#[test]
+fn count_jump() {
+    check_compiled("{ label: if (--A) goto label; }", &[
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[Int(1)]),
+        instr(SUB_INT_OPCODE, &[]),
+        instr(POP_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(PUSH_INT_OPCODE, &[IntReg(REG_A)]),
+        instr(JUMP_NE_OPCODE, &[Int(0), Int(0)]),
+    ]);
+}
+
+#[test]
+fn missing_stack_op() {
+    expect_error("{ X = sqrt(Y); }", "this unary operation not supported");
+}
+
+#[test]
+fn raise_expressions() {
+    check_raised("{ A = (B + 3) * A; }", "$REG[1000] = ($REG[1001] + 3) * $REG[1000];");
+    check_raised("{ X = -sin(Y) + 1.5; }", "%REG[1004] = (-sin(%REG[1005])) + 1.5;");
+    check_raised("{ X = float(A + 1); }", "%REG[1004] = float($REG[1000] + 1);");
+    check_raised("{ A -= B; }", "$REG[1000] = $REG[1000] - $REG[1001];");
+}
+
+#[test]
+fn raise_cond_jumps() {
+    check_raised("{ label: if (A < 3) goto label; }", "if ($REG[1000] < 3) goto label_0;");
+    check_raised("{ label: unless (A + B) goto label; }", "unless ($REG[1000] + $REG[1001]) goto label_0;");
+    check_raised("{ label: if (!A) goto label; }", "unless ($REG[1000]) goto label_0;");
+}
+
+#[test]
+fn raise_instruction_args() {
+    // the temporary slot is assigned like any other register
+    check_raised("{ foo(A + 1, B); }", "$REG[0] = $REG[1000] + 1;");
+}
+
+#[test]
+fn raise_pseudo_pop() {
+    check_raised("{ ins_10(A); ins_100(@pop=1, 1, 2); }", "@pop=1");
+}
+
+#[test]
+fn raise_async_calls() {
+    truth::setup_for_test_harness();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let hooks = make_language();
+    load_mapfile(&mut truth);
+
+    // (the sub can't be defined in a block, so these are written as raw instructions)
+    let text = "{ ins_60(0); ins_10(3); ins_10(A); ins_61(@pop=2, 0, 7); }";
+    let instrs = compile(&mut truth, &hooks, text).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    let decompiled = decompile(&mut truth, &hooks, &instrs).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert!(decompiled.contains("worker() async;"), "{}", decompiled);
+    assert!(decompiled.contains("worker(3, $REG[1000]) async 7;"), "{}", decompiled);
+}
+
+#[test]
+fn raise_unbalanced_stack() {
+    // a value left on the stack across a label can't be part of any expression
+    let diagnostics = check_raised(
+        "{ ins_10(A); label: ins_10(3); ins_20(); ins_12(A); goto label; }",
+        "ins_10($REG[1000]);\nlabel_8:\n    ins_10(3);\n    ins_20();\n",
+    );
+    assert!(diagnostics.contains("could not rebuild a stack expression"), "{}", diagnostics);
+}
+
+#[test]
+fn wide_param_mask() {
+    truth::setup_for_test_harness();
+
+    let mut scope = truth::Builder::new().capture_diagnostics(true).build();
+    let mut truth = scope.truth();
+    let mut hooks = make_language();
+    hooks.param_mask_bits = 32;
+    load_mapfile(&mut truth);
+
+    let mut args = vec![Int(0); 20];
+    args[17] = IntReg(REG_A);
+    let text = "{ wide(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, A, 0, 0); }";
+
+    let instrs = compile(&mut truth, &hooks, text).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert_eq!(simplify(&instrs), vec![instr(WIDE_OPCODE, &args)]);
+
+    let decompiled = decompile(&mut truth, &hooks, &instrs).unwrap_or_else(|_| panic!("{}", truth.get_captured_diagnostics().unwrap()));
+    assert!(decompiled.contains("0, $REG[1000], 0"), "{}", decompiled);
+    assert!(!truth.get_captured_diagnostics().unwrap().contains("unused mask bits"));
+}
+
+#[test]
+fn wide_param_mask_too_many_args() {
+    // the default test format only has 16 bits
+    expect_error(
+        "{ wide(0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, A, 0, 0); }",
+        "can only describe 16 arguments",
+    );
+    expect_error("{ foo(@mask=0x10000, 1, 2); }", "parameter mask too wide");
+}
@@ -0,0 +1,115 @@
+//! Tests that compiling and decompiling on multiple threads is indistinguishable from using one.
+
+use truth::{Game, FileFormat, Truth};
+
+mod api_impl;
+use api_impl::{anm_entry, with_truth_from};
+
+fn with_truth<R>(num_threads: usize, func: impl FnOnce(&mut Truth) -> R) -> (R, String) {
+    with_truth_from(truth::Builder::new().num_threads(Some(num_threads)), func)
+}
+
+/// Many scripts that each need scratch registers, so that every thread gets some work.
+fn many_anm_scripts(count: usize) -> String {
+    (0..count).map(|i| format!(r#"
+script script{i} {{
+    $REG[10000] = $REG[10001] * {i} + $REG[10001] * ($REG[10001] - {i});
+    %REG[10004] = (%REG[10005] + {i}.0) * (%REG[10005] - 1.0);
+    +{i}:
+    if ($REG[10000] > {i}) {{
+        ins_1();
+    }}
+}}
+"#)).collect()
+}
+
+fn compile_anm(num_threads: usize, source: &str) -> (Option<Vec<u8>>, String) {
+    let (result, stderr) = with_truth(num_threads, |truth| {
+        truth.compile_from_str(FileFormat::Anm, Game::Th12, "<input>", source, &[])
+    });
+    (result.ok(), stderr)
+}
+
+#[test]
+fn compile_is_deterministic() {
+    let source = anm_entry("a.png", &many_anm_scripts(40));
+    let (expected, stderr) = compile_anm(1, &source);
+    let expected = expected.expect(&stderr);
+    assert_eq!(stderr, "");
+
+    for num_threads in [2, 3, 8] {
+        let (actual, stderr) = compile_anm(num_threads, &source);
+        assert_eq!(actual.expect(&stderr), expected, "{num_threads} threads");
+    }
+}
+
+#[test]
+fn diagnostics_are_sorted() {
+    // errors in every script, which the workers will find in an unpredictable order
+    let scripts = (0..20).map(|i| format!("script script{i} {{ ins_{}(); }}\n", 1000 + i)).collect::<String>();
+    let source = anm_entry("a.png", &scripts);
+
+    let (result, expected_stderr) = compile_anm(1, &source);
+    assert!(result.is_none());
+    let lines = (0..20).map(|i| {
+        let opcode = format!("ins_{}", 1000 + i);
+        expected_stderr.find(&opcode).unwrap_or_else(|| panic!("{opcode} not reported: {expected_stderr}"))
+    }).collect::<Vec<_>>();
+    assert!(lines.windows(2).all(|w| w[0] < w[1]), "{expected_stderr}");
+
+    for num_threads in [2, 8] {
+        let (_, stderr) = compile_anm(num_threads, &source);
+        assert_eq!(stderr, expected_stderr, "{num_threads} threads");
+    }
+}
+
+#[test]
+fn decompile_is_deterministic() {
+    let (bytes, stderr) = compile_anm(1, &anm_entry("a.png", &many_anm_scripts(40)));
+    let bytes = bytes.expect(&stderr);
+
+    let decompile = |num_threads| with_truth(num_threads, |truth| {
+        truth.decompile_from_bytes(FileFormat::Anm, Game::Th12, "a.anm", &bytes, &[], &Default::default())
+    });
+    let (expected, stderr) = decompile(1);
+    let expected = expected.expect(&stderr);
+    assert!(expected.contains("script 39 script39"), "{expected}");
+
+    for num_threads in [2, 8] {
+        let (actual, stderr) = decompile(num_threads);
+        assert_eq!(actual.expect(&stderr), expected, "{num_threads} threads");
+    }
+}
+
+#[test]
+fn ecl_subs_and_timelines() {
+    let subs = (0..30).map(|i| format!(r#"
+void sub{i}(int x, float y) {{
+    $REG[10000] = x * {i} + (x - {i}) * (x + 2);
+    %REG[10004] = (y + {i}.0) * (y - 1.0);
+    {call}
+}}
+"#, call = if i > 0 { format!("sub{}(x + 1, y);", i - 1) } else { String::new() })).collect::<String>();
+    let source = format!("timeline 0 {{ +10: ins_0(sub3, 1.0, 2.0, 0, 0, 0); }}\ntimeline 1 {{ +20: ins_0(sub5, 3.0, 4.0, 0, 0, 0); }}\n{subs}");
+
+    let compile = |num_threads| with_truth(num_threads, |truth| {
+        truth.compile_from_str(FileFormat::Ecl, Game::Th08, "<input>", &source, &[])
+    });
+    let (expected, stderr) = compile(1);
+    let expected = expected.expect(&stderr);
+    for num_threads in [2, 8] {
+        let (actual, stderr) = compile(num_threads);
+        assert_eq!(actual.expect(&stderr), expected, "{num_threads} threads");
+    }
+
+    let decompile = |num_threads| with_truth(num_threads, |truth| {
+        truth.decompile_from_bytes(FileFormat::Ecl, Game::Th08, "a.ecl", &expected, &[], &Default::default())
+    });
+    let (expected, stderr) = decompile(1);
+    let expected = expected.expect(&stderr);
+    assert!(expected.contains("sub29("), "{expected}");
+    for num_threads in [2, 8] {
+        let (actual, stderr) = decompile(num_threads);
+        assert_eq!(actual.expect(&stderr), expected, "{num_threads} threads");
+    }
+}